@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const TIMESTAMP_HEADER: &str = "X-Erp-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Erp-Signature";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Callback {
+	// TODO: add more options than just python
+	Script {
+		name: String,
+		path: String,
+	},
+	Webhook {
+		name: String,
+		url: String, // should be Uri
+		headers: HashMap<String, String>,
+		// the callback server gives up on the request after this long
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		#[cfg_attr(feature = "ts", ts(type = "number | null"))]
+		timeout_ms: Option<u64>
+	}
+}
+
+impl Callback {
+	pub fn name(&self) -> &str {
+		return match self {
+			Callback::Script { name, .. } => name,
+			Callback::Webhook { name, .. } => name
+		};
+	}
+}
+
+// sent with the payload so the callbacks can check it came from the server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PayloadSignature {
+	// json numbers, not bigint
+	#[cfg_attr(feature = "ts", ts(type = "number"))]
+	pub timestamp: i64,
+	pub signature: String
+}
+
+// body of a task on the nats work queue. data is the payload exactly as it was signed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CallbackTask {
+	pub data: String,
+	pub callbacks: Vec<Callback>,
+	// missing from servers before signing was added
+	#[serde(default)]
+	pub signature: Option<PayloadSignature>,
+	// of the server request that queued the task
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub request_id: Option<String>
+}
+
+// "v1=" + hex hmac-sha256 of "<timestamp>.<body>" keyed with the callback secret of the process
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+	mac.update(timestamp.to_string().as_bytes());
+	mac.update(b".");
+	mac.update(body);
+	return format!("v1={}", hex::encode(mac.finalize().into_bytes()));
+}
+
+#[cfg(test)]
+mod callbacks_tests {
+	use serde_json::json;
+	use super::{Callback, CallbackTask};
+
+	#[test]
+	fn tasks_of_older_servers_still_parse() {
+		let task: CallbackTask = serde_json::from_value(json!({
+			"data": "{}",
+			"callbacks": [{ "type": "webhook", "name": "crm", "url": "http://crm", "headers": {} }]
+		})).unwrap();
+		assert_eq!(task.signature, None);
+		assert_eq!(task.request_id, None);
+		assert_eq!(task.callbacks[0].name(), "crm");
+
+		// and the servers of before the timeout do not get a field they do not know
+		let sent = serde_json::to_value(&task.callbacks[0]).unwrap();
+		assert!(sent.get("timeout_ms").is_none());
+		assert!(matches!(task.callbacks[0], Callback::Webhook { timeout_ms: None, .. }));
+	}
+}
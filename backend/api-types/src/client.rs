@@ -0,0 +1,138 @@
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use crate::callbacks::{sign_payload, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::tickets::{CancelTicket, CreateTicket, CreatedTicket, GetUserTicketsReq, UpdateTicket, UserTickets};
+use crate::{Problem, API_KEY_HEADER};
+
+#[derive(Debug)]
+pub enum ClientError {
+	Request(reqwest::Error),
+	// the server answered with an error, described by the problem when the body could be read
+	Status(StatusCode, Option<Box<Problem>>),
+	// the call needs credentials the client was not built with
+	MissingCredentials
+}
+
+impl std::fmt::Display for ClientError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match self {
+			ClientError::Request(e) => write!(f, "request failed: {}", e),
+			ClientError::Status(status, Some(problem)) if problem.errors.is_empty() => {
+				write!(f, "server answered {} {}: {}", status, problem.code, problem.detail.as_deref().unwrap_or_default())
+			}
+			ClientError::Status(status, Some(problem)) => write!(f, "server answered {} {}: {:?}", status, problem.code, problem.errors),
+			ClientError::Status(status, None) => write!(f, "server answered {}", status),
+			ClientError::MissingCredentials => write!(f, "no credentials for this call")
+		};
+	}
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+	fn from(e: reqwest::Error) -> Self {
+		return ClientError::Request(e);
+	}
+}
+
+// an api key and the callback secret of the process its tasks belong to
+#[derive(Clone)]
+struct ServiceKey {
+	key: String,
+	secret: String
+}
+
+// talks to the server at base_url, e.g. http://localhost:3000. calls made for a user need with_token,
+// services completing tasks need with_api_key
+#[derive(Clone)]
+pub struct Client {
+	http: reqwest::Client,
+	base_url: String,
+	token: Option<String>,
+	service_key: Option<ServiceKey>
+}
+
+impl Client {
+	pub fn new(base_url: &str) -> Client {
+		return Client::with_http(reqwest::Client::new(), base_url);
+	}
+
+	// for callers that set their own timeouts or proxies
+	pub fn with_http(http: reqwest::Client, base_url: &str) -> Client {
+		return Client { http, base_url: base_url.trim_end_matches('/').to_string(), token: None, service_key: None };
+	}
+
+	// an access token from /login
+	pub fn with_token(mut self, token: &str) -> Client {
+		self.token = Some(token.to_string());
+		return self;
+	}
+
+	// requests to /service are signed with the callback secret, like the payloads the server sends to callbacks
+	pub fn with_api_key(mut self, key: &str, callback_secret: &str) -> Client {
+		self.service_key = Some(ServiceKey { key: key.to_string(), secret: callback_secret.to_string() });
+		return self;
+	}
+
+	fn url(&self, path: &str) -> String {
+		return format!("{}{}", self.base_url, path);
+	}
+
+	fn authorized(&self, request: RequestBuilder) -> Result<RequestBuilder, ClientError> {
+		let token = self.token.as_ref().ok_or(ClientError::MissingCredentials)?;
+		return Ok(request.bearer_auth(token));
+	}
+
+	async fn send(request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+		let response = request.send().await?;
+		let status = response.status();
+		if status.is_success() {
+			return Ok(response);
+		}
+		let problem = response.json::<Problem>().await.ok().map(Box::new);
+		return Err(ClientError::Status(status, problem));
+	}
+
+	async fn send_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ClientError> {
+		return Ok(Client::send(request).await?.json::<T>().await?);
+	}
+
+	pub async fn create_ticket(&self, payload: &CreateTicket) -> Result<CreatedTicket, ClientError> {
+		let request = self.authorized(self.http.post(self.url("/ticket")))?.json(payload);
+		return Client::send_json(request).await;
+	}
+
+	// completes node `payload.node` as the user of the token
+	pub async fn update_ticket(&self, payload: &UpdateTicket) -> Result<(), ClientError> {
+		let request = self.authorized(self.http.post(self.url("/ticket/update")))?.json(payload);
+		Client::send(request).await?;
+		return Ok(());
+	}
+
+	pub async fn cancel_ticket(&self, payload: &CancelTicket) -> Result<(), ClientError> {
+		let request = self.authorized(self.http.post(self.url("/ticket/cancel")))?.json(payload);
+		Client::send(request).await?;
+		return Ok(());
+	}
+
+	pub async fn user_tickets(&self, query: &GetUserTicketsReq) -> Result<UserTickets, ClientError> {
+		let request = self.authorized(self.http.get(self.url("/ticket/user")))?.query(query);
+		return Client::send_json(request).await;
+	}
+
+	// completes a blocking task node with the api key, in the name of the ticket owner
+	pub async fn complete_task(&self, payload: &UpdateTicket) -> Result<(), ClientError> {
+		let service_key = self.service_key.as_ref().ok_or(ClientError::MissingCredentials)?;
+		let body = serde_json::to_vec(payload).expect("tickets serialize to json");
+		let timestamp = chrono::Utc::now().timestamp();
+
+		let request = self.http.post(self.url("/service/ticket/update"))
+			.header(API_KEY_HEADER, &service_key.key)
+			.header(TIMESTAMP_HEADER, timestamp)
+			.header(SIGNATURE_HEADER, sign_payload(&service_key.secret, timestamp, &body))
+			.header(CONTENT_TYPE, "application/json")
+			.body(body);
+		Client::send(request).await?;
+		return Ok(());
+	}
+}
@@ -0,0 +1,59 @@
+// i dont like implicit returns. sometimes explicitly saying return is nicer when rereading code
+#![allow(clippy::needless_return)]
+
+// request and response types of the server api. the server uses them for its handlers, other services
+// use them, or the client behind the client feature, to talk to it
+pub mod tickets;
+pub mod callbacks;
+#[cfg(feature = "client")]
+pub mod client;
+
+use serde::{Deserialize, Serialize};
+
+// sent back with every request and passed on to callbacks and logs
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// authenticates services completing tasks, see client::Client::with_api_key
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+// content type of Problem bodies
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+// why a request was rejected. field is a json pointer into the sent data, empty when it is about the whole request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+	pub field: String,
+	pub message: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldErrors {
+	pub errors: Vec<FieldError>
+}
+
+// body of every error response, rfc 7807 with `code` and `errors` as extensions. code is stable and meant for
+// programs, like ticket_not_open or database_unavailable, detail is for people. the defaults let older bodies
+// with only errors be read as well
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Problem {
+	#[serde(rename = "type", default = "about_blank")]
+	pub type_: String,
+	#[serde(default)]
+	pub title: String,
+	#[serde(default)]
+	pub status: u16,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub detail: Option<String>,
+	#[serde(default)]
+	pub code: String,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub errors: Vec<FieldError>
+}
+
+fn about_blank() -> String {
+	return "about:blank".to_string();
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// status column of tickets. drafts are only seen by their owner until submitted, the last three are final
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(type_name = "ticket_status", rename_all = "lowercase"))]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum TicketStatus {Draft, Open, Closed, Rejected, Cancelled}
+
+// type_ column of user_active_tickets. own rows list a ticket for its owner, approve rows for whoever has to act on it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(type_name = "assignment_type", rename_all = "lowercase"))]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum AssignmentType {Own, Approve}
+
+impl TicketStatus {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			TicketStatus::Draft => "draft",
+			TicketStatus::Open => "open",
+			TicketStatus::Closed => "closed",
+			TicketStatus::Rejected => "rejected",
+			TicketStatus::Cancelled => "cancelled"
+		};
+	}
+}
+
+impl std::fmt::Display for TicketStatus {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return f.write_str(self.as_str());
+	}
+}
+
+impl AssignmentType {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			AssignmentType::Own => "own",
+			AssignmentType::Approve => "approve"
+		};
+	}
+}
+
+impl std::fmt::Display for AssignmentType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return f.write_str(self.as_str());
+	}
+}
+
+// the derive only maps the type itself, binding a slice for `status=any($1)` needs the array type as well
+#[cfg(feature = "sqlx")]
+impl sqlx::postgres::PgHasArrayType for TicketStatus {
+	fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+		return sqlx::postgres::PgTypeInfo::with_name("_ticket_status");
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreateTicket {
+	pub process_id: String,
+	// taken from the token of the request, set directly by the scheduler
+	#[serde(skip)]
+	#[cfg_attr(feature = "ts", ts(skip))]
+	pub owner_id: uuid::Uuid,
+	#[serde(skip)]
+	#[cfg_attr(feature = "ts", ts(skip))]
+	pub owner_name: String,
+	pub is_public: bool,
+	// higher is more urgent, defaults to 0
+	pub priority: Option<i32>,
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub tags: Option<Vec<String>>,
+	// drafts are stored without executing node 0
+	#[serde(default)]
+	pub draft: bool,
+	#[cfg_attr(feature = "ts", ts(type = "Record<string, unknown> | null"))]
+	#[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+	pub data: Option<Map<String, Value>>
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreatedTicket {
+	pub id: i32,
+	pub log_id: uuid::Uuid,
+	pub status: TicketStatus
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdateTicket {
+	pub ticket_id: i32,
+	// taken from the token of the request
+	#[serde(skip)]
+	#[cfg_attr(feature = "ts", ts(skip))]
+	pub user_id: uuid::Uuid,
+	pub status: bool,
+	pub node: i32,
+	// a json merge patch (RFC 7396) of the state: objects are merged key by key and a null removes the key
+	#[cfg_attr(feature = "ts", ts(type = "Record<string, unknown> | null"))]
+	#[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+	pub data: Option<Map<String, Value>>,
+	// which element of a multi instance node is being completed
+	pub instance: Option<i32>,
+	// required when status is false
+	pub reason: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SubmitTicket {
+	// merged into the state saved with the draft
+	#[cfg_attr(feature = "ts", ts(type = "Record<string, unknown> | null"))]
+	#[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+	pub data: Option<Map<String, Value>>
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CancelTicket {
+	pub ticket_id: i32,
+	pub reason: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct GetUserTicketsReq {
+	pub status: Option<TicketStatus>,
+	pub process_id: Option<String>,
+	pub priority: Option<i32>,
+	pub tag: Option<String>,
+	// range on the creation time of the ticket
+	pub from: Option<chrono::DateTime<chrono::Utc>>,
+	pub to: Option<chrono::DateTime<chrono::Utc>>,
+	// priority, created_at or updated_at. defaults to priority for current tickets and updated_at for own tickets
+	pub sort: Option<String>,
+	// asc or desc, defaults to desc
+	pub order: Option<String>,
+	#[cfg_attr(feature = "ts", ts(type = "number | null"))]
+	pub limit: Option<i64>,
+	pub current_cursor: Option<String>,
+	pub own_cursor: Option<String>,
+	// also list own tickets that were moved to tickets_archive
+	pub include_archived: Option<bool>
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UserTickets {
+	pub current_tickets: Vec<CurrentTicket>,
+	pub own_tickets: Vec<OwnTicket>,
+	pub next_cursor: NextCursor
+}
+
+// the two lists are paged independently. None means there are no more rows
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NextCursor {
+	pub current_tickets: Option<String>,
+	pub own_tickets: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CurrentTicket {
+	// row id in user_active_tickets
+	pub id: i32,
+	pub type_: AssignmentType,
+	pub ticketid: i32,
+	pub active: bool,
+	pub node_number: i32,
+	pub instance: Option<i32>,
+	pub process_id: String,
+	pub owner_name: String,
+	pub status: TicketStatus,
+	pub priority: i32,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>,
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub overdue: bool
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OwnTicket {
+	pub id: i32,
+	pub process_id: String,
+	pub is_public: bool,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>,
+	pub status: TicketStatus,
+	pub priority: i32,
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub overdue: bool
+}
+
+#[cfg(test)]
+mod tickets_tests {
+	use serde_json::json;
+	use super::{AssignmentType, GetUserTicketsReq, TicketStatus, UpdateTicket};
+
+	#[test]
+	fn ids_from_the_token_are_never_sent() {
+		let update = UpdateTicket {
+			ticket_id: 3, user_id: uuid::Uuid::from_u128(7), status: true, node: 1, data: None, instance: None, reason: None
+		};
+		let sent = serde_json::to_value(&update).unwrap();
+		assert_eq!(sent, json!({ "ticket_id": 3, "status": true, "node": 1, "data": null, "instance": null, "reason": null }));
+
+		// a client cannot act for someone else by setting it either
+		let received: UpdateTicket = serde_json::from_value(json!({ "ticket_id": 3, "user_id": uuid::Uuid::from_u128(7), "status": true, "node": 1 })).unwrap();
+		assert!(received.user_id.is_nil());
+	}
+
+	#[test]
+	fn statuses_are_the_names_in_the_db() {
+		assert_eq!(serde_json::to_value(TicketStatus::Cancelled).unwrap(), json!("cancelled"));
+		assert_eq!(TicketStatus::Draft.to_string(), "draft");
+		assert_eq!(serde_json::from_value::<AssignmentType>(json!("approve")).unwrap(), AssignmentType::Approve);
+
+		// a misspelled filter is refused instead of matching nothing
+		assert!(serde_json::from_value::<GetUserTicketsReq>(json!({ "status": "opne" })).is_err());
+		let query: GetUserTicketsReq = serde_json::from_value(json!({ "status": "open" })).unwrap();
+		assert_eq!(query.status, Some(TicketStatus::Open));
+	}
+}
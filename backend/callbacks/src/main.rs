@@ -1,32 +1,24 @@
-use std::{collections::{HashMap, VecDeque}, io::ErrorKind, net::SocketAddr, time::Duration};
+// i dont like implicit returns. sometimes explicitly saying return is nicer when rereading code
+#![allow(clippy::needless_return)]
+
+use std::{collections::VecDeque, net::SocketAddr, time::Duration};
+use async_nats::jetstream;
 use chrono::Local;
+use erp_api_types::callbacks::{Callback, CallbackTask, PayloadSignature, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use erp_api_types::REQUEST_ID_HEADER;
+use futures_util::StreamExt;
 use once_cell::sync::Lazy;
-use reqwest::Method;
-use serde::{Deserialize, Serialize};
-use tokio::{io::AsyncReadExt, net::{TcpListener, TcpStream}, process::Command, sync::Mutex, time::sleep};
+use reqwest::{header::CONTENT_TYPE, Method};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, process::Command, sync::Mutex, time::sleep};
 
 
 static MAX_TASK_EXECUTORS: usize = 4;
 
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-pub enum Callback {
-	Script {
-		name: String,
-		path: String
-	},
-	Webhook {
-		name: String,
-		url: String,
-		headers: HashMap<String, String>
-	}
-}
-#[derive(Debug)]
+// a task from the server with the nats message it came in, tcp tasks were already acknowledged when queued
 pub struct Task {
-	data: serde_json::Value,
-	callbacks: Vec<Callback>
+	body: CallbackTask,
+	// tasks from nats are acknowledged after their callbacks ran, nats hands them to another worker otherwise
+	message: Option<jetstream::Message>
 }
 
 static TASK_QUEUE : Lazy<Mutex<VecDeque<Task>>> = Lazy::new(|| {
@@ -44,6 +36,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		tokio::spawn(execute_task());
 	}
 
+	if std::env::var("CALLBACK_TRANSPORT").as_deref() == Ok("nats") {
+		let url = std::env::var("CALLBACK_NATS_URL").expect("CALLBACK_NATS_URL not defined");
+		tokio::spawn(async move {
+			if let Err(e) = consume_nats(&url).await {
+				eprintln!("[ERROR] [{}] Stopped consuming tasks from nats: {}", Local::now(), e);
+			}
+		});
+	}
+
     // listen to pings
     let addr = SocketAddr::from(([0, 0, 0, 0], callback_port));
     let listener = TcpListener::bind(&addr).await.unwrap();
@@ -70,15 +71,61 @@ async fn execute_task() {
 			continue;
 		}
 		let task = task.unwrap();
-		for callback in task.callbacks {
-			let res = callback.execute(&task.data).await;
+		let body = &task.body;
+		for callback in body.callbacks.iter() {
+			let res = execute_callback(callback, &body.data, &body.signature, &body.request_id).await;
 			if let Err(e) = res {
-				eprintln!("[ERROR] [{}] Callback : {} failed: e: {}", Local::now(), callback.name(), e);
+				eprintln!("[ERROR] [{}] Callback : {} failed, request id: {:?}: e: {}", Local::now(), callback.name(), body.request_id, e);
+			}
+		}
+		if let Some(message) = task.message {
+			if let Err(e) = message.ack().await {
+				eprintln!("[ERROR] [{}] Failed to acknowledge nats task: {}", Local::now(), e);
 			}
 		}
 	}
 }
 
+// pulls tasks from the work queue the server publishes to. every callback server is one more worker on the same consumer
+async fn consume_nats(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+	let subject = std::env::var("CALLBACK_NATS_SUBJECT").unwrap_or("erp.callbacks.tasks".to_string());
+	let stream_name = std::env::var("CALLBACK_NATS_STREAM").unwrap_or("ERP_CALLBACKS".to_string());
+
+	let client = async_nats::connect(url).await?;
+	let stream = jetstream::new(client).get_or_create_stream(jetstream::stream::Config {
+		name: stream_name,
+		subjects: vec![subject],
+		retention: jetstream::stream::RetentionPolicy::WorkQueue,
+		..Default::default()
+	}).await?;
+	let consumer = stream.get_or_create_consumer("callback-workers", jetstream::consumer::pull::Config {
+		durable_name: Some("callback-workers".to_string()),
+		// callbacks can run for a while before the task is acknowledged
+		ack_wait: Duration::from_secs(600),
+		// no more tasks than the executors can work through are held in memory
+		max_ack_pending: (MAX_TASK_EXECUTORS * 4) as i64,
+		..Default::default()
+	}).await?;
+	println!("[INFO] [{}] Consuming tasks from nats at {}", Local::now(), url);
+
+	let mut messages = consumer.messages().await?;
+	while let Some(message) = messages.next().await {
+		let message = message?;
+		let body: CallbackTask = match serde_json::from_slice(&message.payload) {
+			Ok(body) => body,
+			Err(e) => {
+				// redelivering does not fix a broken task
+				eprintln!("[ERROR] [{}] Dropping invalid nats task: {}", Local::now(), e);
+				let _ = message.ack().await;
+				continue;
+			}
+		};
+		let mut guard = TASK_QUEUE.lock().await;
+		guard.push_back(Task { body, message: Some(message) });
+	}
+	return Ok(());
+}
+
 async fn handle_ping(mut stream: TcpStream, addr: SocketAddr) {
     println!("[INFO] [{}] Incoming connection from: {}", Local::now(), addr);
     let header : u64 = stream.read_u64_le().await.unwrap();
@@ -89,25 +136,50 @@ async fn handle_ping(mut stream: TcpStream, addr: SocketAddr) {
 		let data_len = stream.read_u64_le().await.unwrap() as usize;
 		let mut data_buffer = vec![0u8; data_len];
 		// silently fail. if this fails now then the server will log the failure
-		if let Err(_) = stream.read_exact(&mut data_buffer).await {
+		if stream.read_exact(&mut data_buffer).await.is_err() {
 			return;
 		}
 		
 		// read callback vector
 		let callback_len = stream.read_u64_le().await.unwrap() as usize;
 		let mut callback_buffer = vec![0u8; callback_len];
-		if let Err(_) = stream.read_exact(&mut callback_buffer).await {
+		if stream.read_exact(&mut callback_buffer).await.is_err() {
 			return;
 		}
 
 
+		// read payload signature. servers before signing was added end the message after the callbacks
+		let mut signature = None;
+		if let Ok(signature_len) = stream.read_u64_le().await {
+			let mut signature_buffer = vec![0u8; signature_len as usize];
+			if stream.read_exact(&mut signature_buffer).await.is_err() {
+				return;
+			}
+			signature = serde_json::from_slice::<PayloadSignature>(&signature_buffer).ok();
+		}
+
+		// request id, also missing from older servers
+		let mut request_id = None;
+		if let Ok(request_id_len) = stream.read_u64_le().await {
+			let mut request_id_buffer = vec![0u8; request_id_len as usize];
+			if stream.read_exact(&mut request_id_buffer).await.is_err() {
+				return;
+			}
+			request_id = String::from_utf8(request_id_buffer).ok().filter(|id| !id.is_empty());
+		}
+
 		let data = String::from_utf8(data_buffer).unwrap();
-		let deserialized : serde_json::Value = serde_json::from_str(&data).unwrap();
+		// only checked, the callbacks get the payload unchanged so the signature stays valid
+		let _ : serde_json::Value = serde_json::from_str(&data).unwrap();
 		let callbacks: Vec<Callback> = serde_json::from_slice(&callback_buffer).unwrap();
 
 		{
 			let mut guard = TASK_QUEUE.lock().await;
-			guard.push_back(Task { data: deserialized, callbacks });
+			guard.push_back(Task { body: CallbackTask { data, callbacks, signature, request_id }, message: None });
+		}
+		// the server keeps the task in its queue until it is acknowledged
+		if let Err(e) = stream.write_u8(1).await {
+			eprintln!("[ERROR] [{}] Failed to acknowledge task from {}: {}", Local::now(), addr, e);
 		}
 	}
 	// RegisterCallback == 2u64
@@ -117,65 +189,70 @@ async fn handle_ping(mut stream: TcpStream, addr: SocketAddr) {
 }
 
 
-impl Callback {
-	pub async fn execute(&self, data: &serde_json::Value) -> Result<(), std::io::Error> {
-		match self {
-			Callback::Script {name, path} => {
-				println!("[INFO] [{}] Executing callback: {}", Local::now(), name);
+pub async fn execute_callback(callback: &Callback, data: &str, signature: &Option<PayloadSignature>, request_id: &Option<String>) -> Result<(), std::io::Error> {
+	match callback {
+		Callback::Script {name, path} => {
+			println!("[INFO] [{}] Executing callback: {}", Local::now(), name);
 
-				let script_base_path = std::env::var("CALLBACK_DATA_PATH").unwrap();
-				// FIXME: this is weird maybe
-				let prgm = match path.ends_with(".py") {
-					true => "python",
-					false => "node"
-				};
-				let serialized = serde_json::to_string(data).unwrap();
-
-				let result = Command::new(prgm)
-					.current_dir(script_base_path)
-					.args([path, &serialized])
-					.output()
-					.await?;
+			let script_base_path = std::env::var("CALLBACK_DATA_PATH").unwrap();
+			// FIXME: this is weird maybe
+			let prgm = match path.ends_with(".py") {
+				true => "python",
+				false => "node"
+			};
+			let mut command = Command::new(prgm);
+			command.current_dir(script_base_path).args([path, data]);
+			if let Some(signature) = signature {
+				command.env("ERP_TIMESTAMP", signature.timestamp.to_string())
+					.env("ERP_SIGNATURE", &signature.signature);
+			}
+			if let Some(request_id) = request_id {
+				command.env("ERP_REQUEST_ID", request_id);
+			}
+			let result = command.output().await?;
 
 
-				if !result.status.success() {
-					let err_msg = String::from_utf8(result.stderr).unwrap();
-					let exit_code = result.status.code().unwrap();
-					return Err(std::io::Error::new(ErrorKind::Other, 
-						format!("Code: {}, msg: {}", exit_code, err_msg)
-					));
-				}
+			if !result.status.success() {
+				let err_msg = String::from_utf8(result.stderr).unwrap();
+				let exit_code = result.status.code().unwrap();
+				return Err(std::io::Error::other(
+					format!("Code: {}, msg: {}", exit_code, err_msg)
+				));
+			}
 
-				let res_stdout = String::from_utf8(result.stdout).unwrap();
-				println!("[INFO] [{}] Callback {}. Stdout: {}", Local::now(), name, res_stdout);
+			let res_stdout = String::from_utf8(result.stdout).unwrap();
+			println!("[INFO] [{}] Callback {}. Stdout: {}", Local::now(), name, res_stdout);
 
-				return Ok(());
+			return Ok(());
+		}
+		Callback::Webhook { name, url, headers, timeout_ms } => {
+			let mut client = reqwest::Client::new().request(Method::POST, url);
+			if let Some(timeout_ms) = timeout_ms {
+				client = client.timeout(Duration::from_millis(*timeout_ms));
 			}
-			Callback::Webhook { name, url, headers } => {
-				let mut client = reqwest::Client::new().request(Method::POST, url);
-				// prepare headers
-				for (header_name, header_val) in headers {
-					client = client.header(header_name, header_val);
-				}
-				let res = client.json(data)
-				.send()
-				.await;
-				
-				if let Err(e) = res {
-					return Err(std::io::Error::new(ErrorKind::Other, e));
-				}
-
-				let res = res.unwrap();
-				println!("[INFO] [{}] Webhook {} returned StatusCode: {}, text: {:?}", Local::now(), name, res.status(), res.text().await);
-				return Ok(())
+			// prepare headers
+			for (header_name, header_val) in headers {
+				client = client.header(header_name, header_val);
+			}
+			if let Some(signature) = signature {
+				client = client.header(TIMESTAMP_HEADER, signature.timestamp)
+					.header(SIGNATURE_HEADER, &signature.signature);
+			}
+			if let Some(request_id) = request_id {
+				client = client.header(REQUEST_ID_HEADER, request_id);
+			}
+			let res = client.header(CONTENT_TYPE, "application/json")
+			.body(data.to_string())
+			.send()
+			.await;
+			
+			if let Err(e) = res {
+				return Err(std::io::Error::other(e));
 			}
-		}
-	}
 
-	pub fn name(self) -> String {
-		match self {
-			Callback::Script { name, .. } => name,
-			Callback::Webhook { name, .. } => name
+			let res = res.unwrap();
+			println!("[INFO] [{}] Webhook {} returned StatusCode: {}, text: {:?}", Local::now(), name, res.status(), res.text().await);
+			return Ok(())
 		}
 	}
-}
\ No newline at end of file
+}
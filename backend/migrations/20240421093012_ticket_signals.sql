@@ -0,0 +1,9 @@
+-- Add migration script here
+create table ticket_signals (
+	id serial primary key,
+	ticketid integer references tickets(id),
+	node_number int not null,
+	active boolean not null default true,
+	created_at timestamptz,
+	received_at timestamptz
+);
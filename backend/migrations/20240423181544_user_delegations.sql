@@ -0,0 +1,11 @@
+-- Add migration script here
+create table user_delegations (
+	id serial primary key,
+	userid uuid not null references users(userid),
+	delegate_id uuid not null references users(userid),
+	starts_at timestamptz not null,
+	ends_at timestamptz not null,
+	created_at timestamptz,
+	check (userid <> delegate_id),
+	check (starts_at < ends_at)
+);
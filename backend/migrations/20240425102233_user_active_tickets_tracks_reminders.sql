@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table user_active_tickets add created_at timestamptz not null default now();
+alter table user_active_tickets add last_reminded_at timestamptz;
@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table tickets add column instances jsonb not null default '{}';
+alter table user_active_tickets add instance int;
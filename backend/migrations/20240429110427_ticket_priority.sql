@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table tickets add priority int not null default 0;
+create index tickets_priority_idx on tickets (priority desc, created_at);
@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table tickets add due_at timestamptz;
+create index tickets_open_due_at_idx on tickets (due_at) where status='open';
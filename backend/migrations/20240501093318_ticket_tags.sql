@@ -0,0 +1,7 @@
+-- Add migration script here
+create table ticket_tags (
+	ticketid integer not null references tickets(id) on delete cascade,
+	tag varchar(64) not null,
+	primary key (ticketid, tag)
+);
+create index ticket_tags_tag_idx on ticket_tags (tag);
@@ -0,0 +1,7 @@
+-- Add migration script here
+create table ticket_watchers (
+	ticketid integer not null references tickets(id) on delete cascade,
+	userid uuid not null references users(userid),
+	created_at timestamptz not null default now(),
+	primary key (ticketid, userid)
+);
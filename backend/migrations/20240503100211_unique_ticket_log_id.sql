@@ -0,0 +1,3 @@
+-- Add migration script here
+-- log files are keyed by log_id so two tickets must never share one
+alter table tickets add constraint tickets_log_id_key unique (log_id);
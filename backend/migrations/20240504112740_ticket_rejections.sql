@@ -0,0 +1,10 @@
+-- Add migration script here
+create table ticket_rejections (
+	id serial primary key,
+	ticketid integer not null references tickets(id) on delete cascade,
+	node_number int not null,
+	userid uuid not null references users(userid),
+	reason text not null,
+	created_at timestamptz not null default now()
+);
+create index ticket_rejections_ticketid_idx on ticket_rejections (ticketid);
@@ -0,0 +1,15 @@
+-- Add migration script here
+create table ticket_schedules (
+	id serial primary key,
+	process_id varchar(255) not null,
+	owner_id uuid not null references users(userid),
+	cron text not null,
+	state jsonb not null default '{}',
+	is_public boolean not null default false,
+	priority int not null default 0,
+	active boolean not null default true,
+	next_run_at timestamptz not null,
+	last_run_at timestamptz,
+	created_at timestamptz not null default now()
+);
+create index ticket_schedules_due_idx on ticket_schedules (next_run_at) where active=true;
@@ -0,0 +1,34 @@
+-- Add migration script here
+-- closed tickets are moved here by the retention job. tags and rejections are kept inline since their tables cascade on delete
+create table tickets_archive (
+	id integer primary key,
+	owner_id uuid,
+	process_id varchar,
+	log_id uuid not null,
+	is_public boolean,
+	created_at timestamptz,
+	updated_at timestamptz,
+	status varchar(255),
+	complete integer,
+	state jsonb,
+	instances jsonb not null default '{}',
+	priority int not null default 0,
+	due_at timestamptz,
+	tags text[] not null default '{}',
+	rejections jsonb not null default '[]',
+	archived_at timestamptz not null default now()
+);
+create index tickets_archive_owner_idx on tickets_archive (owner_id);
+
+create table user_active_tickets_archive (
+	id integer primary key,
+	userid uuid,
+	ticketid integer not null,
+	active boolean,
+	node_number int not null,
+	type_ varchar(255) not null,
+	instance int,
+	created_at timestamptz,
+	last_reminded_at timestamptz
+);
+create index user_active_tickets_archive_ticketid_idx on user_active_tickets_archive (ticketid);
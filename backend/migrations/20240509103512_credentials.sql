@@ -0,0 +1,23 @@
+-- Add migration script here
+create table credentials (
+	userid uuid primary key references users(userid),
+	password_hash varchar not null,
+	updated_at timestamptz not null
+);
+
+-- hashed at registration and moved to credentials when the user is approved
+alter table new_users add password_hash varchar;
+
+create table login_attempts (
+	id serial primary key,
+	username varchar not null,
+	success boolean not null,
+	created_at timestamptz not null
+);
+create index login_attempts_username_idx on login_attempts (username, created_at);
+
+-- tokens ended with /auth/logout, kept until they would have expired anyway
+create table revoked_tokens (
+	jti uuid primary key,
+	expires_at timestamptz not null
+);
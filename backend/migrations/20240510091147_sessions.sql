@@ -0,0 +1,18 @@
+-- Add migration script here
+-- one row per login. access tokens carry the session id and refresh tokens rotate on every use
+create table sessions (
+	id uuid primary key,
+	userid uuid not null references users(userid),
+	-- sha256 of the secret part of the current refresh token
+	refresh_hash varchar not null,
+	-- hash of the token it replaced. presenting it again means the token was stolen
+	previous_hash varchar,
+	created_at timestamptz not null,
+	last_used_at timestamptz not null,
+	expires_at timestamptz not null,
+	revoked_at timestamptz
+);
+create index sessions_userid_idx on sessions (userid);
+
+-- replaced by sessions.revoked_at
+drop table revoked_tokens;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- the assignment table gets a name that says what it holds
+alter table roles rename to user_roles;
+
+insert into role_defs (role_) values ('admin') on conflict do nothing;
+
+-- permission matrix. '*' grants every permission
+create table role_permissions (
+	role_ varchar not null references role_defs(role_) on delete cascade,
+	permission varchar not null,
+	primary key (role_, permission)
+);
+insert into role_permissions (role_, permission) values ('admin', '*');
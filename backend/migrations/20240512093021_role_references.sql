@@ -0,0 +1,13 @@
+-- Add migration script here
+-- process_defs.allowed_roles is an array so it cannot have a foreign key. assignments are covered by the one on user_roles
+create function check_role_unreferenced() returns trigger as $$
+begin
+	if exists (select 1 from process_defs where old.role_ = any(allowed_roles)) then
+		raise exception 'role % is used by a process definition', old.role_ using errcode = 'foreign_key_violation';
+	end if;
+	return old;
+end;
+$$ language plpgsql;
+
+create trigger role_defs_unreferenced before delete on role_defs
+	for each row execute function check_role_unreferenced();
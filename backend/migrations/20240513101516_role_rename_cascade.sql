@@ -0,0 +1,7 @@
+-- Add migration script here
+-- renaming a role carries its assignments and permissions along
+alter table user_roles drop constraint roles_role__fkey;
+alter table user_roles add constraint user_roles_role__fkey foreign key (role_) references role_defs(role_) on update cascade;
+
+alter table role_permissions drop constraint role_permissions_role__fkey;
+alter table role_permissions add constraint role_permissions_role__fkey foreign key (role_) references role_defs(role_) on update cascade on delete cascade;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- keys used by the callback and notifier servers and other external systems. the key is "<id>.<secret>", only the hash of the secret is stored
+create table api_keys (
+	id uuid primary key,
+	name varchar not null,
+	key_hash varchar not null,
+	scopes varchar[] not null default '{}',
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	rotated_at timestamptz,
+	last_used_at timestamptz,
+	revoked_at timestamptz
+);
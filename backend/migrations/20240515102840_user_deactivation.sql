@@ -0,0 +1,4 @@
+-- Add migration script here
+-- users are deactivated instead of deleted so tickets, logs and rejections keep pointing at them
+alter table users add active boolean not null default true;
+alter table users add deactivated_at timestamptz;
@@ -0,0 +1,18 @@
+-- Add migration script here
+create table departments (
+	id serial primary key,
+	name varchar not null unique,
+	-- approvals for the manager of a department go to the manager of its parent
+	parent_id integer references departments(id) on delete set null,
+	manager_id uuid references users(userid),
+	created_at timestamptz not null
+);
+
+create table user_departments (
+	userid uuid not null references users(userid),
+	department_id integer not null references departments(id) on delete cascade,
+	-- the department used to route approvals of users in more than one department
+	is_primary boolean not null default false,
+	primary key (userid, department_id)
+);
+create unique index user_departments_primary on user_departments (userid) where is_primary;
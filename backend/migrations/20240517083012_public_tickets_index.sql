@@ -0,0 +1,3 @@
+-- Add migration script here
+-- GET /tickets/public lists public tickets newest first
+create index tickets_public_created_at on tickets (created_at desc, id desc) where is_public;
@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- notifications are kept after the notifier delivered them so users can page through them and mark them read
+alter table notifications add column delivered_at timestamptz;
+alter table notifications add column read_at timestamptz;
+
+update notifications set created_at=now() where created_at is null;
+alter table notifications alter column created_at set default now();
+alter table notifications alter column created_at set not null;
+
+create index notifications_user_idx on notifications (userid, created_at desc, id desc);
+create index notifications_unread_idx on notifications (userid) where read_at is null;
+create index notifications_undelivered_idx on notifications (id) where delivered_at is null;
@@ -0,0 +1,17 @@
+-- Add migration script here
+
+-- the language notifications are rendered in for the user
+alter table users add column locale varchar not null default 'en';
+
+-- a template without a node applies to every notify node of the process
+create table notification_templates (
+	id serial primary key,
+	process_id varchar not null references process_defs(process_id) on delete cascade,
+	node int,
+	locale varchar not null,
+	template text not null,
+	updated_by uuid references users(userid),
+	updated_at timestamptz not null default now()
+);
+
+create unique index notification_templates_key on notification_templates (process_id, coalesce(node, -1), locale);
@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- users in digest mode get their non-urgent notifications as one summary per digest interval
+alter table users add column notification_digest boolean not null default false;
+
+alter table notifications add column urgent boolean not null default false;
+-- set on summary rows, the number of notifications the summary covers
+alter table notifications add column digest_count int;
+
+create index notifications_held_idx on notifications (userid) where delivered_at is null and urgent=false and digest_count is null;
@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- the slack member id of the user, used to map slack button presses to users
+alter table users add column slack_user_id varchar unique;
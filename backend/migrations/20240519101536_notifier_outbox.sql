@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- a row is written in the same transaction as new notifications and removed once the notifier acknowledged a ping
+create table notifier_outbox (
+	id bigserial primary key,
+	created_at timestamptz not null default now(),
+	attempts int not null default 0,
+	next_attempt_at timestamptz not null default now(),
+	last_error text
+);
+
+create index notifier_outbox_due_idx on notifier_outbox (next_attempt_at);
@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- notify nodes sending the same template about the same ticket to a user within the dedup window share one row
+alter table notifications add column ticket_id int;
+alter table notifications add column dedup_key varchar;
+alter table notifications add column occurrences int not null default 1;
+
+create index notifications_dedup_idx on notifications (userid, ticket_id, dedup_key) where read_at is null and dedup_key is not null;
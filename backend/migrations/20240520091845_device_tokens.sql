@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- phones of users that receive push notifications. a token belongs to the user that registered it last
+create table device_tokens (
+	id serial primary key,
+	userid uuid not null references users(userid) on delete cascade,
+	platform varchar not null check (platform in ('fcm', 'apns')),
+	token varchar not null unique,
+	created_at timestamptz not null default now(),
+	last_seen_at timestamptz not null default now()
+);
+
+create index device_tokens_user_idx on device_tokens (userid);
@@ -0,0 +1,18 @@
+-- Add migration script here
+
+-- callbacks of reached nodes. written in the ticket transaction and sent to the callback server by a retrying worker
+create table callback_jobs (
+	id bigserial primary key,
+	ticket_id int not null,
+	node int not null,
+	payload jsonb,
+	callbacks jsonb not null,
+	status varchar not null default 'pending' check (status in ('pending', 'sent')),
+	attempts int not null default 0,
+	next_attempt_at timestamptz not null default now(),
+	last_error text,
+	created_at timestamptz not null default now(),
+	sent_at timestamptz
+);
+
+create index callback_jobs_due_idx on callback_jobs (next_attempt_at) where status='pending';
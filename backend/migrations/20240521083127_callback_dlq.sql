@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- callback jobs that failed too often. admins requeue them with /admin/callbacks/{id}/retry
+create table callback_dlq (
+	id bigint primary key,
+	ticket_id int not null,
+	node int not null,
+	payload jsonb,
+	callbacks jsonb not null,
+	attempts int not null,
+	last_error text,
+	created_at timestamptz not null,
+	failed_at timestamptz not null default now()
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- shared secret the callback payloads of a process are signed with
+alter table process_defs add column callback_secret varchar;
+update process_defs set callback_secret = md5(random()::text || clock_timestamp()::text) || md5(random()::text || process_id);
+alter table process_defs alter column callback_secret set not null;
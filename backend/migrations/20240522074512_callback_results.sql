@@ -0,0 +1,6 @@
+-- Add migration script here
+-- outcome reported by the callback server on /ticket/:id/callback_result. a job is resolved once
+alter table callback_jobs add column result varchar check (result in ('completed', 'failed'));
+-- the node data of a completed task or the error payload of a failed one
+alter table callback_jobs add column result_data jsonb;
+alter table callback_jobs add column resolved_at timestamptz;
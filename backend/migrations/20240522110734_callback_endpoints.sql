@@ -0,0 +1,16 @@
+-- Add migration script here
+
+-- overrides the target of a webhook callback of a process file by its name. a row without a node applies to every node
+create table callback_endpoints (
+	id serial primary key,
+	process_id varchar not null references process_defs(process_id) on delete cascade,
+	node int,
+	name varchar not null,
+	url varchar not null,
+	headers jsonb not null default '{}',
+	timeout_ms int check (timeout_ms > 0),
+	updated_by uuid references users(userid),
+	updated_at timestamptz not null default now()
+);
+
+create unique index callback_endpoints_key on callback_endpoints (process_id, coalesce(node, -1), name);
@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- every attempt to hand a callback job to its transport
+create table callback_log (
+	id bigserial primary key,
+	job_id bigint not null,
+	ticket_id int not null,
+	node int not null,
+	target varchar not null,
+	-- sha256 of the signed task payload
+	payload_hash varchar not null,
+	attempt int not null,
+	status varchar not null check (status in ('sent', 'failed')),
+	error text,
+	latency_ms int not null,
+	created_at timestamptz not null default now()
+);
+
+create index callback_log_ticket_idx on callback_log (ticket_id, created_at);
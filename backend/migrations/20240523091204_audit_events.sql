@@ -0,0 +1,31 @@
+-- Add migration script here
+
+-- who changed approvals, assignments and roles. rows are only ever inserted
+create table audit_events (
+	id bigserial primary key,
+	-- null when a service made the change
+	actor uuid,
+	action varchar not null,
+	entity_type varchar not null,
+	entity_id varchar not null,
+	before jsonb,
+	after jsonb,
+	created_at timestamptz not null default now(),
+	-- set when AUDIT_HASH_CHAIN is on. hash covers the event and the hash of the event before it
+	prev_hash varchar,
+	hash varchar
+);
+
+create index audit_events_entity_idx on audit_events (entity_type, entity_id, id);
+create index audit_events_actor_idx on audit_events (actor, id);
+
+create function audit_events_append_only() returns trigger as $$
+begin
+	raise exception 'audit_events is append only';
+end;
+$$ language plpgsql;
+
+create trigger audit_events_no_update before update or delete on audit_events
+	for each row execute function audit_events_append_only();
+create trigger audit_events_no_truncate before truncate on audit_events
+	for each statement execute function audit_events_append_only();
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- X-Request-Id of the request that queued the row, forwarded to the callback workers and the notifier
+alter table callback_jobs add column request_id varchar;
+alter table callback_dlq add column request_id varchar;
+alter table notifier_outbox add column request_id varchar;
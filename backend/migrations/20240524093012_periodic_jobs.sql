@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- periodic work shared by every server instance. an instance leases a due job before running it
+create table periodic_jobs (
+	name varchar primary key,
+	next_run_at timestamptz not null,
+	-- instance running the job. the lease expires if it dies before finishing
+	leased_by uuid,
+	leased_until timestamptz,
+	last_run_at timestamptz,
+	last_error text
+);
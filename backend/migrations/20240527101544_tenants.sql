@@ -0,0 +1,94 @@
+-- Add migration script here
+
+-- subsidiaries hosted on the same deployment. everything that existed before belongs to the default tenant
+create table tenants (
+	id serial primary key,
+	name varchar not null unique,
+	created_at timestamptz not null default now()
+);
+insert into tenants (id, name) values (1, 'default');
+select setval('tenants_id_seq', 1);
+
+-- tenant of the request using the connection, set by the server on every acquire. null for background work,
+-- logins and anything else that is not scoped to a tenant
+create function current_tenant() returns int as $$
+	select nullif(current_setting('app.tenant_id', true), '')::int
+$$ language sql stable;
+
+alter table users add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table role_defs add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table process_defs add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table api_keys add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table tickets add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table tickets_archive add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table notifications add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+
+create index users_tenant_idx on users (tenant_id);
+create index tickets_tenant_idx on tickets (tenant_id);
+create index tickets_archive_tenant_idx on tickets_archive (tenant_id);
+create index notifications_tenant_idx on notifications (tenant_id, userid);
+
+-- rows written by background work take the tenant of the user they belong to
+create function tenant_of_owner() returns trigger as $$
+begin
+	if current_tenant() is null then
+		new.tenant_id := coalesce((select tenant_id from users where userid=new.owner_id), new.tenant_id);
+	end if;
+	return new;
+end;
+$$ language plpgsql;
+
+create function tenant_of_user() returns trigger as $$
+begin
+	if current_tenant() is null then
+		new.tenant_id := coalesce((select tenant_id from users where userid=new.userid), new.tenant_id);
+	end if;
+	return new;
+end;
+$$ language plpgsql;
+
+create trigger tickets_tenant before insert on tickets for each row execute function tenant_of_owner();
+create trigger notifications_tenant before insert on notifications for each row execute function tenant_of_user();
+
+-- forced so the server's own role, which owns the tables, is filtered as well
+alter table users enable row level security;
+alter table users force row level security;
+create policy users_tenant on users
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table role_defs enable row level security;
+alter table role_defs force row level security;
+create policy role_defs_tenant on role_defs
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table process_defs enable row level security;
+alter table process_defs force row level security;
+create policy process_defs_tenant on process_defs
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table api_keys enable row level security;
+alter table api_keys force row level security;
+create policy api_keys_tenant on api_keys
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table tickets enable row level security;
+alter table tickets force row level security;
+create policy tickets_tenant on tickets
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table tickets_archive enable row level security;
+alter table tickets_archive force row level security;
+create policy tickets_archive_tenant on tickets_archive
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table notifications enable row level security;
+alter table notifications force row level security;
+create policy notifications_tenant on notifications
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,38 @@
+-- Add migration script here
+
+-- external systems told about ticket lifecycle events. process_id null means every process
+create table webhook_subscriptions (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	url varchar not null,
+	events text[] not null,
+	process_id varchar references process_defs(process_id) on delete cascade,
+	-- deliveries are signed with it like callback tasks, see callbacks::sign_payload
+	secret varchar not null,
+	created_by uuid references users(userid) on delete set null,
+	created_at timestamptz not null
+);
+create index webhook_subscriptions_tenant_idx on webhook_subscriptions (tenant_id);
+
+alter table webhook_subscriptions enable row level security;
+alter table webhook_subscriptions force row level security;
+create policy webhook_subscriptions_tenant on webhook_subscriptions
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+-- written in the ticket transaction and sent by the webhook worker. status is pending, delivered or failed
+create table webhook_deliveries (
+	id bigserial primary key,
+	subscription_id int not null references webhook_subscriptions(id) on delete cascade,
+	event varchar not null,
+	payload jsonb not null,
+	status varchar not null default 'pending',
+	attempts int not null default 0,
+	next_attempt_at timestamptz not null,
+	last_error text,
+	response_status int,
+	created_at timestamptz not null,
+	delivered_at timestamptz
+);
+create index webhook_deliveries_due_idx on webhook_deliveries (next_attempt_at) where status='pending';
+create index webhook_deliveries_subscription_idx on webhook_deliveries (subscription_id, id);
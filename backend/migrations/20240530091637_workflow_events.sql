@@ -0,0 +1,15 @@
+-- Add migration script here
+
+-- engine transitions waiting to be published to the event stream, written in the ticket transaction.
+-- rows are deleted once the stream stored them, only written while EVENT_PUBLISHER is set
+create table workflow_events (
+	id bigserial primary key,
+	ticket_id int not null,
+	kind varchar not null,
+	payload jsonb not null,
+	created_at timestamptz not null,
+	attempts int not null default 0,
+	next_attempt_at timestamptz not null,
+	last_error text
+);
+create index workflow_events_due_idx on workflow_events (next_attempt_at, id);
@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- secret of the calendar feed url of the user, null while the feed is turned off. only the hash is stored
+alter table users add column calendar_token_hash varchar unique;
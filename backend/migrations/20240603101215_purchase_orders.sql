@@ -0,0 +1,38 @@
+-- Add migration script here
+
+-- approved through a ticket of the purchase order process. status follows the nodes of the ticket, see linked.rs
+create table purchase_orders (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	vendor varchar not null,
+	currency varchar(3) not null,
+	-- sum of the lines, in the smallest unit of the currency
+	total_cents bigint not null,
+	-- pending_approval, approved, sent, received, closed, rejected or cancelled
+	status varchar not null default 'pending_approval',
+	notes text,
+	-- no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null
+);
+create index purchase_orders_tenant_idx on purchase_orders (tenant_id);
+create index purchase_orders_created_by_idx on purchase_orders (created_by, id);
+
+alter table purchase_orders enable row level security;
+alter table purchase_orders force row level security;
+create policy purchase_orders_tenant on purchase_orders
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+create table purchase_order_lines (
+	id serial primary key,
+	purchase_order_id int not null references purchase_orders(id) on delete cascade,
+	line_number int not null,
+	description varchar not null,
+	quantity int not null,
+	unit_price_cents bigint not null,
+	total_cents bigint not null,
+	unique (purchase_order_id, line_number)
+);
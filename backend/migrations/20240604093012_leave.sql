@@ -0,0 +1,51 @@
+-- Add migration script here
+
+-- days of leave of a type a user may take in a year. approved requests add to used_days, see leave.rs
+create table leave_balances (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	userid uuid not null references users(userid) on delete cascade,
+	-- e.g. vacation, sick
+	leave_type varchar not null,
+	year int not null,
+	total_days int not null check (total_days >= 0),
+	used_days int not null default 0 check (used_days >= 0),
+	updated_at timestamptz not null,
+	unique (userid, leave_type, year),
+	check (used_days <= total_days)
+);
+create index leave_balances_tenant_idx on leave_balances (tenant_id);
+
+alter table leave_balances enable row level security;
+alter table leave_balances force row level security;
+create policy leave_balances_tenant on leave_balances
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+-- approved through a ticket of the leave process
+create table leave_requests (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	userid uuid not null references users(userid),
+	leave_type varchar not null,
+	start_date date not null,
+	end_date date not null,
+	-- working days between the dates, taken from the balance of the year of start_date
+	days int not null,
+	reason text,
+	-- pending_approval, approved, rejected or cancelled
+	status varchar not null default 'pending_approval',
+	-- no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	check (start_date <= end_date)
+);
+create index leave_requests_tenant_idx on leave_requests (tenant_id);
+create index leave_requests_userid_idx on leave_requests (userid, start_date);
+
+alter table leave_requests enable row level security;
+alter table leave_requests force row level security;
+create policy leave_requests_tenant on leave_requests
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,62 @@
+-- Add migration script here
+
+-- customer invoices. approved through a ticket of the invoice process before they can be sent, see invoices.rs
+create table invoices (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	-- given when the invoice is sent
+	number varchar unique,
+	customer_name varchar not null,
+	customer_address text,
+	currency varchar(3) not null,
+	-- amounts in the smallest unit of the currency
+	subtotal_cents bigint not null,
+	-- in basis points, 1900 is 19%
+	tax_rate_bp int not null,
+	tax_cents bigint not null,
+	total_cents bigint not null,
+	paid_cents bigint not null default 0,
+	issue_date date,
+	due_date date not null,
+	-- draft, approved, sent, paid, overdue, rejected or cancelled
+	status varchar not null default 'draft',
+	notes text,
+	-- no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	check (paid_cents <= total_cents)
+);
+create index invoices_tenant_idx on invoices (tenant_id);
+create index invoices_status_idx on invoices (status, due_date);
+
+alter table invoices enable row level security;
+alter table invoices force row level security;
+create policy invoices_tenant on invoices
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+create table invoice_lines (
+	id serial primary key,
+	invoice_id int not null references invoices(id) on delete cascade,
+	line_number int not null,
+	description varchar not null,
+	quantity int not null,
+	unit_price_cents bigint not null,
+	total_cents bigint not null,
+	unique (invoice_id, line_number)
+);
+
+create table invoice_payments (
+	id serial primary key,
+	invoice_id int not null references invoices(id) on delete cascade,
+	amount_cents bigint not null check (amount_cents > 0),
+	paid_on date not null,
+	-- e.g. bank transfer
+	method varchar,
+	reference varchar,
+	recorded_by uuid not null references users(userid),
+	created_at timestamptz not null
+);
+create index invoice_payments_invoice_idx on invoice_payments (invoice_id);
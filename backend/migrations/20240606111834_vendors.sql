@@ -0,0 +1,31 @@
+-- Add migration script here
+
+-- suppliers. usable once their onboarding ticket is approved, see vendors.rs
+create table vendors (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	name varchar not null,
+	tax_id varchar,
+	email varchar,
+	phone varchar,
+	address text,
+	bank_account_holder varchar not null,
+	-- stored without spaces, upper case
+	iban varchar(34) not null,
+	bic varchar(11),
+	-- pending_onboarding, active, rejected, cancelled or inactive
+	status varchar not null default 'pending_onboarding',
+	-- of the latest onboarding. no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	unique (tenant_id, name)
+);
+create index vendors_status_idx on vendors (tenant_id, status);
+
+alter table vendors enable row level security;
+alter table vendors force row level security;
+create policy vendors_tenant on vendors
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,77 @@
+-- Add migration script here
+
+-- company equipment. assigned by the callback of the asset request and return processes, see assets.rs
+create table assets (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	-- inventory label, e.g. LT-0042
+	tag varchar not null,
+	name varchar not null,
+	-- laptop, monitor, phone, ...
+	category varchar not null,
+	serial_number varchar,
+	-- available, assigned or retired
+	status varchar not null default 'available',
+	assigned_to uuid references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	unique (tenant_id, tag),
+	check ((status='assigned') = (assigned_to is not null))
+);
+create index assets_category_idx on assets (tenant_id, category, status);
+
+-- asking for an asset of a category, or giving one back
+create table asset_requests (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	-- request or return
+	kind varchar not null check (kind in ('request', 'return')),
+	userid uuid not null references users(userid),
+	-- what was asked for. null for returns
+	category varchar,
+	-- the asset given back, or the one handed out once the request is fulfilled
+	asset_id int references assets(id),
+	reason text,
+	-- pending_approval, approved, closed, rejected or cancelled
+	status varchar not null default 'pending_approval',
+	fulfilled_at timestamptz,
+	-- why the last fulfilment failed, e.g. nothing of the category was available
+	fulfil_error text,
+	-- no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_at timestamptz not null,
+	updated_at timestamptz not null
+);
+create index asset_requests_userid_idx on asset_requests (userid);
+
+-- who had an asset when
+create table asset_assignments (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	asset_id int not null references assets(id),
+	userid uuid not null references users(userid),
+	request_id int references asset_requests(id),
+	return_request_id int references asset_requests(id),
+	assigned_at timestamptz not null,
+	returned_at timestamptz
+);
+create index asset_assignments_asset_idx on asset_assignments (asset_id, assigned_at);
+create unique index asset_assignments_open_idx on asset_assignments (asset_id) where returned_at is null;
+
+alter table assets enable row level security;
+alter table assets force row level security;
+create policy assets_tenant on assets
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table asset_requests enable row level security;
+alter table asset_requests force row level security;
+create policy asset_requests_tenant on asset_requests
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table asset_assignments enable row level security;
+alter table asset_assignments force row level security;
+create policy asset_assignments_tenant on asset_assignments
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
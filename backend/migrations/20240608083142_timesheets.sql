@@ -0,0 +1,42 @@
+-- Add migration script here
+
+-- the hours someone worked in a week. approved by the manager of their department, see timesheets.rs
+create table timesheets (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	userid uuid not null references users(userid),
+	-- monday of the week
+	week_start date not null check (extract(isodow from week_start) = 1),
+	-- draft, submitted, approved, rejected or cancelled. approved weeks cannot be changed anymore
+	status varchar not null default 'draft',
+	-- of the latest submission. no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	submitted_at timestamptz,
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	unique (tenant_id, userid, week_start)
+);
+
+create table timesheet_entries (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	timesheet_id int not null references timesheets(id) on delete cascade,
+	project varchar not null,
+	work_date date not null,
+	minutes int not null check (minutes > 0 and minutes <= 1440),
+	note text,
+	unique (timesheet_id, project, work_date)
+);
+create index timesheet_entries_project_idx on timesheet_entries (tenant_id, project, work_date);
+
+alter table timesheets enable row level security;
+alter table timesheets force row level security;
+create policy timesheets_tenant on timesheets
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table timesheet_entries enable row level security;
+alter table timesheet_entries force row level security;
+create policy timesheet_entries_tenant on timesheet_entries
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
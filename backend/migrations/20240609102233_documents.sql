@@ -0,0 +1,86 @@
+-- Add migration script here
+
+-- versioned files. changed by checking them out and checking a new version in, approved through a ticket, see documents.rs
+create table documents (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	title varchar not null,
+	-- contract, invoice, id_document, ... process steps can require an approved document of a type
+	doc_type varchar not null,
+	owner_id uuid not null references users(userid),
+	-- draft, pending_approval, approved, rejected or cancelled. checking in a new version makes it a draft again
+	status varchar not null default 'draft',
+	-- 0 until the first version is checked in
+	current_version int not null default 0,
+	checked_out_by uuid references users(userid),
+	checked_out_at timestamptz,
+	-- of the latest approval. no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_at timestamptz not null,
+	updated_at timestamptz not null
+);
+create index documents_owner_idx on documents (owner_id);
+
+create table document_versions (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	document_id int not null references documents(id) on delete cascade,
+	version int not null,
+	filename varchar not null,
+	content_type varchar not null,
+	size_bytes int not null,
+	-- hex sha-256 of the content
+	sha256 varchar(64) not null,
+	content bytea not null,
+	comment text,
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	unique (document_id, version)
+);
+
+-- who besides the owner can read or change a document. either a user or a role
+create table document_acl (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	document_id int not null references documents(id) on delete cascade,
+	userid uuid references users(userid),
+	role_ varchar references role_defs(role_) on update cascade on delete cascade,
+	can_write boolean not null default false,
+	check (num_nonnulls(userid, role_) = 1)
+);
+create index document_acl_document_idx on document_acl (document_id);
+
+-- documents attached to tickets. no foreign key on the ticket, it may be moved to tickets_archive
+create table document_attachments (
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	document_id int not null references documents(id) on delete cascade,
+	ticket_id int not null,
+	attached_by uuid not null references users(userid),
+	attached_at timestamptz not null,
+	primary key (document_id, ticket_id)
+);
+create index document_attachments_ticket_idx on document_attachments (ticket_id);
+
+alter table documents enable row level security;
+alter table documents force row level security;
+create policy documents_tenant on documents
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table document_versions enable row level security;
+alter table document_versions force row level security;
+create policy document_versions_tenant on document_versions
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table document_acl enable row level security;
+alter table document_acl force row level security;
+create policy document_acl_tenant on document_acl
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table document_attachments enable row level security;
+alter table document_attachments force row level security;
+create policy document_attachments_tenant on document_attachments
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
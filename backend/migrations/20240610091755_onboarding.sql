@@ -0,0 +1,72 @@
+-- Add migration script here
+
+-- what onboarding and offboarding look like for the members of a department, see onboarding.rs
+create table onboarding_configs (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	department_id int not null references departments(id) on delete cascade,
+	-- onboarding or offboarding
+	kind varchar not null check (kind in ('onboarding', 'offboarding')),
+	-- null for the built-in process of the kind
+	process_id varchar,
+	-- an asset of each is handed out to new employees
+	asset_categories varchar[] not null default '{}',
+	-- documents new employees acknowledge
+	policy_ids int[] not null default '{}',
+	updated_by uuid references users(userid),
+	updated_at timestamptz not null,
+	unique (department_id, kind)
+);
+
+-- an employee joining or leaving. the configuration of the department is copied when it starts
+create table onboardings (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	kind varchar not null check (kind in ('onboarding', 'offboarding')),
+	userid uuid not null references users(userid),
+	department_id int references departments(id) on delete set null,
+	-- first or last working day
+	start_date date not null,
+	process_id varchar not null,
+	asset_categories varchar[] not null,
+	policy_ids int[] not null,
+	-- in_progress, completed, rejected or cancelled
+	status varchar not null default 'in_progress',
+	-- no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null
+);
+create unique index onboardings_in_progress_idx on onboardings (userid, kind) where status='in_progress';
+
+create table policy_acknowledgments (
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	onboarding_id int not null references onboardings(id) on delete cascade,
+	document_id int not null references documents(id),
+	-- the version that was read
+	version int not null,
+	acknowledged_at timestamptz not null,
+	primary key (onboarding_id, document_id)
+);
+
+-- the equipment handed out or taken back by an onboarding has no ticket of its own
+alter table asset_requests add column onboarding_id int references onboardings(id);
+
+alter table onboarding_configs enable row level security;
+alter table onboarding_configs force row level security;
+create policy onboarding_configs_tenant on onboarding_configs
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table onboardings enable row level security;
+alter table onboardings force row level security;
+create policy onboardings_tenant on onboardings
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table policy_acknowledgments enable row level security;
+alter table policy_acknowledgments force row level security;
+create policy policy_acknowledgments_tenant on policy_acknowledgments
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,97 @@
+-- Add migration script here
+
+-- the companies sold to, see crm.rs
+create table customers (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	name varchar not null,
+	tax_id varchar,
+	email varchar,
+	phone varchar,
+	address text,
+	-- active or inactive
+	status varchar not null default 'active',
+	created_by uuid not null references users(userid),
+	created_at timestamptz not null,
+	updated_at timestamptz not null,
+	unique (tenant_id, name)
+);
+
+create table customer_contacts (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	customer_id int not null references customers(id) on delete cascade,
+	name varchar not null,
+	job_title varchar,
+	email varchar,
+	phone varchar,
+	is_primary boolean not null default false
+);
+create index customer_contacts_customer_idx on customer_contacts (customer_id);
+create unique index customer_contacts_primary on customer_contacts (customer_id) where is_primary;
+
+-- a possible sale. moving it to proposal or won with a discount over the threshold waits for a discount approval ticket
+create table opportunities (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	customer_id int not null references customers(id),
+	contact_id int references customer_contacts(id) on delete set null,
+	title varchar not null,
+	currency varchar(3) not null,
+	-- before the discount, in the smallest unit of the currency
+	amount_cents bigint not null check (amount_cents >= 0),
+	-- in basis points, 1500 is 15%
+	discount_bp int not null default 0 check (discount_bp between 0 and 10000),
+	-- the highest discount approved so far. moves up to it need no new approval
+	approved_discount_bp int,
+	-- lead, qualified, proposal, won or lost
+	stage varchar not null default 'lead',
+	-- the stage waiting for the discount approval of ticket_id
+	pending_stage varchar,
+	expected_close_date date,
+	owner_id uuid not null references users(userid),
+	-- of the latest discount approval. no foreign key, the ticket may be moved to tickets_archive
+	ticket_id int unique,
+	closed_at timestamptz,
+	created_at timestamptz not null,
+	updated_at timestamptz not null
+);
+create index opportunities_customer_idx on opportunities (customer_id);
+create index opportunities_stage_idx on opportunities (tenant_id, stage);
+
+create table opportunity_stage_changes (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	opportunity_id int not null references opportunities(id) on delete cascade,
+	from_stage varchar not null,
+	to_stage varchar not null,
+	-- null when the approval ticket moved it
+	changed_by uuid references users(userid),
+	ticket_id int,
+	changed_at timestamptz not null
+);
+create index opportunity_stage_changes_opportunity_idx on opportunity_stage_changes (opportunity_id);
+
+alter table customers enable row level security;
+alter table customers force row level security;
+create policy customers_tenant on customers
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table customer_contacts enable row level security;
+alter table customer_contacts force row level security;
+create policy customer_contacts_tenant on customer_contacts
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table opportunities enable row level security;
+alter table opportunities force row level security;
+create policy opportunities_tenant on opportunities
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table opportunity_stage_changes enable row level security;
+alter table opportunity_stage_changes force row level security;
+create policy opportunity_stage_changes_tenant on opportunity_stage_changes
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- the highest state.amount a holder of the role can approve, see limits.rs. roles without a row are not limited
+create table role_limits (
+	role_ varchar primary key references role_defs(role_) on update cascade on delete cascade,
+	max_amount numeric(18, 2) not null check (max_amount >= 0),
+	updated_by uuid references users(userid),
+	updated_at timestamptz not null
+);
+
+-- approvals handed to the next manager up because the amount was over the limit of the approver.
+-- no foreign key on the ticket, it may be moved to tickets_archive
+create table approval_escalations (
+	id serial primary key,
+	ticket_id int not null,
+	node_number int not null,
+	instance int,
+	approver uuid not null references users(userid),
+	escalated_to uuid not null references users(userid),
+	amount numeric(18, 2) not null,
+	max_amount numeric(18, 2) not null,
+	created_at timestamptz not null
+);
+create index approval_escalations_ticket_idx on approval_escalations (ticket_id);
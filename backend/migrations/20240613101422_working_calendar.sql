@@ -0,0 +1,41 @@
+-- Add migration script here
+
+-- working hours of the organization, the row without a department, and of departments working differently.
+-- deadlines and reminders count time inside them only, see working_time.rs
+create table work_calendars (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	department_id int references departments(id) on delete cascade,
+	-- iso days of the week, 1 is monday
+	workdays int[] not null,
+	day_start time not null,
+	day_end time not null,
+	-- the times are local to this offset from utc
+	utc_offset_minutes int not null default 0 check (utc_offset_minutes between -840 and 840),
+	updated_by uuid references users(userid),
+	updated_at timestamptz not null,
+	check (day_start < day_end)
+);
+create unique index work_calendars_department_idx on work_calendars (tenant_id, coalesce(department_id, 0));
+
+-- days nobody works. without a department they apply to the whole organization
+create table public_holidays (
+	id serial primary key,
+	tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id),
+	department_id int references departments(id) on delete cascade,
+	day date not null,
+	name varchar not null
+);
+create unique index public_holidays_day_idx on public_holidays (tenant_id, coalesce(department_id, 0), day);
+
+alter table work_calendars enable row level security;
+alter table work_calendars force row level security;
+create policy work_calendars_tenant on work_calendars
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
+
+alter table public_holidays enable row level security;
+alter table public_holidays force row level security;
+create policy public_holidays_tenant on public_holidays
+	using (current_tenant() is null or tenant_id=current_tenant())
+	with check (current_tenant() is null or tenant_id=current_tenant());
@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- the windows of /admin/dashboard
+create index tickets_created_at_idx on tickets (created_at);
+create index tickets_archive_created_at_idx on tickets_archive (created_at);
+create index audit_events_action_created_at_idx on audit_events (action, created_at);
+create index callback_log_failed_idx on callback_log (created_at) where status='failed';
+create index user_active_tickets_approve_idx on user_active_tickets (ticketid, node_number) where type_='approve';
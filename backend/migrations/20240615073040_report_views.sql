@@ -0,0 +1,80 @@
+-- Add migration script here
+
+-- summaries the reports read instead of the ticket tables, refreshed by the reports job (see reports.rs).
+-- materialized views have no row level security, every one carries the tenant and the reports filter by it
+create materialized view report_ticket_counts as
+	select tenant_id, (created_at at time zone 'utc')::date as day, process_id, status, count(*) as tickets
+	from (select tenant_id, created_at, process_id, status from tickets
+		union all select tenant_id, created_at, process_id, status from tickets_archive) t
+	group by 1, 2, 3, 4;
+create unique index report_ticket_counts_idx on report_ticket_counts (tenant_id, day, process_id, status);
+
+-- from the first request on the node, escalations included, to the approval that did not escalate further
+create materialized view report_approval_times as
+	select a.id, tt.tenant_id, a.created_at as approved_at, extract(epoch from a.created_at - r.requested_at)::float8 as seconds
+	from audit_events a
+	cross join lateral (
+		select tenant_id from tickets where id=a.entity_id::int
+		union all select tenant_id from tickets_archive where id=a.entity_id::int
+		limit 1
+	) tt
+	cross join lateral (
+		select min(created_at) as requested_at from (
+			select created_at from user_active_tickets
+				where type_='approve' and ticketid=a.entity_id::int and node_number=(a.after->>'node')::int
+			union all select created_at from user_active_tickets_archive
+				where type_='approve' and ticketid=a.entity_id::int and node_number=(a.after->>'node')::int
+		) requests
+	) r
+	where a.action='approve' and a.entity_type='ticket' and a.after->>'escalated_to' is null and r.requested_at is not null;
+create unique index report_approval_times_idx on report_approval_times (id);
+create index report_approval_times_tenant_idx on report_approval_times (tenant_id, approved_at);
+
+-- approvals and rejections per approver and day
+create materialized view report_approver_decisions as
+	select u.tenant_id, (a.created_at at time zone 'utc')::date as day, a.actor as userid, count(*) as decided
+	from audit_events a join users u on u.userid=a.actor
+	where a.action in ('approve', 'reject')
+	group by 1, 2, 3;
+create unique index report_approver_decisions_idx on report_approver_decisions (userid, day);
+
+-- failed attempts per target and day, and jobs that went to the dead letter queue with the target they last failed on
+create materialized view report_callback_failures as
+	with ticket_tenants as (
+		select id, tenant_id from tickets union all select id, tenant_id from tickets_archive
+	), failed as (
+		select tt.tenant_id, (l.created_at at time zone 'utc')::date as day, l.target, count(*) as failed
+		from callback_log l join ticket_tenants tt on tt.id=l.ticket_id
+		where l.status='failed'
+		group by 1, 2, 3
+	), dead as (
+		select tt.tenant_id, (d.failed_at at time zone 'utc')::date as day, coalesce(l.target, 'unknown') as target, count(*) as dead
+		from callback_dlq d join ticket_tenants tt on tt.id=d.ticket_id
+		left join lateral (select target from callback_log where job_id=d.id order by id desc limit 1) l on true
+		group by 1, 2, 3
+	)
+	select coalesce(f.tenant_id, d.tenant_id) as tenant_id, coalesce(f.day, d.day) as day, coalesce(f.target, d.target) as target,
+		coalesce(f.failed, 0) as failed, coalesce(d.dead, 0) as dead
+	from failed f full join dead d on f.tenant_id=d.tenant_id and f.day=d.day and f.target=d.target;
+create unique index report_callback_failures_idx on report_callback_failures (tenant_id, day, target);
+
+-- open tickets with every user they wait on, one row without a user for tickets nobody has a pending node on
+create materialized view report_open_tickets as
+	select t.id as ticket_id, t.tenant_id, t.process_id, t.created_at, t.due_at, a.userid, coalesce(a.userid::text, '') as assignee_key,
+		coalesce(a.approval, false) as approval
+	from tickets t
+	left join lateral (
+		select userid, bool_or(type_='approve') as approval from user_active_tickets
+		where ticketid=t.id and active=true and type_<>'own' and userid is not null
+		group by userid
+	) a on true
+	where t.status='open';
+create unique index report_open_tickets_idx on report_open_tickets (ticket_id, assignee_key);
+create index report_open_tickets_tenant_idx on report_open_tickets (tenant_id, process_id);
+
+-- when the views were last refreshed
+create table report_refreshes (
+	id int primary key default 1 check (id = 1),
+	refreshed_at timestamptz not null
+);
+insert into report_refreshes (refreshed_at) values (now());
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- definitions of the processes for PROCESS_PROVIDER=postgres. like the files they replace they are not per tenant,
+-- process_defs keeps who can use them
+create table process_definitions (
+	process_id varchar primary key,
+	definition jsonb not null,
+	updated_at timestamptz not null
+);
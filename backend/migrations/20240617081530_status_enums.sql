@@ -0,0 +1,66 @@
+-- Add migration script here
+
+-- the statuses of tickets and the kinds of user_active_tickets rows were free text. they map to TicketStatus and
+-- AssignmentType in api-types, a value the enums do not know is now refused by the db
+create type ticket_status as enum ('draft', 'open', 'closed', 'rejected', 'cancelled');
+create type assignment_type as enum ('own', 'approve');
+
+-- the report views and the partial indexes read both columns, they are created again below as they were
+drop materialized view report_ticket_counts;
+drop materialized view report_approval_times;
+drop materialized view report_open_tickets;
+drop index tickets_open_due_at_idx;
+drop index user_active_tickets_approve_idx;
+
+alter table tickets alter column status drop default;
+alter table tickets alter column status type ticket_status using status::ticket_status;
+alter table tickets alter column status set default 'open';
+alter table tickets_archive alter column status type ticket_status using status::ticket_status;
+
+alter table user_active_tickets alter column type_ drop default;
+alter table user_active_tickets alter column type_ type assignment_type using type_::assignment_type;
+alter table user_active_tickets alter column type_ set default 'own';
+alter table user_active_tickets_archive alter column type_ type assignment_type using type_::assignment_type;
+
+create index tickets_open_due_at_idx on tickets (due_at) where status='open';
+create index user_active_tickets_approve_idx on user_active_tickets (ticketid, node_number) where type_='approve';
+
+create materialized view report_ticket_counts as
+	select tenant_id, (created_at at time zone 'utc')::date as day, process_id, status, count(*) as tickets
+	from (select tenant_id, created_at, process_id, status from tickets
+		union all select tenant_id, created_at, process_id, status from tickets_archive) t
+	group by 1, 2, 3, 4;
+create unique index report_ticket_counts_idx on report_ticket_counts (tenant_id, day, process_id, status);
+
+create materialized view report_approval_times as
+	select a.id, tt.tenant_id, a.created_at as approved_at, extract(epoch from a.created_at - r.requested_at)::float8 as seconds
+	from audit_events a
+	cross join lateral (
+		select tenant_id from tickets where id=a.entity_id::int
+		union all select tenant_id from tickets_archive where id=a.entity_id::int
+		limit 1
+	) tt
+	cross join lateral (
+		select min(created_at) as requested_at from (
+			select created_at from user_active_tickets
+				where type_='approve' and ticketid=a.entity_id::int and node_number=(a.after->>'node')::int
+			union all select created_at from user_active_tickets_archive
+				where type_='approve' and ticketid=a.entity_id::int and node_number=(a.after->>'node')::int
+		) requests
+	) r
+	where a.action='approve' and a.entity_type='ticket' and a.after->>'escalated_to' is null and r.requested_at is not null;
+create unique index report_approval_times_idx on report_approval_times (id);
+create index report_approval_times_tenant_idx on report_approval_times (tenant_id, approved_at);
+
+create materialized view report_open_tickets as
+	select t.id as ticket_id, t.tenant_id, t.process_id, t.created_at, t.due_at, a.userid, coalesce(a.userid::text, '') as assignee_key,
+		coalesce(a.approval, false) as approval
+	from tickets t
+	left join lateral (
+		select userid, bool_or(type_='approve') as approval from user_active_tickets
+		where ticketid=t.id and active=true and type_<>'own' and userid is not null
+		group by userid
+	) a on true
+	where t.status='open';
+create unique index report_open_tickets_idx on report_open_tickets (ticket_id, assignee_key);
+create index report_open_tickets_tenant_idx on report_open_tickets (tenant_id, process_id);
@@ -0,0 +1,40 @@
+-- Add migration script here
+
+-- every transition of a ticket in the order it happened, see ticket_events.rs. status, complete, state and instances
+-- of the ticket row are what the changes add up to. rows are only ever inserted and stay when the ticket is archived
+create table ticket_events (
+	id bigserial primary key,
+	ticket_id int not null,
+	-- position in the events of the ticket, from 1
+	seq int not null,
+	kind varchar not null,
+	node int,
+	instance int,
+	-- null when the engine moved on by itself
+	actor uuid,
+	change jsonb not null,
+	created_at timestamptz not null,
+	unique (ticket_id, seq)
+);
+
+create function ticket_events_append_only() returns trigger as $$
+begin
+	raise exception 'ticket_events is append only';
+end;
+$$ language plpgsql;
+
+create trigger ticket_events_no_update before update or delete on ticket_events
+	for each row execute function ticket_events_append_only();
+create trigger ticket_events_no_truncate before truncate on ticket_events
+	for each statement execute function ticket_events_append_only();
+
+-- tickets from before the events were recorded start from the row as it is now
+insert into ticket_events (ticket_id, seq, kind, change, created_at)
+	select t.id, 1, 'snapshot', jsonb_build_object(
+		'status', coalesce(t.status, 'open'),
+		'completed', coalesce((select jsonb_agg(n order by n) from generate_series(0, 31) n where t.complete & (1 << n) <> 0), '[]'),
+		'state', jsonb_build_object('set', coalesce(t.state, '{}')),
+		'instances', jsonb_build_object('set', coalesce(t.instances, '{}'))
+	), t.updated_at
+	from (select id, status, complete, state, instances, updated_at from tickets
+		union all select id, status, complete, state, instances, updated_at from tickets_archive) t;
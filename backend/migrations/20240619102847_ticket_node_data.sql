@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- the data every submission sent for a node, as it was sent. state only keeps the merged result, where a later
+-- submission overwrites the keys of an earlier one. not tied to tickets so it stays when the ticket is archived
+create table ticket_node_data (
+	id bigserial primary key,
+	ticketid int not null,
+	node_number int not null,
+	instance int,
+	userid uuid not null references users(userid),
+	data jsonb not null,
+	created_at timestamptz not null default now()
+);
+create index ticket_node_data_ticketid_idx on ticket_node_data (ticketid);
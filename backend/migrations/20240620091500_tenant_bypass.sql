@@ -0,0 +1,20 @@
+-- Add migration script here
+
+-- a connection without a tenant used to see the rows of every tenant, so requests without a valid token or key
+-- ran unscoped. now the server turns app.all_tenants on for background work and sets a tenant that does not exist
+-- for unauthenticated requests, and a connection with neither sees nothing
+create function all_tenants() returns boolean as $$
+	select coalesce(current_setting('app.all_tenants', true), '') = 'on'
+$$ language sql stable;
+
+do $$
+declare
+	p record;
+begin
+	for p in select schemaname, tablename, policyname from pg_policies where qual like '%current_tenant() IS NULL%' loop
+		execute format(
+			'alter policy %I on %I.%I using (all_tenants() or tenant_id=current_tenant()) with check (all_tenants() or tenant_id=current_tenant())',
+			p.policyname, p.schemaname, p.tablename);
+	end loop;
+end;
+$$;
@@ -0,0 +1,137 @@
+-- Add migration script here
+
+-- the tables that existed before tenants or hang off other tables had no tenant, so every tenant read the audit events,
+-- departments, roles and endpoints of the others. they get a tenant_id and the policy of the other tables, and names
+-- are unique per tenant instead of across the deployment
+
+alter table audit_events add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table departments add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table user_departments add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table user_roles add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table role_permissions add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table role_limits add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table user_delegations add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table ticket_schedules add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table callback_endpoints add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table notification_templates add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table ticket_events add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table ticket_watchers add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table user_active_tickets add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table user_active_tickets_archive add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+alter table process_definitions add column tenant_id int not null default coalesce(current_tenant(), 1) references tenants(id);
+
+-- the rows that are already there belong to the tenant of their user, ticket, role or process
+alter table audit_events disable trigger audit_events_no_update;
+update audit_events a set tenant_id=u.tenant_id from users u where u.userid=a.actor;
+update audit_events a set tenant_id=t.tenant_id
+	from (select id, tenant_id from tickets union all select id, tenant_id from tickets_archive) t
+	where a.actor is null and a.entity_type='ticket' and a.entity_id=t.id::varchar;
+alter table audit_events enable trigger audit_events_no_update;
+
+update departments d set tenant_id=u.tenant_id from users u where u.userid=d.manager_id;
+update user_departments d set tenant_id=u.tenant_id from users u where u.userid=d.userid;
+update user_roles r set tenant_id=u.tenant_id from users u where u.userid=r.userid;
+update role_permissions p set tenant_id=r.tenant_id from role_defs r where r.role_=p.role_;
+update role_limits l set tenant_id=r.tenant_id from role_defs r where r.role_=l.role_;
+update user_delegations d set tenant_id=u.tenant_id from users u where u.userid=d.userid;
+update ticket_schedules s set tenant_id=u.tenant_id from users u where u.userid=s.owner_id;
+update callback_endpoints e set tenant_id=p.tenant_id from process_defs p where p.process_id=e.process_id;
+update notification_templates n set tenant_id=p.tenant_id from process_defs p where p.process_id=n.process_id;
+update process_definitions d set tenant_id=p.tenant_id from process_defs p where p.process_id=d.process_id;
+update ticket_events e set tenant_id=t.tenant_id
+	from (select id, tenant_id from tickets union all select id, tenant_id from tickets_archive) t where t.id=e.ticket_id;
+update ticket_watchers w set tenant_id=t.tenant_id from tickets t where t.id=w.ticketid;
+update user_active_tickets a set tenant_id=t.tenant_id from tickets t where t.id=a.ticketid;
+update user_active_tickets_archive a set tenant_id=t.tenant_id from tickets_archive t where t.id=a.ticketid;
+
+-- rows written by background work take the tenant of the ticket they belong to. the argument is the column with its id
+create function tenant_of_ticket() returns trigger as $$
+declare
+	ticket int := (to_jsonb(new)->>tg_argv[0])::int;
+begin
+	if current_tenant() is null then
+		new.tenant_id := coalesce(
+			(select tenant_id from tickets where id=ticket),
+			(select tenant_id from tickets_archive where id=ticket),
+			new.tenant_id);
+	end if;
+	return new;
+end;
+$$ language plpgsql;
+
+create trigger ticket_events_tenant before insert on ticket_events for each row execute function tenant_of_ticket('ticket_id');
+create trigger ticket_watchers_tenant before insert on ticket_watchers for each row execute function tenant_of_ticket('ticketid');
+create trigger user_active_tickets_tenant before insert on user_active_tickets for each row execute function tenant_of_ticket('ticketid');
+create trigger user_departments_tenant before insert on user_departments for each row execute function tenant_of_user();
+create trigger user_roles_tenant before insert on user_roles for each row execute function tenant_of_user();
+create trigger user_delegations_tenant before insert on user_delegations for each row execute function tenant_of_user();
+create trigger ticket_schedules_tenant before insert on ticket_schedules for each row execute function tenant_of_owner();
+
+-- names only have to be unique within a tenant
+alter table users drop constraint username_uniq;
+alter table users add constraint users_username_key unique (tenant_id, username);
+
+alter table departments drop constraint departments_name_key;
+alter table departments add constraint departments_name_key unique (tenant_id, name);
+
+alter table user_roles drop constraint user_roles_role__fkey;
+alter table role_permissions drop constraint role_permissions_role__fkey;
+alter table role_limits drop constraint role_limits_role__fkey;
+alter table document_acl drop constraint document_acl_role__fkey;
+alter table role_defs drop constraint role_defs_role__key;
+alter table role_defs add constraint role_defs_role__key unique (tenant_id, role_);
+alter table user_roles add constraint user_roles_role__fkey foreign key (tenant_id, role_)
+	references role_defs (tenant_id, role_) on update cascade;
+alter table role_permissions drop constraint role_permissions_pkey;
+alter table role_permissions add primary key (tenant_id, role_, permission);
+alter table role_permissions add constraint role_permissions_role__fkey foreign key (tenant_id, role_)
+	references role_defs (tenant_id, role_) on update cascade on delete cascade;
+alter table role_limits drop constraint role_limits_pkey;
+alter table role_limits add primary key (tenant_id, role_);
+alter table role_limits add constraint role_limits_role__fkey foreign key (tenant_id, role_)
+	references role_defs (tenant_id, role_) on update cascade on delete cascade;
+alter table document_acl add constraint document_acl_role__fkey foreign key (tenant_id, role_)
+	references role_defs (tenant_id, role_) on update cascade on delete cascade;
+
+alter table tickets drop constraint tickets_process_id_fkey;
+alter table notification_templates drop constraint notification_templates_process_id_fkey;
+alter table callback_endpoints drop constraint callback_endpoints_process_id_fkey;
+alter table webhook_subscriptions drop constraint webhook_subscriptions_process_id_fkey;
+alter table process_defs drop constraint process_defs_pkey;
+alter table process_defs add primary key (tenant_id, process_id);
+alter table tickets add constraint tickets_process_id_fkey foreign key (tenant_id, process_id)
+	references process_defs (tenant_id, process_id);
+alter table notification_templates add constraint notification_templates_process_id_fkey foreign key (tenant_id, process_id)
+	references process_defs (tenant_id, process_id) on delete cascade;
+alter table callback_endpoints add constraint callback_endpoints_process_id_fkey foreign key (tenant_id, process_id)
+	references process_defs (tenant_id, process_id) on delete cascade;
+alter table webhook_subscriptions add constraint webhook_subscriptions_process_id_fkey foreign key (tenant_id, process_id)
+	references process_defs (tenant_id, process_id) on delete cascade;
+
+alter table process_definitions drop constraint process_definitions_pkey;
+alter table process_definitions add primary key (tenant_id, process_id);
+
+drop index callback_endpoints_key;
+create unique index callback_endpoints_key on callback_endpoints (tenant_id, process_id, coalesce(node, -1), name);
+drop index notification_templates_key;
+create unique index notification_templates_key on notification_templates (tenant_id, process_id, coalesce(node, -1), locale);
+
+-- every tenant has a hash chain of its own
+create index audit_events_tenant_idx on audit_events (tenant_id, id);
+create index user_roles_tenant_idx on user_roles (tenant_id, role_);
+
+do $$
+declare
+	t text;
+begin
+	foreach t in array array['audit_events', 'departments', 'user_departments', 'user_roles', 'role_permissions', 'role_limits',
+		'user_delegations', 'ticket_schedules', 'callback_endpoints', 'notification_templates', 'ticket_events', 'ticket_watchers',
+		'user_active_tickets', 'user_active_tickets_archive', 'process_definitions'] loop
+		execute format('alter table %I enable row level security', t);
+		execute format('alter table %I force row level security', t);
+		execute format(
+			'create policy %I on %I using (all_tenants() or tenant_id=current_tenant()) with check (all_tenants() or tenant_id=current_tenant())',
+			t || '_tenant', t);
+	end loop;
+end;
+$$;
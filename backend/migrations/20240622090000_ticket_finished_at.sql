@@ -0,0 +1,27 @@
+-- Add migration script here
+
+-- finished tickets were archived by updated_at, which a rejection or cancellation does not always move, so a ticket
+-- rejected long after its last update was archived right away. finished_at is set when the ticket leaves open, whoever
+-- writes the status
+alter table tickets add column finished_at timestamptz;
+alter table tickets_archive add column finished_at timestamptz;
+update tickets set finished_at=updated_at where status<>'open';
+update tickets_archive set finished_at=updated_at;
+
+create function ticket_finished_at() returns trigger as $$
+begin
+	if new.status='open' then
+		new.finished_at := null;
+	elsif tg_op='INSERT' then
+		-- imported tickets finished when they were last updated
+		new.finished_at := coalesce(new.finished_at, new.updated_at);
+	elsif old.status='open' then
+		new.finished_at := now();
+	end if;
+	return new;
+end;
+$$ language plpgsql;
+
+create trigger tickets_finished_at before insert or update of status on tickets for each row execute function ticket_finished_at();
+
+create index tickets_finished_at_idx on tickets (finished_at) where finished_at is not null;
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- the pending jobs of a ticket that is cancelled or rejected are not sent anymore
+alter table callback_jobs drop constraint callback_jobs_status_check;
+alter table callback_jobs add constraint callback_jobs_status_check check (status in ('pending', 'sent', 'cancelled'));
+update callback_jobs j set status='cancelled' from tickets t where t.id=j.ticket_id and j.status='pending' and t.status in ('rejected', 'cancelled');
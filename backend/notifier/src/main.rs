@@ -1,10 +1,13 @@
+// i dont like implicit returns. sometimes explicitly saying return is nicer when rereading code
+#![allow(clippy::needless_return)]
+
 use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 use futures::{pin_mut, SinkExt, StreamExt, TryStreamExt};
 use once_cell::sync::Lazy;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
@@ -27,7 +30,7 @@ struct NewClientData {
 	expires_at: i64
 }
 
-#[derive(Serialize, Deserialize, sqlx::FromRow, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 struct Notification {
 	userid: uuid::Uuid,
 	messages: Vec<(String, chrono::DateTime<chrono::Utc>)>,
@@ -40,7 +43,10 @@ static NEW_CLIENT_QUEUE : Lazy<Mutex<HashMap<String,NewClientData>>> = Lazy::new
 	return Mutex::new(HashMap::new());
 });
 
-static CONNECTED_CLIENTS : Lazy<Mutex<HashMap<String,Vec<(String, UnboundedSender<Notification>)>>>> = Lazy::new(|| {
+// (client token, channel to the client socket task)
+type ClientSenders = Vec<(String, UnboundedSender<Notification>)>;
+
+static CONNECTED_CLIENTS : Lazy<Mutex<HashMap<String, ClientSenders>>> = Lazy::new(|| {
 	return Mutex::new(HashMap::new());
 });
 
@@ -111,7 +117,7 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 		.expect("Couldn't complete websocket handshake.");
 
 	// TODO: authenticate the client first before connecting
-	println!("[INFO] [{}] New Client attempting to connect : {}", Local::now(), addr.to_string());
+	println!("[INFO] [{}] New Client attempting to connect : {}", Local::now(), addr);
 
 	let (mut send, mut recv) = ws_stream.split();
 	send.send(Message::Text("Hello".to_string()))
@@ -121,12 +127,12 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 	// receive token from the client
 	let data = recv.try_next().await.expect("Could not read client_id from client message");
 	if data.is_none() {
-		eprintln!("[Error] [{}] Client: {} did not send token", Local::now(), addr.to_string());
+		eprintln!("[Error] [{}] Client: {} did not send token", Local::now(), addr);
 		return;
 	}
 	let client_token = data.unwrap();
 	if !client_token.is_text() {
-		eprintln!("[Error] [{}] Client: {} did not send text message for token", Local::now(), addr.to_string());
+		eprintln!("[Error] [{}] Client: {} did not send text message for token", Local::now(), addr);
 		return;
 	}
 	let client_token = client_token.to_text().unwrap().to_string();
@@ -137,13 +143,13 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 		let mut guard = NEW_CLIENT_QUEUE.lock().await;
 		let saved_token = guard.get_mut(&client_token);
 		if saved_token.is_none() {
-			eprintln!("[Error] [{}] Client: {} sent invalid token", Local::now(), addr.to_string());
+			eprintln!("[Error] [{}] Client: {} sent invalid token", Local::now(), addr);
 			return;
 		}
 		let saved_token = saved_token.unwrap();
 		if cur_time >= saved_token.expires_at {
 			// this token will be removed by the cleaner thread later so no need to do it now
-			eprintln!("[Error] [{}] Client: {} sent expired token", Local::now(), addr.to_string());
+			eprintln!("[Error] [{}] Client: {} sent expired token", Local::now(), addr);
 			return;
 		}
 		// clear the token and take the userid of the token
@@ -167,7 +173,7 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 		else {
 			guard.insert(client_userid.clone(), vec![(client_token, client_tx)]);
 		}
-		println!("[INFO] [{}] Client: {}, userid: {} successfully connected", Local::now(), addr.to_string(), client_userid);
+		println!("[INFO] [{}] Client: {}, userid: {} successfully connected", Local::now(), addr, client_userid);
 	}
 
 	// send notifications to client
@@ -185,7 +191,7 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 				match send.send(Message::Text(serialized)).await {
 					Ok(_) => {}
 					Err(e) => {
-						eprintln!("[Error] [{}] Failed to send msg to client: {} userid: {}. Error: {}", Local::now(), addr.to_string(), client_userid, e);
+						eprintln!("[Error] [{}] Failed to send msg to client: {} userid: {}. Error: {}", Local::now(), addr, client_userid, e);
 						return;
 					}
 				}
@@ -197,17 +203,17 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 		async move {
 			loop {
 				let res = recv.try_next().await;
-				if let Err(_) = res {
+				if res.is_err() {
 					// client unexpectedly disconnected
 					return;
 				}
 				let msg = res.unwrap();
 				if msg.is_none() {
-					eprintln!("[Warning] [{}] Empty (keep-alive?) msg from client: {} userid: {}", Local::now(), addr.to_string(), client_userid);
+					eprintln!("[Warning] [{}] Empty (keep-alive?) msg from client: {} userid: {}", Local::now(), addr, client_userid);
 				}
 				let msg = msg.unwrap();
 				if msg.is_close() {
-					println!("[INFO] [{}] Received close msg from client: {} userid: {}", Local::now(), addr.to_string(), client_userid);
+					println!("[INFO] [{}] Received close msg from client: {} userid: {}", Local::now(), addr, client_userid);
 					return;
 				}
 			}
@@ -235,7 +241,7 @@ async fn handle_socket(stream: TcpStream, addr: SocketAddr) {
 		}
 	}
 
-	println!("[INFO] [{}] Client: {}, userid: {} disconnected", Local::now(), addr.to_string(), client_userid);
+	println!("[INFO] [{}] Client: {}, userid: {} disconnected", Local::now(), addr, client_userid);
 }
 
 async fn handle_pings(tx: UnboundedSender<Ping>) {
@@ -262,21 +268,39 @@ async fn handle_pings(tx: UnboundedSender<Ping>) {
 						stream.read_exact(&mut buf).await.unwrap();
 						let data_buf = buf.to_vec();
 						let client_id = String::from_utf8(data_buf);
-						if let Err(_) = client_id {
+						if client_id.is_err() {
 							eprintln!("[Error] [{}] Failed to parse client id. Bytes received: {:?}", Local::now(), buf);
 						}
 
 						stream.read_exact(&mut buf).await.unwrap();
 						let data_buf = buf.to_vec();
 						let client_token = String::from_utf8(data_buf);
-						if let Err(_) = client_token {
+						if client_token.is_err() {
 							eprintln!("[Error] [{}] Failed to parse client token. Bytes received: {:?}", Local::now(), buf);
 						}
 
 						tx.send(Ping::ClientIdDataTransfer((client_id.unwrap(), client_token.unwrap()))).unwrap();
 						
 					}
-					_ => eprintln!("[Error] [{}] Malformed ping received.", Local::now()),
+					_ => {
+						eprintln!("[Error] [{}] Malformed ping received.", Local::now());
+						continue;
+					}
+				}
+				// X-Request-Id of the requests the ping was sent for
+				let request_ids = match read_request_ids(&mut stream).await {
+					Ok(ids) => ids,
+					Err(e) => {
+						eprintln!("[Error] [{}] Failed to read request ids of ping {}. {}", Local::now(), data, e);
+						continue;
+					}
+				};
+				if !request_ids.is_empty() {
+					println!("[INFO] [{}] Ping {} sent for requests: {}", Local::now(), data, request_ids);
+				}
+				// acknowledge the ping so the server can drop it from its outbox
+				if let Err(e) = stream.write_all(&[1u8]).await {
+					eprintln!("[Error] [{}] Failed to acknowledge ping. {}", Local::now(), e);
 				}
 			}
 			Err(e) => {
@@ -286,6 +310,17 @@ async fn handle_pings(tx: UnboundedSender<Ping>) {
 	}
 }
 
+async fn read_request_ids(stream: &mut TcpStream) -> Result<String, std::io::Error> {
+	let len = stream.read_u64_le().await?;
+	// a few ids of at most 128 characters
+	if len > 64 * 1024 {
+		return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("request ids too long: {}", len)));
+	}
+	let mut buf = vec![0u8; len as usize];
+	stream.read_exact(&mut buf).await?;
+	return Ok(String::from_utf8_lossy(&buf).to_string());
+}
+
 async fn exec_ping(mut ping_rx: UnboundedReceiver<Ping>, notif_tx: UnboundedSender<()>) {
 	while let Some(ping) = ping_rx.recv().await {
 		match ping {
@@ -335,21 +370,33 @@ async fn pull_notifications(mut notif_rx: UnboundedReceiver<()>) {
 	let pool = sqlx::PgPool::connect(&db_url).await.expect("Failed to connect to db");
 
 	while let Some(()) = notif_rx.recv().await {
-		let mut transaction = pool.begin().await.expect("Failed to start transaction");
-		let query: Result<Vec<Notification>, _> = sqlx::query_as("select userid, array_agg((message, created_at)) as messages from notifications group by userid")
-		.fetch_all(&mut *transaction)
+		// the rows are kept so users can read them later, they are only marked as delivered.
+		// non-urgent rows of users in digest mode are left for the server's digest summary
+		let query: Result<Vec<(uuid::Uuid, String, chrono::DateTime<chrono::Utc>)>, _> = sqlx::query_as(
+			r#"update notifications n set delivered_at=$1 where n.delivered_at is null and n.userid is not null
+				and (n.urgent or n.digest_count is not null
+					or not exists (select 1 from users u where u.userid=n.userid and u.notification_digest))
+				returning n.userid, coalesce(n.message, ''), n.created_at"#)
+		.bind(chrono::Utc::now())
+		.fetch_all(&pool)
 		.await;
 
 		if let Err(e) = query {
 			eprintln!("[Error] [{}] Failed to query db. Error: {}", Local::now(), e);
 			return;
 		}
-		let notifications = query.unwrap();
+		let mut notifications: HashMap<uuid::Uuid, Notification> = HashMap::new();
+		for (userid, message, created_at) in query.unwrap() {
+			notifications.entry(userid)
+				.or_insert_with(|| Notification { userid, messages: Vec::new() })
+				.messages
+				.push((message, created_at));
+		}
 
 		{
 			let guard = CONNECTED_CLIENTS.lock().await;
-			// TODO: save notifications that were not sent
-			for notif in notifications {
+			// users that are not connected find the notifications through the server's /notifications
+			for notif in notifications.into_values() {
 				let userid = notif.userid.to_string();
 				if !guard.contains_key(&userid) {
 					continue;
@@ -361,15 +408,5 @@ async fn pull_notifications(mut notif_rx: UnboundedReceiver<()>) {
 				}
 			}
 		}
-		let query = sqlx::query("delete from notifications")
-			.execute(&mut *transaction)
-			.await;
-
-		if let Err(e) = query {
-			eprintln!("[Error] [{}] Failed to query db. Error: {}", Local::now(), e);
-			return;
-		}
-
-		transaction.commit().await.expect("Failed to commit transaction");
 	}
 }
\ No newline at end of file
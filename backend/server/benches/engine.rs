@@ -0,0 +1,169 @@
+// the engine and the queries behind the ticket lists, run with `cargo bench --bench engine`.
+// get_user_tickets needs BENCH_DATABASE_URL, a scratch database. it is migrated and every run adds users and tickets to it
+#![allow(clippy::needless_return)]
+
+use axum::extract::State;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use erp_api_types::tickets::{GetUserTicketsReq, TicketStatus, UpdateTicket};
+use serde_json::{json, Map, Value};
+use server::auth::AuthUser;
+use server::db_types::Ticket;
+use server::extractors::ValidQuery;
+use server::process::{provider::{self, MemoryProvider, ProcessProvider}, Process};
+use server::{migrations, tenant, ticket};
+use sqlx::PgPool;
+
+// the complete bitmask of a ticket is an i32, so with initiate and complete a process has at most 30 nodes besides them
+const SIZES: [usize; 3] = [8, 16, 30];
+const OWNERS: usize = 1000;
+const TICKETS: i32 = 20000;
+// one in this many tickets waits on the user the lists are read for
+const ASSIGNED_EVERY: i32 = 40;
+
+fn process(pid: &str, steps: Value) -> Process {
+	return serde_json::from_value(json!({ "pname": pid, "pid": pid, "steps": steps, "desc": null, "roles": ["any"] })).unwrap();
+}
+
+fn notify(next: Vec<usize>, required: Vec<usize>) -> Value {
+	return json!({ "event": "notify", "args": ["asha"], "next": next, "required": required });
+}
+
+// initiate, `width` notify nodes side by side and a complete that waits for all of them
+fn wide(width: usize) -> Process {
+	let mut steps = vec![json!({ "event": "initiate", "args": [], "next": (1..=width).collect::<Vec<_>>(), "required": [] })];
+	steps.extend((1..=width).map(|_| notify(vec![width + 1], vec![0])));
+	steps.push(json!({ "event": "complete", "args": null, "next": [], "required": (1..=width).collect::<Vec<_>>() }));
+	return process(&format!("wide_{}", width), Value::Array(steps));
+}
+
+// initiate, then `depth` notify nodes one after the other and a complete
+fn deep(depth: usize) -> Process {
+	let mut steps = vec![json!({ "event": "initiate", "args": [], "next": [1], "required": [] })];
+	steps.extend((1..=depth).map(|i| notify(vec![i + 1], vec![i - 1])));
+	steps.push(json!({ "event": "complete", "args": null, "next": [], "required": [depth] }));
+	return process(&format!("deep_{}", depth), Value::Array(steps));
+}
+
+fn ticket(process_id: &str) -> Ticket {
+	return Ticket {
+		id: 1,
+		owner_id: uuid::Uuid::new_v4(),
+		process_id: process_id.to_string(),
+		log_id: uuid::Uuid::new_v4(),
+		is_public: false,
+		priority: 0,
+		due_at: None,
+		created_at: chrono::Utc::now(),
+		updated_at: chrono::Utc::now(),
+		status: TicketStatus::Open,
+		complete: 0,
+		state: Value::Object(Map::new()),
+		instances: Value::Object(Map::new())
+	};
+}
+
+// runs the whole process from the initiate node. returns the notifications and completions it produced
+async fn execute(pid: &str) -> usize {
+	let request = UpdateTicket { ticket_id: 1, user_id: uuid::Uuid::nil(), status: true, node: 0, data: None, instance: None, reason: None };
+	let mut ticket = ticket(pid);
+	let new_tickets = ticket::update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
+	return new_tickets.ok().map(|t| t.len()).unwrap_or(0);
+}
+
+fn update_internal(c: &mut Criterion) {
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+	// the definitions are read from memory so only the engine is measured
+	let memory = MemoryProvider::default();
+	for size in SIZES {
+		runtime.block_on(memory.save(&wide(size))).unwrap();
+		runtime.block_on(memory.save(&deep(size))).unwrap();
+	}
+	provider::set(Box::new(memory)).unwrap();
+
+	let mut group = c.benchmark_group("update_internal");
+	for shape in ["wide", "deep"] {
+		for size in SIZES {
+			let pid = format!("{}_{}", shape, size);
+			// a notification per node and the completion, or the process did not run to its end
+			assert_eq!(runtime.block_on(execute(&pid)), size + 1, "{}", pid);
+			group.bench_with_input(BenchmarkId::new(shape, size), &pid, |b, pid| {
+				b.to_async(&runtime).iter(|| execute(pid));
+			});
+		}
+	}
+	group.finish();
+}
+
+// OWNERS users owning TICKETS open tickets between them, each waiting on one of them. returns the user that every
+// ASSIGNED_EVERY-th ticket waits on, who also owns tickets
+async fn seed(pool: &PgPool) -> Result<AuthUser, sqlx::Error> {
+	sqlx::query("insert into process_defs (process_id, allowed_roles, callback_secret) values ('bench', '{any}', '') on conflict do nothing")
+		.execute(pool)
+		.await?;
+	let users: Vec<uuid::Uuid> = (0..OWNERS).map(|_| uuid::Uuid::new_v4()).collect();
+	sqlx::query("insert into users (userid, username) select u, 'bench_' || replace(u::text, '-', '') from unnest($1::uuid[]) u")
+		.bind(&users)
+		.execute(pool)
+		.await?;
+
+	let tickets: Vec<(i32,)> = sqlx::query_as(
+		r#"insert into tickets (owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, state, priority)
+			select $1[1 + i % cardinality($1)], 'bench', gen_random_uuid(), false, now() - i * interval '1 minute',
+				now() - i * interval '1 minute', 'open', 1, '{}', i % 3
+			from generate_series(1, $2) i returning id"#)
+		.bind(&users)
+		.bind(TICKETS)
+		.fetch_all(pool)
+		.await?;
+	let ids: Vec<i32> = tickets.into_iter().map(|t| t.0).collect();
+
+	sqlx::query(
+		r#"insert into user_active_tickets (userid, ticketid, active, node_number, type_)
+			select t.owner_id, t.id, true, 0, 'own' from tickets t where t.id=any($2)
+			union all
+			select case when n % $3 = 0 then $1[1] else $1[1 + n % cardinality($1)] end, id, true, 1, 'approve'
+			from unnest($2::int4[]) with ordinality as a(id, n)"#)
+		.bind(&users)
+		.bind(&ids)
+		.bind(ASSIGNED_EVERY as i64)
+		.execute(pool)
+		.await?;
+
+	return Ok(AuthUser { userid: users[0], username: format!("bench_{}", users[0].simple()), session_id: uuid::Uuid::new_v4() });
+}
+
+async fn user_tickets(pool: &PgPool, user: &AuthUser, query: &Value) -> bool {
+	let user = AuthUser { userid: user.userid, username: user.username.clone(), session_id: user.session_id };
+	let query: GetUserTicketsReq = serde_json::from_value(query.clone()).unwrap();
+	return ticket::get_user_tickets(user, ValidQuery(query), State(pool.clone())).await.is_ok();
+}
+
+fn get_user_tickets(c: &mut Criterion) {
+	let url = match std::env::var("BENCH_DATABASE_URL") {
+		Ok(url) => url,
+		Err(_) => {
+			eprintln!("BENCH_DATABASE_URL not defined, skipping get_user_tickets");
+			return;
+		}
+	};
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+	let (pool, user) = runtime.block_on(async {
+		let pool = tenant::pool_options().max_connections(5).connect(&url).await.expect("Unable to connect to db");
+		migrations::run(&pool).await.unwrap();
+		let user = seed(&pool).await.unwrap();
+		return (pool, user);
+	});
+
+	let mut group = c.benchmark_group("get_user_tickets");
+	let pages = [("first_page", json!({})), ("by_created_at", json!({ "sort": "created_at", "order": "asc" })), ("open", json!({ "status": "open" }))];
+	for (name, query) in pages {
+		assert!(runtime.block_on(user_tickets(&pool, &user, &query)), "{}", name);
+		group.bench_with_input(BenchmarkId::from_parameter(name), &query, |b, query| {
+			b.to_async(&runtime).iter(|| user_tickets(&pool, &user, query));
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, update_internal, get_user_tickets);
+criterion_main!(benches);
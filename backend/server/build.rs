@@ -0,0 +1,29 @@
+#![allow(clippy::needless_return)]
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> Method {
+	return Method::builder()
+		.name(name)
+		.route_name(route)
+		.input_type(format!("crate::grpc::{}", input))
+		.output_type(format!("crate::grpc::{}", output))
+		.codec_path("tonic::codec::ProstCodec")
+		.build();
+}
+
+fn main() {
+	// the migrations are embedded with sqlx::migrate!, new files have to rebuild the server
+	println!("cargo:rerun-if-changed=../migrations");
+
+	// the server side of ../proto/tickets.proto. the messages are written by hand in grpc.rs so building
+	// does not need protoc
+	let tickets = Service::builder()
+		.name("Tickets")
+		.package("erp.v1")
+		.method(method("create_ticket", "CreateTicket", "CreateTicketRequest", "CreatedTicket"))
+		.method(method("update_ticket", "UpdateTicket", "UpdateTicketRequest", "UpdateTicketResponse"))
+		.method(method("complete_task", "CompleteTask", "UpdateTicketRequest", "UpdateTicketResponse"))
+		.build();
+	Builder::new().build_client(false).compile(&[tickets]);
+}
@@ -0,0 +1,325 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use crate::{archive, audit::{self, AuditAction, AuditEvent}, auth, db_types::Ticket, logger::{self, admin_logger, log, AdminLogEntry, LogFilter, LogMetrics, LogType}, replica, tenant, ticket};
+use crate::events::{self, EngineEvent, WorkflowEvent};
+use crate::ws::{LiveEvent, LiveEventKind};
+use crate::rbac::{Authorized, ManageProcesses, ManageUsers, ViewLogs};
+use crate::api_error::db_status;
+use erp_api_types::tickets::TicketStatus;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_LOG_LIMIT: usize = 500;
+const MAX_LOG_LIMIT: usize = 5000;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReassignRequest {
+	pub from_user: uuid::Uuid,
+	pub to_user: uuid::Uuid,
+	// only needed when from_user has more than one pending node on the ticket
+	pub node: Option<i32>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ArchiveRequest {
+	// defaults to ARCHIVE_AFTER_DAYS
+	pub older_than_days: Option<i64>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LogsQuery {
+	// X-Request-Id of the request that wrote the entries
+	pub request_id: Option<String>,
+	pub log_id: Option<uuid::Uuid>,
+	pub ticket_id: Option<i32>,
+	// a LogType like APPROVAL or FAILED_TO_PING
+	#[serde(rename = "type")]
+	pub type_: Option<String>,
+	pub from: Option<chrono::DateTime<chrono::Utc>>,
+	pub to: Option<chrono::DateTime<chrono::Utc>>,
+	pub limit: Option<usize>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArchiveResponse {
+	pub archived: u64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RevokedSessions {
+	pub revoked: u64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct OverdueTicket {
+	pub id: i32,
+	pub process_id: String,
+	pub owner_id: uuid::Uuid,
+	pub priority: i32,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub due_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct ReassignedNode {
+	pub node_number: i32
+}
+
+#[utoipa::path(post, path = "/admin/ticket/{id}/reassign", tag = "admin", params(("id" = i32, Path, description = "Ticket id")), request_body = ReassignRequest, responses(
+	(status = 200, description = "Nodes moved to to_user", body = Vec<ReassignedNode>),
+	(status = 400, description = "to_user is not an active user"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown ticket or from_user has no pending node on it"),
+	(status = 409, description = "Ticket is not open")
+))]
+pub async fn reassign_ticket(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>,
+	Json(payload): Json<ReassignRequest>
+) -> Result<Json<Vec<ReassignedNode>>, StatusCode> {
+	let mut tx = pool.begin().await.map_err(db_status)?;
+
+	// an approval of the node running at the same time would complete it for the old assignee
+	if let Err(e) = ticket::lock_ticket(&mut tx, ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {} in reassign_ticket: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let query: Result<Option<Ticket>, _> = sqlx::query_as("select * from tickets where id=$1")
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} in reassign_ticket: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let ticket = query.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	if ticket.status != TicketStatus::Open {
+		return Err(StatusCode::CONFLICT);
+	}
+
+	// deactivated users can not approve anything
+	let target_active: Result<Option<(bool,)>, _> = sqlx::query_as("select active from users where userid=$1")
+		.bind(payload.to_user)
+		.fetch_optional(&mut *tx)
+		.await;
+
+	if let Err(e) = target_active {
+		log(LogType::Error, format!("Error reading user {} in reassign_ticket: {}", payload.to_user, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if target_active.unwrap() != Some((true,)) {
+		return Err(StatusCode::BAD_REQUEST);
+	}
+
+	// the rows of the old assignee are kept inactive, they can still read the ticket they worked on
+	let query: Result<Vec<ReassignedNode>, _> = sqlx::query_as(
+		r#"with previous as (
+				update user_active_tickets set active=false
+				where ticketid=$2 and userid=$3 and type_='approve' and active=true and ($4::int is null or node_number=$4)
+				returning node_number, instance)
+			insert into user_active_tickets (userid, ticketid, active, node_number, type_, instance)
+			select $1, $2, true, node_number, 'approve', instance from previous
+			returning node_number"#)
+		.bind(payload.to_user)
+		.bind(ticket.id)
+		.bind(payload.from_user)
+		.bind(payload.node)
+		.fetch_all(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		log(LogType::Error, format!("Error reassigning ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let reassigned = query.unwrap();
+
+	if reassigned.is_empty() {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	let nodes: Vec<i32> = reassigned.iter().map(|n| n.node_number).collect();
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::Reassign, "ticket", ticket.id,
+		Some(serde_json::json!({"user": payload.from_user, "nodes": nodes})),
+		Some(serde_json::json!({"user": payload.to_user, "nodes": nodes})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		log(LogType::Error, format!("Error auditing reassignment of ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	// the new assignee is asked for the approval like the one the node was reached with
+	let requested: Vec<WorkflowEvent> = reassigned.iter()
+		.map(|node| WorkflowEvent::new(EngineEvent::ApprovalRequested, &ticket, Some(node.node_number),
+			serde_json::json!({"approver": payload.to_user, "assignee": payload.to_user, "reassigned_from": payload.from_user})))
+		.collect();
+	if let Err(e) = events::record(&mut tx, &requested).await {
+		log(LogType::Error, format!("Error recording the reassignment of ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if let Err(e) = tx.commit().await {
+		log(LogType::Error, format!("Error commiting transaction: {} for ticket {}", e, ticket.id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let mut live_events = Vec::new();
+	for node in reassigned.iter() {
+		log(LogType::Info,
+			format!("Ticket {} node {} reassigned from {} to {} by admin {}", ticket.id, node.node_number, payload.from_user, payload.to_user, auth.user.userid),
+			ticket.log_id);
+		let message = format!("Ticket {} was reassigned to you for approval. Process Id: {}", ticket.id, ticket.process_id);
+		live_events.push((payload.to_user, LiveEvent::new(LiveEventKind::ApproveRequest, &ticket, node.node_number, message)));
+	}
+	ticket::after_commit(&pool, live_events).await;
+
+	return Ok(Json(reassigned));
+}
+
+// like the aging report of the dashboard
+#[utoipa::path(get, path = "/tickets/overdue", tag = "admin", responses(
+	(status = 200, description = "Open tickets past their due date", body = Vec<OverdueTicket>),
+	(status = 403, description = "Missing the view_logs permission")
+))]
+pub async fn get_overdue_tickets(
+	_auth: Authorized<ViewLogs>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<OverdueTicket>>, StatusCode> {
+	let pool = replica::read_pool(pool);
+	let mut conn = pool.acquire().await.map_err(db_status)?;
+
+	let query: Result<Vec<OverdueTicket>, _> = sqlx::query_as(
+		r#"select id, process_id, owner_id, priority, created_at, due_at from tickets
+			where status='open' and due_at<now() order by due_at"#)
+		.fetch_all(&mut *conn)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading overdue tickets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// removes tickets from the lists, like the archiver does on its own. same permission as importing them
+#[utoipa::path(post, path = "/admin/tickets/archive", tag = "admin", request_body = ArchiveRequest, responses(
+	(status = 200, description = "Finished tickets moved to the archive", body = ArchiveResponse),
+	(status = 400, description = "Invalid older_than_days"),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn archive_tickets(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<ArchiveRequest>
+) -> Result<Json<ArchiveResponse>, StatusCode> {
+	let older_than = archive::older_than(payload.older_than_days).ok_or(StatusCode::BAD_REQUEST)?;
+
+	let result = archive::archive_tickets(&pool, older_than).await;
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error archiving tickets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let archived = result.unwrap();
+
+	admin_logger(LogType::Info, &format!("Admin {} archived {} tickets", auth.user.userid, archived), None);
+	return Ok(Json(ArchiveResponse { archived }));
+}
+
+// ends every session of a user, e.g. for a lost laptop. their access tokens stop working on the next request
+#[utoipa::path(delete, path = "/admin/users/{id}/sessions", tag = "admin", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Every session of the user is revoked", body = RevokedSessions),
+	(status = 403, description = "Missing the manage_users permission")
+))]
+pub async fn revoke_sessions(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<Json<RevokedSessions>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(db_status)?;
+
+	let result = auth::revoke_sessions(&mut conn, userid, None).await;
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error revoking sessions of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let revoked = result.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} revoked {} sessions of {}", auth.user.userid, revoked, userid), None);
+	return Ok(Json(RevokedSessions { revoked }));
+}
+
+// entries of the server log written in requests of the tenant. with a ticket_id the entries written with the log_id
+// of the ticket are included, so the whole lifecycle of the ticket is returned even for entries written outside of a request on it
+#[utoipa::path(get, path = "/admin/logs", tag = "admin", params(LogsQuery), responses(
+	(status = 200, description = "Entries of server.log, newest first", body = Vec<AdminLogEntry>),
+	(status = 403, description = "Missing the view_logs permission"),
+	(status = 404, description = "Unknown ticket")
+))]
+pub async fn get_logs(
+	_auth: Authorized<ViewLogs>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<LogsQuery>
+) -> Result<Json<Vec<AdminLogEntry>>, StatusCode> {
+	let mut log_id = query.log_id;
+	if let (Some(ticket_id), None) = (query.ticket_id, log_id) {
+		let ticket: Result<Option<(uuid::Uuid,)>, _> = sqlx::query_as(
+			"select log_id from tickets where id=$1 union all select log_id from tickets_archive where id=$1")
+			.bind(ticket_id)
+			.fetch_optional(&pool)
+			.await;
+		if let Err(e) = ticket {
+			admin_logger(LogType::Error, &format!("Error reading log id of ticket {}: {}", ticket_id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		log_id = ticket.unwrap().map(|t| t.0);
+	}
+	else if let Some(id) = log_id {
+		// only the log of a ticket of the tenant
+		let ticket: Result<Option<(i32,)>, _> = sqlx::query_as(
+			"select 1 from tickets where log_id=$1 union all select 1 from tickets_archive where log_id=$1 limit 1")
+			.bind(id)
+			.fetch_optional(&pool)
+			.await;
+		if let Err(e) = ticket {
+			admin_logger(LogType::Error, &format!("Error reading the ticket of log {}: {}", id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		if ticket.unwrap().is_none() {
+			return Err(StatusCode::NOT_FOUND);
+		}
+	}
+
+	let filter = LogFilter {
+		tenant_id: tenant::current_tenant(),
+		request_id: query.request_id,
+		log_id,
+		ticket_id: query.ticket_id,
+		category: query.type_.map(|t| t.to_uppercase()),
+		from: query.from,
+		to: query.to
+	};
+	let limit = query.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, MAX_LOG_LIMIT);
+	let entries = tokio::task::spawn_blocking(move || logger::read_server_log(&filter, limit)).await
+		.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if let Err(e) = entries {
+		admin_logger(LogType::Error, &format!("Error reading server log: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(entries.unwrap()));
+}
+
+// how far the log writers are behind and what they had to drop
+#[utoipa::path(get, path = "/admin/logs/metrics", tag = "admin", responses(
+	(status = 200, description = "Counters of the log writers", body = LogMetrics),
+	(status = 403, description = "Missing the view_logs permission")
+))]
+pub async fn get_log_metrics(
+	_auth: Authorized<ViewLogs>
+) -> Result<Json<LogMetrics>, StatusCode> {
+	return Ok(Json(logger::log_metrics()));
+}
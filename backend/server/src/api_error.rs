@@ -0,0 +1,139 @@
+use axum::{http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use crate::logger::{admin_logger, LogType};
+use crate::schema::{FieldError, FieldErrors};
+
+pub use erp_api_types::{Problem, PROBLEM_CONTENT_TYPE};
+
+// an error response with a code clients can tell apart, sent as problem+json. the code stays the same when
+// the wording of the detail changes
+#[derive(Debug)]
+pub struct ApiError {
+	pub status: StatusCode,
+	pub code: &'static str,
+	pub detail: Option<String>,
+	pub errors: Vec<FieldError>
+}
+
+impl ApiError {
+	pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> ApiError {
+		return ApiError { status, code, detail: Some(detail.into()), errors: Vec::new() };
+	}
+
+	// the request was read, but some of its fields are wrong
+	pub fn fields(status: StatusCode, code: &'static str, errors: Vec<FieldError>) -> ApiError {
+		return ApiError { status, code, detail: None, errors };
+	}
+
+	pub fn field(status: StatusCode, code: &'static str, field: &str, message: impl Into<String>) -> ApiError {
+		return ApiError::fields(status, code, vec![FieldError { field: field.to_string(), message: message.into() }]);
+	}
+
+	// the detail of server errors is in the logs, never in the response
+	pub fn internal() -> ApiError {
+		return ApiError::from(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	pub fn problem(&self) -> Problem {
+		return Problem {
+			type_: "about:blank".to_string(),
+			title: self.status.canonical_reason().unwrap_or_default().to_string(),
+			status: self.status.as_u16(),
+			detail: self.detail.clone(),
+			code: self.code.to_string(),
+			errors: self.errors.clone()
+		};
+	}
+}
+
+// the code of errors that only have a status
+pub fn status_code(status: StatusCode) -> &'static str {
+	return match status {
+		StatusCode::BAD_REQUEST => "bad_request",
+		StatusCode::UNAUTHORIZED => "unauthorized",
+		StatusCode::FORBIDDEN => "forbidden",
+		StatusCode::NOT_FOUND => "not_found",
+		StatusCode::CONFLICT => "conflict",
+		StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+		StatusCode::UNPROCESSABLE_ENTITY => "invalid_data",
+		StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+		StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+		_ if status.is_server_error() => "internal_error",
+		_ => "error"
+	};
+}
+
+impl From<StatusCode> for ApiError {
+	fn from(status: StatusCode) -> Self {
+		return ApiError { status, code: status_code(status), detail: None, errors: Vec::new() };
+	}
+}
+
+// the database being unreachable is worth retrying, anything else it answered is not
+impl From<sqlx::Error> for ApiError {
+	fn from(e: sqlx::Error) -> Self {
+		return match e {
+			sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+				ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "database_unavailable", "The database can not be reached, try again later")
+			}
+			_ => ApiError::from(StatusCode::INTERNAL_SERVER_ERROR)
+		};
+	}
+}
+
+// for failures of the database outside of a query, like starting a transaction. logged here, unlike queries
+// that are logged with what they were doing
+pub fn db_error(e: sqlx::Error) -> ApiError {
+	admin_logger(LogType::Error, &format!("Database error: {}", e), None);
+	return ApiError::from(e);
+}
+
+// db_error for handlers that only return a status
+pub fn db_status(e: sqlx::Error) -> StatusCode {
+	return db_error(e).status;
+}
+
+impl From<(StatusCode, Json<FieldErrors>)> for ApiError {
+	fn from((status, Json(errors)): (StatusCode, Json<FieldErrors>)) -> Self {
+		return ApiError::fields(status, status_code(status), errors.errors);
+	}
+}
+
+impl IntoResponse for ApiError {
+	fn into_response(self) -> Response {
+		let status = self.status;
+		return (status, [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)], Json(self.problem())).into_response();
+	}
+}
+
+#[cfg(test)]
+mod api_error_tests {
+	use axum::http::StatusCode;
+	use serde_json::json;
+	use super::{ApiError, Problem};
+
+	#[test]
+	fn problems_carry_a_code() {
+		let problem = ApiError::new(StatusCode::CONFLICT, "ticket_not_open", "Ticket 4 is closed").problem();
+		assert_eq!(serde_json::to_value(&problem).unwrap(), json!({
+			"type": "about:blank", "title": "Conflict", "status": 409, "detail": "Ticket 4 is closed", "code": "ticket_not_open"
+		}));
+
+		let problem = ApiError::field(StatusCode::UNPROCESSABLE_ENTITY, "invalid_data", "/reason", "required").problem();
+		assert_eq!(serde_json::to_value(&problem).unwrap()["errors"], json!([{"field": "/reason", "message": "required"}]));
+	}
+
+	#[test]
+	fn unreachable_databases_are_told_apart() {
+		assert_eq!(ApiError::from(sqlx::Error::PoolTimedOut).status, StatusCode::SERVICE_UNAVAILABLE);
+		assert_eq!(ApiError::from(sqlx::Error::PoolTimedOut).code, "database_unavailable");
+		assert_eq!(ApiError::from(sqlx::Error::RowNotFound).code, "internal_error");
+		assert_eq!(ApiError::from(StatusCode::FORBIDDEN).code, "forbidden");
+	}
+
+	#[test]
+	fn bodies_with_only_errors_are_read() {
+		let problem: Problem = serde_json::from_value(json!({"errors": [{"field": "/node", "message": "unknown"}]})).unwrap();
+		assert_eq!(problem.type_, "about:blank");
+		assert_eq!(problem.errors.len(), 1);
+	}
+}
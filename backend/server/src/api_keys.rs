@@ -0,0 +1,312 @@
+use axum::{async_trait, body::Bytes, extract::{self, FromRequestParts}, http::{request::Parts, HeaderMap, Method, StatusCode}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use crate::auth::{hash_secret, new_secret, parse_secret_token};
+use crate::callbacks::{self, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageApiKeys};
+use crate::ratelimit;
+use crate::ticket::{self, UpdateErr, UpdateSource, UpdateTicket};
+use utoipa::ToSchema;
+
+pub use erp_api_types::API_KEY_HEADER;
+
+// what a key can be used for. keys only get the scopes they were minted with
+pub const SCOPES: [&str; 3] = ["complete_blocking_task", "read_onboarding", "signal"];
+
+// a service authenticated by the X-Api-Key header. rejects the request with 401 if the key is missing, unknown or revoked
+pub struct ApiKey {
+	pub id: uuid::Uuid,
+	pub name: String,
+	pub scopes: Vec<String>
+}
+
+#[derive(FromRow)]
+struct StoredKey {
+	name: String,
+	key_hash: String,
+	scopes: Vec<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKey {
+	pub name: String,
+	pub scopes: Vec<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct ApiKeyInfo {
+	pub id: uuid::Uuid,
+	pub name: String,
+	pub scopes: Vec<String>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub rotated_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub revoked_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+// the key is only returned when it is minted or rotated
+#[derive(Serialize, ToSchema)]
+pub struct MintedKey {
+	pub id: uuid::Uuid,
+	pub key: String,
+	pub scopes: Vec<String>
+}
+
+pub fn validate_scopes(scopes: &[String]) -> Result<Vec<String>, String> {
+	let mut result = Vec::new();
+	for scope in scopes {
+		let scope = scope.trim();
+		if !SCOPES.contains(&scope) {
+			return Err(scope.to_string());
+		}
+		if !result.iter().any(|s| s == scope) {
+			result.push(scope.to_string());
+		}
+	}
+	return Ok(result);
+}
+
+impl ApiKey {
+	pub fn require(&self, scope: &str) -> Result<(), StatusCode> {
+		if self.scopes.iter().any(|s| s == scope) {
+			return Ok(());
+		}
+		admin_logger(LogType::Warning, &format!("Api key {} ({}) lacks scope {}", self.name, self.id, scope), None);
+		return Err(StatusCode::FORBIDDEN);
+	}
+}
+
+// checks the key in the X-Api-Key header. `path` is only used for logging
+pub async fn authenticate_key(pool: &PgPool, headers: &HeaderMap, path: &str) -> Result<ApiKey, StatusCode> {
+	let (id, secret) = headers
+		.get(API_KEY_HEADER)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|h| parse_secret_token(h.trim()))
+		.ok_or(StatusCode::UNAUTHORIZED)?;
+
+	let key: Result<Option<StoredKey>, _> = sqlx::query_as("select name, key_hash, scopes from api_keys where id=$1 and revoked_at is null")
+		.bind(id)
+		.fetch_optional(pool)
+		.await;
+	if let Err(e) = key {
+		admin_logger(LogType::Error, &format!("Error reading api key {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let key = match key.unwrap() {
+		Some(key) if key.key_hash == hash_secret(secret) => key,
+		_ => {
+			admin_logger(LogType::Warning, &format!("Invalid api key {} used for {}", id, path), None);
+			return Err(StatusCode::UNAUTHORIZED);
+		}
+	};
+
+	// only informational, a failure here does not reject the request
+	let _ = sqlx::query("update api_keys set last_used_at=$2 where id=$1")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(pool)
+		.await;
+
+	return Ok(ApiKey { id, name: key.name, scopes: key.scopes });
+}
+
+#[async_trait]
+impl FromRequestParts<PgPool> for ApiKey {
+	type Rejection = Response;
+
+	async fn from_request_parts(parts: &mut Parts, pool: &PgPool) -> Result<Self, Self::Rejection> {
+		let key = authenticate_key(pool, &parts.headers, parts.uri.path()).await.map_err(IntoResponse::into_response)?;
+		// limited only after the secret is checked, so the id of a key is not enough to use up its writes
+		if parts.method != Method::GET {
+			if let Err(retry_after) = ratelimit::take_write(&ratelimit::key_identity(key.id)) {
+				admin_logger(LogType::Warning, &format!("Rate limited api key {} on {}, retry after {}s", key.id, parts.uri.path(), retry_after), None);
+				return Err(ratelimit::too_many_requests(retry_after));
+			}
+		}
+		return Ok(key);
+	}
+}
+
+#[utoipa::path(post, path = "/admin/api_keys", tag = "api_keys", request_body = CreateApiKey, responses(
+	(status = 201, description = "The key, only shown here", body = MintedKey),
+	(status = 403, description = "Missing the manage_api_keys permission"),
+	(status = 422, description = "Missing name or unknown scope", body = String)
+))]
+pub async fn create_api_key(
+	auth: Authorized<ManageApiKeys>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateApiKey>
+) -> Result<(StatusCode, Json<MintedKey>), (StatusCode, String)> {
+	let name = payload.name.trim();
+	if name.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "A name is required".to_string()));
+	}
+	let scopes = validate_scopes(&payload.scopes)
+		.map_err(|s| (StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown scope: {}", s)))?;
+
+	let id = uuid::Uuid::new_v4();
+	let secret = new_secret();
+	let query = sqlx::query(
+		r#"insert into api_keys (id, name, key_hash, scopes, created_by, created_at)
+			values ($1, $2, $3, $4, $5, $6)"#)
+		.bind(id)
+		.bind(name)
+		.bind(hash_secret(&secret))
+		.bind(&scopes)
+		.bind(auth.user.userid)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting api key {}: {}", name, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} minted api key {} ({}) with scopes {:?}", auth.user.userid, name, id, scopes), None);
+	return Ok((StatusCode::CREATED, Json(MintedKey { id, key: format!("{}.{}", id, secret), scopes })));
+}
+
+#[utoipa::path(get, path = "/admin/api_keys", tag = "api_keys", responses(
+	(status = 200, description = "Keys without their secrets", body = Vec<ApiKeyInfo>),
+	(status = 403, description = "Missing the manage_api_keys permission")
+))]
+pub async fn get_api_keys(
+	_auth: Authorized<ManageApiKeys>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<ApiKeyInfo>>, StatusCode> {
+	let query: Result<Vec<ApiKeyInfo>, _> = sqlx::query_as(
+		r#"select id, name, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at
+			from api_keys order by created_at"#)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading api keys: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// replaces the secret of the key. the old key stops working immediately
+#[utoipa::path(post, path = "/admin/api_keys/{id}/rotate", tag = "api_keys", params(("id" = uuid::Uuid, Path, description = "Key id")), responses(
+	(status = 200, description = "The new key, the old one stops working", body = MintedKey),
+	(status = 403, description = "Missing the manage_api_keys permission"),
+	(status = 404, description = "Unknown or revoked key")
+))]
+pub async fn rotate_api_key(
+	auth: Authorized<ManageApiKeys>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<uuid::Uuid>
+) -> Result<Json<MintedKey>, StatusCode> {
+	let secret = new_secret();
+	let query: Result<Option<(Vec<String>,)>, _> = sqlx::query_as(
+		"update api_keys set key_hash=$2, rotated_at=$3 where id=$1 and revoked_at is null returning scopes")
+		.bind(id)
+		.bind(hash_secret(&secret))
+		.bind(chrono::Utc::now())
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error rotating api key {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let scopes = query.unwrap().ok_or(StatusCode::NOT_FOUND)?.0;
+
+	admin_logger(LogType::Info, &format!("User {} rotated api key {}", auth.user.userid, id), None);
+	return Ok(Json(MintedKey { id, key: format!("{}.{}", id, secret), scopes }));
+}
+
+#[utoipa::path(delete, path = "/admin/api_keys/{id}", tag = "api_keys", params(("id" = uuid::Uuid, Path, description = "Key id")), responses(
+	(status = 200, description = "Revoked"),
+	(status = 403, description = "Missing the manage_api_keys permission"),
+	(status = 404, description = "Unknown or revoked key")
+))]
+pub async fn revoke_api_key(
+	auth: Authorized<ManageApiKeys>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<uuid::Uuid>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update api_keys set revoked_at=$2 where id=$1 and revoked_at is null")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error revoking api key {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} revoked api key {}", auth.user.userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+// checks the signature of a request made by the callback server for the ticket. returns the ticket owner
+pub(crate) async fn verify_completion(pool: &PgPool, key: &ApiKey, ticket_id: i32, headers: &HeaderMap, body: &[u8]) -> Result<uuid::Uuid, StatusCode> {
+	let owner: Result<Option<(uuid::Uuid, String)>, _> = sqlx::query_as(
+		"select t.owner_id, p.callback_secret from tickets t join process_defs p on t.tenant_id=p.tenant_id and t.process_id=p.process_id where t.id=$1")
+		.bind(ticket_id)
+		.fetch_optional(pool)
+		.await;
+	if let Err(e) = owner {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} for api key {}: {}", ticket_id, key.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (owner_id, secret) = owner.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).unwrap_or("");
+	if !callbacks::verify_payload(&secret, header(TIMESTAMP_HEADER), body, header(SIGNATURE_HEADER), chrono::Utc::now().timestamp()) {
+		admin_logger(LogType::Warning, &format!("Api key {} ({}) sent an unsigned or badly signed completion for ticket {}", key.name, key.id, ticket_id), None);
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+	return Ok(owner_id);
+}
+
+// completes a blocking task node on behalf of an external system, e.g. when a callback finished its work.
+// like signals the update is made in the name of the ticket owner.
+// the body has to be signed with the callback secret of the process, see callbacks::sign_payload
+#[utoipa::path(post, path = "/service/ticket/update", tag = "service", security(("api_key" = [])), params(("X-Erp-Timestamp" = i64, Header, description = "Unix seconds the body was signed at"), ("X-Erp-Signature" = String, Header, description = "Hex hmac-sha256 of the timestamp and body with the callback secret of the process")), request_body = UpdateTicket, responses(
+	(status = 200, description = "Node completed"),
+	(status = 400, description = "The node cannot be completed this way", body = Problem, content_type = "application/problem+json"),
+	(status = 401, description = "Bad signature"),
+	(status = 403, description = "The key lacks the complete_blocking_task scope"),
+	(status = 404, description = "Unknown ticket"),
+	(status = 409, description = "Ticket is not open", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid body or node data", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn complete_task(
+	key: ApiKey,
+	extract::State(pool): extract::State<PgPool>,
+	headers: HeaderMap,
+	body: Bytes
+) -> Result<StatusCode, UpdateErr> {
+	let payload: UpdateTicket = serde_json::from_slice(&body).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+	return complete_for_key(&pool, &key, payload, &headers, &body).await;
+}
+
+// shared by complete_task and the grpc service. `body` is what the signature was made over
+pub(crate) async fn complete_for_key(pool: &PgPool, key: &ApiKey, mut payload: UpdateTicket, headers: &HeaderMap, body: &[u8]) -> Result<StatusCode, UpdateErr> {
+	key.require("complete_blocking_task")?;
+	payload.user_id = verify_completion(pool, key, payload.ticket_id, headers, body).await?;
+
+	admin_logger(LogType::Info, &format!("Api key {} ({}) completing node {} of ticket {}", key.name, key.id, payload.node, payload.ticket_id), None);
+	return ticket::apply_update(pool, payload, UpdateSource::Service).await;
+}
+
+#[cfg(test)]
+mod api_keys_tests {
+	use super::validate_scopes;
+
+	#[test]
+	fn scopes_are_validated() {
+		let scopes = vec!["signal".to_string(), " signal".to_string(), "complete_blocking_task".to_string()];
+		assert_eq!(validate_scopes(&scopes).unwrap(), vec!["signal".to_string(), "complete_blocking_task".to_string()]);
+
+		// keys never get wildcard access
+		assert_eq!(validate_scopes(&["*".to_string()]), Err("*".to_string()));
+	}
+}
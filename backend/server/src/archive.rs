@@ -0,0 +1,124 @@
+use std::time::Duration;
+use sqlx::PgPool;
+use crate::callbacks;
+use crate::logger::{admin_logger, LogType};
+use crate::jobs;
+use crate::ticket::TicketStatus;
+
+// tickets in one of these states that finished this long ago are archived
+pub const ARCHIVED_STATUSES: [TicketStatus; 3] = [TicketStatus::Closed, TicketStatus::Rejected, TicketStatus::Cancelled];
+
+pub fn archive_after() -> chrono::Duration {
+	let days = std::env::var("ARCHIVE_AFTER_DAYS")
+		.ok()
+		.and_then(|d| d.parse::<i64>().ok())
+		.unwrap_or(180);
+	return chrono::Duration::days(days);
+}
+
+// how old the finished tickets of an archive request have to be. None for a negative number of days
+pub fn older_than(days: Option<i64>) -> Option<chrono::Duration> {
+	return match days {
+		Some(days) if days < 0 => None,
+		Some(days) => Some(chrono::Duration::days(days)),
+		None => Some(archive_after())
+	};
+}
+
+fn scan_interval() -> Duration {
+	let secs = std::env::var("ARCHIVE_INTERVAL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(24 * 3600);
+	return Duration::from_secs(secs);
+}
+
+// moves tickets finished before now - older_than into tickets_archive, together with their
+// user_active_tickets rows. their pending callback jobs are cancelled. returns the number of archived tickets
+pub async fn archive_tickets(pool: &PgPool, older_than: chrono::Duration) -> Result<u64, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	let ids: Vec<(i32,)> = sqlx::query_as("select id from tickets where status=any($1) and finished_at<$2 for update skip locked")
+		.bind(&ARCHIVED_STATUSES[..])
+		.bind(now - older_than)
+		.fetch_all(&mut *tx)
+		.await?;
+	let ids = ids.into_iter().map(|i| i.0).collect::<Vec<_>>();
+	if ids.is_empty() {
+		return Ok(0);
+	}
+
+	sqlx::query(
+		r#"insert into tickets_archive (id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, state,
+				instances, priority, due_at, tenant_id, finished_at, tags, rejections, archived_at)
+			select t.id, t.owner_id, t.process_id, t.log_id, t.is_public, t.created_at, t.updated_at, t.status, t.complete, t.state,
+				t.instances, t.priority, t.due_at, t.tenant_id, t.finished_at,
+				coalesce((select array_agg(tag order by tag) from ticket_tags where ticketid=t.id), '{}'),
+				coalesce((select jsonb_agg(jsonb_build_object('node_number', r.node_number, 'userid', r.userid, 'reason', r.reason, 'created_at', r.created_at) order by r.created_at)
+					from ticket_rejections r where r.ticketid=t.id), '[]'),
+				$2
+			from tickets t where t.id=any($1)"#)
+		.bind(&ids)
+		.bind(now)
+		.execute(&mut *tx)
+		.await?;
+
+	sqlx::query(
+		r#"insert into user_active_tickets_archive (id, userid, ticketid, active, node_number, type_, instance, created_at, last_reminded_at, tenant_id)
+			select id, userid, ticketid, active, node_number, type_, instance, created_at, last_reminded_at, tenant_id
+			from user_active_tickets where ticketid=any($1)"#)
+		.bind(&ids)
+		.execute(&mut *tx)
+		.await?;
+
+	sqlx::query("delete from user_active_tickets where ticketid=any($1)")
+		.bind(&ids)
+		.execute(&mut *tx)
+		.await?;
+	sqlx::query("delete from ticket_signals where ticketid=any($1)")
+		.bind(&ids)
+		.execute(&mut *tx)
+		.await?;
+	// jobs still pending would never be sent without their ticket
+	for id in &ids {
+		callbacks::cancel_jobs(&mut tx, *id).await?;
+	}
+	// tags, watchers and rejections cascade
+	let deleted = sqlx::query("delete from tickets where id=any($1)")
+		.bind(&ids)
+		.execute(&mut *tx)
+		.await?;
+
+	tx.commit().await?;
+	return Ok(deleted.rows_affected());
+}
+
+pub async fn run_archiver(pool: PgPool) {
+	jobs::run_periodic(pool, "archive", scan_interval(), |pool| async move {
+		match archive_tickets(&pool, archive_after()).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Failed to archive tickets: {}", e), None);
+				return Err(e.to_string());
+			}
+			Ok(0) => {}
+			Ok(n) => {
+				admin_logger(LogType::Info, &format!("Archived {} tickets", n), None);
+			}
+		}
+		return Ok(());
+	}).await;
+}
+
+#[cfg(test)]
+mod archive_tests {
+	use super::{archive_after, older_than};
+
+	#[test]
+	fn requests_archive_tickets_older_than_their_days() {
+		assert_eq!(older_than(Some(0)), Some(chrono::Duration::zero()));
+		assert_eq!(older_than(Some(30)), Some(chrono::Duration::days(30)));
+		assert_eq!(older_than(None), Some(archive_after()));
+		assert_eq!(older_than(Some(-1)), None);
+	}
+}
@@ -0,0 +1,743 @@
+use axum::{body::Bytes, extract, http::{HeaderMap, StatusCode}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::callbacks;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::rbac::{self, Authorized, ManageAssets};
+use crate::schema::{FieldError, FieldErrors, internal_error, status_error, unprocessable};
+use crate::tenant;
+use crate::ticket;
+use utoipa::{IntoParams, ToSchema};
+
+// the processes whose tickets hand out and take back assets. both end in a non_blocking_task whose webhook calls
+// /service/assets/fulfil, its url can be changed with a callback endpoint
+fn request_process_id() -> String {
+	return std::env::var("ASSET_REQUEST_PROCESS").unwrap_or("asset_request".to_string());
+}
+
+fn return_process_id() -> String {
+	return std::env::var("ASSET_RETURN_PROCESS").unwrap_or("asset_return".to_string());
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AssetInput {
+	pub tag: String,
+	pub name: String,
+	pub category: String,
+	pub serial_number: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Asset {
+	pub id: i32,
+	pub tag: String,
+	pub name: String,
+	pub category: String,
+	pub serial_number: Option<String>,
+	pub status: String,
+	pub assigned_to: Option<uuid::Uuid>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Assignment {
+	pub id: i32,
+	pub userid: uuid::Uuid,
+	pub request_id: Option<i32>,
+	pub return_request_id: Option<i32>,
+	pub assigned_at: chrono::DateTime<chrono::Utc>,
+	pub returned_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AssetDetail {
+	#[serde(flatten)]
+	pub asset: Asset,
+	// newest first
+	pub history: Vec<Assignment>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AssetsQuery {
+	pub status: Option<String>,
+	pub category: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateAssetRequest {
+	// request or return
+	pub kind: String,
+	// of a request
+	pub category: Option<String>,
+	// of a return
+	pub asset_id: Option<i32>,
+	pub reason: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct AssetRequest {
+	pub id: i32,
+	pub kind: String,
+	pub userid: uuid::Uuid,
+	pub category: Option<String>,
+	pub asset_id: Option<i32>,
+	pub reason: Option<String>,
+	pub status: String,
+	pub fulfilled_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub fulfil_error: Option<String>,
+	pub ticket_id: Option<i32>,
+	// set instead of ticket_id for the equipment of an onboarding or offboarding
+	pub onboarding_id: Option<i32>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+pub fn check_asset(asset: &AssetInput) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if asset.tag.trim().is_empty() {
+		error("/tag", "A tag is required");
+	}
+	if asset.name.trim().is_empty() {
+		error("/name", "A name is required");
+	}
+	if asset.category.trim().is_empty() {
+		error("/category", "A category is required");
+	}
+	return errors;
+}
+
+// requests name a category, returns the asset given back
+pub fn check_request(request: &CreateAssetRequest) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	match request.kind.as_str() {
+		"request" => {
+			if request.category.as_deref().map(str::trim).unwrap_or("").is_empty() {
+				error("/category", "A category is required");
+			}
+			if request.asset_id.is_some() {
+				error("/asset_id", "Requests are for a category, the asset is picked when the request is fulfilled");
+			}
+		}
+		"return" => {
+			if request.asset_id.is_none() {
+				error("/asset_id", "The asset to return is required");
+			}
+		}
+		_ => error("/kind", "Expected request or return")
+	}
+	return errors;
+}
+
+// the status a request in `current` moves to when its ticket asks for `target`
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("pending_approval", "approved") => Some("approved"),
+		("pending_approval" | "approved", "closed") => Some("closed"),
+		("pending_approval", "rejected") => Some("rejected"),
+		("pending_approval" | "approved", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+fn tag_taken(e: &sqlx::Error) -> bool {
+	return e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false);
+}
+
+async fn read_asset(conn: &mut PgConnection, id: i32) -> Result<Asset, StatusCode> {
+	let asset: Result<Option<Asset>, _> = sqlx::query_as("select * from assets where id=$1")
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = asset {
+		admin_logger(LogType::Error, &format!("Error reading asset {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return asset.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_request(conn: &mut PgConnection, id: i32, lock: bool) -> Result<AssetRequest, StatusCode> {
+	let sql = if lock { "select * from asset_requests where id=$1 for update" } else { "select * from asset_requests where id=$1" };
+	let request: Result<Option<AssetRequest>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = request {
+		admin_logger(LogType::Error, &format!("Error reading asset request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return request.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+#[utoipa::path(post, path = "/assets", tag = "assets", request_body = AssetInput, responses(
+	(status = 201, description = "Created", body = Asset),
+	(status = 403, description = "Missing the manage_assets permission"),
+	(status = 409, description = "An asset with this tag exists", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn create_asset(
+	auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<AssetInput>
+) -> Result<(StatusCode, Json<Asset>), (StatusCode, Json<FieldErrors>)> {
+	let errors = check_asset(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let asset: Result<Asset, _> = sqlx::query_as(
+		r#"insert into assets (tag, name, category, serial_number, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $5) returning *"#)
+		.bind(payload.tag.trim())
+		.bind(payload.name.trim())
+		.bind(payload.category.trim())
+		.bind(payload.serial_number.as_deref().map(str::trim))
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = asset {
+		if tag_taken(&e) {
+			return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError { field: "/tag".to_string(), message: "An asset with this tag exists".to_string() }] })));
+		}
+		admin_logger(LogType::Error, &format!("Error saving asset of {}: {}", auth.user.userid, e), None);
+		return Err(internal_error());
+	}
+	let asset = asset.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} added asset {} ({})", auth.user.userid, asset.id, asset.tag), None);
+	return Ok((StatusCode::CREATED, Json(asset)));
+}
+
+#[utoipa::path(get, path = "/assets", tag = "assets", params(AssetsQuery), responses(
+	(status = 200, description = "Assets by tag", body = Vec<Asset>),
+	(status = 403, description = "Missing the manage_assets permission")
+))]
+pub async fn get_assets(
+	_auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<AssetsQuery>
+) -> Result<Json<Vec<Asset>>, StatusCode> {
+	let assets: Result<Vec<Asset>, _> = sqlx::query_as(
+		"select * from assets where ($1::varchar is null or status=$1) and ($2::varchar is null or category=$2) order by category, tag")
+		.bind(&query.status)
+		.bind(&query.category)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = assets {
+		admin_logger(LogType::Error, &format!("Error reading assets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(assets.unwrap()));
+}
+
+// the asset with everyone who had it
+#[utoipa::path(get, path = "/assets/{id}", tag = "assets", params(("id" = i32, Path, description = "Asset id")), responses(
+	(status = 200, description = "The asset with its assignments", body = AssetDetail),
+	(status = 403, description = "Missing the manage_assets permission"),
+	(status = 404, description = "Unknown asset")
+))]
+pub async fn get_asset(
+	_auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<AssetDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let asset = read_asset(&mut conn, id).await?;
+
+	let history: Result<Vec<Assignment>, _> = sqlx::query_as(
+		r#"select id, userid, request_id, return_request_id, assigned_at, returned_at from asset_assignments
+			where asset_id=$1 order by assigned_at desc, id desc"#)
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = history {
+		admin_logger(LogType::Error, &format!("Error reading the history of asset {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(AssetDetail { asset, history: history.unwrap() }));
+}
+
+// changes the description of the asset. who has it only changes through requests and returns
+#[utoipa::path(put, path = "/assets/{id}", tag = "assets", params(("id" = i32, Path, description = "Asset id")), request_body = AssetInput, responses(
+	(status = 200, description = "Updated", body = Asset),
+	(status = 403, description = "Missing the manage_assets permission"),
+	(status = 404, description = "Unknown asset", body = FieldErrors),
+	(status = 409, description = "An asset with this tag exists", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn update_asset(
+	auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<AssetInput>
+) -> Result<Json<Asset>, (StatusCode, Json<FieldErrors>)> {
+	let errors = check_asset(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let asset: Result<Option<Asset>, _> = sqlx::query_as(
+		"update assets set tag=$2, name=$3, category=$4, serial_number=$5, updated_at=$6 where id=$1 returning *")
+		.bind(id)
+		.bind(payload.tag.trim())
+		.bind(payload.name.trim())
+		.bind(payload.category.trim())
+		.bind(payload.serial_number.as_deref().map(str::trim))
+		.bind(chrono::Utc::now())
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = asset {
+		if tag_taken(&e) {
+			return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError { field: "/tag".to_string(), message: "An asset with this tag exists".to_string() }] })));
+		}
+		admin_logger(LogType::Error, &format!("Error updating asset {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let asset = asset.unwrap().ok_or(status_error(StatusCode::NOT_FOUND))?;
+
+	admin_logger(LogType::Info, &format!("User {} updated asset {}", auth.user.userid, id), None);
+	return Ok(Json(asset));
+}
+
+// assets stay for their history, they are only retired. an assigned asset has to be returned first
+#[utoipa::path(delete, path = "/assets/{id}", tag = "assets", params(("id" = i32, Path, description = "Asset id")), responses(
+	(status = 204, description = "Retired"),
+	(status = 403, description = "Missing the manage_assets permission"),
+	(status = 404, description = "Unknown asset"),
+	(status = 409, description = "The asset is assigned")
+))]
+pub async fn retire_asset(
+	auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update assets set status='retired', updated_at=$2 where id=$1 and status<>'assigned'")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error retiring asset {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		// exists, so it is assigned
+		read_asset(&mut conn, id).await?;
+		return Err(StatusCode::CONFLICT);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} retired asset {}", auth.user.userid, id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// the assets someone has now. for themselves and manage_assets holders
+#[utoipa::path(get, path = "/users/{id}/assets", tag = "assets", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Assets assigned to the user", body = Vec<Asset>),
+	(status = 403, description = "Only the own assets or with manage_assets")
+))]
+pub async fn get_user_assets(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<Json<Vec<Asset>>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if userid != user.userid {
+		match rbac::has_permission(&mut conn, user.userid, "manage_assets").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN),
+			Ok(true) => {}
+		}
+	}
+
+	let assets: Result<Vec<Asset>, _> = sqlx::query_as("select * from assets where assigned_to=$1 order by category, tag")
+		.bind(userid)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = assets {
+		admin_logger(LogType::Error, &format!("Error reading the assets of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(assets.unwrap()));
+}
+
+// saves the request and starts its ticket. the asset is handed out or taken back once the ticket reaches its task node
+#[utoipa::path(post, path = "/asset_requests", tag = "assets", request_body = CreateAssetRequest, responses(
+	(status = 201, description = "Created with its approval ticket", body = AssetRequest),
+	(status = 422, description = "Invalid kind, category or asset", body = FieldErrors)
+))]
+pub async fn create_asset_request(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateAssetRequest>
+) -> Result<(StatusCode, Json<AssetRequest>), (StatusCode, Json<FieldErrors>)> {
+	let errors = check_request(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+	let category = payload.category.as_deref().map(str::trim);
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	// the approvers see what is asked for in the ticket
+	let mut data = Map::new();
+	data.insert("kind".to_string(), json!(payload.kind));
+	data.insert("reason".to_string(), json!(payload.reason));
+	let process_id = if payload.kind == "request" {
+		let kept: Result<(bool,), _> = sqlx::query_as("select exists(select 1 from assets where category=$1 and status<>'retired')")
+			.bind(category)
+			.fetch_one(&mut *tx)
+			.await;
+		if let Err(e) = kept {
+			admin_logger(LogType::Error, &format!("Error reading assets of category {:?}: {}", category, e), None);
+			return Err(internal_error());
+		}
+		if !kept.unwrap().0 {
+			return Err(unprocessable("/category", format!("There are no assets of category {}", category.unwrap_or(""))));
+		}
+		data.insert("category".to_string(), json!(category));
+		request_process_id()
+	} else {
+		let asset_id = payload.asset_id.unwrap_or(0);
+		let asset: Result<Option<(String, String, bool)>, _> = sqlx::query_as(
+			r#"select tag, name, exists(select 1 from asset_requests r where r.asset_id=a.id and r.kind='return'
+					and r.status in ('pending_approval', 'approved') and r.fulfilled_at is null)
+				from assets a where id=$1 and assigned_to=$2"#)
+			.bind(asset_id)
+			.bind(user.userid)
+			.fetch_optional(&mut *tx)
+			.await;
+		if let Err(e) = asset {
+			admin_logger(LogType::Error, &format!("Error reading asset {}: {}", asset_id, e), None);
+			return Err(internal_error());
+		}
+		let Some((tag, name, returning)) = asset.unwrap() else {
+			return Err(unprocessable("/asset_id", format!("Asset {} is not assigned to you", asset_id)));
+		};
+		if returning {
+			return Err(unprocessable("/asset_id", format!("Asset {} is already being returned", tag)));
+		}
+		data.insert("asset_id".to_string(), json!(asset_id));
+		data.insert("tag".to_string(), json!(tag));
+		data.insert("name".to_string(), json!(name));
+		return_process_id()
+	};
+
+	let now = chrono::Utc::now();
+	let request: Result<AssetRequest, _> = sqlx::query_as(
+		r#"insert into asset_requests (kind, userid, category, asset_id, reason, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $6) returning *"#)
+		.bind(&payload.kind)
+		.bind(user.userid)
+		.bind(category)
+		.bind(payload.asset_id)
+		.bind(&payload.reason)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = request {
+		admin_logger(LogType::Error, &format!("Error saving asset request of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let request = request.unwrap();
+
+	data.insert("request_id".to_string(), json!(request.id));
+	let tag = if payload.kind == "request" { "asset-request" } else { "asset-return" };
+	let new = NewTicket { process_id, data, tag, due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "asset_requests", id: request.id }, new)
+		.await
+		.map_err(status_error)?;
+	// node 0 may already have moved it
+	let request = read_request(&mut tx, request.id, false).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting asset request {}: {}", request.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("User {} made asset {} {} with ticket {}", user.userid, request.kind, request.id, ticket.id), ticket.log_id);
+	return Ok((StatusCode::CREATED, Json(request)));
+}
+
+#[utoipa::path(get, path = "/asset_requests", tag = "assets", responses(
+	(status = 200, description = "Own asset requests", body = Vec<AssetRequest>)
+))]
+pub async fn get_asset_requests(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<AssetRequest>>, StatusCode> {
+	let requests: Result<Vec<AssetRequest>, _> = sqlx::query_as("select * from asset_requests where userid=$1 order by created_at desc, id desc")
+		.bind(user.userid)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = requests {
+		admin_logger(LogType::Error, &format!("Error reading asset requests of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(requests.unwrap()));
+}
+
+// for the requester, the approvers of its ticket and manage_assets holders
+#[utoipa::path(get, path = "/asset_requests/{id}", tag = "assets", params(("id" = i32, Path, description = "Asset request id")), responses(
+	(status = 200, description = "The request", body = AssetRequest),
+	(status = 404, description = "Unknown request or not visible to the user")
+))]
+pub async fn get_asset_request(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<AssetRequest>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let request = read_request(&mut conn, id, false).await?;
+
+	if request.userid != user.userid {
+		let approver: Result<(bool,), _> = sqlx::query_as("select exists(select 1 from user_active_tickets where ticketid=$1 and userid=$2 and type_='approve')")
+			.bind(request.ticket_id)
+			.bind(user.userid)
+			.fetch_one(&mut *conn)
+			.await;
+		let allowed = match approver {
+			Ok((true,)) => Ok(true),
+			Ok((false,)) => rbac::has_permission(&mut conn, user.userid, "manage_assets").await,
+			Err(e) => Err(e)
+		};
+		match allowed {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking access of {} to asset request {}: {}", user.userid, id, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			// the same answer as for requests that do not exist
+			Ok(false) => return Err(StatusCode::NOT_FOUND),
+			Ok(true) => {}
+		}
+	}
+	return Ok(Json(request));
+}
+
+// assigns an available asset of the category to the requester. Ok(Err) when there is none, nothing is written then
+async fn hand_out(conn: &mut PgConnection, request: &AssetRequest, now: chrono::DateTime<chrono::Utc>) -> Result<Result<i32, FieldError>, sqlx::Error> {
+	let category = request.category.clone().unwrap_or_default();
+	let asset: Option<(i32,)> = sqlx::query_as("select id from assets where category=$1 and status='available' order by id limit 1 for update skip locked")
+		.bind(&category)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((asset_id,)) = asset else {
+		return Ok(Err(FieldError { field: "/category".to_string(), message: format!("No {} is available", category) }));
+	};
+
+	sqlx::query("update assets set status='assigned', assigned_to=$2, updated_at=$3 where id=$1")
+		.bind(asset_id)
+		.bind(request.userid)
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	// the service call has no tenant, the assignment belongs to the one of the asset
+	sqlx::query(
+		r#"insert into asset_assignments (tenant_id, asset_id, userid, request_id, assigned_at)
+			values ((select tenant_id from assets where id=$1), $1, $2, $3, $4)"#)
+		.bind(asset_id)
+		.bind(request.userid)
+		.bind(request.id)
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	return Ok(Ok(asset_id));
+}
+
+// makes the returned asset available again. Ok(Err) when the requester does not have it anymore
+async fn take_back(conn: &mut PgConnection, request: &AssetRequest, now: chrono::DateTime<chrono::Utc>) -> Result<Result<i32, FieldError>, sqlx::Error> {
+	let asset_id = request.asset_id.unwrap_or(0);
+	let query = sqlx::query("update assets set status='available', assigned_to=null, updated_at=$3 where id=$1 and assigned_to=$2")
+		.bind(asset_id)
+		.bind(request.userid)
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	if query.rows_affected() == 0 {
+		return Ok(Err(FieldError { field: "/asset_id".to_string(), message: format!("Asset {} is not assigned to the requester", asset_id) }));
+	}
+
+	sqlx::query("update asset_assignments set returned_at=$2, return_request_id=$3 where asset_id=$1 and returned_at is null")
+		.bind(asset_id)
+		.bind(now)
+		.bind(request.id)
+		.execute(&mut *conn)
+		.await?;
+	return Ok(Ok(asset_id));
+}
+
+// hands out or takes back the asset of an approved request. fulfilled requests are left alone, the task may be delivered
+// more than once. a failure is kept in fulfil_error and returned, manage_assets holders can retry once it is fixed
+pub(crate) async fn fulfil(pool: &PgPool, id: i32) -> Result<Result<AssetRequest, FieldError>, StatusCode> {
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let request = read_request(&mut tx, id, true).await?;
+	if request.fulfilled_at.is_some() {
+		return Ok(Ok(request));
+	}
+	if !matches!(request.status.as_str(), "approved" | "closed") {
+		return Err(StatusCode::CONFLICT);
+	}
+
+	let now = chrono::Utc::now();
+	let outcome = if request.kind == "request" { hand_out(&mut tx, &request, now).await } else { take_back(&mut tx, &request, now).await };
+	if let Err(e) = outcome {
+		admin_logger(LogType::Error, &format!("Error fulfilling asset request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let outcome = outcome.unwrap();
+
+	let query = match &outcome {
+		Ok(asset_id) => sqlx::query("update asset_requests set asset_id=$2, fulfilled_at=$3, fulfil_error=null, updated_at=$3 where id=$1")
+			.bind(id)
+			.bind(asset_id)
+			.bind(now),
+		Err(error) => sqlx::query("update asset_requests set fulfil_error=$2, updated_at=$3 where id=$1")
+			.bind(id)
+			.bind(&error.message)
+			.bind(now)
+	};
+	if let Err(e) = query.execute(&mut *tx).await {
+		admin_logger(LogType::Error, &format!("Error saving the fulfilment of asset request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let request = read_request(&mut tx, id, false).await?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the fulfilment of asset request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	match outcome {
+		Ok(asset_id) => {
+			admin_logger(LogType::Info, &format!("Asset {} {} for request {} of {}", asset_id, if request.kind == "request" { "assigned" } else { "returned" }, id, request.userid), None);
+			return Ok(Ok(request));
+		}
+		Err(error) => {
+			admin_logger(LogType::Warning, &format!("Asset request {} cannot be fulfilled: {}", id, error.message), None);
+			return Ok(Err(error));
+		}
+	}
+}
+
+// the webhook of the task node of the asset processes
+#[utoipa::path(post, path = "/service/assets/fulfil", tag = "service", security(()), params(("X-Erp-Timestamp" = i64, Header, description = "Unix seconds the body was signed at"), ("X-Erp-Signature" = String, Header, description = "Hex hmac-sha256 of the timestamp and body with the callback secret of the process")), request_body(content = Object, description = "The task payload posted by the webhook callback of the node, unchanged"), responses(
+	(status = 200, description = "The approved request is fulfilled"),
+	(status = 401, description = "Bad signature"),
+	(status = 404, description = "No asset request belongs to the ticket"),
+	(status = 409, description = "The request cannot be fulfilled"),
+	(status = 422, description = "Not a task payload")
+))]
+pub async fn fulfil_task(
+	extract::State(pool): extract::State<PgPool>,
+	headers: HeaderMap,
+	body: Bytes
+) -> Result<StatusCode, StatusCode> {
+	let tenant = callbacks::task_tenant(&pool, &body).await?;
+	return tenant::scoped(Some(tenant), fulfil_signed_task(pool, headers, body)).await;
+}
+
+async fn fulfil_signed_task(pool: PgPool, headers: HeaderMap, body: Bytes) -> Result<StatusCode, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let ticket_id = callbacks::verify_task(&mut conn, &headers, &body).await?;
+
+	let request: Result<Option<(i32,)>, _> = sqlx::query_as("select id from asset_requests where ticket_id=$1")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = request {
+		admin_logger(LogType::Error, &format!("Error reading the asset request of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (id,) = request.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+	drop(conn);
+
+	return match fulfil(&pool, id).await? {
+		Ok(_) => Ok(StatusCode::OK),
+		Err(_) => Err(StatusCode::CONFLICT)
+	};
+}
+
+// tries the fulfilment of an approved request again, e.g. after assets of the category were added
+#[utoipa::path(post, path = "/admin/asset_requests/{id}/fulfil", tag = "assets", params(("id" = i32, Path, description = "Asset request id")), responses(
+	(status = 200, description = "Fulfilled", body = AssetRequest),
+	(status = 403, description = "Missing the manage_assets permission"),
+	(status = 404, description = "Unknown request", body = FieldErrors),
+	(status = 409, description = "Still cannot be fulfilled, e.g. no free asset of the category", body = FieldErrors)
+))]
+pub async fn retry_fulfilment(
+	auth: Authorized<ManageAssets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<AssetRequest>, (StatusCode, Json<FieldErrors>)> {
+	admin_logger(LogType::Info, &format!("User {} retrying the fulfilment of asset request {}", auth.user.userid, id), None);
+	return match fulfil(&pool, id).await.map_err(status_error)? {
+		Ok(request) => Ok(Json(request)),
+		Err(error) => Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![error] })))
+	};
+}
+
+// moves the request of the ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let request: Option<(i32, String)> = sqlx::query_as("select id, status from asset_requests where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = request else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update asset_requests set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Asset request {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod assets_tests {
+	use super::{check_request, next_status, CreateAssetRequest};
+
+	fn request(kind: &str, category: Option<&str>, asset_id: Option<i32>) -> CreateAssetRequest {
+		return CreateAssetRequest { kind: kind.to_string(), category: category.map(str::to_string), asset_id, reason: None };
+	}
+
+	#[test]
+	fn requests_name_a_category_and_returns_an_asset() {
+		assert!(check_request(&request("request", Some("laptop"), None)).is_empty());
+		assert!(check_request(&request("return", None, Some(3))).is_empty());
+
+		let fields = |r: CreateAssetRequest| check_request(&r).into_iter().map(|e| e.field).collect::<Vec<String>>();
+		assert_eq!(fields(request("request", Some(" "), Some(3))), vec!["/category", "/asset_id"]);
+		assert_eq!(fields(request("return", Some("laptop"), None)), vec!["/asset_id"]);
+		assert_eq!(fields(request("borrow", None, None)), vec!["/kind"]);
+	}
+
+	#[test]
+	fn requests_follow_their_ticket() {
+		assert_eq!(next_status("pending_approval", "approved"), Some("approved"));
+		assert_eq!(next_status("approved", "closed"), Some("closed"));
+		assert_eq!(next_status("approved", "rejected"), None);
+		assert_eq!(next_status("closed", "cancelled"), None);
+		assert_eq!(next_status("pending_approval", "sent"), None);
+	}
+}
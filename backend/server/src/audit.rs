@@ -0,0 +1,320 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::SubsecRound;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ViewAudit};
+use crate::tenant;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+const MAX_AUDIT_LIMIT: i64 = 1000;
+const VERIFY_BATCH: i64 = 1000;
+// advisory lock held by chained inserts of a tenant until their transaction ends
+const CHAIN_LOCK: i32 = 7_417_753;
+
+// links every event to the one before it so edited or removed rows can be detected. off by default,
+// chained inserts wait for each other until their transactions commit
+fn hash_chain_enabled() -> bool {
+	return std::env::var("AUDIT_HASH_CHAIN")
+		.ok()
+		.and_then(|s| s.parse::<bool>().ok())
+		.unwrap_or(false);
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {Approve, Reject, Reassign, AssignRole, UnassignRole, RenameRole, DeleteRole, SetPermissions, SetLimit, Replay}
+
+impl AuditAction {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			AuditAction::Approve => "approve",
+			AuditAction::Reject => "reject",
+			AuditAction::Reassign => "reassign",
+			AuditAction::AssignRole => "assign_role",
+			AuditAction::UnassignRole => "unassign_role",
+			AuditAction::RenameRole => "rename_role",
+			AuditAction::DeleteRole => "delete_role",
+			AuditAction::SetPermissions => "set_permissions",
+			AuditAction::SetLimit => "set_limit",
+			AuditAction::Replay => "replay"
+		};
+	}
+}
+
+#[derive(Serialize, FromRow, Clone, Debug, ToSchema)]
+pub struct AuditEvent {
+	pub actor: Option<uuid::Uuid>,
+	pub action: String,
+	// ticket, user or role
+	pub entity_type: String,
+	pub entity_id: String,
+	#[schema(value_type = Option<Object>)]
+	pub before: Option<serde_json::Value>,
+	#[schema(value_type = Option<Object>)]
+	pub after: Option<serde_json::Value>,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct AuditRecord {
+	pub id: i64,
+	#[sqlx(flatten)]
+	#[serde(flatten)]
+	pub event: AuditEvent,
+	pub prev_hash: Option<String>,
+	pub hash: Option<String>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+	pub entity_type: Option<String>,
+	pub entity_id: Option<String>,
+	pub actor: Option<uuid::Uuid>,
+	pub action: Option<String>,
+	pub from: Option<chrono::DateTime<chrono::Utc>>,
+	pub to: Option<chrono::DateTime<chrono::Utc>>,
+	// id of the oldest event of the previous page
+	pub before_id: Option<i64>,
+	pub limit: Option<i64>
+}
+
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct ChainStatus {
+	pub checked: u64,
+	pub hashed: u64,
+	// first event whose hash or link to the previous event does not match
+	pub broken_at: Option<i64>
+}
+
+impl AuditEvent {
+	pub fn new(
+		actor: Option<uuid::Uuid>,
+		action: AuditAction,
+		entity_type: &str,
+		entity_id: impl ToString,
+		before: Option<serde_json::Value>,
+		after: Option<serde_json::Value>
+	) -> AuditEvent {
+		return AuditEvent {
+			actor,
+			action: action.as_str().to_string(),
+			entity_type: entity_type.to_string(),
+			entity_id: entity_id.to_string(),
+			before,
+			after,
+			// postgres keeps microseconds, the hash has to match what is read back
+			created_at: chrono::Utc::now().trunc_subsecs(6)
+		};
+	}
+}
+
+// hex sha256 of the event and the hash before it. json objects are serialized with sorted keys
+// so the snapshots hash the same after the round trip through jsonb
+pub fn event_hash(prev_hash: Option<&str>, event: &AuditEvent) -> String {
+	let canonical = serde_json::json!([
+		prev_hash,
+		event.actor,
+		event.action,
+		event.entity_type,
+		event.entity_id,
+		event.before,
+		event.after,
+		event.created_at.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+	]);
+	return format!("{:x}", Sha256::digest(canonical.to_string().as_bytes()));
+}
+
+// events have to be in id order. the chain may start after events that were written without a hash
+pub fn verify_chain(previous: Option<&AuditRecord>, records: &[AuditRecord], status: &mut ChainStatus) {
+	let mut previous_hash = previous.and_then(|r| r.hash.clone());
+	for record in records {
+		status.checked += 1;
+		if let Some(hash) = &record.hash {
+			status.hashed += 1;
+			let linked = record.prev_hash == previous_hash;
+			if status.broken_at.is_none() && (!linked || *hash != event_hash(record.prev_hash.as_deref(), &record.event)) {
+				status.broken_at = Some(record.id);
+			}
+		}
+		previous_hash = record.hash.clone();
+	}
+}
+
+// written in the transaction of the change so the event exists exactly when the change does
+pub async fn record(conn: &mut PgConnection, event: AuditEvent) -> Result<(), sqlx::Error> {
+	// the tenant of the request, or of the actor for work outside of one. every tenant has a chain of its own
+	let (tenant,): (i32,) = sqlx::query_as("select coalesce(current_tenant(), (select tenant_id from users where userid=$1), $2)")
+		.bind(event.actor)
+		.bind(tenant::DEFAULT_TENANT)
+		.fetch_one(&mut *conn)
+		.await?;
+	let (prev_hash, hash) = if hash_chain_enabled() {
+		sqlx::query("select pg_advisory_xact_lock($1, $2)")
+			.bind(CHAIN_LOCK)
+			.bind(tenant)
+			.execute(&mut *conn)
+			.await?;
+		let last: Option<(Option<String>,)> = sqlx::query_as("select hash from audit_events where tenant_id=$1 order by id desc limit 1")
+			.bind(tenant)
+			.fetch_optional(&mut *conn)
+			.await?;
+		let prev_hash = last.and_then(|l| l.0);
+		let hash = event_hash(prev_hash.as_deref(), &event);
+		(prev_hash, Some(hash))
+	}
+	else {
+		(None, None)
+	};
+
+	sqlx::query(
+		r#"insert into audit_events (actor, action, entity_type, entity_id, before, after, created_at, prev_hash, hash, tenant_id)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#)
+		.bind(event.actor)
+		.bind(&event.action)
+		.bind(&event.entity_type)
+		.bind(&event.entity_id)
+		.bind(&event.before)
+		.bind(&event.after)
+		.bind(event.created_at)
+		.bind(prev_hash)
+		.bind(hash)
+		.bind(tenant)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+// newest first
+#[utoipa::path(get, path = "/admin/audit", tag = "admin", params(AuditQuery), responses(
+	(status = 200, description = "Audit events, newest first", body = Vec<AuditRecord>),
+	(status = 403, description = "Missing the view_audit permission")
+))]
+pub async fn get_audit_events(
+	_auth: Authorized<ViewAudit>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<AuditQuery>
+) -> Result<Json<Vec<AuditRecord>>, StatusCode> {
+	let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT).clamp(1, MAX_AUDIT_LIMIT);
+	let records: Result<Vec<AuditRecord>, _> = sqlx::query_as(
+		r#"select * from audit_events
+			where ($1::varchar is null or entity_type=$1)
+				and ($2::varchar is null or entity_id=$2)
+				and ($3::uuid is null or actor=$3)
+				and ($4::varchar is null or action=$4)
+				and ($5::timestamptz is null or created_at>=$5)
+				and ($6::timestamptz is null or created_at<$6)
+				and ($7::bigint is null or id<$7)
+			order by id desc limit $8"#)
+		.bind(query.entity_type)
+		.bind(query.entity_id)
+		.bind(query.actor)
+		.bind(query.action)
+		.bind(query.from)
+		.bind(query.to)
+		.bind(query.before_id)
+		.bind(limit)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = records {
+		admin_logger(LogType::Error, &format!("Error reading audit events: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(records.unwrap()));
+}
+
+// walks the events of the tenant in batches and recomputes every hash
+#[utoipa::path(get, path = "/admin/audit/verify", tag = "admin", responses(
+	(status = 200, description = "Whether the hash chain of the tenant is intact", body = ChainStatus),
+	(status = 403, description = "Missing the view_audit permission")
+))]
+pub async fn verify_audit_chain(
+	auth: Authorized<ViewAudit>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<ChainStatus>, StatusCode> {
+	let mut status = ChainStatus { checked: 0, hashed: 0, broken_at: None };
+	let mut previous: Option<AuditRecord> = None;
+	loop {
+		let batch: Result<Vec<AuditRecord>, _> = sqlx::query_as("select * from audit_events where id>$1 order by id limit $2")
+			.bind(previous.as_ref().map(|r| r.id).unwrap_or(0))
+			.bind(VERIFY_BATCH)
+			.fetch_all(&pool)
+			.await;
+		if let Err(e) = batch {
+			admin_logger(LogType::Error, &format!("Error reading audit events: {}", e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		let mut batch = batch.unwrap();
+		verify_chain(previous.as_ref(), &batch, &mut status);
+		if (batch.len() as i64) < VERIFY_BATCH {
+			break;
+		}
+		previous = batch.pop();
+	}
+
+	if let Some(id) = status.broken_at {
+		admin_logger(LogType::Warning, &format!("Audit chain verified by {} is broken at event {}", auth.user.userid, id), None);
+	}
+	return Ok(Json(status));
+}
+
+#[cfg(test)]
+mod audit_tests {
+	use super::{event_hash, verify_chain, AuditAction, AuditEvent, AuditRecord, ChainStatus};
+
+	fn chain(events: Vec<AuditEvent>) -> Vec<AuditRecord> {
+		let mut records: Vec<AuditRecord> = Vec::new();
+		for (i, event) in events.into_iter().enumerate() {
+			let prev_hash = records.last().and_then(|r| r.hash.clone());
+			let hash = event_hash(prev_hash.as_deref(), &event);
+			records.push(AuditRecord { id: i as i64 + 1, event, prev_hash, hash: Some(hash) });
+		}
+		return records;
+	}
+
+	fn status() -> ChainStatus {
+		return ChainStatus { checked: 0, hashed: 0, broken_at: None };
+	}
+
+	#[test]
+	fn chains_detect_tampering() {
+		let admin = uuid::Uuid::new_v4();
+		let mut records = chain(vec![
+			AuditEvent::new(Some(admin), AuditAction::AssignRole, "user", admin, None, Some(serde_json::json!({"role_": "hr", "b": 1, "a": 2}))),
+			AuditEvent::new(Some(admin), AuditAction::Approve, "ticket", 7, None, Some(serde_json::json!({"node": 2}))),
+			AuditEvent::new(None, AuditAction::Reject, "ticket", 8, None, None)
+		]);
+
+		let mut intact = status();
+		verify_chain(None, &records, &mut intact);
+		assert_eq!(intact, ChainStatus { checked: 3, hashed: 3, broken_at: None });
+
+		// verifying from the middle uses the event before the batch
+		let mut tail = status();
+		verify_chain(Some(&records[0]), &records[1..], &mut tail);
+		assert_eq!(tail.broken_at, None);
+
+		records[1].event.after = Some(serde_json::json!({"node": 3}));
+		let mut edited = status();
+		verify_chain(None, &records, &mut edited);
+		assert_eq!(edited.broken_at, Some(2));
+
+		let mut removed = status();
+		verify_chain(None, &[records.remove(0), records.remove(1)], &mut removed);
+		assert_eq!(removed.broken_at, Some(3));
+	}
+
+	#[test]
+	fn hashes_survive_the_jsonb_round_trip() {
+		let event = AuditEvent::new(None, AuditAction::SetPermissions, "role", "hr", Some(serde_json::json!({"z": [1], "a": "x"})), None);
+		let mut read_back = event.clone();
+		// jsonb hands the keys back in its own order
+		read_back.before = Some(serde_json::from_str(r#"{"a": "x", "z": [1]}"#).unwrap());
+		assert_eq!(event_hash(None, &event), event_hash(None, &read_back));
+		assert_ne!(event_hash(None, &event), event_hash(Some("00"), &event));
+	}
+}
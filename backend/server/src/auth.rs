@@ -0,0 +1,539 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use axum::{async_trait, extract::{self, FromRequestParts}, http::{header, request::Parts, HeaderMap, StatusCode}, Json};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::logger::{self, admin_logger, LogType};
+use crate::tenant;
+use utoipa::ToSchema;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub static JWT_SECRET: Lazy<String> = Lazy::new(|| {
+	return std::env::var("JWT_SECRET").expect("JWT_SECRET not defined");
+});
+
+// access tokens are short lived, clients keep their session with /auth/refresh
+fn token_ttl() -> chrono::Duration {
+	let secs = std::env::var("JWT_TTL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(900);
+	return chrono::Duration::seconds(secs);
+}
+
+// a session that has not been refreshed for this long expires
+fn refresh_ttl() -> chrono::Duration {
+	let secs = std::env::var("REFRESH_TTL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(7 * 24 * 3600);
+	return chrono::Duration::seconds(secs);
+}
+
+fn max_failed_logins() -> i64 {
+	return std::env::var("LOGIN_MAX_ATTEMPTS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(5);
+}
+
+// failed logins older than this are forgotten
+fn lockout_window() -> chrono::Duration {
+	let secs = std::env::var("LOGIN_LOCKOUT_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(900);
+	return chrono::Duration::seconds(secs);
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Claims {
+	// userid
+	pub sub: uuid::Uuid,
+	pub username: String,
+	// tenant of the user. every query of the request is scoped to it
+	pub tid: i32,
+	// session the token belongs to. checked on every request so revoked sessions stop working immediately
+	pub sid: uuid::Uuid,
+	pub iat: i64,
+	pub exp: i64
+}
+
+// the user a request was made by, resolved from the `Authorization: Bearer <token>` header
+pub struct AuthUser {
+	pub userid: uuid::Uuid,
+	pub username: String,
+	pub session_id: uuid::Uuid
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LoginRequest {
+	pub username: String,
+	pub password: String,
+	// the name of the tenant, needed when the username is taken in more than one
+	#[serde(default)]
+	pub tenant: Option<String>
+}
+#[derive(Serialize, ToSchema)]
+pub struct LoginResponse {
+	pub token: String,
+	pub expires_at: chrono::DateTime<chrono::Utc>,
+	// "<session id>.<secret>", can be used once
+	pub refresh_token: String
+}
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshRequest {
+	pub refresh_token: String
+}
+#[derive(FromRow)]
+struct Session {
+	id: uuid::Uuid,
+	userid: uuid::Uuid,
+	username: String,
+	tenant_id: i32,
+	refresh_hash: String,
+	previous_hash: Option<String>,
+	expires_at: chrono::DateTime<chrono::Utc>,
+	revoked_at: Option<chrono::DateTime<chrono::Utc>>
+}
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePassword {
+	pub current_password: String,
+	pub new_password: String
+}
+
+// argon2id with the default parameters
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+	let salt = SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes())?;
+	let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+	return Ok(hash.to_string());
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+	return match PasswordHash::new(hash) {
+		Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+		Err(_) => false
+	};
+}
+
+pub fn check_password_strength(password: &str) -> Result<(), StatusCode> {
+	if password.chars().count() < MIN_PASSWORD_LENGTH {
+		return Err(StatusCode::UNPROCESSABLE_ENTITY);
+	}
+	return Ok(());
+}
+
+pub fn encode_token(claims: &Claims, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
+	return jsonwebtoken::encode(&Header::new(Algorithm::HS256), claims, &EncodingKey::from_secret(secret.as_bytes()));
+}
+
+// checks the signature and expiry
+pub fn decode_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+	let data = jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::new(Algorithm::HS256))?;
+	return Ok(data.claims);
+}
+
+pub fn issue_token(userid: uuid::Uuid, username: &str, tenant_id: i32, session_id: uuid::Uuid) -> Result<(String, Claims), jsonwebtoken::errors::Error> {
+	let now = chrono::Utc::now();
+	let claims = Claims {
+		sub: userid,
+		username: username.to_string(),
+		tid: tenant_id,
+		sid: session_id,
+		iat: now.timestamp(),
+		exp: (now + token_ttl()).timestamp()
+	};
+	let token = encode_token(&claims, &JWT_SECRET)?;
+	return Ok((token, claims));
+}
+
+fn login_response(userid: uuid::Uuid, username: &str, tenant_id: i32, session_id: uuid::Uuid, secret: &str) -> Result<LoginResponse, jsonwebtoken::errors::Error> {
+	let (token, claims) = issue_token(userid, username, tenant_id, session_id)?;
+	return Ok(LoginResponse {
+		token,
+		expires_at: chrono::DateTime::from_timestamp(claims.exp, 0).unwrap_or_default(),
+		refresh_token: format!("{}.{}", session_id, secret)
+	});
+}
+
+// 244 random bits. only the hash is stored
+pub fn new_secret() -> String {
+	return format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+}
+
+pub fn hash_secret(secret: &str) -> String {
+	return format!("{:x}", Sha256::digest(secret.as_bytes()));
+}
+
+pub fn parse_secret_token(token: &str) -> Option<(uuid::Uuid, &str)> {
+	let (session_id, secret) = token.split_once('.')?;
+	let session_id = uuid::Uuid::parse_str(session_id).ok()?;
+	if secret.is_empty() {
+		return None;
+	}
+	return Some((session_id, secret));
+}
+
+// returns the session id and the secret of its first refresh token
+async fn create_session(conn: &mut PgConnection, userid: uuid::Uuid) -> Result<(uuid::Uuid, String), sqlx::Error> {
+	let now = chrono::Utc::now();
+	let session_id = uuid::Uuid::new_v4();
+	let secret = new_secret();
+	sqlx::query(
+		r#"insert into sessions (id, userid, refresh_hash, created_at, last_used_at, expires_at)
+			values ($1, $2, $3, $4, $4, $5)"#)
+		.bind(session_id)
+		.bind(userid)
+		.bind(hash_secret(&secret))
+		.bind(now)
+		.bind(now + refresh_ttl())
+		.execute(conn)
+		.await?;
+	return Ok((session_id, secret));
+}
+
+// revokes every open session of the user except `keep`. returns the number of revoked sessions
+pub async fn revoke_sessions(conn: &mut PgConnection, userid: uuid::Uuid, keep: Option<uuid::Uuid>) -> Result<u64, sqlx::Error> {
+	let result = sqlx::query("update sessions set revoked_at=$2 where userid=$1 and revoked_at is null and id is distinct from $3")
+		.bind(userid)
+		.bind(chrono::Utc::now())
+		.bind(keep)
+		.execute(conn)
+		.await?;
+	return Ok(result.rows_affected());
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+	return headers
+		.get(header::AUTHORIZATION)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|h| h.strip_prefix("Bearer "))
+		.map(str::trim);
+}
+
+// checks the access token and that its session was not revoked
+pub async fn authenticate(pool: &PgPool, token: &str) -> Result<AuthUser, StatusCode> {
+	let claims = decode_token(token, &JWT_SECRET).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+	let session = sqlx::query("select id from sessions where id=$1 and revoked_at is null")
+		.bind(claims.sid)
+		.fetch_optional(pool)
+		.await;
+	if let Err(e) = session {
+		admin_logger(LogType::Error, &format!("Error reading session {}: {}", claims.sid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if session.unwrap().is_none() {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	logger::record_user(claims.sub);
+	return Ok(AuthUser {
+		userid: claims.sub,
+		username: claims.username,
+		session_id: claims.sid
+	});
+}
+
+#[async_trait]
+impl FromRequestParts<PgPool> for AuthUser {
+	type Rejection = StatusCode;
+
+	async fn from_request_parts(parts: &mut Parts, pool: &PgPool) -> Result<Self, Self::Rejection> {
+		let token = bearer_token(&parts.headers).ok_or(StatusCode::UNAUTHORIZED)?;
+		return authenticate(pool, token).await;
+	}
+}
+
+#[utoipa::path(post, path = "/auth/login", tag = "auth", security(()), request_body = LoginRequest, responses(
+	(status = 200, description = "Access and refresh token", body = LoginResponse),
+	(status = 401, description = "Wrong username or password"),
+	(status = 429, description = "Too many failed logins, try again later")
+))]
+pub async fn login(
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<LoginRequest>
+) -> Result<Json<LoginResponse>, StatusCode> {
+	let now = chrono::Utc::now();
+
+	// failures since the last successful login inside the lockout window
+	let failures: Result<(i64,), _> = sqlx::query_as(
+		r#"select count(*) from login_attempts where username=$1 and success=false and created_at>$2
+			and created_at>coalesce((select max(created_at) from login_attempts where username=$1 and success=true), '-infinity')"#)
+		.bind(&payload.username)
+		.bind(now - lockout_window())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = failures {
+		admin_logger(LogType::Error, &format!("Error counting login attempts of {}: {}", payload.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if failures.unwrap().0 >= max_failed_logins() {
+		admin_logger(LogType::Warning, &format!("Login of {} throttled after too many failed attempts", payload.username), None);
+		return Err(StatusCode::TOO_MANY_REQUESTS);
+	}
+
+	// the request has no token yet, the tenant is the one of the user logging in
+	let users: Result<Vec<(uuid::Uuid, String, i32)>, _> = tenant::across_tenants(sqlx::query_as(
+		r#"select u.userid, c.password_hash, u.tenant_id from users u join credentials c on u.userid=c.userid
+			join tenants t on t.id=u.tenant_id where u.username=$1 and u.active=true and ($2::varchar is null or t.name=$2)"#)
+		.bind(&payload.username)
+		.bind(&payload.tenant)
+		.fetch_all(&pool)).await;
+	if let Err(e) = users {
+		admin_logger(LogType::Error, &format!("Error reading credentials of {}: {}", payload.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let users = users.unwrap();
+	// usernames are unique per tenant, without the tenant the password could belong to either user
+	if users.len() > 1 {
+		admin_logger(LogType::Warning, &format!("Login of {} names no tenant and the username is taken in {} tenants", payload.username, users.len()), None);
+	}
+	// unknown users and users without a password fail the same way as a wrong password
+	let user = if users.len() == 1 { users.into_iter().next() } else { None }
+		.filter(|(_, hash, _)| verify_password(&payload.password, hash));
+
+	let query = sqlx::query("insert into login_attempts (username, success, created_at) values ($1, $2, $3)")
+		.bind(&payload.username)
+		.bind(user.is_some())
+		.bind(now)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error recording login attempt of {}: {}", payload.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if user.is_none() {
+		admin_logger(LogType::Warning, &format!("Failed login for {}", payload.username), None);
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+	let (userid, _, tenant_id) = user.unwrap();
+
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let session = create_session(&mut conn, userid).await;
+	if let Err(e) = session {
+		admin_logger(LogType::Error, &format!("Error creating session for {}: {}", payload.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (session_id, secret) = session.unwrap();
+
+	let response = login_response(userid, &payload.username, tenant_id, session_id, &secret);
+	if let Err(e) = response {
+		admin_logger(LogType::Error, &format!("Error issuing token for {}: {}", payload.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} ({}) logged in, session {}", payload.username, userid, session_id), None);
+	return Ok(Json(response.unwrap()));
+}
+
+// exchanges a refresh token for a new access token and a new refresh token
+#[utoipa::path(post, path = "/auth/refresh", tag = "auth", security(()), request_body = RefreshRequest, responses(
+	(status = 200, description = "New tokens. the sent refresh token cannot be used again", body = LoginResponse),
+	(status = 401, description = "Unknown, expired or reused refresh token")
+))]
+pub async fn refresh(
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<RefreshRequest>
+) -> Result<Json<LoginResponse>, StatusCode> {
+	let (session_id, secret) = parse_secret_token(&payload.refresh_token).ok_or(StatusCode::UNAUTHORIZED)?;
+	let now = chrono::Utc::now();
+	// the access token may have expired, the tenant is the one of the user of the session
+	let mut tx = tenant::across_tenants(pool.begin()).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+	let session: Result<Option<Session>, _> = sqlx::query_as(
+		r#"select s.id, s.userid, u.username, u.tenant_id, s.refresh_hash, s.previous_hash, s.expires_at, s.revoked_at
+			from sessions s join users u on s.userid=u.userid where s.id=$1 for update of s"#)
+		.bind(session_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = session {
+		admin_logger(LogType::Error, &format!("Error reading session {}: {}", session_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let session = session.unwrap().ok_or(StatusCode::UNAUTHORIZED)?;
+	if session.revoked_at.is_some() || session.expires_at < now {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let hash = hash_secret(secret);
+	if session.previous_hash.as_ref() == Some(&hash) {
+		// an already rotated token was used again. either the client or an attacker holds a copy, end the session for both
+		let query = sqlx::query("update sessions set revoked_at=$2 where id=$1")
+			.bind(session.id)
+			.bind(now)
+			.execute(&mut *tx)
+			.await;
+		if query.is_err() || tx.commit().await.is_err() {
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		admin_logger(LogType::Warning, &format!("Refresh token of session {} of {} was reused, session revoked", session.id, session.userid), None);
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+	if session.refresh_hash != hash {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let new_secret = new_secret();
+	let query = sqlx::query("update sessions set previous_hash=refresh_hash, refresh_hash=$2, last_used_at=$3, expires_at=$4 where id=$1")
+		.bind(session.id)
+		.bind(hash_secret(&new_secret))
+		.bind(now)
+		.bind(now + refresh_ttl())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error rotating refresh token of session {}: {}", session.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let response = login_response(session.userid, &session.username, session.tenant_id, session.id, &new_secret);
+	if let Err(e) = response {
+		admin_logger(LogType::Error, &format!("Error issuing token for {}: {}", session.username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting refresh of session {}: {}", session.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(response.unwrap()));
+}
+
+#[utoipa::path(post, path = "/auth/logout", tag = "auth", responses(
+	(status = 200, description = "The session of the token is revoked")
+))]
+pub async fn logout(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update sessions set revoked_at=$2 where id=$1 and revoked_at is null")
+		.bind(user.session_id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error revoking session {} of {}: {}", user.session_id, user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} ({}) logged out, session {}", user.username, user.userid, user.session_id), None);
+	return Ok(StatusCode::OK);
+}
+
+#[utoipa::path(post, path = "/auth/password", tag = "auth", request_body = ChangePassword, responses(
+	(status = 200, description = "Changed, every other session is revoked"),
+	(status = 403, description = "Wrong current password"),
+	(status = 422, description = "The new password is too weak")
+))]
+pub async fn change_password(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<ChangePassword>
+) -> Result<StatusCode, StatusCode> {
+	check_password_strength(&payload.new_password)?;
+
+	let current: Result<Option<(String,)>, _> = sqlx::query_as("select password_hash from credentials where userid=$1")
+		.bind(user.userid)
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = current {
+		admin_logger(LogType::Error, &format!("Error reading credentials of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	match current.unwrap() {
+		Some((hash,)) if verify_password(&payload.current_password, &hash) => {}
+		_ => {
+			admin_logger(LogType::Warning, &format!("Failed password change for {} ({})", user.username, user.userid), None);
+			return Err(StatusCode::FORBIDDEN);
+		}
+	}
+
+	let hash = hash_password(&payload.new_password).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let query = sqlx::query("update credentials set password_hash=$2, updated_at=$3 where userid=$1")
+		.bind(user.userid)
+		.bind(hash)
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error updating password of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	// sessions opened with the old password are ended, the one making the change stays
+	if let Err(e) = revoke_sessions(&mut tx, user.userid, Some(user.session_id)).await {
+		admin_logger(LogType::Error, &format!("Error revoking sessions of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting password change of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} ({}) changed their password", user.username, user.userid), None);
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod auth_tests {
+	use super::{decode_token, encode_token, hash_password, hash_secret, parse_secret_token, verify_password, Claims};
+
+	fn claims(exp_offset: i64) -> Claims {
+		let now = chrono::Utc::now().timestamp();
+		return Claims {
+			sub: uuid::Uuid::new_v4(),
+			username: "erp_admin".to_string(),
+			tid: 1,
+			sid: uuid::Uuid::new_v4(),
+			iat: now,
+			exp: now + exp_offset
+		};
+	}
+
+	#[test]
+	fn token_round_trip() {
+		let claims = claims(600);
+		let token = encode_token(&claims, "secret").unwrap();
+		assert_eq!(decode_token(&token, "secret").unwrap(), claims);
+
+		// signed with another secret
+		assert!(decode_token(&token, "other").is_err());
+	}
+
+	#[test]
+	fn expired_token_is_rejected() {
+		// past the default leeway of a minute
+		let token = encode_token(&claims(-120), "secret").unwrap();
+		assert!(decode_token(&token, "secret").is_err());
+	}
+
+	#[test]
+	fn passwords_are_hashed_with_argon2id() {
+		let hash = hash_password("correct horse").unwrap();
+		assert!(hash.starts_with("$argon2id$"));
+		assert!(verify_password("correct horse", &hash));
+		assert!(!verify_password("wrong horse", &hash));
+		assert!(!verify_password("correct horse", "not a hash"));
+	}
+
+	#[test]
+	fn refresh_tokens_are_parsed() {
+		let session_id = uuid::Uuid::new_v4();
+		let token = format!("{}.{}", session_id, "abc");
+		assert_eq!(parse_secret_token(&token), Some((session_id, "abc")));
+
+		assert!(parse_secret_token("not-a-uuid.abc").is_none());
+		assert!(parse_secret_token(&format!("{}.", session_id)).is_none());
+		assert!(parse_secret_token(&session_id.to_string()).is_none());
+
+		assert_eq!(hash_secret("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+	}
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::{http::StatusCode, Json};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use crate::rbac::{Authorized, ManageProcesses};
+use utoipa::ToSchema;
+
+// consecutive failures after which a callback target is not tried anymore
+fn failure_threshold() -> u32 {
+	return std::env::var("CALLBACK_BREAKER_THRESHOLD")
+		.ok()
+		.and_then(|s| s.parse::<u32>().ok())
+		.unwrap_or(5);
+}
+
+// how long an open breaker waits before it lets a single request through again
+fn cooldown() -> chrono::Duration {
+	let secs = std::env::var("CALLBACK_BREAKER_COOLDOWN_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(60);
+	return chrono::Duration::seconds(secs);
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {Closed, Open, HalfOpen}
+
+#[derive(Default, Debug)]
+struct Breaker {
+	failures: u32,
+	opened_at: Option<chrono::DateTime<chrono::Utc>>,
+	// a half open breaker only lets one request through
+	probing: bool
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BreakerStatus {
+	pub target: String,
+	pub state: BreakerState,
+	pub consecutive_failures: u32,
+	pub opened_at: Option<chrono::DateTime<chrono::Utc>>,
+	// when an open breaker half opens
+	pub retry_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+// breakers of every target that failed at least once since the server started
+#[derive(Default)]
+pub struct Breakers {
+	targets: HashMap<String, Breaker>
+}
+
+static BREAKERS: Lazy<Mutex<Breakers>> = Lazy::new(|| {
+	return Mutex::new(Breakers::default());
+});
+
+impl Breaker {
+	fn state(&self, now: chrono::DateTime<chrono::Utc>, cooldown: chrono::Duration) -> BreakerState {
+		return match self.opened_at {
+			None => BreakerState::Closed,
+			Some(opened_at) if now - opened_at >= cooldown => BreakerState::HalfOpen,
+			Some(_) => BreakerState::Open
+		};
+	}
+}
+
+impl Breakers {
+	// whether a request to the target may be made now. every allowed request has to be followed by record
+	pub fn allow(&mut self, target: &str, now: chrono::DateTime<chrono::Utc>, cooldown: chrono::Duration) -> bool {
+		let breaker = match self.targets.get_mut(target) {
+			Some(breaker) => breaker,
+			None => return true
+		};
+		return match breaker.state(now, cooldown) {
+			BreakerState::Closed => true,
+			BreakerState::Open => false,
+			BreakerState::HalfOpen if breaker.probing => false,
+			BreakerState::HalfOpen => {
+				breaker.probing = true;
+				true
+			}
+		};
+	}
+
+	pub fn record(&mut self, target: &str, success: bool, now: chrono::DateTime<chrono::Utc>, threshold: u32) {
+		if success {
+			self.targets.remove(target);
+			return;
+		}
+		let breaker = self.targets.entry(target.to_string()).or_default();
+		breaker.failures += 1;
+		// a failed probe opens the breaker for another cool-down
+		if breaker.failures >= threshold || breaker.probing {
+			breaker.opened_at = Some(now);
+		}
+		breaker.probing = false;
+	}
+
+	pub fn statuses(&self, now: chrono::DateTime<chrono::Utc>, cooldown: chrono::Duration) -> Vec<BreakerStatus> {
+		let mut statuses: Vec<BreakerStatus> = self.targets.iter().map(|(target, breaker)| BreakerStatus {
+			target: target.clone(),
+			state: breaker.state(now, cooldown),
+			consecutive_failures: breaker.failures,
+			opened_at: breaker.opened_at,
+			retry_at: breaker.opened_at.map(|t| t + cooldown)
+		}).collect();
+		statuses.sort_by(|a, b| a.target.cmp(&b.target));
+		return statuses;
+	}
+}
+
+pub fn allow(target: &str) -> bool {
+	return BREAKERS.lock().unwrap().allow(target, chrono::Utc::now(), cooldown());
+}
+
+pub fn record(target: &str, success: bool) {
+	BREAKERS.lock().unwrap().record(target, success, chrono::Utc::now(), failure_threshold());
+}
+
+// targets without failures are closed and not listed
+#[utoipa::path(get, path = "/admin/callbacks/breakers", tag = "callbacks", responses(
+	(status = 200, description = "Circuit breakers of the callback targets", body = Vec<BreakerStatus>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_breakers(
+	_auth: Authorized<ManageProcesses>
+) -> Result<Json<Vec<BreakerStatus>>, StatusCode> {
+	let statuses = BREAKERS.lock().unwrap().statuses(chrono::Utc::now(), cooldown());
+	return Ok(Json(statuses));
+}
+
+#[cfg(test)]
+mod breaker_tests {
+	use super::{BreakerState, Breakers};
+
+	#[test]
+	fn breaker_opens_and_half_opens() {
+		let cooldown = chrono::Duration::seconds(60);
+		let now = chrono::Utc::now();
+		let mut breakers = Breakers::default();
+
+		breakers.record("callbacks", false, now, 3);
+		breakers.record("callbacks", false, now, 3);
+		assert!(breakers.allow("callbacks", now, cooldown));
+		breakers.record("callbacks", false, now, 3);
+		assert!(!breakers.allow("callbacks", now, cooldown));
+		assert!(breakers.allow("other", now, cooldown));
+		assert_eq!(breakers.statuses(now, cooldown)[0].state, BreakerState::Open);
+
+		// one probe after the cool-down
+		let later = now + cooldown;
+		assert_eq!(breakers.statuses(later, cooldown)[0].state, BreakerState::HalfOpen);
+		assert!(breakers.allow("callbacks", later, cooldown));
+		assert!(!breakers.allow("callbacks", later, cooldown));
+
+		// a failed probe opens it again
+		breakers.record("callbacks", false, later, 3);
+		assert!(!breakers.allow("callbacks", later, cooldown));
+
+		let latest = later + cooldown;
+		assert!(breakers.allow("callbacks", latest, cooldown));
+		breakers.record("callbacks", true, latest, 3);
+		assert!(breakers.allow("callbacks", latest, cooldown));
+		assert!(breakers.statuses(latest, cooldown).is_empty());
+	}
+}
@@ -0,0 +1,248 @@
+use axum::{extract, http::{header, StatusCode}, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use crate::auth::{hash_secret, new_secret, AuthUser};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac;
+use crate::tenant;
+use utoipa::{IntoParams, ToSchema};
+
+// content lines longer than this are folded, see rfc 5545 3.1
+const MAX_LINE_OCTETS: usize = 75;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CalendarQuery {
+	// calendar apps cannot send an access token, the feed url carries its own secret
+	pub token: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CalendarFeed {
+	// shown once, a new token replaces the url of the old one
+	pub token: String,
+	pub path: String
+}
+
+// a due date of an own ticket or of a ticket waiting for the approval of the user
+#[derive(FromRow, Debug)]
+pub struct Deadline {
+	pub kind: String,
+	// row id in tickets for due dates, in user_active_tickets for approvals
+	pub id: i32,
+	pub ticket_id: i32,
+	pub process_id: String,
+	pub due_at: chrono::DateTime<chrono::Utc>
+}
+
+fn ics_time(time: &chrono::DateTime<chrono::Utc>) -> String {
+	return time.format("%Y%m%dT%H%M%SZ").to_string();
+}
+
+// text values escape backslashes, separators and newlines
+pub fn escape_text(text: &str) -> String {
+	return text
+		.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace('\n', "\\n");
+}
+
+// long lines continue on the next line after a space, never splitting a character
+pub fn fold_line(line: &str) -> String {
+	let mut folded = String::with_capacity(line.len() + 8);
+	let mut octets = 0;
+	for c in line.chars() {
+		if octets + c.len_utf8() > MAX_LINE_OCTETS {
+			folded.push_str("\r\n ");
+			// the space counts towards the new line
+			octets = 1;
+		}
+		folded.push(c);
+		octets += c.len_utf8();
+	}
+	folded.push_str("\r\n");
+	return folded;
+}
+
+pub fn render_calendar(deadlines: &[Deadline], now: chrono::DateTime<chrono::Utc>) -> String {
+	let mut lines = vec![
+		"BEGIN:VCALENDAR".to_string(),
+		"VERSION:2.0".to_string(),
+		"PRODID:-//erp_system//deadlines//EN".to_string(),
+		"CALSCALE:GREGORIAN".to_string(),
+		"METHOD:PUBLISH".to_string(),
+		"X-WR-CALNAME:ERP deadlines".to_string()
+	];
+	for deadline in deadlines {
+		let summary = match deadline.kind.as_str() {
+			"approval" => format!("Approve ticket {} ({})", deadline.ticket_id, deadline.process_id),
+			_ => format!("Ticket {} ({}) is due", deadline.ticket_id, deadline.process_id)
+		};
+		lines.extend([
+			"BEGIN:VEVENT".to_string(),
+			// stable across fetches so calendar apps update the event instead of adding another
+			format!("UID:{}-{}@erp_system", deadline.kind, deadline.id),
+			format!("DTSTAMP:{}", ics_time(&now)),
+			format!("DTSTART:{}", ics_time(&deadline.due_at)),
+			format!("DTEND:{}", ics_time(&deadline.due_at)),
+			format!("SUMMARY:{}", escape_text(&summary)),
+			"TRANSP:TRANSPARENT".to_string(),
+			"END:VEVENT".to_string()
+		]);
+	}
+	lines.push("END:VCALENDAR".to_string());
+	return lines.iter().map(|l| fold_line(l)).collect();
+}
+
+async fn can_manage_feed(pool: &PgPool, user: &AuthUser, userid: uuid::Uuid) -> Result<(), StatusCode> {
+	if user.userid == userid {
+		return Ok(());
+	}
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	match rbac::has_permission(&mut conn, user.userid, "manage_users").await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Ok(false) => return Err(StatusCode::FORBIDDEN),
+		Ok(true) => return Ok(())
+	}
+}
+
+// turns the feed on, or moves it to a new url when it was on
+#[utoipa::path(post, path = "/users/{id}/calendar_token", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 201, description = "The feed url, shown once. replaces the url of the previous token", body = CalendarFeed),
+	(status = 403, description = "Only for the own calendar or with manage_users"),
+	(status = 404, description = "Unknown user")
+))]
+pub async fn create_calendar_token(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<(StatusCode, Json<CalendarFeed>), StatusCode> {
+	can_manage_feed(&pool, &user, userid).await?;
+
+	let token = new_secret();
+	let query = sqlx::query("update users set calendar_token_hash=$2 where userid=$1")
+		.bind(userid)
+		.bind(hash_secret(&token))
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error saving the calendar token of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} created a calendar feed for {}", user.userid, userid), None);
+	let path = format!("/users/{}/calendar.ics?token={}", userid, token);
+	return Ok((StatusCode::CREATED, Json(CalendarFeed { token, path })));
+}
+
+#[utoipa::path(delete, path = "/users/{id}/calendar_token", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 204, description = "The feed url no longer works"),
+	(status = 403, description = "Only for the own calendar or with manage_users"),
+	(status = 404, description = "The user has no feed")
+))]
+pub async fn delete_calendar_token(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<StatusCode, StatusCode> {
+	can_manage_feed(&pool, &user, userid).await?;
+
+	let query = sqlx::query("update users set calendar_token_hash=null where userid=$1")
+		.bind(userid)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing the calendar token of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// due dates of open own tickets and of the tickets waiting for an approval of the user
+#[utoipa::path(get, path = "/users/{id}/calendar.ics", tag = "users", security(()), params(("id" = uuid::Uuid, Path, description = "User id"), CalendarQuery), responses(
+	(status = 200, description = "Due dates and pending approvals of the user as an icalendar feed", body = String, content_type = "text/calendar"),
+	(status = 401, description = "Missing or wrong feed token"),
+	(status = 404, description = "Unknown user")
+))]
+pub async fn get_calendar(
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>,
+	extract::Query(query): extract::Query<CalendarQuery>
+) -> Result<impl IntoResponse, StatusCode> {
+	let token = query.token.filter(|t| !t.is_empty()).ok_or(StatusCode::UNAUTHORIZED)?;
+
+	// the request has no access token, so it is scoped to the tenant of the user the feed belongs to
+	let tenant: Result<Option<(i32,)>, _> = tenant::across_tenants(sqlx::query_as("select tenant_id from users where userid=$1 and calendar_token_hash=$2 and active=true")
+		.bind(userid)
+		.bind(hash_secret(&token))
+		.fetch_optional(&pool)).await;
+	if let Err(e) = tenant {
+		admin_logger(LogType::Error, &format!("Error checking the calendar token of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (tenant,) = tenant.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	let deadlines: Result<Vec<Deadline>, _> = tenant::scoped(Some(tenant), sqlx::query_as(
+		r#"select 'due' as kind, id, id as ticket_id, process_id, due_at from tickets
+			where owner_id=$1 and status in ('open', 'draft') and due_at is not null
+		union all
+		select 'approval' as kind, a.id, t.id as ticket_id, t.process_id, t.due_at from user_active_tickets a
+			join tickets t on t.id=a.ticketid
+			where a.userid=$1 and a.type_='approve' and a.active=true and t.status='open' and t.due_at is not null
+		order by due_at"#)
+		.bind(userid)
+		.fetch_all(&pool)).await;
+	if let Err(e) = deadlines {
+		admin_logger(LogType::Error, &format!("Error reading the deadlines of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let calendar = render_calendar(&deadlines.unwrap(), chrono::Utc::now());
+	return Ok(([(header::CONTENT_TYPE, "text/calendar; charset=utf-8")], calendar));
+}
+
+#[cfg(test)]
+mod calendar_tests {
+	use super::{escape_text, fold_line, render_calendar, Deadline};
+
+	#[test]
+	fn text_is_escaped_and_folded() {
+		assert_eq!(escape_text("a,b;c\\d\ne"), "a\\,b\\;c\\\\d\\ne");
+
+		let folded = fold_line(&format!("SUMMARY:{}", "é".repeat(50)));
+		let lines: Vec<&str> = folded.trim_end_matches("\r\n").split("\r\n").collect();
+		assert_eq!(lines.len(), 2);
+		assert!(lines.iter().all(|l| l.len() <= 75));
+		assert!(lines[1].starts_with(' '));
+		assert_eq!(lines.concat().replacen(' ', "", 1), format!("SUMMARY:{}", "é".repeat(50)));
+	}
+
+	#[test]
+	fn deadlines_become_events() {
+		let due_at = "2024-06-03T09:30:00Z".parse().unwrap();
+		let now = "2024-06-01T08:00:00Z".parse().unwrap();
+		let deadlines = vec![
+			Deadline { kind: "due".to_string(), id: 7, ticket_id: 7, process_id: "leave".to_string(), due_at },
+			Deadline { kind: "approval".to_string(), id: 40, ticket_id: 9, process_id: "travel, abroad".to_string(), due_at }
+		];
+		let calendar = render_calendar(&deadlines, now);
+
+		assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+		assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+		assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+		assert!(calendar.contains("UID:due-7@erp_system\r\nDTSTAMP:20240601T080000Z\r\nDTSTART:20240603T093000Z\r\n"));
+		assert!(calendar.contains("SUMMARY:Ticket 7 (leave) is due\r\n"));
+		assert!(calendar.contains("UID:approval-40@erp_system\r\n"));
+		assert!(calendar.contains("SUMMARY:Approve ticket 9 (travel\\, abroad)\r\n"));
+	}
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as DbJson, FromRow, PgConnection, PgPool};
+use crate::callbacks::Callback;
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageProcesses};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Serialize, FromRow, Clone, Debug, ToSchema)]
+pub struct CallbackEndpoint {
+	pub id: i32,
+	pub process_id: String,
+	pub node: Option<i32>,
+	// name of the webhook callback in the process file
+	pub name: String,
+	pub url: String,
+	#[schema(value_type = HashMap<String, String>)]
+	pub headers: DbJson<HashMap<String, String>>,
+	pub timeout_ms: Option<i32>,
+	pub updated_by: Option<uuid::Uuid>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveEndpoint {
+	pub process_id: String,
+	pub node: Option<i32>,
+	pub name: String,
+	pub url: String,
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+	pub timeout_ms: Option<i32>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EndpointsQuery {
+	pub process_id: Option<String>
+}
+
+pub fn check_endpoint(payload: &SaveEndpoint) -> Result<(), String> {
+	if payload.name.trim().is_empty() {
+		return Err("A callback name is required".to_string());
+	}
+	if !payload.url.starts_with("https://") && !payload.url.starts_with("http://") {
+		return Err(format!("Invalid url: {}", payload.url));
+	}
+	if payload.timeout_ms.map(|t| t <= 0).unwrap_or(false) {
+		return Err("The timeout has to be positive".to_string());
+	}
+	return Ok(());
+}
+
+// where the callback service reaches the /service routes of this server
+fn service_url() -> String {
+	return std::env::var("SERVICE_URL").unwrap_or_else(|_| {
+		format!("http://127.0.0.1:{}", std::env::var("PORT").unwrap_or_else(|_| "3000".to_string()))
+	});
+}
+
+// process files name the webhooks served by this server by their path, e.g. /service/assets/fulfil
+fn absolute_url(service_url: &str, url: &str) -> String {
+	if !url.starts_with('/') {
+		return url.to_string();
+	}
+	return format!("{}{}", service_url.trim_end_matches('/'), url);
+}
+
+// replaces the target of the webhooks that have an endpoint. a node endpoint beats a process endpoint.
+// configured headers are added to the ones of the process file and win on conflicts. paths left without
+// an endpoint are sent to `service_url`
+pub fn apply_endpoints(callbacks: &[Callback], node: i32, endpoints: &[CallbackEndpoint], service_url: &str) -> Vec<Callback> {
+	return callbacks.iter().map(|callback| {
+		let Callback::Webhook { name, url, headers, timeout_ms } = callback else {
+			return callback.clone();
+		};
+		let endpoint = endpoints.iter()
+			.filter(|e| e.name == *name && (e.node == Some(node) || e.node.is_none()))
+			.min_by_key(|e| e.node.is_none());
+		match endpoint {
+			Some(endpoint) => {
+				let mut headers = headers.clone();
+				headers.extend(endpoint.headers.0.clone());
+				Callback::Webhook {
+					name: name.clone(),
+					url: endpoint.url.clone(),
+					headers,
+					timeout_ms: endpoint.timeout_ms.map(|t| t as u64)
+				}
+			}
+			None => Callback::Webhook {
+				name: name.clone(),
+				url: absolute_url(service_url, url),
+				headers: headers.clone(),
+				timeout_ms: *timeout_ms
+			}
+		}
+	}).collect();
+}
+
+// called right before a job is sent so changed endpoints apply to jobs that are already queued
+pub async fn resolve_endpoints(conn: &mut PgConnection, process_id: &str, node: i32, callbacks: &[Callback]) -> Result<Vec<Callback>, sqlx::Error> {
+	let endpoints: Vec<CallbackEndpoint> = sqlx::query_as(
+		r#"select id, process_id, node, name, url, headers, timeout_ms, updated_by, updated_at from callback_endpoints
+			where process_id=$1 and (node=$2 or node is null)"#)
+		.bind(process_id)
+		.bind(node)
+		.fetch_all(conn)
+		.await?;
+	return Ok(apply_endpoints(callbacks, node, &endpoints, &service_url()));
+}
+
+#[utoipa::path(put, path = "/admin/callback_endpoints", tag = "callbacks", request_body = SaveEndpoint, responses(
+	(status = 200, description = "Saved, the webhook callback of the process is sent here instead", body = CallbackEndpoint),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown process", body = String),
+	(status = 422, description = "Invalid url, node or timeout", body = String)
+))]
+pub async fn save_endpoint(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SaveEndpoint>
+) -> Result<Json<CallbackEndpoint>, (StatusCode, String)> {
+	check_endpoint(&payload).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+	let query: Result<CallbackEndpoint, _> = sqlx::query_as(
+		r#"insert into callback_endpoints (process_id, node, name, url, headers, timeout_ms, updated_by, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8)
+			on conflict (tenant_id, process_id, coalesce(node, -1), name) do update set url=$4, headers=$5, timeout_ms=$6, updated_by=$7, updated_at=$8
+			returning id, process_id, node, name, url, headers, timeout_ms, updated_by, updated_at"#)
+		.bind(&payload.process_id)
+		.bind(payload.node)
+		.bind(payload.name.trim())
+		.bind(&payload.url)
+		.bind(DbJson(&payload.headers))
+		.bind(payload.timeout_ms)
+		.bind(auth.user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::NOT_FOUND, format!("Unknown process: {}", payload.process_id)));
+		}
+		admin_logger(LogType::Error, &format!("Error saving callback endpoint {} of {}: {}", payload.name, payload.process_id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	// the url is logged, the headers may hold credentials
+	admin_logger(LogType::Info, &format!("User {} pointed callback {} of {} node {:?} to {}", auth.user.userid, payload.name, payload.process_id, payload.node, payload.url), None);
+	return Ok(Json(query.unwrap()));
+}
+
+#[utoipa::path(get, path = "/admin/callback_endpoints", tag = "callbacks", params(EndpointsQuery), responses(
+	(status = 200, description = "Endpoints overriding webhook callbacks", body = Vec<CallbackEndpoint>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_endpoints(
+	_auth: Authorized<ManageProcesses>,
+	extract::Query(query): extract::Query<EndpointsQuery>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<CallbackEndpoint>>, StatusCode> {
+	let query: Result<Vec<CallbackEndpoint>, _> = sqlx::query_as(
+		r#"select id, process_id, node, name, url, headers, timeout_ms, updated_by, updated_at from callback_endpoints
+			where $1::varchar is null or process_id=$1 order by process_id, node nulls first, name"#)
+		.bind(query.process_id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading callback endpoints: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// the webhook falls back to the target in the process file
+#[utoipa::path(delete, path = "/admin/callback_endpoints/{id}", tag = "callbacks", params(("id" = i32, Path, description = "Endpoint id")), responses(
+	(status = 200, description = "Deleted, the url of the process file is used again"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown endpoint")
+))]
+pub async fn delete_endpoint(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from callback_endpoints where id=$1")
+		.bind(id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting callback endpoint {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted callback endpoint {}", auth.user.userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod callback_endpoints_tests {
+	use std::collections::HashMap;
+	use sqlx::types::Json;
+	use super::{apply_endpoints, check_endpoint, CallbackEndpoint, SaveEndpoint};
+	use crate::callbacks::Callback;
+
+	fn endpoint(node: Option<i32>, name: &str, url: &str) -> CallbackEndpoint {
+		return CallbackEndpoint {
+			id: 1,
+			process_id: "purchase".to_string(),
+			node,
+			name: name.to_string(),
+			url: url.to_string(),
+			headers: Json(HashMap::from([("Authorization".to_string(), "Bearer new".to_string())])),
+			timeout_ms: Some(2000),
+			updated_by: None,
+			updated_at: chrono::Utc::now()
+		};
+	}
+
+	#[test]
+	fn endpoints_replace_webhook_targets() {
+		let callbacks = vec![
+			Callback::Webhook {
+				name: "erp".to_string(),
+				url: "http://old".to_string(),
+				headers: HashMap::from([
+					("Authorization".to_string(), "Bearer old".to_string()),
+					("X-Source".to_string(), "erp".to_string())
+				]),
+				timeout_ms: None
+			},
+			Callback::Script { name: "erp".to_string(), path: "erp.py".to_string() }
+		];
+		let endpoints = vec![endpoint(None, "erp", "http://process"), endpoint(Some(3), "erp", "http://node"), endpoint(None, "other", "http://other")];
+
+		let resolved = apply_endpoints(&callbacks, 3, &endpoints, "http://erp");
+		let Callback::Webhook { url, headers, timeout_ms, .. } = &resolved[0] else { panic!("webhook expected") };
+		assert_eq!(url, "http://node");
+		assert_eq!(headers["Authorization"], "Bearer new");
+		assert_eq!(headers["X-Source"], "erp");
+		assert_eq!(*timeout_ms, Some(2000));
+		// scripts are not configured here
+		assert!(matches!(&resolved[1], Callback::Script { path, .. } if path == "erp.py"));
+
+		let Callback::Webhook { url, .. } = &apply_endpoints(&callbacks, 1, &endpoints, "http://erp")[0] else { panic!("webhook expected") };
+		assert_eq!(url, "http://process");
+		let Callback::Webhook { url, .. } = &apply_endpoints(&callbacks, 1, &[], "http://erp")[0] else { panic!("webhook expected") };
+		assert_eq!(url, "http://old");
+	}
+
+	#[test]
+	fn paths_go_to_this_server() {
+		let callbacks = vec![Callback::Webhook {
+			name: "assign_asset".to_string(),
+			url: "/service/assets/fulfil".to_string(),
+			headers: HashMap::new(),
+			timeout_ms: None
+		}];
+		let Callback::Webhook { url, .. } = &apply_endpoints(&callbacks, 1, &[], "https://erp.example.com/")[0] else { panic!("webhook expected") };
+		assert_eq!(url, "https://erp.example.com/service/assets/fulfil");
+		// an endpoint still moves the webhook elsewhere
+		let endpoints = vec![endpoint(None, "assign_asset", "http://assets")];
+		let Callback::Webhook { url, .. } = &apply_endpoints(&callbacks, 1, &endpoints, "https://erp.example.com")[0] else { panic!("webhook expected") };
+		assert_eq!(url, "http://assets");
+	}
+
+	#[test]
+	fn endpoints_are_validated() {
+		let mut payload = SaveEndpoint {
+			process_id: "purchase".to_string(),
+			node: None,
+			name: "erp".to_string(),
+			url: "https://erp.example.com/hook".to_string(),
+			headers: HashMap::new(),
+			timeout_ms: Some(500)
+		};
+		assert!(check_endpoint(&payload).is_ok());
+		payload.url = "ftp://erp".to_string();
+		assert!(check_endpoint(&payload).is_err());
+		payload.url = "https://erp".to_string();
+		payload.timeout_ms = Some(0);
+		assert!(check_endpoint(&payload).is_err());
+	}
+}
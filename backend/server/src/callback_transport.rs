@@ -0,0 +1,213 @@
+use std::net::SocketAddr;
+use async_nats::jetstream;
+use axum::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::OnceCell;
+use crate::callbacks::{Callback, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+pub use erp_api_types::callbacks::{CallbackTask, PayloadSignature};
+use crate::logger::REQUEST_ID_HEADER;
+
+const DEFAULT_NATS_SUBJECT: &str = "erp.callbacks.tasks";
+const DEFAULT_NATS_STREAM: &str = "ERP_CALLBACKS";
+
+
+// a callback job ready to be handed to the workers. the payload is sent exactly as it was signed
+pub struct TaskMessage {
+	pub job_id: i64,
+	pub payload: String,
+	pub callbacks: Vec<Callback>,
+	pub signature: PayloadSignature,
+	// of the request that queued the job. sent on as X-Request-Id
+	pub request_id: Option<String>
+}
+
+// how tasks reach the callback workers. set with CALLBACK_TRANSPORT, tcp by default
+#[async_trait]
+pub trait CallbackTransport: Send + Sync {
+	// where the tasks go. failures are counted per target by the circuit breaker
+	fn target(&self) -> String;
+	// returns once the other side has stored the task
+	async fn send(&self, task: &TaskMessage) -> Result<(), String>;
+}
+
+// a single callback server listening on CALLBACK_SERVER_PORT
+pub struct TcpTransport {
+	pub addr: SocketAddr
+}
+
+// a jetstream work queue shared by any number of callback servers
+pub struct NatsTransport {
+	jetstream: jetstream::Context,
+	subject: String
+}
+
+static TRANSPORT: OnceCell<Box<dyn CallbackTransport>> = OnceCell::const_new();
+
+pub fn tcp_message(task: &TaskMessage) -> Vec<u8> {
+	let header_bytes = 1u64.to_le_bytes();
+	let serialized_callbacks = serde_json::to_string(&task.callbacks).unwrap();
+	let signature = serde_json::to_string(&task.signature).unwrap();
+	let request_id = task.request_id.as_deref().unwrap_or_default();
+
+	let mut message = Vec::with_capacity(40 + task.payload.len() + serialized_callbacks.len() + signature.len() + request_id.len());
+	message.extend_from_slice(&header_bytes);
+	// data for the callbacks
+	message.extend_from_slice(&(task.payload.len() as u64).to_le_bytes());
+	message.extend_from_slice(task.payload.as_bytes());
+	// callbacks
+	message.extend_from_slice(&(serialized_callbacks.len() as u64).to_le_bytes());
+	message.extend_from_slice(serialized_callbacks.as_bytes());
+	// signature of the task payload
+	message.extend_from_slice(&(signature.len() as u64).to_le_bytes());
+	message.extend_from_slice(signature.as_bytes());
+	// request id, empty when the job was not queued by a request
+	message.extend_from_slice(&(request_id.len() as u64).to_le_bytes());
+	message.extend_from_slice(request_id.as_bytes());
+	return message;
+}
+
+pub fn queued_task(task: &TaskMessage) -> Vec<u8> {
+	return serde_json::to_vec(&CallbackTask {
+		data: task.payload.clone(),
+		callbacks: task.callbacks.clone(),
+		signature: Some(task.signature.clone()),
+		request_id: task.request_id.clone()
+	}).unwrap();
+}
+
+#[async_trait]
+impl CallbackTransport for TcpTransport {
+	fn target(&self) -> String {
+		return format!("tcp://{}", self.addr);
+	}
+
+	// the callback server answers with one byte once the task is queued
+	async fn send(&self, task: &TaskMessage) -> Result<(), String> {
+		let mut conn = TcpStream::connect(self.addr).await
+			.map_err(|e| format!("Failed to connect to callback server. e: {}", e))?;
+		conn.write_all(&tcp_message(task)).await
+			.map_err(|e| format!("Failed to send task to callback server. e: {}", e))?;
+
+		let mut ack = [0u8; 1];
+		conn.read_exact(&mut ack).await
+			.map_err(|e| format!("Callback server closed the connection. e: {}", e))?;
+		return Ok(());
+	}
+}
+
+impl NatsTransport {
+	pub async fn connect(url: &str) -> Result<NatsTransport, String> {
+		let subject = std::env::var("CALLBACK_NATS_SUBJECT").unwrap_or(DEFAULT_NATS_SUBJECT.to_string());
+		let stream = std::env::var("CALLBACK_NATS_STREAM").unwrap_or(DEFAULT_NATS_STREAM.to_string());
+
+		let client = async_nats::connect(url).await
+			.map_err(|e| format!("Failed to connect to nats at {}. e: {}", url, e))?;
+		let jetstream = jetstream::new(client);
+		// every task is consumed by exactly one worker and removed once acknowledged
+		jetstream.get_or_create_stream(jetstream::stream::Config {
+			name: stream.clone(),
+			subjects: vec![subject.clone()],
+			retention: jetstream::stream::RetentionPolicy::WorkQueue,
+			..Default::default()
+		}).await
+			.map_err(|e| format!("Failed to create nats stream {}. e: {}", stream, e))?;
+
+		return Ok(NatsTransport { jetstream, subject });
+	}
+}
+
+#[async_trait]
+impl CallbackTransport for NatsTransport {
+	fn target(&self) -> String {
+		return format!("nats://{}", self.subject);
+	}
+
+	// waits for the stream to store the task
+	async fn send(&self, task: &TaskMessage) -> Result<(), String> {
+		let mut headers = async_nats::HeaderMap::new();
+		// a job that is sent again after a lost acknowledgement is dropped by the stream
+		headers.insert("Nats-Msg-Id", task.job_id.to_string().as_str());
+		headers.insert(TIMESTAMP_HEADER, task.signature.timestamp.to_string().as_str());
+		headers.insert(SIGNATURE_HEADER, task.signature.signature.as_str());
+		if let Some(request_id) = &task.request_id {
+			headers.insert(REQUEST_ID_HEADER, request_id.as_str());
+		}
+
+		let ack = self.jetstream.publish_with_headers(self.subject.clone(), headers, queued_task(task).into()).await
+			.map_err(|e| format!("Failed to publish task to nats. e: {}", e))?;
+		ack.await
+			.map_err(|e| format!("Nats did not store the task. e: {}", e))?;
+		return Ok(());
+	}
+}
+
+async fn connect_transport() -> Result<Box<dyn CallbackTransport>, String> {
+	let kind = std::env::var("CALLBACK_TRANSPORT").unwrap_or("tcp".to_string());
+	match kind.as_str() {
+		"tcp" => {
+			let callback_port = std::env::var("CALLBACK_SERVER_PORT")
+				.map_err(|_| "CALLBACK_SERVER_PORT not defined".to_string())?
+				.parse::<u16>()
+				.map_err(|e| format!("Invalid CALLBACK_SERVER_PORT. e: {}", e))?;
+			return Ok(Box::new(TcpTransport { addr: SocketAddr::from(([127, 0, 0, 1], callback_port)) }));
+		}
+		"nats" => {
+			let url = std::env::var("CALLBACK_NATS_URL").map_err(|_| "CALLBACK_NATS_URL not defined".to_string())?;
+			return Ok(Box::new(NatsTransport::connect(&url).await?));
+		}
+		_ => return Err(format!("Unknown CALLBACK_TRANSPORT: {}", kind))
+	}
+}
+
+// connected on first use. a failed connection is tried again on the next send
+pub async fn transport() -> Result<&'static dyn CallbackTransport, String> {
+	let transport = TRANSPORT.get_or_try_init(connect_transport).await?;
+	return Ok(transport.as_ref());
+}
+
+#[cfg(test)]
+mod callback_transport_tests {
+	use super::{queued_task, tcp_message, PayloadSignature, TaskMessage};
+	use crate::callbacks::Callback;
+
+	fn task() -> TaskMessage {
+		return TaskMessage {
+			job_id: 4,
+			payload: r#"{"job_id":4,"ticket_id":3}"#.to_string(),
+			callbacks: vec![Callback::Script { name: "erp".to_string(), path: "erp.py".to_string() }],
+			signature: PayloadSignature { timestamp: 1716280000, signature: "v1=00".to_string() },
+			request_id: Some("req-1".to_string())
+		};
+	}
+
+	#[test]
+	fn tcp_messages_are_framed() {
+		let task = task();
+		let message = tcp_message(&task);
+		assert_eq!(&message[0..8], &1u64.to_le_bytes());
+		assert_eq!(&message[8..16], &(task.payload.len() as u64).to_le_bytes());
+		assert_eq!(&message[16..16 + task.payload.len()], task.payload.as_bytes());
+
+		let signature = br#"{"timestamp":1716280000,"signature":"v1=00"}"#;
+		let mut tail = (signature.len() as u64).to_le_bytes().to_vec();
+		tail.extend_from_slice(signature);
+		tail.extend_from_slice(&5u64.to_le_bytes());
+		tail.extend_from_slice(b"req-1");
+		assert!(message.ends_with(&tail));
+
+		let mut task = task;
+		task.request_id = None;
+		assert!(tcp_message(&task).ends_with(&0u64.to_le_bytes()));
+	}
+
+	#[test]
+	fn queued_tasks_keep_the_signed_payload() {
+		let queued: serde_json::Value = serde_json::from_slice(&queued_task(&task())).unwrap();
+		// a string, not an object, so the bytes the signature covers survive
+		assert_eq!(queued["data"], r#"{"job_id":4,"ticket_id":3}"#);
+		assert_eq!(queued["callbacks"][0]["type"], "script");
+		assert_eq!(queued["signature"]["timestamp"], 1716280000);
+		assert_eq!(queued["request_id"], "req-1");
+	}
+}
@@ -1,73 +1,707 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::time::Duration;
+use axum::{body::Bytes, extract, http::{HeaderMap, StatusCode}};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use tokio::net::TcpStream;
-use once_cell::sync::Lazy;
-use tokio::io::AsyncWriteExt;
-use crate::{logger::{admin_logger, LogType}, ticket::ExecuteErr, utils::make_task_payload};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, FromRow, PgConnection, PgPool};
+use crate::{api_keys::{self, ApiKey}, auth::new_secret, breaker, callback_endpoints, callback_transport::{self, CallbackTransport, PayloadSignature, TaskMessage}, logger::{self, admin_logger, LogType}, outbox::{self, backoff}, rbac::{Authorized, ManageProcesses, Permission}, shutdown, tenant, utils::make_task_payload};
+use crate::ticket::{self, UpdateErr, UpdateSource, UpdateTicket};
+use utoipa::ToSchema;
 
 
 
+// connecting, sending and waiting for the acknowledgement of a task together may not take longer
+fn request_timeout() -> Duration {
+	let millis = std::env::var("CALLBACK_TIMEOUT_MS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5000);
+	return Duration::from_millis(millis);
+}
 
-static CALLBACK_ADDR : Lazy<SocketAddr> = Lazy::new(|| {
-	let callback_port = std::env::var("CALLBACK_SERVER_PORT").unwrap()
-		.parse::<u16>().unwrap();
-	SocketAddr::from(([127, 0, 0, 1], callback_port))
-});
+// set on webhook calls and required on /service/ticket/update
+pub use erp_api_types::callbacks::{sign_payload, Callback, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+// older signatures are rejected so a captured request cannot be replayed later
+const MAX_SIGNATURE_AGE_SECS: i64 = 300;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-pub enum Callback {
-	// TODO: add more options than just python
-	Script {
-		name: String,
-		path: String,
-	},
-	Webhook {
-		name: String,
-		url: String, // should be Uri
-		headers: HashMap<String, String>
-	}
+// jobs failing this often are moved to callback_dlq
+fn max_attempts() -> i32 {
+	return std::env::var("CALLBACK_MAX_ATTEMPTS")
+		.ok()
+		.and_then(|s| s.parse::<i32>().ok())
+		.unwrap_or(8);
 }
+
+fn poll_interval() -> Duration {
+	let secs = std::env::var("CALLBACK_POLL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5);
+	return Duration::from_secs(secs);
+}
+
 pub enum SignalType {
 	SendTask, // 1u64
 	RegisterCallback // 2u64
 }
 
-pub async fn send_task(ticket_id: i32, cur_node: i32, payload: &Option<Map<String, Value>>, callbacks: &Vec<Callback>) {
-	let header_bytes = 1u64.to_le_bytes();
+// the callbacks of a node reached while executing a ticket. saved with enqueue_jobs in the ticket transaction
+#[derive(Debug, Clone)]
+pub struct CallbackJob {
+	pub ticket_id: i32,
+	pub node: i32,
+	pub payload: Option<Map<String, Value>>,
+	pub callbacks: Vec<Callback>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct DeadJob {
+	pub id: i64,
+	pub ticket_id: i32,
+	pub node: i32,
+	#[schema(value_type = Option<Object>)]
+	pub payload: Option<Json<Map<String, Value>>>,
+	#[schema(value_type = Vec<Callback>)]
+	pub callbacks: Json<Vec<Callback>>,
+	pub attempts: i32,
+	pub last_error: Option<String>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub failed_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(FromRow)]
+struct StoredJob {
+	id: i64,
+	ticket_id: i32,
+	node: i32,
+	payload: Option<Json<Map<String, Value>>>,
+	callbacks: Json<Vec<Callback>>,
+	attempts: i32,
+	// null when the ticket is gone
+	process_id: Option<String>,
+	callback_secret: Option<String>,
+	request_id: Option<String>
+}
+
+// one entry of callback_log
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct CallbackAttempt {
+	pub job_id: i64,
+	pub ticket_id: i32,
+	pub node: i32,
+	pub target: String,
+	pub payload_hash: String,
+	pub attempt: i32,
+	// sent or failed
+	pub status: String,
+	pub error: Option<String>,
+	pub latency_ms: i32,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct JobState {
+	pub id: i64,
+	pub node: i32,
+	// pending, sent, cancelled or dead
+	pub status: String,
+	pub result: Option<String>,
+	pub attempts: i32,
+	pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub last_error: Option<String>,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TicketCallbacks {
+	pub jobs: Vec<JobState>,
+	pub attempts: Vec<CallbackAttempt>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackOutcome {Completed, Failed}
+
+impl CallbackOutcome {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			CallbackOutcome::Completed => "completed",
+			CallbackOutcome::Failed => "failed"
+		};
+	}
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CallbackResult {
+	// job_id of the task payload
+	pub job_id: i64,
+	pub outcome: CallbackOutcome,
+	// node data of a completed task
+	#[schema(value_type = Option<Object>)]
+	pub data: Option<Map<String, Value>>,
+	// kept as sent for failed tasks
+	#[schema(value_type = Option<Object>)]
+	pub error: Option<Value>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CallbackSecret {
+	pub process_id: String,
+	pub secret: String
+}
+
+pub fn verify_payload(secret: &str, timestamp: &str, body: &[u8], signature: &str, now: i64) -> bool {
+	let sent_at = match timestamp.parse::<i64>() {
+		Ok(t) => t,
+		Err(_) => return false
+	};
+	if (now - sent_at).abs() > MAX_SIGNATURE_AGE_SECS {
+		return false;
+	}
+	let signature = match signature.strip_prefix("v1=").and_then(|s| hex::decode(s).ok()) {
+		Some(s) => s,
+		None => return false
+	};
+
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+	mac.update(timestamp.as_bytes());
+	mac.update(b".");
+	mac.update(body);
+	// constant time comparison
+	return mac.verify_slice(&signature).is_ok();
+}
+
+// the secret of the process the ticket runs. archived tickets are not looked up, they cannot be completed anymore
+pub async fn ticket_secret(conn: &mut PgConnection, ticket_id: i32) -> Result<Option<String>, sqlx::Error> {
+	let secret: Option<(String,)> = sqlx::query_as(
+		"select p.callback_secret from tickets t join process_defs p on t.tenant_id=p.tenant_id and t.process_id=p.process_id where t.id=$1")
+		.bind(ticket_id)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(secret.map(|s| s.0));
+}
+
+// the part of a task payload a webhook into this server needs
+#[derive(Deserialize)]
+struct PostedTask {
+	ticket_id: i32
+}
+
+// the tenant of the ticket of a task posted back to this server by a webhook callback. the request has no token or api
+// key to be scoped by, so the ticket is looked up in every tenant and the handler runs in the tenant of the ticket
+pub async fn task_tenant(pool: &PgPool, body: &[u8]) -> Result<i32, StatusCode> {
+	let task: PostedTask = serde_json::from_slice(body).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+	let tenant: Result<Option<(i32,)>, _> = tenant::across_tenants(sqlx::query_as("select tenant_id from tickets where id=$1")
+		.bind(task.ticket_id)
+		.fetch_optional(pool)).await;
+	if let Err(e) = tenant {
+		admin_logger(LogType::Error, &format!("Error reading the tenant of ticket {}: {}", task.ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return tenant.unwrap().map(|t| t.0).ok_or(StatusCode::NOT_FOUND);
+}
+
+// checks a task posted back to this server by a webhook callback, e.g. to /service/assets/fulfil. the signature made
+// with the callback secret of the process proves the task comes from this server, the callback server sends no api key.
+// returns the ticket of the task
+pub async fn verify_task(conn: &mut PgConnection, headers: &HeaderMap, body: &[u8]) -> Result<i32, StatusCode> {
+	let task: PostedTask = serde_json::from_slice(body).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+	let secret = ticket_secret(&mut *conn, task.ticket_id).await;
+	if let Err(e) = secret {
+		admin_logger(LogType::Error, &format!("Error reading the callback secret of ticket {}: {}", task.ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let secret = secret.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+	let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).unwrap_or("");
+	if !verify_payload(&secret, header(TIMESTAMP_HEADER), body, header(SIGNATURE_HEADER), chrono::Utc::now().timestamp()) {
+		admin_logger(LogType::Warning, &format!("Unsigned or badly signed task of ticket {}", task.ticket_id), None);
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+	return Ok(task.ticket_id);
+}
+
+pub async fn enqueue_jobs(conn: &mut PgConnection, jobs: &[CallbackJob]) -> Result<(), sqlx::Error> {
+	for job in jobs {
+		sqlx::query("insert into callback_jobs (ticket_id, node, payload, callbacks, created_at, request_id) values ($1, $2, $3, $4, $5, $6)")
+			.bind(job.ticket_id)
+			.bind(job.node)
+			.bind(job.payload.as_ref().map(Json))
+			.bind(Json(&job.callbacks))
+			.bind(chrono::Utc::now())
+			.bind(logger::current_request_id())
+			.execute(&mut *conn)
+			.await?;
+	}
+	return Ok(());
+}
+
+// the pending jobs of a ticket that was cancelled, rejected or archived, nothing can complete their node anymore
+pub async fn cancel_jobs(conn: &mut PgConnection, ticket_id: i32) -> Result<(), sqlx::Error> {
+	sqlx::query("update callback_jobs set status='cancelled' where ticket_id=$1 and status='pending'")
+		.bind(ticket_id)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+pub fn task_message(
+	job_id: i64,
+	ticket_id: i32,
+	cur_node: i32,
+	payload: &Option<Map<String, Value>>,
+	callbacks: Vec<Callback>,
+	secret: &str,
+	request_id: Option<String>
+) -> TaskMessage {
+	let task_payload = make_task_payload(job_id, ticket_id, cur_node, payload);
+	let timestamp = chrono::Utc::now().timestamp();
+	return TaskMessage {
+		job_id,
+		signature: PayloadSignature {
+			timestamp,
+			signature: sign_payload(secret, timestamp, task_payload.as_bytes())
+		},
+		payload: task_payload,
+		callbacks,
+		request_id
+	};
+}
+
+// hands the task to the callback workers through the configured transport
+pub async fn send_task(transport: &dyn CallbackTransport, task: &TaskMessage) -> Result<(), String> {
+	let timeout = request_timeout();
+	match tokio::time::timeout(timeout, transport.send(task)).await {
+		Ok(result) => return result,
+		Err(_) => return Err(format!("{} did not acknowledge the task within {} ms", transport.target(), timeout.as_millis()))
+	}
+}
+
+pub fn payload_hash(payload: &str) -> String {
+	return format!("{:x}", Sha256::digest(payload.as_bytes()));
+}
+
+async fn log_attempt(conn: &mut PgConnection, attempt: &CallbackAttempt) -> Result<(), sqlx::Error> {
+	sqlx::query(
+		r#"insert into callback_log (job_id, ticket_id, node, target, payload_hash, attempt, status, error, latency_ms, created_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#)
+		.bind(attempt.job_id)
+		.bind(attempt.ticket_id)
+		.bind(attempt.node)
+		.bind(&attempt.target)
+		.bind(&attempt.payload_hash)
+		.bind(attempt.attempt)
+		.bind(&attempt.status)
+		.bind(&attempt.error)
+		.bind(attempt.latency_ms)
+		.bind(attempt.created_at)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+// the jobs of a ticket, dead ones included, and every attempt made to send them
+pub async fn ticket_callbacks(conn: &mut PgConnection, ticket_id: i32) -> Result<TicketCallbacks, sqlx::Error> {
+	let jobs: Vec<JobState> = sqlx::query_as(
+		r#"select id, node, status, result, attempts, next_attempt_at, last_error, created_at from callback_jobs where ticket_id=$1
+			union all select id, node, 'dead', null, attempts, null, last_error, created_at from callback_dlq where ticket_id=$1
+			order by id"#)
+		.bind(ticket_id)
+		.fetch_all(&mut *conn)
+		.await?;
+	let attempts: Vec<CallbackAttempt> = sqlx::query_as(
+		r#"select job_id, ticket_id, node, target, payload_hash, attempt, status, error, latency_ms, created_at
+			from callback_log where ticket_id=$1 order by created_at, id"#)
+		.bind(ticket_id)
+		.fetch_all(&mut *conn)
+		.await?;
+	return Ok(TicketCallbacks { jobs, attempts });
+}
+
+// sends one due job. returns false when there is nothing left to send
+async fn send_next_job(pool: &PgPool) -> Result<bool, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	// jobs locked by another worker are skipped. the jobs of a closed ticket are still sent, the node that queued them
+	// may have led straight to the end of the process. cancelled and rejected tickets cancel theirs
+	let job: Option<StoredJob> = sqlx::query_as(
+		r#"select j.id, j.ticket_id, j.node, j.payload, j.callbacks, j.attempts, t.process_id, p.callback_secret, j.request_id from callback_jobs j
+			left join tickets t on j.ticket_id=t.id left join process_defs p on t.tenant_id=p.tenant_id and t.process_id=p.process_id
+			where j.status='pending' and j.next_attempt_at <= $1 order by j.id limit 1 for update of j skip locked"#)
+		.bind(now)
+		.fetch_optional(&mut *tx)
+		.await?;
+	let job = match job {
+		Some(job) => job,
+		None => return Ok(false)
+	};
+
+	let payload = job.payload.map(|p| p.0);
+	let sent = match (&job.process_id, &job.callback_secret) {
+		(Some(process_id), Some(secret)) => {
+			let callbacks = callback_endpoints::resolve_endpoints(&mut tx, process_id, job.node, &job.callbacks.0).await?;
+			match callback_transport::transport().await {
+				Ok(transport) => {
+					// the job stays due and keeps its attempts while the target is not tried
+					let target = transport.target();
+					if !breaker::allow(&target) {
+						return Ok(false);
+					}
+					let task = task_message(job.id, job.ticket_id, job.node, &payload, callbacks, secret, job.request_id.clone());
+					let started = std::time::Instant::now();
+					let sent = send_task(transport, &task).await;
+					breaker::record(&target, sent.is_ok());
+					log_attempt(&mut tx, &CallbackAttempt {
+						job_id: job.id,
+						ticket_id: job.ticket_id,
+						node: job.node,
+						target,
+						payload_hash: payload_hash(&task.payload),
+						attempt: job.attempts + 1,
+						status: if sent.is_ok() { "sent" } else { "failed" }.to_string(),
+						error: sent.as_ref().err().cloned(),
+						latency_ms: started.elapsed().as_millis() as i32,
+						created_at: chrono::Utc::now()
+					}).await?;
+					sent
+				}
+				Err(e) => Err(e)
+			}
+		}
+		_ => Err(format!("Ticket {} or its process no longer exists", job.ticket_id))
+	};
+	match sent {
+		Ok(_) => {
+			sqlx::query("update callback_jobs set status='sent', attempts=attempts+1, sent_at=$2, last_error=null where id=$1")
+				.bind(job.id)
+				.bind(chrono::Utc::now())
+				.execute(&mut *tx)
+				.await?;
+		}
+		Err(e) => {
+			// logged under the request that queued the job
+			tracing::info_span!("callback_job", request_id = job.request_id.as_deref(), ticket_id = job.ticket_id)
+				.in_scope(|| admin_logger(LogType::FailedToPing, &format!("Callback job {} of ticket {} failed: {}", job.id, job.ticket_id, e), None));
+			sqlx::query("update callback_jobs set attempts=attempts+1, next_attempt_at=$2, last_error=$3 where id=$1")
+				.bind(job.id)
+				.bind(now + backoff(job.attempts + 1))
+				.bind(&e)
+				.execute(&mut *tx)
+				.await?;
+			if job.attempts + 1 >= max_attempts() {
+				bury_job(&mut tx, job.id, job.ticket_id).await?;
+			}
+			tx.commit().await?;
+			// the callback server is most likely down, the other jobs are tried on the next poll
+			return Ok(false);
+		}
+	}
+
+	tx.commit().await?;
+	return Ok(true);
+}
+
+// moves the job to the dead letter queue and tells the users managing the processes of the ticket's tenant. the BlockingTask waiting for it stays open until it is requeued
+async fn bury_job(conn: &mut PgConnection, id: i64, ticket_id: i32) -> Result<(), sqlx::Error> {
+	sqlx::query(
+		r#"with dead as (delete from callback_jobs where id=$1 returning *)
+		insert into callback_dlq (id, ticket_id, node, payload, callbacks, attempts, last_error, created_at, failed_at, request_id)
+		select id, ticket_id, node, payload, callbacks, attempts, last_error, created_at, $2, request_id from dead"#)
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+
+	let message = format!("Callback job {} of ticket {} failed too often and was moved to the dead letter queue", id, ticket_id);
+	admin_logger(LogType::Error, &message, None);
+	// the worker sees every tenant, only the users of the ticket's tenant who manage its processes are told
+	let notified = sqlx::query(
+		r#"insert into notifications (userid, message, created_at, urgent)
+			select distinct u.userid, $1, $2, true from users u join user_roles ur on u.userid=ur.userid
+			join role_permissions rp on ur.tenant_id=rp.tenant_id and ur.role_=rp.role_
+			where (rp.permission=$3 or rp.permission='*') and u.active=true
+			and u.tenant_id=coalesce((select tenant_id from tickets where id=$4), (select tenant_id from tickets_archive where id=$4))"#)
+		.bind(&message)
+		.bind(chrono::Utc::now())
+		.bind(ManageProcesses::NAME)
+		.bind(ticket_id)
+		.execute(&mut *conn)
+		.await?;
+	if notified.rows_affected() > 0 {
+		outbox::enqueue(conn).await?;
+	}
+	return Ok(());
+}
+
+// every job is sent in its own transaction. on shutdown the jobs left are sent by the next server
+pub async fn send_due_jobs(pool: &PgPool) {
+	while !shutdown::is_stopping() {
+		match send_next_job(pool).await {
+			Ok(true) => {}
+			Ok(false) => return,
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error sending callback jobs: {}", e), None);
+				return;
+			}
+		}
+	}
+}
+
+// sends the jobs of a committed transaction without waiting for the next poll
+pub fn dispatch(pool: &PgPool) {
+	let pool = pool.clone();
+	shutdown::spawn(async move {
+		send_due_jobs(&pool).await;
+	});
+}
+
+pub async fn run_callback_jobs(pool: PgPool) {
+	let mut interval = tokio::time::interval(poll_interval());
+	while shutdown::tick(&mut interval).await {
+		send_due_jobs(&pool).await;
+	}
+}
+
+#[utoipa::path(get, path = "/admin/callbacks/dead", tag = "callbacks", responses(
+	(status = 200, description = "Callback jobs that ran out of attempts, last failed first", body = Vec<DeadJob>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_dead_jobs(
+	_auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<axum::Json<Vec<DeadJob>>, StatusCode> {
+	// callback_dlq has no tenant, the join keeps the jobs of tickets of other tenants out
+	let query: Result<Vec<DeadJob>, _> = sqlx::query_as(
+		r#"select d.id, d.ticket_id, d.node, d.payload, d.callbacks, d.attempts, d.last_error, d.created_at, d.failed_at
+			from callback_dlq d join (select id from tickets union all select id from tickets_archive) t on t.id=d.ticket_id
+			order by d.failed_at desc"#)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading dead callback jobs: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(axum::Json(query.unwrap()));
+}
+
+// puts a dead job back in the queue with a fresh attempt count
+#[utoipa::path(post, path = "/admin/callbacks/{id}/retry", tag = "callbacks", params(("id" = i64, Path, description = "Job id")), responses(
+	(status = 202, description = "Queued again with a fresh attempt count"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "No dead job with this id")
+))]
+pub async fn retry_dead_job(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i64>
+) -> Result<StatusCode, StatusCode> {
+	// only jobs of tickets of the tenant, and not of archived ones, which cannot be sent anymore
+	let query = sqlx::query(
+		r#"with dead as (delete from callback_dlq d using tickets t where d.id=$1 and t.id=d.ticket_id returning d.*)
+		insert into callback_jobs (id, ticket_id, node, payload, callbacks, status, attempts, next_attempt_at, last_error, created_at, request_id)
+		select id, ticket_id, node, payload, callbacks, 'pending', 0, $2, last_error, created_at, request_id from dead"#)
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error requeueing dead callback job {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} requeued callback job {}", auth.user.userid, id), None);
+	dispatch(&pool);
+	return Ok(StatusCode::ACCEPTED);
+}
+
+// whether a reported outcome still has to be applied to a job that may already be resolved.
+// redeliveries of the same outcome are accepted without doing anything, a different outcome conflicts
+pub fn resolve_outcome(previous: Option<&str>, outcome: CallbackOutcome) -> Result<bool, StatusCode> {
+	return match previous {
+		None => Ok(true),
+		Some(previous) if previous == outcome.as_str() => Ok(false),
+		Some(_) => Err(StatusCode::CONFLICT)
+	};
+}
+
+// reports the outcome of a callback job. completed jobs complete their BlockingTask node like /service/ticket/update,
+// failed jobs leave the node open and tell the ticket owner. signed like /service/ticket/update
+#[utoipa::path(post, path = "/ticket/{id}/callback_result", tag = "service", security(("api_key" = [])), params(
+	("id" = i32, Path, description = "Ticket id"),
+	("X-Erp-Timestamp" = i64, Header, description = "Unix seconds the body was signed at"),
+	("X-Erp-Signature" = String, Header, description = "Hex hmac-sha256 of the timestamp and body with the callback secret of the process")
+), request_body = CallbackResult, responses(
+	(status = 200, description = "Applied, or the same outcome was already reported"),
+	(status = 401, description = "Bad signature"),
+	(status = 403, description = "The key lacks the complete_blocking_task scope"),
+	(status = 404, description = "Unknown job"),
+	(status = 409, description = "A different outcome was already reported", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid body or node data", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn callback_result(
+	key: ApiKey,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>,
+	headers: HeaderMap,
+	body: Bytes
+) -> Result<StatusCode, UpdateErr> {
+	key.require("complete_blocking_task")?;
+	let payload: CallbackResult = serde_json::from_slice(&body).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+	let owner_id = api_keys::verify_completion(&pool, &key, ticket_id, &headers, &body).await?;
+
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	// taken before the job like every other writer of the ticket, the update takes it again
+	if let Err(e) = ticket::lock_ticket(&mut tx, ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	// the lock makes concurrent redeliveries wait for the first one
+	let job: Result<Option<(i32, Option<String>)>, _> = sqlx::query_as(
+		"select node, result from callback_jobs where id=$1 and ticket_id=$2 for update")
+		.bind(payload.job_id)
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = job {
+		admin_logger(LogType::Error, &format!("Error reading callback job {} of ticket {}: {}", payload.job_id, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let (node, previous) = job.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+	if !resolve_outcome(previous.as_deref(), payload.outcome)? {
+		return Ok(StatusCode::OK);
+	}
 
-	let conn = TcpStream::connect(*CALLBACK_ADDR).await;
-	if let Err(ref e) = conn {
-		let _ = admin_logger(LogType::FailedToPing, &format!("Failed to ping callback server. e: {}", e), None)
-			.map_err(|_e| ExecuteErr::FailedToLog);
+	let result_data = match payload.outcome {
+		CallbackOutcome::Completed => payload.data.clone().map(Value::Object),
+		CallbackOutcome::Failed => payload.error.clone()
+	};
+	// the node is completed and the job resolved together, a job is never left open for a completed node or the other way round
+	let mut events = Vec::new();
+	if payload.outcome == CallbackOutcome::Completed {
+		let update = UpdateTicket {
+			ticket_id,
+			user_id: owner_id,
+			status: true,
+			node,
+			data: payload.data,
+			instance: None,
+			reason: None
+		};
+		events = ticket::apply_update_in(&mut tx, update, UpdateSource::Service).await?;
+	}
+	else {
+		let message = format!("The callback of node {} of ticket {} failed", node, ticket_id);
+		let query = sqlx::query("insert into notifications (userid, ticket_id, message, created_at, urgent) values ($1, $2, $3, $4, true)")
+			.bind(owner_id)
+			.bind(ticket_id)
+			.bind(&message)
+			.bind(chrono::Utc::now())
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error notifying owner of ticket {} about failed callback job {}: {}", ticket_id, payload.job_id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		outbox::enqueue(&mut tx).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 	}
 
-	let mut conn = conn.unwrap();
-	let res = conn.write(&header_bytes).await;
-	
-	if let Err(ref e) = res {
-		let _ = admin_logger(LogType::FailedToPing, &format!("Failed to send header to callback server. e: {}", e), None)
-			.map_err(|_e| ExecuteErr::FailedToLog);
-	}	
+	let query = sqlx::query("update callback_jobs set result=$2, result_data=$3, resolved_at=$4 where id=$1")
+		.bind(payload.job_id)
+		.bind(payload.outcome.as_str())
+		.bind(result_data.map(Json))
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error resolving callback job {} of ticket {}: {}", payload.job_id, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting result of callback job {}: {}", payload.job_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
 
+	let log_type = match payload.outcome {
+		CallbackOutcome::Completed => LogType::Info,
+		CallbackOutcome::Failed => LogType::Warning
+	};
+	admin_logger(log_type, &format!("Api key {} ({}) reported callback job {} of ticket {} as {}", key.name, key.id, payload.job_id, ticket_id, payload.outcome.as_str()), None);
+	ticket::after_commit(&pool, events).await;
+	return Ok(StatusCode::OK);
+}
 
-	let serialized_callbacks = serde_json::to_string(callbacks).unwrap();
-	let task_payload = make_task_payload(ticket_id, cur_node, payload);
-	
-	// FIXME: : The connection might break at this stage...
-	
-	// send data for the callbacks
-	let _ = conn.write(&(task_payload.len() as u64).to_le_bytes()).await;
-	let _ = conn.write(&task_payload.as_bytes()).await;
-	
-	// send callbacks
-	let _ = conn.write(&(serialized_callbacks.len() as u64).to_le_bytes()).await;
-	let _ = conn.write(&serialized_callbacks.as_bytes()).await;
+// replaces the callback secret of the process. tasks signed with the old secret can no longer be completed
+#[utoipa::path(post, path = "/process/{id}/callback_secret", tag = "processes", params(("id" = String, Path, description = "Process id")), responses(
+	(status = 200, description = "The new secret. tasks signed with the old one can no longer be completed", body = CallbackSecret),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown process")
+))]
+pub async fn rotate_callback_secret(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(process_id): extract::Path<String>
+) -> Result<axum::Json<CallbackSecret>, StatusCode> {
+	let secret = new_secret();
+	let query = sqlx::query("update process_defs set callback_secret=$2 where process_id=$1")
+		.bind(&process_id)
+		.bind(&secret)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error rotating callback secret of process {}: {}", process_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
+	admin_logger(LogType::Info, &format!("User {} rotated the callback secret of process {}", auth.user.userid, process_id), None);
+	return Ok(axum::Json(CallbackSecret { process_id, secret }));
 }
 
+#[cfg(test)]
+mod callbacks_tests {
+	use axum::http::StatusCode;
+	use super::{payload_hash, resolve_outcome, sign_payload, verify_payload, CallbackOutcome, MAX_SIGNATURE_AGE_SECS};
 
+	#[test]
+	fn payloads_are_signed() {
+		let body = br#"{"ticket_id":3,"node":2,"cur_node_payload":null}"#;
+		let signature = sign_payload("secret", 1716280000, body);
+		assert!(signature.starts_with("v1="));
+		assert!(verify_payload("secret", "1716280000", body, &signature, 1716280010));
 
+		// other secret, body or timestamp
+		assert!(!verify_payload("other", "1716280000", body, &signature, 1716280010));
+		assert!(!verify_payload("secret", "1716280000", b"{}", &signature, 1716280010));
+		assert!(!verify_payload("secret", "1716280001", body, &signature, 1716280010));
+	}
 
+	#[test]
+	fn payload_hashes_are_hex_sha256() {
+		assert_eq!(payload_hash("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+	}
+
+	#[test]
+	fn stale_and_malformed_signatures_are_rejected() {
+		let body = b"{}";
+		let signature = sign_payload("secret", 1716280000, body);
+		assert!(!verify_payload("secret", "1716280000", body, &signature, 1716280001 + MAX_SIGNATURE_AGE_SECS));
+		assert!(!verify_payload("secret", "yesterday", body, &signature, 1716280000));
+		assert!(!verify_payload("secret", "1716280000", body, "", 1716280000));
+		assert!(!verify_payload("secret", "1716280000", body, &signature.replace("v1=", "v0="), 1716280000));
+	}
+
+	#[test]
+	fn redelivered_results_are_idempotent() {
+		assert_eq!(resolve_outcome(None, CallbackOutcome::Completed), Ok(true));
+		assert_eq!(resolve_outcome(Some("completed"), CallbackOutcome::Completed), Ok(false));
+		assert_eq!(resolve_outcome(Some("failed"), CallbackOutcome::Failed), Ok(false));
+		assert_eq!(resolve_outcome(Some("failed"), CallbackOutcome::Completed), Err(StatusCode::CONFLICT));
+	}
+}
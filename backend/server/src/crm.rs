@@ -0,0 +1,907 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::invoices::format_cents;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::purchase_orders::valid_currency;
+use crate::rbac::{Authorized, ManageCustomers};
+use crate::schema::{FieldError, FieldErrors, field_error, internal_error, status_error};
+use crate::ticket;
+use utoipa::{IntoParams, ToSchema};
+
+pub const STAGES: [&str; 5] = ["lead", "qualified", "proposal", "won", "lost"];
+
+// the process whose tickets approve discounts
+fn process_id() -> String {
+	return std::env::var("DISCOUNT_APPROVAL_PROCESS").unwrap_or("discount_approval".to_string());
+}
+
+// discounts above it need an approval, in basis points. 1000 is 10%
+pub fn discount_threshold_bp() -> i32 {
+	return std::env::var("DISCOUNT_APPROVAL_THRESHOLD_BP")
+		.ok()
+		.and_then(|bp| bp.parse().ok())
+		.unwrap_or(1000);
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CustomerInput {
+	pub name: String,
+	pub tax_id: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	pub address: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Customer {
+	pub id: i32,
+	pub name: String,
+	pub tax_id: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	pub address: Option<String>,
+	pub status: String,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ContactInput {
+	pub name: String,
+	pub job_title: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	#[serde(default)]
+	pub is_primary: bool
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Contact {
+	pub id: i32,
+	pub customer_id: i32,
+	pub name: String,
+	pub job_title: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	pub is_primary: bool
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OpportunityInput {
+	pub contact_id: Option<i32>,
+	pub title: String,
+	// iso 4217 code like EUR
+	pub currency: String,
+	pub amount_cents: i64,
+	// in basis points, 1500 is 15%
+	#[serde(default)]
+	pub discount_bp: i32,
+	pub expected_close_date: Option<NaiveDate>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateOpportunity {
+	pub customer_id: i32,
+	#[serde(flatten)]
+	pub opportunity: OpportunityInput
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Opportunity {
+	pub id: i32,
+	pub customer_id: i32,
+	pub contact_id: Option<i32>,
+	pub title: String,
+	pub currency: String,
+	pub amount_cents: i64,
+	pub discount_bp: i32,
+	pub approved_discount_bp: Option<i32>,
+	pub stage: String,
+	pub pending_stage: Option<String>,
+	pub expected_close_date: Option<NaiveDate>,
+	pub owner_id: uuid::Uuid,
+	pub ticket_id: Option<i32>,
+	pub closed_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct StageChange {
+	pub from_stage: String,
+	pub to_stage: String,
+	pub changed_by: Option<uuid::Uuid>,
+	pub ticket_id: Option<i32>,
+	pub changed_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CustomerDetail {
+	#[serde(flatten)]
+	pub customer: Customer,
+	pub contacts: Vec<Contact>,
+	pub opportunities: Vec<Opportunity>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OpportunityDetail {
+	#[serde(flatten)]
+	pub opportunity: Opportunity,
+	pub net_cents: i64,
+	pub stage_changes: Vec<StageChange>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CustomersQuery {
+	pub status: Option<String>,
+	// part of the name
+	pub q: Option<String>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OpportunitiesQuery {
+	pub stage: Option<String>,
+	pub customer_id: Option<i32>,
+	pub owner_id: Option<uuid::Uuid>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MoveOpportunity {
+	pub stage: String
+}
+
+pub fn check_customer(customer: &CustomerInput) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if customer.name.trim().is_empty() {
+		error("/name", "A name is required");
+	}
+	if customer.email.as_deref().is_some_and(|email| !email.contains('@')) {
+		error("/email", "Not an email address");
+	}
+	return errors;
+}
+
+pub fn check_contact(contact: &ContactInput) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if contact.name.trim().is_empty() {
+		error("/name", "A name is required");
+	}
+	if contact.email.as_deref().is_some_and(|email| !email.contains('@')) {
+		error("/email", "Not an email address");
+	}
+	return errors;
+}
+
+pub fn check_opportunity(opportunity: &OpportunityInput) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if opportunity.title.trim().is_empty() {
+		error("/title", "A title is required");
+	}
+	if !valid_currency(&opportunity.currency) {
+		error("/currency", "Expected a three letter currency code like EUR");
+	}
+	if opportunity.amount_cents < 0 {
+		error("/amount_cents", "Cannot be negative");
+	}
+	if opportunity.discount_bp < 0 || opportunity.discount_bp > 10_000 {
+		error("/discount_bp", "Expected between 0 and 10000 basis points");
+	}
+	return errors;
+}
+
+// the amount after the discount, the discount rounded half up
+pub fn net_cents(amount: i64, discount_bp: i32) -> i64 {
+	let discount = (amount as i128 * discount_bp as i128 + 5_000) / 10_000;
+	return amount - discount as i64;
+}
+
+// whether an opportunity in `current` can be moved to `target` by its owner. won and lost are final
+pub fn can_move(current: &str, target: &str) -> bool {
+	return matches!((current, target),
+		("lead", "qualified") | ("qualified", "proposal") | ("proposal", "won")
+		| ("proposal", "qualified") | ("lead" | "qualified" | "proposal", "lost"));
+}
+
+// moving to `target` with the discount waits for an approval, unless an approval covered at least this discount
+pub fn needs_approval(target: &str, discount_bp: i32, approved_discount_bp: Option<i32>, threshold_bp: i32) -> bool {
+	return matches!(target, "proposal" | "won")
+		&& discount_bp > threshold_bp
+		&& approved_discount_bp.is_none_or(|approved| discount_bp > approved);
+}
+
+// whether the approval ticket asking for `target` approved the discount. None for the statuses that do not decide it
+pub fn approval_outcome(target: &str) -> Option<bool> {
+	return match target {
+		"approved" | "closed" => Some(true),
+		"rejected" | "cancelled" => Some(false),
+		_ => None
+	};
+}
+
+fn unprocessable(errors: Vec<FieldError>) -> Result<(), (StatusCode, Json<FieldErrors>)> {
+	if errors.is_empty() {
+		return Ok(());
+	}
+	return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+}
+
+fn name_taken(e: &sqlx::Error) -> bool {
+	return e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false);
+}
+
+async fn read_customer(conn: &mut PgConnection, id: i32) -> Result<Customer, StatusCode> {
+	let customer: Result<Option<Customer>, _> = sqlx::query_as("select * from customers where id=$1")
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = customer {
+		admin_logger(LogType::Error, &format!("Error reading customer {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return customer.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_opportunity(conn: &mut PgConnection, id: i32, lock: bool) -> Result<Opportunity, StatusCode> {
+	let sql = if lock { "select * from opportunities where id=$1 for update" } else { "select * from opportunities where id=$1" };
+	let opportunity: Result<Option<Opportunity>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = opportunity {
+		admin_logger(LogType::Error, &format!("Error reading opportunity {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return opportunity.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_opportunity_detail(conn: &mut PgConnection, opportunity: Opportunity) -> Result<OpportunityDetail, StatusCode> {
+	let stage_changes: Result<Vec<StageChange>, _> = sqlx::query_as(
+		"select from_stage, to_stage, changed_by, ticket_id, changed_at from opportunity_stage_changes where opportunity_id=$1 order by id")
+		.bind(opportunity.id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = stage_changes {
+		admin_logger(LogType::Error, &format!("Error reading the stages of opportunity {}: {}", opportunity.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let net_cents = net_cents(opportunity.amount_cents, opportunity.discount_bp);
+	return Ok(OpportunityDetail { opportunity, net_cents, stage_changes: stage_changes.unwrap() });
+}
+
+// the contact has to be one of the customer
+async fn check_contact_of(conn: &mut PgConnection, contact_id: Option<i32>, customer_id: i32) -> Result<(), (StatusCode, Json<FieldErrors>)> {
+	let Some(contact_id) = contact_id else {
+		return Ok(());
+	};
+	let found: Result<(bool,), _> = sqlx::query_as("select exists(select 1 from customer_contacts where id=$1 and customer_id=$2)")
+		.bind(contact_id)
+		.bind(customer_id)
+		.fetch_one(&mut *conn)
+		.await;
+	if let Err(e) = found {
+		admin_logger(LogType::Error, &format!("Error reading contact {}: {}", contact_id, e), None);
+		return Err(internal_error());
+	}
+	if !found.unwrap().0 {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/contact_id", format!("Contact {} is not one of the customer", contact_id)));
+	}
+	return Ok(());
+}
+
+async fn record_change(
+	conn: &mut PgConnection,
+	opportunity: &Opportunity,
+	to_stage: &str,
+	changed_by: Option<uuid::Uuid>,
+	ticket_id: Option<i32>,
+	now: chrono::DateTime<chrono::Utc>
+) -> Result<(), sqlx::Error> {
+	sqlx::query(
+		r#"update opportunities set stage=$2, pending_stage=null, closed_at=case when $2 in ('won', 'lost') then $3 end, updated_at=$3
+			where id=$1"#)
+		.bind(opportunity.id)
+		.bind(to_stage)
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	sqlx::query(
+		r#"insert into opportunity_stage_changes (tenant_id, opportunity_id, from_stage, to_stage, changed_by, ticket_id, changed_at)
+			select tenant_id, id, $2, $3, $4, $5, $6 from opportunities where id=$1"#)
+		.bind(opportunity.id)
+		.bind(&opportunity.stage)
+		.bind(to_stage)
+		.bind(changed_by)
+		.bind(ticket_id)
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	return Ok(());
+}
+
+#[utoipa::path(post, path = "/customers", tag = "crm", request_body = CustomerInput, responses(
+	(status = 201, description = "Created", body = Customer),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 409, description = "A customer with this name exists", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn create_customer(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CustomerInput>
+) -> Result<(StatusCode, Json<Customer>), (StatusCode, Json<FieldErrors>)> {
+	unprocessable(check_customer(&payload))?;
+	let now = chrono::Utc::now();
+	let customer: Result<Customer, _> = sqlx::query_as(
+		r#"insert into customers (name, tax_id, email, phone, address, created_by, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $7) returning *"#)
+		.bind(payload.name.trim())
+		.bind(&payload.tax_id)
+		.bind(&payload.email)
+		.bind(&payload.phone)
+		.bind(&payload.address)
+		.bind(auth.user.userid)
+		.bind(now)
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = customer {
+		if name_taken(&e) {
+			return Err(field_error(StatusCode::CONFLICT, "/name", "A customer with this name exists".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error saving customer of {}: {}", auth.user.userid, e), None);
+		return Err(internal_error());
+	}
+	let customer = customer.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} created customer {}", auth.user.userid, customer.id), None);
+	return Ok((StatusCode::CREATED, Json(customer)));
+}
+
+#[utoipa::path(get, path = "/customers", tag = "crm", params(CustomersQuery), responses(
+	(status = 200, description = "Customers by name", body = Vec<Customer>),
+	(status = 403, description = "Missing the manage_customers permission")
+))]
+pub async fn get_customers(
+	_auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<CustomersQuery>
+) -> Result<Json<Vec<Customer>>, StatusCode> {
+	let customers: Result<Vec<Customer>, _> = sqlx::query_as(
+		r#"select * from customers where ($1::varchar is null or status=$1) and ($2::varchar is null or strpos(lower(name), lower($2)) > 0)
+			order by name"#)
+		.bind(&query.status)
+		.bind(&query.q)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = customers {
+		admin_logger(LogType::Error, &format!("Error reading customers: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(customers.unwrap()));
+}
+
+// the customer with its contacts and opportunities
+#[utoipa::path(get, path = "/customers/{id}", tag = "crm", params(("id" = i32, Path, description = "Customer id")), responses(
+	(status = 200, description = "The customer with contacts and opportunities", body = CustomerDetail),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer")
+))]
+pub async fn get_customer(
+	_auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<CustomerDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let customer = read_customer(&mut conn, id).await?;
+	let contacts: Result<Vec<Contact>, _> = sqlx::query_as(
+		"select id, customer_id, name, job_title, email, phone, is_primary from customer_contacts where customer_id=$1 order by is_primary desc, name")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	let opportunities: Result<Vec<Opportunity>, _> = sqlx::query_as("select * from opportunities where customer_id=$1 order by created_at desc")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	match (contacts, opportunities) {
+		(Ok(contacts), Ok(opportunities)) => return Ok(Json(CustomerDetail { customer, contacts, opportunities })),
+		(Err(e), _) | (_, Err(e)) => {
+			admin_logger(LogType::Error, &format!("Error reading customer {}: {}", id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+	}
+}
+
+#[utoipa::path(put, path = "/customers/{id}", tag = "crm", params(("id" = i32, Path, description = "Customer id")), request_body = CustomerInput, responses(
+	(status = 200, description = "Updated", body = Customer),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer", body = FieldErrors),
+	(status = 409, description = "A customer with this name exists", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn update_customer(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<CustomerInput>
+) -> Result<Json<Customer>, (StatusCode, Json<FieldErrors>)> {
+	unprocessable(check_customer(&payload))?;
+	let customer: Result<Option<Customer>, _> = sqlx::query_as(
+		"update customers set name=$2, tax_id=$3, email=$4, phone=$5, address=$6, updated_at=$7 where id=$1 returning *")
+		.bind(id)
+		.bind(payload.name.trim())
+		.bind(&payload.tax_id)
+		.bind(&payload.email)
+		.bind(&payload.phone)
+		.bind(&payload.address)
+		.bind(chrono::Utc::now())
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = customer {
+		if name_taken(&e) {
+			return Err(field_error(StatusCode::CONFLICT, "/name", "A customer with this name exists".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error updating customer {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let customer = customer.unwrap().ok_or(status_error(StatusCode::NOT_FOUND))?;
+
+	admin_logger(LogType::Info, &format!("User {} updated customer {}", auth.user.userid, id), None);
+	return Ok(Json(customer));
+}
+
+// customers stay for their opportunities, they are only deactivated. inactive customers get no new opportunities
+#[utoipa::path(delete, path = "/customers/{id}", tag = "crm", params(("id" = i32, Path, description = "Customer id")), responses(
+	(status = 204, description = "Deactivated"),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer")
+))]
+pub async fn deactivate_customer(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update customers set status='inactive', updated_at=$2 where id=$1")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deactivating customer {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deactivated customer {}", auth.user.userid, id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// saves the contact. a primary contact replaces the previous one
+async fn save_contact(conn: &mut PgConnection, customer_id: i32, contact_id: Option<i32>, contact: &ContactInput) -> Result<Option<Contact>, sqlx::Error> {
+	if contact.is_primary {
+		sqlx::query("update customer_contacts set is_primary=false where customer_id=$1 and is_primary and id is distinct from $2")
+			.bind(customer_id)
+			.bind(contact_id)
+			.execute(&mut *conn)
+			.await?;
+	}
+	let query = match contact_id {
+		None => sqlx::query_as(
+			r#"insert into customer_contacts (tenant_id, customer_id, name, job_title, email, phone, is_primary)
+				select tenant_id, id, $2, $3, $4, $5, $6 from customers where id=$1
+				returning id, customer_id, name, job_title, email, phone, is_primary"#)
+			.bind(customer_id),
+		Some(contact_id) => sqlx::query_as(
+			r#"update customer_contacts set name=$3, job_title=$4, email=$5, phone=$6, is_primary=$7 where customer_id=$1 and id=$2
+				returning id, customer_id, name, job_title, email, phone, is_primary"#)
+			.bind(customer_id)
+			.bind(contact_id)
+	};
+	return query
+		.bind(contact.name.trim())
+		.bind(&contact.job_title)
+		.bind(&contact.email)
+		.bind(&contact.phone)
+		.bind(contact.is_primary)
+		.fetch_optional(&mut *conn)
+		.await;
+}
+
+#[utoipa::path(post, path = "/customers/{id}/contacts", tag = "crm", params(("id" = i32, Path, description = "Customer id")), request_body = ContactInput, responses(
+	(status = 201, description = "Added", body = Contact),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn add_contact(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(customer_id): extract::Path<i32>,
+	Json(payload): Json<ContactInput>
+) -> Result<(StatusCode, Json<Contact>), (StatusCode, Json<FieldErrors>)> {
+	unprocessable(check_contact(&payload))?;
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let contact = save_contact(&mut tx, customer_id, None, &payload).await;
+	if let Err(e) = contact {
+		admin_logger(LogType::Error, &format!("Error saving a contact of customer {}: {}", customer_id, e), None);
+		return Err(internal_error());
+	}
+	let contact = contact.unwrap().ok_or(status_error(StatusCode::NOT_FOUND))?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting contact {}: {}", contact.id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} added contact {} to customer {}", auth.user.userid, contact.id, customer_id), None);
+	return Ok((StatusCode::CREATED, Json(contact)));
+}
+
+#[utoipa::path(put, path = "/customers/{id}/contacts/{contact_id}", tag = "crm", params(("id" = i32, Path, description = "Customer id"), ("contact_id" = i32, Path, description = "Contact id")), request_body = ContactInput, responses(
+	(status = 200, description = "Updated", body = Contact),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer or contact", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn update_contact(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((customer_id, contact_id)): extract::Path<(i32, i32)>,
+	Json(payload): Json<ContactInput>
+) -> Result<Json<Contact>, (StatusCode, Json<FieldErrors>)> {
+	unprocessable(check_contact(&payload))?;
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let contact = save_contact(&mut tx, customer_id, Some(contact_id), &payload).await;
+	if let Err(e) = contact {
+		admin_logger(LogType::Error, &format!("Error updating contact {}: {}", contact_id, e), None);
+		return Err(internal_error());
+	}
+	let contact = contact.unwrap().ok_or(status_error(StatusCode::NOT_FOUND))?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting contact {}: {}", contact_id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} updated contact {} of customer {}", auth.user.userid, contact_id, customer_id), None);
+	return Ok(Json(contact));
+}
+
+// opportunities naming the contact keep their customer
+#[utoipa::path(delete, path = "/customers/{id}/contacts/{contact_id}", tag = "crm", params(("id" = i32, Path, description = "Customer id"), ("contact_id" = i32, Path, description = "Contact id")), responses(
+	(status = 204, description = "Deleted"),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown customer or contact")
+))]
+pub async fn delete_contact(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((customer_id, contact_id)): extract::Path<(i32, i32)>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from customer_contacts where customer_id=$1 and id=$2")
+		.bind(customer_id)
+		.bind(contact_id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting contact {}: {}", contact_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted contact {} of customer {}", auth.user.userid, contact_id, customer_id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// opportunities start as leads owned by their creator
+#[utoipa::path(post, path = "/opportunities", tag = "crm", request_body = CreateOpportunity, responses(
+	(status = 201, description = "Created", body = OpportunityDetail),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 422, description = "Unknown or inactive customer, or invalid details", body = FieldErrors)
+))]
+pub async fn create_opportunity(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateOpportunity>
+) -> Result<(StatusCode, Json<OpportunityDetail>), (StatusCode, Json<FieldErrors>)> {
+	let details = &payload.opportunity;
+	unprocessable(check_opportunity(details))?;
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let customer = read_customer(&mut tx, payload.customer_id).await;
+	let customer = match customer {
+		Err(StatusCode::NOT_FOUND) => return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/customer_id", "Unknown customer".to_string())),
+		other => other.map_err(status_error)?
+	};
+	if customer.status != "active" {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/customer_id", format!("Customer {} is not active", customer.name)));
+	}
+	check_contact_of(&mut tx, details.contact_id, customer.id).await?;
+
+	let now = chrono::Utc::now();
+	let opportunity: Result<Opportunity, _> = sqlx::query_as(
+		r#"insert into opportunities (customer_id, contact_id, title, currency, amount_cents, discount_bp, expected_close_date, owner_id, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9) returning *"#)
+		.bind(customer.id)
+		.bind(details.contact_id)
+		.bind(details.title.trim())
+		.bind(&details.currency)
+		.bind(details.amount_cents)
+		.bind(details.discount_bp)
+		.bind(details.expected_close_date)
+		.bind(auth.user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = opportunity {
+		admin_logger(LogType::Error, &format!("Error saving opportunity of {}: {}", auth.user.userid, e), None);
+		return Err(internal_error());
+	}
+	let detail = read_opportunity_detail(&mut tx, opportunity.unwrap()).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting opportunity {}: {}", detail.opportunity.id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} created opportunity {} for customer {}", auth.user.userid, detail.opportunity.id, customer.id), None);
+	return Ok((StatusCode::CREATED, Json(detail)));
+}
+
+#[utoipa::path(get, path = "/opportunities", tag = "crm", params(OpportunitiesQuery), responses(
+	(status = 200, description = "Opportunities", body = Vec<Opportunity>),
+	(status = 403, description = "Missing the manage_customers permission")
+))]
+pub async fn get_opportunities(
+	_auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<OpportunitiesQuery>
+) -> Result<Json<Vec<Opportunity>>, StatusCode> {
+	let opportunities: Result<Vec<Opportunity>, _> = sqlx::query_as(
+		r#"select * from opportunities
+			where ($1::varchar is null or stage=$1) and ($2::int is null or customer_id=$2) and ($3::uuid is null or owner_id=$3)
+			order by expected_close_date nulls last, id"#)
+		.bind(&query.stage)
+		.bind(query.customer_id)
+		.bind(query.owner_id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = opportunities {
+		admin_logger(LogType::Error, &format!("Error reading opportunities: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(opportunities.unwrap()));
+}
+
+#[utoipa::path(get, path = "/opportunities/{id}", tag = "crm", params(("id" = i32, Path, description = "Opportunity id")), responses(
+	(status = 200, description = "The opportunity with its stage changes", body = OpportunityDetail),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown opportunity")
+))]
+pub async fn get_opportunity(
+	_auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<OpportunityDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let opportunity = read_opportunity(&mut conn, id, false).await?;
+	return Ok(Json(read_opportunity_detail(&mut conn, opportunity).await?));
+}
+
+// replaces the details of an open opportunity. a new discount is checked again on the next move
+#[utoipa::path(put, path = "/opportunities/{id}", tag = "crm", params(("id" = i32, Path, description = "Opportunity id")), request_body = OpportunityInput, responses(
+	(status = 200, description = "Updated", body = OpportunityDetail),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown opportunity", body = FieldErrors),
+	(status = 409, description = "The opportunity is closed or its discount is being approved", body = FieldErrors),
+	(status = 422, description = "Invalid details", body = FieldErrors)
+))]
+pub async fn update_opportunity(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<OpportunityInput>
+) -> Result<Json<OpportunityDetail>, (StatusCode, Json<FieldErrors>)> {
+	unprocessable(check_opportunity(&payload))?;
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let current = read_opportunity(&mut tx, id, true).await.map_err(status_error)?;
+	if current.pending_stage.is_some() {
+		// the approvers would approve a discount that is not in their ticket
+		return Err(field_error(StatusCode::CONFLICT, "/stage", "The discount is being approved, cancel its ticket to change it".to_string()));
+	}
+	if current.closed_at.is_some() {
+		return Err(field_error(StatusCode::CONFLICT, "/stage", format!("The opportunity is {}", current.stage)));
+	}
+	check_contact_of(&mut tx, payload.contact_id, current.customer_id).await?;
+
+	let opportunity: Result<Opportunity, _> = sqlx::query_as(
+		r#"update opportunities set contact_id=$2, title=$3, currency=$4, amount_cents=$5, discount_bp=$6, expected_close_date=$7, updated_at=$8
+			where id=$1 returning *"#)
+		.bind(id)
+		.bind(payload.contact_id)
+		.bind(payload.title.trim())
+		.bind(&payload.currency)
+		.bind(payload.amount_cents)
+		.bind(payload.discount_bp)
+		.bind(payload.expected_close_date)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = opportunity {
+		admin_logger(LogType::Error, &format!("Error updating opportunity {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let detail = read_opportunity_detail(&mut tx, opportunity.unwrap()).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting opportunity {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} updated opportunity {}", auth.user.userid, id), None);
+	return Ok(Json(detail));
+}
+
+// moves the opportunity to the next stage. with a discount over the threshold it stays where it is until the ticket
+// of the discount approval process approved it, see follow_ticket
+#[utoipa::path(post, path = "/opportunities/{id}/stage", tag = "crm", params(("id" = i32, Path, description = "Opportunity id")), request_body = MoveOpportunity, responses(
+	(status = 200, description = "Moved, or waiting for the approval of its discount", body = OpportunityDetail),
+	(status = 403, description = "Missing the manage_customers permission"),
+	(status = 404, description = "Unknown opportunity", body = FieldErrors),
+	(status = 409, description = "The move is not allowed from the current stage", body = FieldErrors),
+	(status = 422, description = "Unknown stage", body = FieldErrors)
+))]
+pub async fn move_opportunity(
+	auth: Authorized<ManageCustomers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<MoveOpportunity>
+) -> Result<Json<OpportunityDetail>, (StatusCode, Json<FieldErrors>)> {
+	if !STAGES.contains(&payload.stage.as_str()) {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/stage", format!("Expected one of {}", STAGES.join(", "))));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let mut opportunity = read_opportunity(&mut tx, id, true).await.map_err(status_error)?;
+	if let Some(pending) = &opportunity.pending_stage {
+		return Err(field_error(StatusCode::CONFLICT, "/stage", format!("The move to {} waits for its discount approval", pending)));
+	}
+	if !can_move(&opportunity.stage, &payload.stage) {
+		return Err(field_error(StatusCode::CONFLICT, "/stage", format!("Cannot move from {} to {}", opportunity.stage, payload.stage)));
+	}
+
+	let now = chrono::Utc::now();
+	let mut events = Vec::new();
+	if needs_approval(&payload.stage, opportunity.discount_bp, opportunity.approved_discount_bp, discount_threshold_bp()) {
+		let customer = read_customer(&mut tx, opportunity.customer_id).await.map_err(status_error)?;
+		let query = sqlx::query("update opportunities set pending_stage=$2, updated_at=$3 where id=$1")
+			.bind(id)
+			.bind(&payload.stage)
+			.bind(now)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error moving opportunity {}: {}", id, e), None);
+			return Err(internal_error());
+		}
+
+		// the approvers see what they give away
+		let mut data = Map::new();
+		data.insert("opportunity_id".to_string(), json!(id));
+		data.insert("customer".to_string(), json!(customer.name));
+		data.insert("title".to_string(), json!(opportunity.title));
+		data.insert("stage".to_string(), json!(payload.stage));
+		data.insert("currency".to_string(), json!(opportunity.currency));
+		data.insert("amount".to_string(), json!(format_cents(opportunity.amount_cents)));
+		data.insert("discount_percent".to_string(), json!(opportunity.discount_bp as f64 / 100.0));
+		data.insert("net_amount".to_string(), json!(format_cents(net_cents(opportunity.amount_cents, opportunity.discount_bp))));
+		let new = NewTicket { process_id: process_id(), data, tag: "discount", due_at: None };
+		let (ticket, ticket_events) = linked::start_ticket(&mut tx, &auth.user, Record { table: "opportunities", id }, new)
+			.await
+			.map_err(status_error)?;
+		events = ticket_events;
+		log(LogType::Info, format!("User {} asked to approve a discount of {} bp on opportunity {} with ticket {}", auth.user.userid, opportunity.discount_bp, id, ticket.id), ticket.log_id);
+	} else {
+		if let Err(e) = record_change(&mut tx, &opportunity, &payload.stage, Some(auth.user.userid), None, now).await {
+			admin_logger(LogType::Error, &format!("Error moving opportunity {}: {}", id, e), None);
+			return Err(internal_error());
+		}
+		admin_logger(LogType::Info, &format!("User {} moved opportunity {} from {} to {}", auth.user.userid, id, opportunity.stage, payload.stage), None);
+	}
+
+	// node 0 may already have moved it
+	opportunity = read_opportunity(&mut tx, id, false).await.map_err(status_error)?;
+	let detail = read_opportunity_detail(&mut tx, opportunity).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting opportunity {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+	return Ok(Json(detail));
+}
+
+// moves the opportunity waiting for the discount approval of the ticket, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let opportunity: Option<Opportunity> = sqlx::query_as("select * from opportunities where ticket_id=$1 and pending_stage is not null for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some(opportunity) = opportunity else {
+		return Ok(());
+	};
+	let Some(approved) = statuses.iter().find_map(|target| approval_outcome(target)) else {
+		return Ok(());
+	};
+
+	let now = chrono::Utc::now();
+	let pending = opportunity.pending_stage.clone().unwrap_or_default();
+	if approved {
+		sqlx::query("update opportunities set approved_discount_bp=greatest(approved_discount_bp, discount_bp) where id=$1")
+			.bind(opportunity.id)
+			.execute(&mut *conn)
+			.await?;
+		record_change(&mut *conn, &opportunity, &pending, None, Some(ticket_id), now).await?;
+		admin_logger(LogType::Info, &format!("Opportunity {} moved from {} to {} by ticket {}", opportunity.id, opportunity.stage, pending, ticket_id), None);
+	} else {
+		sqlx::query("update opportunities set pending_stage=null, updated_at=$2 where id=$1")
+			.bind(opportunity.id)
+			.bind(now)
+			.execute(&mut *conn)
+			.await?;
+		admin_logger(LogType::Info, &format!("Opportunity {} stays in {}, ticket {} did not approve its discount", opportunity.id, opportunity.stage, ticket_id), None);
+	}
+	return Ok(());
+}
+
+#[cfg(test)]
+mod crm_tests {
+	use super::{approval_outcome, can_move, check_opportunity, needs_approval, net_cents, OpportunityInput};
+
+	#[test]
+	fn stages_move_forward_until_won_or_lost() {
+		assert!(can_move("lead", "qualified"));
+		assert!(can_move("proposal", "qualified"));
+		assert!(can_move("qualified", "lost"));
+		assert!(!can_move("lead", "won"));
+		assert!(!can_move("won", "lost"));
+		assert!(!can_move("lost", "lead"));
+	}
+
+	#[test]
+	fn discounts_over_the_threshold_need_an_approval() {
+		assert!(needs_approval("proposal", 1500, None, 1000));
+		assert!(needs_approval("won", 2000, Some(1500), 1000));
+		assert!(!needs_approval("won", 1500, Some(1500), 1000));
+		assert!(!needs_approval("proposal", 1000, None, 1000));
+		assert!(!needs_approval("lost", 5000, None, 1000));
+
+		assert_eq!(approval_outcome("approved"), Some(true));
+		assert_eq!(approval_outcome("cancelled"), Some(false));
+		assert_eq!(approval_outcome("anything"), None);
+		assert_eq!(net_cents(10_000, 1250), 8_750);
+		assert_eq!(net_cents(999, 3333), 666);
+	}
+
+	#[test]
+	fn opportunities_are_checked() {
+		let opportunity = OpportunityInput {
+			contact_id: None,
+			title: " ".to_string(),
+			currency: "eur".to_string(),
+			amount_cents: -1,
+			discount_bp: 10_001,
+			expected_close_date: None
+		};
+		let fields: Vec<String> = check_opportunity(&opportunity).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/title", "/currency", "/amount_cents", "/discount_bp"]);
+	}
+}
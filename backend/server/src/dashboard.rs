@@ -0,0 +1,292 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ViewLogs};
+use crate::{replica, reports};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_WINDOW_DAYS: i64 = 7;
+const MAX_WINDOW_DAYS: i64 = 366;
+const DEFAULT_APPROVERS: i64 = 10;
+const MAX_APPROVERS: i64 = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DashboardQuery {
+	// first day of the window, defaults to DEFAULT_WINDOW_DAYS before `to`
+	pub from: Option<NaiveDate>,
+	// last day of the window, defaults to today. days are in utc
+	pub to: Option<NaiveDate>,
+	// how many of the busiest approvers are listed
+	pub approvers: Option<i64>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct ProcessCounts {
+	pub process_id: String,
+	pub open: i64,
+	pub closed: i64,
+	pub rejected: i64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct CallbackFailures {
+	pub target: String,
+	pub failed: i64,
+	// jobs that ran out of attempts and went to the dead letter queue
+	pub dead: i64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct ApproverLoad {
+	pub userid: uuid::Uuid,
+	pub username: String,
+	// approvals and rejections in the window
+	pub decided: i64,
+	// approvals waiting for them at the refresh
+	pub pending: i64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Dashboard {
+	pub from: NaiveDate,
+	pub to: NaiveDate,
+	// the figures are as of the last refresh of the reports
+	pub refreshed_at: chrono::DateTime<chrono::Utc>,
+	// tickets created in the window, by their status at the refresh
+	pub processes: Vec<ProcessCounts>,
+	// from the approval request to the approval. None without approvals in the window
+	pub median_approval_secs: Option<f64>,
+	// pending approvals on open tickets past their due date
+	pub overdue_approvals: i64,
+	pub callback_failures: Vec<CallbackFailures>,
+	pub busiest_approvers: Vec<ApproverLoad>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AgingQuery {
+	pub process_id: Option<String>
+}
+
+// open tickets by how long ago they were created
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct AgeBuckets {
+	// up to a day
+	pub days_0_1: i64,
+	pub days_1_3: i64,
+	pub days_3_7: i64,
+	pub over_7_days: i64,
+	// past their due date
+	pub breached: i64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct ProcessAging {
+	pub process_id: String,
+	#[sqlx(flatten)]
+	#[serde(flatten)]
+	pub buckets: AgeBuckets
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct AssigneeAging {
+	// None for tickets nobody has a pending node on
+	pub userid: Option<uuid::Uuid>,
+	pub username: Option<String>,
+	#[sqlx(flatten)]
+	#[serde(flatten)]
+	pub buckets: AgeBuckets
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgingReport {
+	pub generated_at: chrono::DateTime<chrono::Utc>,
+	// the tickets are the ones open at the last refresh of the reports
+	pub refreshed_at: chrono::DateTime<chrono::Utc>,
+	pub processes: Vec<ProcessAging>,
+	// a ticket waiting on several users is counted for each of them
+	pub assignees: Vec<AssigneeAging>
+}
+
+// the buckets of AgeBuckets, for the rows of report_open_tickets in `t` at $1
+const AGE_BUCKETS: &str = r#"count(*) filter (where t.created_at > $1 - interval '1 day') as days_0_1,
+	count(*) filter (where t.created_at <= $1 - interval '1 day' and t.created_at > $1 - interval '3 days') as days_1_3,
+	count(*) filter (where t.created_at <= $1 - interval '3 days' and t.created_at > $1 - interval '7 days') as days_3_7,
+	count(*) filter (where t.created_at <= $1 - interval '7 days') as over_7_days,
+	count(*) filter (where t.due_at < $1) as breached"#;
+
+// the days of the dashboard, at most MAX_WINDOW_DAYS of them
+pub fn window(from: Option<NaiveDate>, to: Option<NaiveDate>, today: NaiveDate) -> Result<(NaiveDate, NaiveDate), String> {
+	let to = to.unwrap_or(today);
+	let from = from.unwrap_or(to - chrono::Duration::days(DEFAULT_WINDOW_DAYS - 1));
+	if from > to {
+		return Err("from can not be after to".to_string());
+	}
+	if to - from >= chrono::Duration::days(MAX_WINDOW_DAYS) {
+		return Err(format!("The window can be at most {} days long", MAX_WINDOW_DAYS));
+	}
+	return Ok((from, to));
+}
+
+// every figure is one aggregate query over the summaries of reports.rs, they run side by side on the replica
+#[utoipa::path(get, path = "/admin/dashboard", tag = "reports", params(DashboardQuery), responses(
+	(status = 200, description = "Figures of the window as of the last refresh of the reports", body = Dashboard),
+	(status = 400, description = "Invalid window", body = String),
+	(status = 403, description = "Missing the view_logs permission")
+))]
+pub async fn get_dashboard(
+	_auth: Authorized<ViewLogs>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<DashboardQuery>
+) -> Result<Json<Dashboard>, (StatusCode, String)> {
+	let now = chrono::Utc::now();
+	let (from, to) = window(query.from, query.to, now.date_naive()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+	let approvers = query.approvers.unwrap_or(DEFAULT_APPROVERS).clamp(1, MAX_APPROVERS);
+	let pool = replica::read_pool(pool);
+
+	let processes = sqlx::query_as::<_, ProcessCounts>(
+		r#"select process_id,
+				coalesce(sum(tickets) filter (where status='open'), 0)::int8 as open,
+				coalesce(sum(tickets) filter (where status='closed'), 0)::int8 as closed,
+				coalesce(sum(tickets) filter (where status='rejected'), 0)::int8 as rejected
+			from report_ticket_counts
+			where (all_tenants() or tenant_id=current_tenant()) and day between $1 and $2
+			group by process_id order by process_id"#)
+		.bind(from)
+		.bind(to)
+		.fetch_all(&pool);
+
+	let median = sqlx::query_as::<_, (Option<f64>,)>(
+		r#"select percentile_cont(0.5) within group (order by seconds)::float8 from report_approval_times
+			where (all_tenants() or tenant_id=current_tenant())
+				and approved_at >= $1::date and approved_at < $2::date + 1"#)
+		.bind(from)
+		.bind(to)
+		.fetch_one(&pool);
+
+	let overdue = sqlx::query_as::<_, (i64,)>(
+		r#"select count(*) from report_open_tickets
+			where (all_tenants() or tenant_id=current_tenant()) and approval and due_at < $1"#)
+		.bind(now)
+		.fetch_one(&pool);
+
+	let callbacks = sqlx::query_as::<_, CallbackFailures>(
+		r#"select target, sum(failed)::int8 as failed, sum(dead)::int8 as dead from report_callback_failures
+			where (all_tenants() or tenant_id=current_tenant()) and day between $1 and $2
+			group by target order by failed desc, dead desc, target"#)
+		.bind(from)
+		.bind(to)
+		.fetch_all(&pool);
+
+	let busiest = sqlx::query_as::<_, ApproverLoad>(
+		r#"select d.userid, u.username, sum(d.decided)::int8 as decided,
+				(select count(*) from report_open_tickets p where p.userid=d.userid and p.approval) as pending
+			from report_approver_decisions d join users u on u.userid=d.userid
+			where (all_tenants() or d.tenant_id=current_tenant()) and d.day between $1 and $2
+			group by d.userid, u.username order by decided desc, u.username limit $3"#)
+		.bind(from)
+		.bind(to)
+		.bind(approvers)
+		.fetch_all(&pool);
+
+	let refreshed_at = async {
+		let mut conn = pool.acquire().await?;
+		return reports::refreshed_at(&mut conn).await;
+	};
+
+	let figures = tokio::try_join!(processes, median, overdue, callbacks, busiest, refreshed_at);
+	if let Err(e) = figures {
+		admin_logger(LogType::Error, &format!("Error reading the dashboard from {} to {}: {}", from, to, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let (processes, median, overdue, callback_failures, busiest_approvers, refreshed_at) = figures.unwrap();
+
+	return Ok(Json(Dashboard {
+		from,
+		to,
+		refreshed_at,
+		processes,
+		median_approval_secs: median.0,
+		overdue_approvals: overdue.0,
+		callback_failures,
+		busiest_approvers
+	}));
+}
+
+// open tickets by age per process and per user they wait on, for the weekly review
+#[utoipa::path(get, path = "/admin/reports/aging", tag = "reports", params(AgingQuery), responses(
+	(status = 200, description = "Open tickets by age, per process and assignee", body = AgingReport),
+	(status = 403, description = "Missing the view_logs permission")
+))]
+pub async fn get_aging(
+	_auth: Authorized<ViewLogs>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<AgingQuery>
+) -> Result<Json<AgingReport>, StatusCode> {
+	let now = chrono::Utc::now();
+	let pool = replica::read_pool(pool);
+
+	// a ticket has a row per user it waits on, counted once here
+	let per_process = format!(
+		r#"select t.process_id, {} from (select distinct on (ticket_id) * from report_open_tickets
+				where (all_tenants() or tenant_id=current_tenant()) and ($2::varchar is null or process_id=$2)) t
+			group by t.process_id order by t.process_id"#, AGE_BUCKETS);
+	let processes = sqlx::query_as::<_, ProcessAging>(&per_process)
+		.bind(now)
+		.bind(&query.process_id)
+		.fetch_all(&pool);
+
+	let per_assignee = format!(
+		r#"select t.userid, u.username, {} from report_open_tickets t
+			left join users u on u.userid=t.userid
+			where (all_tenants() or t.tenant_id=current_tenant()) and ($2::varchar is null or t.process_id=$2)
+			group by t.userid, u.username order by breached desc, over_7_days desc, u.username nulls first"#, AGE_BUCKETS);
+	let assignees = sqlx::query_as::<_, AssigneeAging>(&per_assignee)
+		.bind(now)
+		.bind(&query.process_id)
+		.fetch_all(&pool);
+
+	let refreshed_at = async {
+		let mut conn = pool.acquire().await?;
+		return reports::refreshed_at(&mut conn).await;
+	};
+
+	let report = tokio::try_join!(processes, assignees, refreshed_at);
+	if let Err(e) = report {
+		admin_logger(LogType::Error, &format!("Error reading the aging report: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (processes, assignees, refreshed_at) = report.unwrap();
+
+	return Ok(Json(AgingReport { generated_at: now, refreshed_at, processes, assignees }));
+}
+
+#[cfg(test)]
+mod dashboard_tests {
+	use chrono::NaiveDate;
+	use super::window;
+
+	fn day(s: &str) -> NaiveDate {
+		return s.parse().unwrap();
+	}
+
+	#[test]
+	fn windows_default_to_the_last_week() {
+		let today = day("2024-06-14");
+		assert_eq!(window(None, None, today), Ok((day("2024-06-08"), today)));
+		assert_eq!(window(Some(day("2024-06-01")), None, today), Ok((day("2024-06-01"), today)));
+		assert_eq!(window(None, Some(day("2024-06-10")), today), Ok((day("2024-06-04"), day("2024-06-10"))));
+		// a single day
+		assert_eq!(window(Some(today), Some(today), today), Ok((today, today)));
+	}
+
+	#[test]
+	fn windows_are_bounded() {
+		let today = day("2024-06-14");
+		assert!(window(Some(day("2024-06-15")), Some(today), today).is_err());
+		assert!(window(Some(day("2022-01-01")), None, today).is_err());
+	}
+}
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use uuid;
 use sqlx::FromRow;
+use erp_api_types::tickets::TicketStatus;
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
@@ -23,7 +25,7 @@ pub struct Role {
 	pub role_: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, FromRow, Debug)]
+#[derive(Clone, Serialize, Deserialize, FromRow, Debug, ToSchema)]
 pub struct Ticket {
 	pub id: i32,
 	pub owner_id: uuid::Uuid,
@@ -32,9 +34,16 @@ pub struct Ticket {
 	pub is_public: bool,
 	pub created_at: chrono::DateTime<chrono::Utc>,
 	pub updated_at: chrono::DateTime<chrono::Utc>,
-	pub status: String,
+	pub status: TicketStatus,
 	pub complete: i32,
-	pub state: serde_json::Value
+	// higher is more urgent
+	pub priority: i32,
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+	#[schema(value_type = Object)]
+	pub state: serde_json::Value,
+	// progress of multi instance nodes. {"<node>": {"total": n, "done": [completed instances]}}
+	#[schema(value_type = Object)]
+	pub instances: serde_json::Value
 }
 
 impl Ticket {
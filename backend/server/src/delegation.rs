@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use utoipa::ToSchema;
+
+// users only delegate their own approvals
+#[derive(Deserialize, ToSchema)]
+pub struct CreateDelegation {
+	pub delegate_id: uuid::Uuid,
+	pub starts_at: chrono::DateTime<chrono::Utc>,
+	pub ends_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Delegation {
+	pub id: i32,
+	pub userid: uuid::Uuid,
+	pub delegate_id: uuid::Uuid,
+	pub starts_at: chrono::DateTime<chrono::Utc>,
+	pub ends_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(FromRow)]
+struct Delegate {
+	userid: uuid::Uuid,
+	delegate_id: uuid::Uuid
+}
+
+// Returns the users that currently handle approvals for those of `userids` that are out of office.
+// Delegations are not followed transitively so two users delegating to each other cannot loop.
+pub async fn active_delegates(conn: &mut PgConnection, userids: &[uuid::Uuid]) -> Result<HashMap<uuid::Uuid, uuid::Uuid>, sqlx::Error> {
+	let query: Vec<Delegate> = sqlx::query_as(
+		r#"select distinct on (userid) userid, delegate_id from user_delegations
+			where userid=any($1) and starts_at <= $2 and ends_at > $2
+			order by userid, created_at desc"#)
+		.bind(userids)
+		.bind(chrono::Utc::now())
+		.fetch_all(conn)
+		.await?;
+
+	return Ok(query.into_iter().map(|d| (d.userid, d.delegate_id)).collect());
+}
+
+fn is_valid(userid: uuid::Uuid, delegation: &CreateDelegation) -> bool {
+	return userid != delegation.delegate_id && delegation.starts_at < delegation.ends_at;
+}
+
+#[utoipa::path(post, path = "/delegations", tag = "delegations", request_body = CreateDelegation, responses(
+	(status = 201, description = "Approvals reaching the user in the period go to the delegate"),
+	(status = 400, description = "Delegating to oneself or the period ends before it starts")
+))]
+pub async fn create_delegation(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateDelegation>
+) -> Result<StatusCode, StatusCode> {
+	if !is_valid(user.userid, &payload) {
+		return Err(StatusCode::BAD_REQUEST);
+	}
+
+	let query = sqlx::query("insert into user_delegations (userid, delegate_id, starts_at, ends_at, created_at) values ($1, $2, $3, $4, $5)")
+		.bind(user.userid)
+		.bind(payload.delegate_id)
+		.bind(payload.starts_at)
+		.bind(payload.ends_at)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting delegation from {} to {}: {}", user.userid, payload.delegate_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} delegated approvals to {} from {} to {}", user.userid, payload.delegate_id, payload.starts_at, payload.ends_at), None);
+	return Ok(StatusCode::CREATED);
+}
+
+// the delegations from and to the user that have not ended
+#[utoipa::path(get, path = "/delegations", tag = "delegations", responses(
+	(status = 200, description = "Delegations of the user", body = Vec<Delegation>)
+))]
+pub async fn get_delegations(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<Delegation>>, StatusCode> {
+	let result: Result<Vec<Delegation>, _> = sqlx::query_as(
+		"select id, userid, delegate_id, starts_at, ends_at from user_delegations where (userid=$1 or delegate_id=$1) and ends_at > $2 order by starts_at")
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_all(&pool)
+		.await;
+
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error reading delegations of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(result.unwrap()));
+}
+
+#[cfg(test)]
+mod delegation_tests {
+	use super::{is_valid, CreateDelegation};
+
+	#[test]
+	fn delegations_need_another_user_and_a_period() {
+		let (userid, delegate_id) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let starts_at = chrono::Utc::now();
+		let delegation = |delegate_id, hours| CreateDelegation { delegate_id, starts_at, ends_at: starts_at + chrono::Duration::hours(hours) };
+
+		assert!(is_valid(userid, &delegation(delegate_id, 8)));
+		assert!(!is_valid(userid, &delegation(userid, 8)));
+		assert!(!is_valid(userid, &delegation(delegate_id, 0)));
+		assert!(!is_valid(userid, &delegation(delegate_id, -8)));
+	}
+}
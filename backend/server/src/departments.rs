@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageUsers};
+use crate::users;
+use utoipa::ToSchema;
+
+// parents are followed at most this far when looking for a manager, so a cycle cannot loop forever
+const MAX_DEPTH: i32 = 32;
+
+// who an approve node is sent to. the node arg is either a username or a rule resolved when the approval is created
+#[derive(Debug, PartialEq)]
+pub enum Approver<'a> {
+	User(&'a str),
+	// dept_manager_of(owner) or dept_manager_of(<username>)
+	DeptManagerOf(&'a str)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveDepartment {
+	pub name: String,
+	pub parent_id: Option<i32>,
+	pub manager_id: Option<uuid::Uuid>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Department {
+	pub id: i32,
+	pub name: String,
+	pub parent_id: Option<i32>,
+	pub manager_id: Option<uuid::Uuid>,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddMember {
+	pub userid: uuid::Uuid,
+	pub is_primary: Option<bool>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct DepartmentMember {
+	pub userid: uuid::Uuid,
+	pub username: String,
+	pub is_primary: bool
+}
+
+pub fn parse_approver(arg: &str) -> Approver<'_> {
+	let arg = arg.trim();
+	return match arg.strip_prefix("dept_manager_of(").and_then(|a| a.strip_suffix(')')) {
+		Some(subject) => Approver::DeptManagerOf(subject.trim()),
+		None => Approver::User(arg)
+	};
+}
+
+// manager of the primary department of `userid`. departments managed by the user themselves or without an
+// active manager are skipped in favour of their parents so nobody approves their own tickets
+pub async fn department_manager(conn: &mut PgConnection, userid: uuid::Uuid) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+	let query: Option<(uuid::Uuid,)> = sqlx::query_as(
+		r#"with recursive chain as (
+				select * from (
+					select d.id, d.parent_id, d.manager_id, 0 as depth from user_departments ud join departments d on ud.department_id=d.id
+					where ud.userid=$1 order by ud.is_primary desc, d.id limit 1
+				) first
+				union all
+				select d.id, d.parent_id, d.manager_id, c.depth + 1 from departments d join chain c on d.id=c.parent_id where c.depth<$2
+			)
+			select c.manager_id from chain c join users u on c.manager_id=u.userid
+			where u.active=true and c.manager_id<>$1 order by c.depth limit 1"#)
+		.bind(userid)
+		.bind(MAX_DEPTH)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(query.map(|q| q.0));
+}
+
+// the users the approval requests of `args` go to, by arg. None if the username does not exist or no manager was found.
+// the usernames are looked up together, managers are still found one rule at a time
+pub async fn resolve_approvers(conn: &mut PgConnection, args: &[&str], owner_id: uuid::Uuid) -> Result<HashMap<String, Option<uuid::Uuid>>, sqlx::Error> {
+	let approvers: Vec<Approver> = args.iter().map(|arg| parse_approver(arg)).collect();
+	let usernames: Vec<&str> = approvers.iter()
+		.filter_map(|approver| match approver {
+			Approver::DeptManagerOf("owner") => None,
+			Approver::DeptManagerOf(username) | Approver::User(username) => Some(*username)
+		})
+		.collect();
+	let userids = users::userids(&mut *conn, &usernames).await?;
+
+	let mut resolved = HashMap::new();
+	for (arg, approver) in args.iter().zip(approvers) {
+		if resolved.contains_key(*arg) {
+			continue;
+		}
+		let userid = match approver {
+			Approver::DeptManagerOf("owner") => department_manager(&mut *conn, owner_id).await?,
+			Approver::User(username) => userids.get(username).copied(),
+			Approver::DeptManagerOf(username) => match userids.get(username) {
+				Some(userid) => department_manager(&mut *conn, *userid).await?,
+				None => None
+			}
+		};
+		resolved.insert(arg.to_string(), userid);
+	}
+	return Ok(resolved);
+}
+
+// true if `department_id` is `parent_id` or one of its ancestors
+async fn creates_cycle(conn: &mut PgConnection, department_id: i32, parent_id: i32) -> Result<bool, sqlx::Error> {
+	let query = sqlx::query(
+		r#"with recursive ancestors as (
+				select id, parent_id, 0 as depth from departments where id=$2
+				union all
+				select d.id, d.parent_id, a.depth + 1 from departments d join ancestors a on d.id=a.parent_id where a.depth<$3
+			)
+			select 1 from ancestors where id=$1 limit 1"#)
+		.bind(department_id)
+		.bind(parent_id)
+		.bind(MAX_DEPTH)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(query.is_some());
+}
+
+#[utoipa::path(post, path = "/departments", tag = "departments", request_body = SaveDepartment, responses(
+	(status = 201, description = "Created", body = Department),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 409, description = "Department exists", body = String),
+	(status = 422, description = "Missing name or unknown parent or manager", body = String)
+))]
+pub async fn create_department(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SaveDepartment>
+) -> Result<(StatusCode, Json<Department>), (StatusCode, String)> {
+	let name = payload.name.trim();
+	if name.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "A name is required".to_string()));
+	}
+
+	let query: Result<Department, _> = sqlx::query_as(
+		r#"insert into departments (name, parent_id, manager_id, created_at) values ($1, $2, $3, $4)
+			returning id, name, parent_id, manager_id, created_at"#)
+		.bind(name)
+		.bind(payload.parent_id)
+		.bind(payload.manager_id)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err((StatusCode::CONFLICT, format!("Department {} already exists", name)));
+		}
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::UNPROCESSABLE_ENTITY, "Parent department or manager does not exist".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error inserting department {}: {}", name, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let department = query.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} created department {} ({})", auth.user.userid, department.name, department.id), None);
+	return Ok((StatusCode::CREATED, Json(department)));
+}
+
+#[utoipa::path(get, path = "/departments", tag = "departments", responses(
+	(status = 200, description = "Every department", body = Vec<Department>)
+))]
+pub async fn get_departments(
+	_user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<Department>>, StatusCode> {
+	let query: Result<Vec<Department>, _> = sqlx::query_as("select id, name, parent_id, manager_id, created_at from departments order by name")
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading departments: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// replaces the name, parent and manager of the department
+#[utoipa::path(put, path = "/departments/{id}", tag = "departments", params(("id" = i32, Path, description = "Department id")), request_body = SaveDepartment, responses(
+	(status = 200, description = "Updated", body = Department),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown department", body = String),
+	(status = 409, description = "Department exists", body = String),
+	(status = 422, description = "Missing name, unknown parent or manager, or a cycle of parents", body = String)
+))]
+pub async fn update_department(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<SaveDepartment>
+) -> Result<Json<Department>, (StatusCode, String)> {
+	let name = payload.name.trim();
+	if name.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "A name is required".to_string()));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	if let Some(parent_id) = payload.parent_id {
+		match creates_cycle(&mut tx, id, parent_id).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error reading parents of department {}: {}", parent_id, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			Ok(true) => return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Department {} cannot be a parent of itself", id))),
+			Ok(false) => {}
+		}
+	}
+
+	let query: Result<Option<Department>, _> = sqlx::query_as(
+		r#"update departments set name=$2, parent_id=$3, manager_id=$4 where id=$1
+			returning id, name, parent_id, manager_id, created_at"#)
+		.bind(id)
+		.bind(name)
+		.bind(payload.parent_id)
+		.bind(payload.manager_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err((StatusCode::CONFLICT, format!("Department {} already exists", name)));
+		}
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::UNPROCESSABLE_ENTITY, "Parent department or manager does not exist".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error updating department {}: {}", id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let department = query.unwrap().ok_or((StatusCode::NOT_FOUND, format!("Department {} does not exist", id)))?;
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting department {}: {}", id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} updated department {} ({})", auth.user.userid, department.name, id), None);
+	return Ok(Json(department));
+}
+
+// members are removed with the department, sub departments lose their parent
+#[utoipa::path(delete, path = "/departments/{id}", tag = "departments", params(("id" = i32, Path, description = "Department id")), responses(
+	(status = 200, description = "Deleted"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown department")
+))]
+pub async fn delete_department(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from departments where id=$1")
+		.bind(id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting department {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted department {}", auth.user.userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+#[utoipa::path(get, path = "/departments/{id}/members", tag = "departments", params(("id" = i32, Path, description = "Department id")), responses(
+	(status = 200, description = "Members of the department", body = Vec<DepartmentMember>)
+))]
+pub async fn get_members(
+	_user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Vec<DepartmentMember>>, StatusCode> {
+	let query: Result<Vec<DepartmentMember>, _> = sqlx::query_as(
+		r#"select u.userid, u.username, ud.is_primary from user_departments ud join users u on ud.userid=u.userid
+			where ud.department_id=$1 order by u.username"#)
+		.bind(id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading members of department {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// adds the user to the department or changes whether it is their primary department
+#[utoipa::path(post, path = "/departments/{id}/members", tag = "departments", params(("id" = i32, Path, description = "Department id")), request_body = AddMember, responses(
+	(status = 200, description = "Added"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown department or user", body = String)
+))]
+pub async fn add_member(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<AddMember>
+) -> Result<StatusCode, (StatusCode, String)> {
+	let is_primary = payload.is_primary.unwrap_or(false);
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	if is_primary {
+		let query = sqlx::query("update user_departments set is_primary=false where userid=$1 and department_id<>$2")
+			.bind(payload.userid)
+			.bind(id)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error updating primary department of {}: {}", payload.userid, e), None);
+			return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+		}
+	}
+
+	let query = sqlx::query(
+		r#"insert into user_departments (userid, department_id, is_primary) values ($1, $2, $3)
+			on conflict (userid, department_id) do update set is_primary=excluded.is_primary"#)
+		.bind(payload.userid)
+		.bind(id)
+		.bind(is_primary)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::NOT_FOUND, "Department or user does not exist".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error adding {} to department {}: {}", payload.userid, id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting member {} of department {}: {}", payload.userid, id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} added {} to department {} (primary: {})", auth.user.userid, payload.userid, id, is_primary), None);
+	return Ok(StatusCode::OK);
+}
+
+#[utoipa::path(delete, path = "/departments/{id}/members/{userid}", tag = "departments", params(("id" = i32, Path, description = "Department id"), ("userid" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Removed"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Not a member")
+))]
+pub async fn remove_member(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((id, userid)): extract::Path<(i32, uuid::Uuid)>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from user_departments where department_id=$1 and userid=$2")
+		.bind(id)
+		.bind(userid)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing {} from department {}: {}", userid, id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} removed {} from department {}", auth.user.userid, userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod departments_tests {
+	use super::{parse_approver, Approver};
+
+	#[test]
+	fn approver_args_are_parsed() {
+		assert_eq!(parse_approver("jdoe"), Approver::User("jdoe"));
+		assert_eq!(parse_approver(" dept_manager_of(owner) "), Approver::DeptManagerOf("owner"));
+		assert_eq!(parse_approver("dept_manager_of( jdoe )"), Approver::DeptManagerOf("jdoe"));
+		// not closed, treated as a username that will not be found
+		assert_eq!(parse_approver("dept_manager_of(owner"), Approver::User("dept_manager_of(owner"));
+	}
+}
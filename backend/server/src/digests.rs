@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use sqlx::{FromRow, PgPool};
+use crate::logger::{admin_logger, LogType};
+use crate::outbox;
+use crate::ws::{self, LiveEvent, LiveEventKind};
+use crate::jobs;
+
+// a summary lists at most this many messages, the rest are only counted
+const MAX_DIGEST_LINES: usize = 10;
+
+fn digest_interval() -> Duration {
+	let secs = std::env::var("DIGEST_INTERVAL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(3600);
+	return Duration::from_secs(secs);
+}
+
+#[derive(FromRow)]
+struct HeldNotifications {
+	userid: uuid::Uuid,
+	messages: Vec<String>
+}
+
+pub fn is_urgent(kind: &LiveEventKind) -> bool {
+	return *kind == LiveEventKind::ApproveRequest;
+}
+
+pub fn digest_message(messages: &[String]) -> String {
+	let mut result = match messages.len() {
+		1 => "1 new notification:".to_string(),
+		n => format!("{} new notifications:", n)
+	};
+	for message in messages.iter().take(MAX_DIGEST_LINES) {
+		result.push_str("\n- ");
+		result.push_str(message);
+	}
+	if messages.len() > MAX_DIGEST_LINES {
+		result.push_str(&format!("\n... and {} more", messages.len() - MAX_DIGEST_LINES));
+	}
+	return result;
+}
+
+// like ws::publish but holds back the non-urgent events of users in digest mode, they get them with the next digest.
+// if the users can not be read everything is sent right away rather than dropped
+pub async fn publish(pool: &PgPool, events: Vec<(uuid::Uuid, LiveEvent)>) -> usize {
+	let recipients = events.iter()
+		.filter(|(_, e)| !is_urgent(&e.kind))
+		.map(|(userid, _)| *userid)
+		.collect::<HashSet<_>>()
+		.into_iter()
+		.collect::<Vec<_>>();
+	if recipients.is_empty() {
+		return ws::publish(events);
+	}
+
+	let query: Result<Vec<(uuid::Uuid,)>, _> = sqlx::query_as("select userid from users where userid=any($1) and notification_digest=true")
+		.bind(&recipients)
+		.fetch_all(pool)
+		.await;
+	let digest_users = match query {
+		Ok(rows) => rows.into_iter().map(|r| r.0).collect::<HashSet<_>>(),
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error reading digest users, sending events right away: {}", e), None);
+			HashSet::new()
+		}
+	};
+
+	let events = events.into_iter()
+		.filter(|(userid, e)| is_urgent(&e.kind) || !digest_users.contains(userid))
+		.collect();
+	return ws::publish(events);
+}
+
+// replaces the held notifications of every digest user with one summary. the held rows stay readable through
+// /notifications, they are only marked as delivered so the notifier skips them. returns the number of summaries
+pub async fn send_digests(pool: &PgPool) -> Result<u64, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	let held: Vec<HeldNotifications> = sqlx::query_as(
+		r#"with held as (
+			update notifications n set delivered_at=$1
+			from users u
+			where n.userid=u.userid and u.notification_digest=true
+				and n.delivered_at is null and n.urgent=false and n.digest_count is null
+			returning n.userid, coalesce(n.message, '') as message, n.created_at, n.id
+		)
+		select userid, array_agg(message order by created_at, id) as messages from held group by userid"#)
+		.bind(now)
+		.fetch_all(&mut *tx)
+		.await?;
+
+	for digest in &held {
+		sqlx::query("insert into notifications (userid, message, created_at, digest_count) values ($1, $2, $3, $4)")
+			.bind(digest.userid)
+			.bind(digest_message(&digest.messages))
+			.bind(now)
+			.bind(digest.messages.len() as i32)
+			.execute(&mut *tx)
+			.await?;
+	}
+	if !held.is_empty() {
+		outbox::enqueue(&mut tx).await?;
+	}
+
+	tx.commit().await?;
+	return Ok(held.len() as u64);
+}
+
+pub async fn run_digests(pool: PgPool) {
+	jobs::run_periodic(pool, "digests", digest_interval(), |pool| async move {
+		match send_digests(&pool).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Failed to send notification digests: {}", e), None);
+				return Err(e.to_string());
+			}
+			Ok(0) => {}
+			Ok(n) => {
+				admin_logger(LogType::NotificationSuccess, &format!("Sent {} notification digests", n), None);
+				outbox::flush(&pool).await;
+			}
+		}
+		return Ok(());
+	}).await;
+}
+
+#[cfg(test)]
+mod digests_tests {
+	use super::{digest_message, is_urgent, MAX_DIGEST_LINES};
+	use crate::ws::LiveEventKind;
+
+	#[test]
+	fn digest_messages() {
+		assert_eq!(digest_message(&["Ticket 3 closed".to_string()]), "1 new notification:\n- Ticket 3 closed");
+
+		let messages = (0..MAX_DIGEST_LINES + 2).map(|i| format!("Ticket {} closed", i)).collect::<Vec<_>>();
+		let message = digest_message(&messages);
+		assert!(message.starts_with("12 new notifications:\n- Ticket 0 closed"));
+		assert!(!message.contains("Ticket 10 closed"));
+		assert!(message.ends_with("... and 2 more"));
+	}
+
+	#[test]
+	fn approvals_are_never_held() {
+		assert!(is_urgent(&LiveEventKind::ApproveRequest));
+		assert!(!is_urgent(&LiveEventKind::Notify));
+		assert!(!is_urgent(&LiveEventKind::Completion));
+	}
+}
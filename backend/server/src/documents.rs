@@ -0,0 +1,851 @@
+use axum::{body::Bytes, extract, http::{header, HeaderMap, StatusCode}, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::process::Step;
+use crate::rbac;
+use crate::schema::{FieldError, FieldErrors, field_error, internal_error, status_error};
+use crate::ticket::{self, Event};
+use utoipa::{IntoParams, ToSchema};
+
+// the process whose tickets approve documents
+fn process_id() -> String {
+	return std::env::var("DOCUMENT_APPROVAL_PROCESS").unwrap_or("document_approval".to_string());
+}
+
+// largest version that can be checked in
+pub fn max_bytes() -> usize {
+	return std::env::var("DOCUMENT_MAX_BYTES")
+		.ok()
+		.and_then(|s| s.parse::<usize>().ok())
+		.unwrap_or(10 * 1024 * 1024);
+}
+
+// what a user can do with a document. owners and manage_documents holders manage it, the acl grants reading or
+// writing, the people working on a ticket it is attached to can read it
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Access {None, Read, Write, Manage}
+
+#[derive(FromRow)]
+pub struct Grants {
+	pub acl_read: bool,
+	pub acl_write: bool,
+	pub on_ticket: bool
+}
+
+impl Grants {
+	pub fn access(&self, owner: bool, manager: bool) -> Access {
+		if owner || manager {
+			return Access::Manage;
+		}
+		if self.acl_write {
+			return Access::Write;
+		}
+		if self.acl_read || self.on_ticket {
+			return Access::Read;
+		}
+		return Access::None;
+	}
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateDocument {
+	pub title: String,
+	pub doc_type: String,
+	#[serde(default)]
+	pub acl: Vec<AclEntry>
+}
+
+#[derive(Deserialize, Serialize, FromRow, Clone, ToSchema)]
+pub struct AclEntry {
+	pub userid: Option<uuid::Uuid>,
+	#[sqlx(rename = "role_")]
+	pub role: Option<String>,
+	#[serde(default)]
+	pub can_write: bool
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Document {
+	pub id: i32,
+	pub title: String,
+	pub doc_type: String,
+	pub owner_id: uuid::Uuid,
+	pub status: String,
+	pub current_version: i32,
+	pub checked_out_by: Option<uuid::Uuid>,
+	pub checked_out_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub ticket_id: Option<i32>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+// a version without its content
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Version {
+	pub version: i32,
+	pub filename: String,
+	pub content_type: String,
+	pub size_bytes: i32,
+	pub sha256: String,
+	pub comment: Option<String>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DocumentDetail {
+	#[serde(flatten)]
+	pub document: Document,
+	pub versions: Vec<Version>,
+	pub acl: Vec<AclEntry>,
+	// ids of the tickets it is attached to
+	pub tickets: Vec<i32>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DocumentsQuery {
+	pub doc_type: Option<String>,
+	// every document instead of the readable ones, needs manage_documents
+	pub all: Option<bool>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CheckIn {
+	pub filename: String,
+	pub comment: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AttachDocument {
+	pub document_id: i32
+}
+
+// names are sent back in content-disposition headers
+pub fn check_filename(filename: &str) -> Option<&'static str> {
+	if filename.trim().is_empty() {
+		return Some("A filename is required");
+	}
+	if filename.len() > 255 {
+		return Some("At most 255 bytes");
+	}
+	if filename.chars().any(|c| c.is_control() || matches!(c, '/' | '\\' | '"')) {
+		return Some("Cannot contain slashes, quotes or control characters");
+	}
+	return None;
+}
+
+pub fn check_acl(acl: &[AclEntry]) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	for (i, entry) in acl.iter().enumerate() {
+		if entry.userid.is_some() == entry.role.is_some() {
+			errors.push(FieldError { field: format!("/acl/{}", i), message: "Expected either a userid or a role".to_string() });
+		}
+	}
+	return errors;
+}
+
+// document requirements can only be put on nodes that users or services complete
+pub fn document_step_errors(steps: &[Step]) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	for (i, step) in steps.iter().enumerate() {
+		let Some(doc_type) = &step.requires_document else {
+			continue;
+		};
+		let field = format!("/steps/{}/requires_document", i);
+		if !matches!(step.event, Event::Approve | Event::BlockingTask) {
+			errors.push(FieldError { field, message: "Only approve and blocking_task nodes can require a document".to_string() });
+		} else if doc_type.trim().is_empty() {
+			errors.push(FieldError { field, message: "A document type is required".to_string() });
+		}
+	}
+	return errors;
+}
+
+// the status a document in `current` moves to when its approval ticket asks for `target`
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("pending_approval", "approved" | "closed") => Some("approved"),
+		("pending_approval", "rejected") => Some("rejected"),
+		("pending_approval", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+// whether an approved document of the type is attached to the ticket. checked before a node with requires_document completes
+pub async fn has_approved_document(conn: &mut PgConnection, ticket_id: i32, doc_type: &str) -> Result<bool, sqlx::Error> {
+	let found: (bool,) = sqlx::query_as(
+		r#"select exists(select 1 from document_attachments a join documents d on a.document_id=d.id
+			where a.ticket_id=$1 and d.doc_type=$2 and d.status='approved')"#)
+		.bind(ticket_id)
+		.bind(doc_type)
+		.fetch_one(conn)
+		.await?;
+	return Ok(found.0);
+}
+
+// reads the document if the user has at least `needed` access to it. documents the user cannot read do not exist for them
+async fn authorize(conn: &mut PgConnection, id: i32, user: &AuthUser, needed: Access, lock: bool) -> Result<Document, StatusCode> {
+	let sql = if lock { "select * from documents where id=$1 for update" } else { "select * from documents where id=$1" };
+	let document: Result<Option<Document>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = document {
+		admin_logger(LogType::Error, &format!("Error reading document {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let document = document.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	let grants: Result<Grants, _> = sqlx::query_as(
+		r#"select
+				exists(select 1 from document_acl a where a.document_id=$1
+					and (a.userid=$2 or a.role_ in (select role_ from user_roles where userid=$2))) as acl_read,
+				exists(select 1 from document_acl a where a.document_id=$1 and a.can_write
+					and (a.userid=$2 or a.role_ in (select role_ from user_roles where userid=$2))) as acl_write,
+				exists(select 1 from document_attachments da where da.document_id=$1
+					and (exists(select 1 from tickets t where t.id=da.ticket_id and t.owner_id=$2)
+						or exists(select 1 from user_active_tickets u where u.ticketid=da.ticket_id and u.userid=$2))) as on_ticket"#)
+		.bind(id)
+		.bind(user.userid)
+		.fetch_one(&mut *conn)
+		.await;
+	let owner = document.owner_id == user.userid;
+	let access = match grants {
+		Ok(grants) if owner => Ok(grants.access(true, false)),
+		Ok(grants) => rbac::has_permission(&mut *conn, user.userid, "manage_documents").await.map(|manager| grants.access(false, manager)),
+		Err(e) => Err(e)
+	};
+	if let Err(e) = access {
+		admin_logger(LogType::Error, &format!("Error checking access of {} to document {}: {}", user.userid, id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let access = access.unwrap();
+
+	if access == Access::None {
+		return Err(StatusCode::NOT_FOUND);
+	}
+	if access < needed {
+		return Err(StatusCode::FORBIDDEN);
+	}
+	return Ok(document);
+}
+
+// the owner and the people currently holding a node of the ticket can attach documents to it,
+// not those who held one before
+async fn works_on_ticket(conn: &mut PgConnection, ticket_id: i32, user: &AuthUser) -> Result<(), StatusCode> {
+	let works: Result<(bool,), _> = sqlx::query_as(
+		r#"select exists(select 1 from tickets where id=$1 and owner_id=$2)
+			or exists(select 1 from user_active_tickets where ticketid=$1 and userid=$2 and active=true)"#)
+		.bind(ticket_id)
+		.bind(user.userid)
+		.fetch_one(&mut *conn)
+		.await;
+	if let Err(e) = works {
+		admin_logger(LogType::Error, &format!("Error checking access of {} to ticket {}: {}", user.userid, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if !works.unwrap().0 {
+		return Err(StatusCode::FORBIDDEN);
+	}
+	return Ok(());
+}
+
+async fn save_acl(conn: &mut PgConnection, id: i32, acl: &[AclEntry]) -> Result<(), sqlx::Error> {
+	sqlx::query("delete from document_acl where document_id=$1")
+		.bind(id)
+		.execute(&mut *conn)
+		.await?;
+	for entry in acl {
+		sqlx::query("insert into document_acl (document_id, userid, role_, can_write) values ($1, $2, $3, $4)")
+			.bind(id)
+			.bind(entry.userid)
+			.bind(entry.role.as_deref().map(str::trim))
+			.bind(entry.can_write)
+			.execute(&mut *conn)
+			.await?;
+	}
+	return Ok(());
+}
+
+fn unknown_principal(e: &sqlx::Error) -> bool {
+	return e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false);
+}
+
+// saves the document checked out by its creator, who checks in the first version
+#[utoipa::path(post, path = "/documents", tag = "documents", request_body = CreateDocument, responses(
+	(status = 201, description = "Created without a version", body = Document),
+	(status = 422, description = "Invalid title, type or acl", body = FieldErrors)
+))]
+pub async fn create_document(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateDocument>
+) -> Result<(StatusCode, Json<Document>), (StatusCode, Json<FieldErrors>)> {
+	let mut errors = check_acl(&payload.acl);
+	if payload.title.trim().is_empty() {
+		errors.push(FieldError { field: "/title".to_string(), message: "A title is required".to_string() });
+	}
+	if payload.doc_type.trim().is_empty() {
+		errors.push(FieldError { field: "/doc_type".to_string(), message: "A document type is required".to_string() });
+	}
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let now = chrono::Utc::now();
+	let document: Result<Document, _> = sqlx::query_as(
+		r#"insert into documents (title, doc_type, owner_id, checked_out_by, checked_out_at, created_at, updated_at)
+			values ($1, $2, $3, $3, $4, $4, $4) returning *"#)
+		.bind(payload.title.trim())
+		.bind(payload.doc_type.trim())
+		.bind(user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = document {
+		admin_logger(LogType::Error, &format!("Error saving document of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let document = document.unwrap();
+
+	if let Err(e) = save_acl(&mut tx, document.id, &payload.acl).await {
+		if unknown_principal(&e) {
+			return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/acl", "Unknown user or role".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error saving the acl of document {}: {}", document.id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting document {}: {}", document.id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} created document {}", user.userid, document.id), None);
+	return Ok((StatusCode::CREATED, Json(document)));
+}
+
+// the documents the user owns or is granted by the acl
+#[utoipa::path(get, path = "/documents", tag = "documents", params(DocumentsQuery), responses(
+	(status = 200, description = "Readable documents, or every document with all", body = Vec<Document>),
+	(status = 403, description = "all needs the manage_documents permission")
+))]
+pub async fn get_documents(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<DocumentsQuery>
+) -> Result<Json<Vec<Document>>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let all = query.all.unwrap_or(false);
+	if all {
+		match rbac::has_permission(&mut conn, user.userid, "manage_documents").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN),
+			Ok(true) => {}
+		}
+	}
+
+	let documents: Result<Vec<Document>, _> = sqlx::query_as(
+		r#"select d.* from documents d where ($1::varchar is null or d.doc_type=$1)
+				and ($3 or d.owner_id=$2 or exists(select 1 from document_acl a where a.document_id=d.id
+					and (a.userid=$2 or a.role_ in (select role_ from user_roles where userid=$2))))
+			order by d.updated_at desc, d.id desc"#)
+		.bind(&query.doc_type)
+		.bind(user.userid)
+		.bind(all)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = documents {
+		admin_logger(LogType::Error, &format!("Error reading documents of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(documents.unwrap()));
+}
+
+#[utoipa::path(get, path = "/documents/{id}", tag = "documents", params(("id" = i32, Path, description = "Document id")), responses(
+	(status = 200, description = "The document with its versions, acl and tickets", body = DocumentDetail),
+	(status = 404, description = "Unknown document or no access")
+))]
+pub async fn get_document(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<DocumentDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let document = authorize(&mut conn, id, &user, Access::Read, false).await?;
+
+	let versions: Result<Vec<Version>, _> = sqlx::query_as(
+		r#"select version, filename, content_type, size_bytes, sha256, comment, created_by, created_at from document_versions
+			where document_id=$1 order by version desc"#)
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	let acl: Result<Vec<AclEntry>, _> = sqlx::query_as("select userid, role_, can_write from document_acl where document_id=$1 order by id")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	let tickets: Result<Vec<(i32,)>, _> = sqlx::query_as("select ticket_id from document_attachments where document_id=$1 order by attached_at")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	match (versions, acl, tickets) {
+		(Ok(versions), Ok(acl), Ok(tickets)) => {
+			let tickets = tickets.into_iter().map(|(t,)| t).collect();
+			return Ok(Json(DocumentDetail { document, versions, acl, tickets }));
+		}
+		(Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+			admin_logger(LogType::Error, &format!("Error reading document {}: {}", id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+	}
+}
+
+#[utoipa::path(get, path = "/documents/{id}/versions/{version}", tag = "documents", params(("id" = i32, Path, description = "Document id"), ("version" = i32, Path, description = "Version number")), responses(
+	(status = 200, description = "The content of the version, with the content type it was checked in with", body = Vec<u8>, content_type = "application/octet-stream"),
+	(status = 404, description = "Unknown document or version, or no access")
+))]
+pub async fn get_version(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((id, version)): extract::Path<(i32, i32)>
+) -> Result<impl IntoResponse, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	authorize(&mut conn, id, &user, Access::Read, false).await?;
+
+	let content: Result<Option<(String, String, Vec<u8>)>, _> = sqlx::query_as(
+		"select filename, content_type, content from document_versions where document_id=$1 and version=$2")
+		.bind(id)
+		.bind(version)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = content {
+		admin_logger(LogType::Error, &format!("Error reading version {} of document {}: {}", version, id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (filename, content_type, content) = content.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+	return Ok((
+		[
+			(header::CONTENT_TYPE, content_type),
+			(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+		],
+		content
+	));
+}
+
+// locks the document for the user until they check a version in or undo the check-out
+#[utoipa::path(post, path = "/documents/{id}/checkout", tag = "documents", params(("id" = i32, Path, description = "Document id")), responses(
+	(status = 200, description = "Checked out to the user", body = Document),
+	(status = 403, description = "Needs write access", body = FieldErrors),
+	(status = 404, description = "Unknown document or no access", body = FieldErrors),
+	(status = 409, description = "Checked out by someone else or being approved", body = FieldErrors)
+))]
+pub async fn check_out(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Document>, (StatusCode, Json<FieldErrors>)> {
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let document = authorize(&mut tx, id, &user, Access::Write, true).await.map_err(status_error)?;
+	if document.status == "pending_approval" {
+		return Err(field_error(StatusCode::CONFLICT, "/status", "The document is being approved, cancel its ticket to change it".to_string()));
+	}
+	match document.checked_out_by {
+		Some(holder) if holder == user.userid => return Ok(Json(document)),
+		Some(_) => return Err(field_error(StatusCode::CONFLICT, "/checked_out_by", "The document is checked out by someone else".to_string())),
+		None => {}
+	}
+
+	let document: Result<Document, _> = sqlx::query_as("update documents set checked_out_by=$2, checked_out_at=$3, updated_at=$3 where id=$1 returning *")
+		.bind(id)
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = document {
+		admin_logger(LogType::Error, &format!("Error checking out document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the check-out of document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	return Ok(Json(document.unwrap()));
+}
+
+// for the user holding the check-out and the managers of the document, e.g. when the holder left
+#[utoipa::path(delete, path = "/documents/{id}/checkout", tag = "documents", params(("id" = i32, Path, description = "Document id")), responses(
+	(status = 204, description = "No longer checked out"),
+	(status = 403, description = "Only the user holding the check out or with manage access"),
+	(status = 404, description = "Unknown document or no access")
+))]
+pub async fn undo_check_out(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let document = authorize(&mut tx, id, &user, Access::Read, true).await?;
+	if document.checked_out_by.is_none() {
+		return Ok(StatusCode::NO_CONTENT);
+	}
+	if document.checked_out_by != Some(user.userid) {
+		authorize(&mut tx, id, &user, Access::Manage, false).await?;
+	}
+
+	let query = sqlx::query("update documents set checked_out_by=null, checked_out_at=null, updated_at=$2 where id=$1")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error undoing the check-out of document {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the check-out of document {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// the body is the content of the new version. checking in releases the check-out, the new version has to be approved again
+#[utoipa::path(post, path = "/documents/{id}/versions", tag = "documents", params(("id" = i32, Path, description = "Document id"), CheckIn), request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "The file, stored with the content type of the request"), responses(
+	(status = 201, description = "Stored as the next version, the check out ends", body = Version),
+	(status = 403, description = "Needs write access", body = FieldErrors),
+	(status = 404, description = "Unknown document or no access", body = FieldErrors),
+	(status = 409, description = "Not checked out by the user", body = FieldErrors),
+	(status = 413, description = "Larger than the upload limit", body = FieldErrors),
+	(status = 422, description = "Invalid filename", body = FieldErrors)
+))]
+pub async fn check_in(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	extract::Query(query): extract::Query<CheckIn>,
+	headers: HeaderMap,
+	body: Bytes
+) -> Result<(StatusCode, Json<Version>), (StatusCode, Json<FieldErrors>)> {
+	let filename = query.filename.trim();
+	if let Some(message) = check_filename(filename) {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/filename", message.to_string()));
+	}
+	if body.is_empty() {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/content", "The document is empty".to_string()));
+	}
+	if body.len() > max_bytes() {
+		return Err(status_error(StatusCode::PAYLOAD_TOO_LARGE));
+	}
+	let content_type = headers.get(header::CONTENT_TYPE)
+		.and_then(|h| h.to_str().ok())
+		.unwrap_or("application/octet-stream");
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let document = authorize(&mut tx, id, &user, Access::Write, true).await.map_err(status_error)?;
+	if document.checked_out_by != Some(user.userid) {
+		return Err(field_error(StatusCode::CONFLICT, "/checked_out_by", "Check the document out before checking a version in".to_string()));
+	}
+
+	let now = chrono::Utc::now();
+	let version: Result<Version, _> = sqlx::query_as(
+		r#"insert into document_versions (document_id, version, filename, content_type, size_bytes, sha256, content, comment, created_by, created_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+			returning version, filename, content_type, size_bytes, sha256, comment, created_by, created_at"#)
+		.bind(id)
+		.bind(document.current_version + 1)
+		.bind(filename)
+		.bind(content_type)
+		.bind(body.len() as i32)
+		.bind(format!("{:x}", Sha256::digest(&body)))
+		.bind(body.as_ref())
+		.bind(&query.comment)
+		.bind(user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = version {
+		admin_logger(LogType::Error, &format!("Error saving a version of document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let version = version.unwrap();
+
+	let query = sqlx::query(
+		"update documents set current_version=$2, status='draft', checked_out_by=null, checked_out_at=null, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(version.version)
+		.bind(now)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error checking in document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting version {} of document {}: {}", version.version, id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} checked in version {} of document {}", user.userid, version.version, id), None);
+	return Ok((StatusCode::CREATED, Json(version)));
+}
+
+// starts the approval of the current version. it cannot be checked out until the ticket ends
+#[utoipa::path(post, path = "/documents/{id}/submit", tag = "documents", params(("id" = i32, Path, description = "Document id")), responses(
+	(status = 200, description = "Submitted with its approval ticket", body = Document),
+	(status = 403, description = "Needs write access", body = FieldErrors),
+	(status = 404, description = "Unknown document or no access", body = FieldErrors),
+	(status = 409, description = "Not a draft or checked out", body = FieldErrors),
+	(status = 422, description = "The document has no version", body = FieldErrors)
+))]
+pub async fn submit_document(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Document>, (StatusCode, Json<FieldErrors>)> {
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let document = authorize(&mut tx, id, &user, Access::Write, true).await.map_err(status_error)?;
+	if document.current_version == 0 {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/current_version", "Check a version in first".to_string()));
+	}
+	if document.checked_out_by.is_some() {
+		return Err(field_error(StatusCode::CONFLICT, "/checked_out_by", "The document is checked out".to_string()));
+	}
+	if !matches!(document.status.as_str(), "draft" | "rejected" | "cancelled") {
+		return Err(field_error(StatusCode::CONFLICT, "/status", format!("The document is {}", document.status)));
+	}
+
+	let query = sqlx::query("update documents set status='pending_approval', updated_at=$2 where id=$1")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error submitting document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	// the approvers download the version named in the ticket
+	let mut data = Map::new();
+	data.insert("document_id".to_string(), json!(id));
+	data.insert("title".to_string(), json!(document.title));
+	data.insert("doc_type".to_string(), json!(document.doc_type));
+	data.insert("version".to_string(), json!(document.current_version));
+	let new = NewTicket { process_id: process_id(), data, tag: "document", due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "documents", id }, new)
+		.await
+		.map_err(status_error)?;
+
+	// node 0 may already have moved it
+	let document = authorize(&mut tx, id, &user, Access::Write, false).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the approval of document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("User {} submitted version {} of document {} with ticket {}", user.userid, document.current_version, id, ticket.id), ticket.log_id);
+	return Ok(Json(document));
+}
+
+// replaces who besides the owner can read or change the document
+#[utoipa::path(put, path = "/documents/{id}/acl", tag = "documents", params(("id" = i32, Path, description = "Document id")), request_body = Vec<AclEntry>, responses(
+	(status = 200, description = "The acl now", body = Vec<AclEntry>),
+	(status = 403, description = "Only the owner and users with manage_documents", body = FieldErrors),
+	(status = 404, description = "Unknown document or no access", body = FieldErrors),
+	(status = 422, description = "Invalid entries or unknown users or roles", body = FieldErrors)
+))]
+pub async fn set_acl(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(acl): Json<Vec<AclEntry>>
+) -> Result<Json<Vec<AclEntry>>, (StatusCode, Json<FieldErrors>)> {
+	let errors = check_acl(&acl);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	authorize(&mut tx, id, &user, Access::Manage, true).await.map_err(status_error)?;
+	if let Err(e) = save_acl(&mut tx, id, &acl).await {
+		if unknown_principal(&e) {
+			return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/acl", "Unknown user or role".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error saving the acl of document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the acl of document {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} changed the acl of document {}", user.userid, id), None);
+	return Ok(Json(acl));
+}
+
+#[utoipa::path(get, path = "/ticket/{id}/documents", tag = "documents", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 200, description = "Documents attached to the ticket", body = Vec<Document>),
+	(status = 403, description = "Not working on the ticket")
+))]
+pub async fn get_ticket_documents(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>
+) -> Result<Json<Vec<Document>>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	works_on_ticket(&mut conn, ticket_id, &user).await?;
+
+	let documents: Result<Vec<Document>, _> = sqlx::query_as(
+		"select d.* from documents d join document_attachments a on a.document_id=d.id where a.ticket_id=$1 order by a.attached_at")
+		.bind(ticket_id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = documents {
+		admin_logger(LogType::Error, &format!("Error reading the documents of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(documents.unwrap()));
+}
+
+// attaching makes the document readable for everyone working on the ticket
+#[utoipa::path(post, path = "/ticket/{id}/documents", tag = "documents", params(("id" = i32, Path, description = "Ticket id")), request_body = AttachDocument, responses(
+	(status = 201, description = "Attached"),
+	(status = 403, description = "Not working on the ticket or no access to the document"),
+	(status = 404, description = "Unknown document or no access")
+))]
+pub async fn attach_document(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>,
+	Json(payload): Json<AttachDocument>
+) -> Result<StatusCode, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	works_on_ticket(&mut conn, ticket_id, &user).await?;
+	authorize(&mut conn, payload.document_id, &user, Access::Read, false).await?;
+
+	let query = sqlx::query(
+		r#"insert into document_attachments (document_id, ticket_id, attached_by, attached_at) values ($1, $2, $3, $4)
+			on conflict (document_id, ticket_id) do nothing"#)
+		.bind(payload.document_id)
+		.bind(ticket_id)
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error attaching document {} to ticket {}: {}", payload.document_id, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} attached document {} to ticket {}", user.userid, payload.document_id, ticket_id), None);
+	return Ok(StatusCode::CREATED);
+}
+
+#[utoipa::path(delete, path = "/ticket/{id}/documents/{document_id}", tag = "documents", params(("id" = i32, Path, description = "Ticket id"), ("document_id" = i32, Path, description = "Document id")), responses(
+	(status = 204, description = "Detached"),
+	(status = 403, description = "Not working on the ticket"),
+	(status = 404, description = "The document is not attached")
+))]
+pub async fn detach_document(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((ticket_id, document_id)): extract::Path<(i32, i32)>
+) -> Result<StatusCode, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	works_on_ticket(&mut conn, ticket_id, &user).await?;
+
+	let query = sqlx::query("delete from document_attachments where document_id=$1 and ticket_id=$2")
+		.bind(document_id)
+		.bind(ticket_id)
+		.execute(&mut *conn)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error detaching document {} from ticket {}: {}", document_id, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} detached document {} from ticket {}", user.userid, document_id, ticket_id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// moves the document of the approval ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let document: Option<(i32, String)> = sqlx::query_as("select id, status from documents where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = document else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update documents set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Document {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod documents_tests {
+	use serde_json::json;
+	use crate::process::Step;
+	use super::{check_acl, check_filename, document_step_errors, Access, AclEntry, Grants};
+
+	#[test]
+	fn access_comes_from_ownership_acl_and_tickets() {
+		let grants = |acl_read, acl_write, on_ticket| Grants { acl_read, acl_write, on_ticket };
+		assert_eq!(grants(false, false, false).access(true, false), Access::Manage);
+		assert_eq!(grants(false, false, false).access(false, true), Access::Manage);
+		assert_eq!(grants(true, true, false).access(false, false), Access::Write);
+		assert_eq!(grants(false, false, true).access(false, false), Access::Read);
+		assert_eq!(grants(false, false, false).access(false, false), Access::None);
+		assert!(Access::Write > Access::Read);
+
+		assert_eq!(check_filename("contract v2.pdf"), None);
+		assert!(check_filename("../contract.pdf").is_some());
+		assert!(check_filename("a\"b.pdf").is_some());
+		let acl = vec![
+			AclEntry { userid: None, role: Some("legal".to_string()), can_write: true },
+			AclEntry { userid: None, role: None, can_write: false }
+		];
+		let fields: Vec<String> = check_acl(&acl).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/acl/1"]);
+	}
+
+	#[test]
+	fn only_completable_nodes_require_documents() {
+		let steps: Vec<Step> = serde_json::from_value(json!([
+			{ "event": "initiate", "args": null, "next": [1], "required": [], "callbacks": null, "requires_document": "contract" },
+			{ "event": "approve", "args": ["legal"], "next": [2], "required": [0], "callbacks": null, "requires_document": "contract" },
+			{ "event": "blocking_task", "args": null, "next": [3], "required": [1], "callbacks": null, "requires_document": " " },
+			{ "event": "complete", "args": null, "next": [], "required": [2], "callbacks": null }
+		])).unwrap();
+		let fields: Vec<String> = document_step_errors(&steps).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/steps/0/requires_document", "/steps/2/requires_document"]);
+	}
+}
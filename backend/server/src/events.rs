@@ -0,0 +1,287 @@
+use std::time::Duration;
+use async_nats::jetstream;
+use axum::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection, PgPool};
+use tokio::sync::OnceCell;
+use crate::db_types::Ticket;
+use crate::logger::{admin_logger, LogType};
+use crate::outbox::backoff;
+use crate::shutdown;
+
+const DEFAULT_NATS_SUBJECT: &str = "erp.events";
+const DEFAULT_NATS_STREAM: &str = "ERP_EVENTS";
+// events published in one go, in the order they were recorded
+const BATCH_SIZE: i64 = 100;
+
+fn poll_interval() -> Duration {
+	let secs = std::env::var("EVENT_POLL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5);
+	return Duration::from_secs(secs);
+}
+
+// where the event stream goes. unset turns the stream off and nothing is recorded
+fn publisher_kind() -> Option<String> {
+	return std::env::var("EVENT_PUBLISHER").ok().filter(|s| !s.trim().is_empty());
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineEvent {NodeCompleted, ApprovalRequested, TicketClosed, TicketRejected, TicketCancelled}
+
+impl EngineEvent {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			EngineEvent::NodeCompleted => "node_completed",
+			EngineEvent::ApprovalRequested => "approval_requested",
+			EngineEvent::TicketClosed => "ticket_closed",
+			EngineEvent::TicketRejected => "ticket_rejected",
+			EngineEvent::TicketCancelled => "ticket_cancelled"
+		};
+	}
+}
+
+// a transition seen while executing a ticket. saved with record in the ticket transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowEvent {
+	pub kind: EngineEvent,
+	pub ticket_id: i32,
+	pub process_id: String,
+	pub node: Option<i32>,
+	pub detail: Value
+}
+
+#[derive(FromRow)]
+pub struct StoredEvent {
+	pub id: i64,
+	pub kind: String,
+	pub payload: Value,
+	pub attempts: i32
+}
+
+impl WorkflowEvent {
+	pub fn new(kind: EngineEvent, ticket: &Ticket, node: Option<i32>, detail: Value) -> WorkflowEvent {
+		return WorkflowEvent { kind, ticket_id: ticket.id, process_id: ticket.process_id.clone(), node, detail };
+	}
+
+	fn payload(&self, now: chrono::DateTime<chrono::Utc>) -> Value {
+		return serde_json::json!({
+			"kind": self.kind,
+			"occurred_at": now,
+			"ticket_id": self.ticket_id,
+			"process_id": self.process_id,
+			"node": self.node,
+			"detail": self.detail
+		});
+	}
+}
+
+// the nodes whose bit is set in `after` but not in `before`, lowest first
+pub fn completed_nodes(before: i32, after: i32) -> Vec<i32> {
+	let added = after & !before;
+	return (0..32).filter(|node| added & (1 << node) != 0).collect();
+}
+
+// what reaches the stream. the id is the row id, the same event may be published again after a lost acknowledgement
+pub fn message(event: &StoredEvent) -> Vec<u8> {
+	let mut payload = event.payload.clone();
+	payload["id"] = Value::from(event.id);
+	return serde_json::to_vec(&payload).unwrap();
+}
+
+// how events reach the consumers of the stream. set with EVENT_PUBLISHER
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+	fn target(&self) -> String;
+	// returns once every event was stored by the other side
+	async fn publish(&self, events: &[StoredEvent]) -> Result<(), String>;
+}
+
+// a jetstream stream keeping every event, read by any number of consumers. events of a kind go to <subject>.<kind>
+pub struct NatsPublisher {
+	jetstream: jetstream::Context,
+	subject: String
+}
+
+impl NatsPublisher {
+	pub async fn connect(url: &str) -> Result<NatsPublisher, String> {
+		let subject = std::env::var("EVENT_NATS_SUBJECT").unwrap_or(DEFAULT_NATS_SUBJECT.to_string());
+		let stream = std::env::var("EVENT_NATS_STREAM").unwrap_or(DEFAULT_NATS_STREAM.to_string());
+
+		let client = async_nats::connect(url).await
+			.map_err(|e| format!("Failed to connect to nats at {}. e: {}", url, e))?;
+		let jetstream = jetstream::new(client);
+		// unlike the callback work queue, events stay in the stream after they were read
+		jetstream.get_or_create_stream(jetstream::stream::Config {
+			name: stream.clone(),
+			subjects: vec![format!("{}.>", subject)],
+			retention: jetstream::stream::RetentionPolicy::Limits,
+			..Default::default()
+		}).await
+			.map_err(|e| format!("Failed to create nats stream {}. e: {}", stream, e))?;
+
+		return Ok(NatsPublisher { jetstream, subject });
+	}
+}
+
+#[async_trait]
+impl EventPublisher for NatsPublisher {
+	fn target(&self) -> String {
+		return format!("nats://{}", self.subject);
+	}
+
+	async fn publish(&self, events: &[StoredEvent]) -> Result<(), String> {
+		for event in events {
+			let mut headers = async_nats::HeaderMap::new();
+			// the stream drops events it already stored
+			headers.insert("Nats-Msg-Id", event.id.to_string().as_str());
+			let subject = format!("{}.{}", self.subject, event.kind);
+			let ack = self.jetstream.publish_with_headers(subject, headers, message(event).into()).await
+				.map_err(|e| format!("Failed to publish event {} to nats. e: {}", event.id, e))?;
+			ack.await
+				.map_err(|e| format!("Nats did not store event {}. e: {}", event.id, e))?;
+		}
+		return Ok(());
+	}
+}
+
+static PUBLISHER: OnceCell<Box<dyn EventPublisher>> = OnceCell::const_new();
+
+async fn connect_publisher() -> Result<Box<dyn EventPublisher>, String> {
+	let kind = publisher_kind().unwrap_or_default();
+	match kind.as_str() {
+		"nats" => {
+			let url = std::env::var("EVENT_NATS_URL").map_err(|_| "EVENT_NATS_URL not defined".to_string())?;
+			return Ok(Box::new(NatsPublisher::connect(&url).await?));
+		}
+		_ => return Err(format!("Unknown EVENT_PUBLISHER: {}", kind))
+	}
+}
+
+// connected on first use. a failed connection is tried again on the next publish
+pub async fn publisher() -> Result<&'static dyn EventPublisher, String> {
+	let publisher = PUBLISHER.get_or_try_init(connect_publisher).await?;
+	return Ok(publisher.as_ref());
+}
+
+// call in the ticket transaction so the stream never has transitions that were rolled back
+pub async fn record(conn: &mut PgConnection, events: &[WorkflowEvent]) -> Result<(), sqlx::Error> {
+	if events.is_empty() || publisher_kind().is_none() {
+		return Ok(());
+	}
+	let now = chrono::Utc::now();
+	for event in events {
+		sqlx::query("insert into workflow_events (ticket_id, kind, payload, created_at, next_attempt_at) values ($1, $2, $3, $4, $4)")
+			.bind(event.ticket_id)
+			.bind(event.kind.as_str())
+			.bind(event.payload(now))
+			.bind(now)
+			.execute(&mut *conn)
+			.await?;
+	}
+	return Ok(());
+}
+
+// publishes the next batch of due events. returns false when there is nothing left to publish
+async fn publish_next_batch(pool: &PgPool) -> Result<bool, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	let due: Vec<StoredEvent> = sqlx::query_as(
+		"select id, kind, payload, attempts from workflow_events where next_attempt_at <= $1 order by id limit $2 for update skip locked")
+		.bind(now)
+		.bind(BATCH_SIZE)
+		.fetch_all(&mut *tx)
+		.await?;
+	if due.is_empty() {
+		return Ok(false);
+	}
+	let ids = due.iter().map(|e| e.id).collect::<Vec<_>>();
+
+	let published = match publisher().await {
+		Ok(publisher) => publisher.publish(&due).await,
+		Err(e) => Err(e)
+	};
+	if let Err(e) = published {
+		admin_logger(LogType::FailedToPing, &format!("Failed to publish {} workflow events: {}", due.len(), e), None);
+		let attempts = due.iter().map(|e| e.attempts).max().unwrap_or(0) + 1;
+		sqlx::query("update workflow_events set attempts=attempts+1, next_attempt_at=$2, last_error=$3 where id=any($1)")
+			.bind(&ids)
+			.bind(now + backoff(attempts))
+			.bind(&e)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		return Ok(false);
+	}
+
+	sqlx::query("delete from workflow_events where id=any($1)")
+		.bind(&ids)
+		.execute(&mut *tx)
+		.await?;
+	tx.commit().await?;
+	return Ok(due.len() as i64 == BATCH_SIZE);
+}
+
+pub async fn publish_due_events(pool: &PgPool) {
+	if publisher_kind().is_none() {
+		return;
+	}
+	while !shutdown::is_stopping() {
+		match publish_next_batch(pool).await {
+			Ok(true) => {}
+			Ok(false) => return,
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error publishing workflow events: {}", e), None);
+				return;
+			}
+		}
+	}
+}
+
+// publishes the events of a committed transaction without waiting for the next poll
+pub fn dispatch(pool: &PgPool) {
+	if publisher_kind().is_none() {
+		return;
+	}
+	let pool = pool.clone();
+	shutdown::spawn(async move {
+		publish_due_events(&pool).await;
+	});
+}
+
+pub async fn run_event_publisher(pool: PgPool) {
+	let mut interval = tokio::time::interval(poll_interval());
+	while shutdown::tick(&mut interval).await {
+		publish_due_events(&pool).await;
+	}
+}
+
+#[cfg(test)]
+mod events_tests {
+	use serde_json::json;
+	use super::{completed_nodes, message, EngineEvent, StoredEvent, WorkflowEvent};
+
+	#[test]
+	fn completed_nodes_are_the_new_bits() {
+		assert_eq!(completed_nodes(0b0001, 0b1011), vec![1, 3]);
+		assert_eq!(completed_nodes(0b0011, 0b0011), Vec::<i32>::new());
+		assert_eq!(completed_nodes(0, 1 << 31), vec![31]);
+	}
+
+	#[test]
+	fn messages_carry_the_row_id() {
+		let event = WorkflowEvent { kind: EngineEvent::ApprovalRequested, ticket_id: 3, process_id: "leave".to_string(), node: Some(2), detail: json!({ "assignee": "asha" }) };
+		let now = chrono::Utc::now();
+		let stored = StoredEvent { id: 41, kind: event.kind.as_str().to_string(), payload: event.payload(now), attempts: 0 };
+
+		let published: serde_json::Value = serde_json::from_slice(&message(&stored)).unwrap();
+		assert_eq!(published, json!({
+			"id": 41, "kind": "approval_requested", "occurred_at": now, "ticket_id": 3, "process_id": "leave", "node": 2,
+			"detail": { "assignee": "asha" }
+		}));
+	}
+}
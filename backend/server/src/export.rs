@@ -0,0 +1,270 @@
+use axum::{body::StreamBody, extract, http::{header, StatusCode}, response::{IntoResponse, Response}};
+use futures_util::TryStreamExt;
+use rust_xlsxwriter::{Workbook, XlsxError};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use tokio::sync::mpsc;
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use crate::ticket::{push_ticket_filters, GetUserTicketsReq, TicketStatus};
+use crate::replica;
+use crate::tenant;
+use crate::users;
+use crate::visibility;
+use utoipa::IntoParams;
+
+const COLUMNS: [&str; 8] = ["id", "process_id", "owner", "status", "priority", "created_at", "updated_at", "due_at"];
+// csv rows are sent to the client in chunks of about this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+	// csv or xlsx, defaults to csv
+	pub format: Option<String>,
+	// comma separated state fields added as extra columns. nested fields are separated by dots, e.g. "amount,vendor.name"
+	pub fields: Option<String>
+}
+
+#[derive(FromRow)]
+struct ExportRow {
+	id: i32,
+	process_id: String,
+	owner: String,
+	status: TicketStatus,
+	priority: i32,
+	created_at: chrono::DateTime<chrono::Utc>,
+	updated_at: chrono::DateTime<chrono::Utc>,
+	due_at: Option<chrono::DateTime<chrono::Utc>>,
+	state: Value
+}
+
+pub fn parse_fields(fields: Option<&str>) -> Vec<String> {
+	return match fields {
+		None => Vec::new(),
+		Some(f) => f.split(',')
+			.map(|f| f.trim().to_string())
+			.filter(|f| !f.is_empty())
+			.collect()
+	};
+}
+
+fn lookup<'a>(state: &'a Value, field: &str) -> Option<&'a Value> {
+	let mut value = state;
+	for key in field.split('.') {
+		value = value.get(key)?;
+	}
+	return Some(value);
+}
+
+// strings are written as is, missing fields and nulls as empty cells and anything else as json
+pub fn state_cell(state: &Value, field: &str) -> String {
+	return match lookup(state, field) {
+		None | Some(Value::Null) => String::new(),
+		Some(Value::String(s)) => s.clone(),
+		Some(v) => v.to_string()
+	};
+}
+
+fn header_row(fields: &[String]) -> Vec<String> {
+	let mut header = COLUMNS.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+	header.extend(fields.iter().map(|f| format!("state.{}", f)));
+	return header;
+}
+
+fn row_cells(row: &ExportRow, fields: &[String]) -> Vec<String> {
+	let mut cells = vec![
+		row.id.to_string(),
+		row.process_id.clone(),
+		row.owner.clone(),
+		row.status.to_string(),
+		row.priority.to_string(),
+		row.created_at.to_rfc3339(),
+		row.updated_at.to_rfc3339(),
+		row.due_at.map(|d| d.to_rfc3339()).unwrap_or_default()
+	];
+	cells.extend(fields.iter().map(|f| state_cell(&row.state, f)));
+	return cells;
+}
+
+// admins export every ticket, everyone else the tickets they own, were assigned to or watch.
+// the list filters apply, sorting and paging do not
+fn export_query(query: &GetUserTicketsReq, userid: uuid::Uuid, is_admin: bool) -> QueryBuilder<'static, Postgres> {
+	let tickets_source = if query.include_archived.unwrap_or(false) {
+		r#"(select id, owner_id, process_id, created_at, updated_at, status, priority, due_at, state from tickets
+			union all select id, owner_id, process_id, created_at, updated_at, status, priority, due_at, state from tickets_archive)"#
+	}
+	else {
+		"tickets"
+	};
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+		r#"select t.id, t.process_id, users.username as owner, t.status, t.priority, t.created_at, t.updated_at, t.due_at, t.state
+			from {} t join users on t.owner_id=users.userid where true"#, tickets_source));
+	if !is_admin {
+		visibility::push_involved_filter(&mut builder, userid);
+	}
+	push_ticket_filters(&mut builder, query);
+	builder.push(" order by t.created_at, t.id");
+	return builder;
+}
+
+fn csv_line(cells: &[String], buffer: &mut Vec<u8>) -> Result<(), csv::Error> {
+	let mut writer = csv::Writer::from_writer(buffer);
+	writer.write_record(cells)?;
+	writer.flush()?;
+	return Ok(());
+}
+
+// rows are written as they are read so large exports are never held in memory
+fn stream_csv(pool: PgPool, query: GetUserTicketsReq, userid: uuid::Uuid, is_admin: bool, fields: Vec<String>) -> Response {
+	let (sender, receiver) = mpsc::channel::<Result<Vec<u8>, String>>(4);
+
+	// spawned tasks run outside of the tenant of the request, the rows are read in it
+	let scope = tenant::current_scope();
+	tokio::spawn(tenant::within(scope, async move {
+		let mut buffer = Vec::new();
+		if let Err(e) = csv_line(&header_row(&fields), &mut buffer) {
+			let _ = sender.send(Err(e.to_string())).await;
+			return;
+		}
+
+		let mut builder = export_query(&query, userid, is_admin);
+		let mut rows = builder.build_query_as::<ExportRow>().fetch(&pool);
+		loop {
+			let row = match rows.try_next().await {
+				Ok(Some(row)) => row,
+				Ok(None) => break,
+				Err(e) => {
+					admin_logger(LogType::Error, &format!("Error exporting tickets for {}: {}", userid, e), None);
+					// aborts the response so the client does not mistake a partial file for a complete one
+					let _ = sender.send(Err(e.to_string())).await;
+					return;
+				}
+			};
+			if let Err(e) = csv_line(&row_cells(&row, &fields), &mut buffer) {
+				let _ = sender.send(Err(e.to_string())).await;
+				return;
+			}
+			if buffer.len() >= CHUNK_SIZE && sender.send(Ok(std::mem::take(&mut buffer))).await.is_err() {
+				// client went away
+				return;
+			}
+		}
+		if !buffer.is_empty() {
+			let _ = sender.send(Ok(buffer)).await;
+		}
+	}));
+
+	let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+		return receiver.recv().await.map(|chunk| (chunk, receiver));
+	});
+	return (
+		[
+			(header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+			(header::CONTENT_DISPOSITION, "attachment; filename=\"tickets.csv\"")
+		],
+		StreamBody::new(body)
+	).into_response();
+}
+
+fn build_xlsx(rows: &[ExportRow], fields: &[String]) -> Result<Vec<u8>, XlsxError> {
+	let mut workbook = Workbook::new();
+	let worksheet = workbook.add_worksheet();
+	for (col, name) in header_row(fields).iter().enumerate() {
+		worksheet.write_string(0, col as u16, name)?;
+	}
+	for (i, row) in rows.iter().enumerate() {
+		let r = i as u32 + 1;
+		for (col, cell) in row_cells(row, fields).iter().enumerate() {
+			// numbers stay numbers so they can be summed in the spreadsheet
+			let number = match col {
+				0 => Some(row.id as f64),
+				4 => Some(row.priority as f64),
+				c if c >= COLUMNS.len() => lookup(&row.state, &fields[c - COLUMNS.len()]).and_then(|v| v.as_f64()),
+				_ => None
+			};
+			match number {
+				Some(n) => worksheet.write_number(r, col as u16, n)?,
+				None => worksheet.write_string(r, col as u16, cell)?
+			};
+		}
+	}
+	return workbook.save_to_buffer();
+}
+
+#[utoipa::path(get, path = "/tickets/export", tag = "tickets", params(GetUserTicketsReq, ExportQuery), responses(
+	(status = 200, description = "The tickets of get /ticket/user as a csv or xlsx file", content(("text/csv" = String), ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" = Vec<u8>))),
+	(status = 400, description = "Unknown format, filter or field")
+))]
+pub async fn export_tickets(
+	user: AuthUser,
+	query: extract::Query<GetUserTicketsReq>,
+	extract::Query(options): extract::Query<ExportQuery>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Response, StatusCode> {
+	let pool = replica::read_pool(pool);
+	let query = query.0;
+	let userid = user.userid;
+	let fields = parse_fields(options.fields.as_deref());
+
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let is_admin = users::user_is_admin(&mut conn, userid).await;
+	if let Err(e) = is_admin {
+		admin_logger(LogType::Error, &format!("Error checking admin role of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let is_admin = is_admin.unwrap();
+
+	match options.format.as_deref() {
+		None | Some("csv") => {
+			drop(conn);
+			return Ok(stream_csv(pool, query, userid, is_admin, fields));
+		}
+		Some("xlsx") => {
+			// the xlsx format is a zip archive and has to be built in memory
+			let mut builder = export_query(&query, userid, is_admin);
+			let rows: Result<Vec<ExportRow>, _> = builder.build_query_as()
+				.fetch_all(&mut *conn)
+				.await;
+			if let Err(e) = rows {
+				admin_logger(LogType::Error, &format!("Error exporting tickets for {}: {}", userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+
+			let buffer = build_xlsx(&rows.unwrap(), &fields);
+			if let Err(e) = buffer {
+				admin_logger(LogType::Error, &format!("Error writing xlsx export for {}: {}", userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+
+			return Ok((
+				[
+					(header::CONTENT_TYPE, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+					(header::CONTENT_DISPOSITION, "attachment; filename=\"tickets.xlsx\"")
+				],
+				buffer.unwrap()
+			).into_response());
+		}
+		Some(_) => return Err(StatusCode::BAD_REQUEST)
+	}
+}
+
+#[cfg(test)]
+mod export_tests {
+	use serde_json::json;
+	use super::{parse_fields, state_cell};
+
+	#[test]
+	fn state_fields_are_flattened() {
+		let state = json!({"amount": 1250.5, "vendor": {"name": "Acme"}, "note": null, "items": ["a", "b"]});
+		assert_eq!(state_cell(&state, "amount"), "1250.5");
+		assert_eq!(state_cell(&state, "vendor.name"), "Acme");
+		assert_eq!(state_cell(&state, "note"), "");
+		assert_eq!(state_cell(&state, "missing.field"), "");
+		assert_eq!(state_cell(&state, "items"), r#"["a","b"]"#);
+
+		assert_eq!(parse_fields(Some(" amount, ,vendor.name")), vec!["amount".to_string(), "vendor.name".to_string()]);
+		assert!(parse_fields(None).is_empty());
+	}
+}
@@ -0,0 +1,172 @@
+use axum::{
+	async_trait,
+	body::HttpBody,
+	extract::{rejection::{JsonRejection, PathRejection, QueryRejection}, FromRequest, FromRequestParts, Path, Query},
+	http::{request::Parts, Request, StatusCode},
+	BoxError, Json
+};
+use serde::de::DeserializeOwned;
+use crate::api_error::ApiError;
+use crate::schema::FieldError;
+use erp_api_types::tickets::{CancelTicket, CreateTicket, SubmitTicket, UpdateTicket};
+
+// checks of a request body beyond its types. empty when the body is fine
+pub trait Validate {
+	fn validate(&self) -> Vec<FieldError>;
+}
+
+// Json that answers bodies it can not read, and bodies that fail Validate, with a problem instead of plain text
+pub struct ValidJson<T>(pub T);
+
+// Query and Path with problem rejections. they only check the types of the parameters
+pub struct ValidQuery<T>(pub T);
+pub struct ValidPath<T>(pub T);
+
+fn json_rejection(rejection: JsonRejection) -> ApiError {
+	let detail = rejection.body_text();
+	return match rejection {
+		JsonRejection::JsonDataError(_) => ApiError::field(StatusCode::UNPROCESSABLE_ENTITY, "invalid_data", "", detail),
+		JsonRejection::JsonSyntaxError(_) => ApiError::new(StatusCode::BAD_REQUEST, "invalid_json", detail),
+		JsonRejection::MissingJsonContentType(_) => ApiError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", detail),
+		_ => ApiError::new(rejection.status(), "unreadable_body", detail)
+	};
+}
+
+fn query_rejection(rejection: QueryRejection) -> ApiError {
+	return ApiError::new(StatusCode::BAD_REQUEST, "invalid_query", rejection.body_text());
+}
+
+fn path_rejection(rejection: PathRejection) -> ApiError {
+	return match rejection {
+		PathRejection::FailedToDeserializePathParams(e) => ApiError::new(StatusCode::BAD_REQUEST, "invalid_path", e.body_text()),
+		// the route and the handler disagree, nothing the client can fix
+		_ => ApiError::internal()
+	};
+}
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidJson<T>
+where
+	T: DeserializeOwned + Validate,
+	S: Send + Sync,
+	B: HttpBody + Send + 'static,
+	B::Data: Send,
+	B::Error: Into<BoxError>
+{
+	type Rejection = ApiError;
+
+	async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+		let Json(value) = Json::<T>::from_request(req, state).await.map_err(json_rejection)?;
+		let errors = value.validate();
+		if !errors.is_empty() {
+			return Err(ApiError::fields(StatusCode::UNPROCESSABLE_ENTITY, "invalid_data", errors));
+		}
+		return Ok(ValidJson(value));
+	}
+}
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ValidQuery<T> {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let Query(value) = Query::<T>::from_request_parts(parts, state).await.map_err(query_rejection)?;
+		return Ok(ValidQuery(value));
+	}
+}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send, S: Send + Sync> FromRequestParts<S> for ValidPath<T> {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let Path(value) = Path::<T>::from_request_parts(parts, state).await.map_err(path_rejection)?;
+		return Ok(ValidPath(value));
+	}
+}
+
+fn error(field: &str, message: &str) -> FieldError {
+	return FieldError { field: field.to_string(), message: message.to_string() };
+}
+
+impl Validate for CreateTicket {
+	fn validate(&self) -> Vec<FieldError> {
+		let mut errors = Vec::new();
+		if self.process_id.trim().is_empty() {
+			errors.push(error("/process_id", "A process is required"));
+		}
+		if let Err(message) = crate::tags::normalize_tags(&self.tags.clone().unwrap_or_default()) {
+			errors.push(error("/tags", &message));
+		}
+		return errors;
+	}
+}
+
+impl Validate for UpdateTicket {
+	fn validate(&self) -> Vec<FieldError> {
+		let mut errors = Vec::new();
+		if self.node < 0 {
+			errors.push(error("/node", "Expected a node number of 0 or more"));
+		}
+		if self.instance.is_some_and(|i| i < 0) {
+			errors.push(error("/instance", "Expected an instance of 0 or more"));
+		}
+		return errors;
+	}
+}
+
+// only their types are checked
+impl Validate for CancelTicket {
+	fn validate(&self) -> Vec<FieldError> {
+		return Vec::new();
+	}
+}
+
+impl Validate for SubmitTicket {
+	fn validate(&self) -> Vec<FieldError> {
+		return Vec::new();
+	}
+}
+
+#[cfg(test)]
+mod extractors_tests {
+	use axum::{body::Body, extract::{FromRequest, FromRequestParts}, http::{header, Request, StatusCode}};
+	use erp_api_types::tickets::{GetUserTicketsReq, UpdateTicket};
+	use super::{ValidJson, ValidQuery};
+
+	fn request(body: &str) -> Request<Body> {
+		return Request::builder()
+			.method("POST")
+			.uri("/ticket/update")
+			.header(header::CONTENT_TYPE, "application/json")
+			.body(Body::from(body.to_string()))
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn bodies_are_read_then_validated() {
+		let update = ValidJson::<UpdateTicket>::from_request(request(r#"{"ticket_id": 1, "status": true, "node": 2}"#), &()).await;
+		assert_eq!(update.ok().map(|u| u.0.node), Some(2));
+
+		let broken = ValidJson::<UpdateTicket>::from_request(request(r#"{"ticket_id": 1,"#), &()).await.err().unwrap();
+		assert_eq!((broken.status, broken.code), (StatusCode::BAD_REQUEST, "invalid_json"));
+
+		let wrong_type = ValidJson::<UpdateTicket>::from_request(request(r#"{"ticket_id": "one", "status": true, "node": 2}"#), &()).await.err().unwrap();
+		assert_eq!((wrong_type.status, wrong_type.code), (StatusCode::UNPROCESSABLE_ENTITY, "invalid_data"));
+
+		let invalid = ValidJson::<UpdateTicket>::from_request(request(r#"{"ticket_id": 1, "status": true, "node": -1, "instance": -2}"#), &()).await.err().unwrap();
+		let fields: Vec<String> = invalid.errors.into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/node", "/instance"]);
+	}
+
+	#[tokio::test]
+	async fn queries_are_typed() {
+		let (mut parts, _) = Request::builder().uri("/ticket/user?limit=ten").body(()).unwrap().into_parts();
+		let rejected = ValidQuery::<GetUserTicketsReq>::from_request_parts(&mut parts, &()).await.err().unwrap();
+		assert_eq!((rejected.status, rejected.code), (StatusCode::BAD_REQUEST, "invalid_query"));
+
+		let (mut parts, _) = Request::builder().uri("/ticket/user?limit=10&order=asc").body(()).unwrap().into_parts();
+		let query = ValidQuery::<GetUserTicketsReq>::from_request_parts(&mut parts, &()).await.ok().unwrap();
+		assert_eq!(query.0.limit, Some(10));
+	}
+}
@@ -0,0 +1,282 @@
+use std::net::SocketAddr;
+use axum::http::{HeaderMap, StatusCode};
+use prost::Message;
+use prost_types::value::Kind;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use tonic::{Code, Request, Response, Status};
+use crate::api_keys;
+use crate::auth::{self, AuthUser};
+use crate::logger::{admin_logger, LogType};
+use crate::ratelimit;
+use crate::schema::FieldErrors;
+use crate::shutdown;
+use crate::tenant;
+use crate::ticket::{self, CreateTicket, UpdateErr, UpdateTicket};
+
+include!(concat!(env!("OUT_DIR"), "/erp.v1.Tickets.rs"));
+
+// the messages of backend/proto/tickets.proto. tags have to match the file
+#[derive(Clone, PartialEq, Message)]
+pub struct CreateTicketRequest {
+	#[prost(string, tag = "1")]
+	pub process_id: String,
+	#[prost(bool, tag = "2")]
+	pub is_public: bool,
+	#[prost(int32, optional, tag = "3")]
+	pub priority: Option<i32>,
+	#[prost(message, optional, tag = "4")]
+	pub due_at: Option<prost_types::Timestamp>,
+	#[prost(string, repeated, tag = "5")]
+	pub tags: Vec<String>,
+	#[prost(bool, tag = "6")]
+	pub draft: bool,
+	#[prost(message, optional, tag = "7")]
+	pub data: Option<prost_types::Struct>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CreatedTicket {
+	#[prost(int32, tag = "1")]
+	pub id: i32,
+	#[prost(string, tag = "2")]
+	pub log_id: String,
+	#[prost(string, tag = "3")]
+	pub status: String
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct UpdateTicketRequest {
+	#[prost(int32, tag = "1")]
+	pub ticket_id: i32,
+	#[prost(bool, tag = "2")]
+	pub status: bool,
+	#[prost(int32, tag = "3")]
+	pub node: i32,
+	#[prost(message, optional, tag = "4")]
+	pub data: Option<prost_types::Struct>,
+	#[prost(int32, optional, tag = "5")]
+	pub instance: Option<i32>,
+	#[prost(string, optional, tag = "6")]
+	pub reason: Option<String>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct UpdateTicketResponse {}
+
+// the grpc server runs when GRPC_PORT is set
+pub fn port() -> Option<u16> {
+	return std::env::var("GRPC_PORT")
+		.ok()
+		.and_then(|s| s.parse::<u16>().ok());
+}
+
+fn json_value(value: prost_types::Value) -> Value {
+	return match value.kind {
+		None | Some(Kind::NullValue(_)) => Value::Null,
+		// struct only has doubles, whole numbers are turned back into integers for the schemas of the nodes
+		Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => Value::from(n as i64),
+		Some(Kind::NumberValue(n)) => serde_json::Number::from_f64(n).map(Value::Number).unwrap_or(Value::Null),
+		Some(Kind::StringValue(s)) => Value::String(s),
+		Some(Kind::BoolValue(b)) => Value::Bool(b),
+		Some(Kind::StructValue(s)) => Value::Object(json_object(s)),
+		Some(Kind::ListValue(list)) => Value::Array(list.values.into_iter().map(json_value).collect())
+	};
+}
+
+fn json_object(data: prost_types::Struct) -> Map<String, Value> {
+	return data.fields.into_iter().map(|(key, value)| (key, json_value(value))).collect();
+}
+
+fn create_payload(request: CreateTicketRequest) -> Result<CreateTicket, StatusCode> {
+	let due_at = match request.due_at {
+		Some(due_at) => Some(
+			chrono::DateTime::from_timestamp(due_at.seconds, due_at.nanos.max(0) as u32)
+				.ok_or(StatusCode::BAD_REQUEST)?
+		),
+		None => None
+	};
+	return Ok(CreateTicket {
+		process_id: request.process_id,
+		owner_id: uuid::Uuid::nil(),
+		owner_name: String::new(),
+		is_public: request.is_public,
+		priority: request.priority,
+		due_at,
+		tags: Some(request.tags),
+		draft: request.draft,
+		data: request.data.map(json_object)
+	});
+}
+
+fn update_payload(request: UpdateTicketRequest) -> UpdateTicket {
+	return UpdateTicket {
+		ticket_id: request.ticket_id,
+		user_id: uuid::Uuid::nil(),
+		status: request.status,
+		node: request.node,
+		data: request.data.map(json_object),
+		instance: request.instance,
+		reason: request.reason
+	};
+}
+
+fn status_code(status: StatusCode) -> Code {
+	return match status {
+		StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+		StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+		StatusCode::FORBIDDEN => Code::PermissionDenied,
+		StatusCode::NOT_FOUND => Code::NotFound,
+		StatusCode::CONFLICT => Code::FailedPrecondition,
+		StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+		_ => Code::Internal
+	};
+}
+
+fn from_status(status: StatusCode) -> Status {
+	return Status::new(status_code(status), status.canonical_reason().unwrap_or_default());
+}
+
+fn from_update_err(e: UpdateErr) -> Status {
+	return match e {
+		UpdateErr::Status(status) => from_status(status),
+		UpdateErr::Problem(e) => Status::new(status_code(e.status), e.detail.unwrap_or(e.code.to_string())),
+		UpdateErr::InvalidData(errors) => Status::invalid_argument(serde_json::to_string(&FieldErrors { errors }).unwrap_or_default()),
+		UpdateErr::InvalidRequest(errors) => Status::failed_precondition(serde_json::to_string(&FieldErrors { errors }).unwrap_or_default())
+	};
+}
+
+fn take_write(identity: Option<String>) -> Result<(), StatusCode> {
+	if let Some(identity) = identity {
+		if let Err(retry_after) = ratelimit::take_write(&identity) {
+			admin_logger(LogType::Warning, &format!("Rate limited {} on grpc, retry after {}s", identity, retry_after), None);
+			return Err(StatusCode::TOO_MANY_REQUESTS);
+		}
+	}
+	return Ok(());
+}
+
+async fn authenticate(pool: &PgPool, headers: &HeaderMap) -> Result<AuthUser, Status> {
+	let token = auth::bearer_token(headers).ok_or_else(|| from_status(StatusCode::UNAUTHORIZED))?;
+	return auth::authenticate(pool, token).await.map_err(from_status);
+}
+
+pub struct TicketService {
+	pool: PgPool
+}
+
+#[tonic::async_trait]
+impl tickets_server::Tickets for TicketService {
+	async fn create_ticket(&self, request: Request<CreateTicketRequest>) -> Result<Response<CreatedTicket>, Status> {
+		let headers = request.metadata().clone().into_headers();
+		take_write(ratelimit::identity(&headers, &auth::JWT_SECRET)).map_err(from_status)?;
+		let tenant = tenant::request_tenant(&self.pool, &headers).await;
+		let created = tenant::scoped(tenant, async {
+			let user = authenticate(&self.pool, &headers).await?;
+			let payload = create_payload(request.into_inner()).map_err(from_status)?;
+			return ticket::create_for_user(&self.pool, user, payload).await.map_err(from_status);
+		}).await?;
+
+		return Ok(Response::new(CreatedTicket {
+			id: created.id,
+			log_id: created.log_id.to_string(),
+			status: created.status.to_string()
+		}));
+	}
+
+	async fn update_ticket(&self, request: Request<UpdateTicketRequest>) -> Result<Response<UpdateTicketResponse>, Status> {
+		let headers = request.metadata().clone().into_headers();
+		take_write(ratelimit::identity(&headers, &auth::JWT_SECRET)).map_err(from_status)?;
+		let tenant = tenant::request_tenant(&self.pool, &headers).await;
+		tenant::scoped(tenant, async {
+			let user = authenticate(&self.pool, &headers).await?;
+			let mut payload = update_payload(request.into_inner());
+			payload.user_id = user.userid;
+			return ticket::apply_update(&self.pool, payload, ticket::UpdateSource::User).await.map_err(from_update_err);
+		}).await?;
+
+		return Ok(Response::new(UpdateTicketResponse {}));
+	}
+
+	async fn complete_task(&self, request: Request<UpdateTicketRequest>) -> Result<Response<UpdateTicketResponse>, Status> {
+		let headers = request.metadata().clone().into_headers();
+		let tenant = tenant::request_tenant(&self.pool, &headers).await;
+		tenant::scoped(tenant, async {
+			let key = api_keys::authenticate_key(&self.pool, &headers, "grpc CompleteTask").await.map_err(from_status)?;
+			take_write(Some(ratelimit::key_identity(key.id))).map_err(from_status)?;
+			// prost encodes struct fields ordered by key, so this is the message the client signed
+			let body = request.get_ref().encode_to_vec();
+			let payload = update_payload(request.into_inner());
+			return api_keys::complete_for_key(&self.pool, &key, payload, &headers, &body).await.map_err(from_update_err);
+		}).await?;
+
+		return Ok(Response::new(UpdateTicketResponse {}));
+	}
+}
+
+// serves until shutdown starts, then lets the running calls finish
+pub async fn serve(pool: PgPool, port: u16) {
+	let addr = SocketAddr::from(([0, 0, 0, 0], port));
+	println!("Serving grpc on {}", addr);
+	let served = tonic::transport::Server::builder()
+		.add_service(tickets_server::TicketsServer::new(TicketService { pool }))
+		.serve_with_shutdown(addr, shutdown::stopped())
+		.await;
+	if let Err(e) = served {
+		admin_logger(LogType::Error, &format!("Grpc server stopped: {}", e), None);
+	}
+}
+
+#[cfg(test)]
+mod grpc_tests {
+	use axum::http::StatusCode;
+	use prost::Message;
+	use prost_types::{value::Kind, ListValue, Struct, Value as ProtoValue};
+	use serde_json::json;
+	use tonic::Code;
+	use crate::schema::FieldError;
+	use crate::ticket::UpdateErr;
+	use super::{from_update_err, json_object, update_payload, UpdateTicketRequest};
+
+	fn value(kind: Kind) -> ProtoValue {
+		return ProtoValue { kind: Some(kind) };
+	}
+
+	#[test]
+	fn struct_data_becomes_json() {
+		let mut inner = Struct::default();
+		inner.fields.insert("ok".to_string(), value(Kind::BoolValue(true)));
+		let mut data = Struct::default();
+		data.fields.insert("days".to_string(), value(Kind::NumberValue(3.0)));
+		data.fields.insert("rate".to_string(), value(Kind::NumberValue(1.5)));
+		data.fields.insert("note".to_string(), value(Kind::StringValue("sick".to_string())));
+		data.fields.insert("none".to_string(), value(Kind::NullValue(0)));
+		data.fields.insert("list".to_string(), value(Kind::ListValue(ListValue { values: vec![value(Kind::NumberValue(1.0))] })));
+		data.fields.insert("inner".to_string(), value(Kind::StructValue(inner)));
+
+		assert_eq!(serde_json::Value::Object(json_object(data)), json!({
+			"days": 3, "rate": 1.5, "note": "sick", "none": null, "list": [1], "inner": { "ok": true }
+		}));
+	}
+
+	#[test]
+	fn requests_round_trip() {
+		let request = UpdateTicketRequest { ticket_id: 4, status: false, node: 2, data: None, instance: Some(1), reason: Some("no".to_string()) };
+		let decoded = UpdateTicketRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+		assert_eq!(decoded, request);
+
+		let payload = update_payload(decoded);
+		assert_eq!((payload.ticket_id, payload.status, payload.node, payload.instance), (4, false, 2, Some(1)));
+		assert_eq!(payload.reason.as_deref(), Some("no"));
+	}
+
+	#[test]
+	fn errors_map_to_grpc_codes() {
+		assert_eq!(from_update_err(UpdateErr::Status(StatusCode::FORBIDDEN)).code(), Code::PermissionDenied);
+		assert_eq!(from_update_err(UpdateErr::Status(StatusCode::INTERNAL_SERVER_ERROR)).code(), Code::Internal);
+
+		let invalid = from_update_err(UpdateErr::InvalidData(vec![FieldError { field: "/days".to_string(), message: "too many".to_string() }]));
+		assert_eq!(invalid.code(), Code::InvalidArgument);
+		assert_eq!(serde_json::from_str::<serde_json::Value>(invalid.message()).unwrap(), json!({ "errors": [{ "field": "/days", "message": "too many" }] }));
+	}
+}
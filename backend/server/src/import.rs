@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use crate::archive::ARCHIVED_STATUSES;
+use crate::logger::{admin_logger, log, LogType};
+use crate::process::read_process_data;
+use crate::rbac::{Authorized, ManageProcesses};
+use crate::ticket::TicketStatus;
+use crate::schema::{FieldError, FieldErrors};
+use crate::tags;
+use crate::ticket_events::{self, Projection, TicketEvent, TicketEventKind};
+use utoipa::ToSchema;
+
+// larger imports are sent in several requests
+pub const MAX_IMPORT: usize = 1000;
+
+// a ticket of the previous helpdesk tool. it is stored as it ended, nothing of the process is executed
+#[derive(Deserialize, ToSchema)]
+pub struct ImportedTicket {
+	pub process_id: String,
+	pub owner_id: uuid::Uuid,
+	#[serde(default)]
+	pub is_public: bool,
+	#[serde(default)]
+	#[schema(value_type = Object)]
+	pub state: Map<String, Value>,
+	// nodes of the process that were done, turned into the complete bitmask
+	#[serde(default)]
+	pub completed_nodes: Vec<i32>,
+	// closed, rejected or cancelled
+	pub status: TicketStatus,
+	#[serde(default)]
+	pub priority: i32,
+	#[serde(default)]
+	pub tags: Vec<String>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	// defaults to created_at
+	pub updated_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+	// ids of the new tickets in the order they were sent
+	pub imported: Vec<i32>
+}
+
+fn field_error(index: usize, field: &str, message: String) -> FieldError {
+	return FieldError { field: format!("/{}/{}", index, field), message };
+}
+
+// the complete bitmask of the ticket, or what is wrong with it. `steps` is the number of nodes of its process
+pub fn check_ticket(index: usize, ticket: &ImportedTicket, steps: usize) -> Result<i32, Vec<FieldError>> {
+	let mut errors = Vec::new();
+	if !ARCHIVED_STATUSES.contains(&ticket.status) {
+		let expected: Vec<&str> = ARCHIVED_STATUSES.iter().map(|s| s.as_str()).collect();
+		errors.push(field_error(index, "status", format!("Only finished tickets can be imported, expected one of {:?}", expected)));
+	}
+	if ticket.updated_at.map(|u| u < ticket.created_at).unwrap_or(false) {
+		errors.push(field_error(index, "updated_at", "Cannot be before created_at".to_string()));
+	}
+	if let Err(tag) = tags::normalize_tags(&ticket.tags) {
+		errors.push(field_error(index, "tags", format!("Invalid tag: {}", tag)));
+	}
+
+	let mut complete = 0i32;
+	// the bitmask has room for 32 nodes
+	for node in ticket.completed_nodes.iter() {
+		if *node < 0 || *node as usize >= steps || *node >= 32 {
+			errors.push(field_error(index, "completed_nodes", format!("Node {} does not exist in process {}", node, ticket.process_id)));
+			continue;
+		}
+		complete |= 1 << node;
+	}
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	return Ok(complete);
+}
+
+// inserts finished tickets of another system in one transaction. nothing is executed, so no callbacks,
+// notifications, webhooks or events are sent. either every ticket is imported or none
+#[utoipa::path(post, path = "/admin/tickets/import", tag = "admin", request_body = Vec<ImportedTicket>, responses(
+	(status = 201, description = "Every ticket was imported", body = ImportResponse),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 413, description = "Too many tickets in one batch", body = FieldErrors),
+	(status = 422, description = "Invalid tickets, nothing was imported", body = FieldErrors)
+))]
+pub async fn import_tickets(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(tickets): Json<Vec<ImportedTicket>>
+) -> Result<(StatusCode, Json<ImportResponse>), (StatusCode, Json<FieldErrors>)> {
+	let fail = |status: StatusCode| (status, Json(FieldErrors { errors: Vec::new() }));
+	if tickets.len() > MAX_IMPORT {
+		let message = format!("At most {} tickets can be imported at once", MAX_IMPORT);
+		return Err((StatusCode::PAYLOAD_TOO_LARGE, Json(FieldErrors { errors: vec![FieldError { field: String::new(), message }] })));
+	}
+
+	// every ticket is checked before anything is written, so the whole batch can be fixed at once
+	let mut steps: HashMap<String, Option<usize>> = HashMap::new();
+	let mut errors = Vec::new();
+	let mut masks = Vec::new();
+	for (index, ticket) in tickets.iter().enumerate() {
+		if !steps.contains_key(&ticket.process_id) {
+			let process = read_process_data(ticket.process_id.clone()).await;
+			steps.insert(ticket.process_id.clone(), process.ok().map(|p| p.steps.len()));
+		}
+		let process_steps = steps[&ticket.process_id];
+		let Some(process_steps) = process_steps else {
+			errors.push(field_error(index, "process_id", format!("Unknown process: {}", ticket.process_id)));
+			continue;
+		};
+		match check_ticket(index, ticket, process_steps) {
+			Ok(mask) => masks.push(mask),
+			Err(mut e) => errors.append(&mut e)
+		}
+	}
+
+	// owners have to be users of the tenant doing the import
+	let mut owners: Vec<uuid::Uuid> = tickets.iter().map(|t| t.owner_id).collect();
+	owners.sort();
+	owners.dedup();
+	let known: Result<Vec<(uuid::Uuid,)>, _> = sqlx::query_as("select userid from users where userid=any($1)")
+		.bind(&owners)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = known {
+		admin_logger(LogType::Error, &format!("Error reading owners of imported tickets: {}", e), None);
+		return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+	}
+	let known = known.unwrap();
+	for (index, ticket) in tickets.iter().enumerate() {
+		if !known.iter().any(|(userid,)| *userid == ticket.owner_id) {
+			errors.push(field_error(index, "owner_id", format!("Unknown user: {}", ticket.owner_id)));
+		}
+	}
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let mut imported = Vec::with_capacity(tickets.len());
+	for (ticket, complete) in tickets.iter().zip(masks) {
+		let log_id = uuid::Uuid::new_v4();
+		let query: Result<(i32,), _> = sqlx::query_as(
+			r#"insert into tickets (owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, state, priority)
+				values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) returning id"#)
+			.bind(ticket.owner_id)
+			.bind(&ticket.process_id)
+			.bind(log_id)
+			.bind(ticket.is_public)
+			.bind(ticket.created_at)
+			.bind(ticket.updated_at.unwrap_or(ticket.created_at))
+			.bind(ticket.status)
+			.bind(complete)
+			.bind(Value::Object(ticket.state.clone()))
+			.bind(ticket.priority)
+			.fetch_one(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error importing ticket of process {} from {}: {}", ticket.process_id, ticket.owner_id, e), None);
+			return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+		}
+		let id = query.unwrap().0;
+
+		let row = Projection { status: ticket.status, complete, state: Value::Object(ticket.state.clone()), instances: Value::Object(Map::new()) };
+		let event = TicketEvent::inserted(TicketEventKind::Imported, &row, Some(auth.user.userid));
+		if let Err(e) = ticket_events::record(&mut tx, id, &[event]).await {
+			admin_logger(LogType::Error, &format!("Error recording the import of ticket {}: {}", id, e), None);
+			return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+		}
+
+		// listed as an own ticket of the owner that is no longer active, like a ticket that ran to its end
+		let query = sqlx::query("insert into user_active_tickets (userid, ticketid, active, node_number, type_) values ($1, $2, false, 0, 'own')")
+			.bind(ticket.owner_id)
+			.bind(id)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error importing ticket {}: {}", id, e), None);
+			return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+		}
+		let tags = tags::normalize_tags(&ticket.tags).unwrap_or_default();
+		if let Err(e) = tags::add_tags(&mut tx, id, &tags).await {
+			admin_logger(LogType::Error, &format!("Error adding tags to imported ticket {}: {}", id, e), None);
+			return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+		}
+		log(LogType::Info, format!("Ticket {} imported by {} as {}", id, auth.user.userid, ticket.status), log_id);
+		imported.push(id);
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting imported tickets: {}", e), None);
+		return Err(fail(StatusCode::INTERNAL_SERVER_ERROR));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} imported {} tickets", auth.user.userid, imported.len()), None);
+	return Ok((StatusCode::CREATED, Json(ImportResponse { imported })));
+}
+
+#[cfg(test)]
+mod import_tests {
+	use serde_json::json;
+	use super::{check_ticket, ImportedTicket};
+
+	fn ticket(status: &str, completed_nodes: Vec<i32>) -> ImportedTicket {
+		return serde_json::from_value(json!({
+			"process_id": "leave",
+			"owner_id": uuid::Uuid::nil(),
+			"completed_nodes": completed_nodes,
+			"status": status,
+			"created_at": "2023-04-01T10:00:00Z"
+		})).unwrap();
+	}
+
+	#[test]
+	fn completed_nodes_become_the_bitmask() {
+		assert_eq!(check_ticket(0, &ticket("closed", vec![0, 1, 3]), 4).ok(), Some(0b1011));
+		assert_eq!(check_ticket(0, &ticket("rejected", vec![]), 4).ok(), Some(0));
+		assert_eq!(check_ticket(0, &ticket("cancelled", vec![2, 2]), 4).ok(), Some(0b100));
+	}
+
+	#[test]
+	fn invalid_tickets_are_reported_by_index() {
+		let errors = check_ticket(3, &ticket("open", vec![0, 4]), 4).unwrap_err();
+		let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+		assert_eq!(fields, vec!["/3/status", "/3/completed_nodes"]);
+
+		let mut late = ticket("closed", vec![0]);
+		late.updated_at = Some("2023-03-01T10:00:00Z".parse().unwrap());
+		assert_eq!(check_ticket(0, &late, 4).unwrap_err()[0].field, "/0/updated_at");
+	}
+}
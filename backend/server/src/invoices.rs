@@ -0,0 +1,738 @@
+use std::time::Duration;
+use axum::{extract, http::{header, StatusCode}, response::IntoResponse, Json};
+use chrono::{Datelike, NaiveDate};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::jobs;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::purchase_orders::{line_totals, valid_currency, NewLine};
+use crate::rbac::{Authorized, ManageInvoices};
+use crate::schema::{FieldError, FieldErrors, internal_error, unprocessable};
+use crate::ticket;
+use utoipa::{IntoParams, ToSchema};
+
+// a tax rate of 100% in basis points
+const MAX_TAX_RATE_BP: i32 = 10_000;
+// lines per page of the pdf
+const LINES_PER_PAGE: usize = 30;
+
+// the process whose tickets approve invoices
+fn process_id() -> String {
+	return std::env::var("INVOICE_PROCESS").unwrap_or("invoice".to_string());
+}
+
+fn overdue_interval() -> Duration {
+	let secs = std::env::var("INVOICE_OVERDUE_INTERVAL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(3600);
+	return Duration::from_secs(secs);
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateInvoice {
+	pub customer_name: String,
+	pub customer_address: Option<String>,
+	// iso 4217 code like EUR
+	pub currency: String,
+	pub lines: Vec<NewLine>,
+	// in basis points, 1900 is 19%
+	pub tax_rate_bp: i32,
+	pub due_date: NaiveDate,
+	pub notes: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Invoice {
+	pub id: i32,
+	pub number: Option<String>,
+	pub customer_name: String,
+	pub customer_address: Option<String>,
+	pub currency: String,
+	pub subtotal_cents: i64,
+	pub tax_rate_bp: i32,
+	pub tax_cents: i64,
+	pub total_cents: i64,
+	pub paid_cents: i64,
+	pub issue_date: Option<NaiveDate>,
+	pub due_date: NaiveDate,
+	pub status: String,
+	pub notes: Option<String>,
+	pub ticket_id: Option<i32>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct InvoiceLine {
+	pub line_number: i32,
+	pub description: String,
+	pub quantity: i32,
+	pub unit_price_cents: i64,
+	pub total_cents: i64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Payment {
+	pub id: i32,
+	pub amount_cents: i64,
+	pub paid_on: NaiveDate,
+	pub method: Option<String>,
+	pub reference: Option<String>,
+	pub recorded_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InvoiceDetail {
+	#[serde(flatten)]
+	pub invoice: Invoice,
+	pub lines: Vec<InvoiceLine>,
+	pub payments: Vec<Payment>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct InvoicesQuery {
+	pub status: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecordPayment {
+	pub amount_cents: i64,
+	// defaults to today
+	pub paid_on: Option<NaiveDate>,
+	pub method: Option<String>,
+	pub reference: Option<String>
+}
+
+// the totals of an invoice, all in the smallest unit of its currency
+#[derive(Debug, PartialEq)]
+pub struct Totals {
+	pub lines: Vec<i64>,
+	pub subtotal: i64,
+	pub tax: i64,
+	pub total: i64
+}
+
+// rounded half up, amounts are never negative
+pub fn tax_cents(subtotal: i64, tax_rate_bp: i32) -> Option<i64> {
+	let tax = (subtotal as i128 * tax_rate_bp as i128 + 5_000) / 10_000;
+	return i64::try_from(tax).ok();
+}
+
+// the totals of the invoice, or what is wrong with it
+pub fn check_invoice(invoice: &CreateInvoice, today: NaiveDate) -> Result<Totals, Vec<FieldError>> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if invoice.customer_name.trim().is_empty() {
+		error("/customer_name", "A customer is required");
+	}
+	if !valid_currency(&invoice.currency) {
+		error("/currency", "Expected a three letter currency code like EUR");
+	}
+	if invoice.tax_rate_bp < 0 || invoice.tax_rate_bp > MAX_TAX_RATE_BP {
+		error("/tax_rate_bp", "Expected between 0 and 10000 basis points");
+	}
+	if invoice.due_date < today {
+		error("/due_date", "Cannot be in the past");
+	}
+	let (lines, subtotal) = line_totals(&invoice.lines, &mut errors);
+	let subtotal = subtotal.unwrap_or_default();
+	let tax = tax_cents(subtotal, invoice.tax_rate_bp.clamp(0, MAX_TAX_RATE_BP));
+	let total = tax.and_then(|tax| subtotal.checked_add(tax));
+	if total.is_none() {
+		errors.push(FieldError { field: "/lines".to_string(), message: "Total is too large".to_string() });
+	}
+
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	return Ok(Totals { lines, subtotal, tax: tax.unwrap(), total: total.unwrap() });
+}
+
+// the status an invoice in `current` moves to when its ticket asks for `target`. a closed ticket approves the invoice,
+// sending and payments move it further, see send_invoice and record_payment
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("draft", "approved" | "closed") => Some("approved"),
+		("draft" | "approved", "rejected") => Some("rejected"),
+		("draft" | "approved", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+// what was paid and the status after a payment of `amount`, or why it cannot be recorded
+pub fn apply_payment(status: &str, total: i64, paid: i64, amount: i64) -> Result<(i64, &'static str), String> {
+	if status != "sent" && status != "overdue" {
+		return Err(format!("Payments can only be recorded for sent or overdue invoices, this one is {}", status));
+	}
+	if amount <= 0 {
+		return Err("Must be more than 0".to_string());
+	}
+	let paid = paid.checked_add(amount).filter(|paid| *paid <= total)
+		.ok_or(format!("Only {} are left to pay", total - paid))?;
+	if paid == total {
+		return Ok((paid, "paid"));
+	}
+	return Ok((paid, if status == "overdue" { "overdue" } else { "sent" }));
+}
+
+// e.g. INV-2024-000042
+pub fn invoice_number(id: i32, issue_date: NaiveDate) -> String {
+	return format!("INV-{}-{:06}", issue_date.year(), id);
+}
+
+// 123456 -> 1234.56, for currencies with two decimals
+pub fn format_cents(cents: i64) -> String {
+	let sign = if cents < 0 { "-" } else { "" };
+	return format!("{}{}.{:02}", sign, (cents / 100).abs(), (cents % 100).abs());
+}
+
+// the standard fonts take windows-1252 text. characters it lacks are replaced
+fn pdf_text(text: &str) -> Vec<u8> {
+	return text.chars()
+		.map(|c| match c {
+			'\u{20AC}' => 0x80,
+			'\n' | '\r' | '\t' => b' ',
+			c if (c as u32) < 0x80 || ((c as u32) >= 0xA0 && (c as u32) <= 0xFF) => c as u8,
+			_ => b'?'
+		})
+		.collect();
+}
+
+// a4 pages of text in helvetica. the header is on the first page, the totals after the last line
+pub fn render_pdf(invoice: &Invoice, lines: &[InvoiceLine]) -> Vec<u8> {
+	let mut pdf = Pdf::new();
+	let catalog_id = Ref::new(1);
+	let tree_id = Ref::new(2);
+	let font_id = Ref::new(3);
+	let bold_id = Ref::new(4);
+	let font = Name(b"F1");
+	let bold = Name(b"F2");
+
+	let title = match &invoice.number {
+		Some(number) => format!("Invoice {}", number),
+		None => format!("Draft invoice {}", invoice.id)
+	};
+	let mut header = vec![
+		(true, title),
+		(false, String::new()),
+		(false, format!("Customer: {}", invoice.customer_name))
+	];
+	for line in invoice.customer_address.iter().flat_map(|a| a.lines()) {
+		header.push((false, format!("          {}", line)));
+	}
+	if let Some(issue_date) = invoice.issue_date {
+		header.push((false, format!("Issued: {}", issue_date)));
+	}
+	header.push((false, format!("Due: {}", invoice.due_date)));
+	header.push((false, String::new()));
+
+	let mut footer = vec![
+		(false, String::new()),
+		(false, format!("Subtotal: {} {}", format_cents(invoice.subtotal_cents), invoice.currency)),
+		(false, format!("Tax ({}%): {} {}", format_cents(invoice.tax_rate_bp as i64), format_cents(invoice.tax_cents), invoice.currency)),
+		(true, format!("Total: {} {}", format_cents(invoice.total_cents), invoice.currency))
+	];
+	if invoice.paid_cents > 0 {
+		footer.push((false, format!("Paid: {} {}", format_cents(invoice.paid_cents), invoice.currency)));
+		footer.push((true, format!("Due: {} {}", format_cents(invoice.total_cents - invoice.paid_cents), invoice.currency)));
+	}
+	if let Some(notes) = &invoice.notes {
+		footer.push((false, String::new()));
+		footer.extend(notes.lines().map(|l| (false, l.to_string())));
+	}
+
+	let chunks: Vec<&[InvoiceLine]> = if lines.is_empty() { vec![&[]] } else { lines.chunks(LINES_PER_PAGE).collect() };
+	let page_ids: Vec<Ref> = (0..chunks.len()).map(|i| Ref::new(5 + 2 * i as i32)).collect();
+	pdf.catalog(catalog_id).pages(tree_id);
+	pdf.pages(tree_id).kids(page_ids.iter().copied()).count(page_ids.len() as i32);
+
+	for (i, chunk) in chunks.iter().enumerate() {
+		let mut rows = Vec::new();
+		if i == 0 {
+			rows.extend(header.iter().cloned());
+		}
+		rows.push((true, "#    Description                                   Qty        Unit price             Total".to_string()));
+		for line in chunk.iter() {
+			let description: String = line.description.chars().take(40).collect();
+			rows.push((false, format!("{:<4} {:<40} {:>8} {:>17} {:>17}", line.line_number, description, line.quantity,
+				format_cents(line.unit_price_cents), format_cents(line.total_cents))));
+		}
+		if i == chunks.len() - 1 {
+			rows.extend(footer.iter().cloned());
+		}
+
+		let mut content = Content::new();
+		content.begin_text();
+		content.next_line(50.0, 800.0);
+		for (is_bold, text) in rows.iter() {
+			content.set_font(if *is_bold { bold } else { font }, 10.0);
+			content.show(Str(&pdf_text(text)));
+			content.next_line(0.0, -14.0);
+		}
+		content.set_font(font, 8.0);
+		content.show(Str(&pdf_text(&format!("Page {} of {}", i + 1, chunks.len()))));
+		content.end_text();
+
+		let content_id = Ref::new(6 + 2 * i as i32);
+		let mut page = pdf.page(page_ids[i]);
+		page.media_box(Rect::new(0.0, 0.0, 595.0, 842.0));
+		page.parent(tree_id);
+		page.contents(content_id);
+		let mut resources = page.resources();
+		let mut fonts = resources.fonts();
+		fonts.pair(font, font_id);
+		fonts.pair(bold, bold_id);
+		fonts.finish();
+		resources.finish();
+		page.finish();
+		pdf.stream(content_id, &content.finish());
+	}
+
+	pdf.type1_font(font_id).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+	pdf.type1_font(bold_id).base_font(Name(b"Helvetica-Bold")).encoding_predefined(Name(b"WinAnsiEncoding"));
+	return pdf.finish();
+}
+
+async fn read_invoice(conn: &mut PgConnection, id: i32, lock: bool) -> Result<Invoice, StatusCode> {
+	let sql = if lock { "select * from invoices where id=$1 for update" } else { "select * from invoices where id=$1" };
+	let invoice: Result<Option<Invoice>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = invoice {
+		admin_logger(LogType::Error, &format!("Error reading invoice {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return invoice.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_lines(conn: &mut PgConnection, id: i32) -> Result<Vec<InvoiceLine>, StatusCode> {
+	let lines: Result<Vec<InvoiceLine>, _> = sqlx::query_as(
+		"select line_number, description, quantity, unit_price_cents, total_cents from invoice_lines where invoice_id=$1 order by line_number")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = lines {
+		admin_logger(LogType::Error, &format!("Error reading lines of invoice {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(lines.unwrap());
+}
+
+async fn read_payments(conn: &mut PgConnection, id: i32) -> Result<Vec<Payment>, StatusCode> {
+	let payments: Result<Vec<Payment>, _> = sqlx::query_as(
+		"select id, amount_cents, paid_on, method, reference, recorded_by, created_at from invoice_payments where invoice_id=$1 order by paid_on, id")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = payments {
+		admin_logger(LogType::Error, &format!("Error reading payments of invoice {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(payments.unwrap());
+}
+
+// saves the invoice as a draft and starts its approval ticket. it can only be sent once the ticket approved it
+#[utoipa::path(post, path = "/invoices", tag = "invoices", request_body = CreateInvoice, responses(
+	(status = 201, description = "Created as a draft with its approval ticket", body = InvoiceDetail),
+	(status = 403, description = "Missing the manage_invoices permission"),
+	(status = 422, description = "Invalid customer, currency, lines, tax rate or due date", body = FieldErrors)
+))]
+pub async fn create_invoice(
+	auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateInvoice>
+) -> Result<(StatusCode, Json<InvoiceDetail>), (StatusCode, Json<FieldErrors>)> {
+	let user = auth.user;
+	let totals = check_invoice(&payload, chrono::Utc::now().date_naive())
+		.map_err(|errors| (StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })))?;
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let now = chrono::Utc::now();
+	let invoice: Result<Invoice, _> = sqlx::query_as(
+		r#"insert into invoices (customer_name, customer_address, currency, subtotal_cents, tax_rate_bp, tax_cents, total_cents,
+				due_date, notes, created_by, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11) returning *"#)
+		.bind(payload.customer_name.trim())
+		.bind(&payload.customer_address)
+		.bind(&payload.currency)
+		.bind(totals.subtotal)
+		.bind(payload.tax_rate_bp)
+		.bind(totals.tax)
+		.bind(totals.total)
+		.bind(payload.due_date)
+		.bind(&payload.notes)
+		.bind(user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = invoice {
+		admin_logger(LogType::Error, &format!("Error saving invoice of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let mut invoice = invoice.unwrap();
+
+	let mut lines = Vec::with_capacity(payload.lines.len());
+	for (i, (line, line_total)) in payload.lines.iter().zip(totals.lines).enumerate() {
+		let saved: Result<InvoiceLine, _> = sqlx::query_as(
+			r#"insert into invoice_lines (invoice_id, line_number, description, quantity, unit_price_cents, total_cents)
+				values ($1, $2, $3, $4, $5, $6) returning line_number, description, quantity, unit_price_cents, total_cents"#)
+			.bind(invoice.id)
+			.bind(i as i32 + 1)
+			.bind(line.description.trim())
+			.bind(line.quantity)
+			.bind(line.unit_price_cents)
+			.bind(line_total)
+			.fetch_one(&mut *tx)
+			.await;
+		if let Err(e) = saved {
+			admin_logger(LogType::Error, &format!("Error saving line {} of invoice {}: {}", i + 1, invoice.id, e), None);
+			return Err(internal_error());
+		}
+		lines.push(saved.unwrap());
+	}
+
+	let mut data = Map::new();
+	data.insert("invoice_id".to_string(), json!(invoice.id));
+	data.insert("customer_name".to_string(), json!(invoice.customer_name));
+	data.insert("currency".to_string(), json!(invoice.currency));
+	data.insert("total_cents".to_string(), json!(invoice.total_cents));
+	data.insert("due_date".to_string(), json!(invoice.due_date));
+	data.insert("lines".to_string(), json!(payload.lines));
+	let new = NewTicket { process_id: process_id(), data, tag: "invoice", due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "invoices", id: invoice.id }, new).await
+		.map_err(|status| (status, Json(FieldErrors { errors: Vec::new() })))?;
+
+	// the first node may have approved the invoice already
+	let status: Result<(String,), _> = sqlx::query_as("select status from invoices where id=$1")
+		.bind(invoice.id)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = status {
+		admin_logger(LogType::Error, &format!("Error reading invoice {}: {}", invoice.id, e), None);
+		return Err(internal_error());
+	}
+	invoice.status = status.unwrap().0;
+	invoice.ticket_id = Some(ticket.id);
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting invoice {}: {}", invoice.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("Invoice {} of {} {} created by {} with ticket {}", invoice.id, invoice.total_cents, invoice.currency, user.userid, ticket.id), ticket.log_id);
+	return Ok((StatusCode::CREATED, Json(InvoiceDetail { invoice, lines, payments: Vec::new() })));
+}
+
+#[utoipa::path(get, path = "/invoices", tag = "invoices", params(InvoicesQuery), responses(
+	(status = 200, description = "Invoices, newest first", body = Vec<Invoice>),
+	(status = 403, description = "Missing the manage_invoices permission")
+))]
+pub async fn get_invoices(
+	_auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<InvoicesQuery>
+) -> Result<Json<Vec<Invoice>>, StatusCode> {
+	let invoices: Result<Vec<Invoice>, _> = sqlx::query_as("select * from invoices where ($1::varchar is null or status=$1) order by id desc")
+		.bind(&query.status)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = invoices {
+		admin_logger(LogType::Error, &format!("Error reading invoices: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(invoices.unwrap()));
+}
+
+#[utoipa::path(get, path = "/invoices/{id}", tag = "invoices", params(("id" = i32, Path, description = "Invoice id")), responses(
+	(status = 200, description = "The invoice with its lines and payments", body = InvoiceDetail),
+	(status = 403, description = "Missing the manage_invoices permission"),
+	(status = 404, description = "Unknown invoice")
+))]
+pub async fn get_invoice(
+	_auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<InvoiceDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let invoice = read_invoice(&mut conn, id, false).await?;
+	let lines = read_lines(&mut conn, id).await?;
+	let payments = read_payments(&mut conn, id).await?;
+	return Ok(Json(InvoiceDetail { invoice, lines, payments }));
+}
+
+#[utoipa::path(get, path = "/invoices/{id}/pdf", tag = "invoices", params(("id" = i32, Path, description = "Invoice id")), responses(
+	(status = 200, description = "The invoice as a pdf", body = Vec<u8>, content_type = "application/pdf"),
+	(status = 403, description = "Missing the manage_invoices permission"),
+	(status = 404, description = "Unknown invoice")
+))]
+pub async fn get_invoice_pdf(
+	_auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<impl IntoResponse, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let invoice = read_invoice(&mut conn, id, false).await?;
+	let lines = read_lines(&mut conn, id).await?;
+
+	let filename = invoice.number.clone().unwrap_or(format!("draft-{}", invoice.id));
+	return Ok((
+		[
+			(header::CONTENT_TYPE, "application/pdf".to_string()),
+			(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}.pdf\"", filename))
+		],
+		render_pdf(&invoice, &lines)
+	));
+}
+
+// issues an approved invoice: it gets its number and issue date and payments can be recorded for it
+#[utoipa::path(post, path = "/invoices/{id}/send", tag = "invoices", params(("id" = i32, Path, description = "Invoice id")), responses(
+	(status = 200, description = "Numbered and sent", body = Invoice),
+	(status = 403, description = "Missing the manage_invoices permission"),
+	(status = 404, description = "Unknown invoice", body = FieldErrors),
+	(status = 409, description = "The invoice is not approved", body = FieldErrors)
+))]
+pub async fn send_invoice(
+	auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Invoice>, (StatusCode, Json<FieldErrors>)> {
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let invoice = read_invoice(&mut tx, id, true).await.map_err(|status| (status, Json(FieldErrors { errors: Vec::new() })))?;
+	if invoice.status != "approved" {
+		return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError {
+			field: "/status".to_string(),
+			message: format!("Only approved invoices can be sent, this one is {}", invoice.status)
+		}] })));
+	}
+
+	let today = chrono::Utc::now().date_naive();
+	let invoice: Result<Invoice, _> = sqlx::query_as("update invoices set status='sent', number=$2, issue_date=$3, updated_at=$4 where id=$1 returning *")
+		.bind(id)
+		.bind(invoice_number(id, today))
+		.bind(today)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = invoice {
+		admin_logger(LogType::Error, &format!("Error sending invoice {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting invoice {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	let invoice = invoice.unwrap();
+	admin_logger(LogType::Info, &format!("User {} sent invoice {} as {}", auth.user.userid, id, invoice.number.as_deref().unwrap_or_default()), None);
+	return Ok(Json(invoice));
+}
+
+// the invoice is paid once the payments cover its total. overpayments are refused
+#[utoipa::path(post, path = "/invoices/{id}/payments", tag = "invoices", params(("id" = i32, Path, description = "Invoice id")), request_body = RecordPayment, responses(
+	(status = 201, description = "Recorded, the invoice is paid once the payments cover the total", body = InvoiceDetail),
+	(status = 403, description = "Missing the manage_invoices permission"),
+	(status = 404, description = "Unknown invoice", body = FieldErrors),
+	(status = 422, description = "The invoice cannot be paid or the amount is invalid", body = FieldErrors)
+))]
+pub async fn record_payment(
+	auth: Authorized<ManageInvoices>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<RecordPayment>
+) -> Result<(StatusCode, Json<InvoiceDetail>), (StatusCode, Json<FieldErrors>)> {
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let invoice = read_invoice(&mut tx, id, true).await.map_err(|status| (status, Json(FieldErrors { errors: Vec::new() })))?;
+	let (paid, status) = apply_payment(&invoice.status, invoice.total_cents, invoice.paid_cents, payload.amount_cents)
+		.map_err(|message| unprocessable("/amount_cents", message))?;
+
+	let now = chrono::Utc::now();
+	let query = sqlx::query(
+		r#"insert into invoice_payments (invoice_id, amount_cents, paid_on, method, reference, recorded_by, created_at)
+			values ($1, $2, $3, $4, $5, $6, $7)"#)
+		.bind(id)
+		.bind(payload.amount_cents)
+		.bind(payload.paid_on.unwrap_or(now.date_naive()))
+		.bind(&payload.method)
+		.bind(&payload.reference)
+		.bind(auth.user.userid)
+		.bind(now)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error saving payment of invoice {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	let invoice: Result<Invoice, _> = sqlx::query_as("update invoices set paid_cents=$2, status=$3, updated_at=$4 where id=$1 returning *")
+		.bind(id)
+		.bind(paid)
+		.bind(status)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = invoice {
+		admin_logger(LogType::Error, &format!("Error updating invoice {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let invoice = invoice.unwrap();
+	let lines = read_lines(&mut tx, id).await.map_err(|_| internal_error())?;
+	let payments = read_payments(&mut tx, id).await.map_err(|_| internal_error())?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting payment of invoice {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} recorded {} {} paid for invoice {}, now {}", auth.user.userid, payload.amount_cents, invoice.currency, id, status), None);
+	return Ok((StatusCode::CREATED, Json(InvoiceDetail { invoice, lines, payments })));
+}
+
+// sent invoices past their due date become overdue
+pub async fn mark_overdue(pool: &PgPool, today: NaiveDate) -> Result<u64, sqlx::Error> {
+	let query = sqlx::query("update invoices set status='overdue', updated_at=$2 where status='sent' and due_date<$1")
+		.bind(today)
+		.bind(chrono::Utc::now())
+		.execute(pool)
+		.await?;
+	return Ok(query.rows_affected());
+}
+
+pub async fn run_overdue_check(pool: PgPool) {
+	jobs::run_periodic(pool, "invoices_overdue", overdue_interval(), |pool| async move {
+		match mark_overdue(&pool, chrono::Utc::now().date_naive()).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Failed to mark overdue invoices: {}", e), None);
+				return Err(e.to_string());
+			}
+			Ok(0) => {}
+			Ok(count) => admin_logger(LogType::Info, &format!("Marked {} invoices overdue", count), None)
+		}
+		return Ok(());
+	}).await;
+}
+
+// moves the invoice of the ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let invoice: Option<(i32, String)> = sqlx::query_as("select id, status from invoices where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = invoice else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update invoices set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Invoice {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod invoices_tests {
+	use chrono::NaiveDate;
+	use crate::purchase_orders::NewLine;
+	use super::{apply_payment, check_invoice, format_cents, next_status, pdf_text, render_pdf, tax_cents, CreateInvoice, Invoice, InvoiceLine, Totals};
+
+	fn date(text: &str) -> NaiveDate {
+		return text.parse().unwrap();
+	}
+
+	fn invoice(tax_rate_bp: i32) -> CreateInvoice {
+		return CreateInvoice {
+			customer_name: "Acme".to_string(),
+			customer_address: None,
+			currency: "EUR".to_string(),
+			lines: vec![
+				NewLine { description: "consulting".to_string(), quantity: 3, unit_price_cents: 12_345 },
+				NewLine { description: "travel".to_string(), quantity: 1, unit_price_cents: 4_999 }
+			],
+			tax_rate_bp,
+			due_date: date("2024-07-01"),
+			notes: None
+		};
+	}
+
+	#[test]
+	fn tax_is_rounded_half_up() {
+		assert_eq!(tax_cents(1_000, 1_900), Some(190));
+		assert_eq!(tax_cents(5, 1_000), Some(1));
+		assert_eq!(tax_cents(4, 1_000), Some(0));
+		assert_eq!(tax_cents(i64::MAX, 10_000), Some(i64::MAX));
+
+		let totals = check_invoice(&invoice(1_900), date("2024-06-05")).unwrap();
+		assert_eq!(totals, Totals { lines: vec![37_035, 4_999], subtotal: 42_034, tax: 7_986, total: 50_020 });
+	}
+
+	#[test]
+	fn invalid_invoices_are_rejected() {
+		let fields = |invoice: CreateInvoice, today: &str| -> Vec<String> {
+			return check_invoice(&invoice, date(today)).unwrap_err().into_iter().map(|e| e.field).collect();
+		};
+		assert_eq!(fields(invoice(10_001), "2024-06-05"), vec!["/tax_rate_bp"]);
+		assert_eq!(fields(invoice(0), "2024-07-02"), vec!["/due_date"]);
+		let mut costly = invoice(1);
+		costly.lines[0].unit_price_cents = i64::MAX / 3;
+		costly.lines[1].unit_price_cents = i64::MAX / 3;
+		assert_eq!(fields(costly, "2024-06-05"), vec!["/lines"]);
+	}
+
+	#[test]
+	fn statuses_follow_approval_and_payments() {
+		assert_eq!(next_status("draft", "closed"), Some("approved"));
+		assert_eq!(next_status("draft", "rejected"), Some("rejected"));
+		// a sent invoice does not go back when its ticket ends
+		assert_eq!(next_status("sent", "cancelled"), None);
+
+		assert_eq!(apply_payment("sent", 1_000, 0, 400), Ok((400, "sent")));
+		assert_eq!(apply_payment("overdue", 1_000, 400, 100), Ok((500, "overdue")));
+		assert_eq!(apply_payment("overdue", 1_000, 400, 600), Ok((1_000, "paid")));
+		assert!(apply_payment("sent", 1_000, 400, 601).is_err());
+		assert!(apply_payment("approved", 1_000, 0, 100).is_err());
+		assert!(apply_payment("sent", 1_000, 0, 0).is_err());
+	}
+
+	#[test]
+	fn pdf_has_a_page_per_thirty_lines() {
+		assert_eq!(format_cents(123_456), "1234.56");
+		assert_eq!(format_cents(5), "0.05");
+
+		let now = chrono::Utc::now();
+		let invoice = Invoice {
+			id: 42, number: Some("INV-2024-000042".to_string()), customer_name: "Müller € Co".to_string(), customer_address: Some("Main st 1\nBerlin".to_string()),
+			currency: "EUR".to_string(), subtotal_cents: 1_000, tax_rate_bp: 1_900, tax_cents: 190, total_cents: 1_190, paid_cents: 0,
+			issue_date: Some(date("2024-06-05")), due_date: date("2024-07-01"), status: "sent".to_string(), notes: None, ticket_id: Some(7),
+			created_by: uuid::Uuid::nil(), created_at: now, updated_at: now
+		};
+		let lines: Vec<InvoiceLine> = (1..=31)
+			.map(|n| InvoiceLine { line_number: n, description: "item".to_string(), quantity: 1, unit_price_cents: 10, total_cents: 10 })
+			.collect();
+		let pdf = render_pdf(&invoice, &lines);
+		let text = String::from_utf8_lossy(&pdf);
+		assert!(pdf.starts_with(b"%PDF-"));
+		assert!(text.contains("/Count 2"));
+		assert!(text.contains("(Invoice INV-2024-000042)"));
+		assert_eq!(pdf_text("Müller € Co ✓"), b"M\xfcller \x80 Co ?");
+	}
+}
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::time::Duration;
+use axum::{extract, http::StatusCode, Json};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageProcesses};
+use crate::shutdown;
+use utoipa::ToSchema;
+
+// identifies the leases of this server
+static INSTANCE: Lazy<uuid::Uuid> = Lazy::new(uuid::Uuid::new_v4);
+
+// how often every instance looks for due jobs
+fn poll_interval() -> Duration {
+	let secs = std::env::var("JOB_POLL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(30);
+	return Duration::from_secs(secs);
+}
+
+// a job still running after this is taken over by another instance
+fn lease_duration() -> chrono::Duration {
+	let secs = std::env::var("JOB_LEASE_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(600);
+	return chrono::Duration::seconds(secs);
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct PeriodicJob {
+	pub name: String,
+	pub next_run_at: chrono::DateTime<chrono::Utc>,
+	pub leased_by: Option<uuid::Uuid>,
+	pub leased_until: Option<chrono::DateTime<chrono::Utc>>,
+	pub last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub last_error: Option<String>
+}
+
+// claims the job if it is due and not leased by a running instance. the next run is planned right away so a
+// crashed run is only retried once the lease runs out
+pub async fn lease(pool: &PgPool, name: &str, every: Duration, now: chrono::DateTime<chrono::Utc>) -> Result<bool, sqlx::Error> {
+	let mut tx = pool.begin().await?;
+	// new jobs are due immediately
+	sqlx::query("insert into periodic_jobs (name, next_run_at) values ($1, $2) on conflict (name) do nothing")
+		.bind(name)
+		.bind(now)
+		.execute(&mut *tx)
+		.await?;
+
+	let due: Option<(String,)> = sqlx::query_as(
+		r#"select name from periodic_jobs where name=$1 and next_run_at<=$2 and (leased_until is null or leased_until<$2)
+			for update skip locked"#)
+		.bind(name)
+		.bind(now)
+		.fetch_optional(&mut *tx)
+		.await?;
+	if due.is_none() {
+		tx.commit().await?;
+		return Ok(false);
+	}
+
+	let every = chrono::Duration::from_std(every).unwrap_or(chrono::Duration::zero());
+	sqlx::query("update periodic_jobs set leased_by=$2, leased_until=$3, next_run_at=$4 where name=$1")
+		.bind(name)
+		.bind(*INSTANCE)
+		.bind(now + lease_duration())
+		.bind(now + every)
+		.execute(&mut *tx)
+		.await?;
+	tx.commit().await?;
+	return Ok(true);
+}
+
+// a lease taken over by another instance is left alone
+pub async fn finish(pool: &PgPool, name: &str, error: Option<String>) -> Result<(), sqlx::Error> {
+	sqlx::query("update periodic_jobs set leased_by=null, leased_until=null, last_run_at=$3, last_error=$4 where name=$1 and leased_by=$2")
+		.bind(name)
+		.bind(*INSTANCE)
+		.bind(chrono::Utc::now())
+		.bind(error)
+		.execute(pool)
+		.await?;
+	return Ok(());
+}
+
+// runs the job every `every` on exactly one of the instances sharing the db. work that already claims its
+// rows with skip locked, like the outbox and the callback jobs, does not need this
+pub async fn run_periodic<F, Fut>(pool: PgPool, name: &'static str, every: Duration, job: F)
+where
+	F: Fn(PgPool) -> Fut,
+	Fut: Future<Output = Result<(), String>>
+{
+	let mut interval = tokio::time::interval(every.min(poll_interval()));
+	while shutdown::tick(&mut interval).await {
+		match lease(&pool, name, every, chrono::Utc::now()).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error leasing job {}: {}", name, e), None);
+				continue;
+			}
+			Ok(false) => continue,
+			Ok(true) => {}
+		}
+
+		let error = job(pool.clone()).await.err();
+		if let Err(e) = finish(&pool, name, error).await {
+			admin_logger(LogType::Error, &format!("Error finishing job {}: {}", name, e), None);
+		}
+	}
+}
+
+#[utoipa::path(get, path = "/admin/jobs", tag = "admin", responses(
+	(status = 200, description = "Periodic jobs and their leases", body = Vec<PeriodicJob>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_jobs(
+	_auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<PeriodicJob>>, StatusCode> {
+	let jobs: Result<Vec<PeriodicJob>, _> = sqlx::query_as("select * from periodic_jobs order by name")
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = jobs {
+		admin_logger(LogType::Error, &format!("Error reading periodic jobs: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(jobs.unwrap()));
+}
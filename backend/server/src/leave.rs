@@ -0,0 +1,488 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::rbac::{self, Authorized, ManageLeave};
+use crate::schema::{FieldError, FieldErrors, internal_error, unprocessable};
+use crate::ticket;
+use utoipa::ToSchema;
+
+// statuses still holding the dates of a request
+const HOLDING_STATUSES: [&str; 2] = ["pending_approval", "approved"];
+
+// the process whose tickets approve leave requests
+fn process_id() -> String {
+	return std::env::var("LEAVE_PROCESS").unwrap_or("leave".to_string());
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateLeaveRequest {
+	pub leave_type: String,
+	// both included
+	pub start_date: NaiveDate,
+	pub end_date: NaiveDate,
+	pub reason: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct LeaveRequest {
+	pub id: i32,
+	pub userid: uuid::Uuid,
+	pub leave_type: String,
+	pub start_date: NaiveDate,
+	pub end_date: NaiveDate,
+	pub days: i32,
+	pub reason: Option<String>,
+	pub status: String,
+	pub ticket_id: Option<i32>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+// a request of someone in a department of the requester taking some of the same days
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct LeaveConflict {
+	pub request_id: i32,
+	pub userid: uuid::Uuid,
+	pub username: String,
+	pub start_date: NaiveDate,
+	pub end_date: NaiveDate,
+	pub status: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LeaveRequestDetail {
+	#[serde(flatten)]
+	pub request: LeaveRequest,
+	pub conflicts: Vec<LeaveConflict>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct LeaveBalance {
+	pub userid: uuid::Uuid,
+	pub leave_type: String,
+	pub year: i32,
+	pub total_days: i32,
+	pub used_days: i32,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetLeaveBalance {
+	pub userid: uuid::Uuid,
+	pub leave_type: String,
+	pub year: i32,
+	pub total_days: i32
+}
+
+// monday to friday between the dates, both included
+pub fn working_days(start: NaiveDate, end: NaiveDate) -> i32 {
+	return start.iter_days()
+		.take_while(|day| *day <= end)
+		.filter(|day| !matches!(day.weekday(), Weekday::Sat | Weekday::Sun))
+		.count() as i32;
+}
+
+// the working days the request takes, or what is wrong with it
+pub fn check_request(request: &CreateLeaveRequest) -> Result<i32, Vec<FieldError>> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if request.leave_type.trim().is_empty() {
+		error("/leave_type", "A leave type is required");
+	}
+	let days = working_days(request.start_date, request.end_date);
+	if request.end_date < request.start_date {
+		error("/end_date", "Cannot be before the start date");
+	} else if request.end_date.year() != request.start_date.year() {
+		// balances are per year
+		error("/end_date", "Requests cannot span two years, split them at the new year");
+	} else if days == 0 {
+		error("/end_date", "The dates do not include a working day");
+	}
+
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	return Ok(days);
+}
+
+// the status a request in `current` moves to when its ticket asks for `target`. a closed ticket approves the request
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("pending_approval", "approved" | "closed") => Some("approved"),
+		("pending_approval" | "approved", "rejected") => Some("rejected"),
+		("pending_approval" | "approved", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+// pending and approved requests of the people sharing a department with `userid` that overlap the dates
+pub async fn team_conflicts(
+	conn: &mut PgConnection,
+	userid: uuid::Uuid,
+	start_date: NaiveDate,
+	end_date: NaiveDate
+) -> Result<Vec<LeaveConflict>, sqlx::Error> {
+	return sqlx::query_as(
+		r#"select r.id as request_id, r.userid, u.username, r.start_date, r.end_date, r.status from leave_requests r
+			join users u on u.userid=r.userid
+			where r.userid<>$1 and r.status=any($4) and r.start_date<=$3 and r.end_date>=$2
+				and r.userid in (select team.userid from user_departments team
+					join user_departments mine on mine.department_id=team.department_id where mine.userid=$1)
+			order by r.start_date, r.id"#)
+		.bind(userid)
+		.bind(start_date)
+		.bind(end_date)
+		.bind(&HOLDING_STATUSES[..])
+		.fetch_all(&mut *conn)
+		.await;
+}
+
+// saves the request and starts its approval ticket. the days are only taken from the balance once it is approved,
+// the approver sees the requests of the team overlapping it in the ticket state
+#[utoipa::path(post, path = "/leave_requests", tag = "leave", request_body = CreateLeaveRequest, responses(
+	(status = 201, description = "Created with its approval ticket, with the overlapping leave of the team", body = LeaveRequestDetail),
+	(status = 422, description = "Invalid dates or type, or not enough balance", body = FieldErrors)
+))]
+pub async fn create_leave_request(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateLeaveRequest>
+) -> Result<(StatusCode, Json<LeaveRequestDetail>), (StatusCode, Json<FieldErrors>)> {
+	let days = check_request(&payload)
+		.map_err(|errors| (StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })))?;
+	let leave_type = payload.leave_type.trim();
+	let year = payload.start_date.year();
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let balance: Result<Option<(i32, i32)>, _> = sqlx::query_as("select total_days, used_days from leave_balances where userid=$1 and leave_type=$2 and year=$3")
+		.bind(user.userid)
+		.bind(leave_type)
+		.bind(year)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = balance {
+		admin_logger(LogType::Error, &format!("Error reading the leave balance of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let remaining = balance.unwrap().map(|(total, used)| total - used).unwrap_or(0);
+	if remaining < days {
+		return Err(unprocessable("/end_date", format!("The request takes {} days, {} days of {} leave are left in {}", days, remaining, leave_type, year)));
+	}
+
+	let now = chrono::Utc::now();
+	let request: Result<LeaveRequest, _> = sqlx::query_as(
+		r#"insert into leave_requests (userid, leave_type, start_date, end_date, days, reason, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $7) returning *"#)
+		.bind(user.userid)
+		.bind(leave_type)
+		.bind(payload.start_date)
+		.bind(payload.end_date)
+		.bind(days)
+		.bind(&payload.reason)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = request {
+		admin_logger(LogType::Error, &format!("Error saving leave request of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let mut request = request.unwrap();
+
+	let conflicts = team_conflicts(&mut tx, user.userid, request.start_date, request.end_date).await;
+	if let Err(e) = conflicts {
+		admin_logger(LogType::Error, &format!("Error reading the leave conflicts of request {}: {}", request.id, e), None);
+		return Err(internal_error());
+	}
+	let conflicts = conflicts.unwrap();
+
+	let mut data = Map::new();
+	data.insert("leave_request_id".to_string(), json!(request.id));
+	data.insert("leave_type".to_string(), json!(request.leave_type));
+	data.insert("start_date".to_string(), json!(request.start_date));
+	data.insert("end_date".to_string(), json!(request.end_date));
+	data.insert("days".to_string(), json!(days));
+	data.insert("remaining_days".to_string(), json!(remaining));
+	data.insert("conflicts".to_string(), json!(conflicts));
+	let new = NewTicket { process_id: process_id(), data, tag: "leave", due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "leave_requests", id: request.id }, new).await
+		.map_err(|status| (status, Json(FieldErrors { errors: Vec::new() })))?;
+
+	// the first node may have moved the request already
+	let status: Result<(String,), _> = sqlx::query_as("select status from leave_requests where id=$1")
+		.bind(request.id)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = status {
+		admin_logger(LogType::Error, &format!("Error reading leave request {}: {}", request.id, e), None);
+		return Err(internal_error());
+	}
+	request.status = status.unwrap().0;
+	request.ticket_id = Some(ticket.id);
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting leave request {}: {}", request.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("Leave request {} of {} for {} days created with ticket {}, {} conflicts", request.id, user.userid, days, ticket.id, conflicts.len()), ticket.log_id);
+	return Ok((StatusCode::CREATED, Json(LeaveRequestDetail { request, conflicts })));
+}
+
+#[utoipa::path(get, path = "/leave_requests", tag = "leave", responses(
+	(status = 200, description = "Own leave requests", body = Vec<LeaveRequest>)
+))]
+pub async fn get_leave_requests(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<LeaveRequest>>, StatusCode> {
+	let requests: Result<Vec<LeaveRequest>, _> = sqlx::query_as("select * from leave_requests where userid=$1 order by start_date desc, id desc")
+		.bind(user.userid)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = requests {
+		admin_logger(LogType::Error, &format!("Error reading leave requests of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(requests.unwrap()));
+}
+
+// for the requester, the approvers of its ticket and manage_leave holders. conflicts are read again, the team may have
+// asked for the same days since the request was made
+#[utoipa::path(get, path = "/leave_requests/{id}", tag = "leave", params(("id" = i32, Path, description = "Leave request id")), responses(
+	(status = 200, description = "The request with the overlapping leave of the team", body = LeaveRequestDetail),
+	(status = 404, description = "Unknown request or not visible to the user")
+))]
+pub async fn get_leave_request(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<LeaveRequestDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let request: Result<Option<LeaveRequest>, _> = sqlx::query_as("select * from leave_requests where id=$1")
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = request {
+		admin_logger(LogType::Error, &format!("Error reading leave request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let request = request.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	if request.userid != user.userid {
+		let approver: Result<(bool,), _> = sqlx::query_as("select exists(select 1 from user_active_tickets where ticketid=$1 and userid=$2 and type_='approve')")
+			.bind(request.ticket_id)
+			.bind(user.userid)
+			.fetch_one(&mut *conn)
+			.await;
+		let allowed = match approver {
+			Ok((true,)) => Ok(true),
+			Ok((false,)) => rbac::has_permission(&mut conn, user.userid, "manage_leave").await,
+			Err(e) => Err(e)
+		};
+		match allowed {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking access of {} to leave request {}: {}", user.userid, id, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			// the same answer as for requests that do not exist
+			Ok(false) => return Err(StatusCode::NOT_FOUND),
+			Ok(true) => {}
+		}
+	}
+
+	let conflicts = team_conflicts(&mut conn, request.userid, request.start_date, request.end_date).await;
+	if let Err(e) = conflicts {
+		admin_logger(LogType::Error, &format!("Error reading the leave conflicts of request {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(LeaveRequestDetail { request, conflicts: conflicts.unwrap() }));
+}
+
+#[utoipa::path(get, path = "/users/{id}/leave_balances", tag = "leave", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Balances of the user by type and year", body = Vec<LeaveBalance>),
+	(status = 403, description = "Only the own balances or with manage_leave")
+))]
+pub async fn get_leave_balances(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<Json<Vec<LeaveBalance>>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if user.userid != userid {
+		match rbac::has_permission(&mut conn, user.userid, "manage_leave").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN),
+			Ok(true) => {}
+		}
+	}
+
+	let balances: Result<Vec<LeaveBalance>, _> = sqlx::query_as(
+		"select userid, leave_type, year, total_days, used_days, updated_at from leave_balances where userid=$1 order by year desc, leave_type")
+		.bind(userid)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = balances {
+		admin_logger(LogType::Error, &format!("Error reading leave balances of {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(balances.unwrap()));
+}
+
+// sets the days a user may take. days already used stay used
+#[utoipa::path(put, path = "/admin/leave_balances", tag = "leave", request_body = SetLeaveBalance, responses(
+	(status = 200, description = "Saved", body = LeaveBalance),
+	(status = 403, description = "Missing the manage_leave permission"),
+	(status = 422, description = "Unknown user or invalid days", body = FieldErrors)
+))]
+pub async fn set_leave_balance(
+	auth: Authorized<ManageLeave>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SetLeaveBalance>
+) -> Result<Json<LeaveBalance>, (StatusCode, Json<FieldErrors>)> {
+	if payload.leave_type.trim().is_empty() {
+		return Err(unprocessable("/leave_type", "A leave type is required".to_string()));
+	}
+	if payload.total_days < 0 {
+		return Err(unprocessable("/total_days", "Cannot be negative".to_string()));
+	}
+
+	let balance: Result<LeaveBalance, _> = sqlx::query_as(
+		r#"insert into leave_balances (userid, leave_type, year, total_days, updated_at) values ($1, $2, $3, $4, $5)
+			on conflict (userid, leave_type, year) do update set total_days=excluded.total_days, updated_at=excluded.updated_at
+			returning userid, leave_type, year, total_days, used_days, updated_at"#)
+		.bind(payload.userid)
+		.bind(payload.leave_type.trim())
+		.bind(payload.year)
+		.bind(payload.total_days)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	match balance {
+		Err(sqlx::Error::Database(e)) if e.is_check_violation() => {
+			return Err(unprocessable("/total_days", "Cannot be less than the days already used".to_string()));
+		}
+		Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+			return Err(unprocessable("/userid", "User does not exist".to_string()));
+		}
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error setting the leave balance of {}: {}", payload.userid, e), None);
+			return Err(internal_error());
+		}
+		Ok(balance) => {
+			admin_logger(LogType::Info, &format!("User {} set {} leave of {} in {} to {} days", auth.user.userid, balance.leave_type, balance.userid, balance.year, balance.total_days), None);
+			return Ok(Json(balance));
+		}
+	}
+}
+
+// moves the request of the ticket along with it, see linked::follow. approving takes the days from the balance in the
+// same statement that checks they are left, rejecting or cancelling an approved request gives them back
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let request: Option<LeaveRequest> = sqlx::query_as("select * from leave_requests where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some(request) = request else {
+		return Ok(());
+	};
+
+	let mut status = request.status.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == request.status {
+		return Ok(());
+	}
+
+	let year = request.start_date.year();
+	if status == "approved" {
+		let taken = sqlx::query(
+			r#"update leave_balances set used_days=used_days+$4, updated_at=$5
+				where userid=$1 and leave_type=$2 and year=$3 and used_days+$4<=total_days"#)
+			.bind(request.userid)
+			.bind(&request.leave_type)
+			.bind(year)
+			.bind(request.days)
+			.bind(chrono::Utc::now())
+			.execute(&mut *conn)
+			.await?;
+		if taken.rows_affected() == 0 {
+			return Err(FollowErr::Refused(format!("Not enough {} leave left in {} for the {} days of request {}", request.leave_type, year, request.days, request.id)));
+		}
+	} else if request.status == "approved" {
+		sqlx::query(
+			r#"update leave_balances set used_days=greatest(used_days-$4, 0), updated_at=$5
+				where userid=$1 and leave_type=$2 and year=$3"#)
+			.bind(request.userid)
+			.bind(&request.leave_type)
+			.bind(year)
+			.bind(request.days)
+			.bind(chrono::Utc::now())
+			.execute(&mut *conn)
+			.await?;
+	}
+
+	sqlx::query("update leave_requests set status=$2, updated_at=$3 where id=$1")
+		.bind(request.id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Leave request {} moved from {} to {} by ticket {}", request.id, request.status, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod leave_tests {
+	use chrono::NaiveDate;
+	use super::{check_request, next_status, working_days, CreateLeaveRequest};
+
+	fn date(text: &str) -> NaiveDate {
+		return text.parse().unwrap();
+	}
+
+	fn request(start: &str, end: &str) -> CreateLeaveRequest {
+		return CreateLeaveRequest { leave_type: "vacation".to_string(), start_date: date(start), end_date: date(end), reason: None };
+	}
+
+	#[test]
+	fn weekends_are_not_counted() {
+		// friday to the tuesday after
+		assert_eq!(working_days(date("2024-06-07"), date("2024-06-11")), 3);
+		assert_eq!(working_days(date("2024-06-08"), date("2024-06-09")), 0);
+		assert_eq!(working_days(date("2024-06-10"), date("2024-06-10")), 1);
+	}
+
+	#[test]
+	fn requests_are_checked() {
+		assert_eq!(check_request(&request("2024-06-03", "2024-06-14")), Ok(10));
+
+		let field = |r: CreateLeaveRequest| check_request(&r).unwrap_err()[0].field.clone();
+		assert_eq!(field(request("2024-06-14", "2024-06-03")), "/end_date");
+		assert_eq!(field(request("2024-12-30", "2025-01-03")), "/end_date");
+		assert_eq!(field(request("2024-06-08", "2024-06-09")), "/end_date");
+		let mut untyped = request("2024-06-03", "2024-06-03");
+		untyped.leave_type = " ".to_string();
+		assert_eq!(field(untyped), "/leave_type");
+	}
+
+	#[test]
+	fn closing_the_ticket_approves() {
+		assert_eq!(next_status("pending_approval", "closed"), Some("approved"));
+		assert_eq!(next_status("pending_approval", "approved"), Some("approved"));
+		assert_eq!(next_status("approved", "closed"), None);
+		assert_eq!(next_status("approved", "cancelled"), Some("cancelled"));
+		assert_eq!(next_status("rejected", "approved"), None);
+	}
+}
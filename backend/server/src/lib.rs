@@ -0,0 +1,239 @@
+// i dont like implicit returns. sometimes explicitly saying return is nicer when rereading code
+#![allow(clippy::needless_return)]
+
+use axum::{extract::DefaultBodyLimit, middleware, routing::{delete, get, post, put}, Router};
+use sqlx::PgPool;
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
+
+pub mod process;
+pub mod users;
+pub mod roles;
+pub mod db_types;
+pub mod ticket;
+pub mod utils;
+pub mod logger;
+pub mod redact;
+pub mod notif_handler;
+pub mod callbacks;
+pub mod callback_endpoints;
+pub mod callback_transport;
+pub mod breaker;
+pub mod schema;
+pub mod signals;
+pub mod script;
+pub mod delegation;
+pub mod admin;
+pub mod audit;
+pub mod reminders;
+pub mod tags;
+pub mod watchers;
+pub mod schedules;
+pub mod archive;
+pub mod export;
+pub mod auth;
+pub mod rbac;
+pub mod api_keys;
+pub mod departments;
+pub mod visibility;
+pub mod ws;
+pub mod notifications;
+pub mod templates;
+pub mod digests;
+pub mod slack;
+pub mod outbox;
+pub mod push;
+pub mod migrations;
+pub mod replica;
+pub mod jobs;
+pub mod tenant;
+pub mod ratelimit;
+pub mod shutdown;
+pub mod openapi;
+pub mod grpc;
+pub mod webhooks;
+pub mod events;
+pub mod import;
+pub mod calendar;
+pub mod linked;
+pub mod purchase_orders;
+pub mod leave;
+pub mod invoices;
+pub mod vendors;
+pub mod assets;
+pub mod timesheets;
+pub mod documents;
+pub mod onboarding;
+pub mod crm;
+pub mod limits;
+pub mod working_time;
+pub mod dashboard;
+pub mod reports;
+pub mod api_error;
+pub mod extractors;
+pub mod repository;
+pub mod ticket_events;
+
+// every route with its middleware. main serves it, the tests in tests/ send requests to it directly
+pub fn app(pool: PgPool, cors: CorsLayer) -> Router {
+	return Router::new()
+		.route("/", get(say_hello))
+		.route("/openapi.json", get(openapi::get_openapi))
+		.route("/docs", get(openapi::get_docs))
+		.route("/process/all", get(process::get_all_processes))
+		.route("/process", get(process::get_process_data))
+		.route("/process", post(process::create_process))
+		.route("/process/import/bpmn", post(process::import_bpmn))
+		.route("/process/:id/callback_secret", post(callbacks::rotate_callback_secret))
+		.route("/auth/login", post(auth::login))
+		.route("/auth/refresh", post(auth::refresh))
+		.route("/auth/logout", post(auth::logout))
+		.route("/auth/password", post(auth::change_password))
+		.route("/users", post(users::create_user))
+		.route("/userid", get(users::get_userid))
+		.route("/users/:id/roles", get(roles::get_user_roles))
+		.route("/users/:id/calendar.ics", get(calendar::get_calendar))
+		.route("/users/:id/calendar_token", post(calendar::create_calendar_token).delete(calendar::delete_calendar_token))
+		.route("/purchase_orders", get(purchase_orders::get_purchase_orders).post(purchase_orders::create_purchase_order))
+		.route("/purchase_orders/:id", get(purchase_orders::get_purchase_order))
+		.route("/leave_requests", get(leave::get_leave_requests).post(leave::create_leave_request))
+		.route("/leave_requests/:id", get(leave::get_leave_request))
+		.route("/users/:id/leave_balances", get(leave::get_leave_balances))
+		.route("/invoices", get(invoices::get_invoices).post(invoices::create_invoice))
+		.route("/invoices/:id", get(invoices::get_invoice))
+		.route("/invoices/:id/pdf", get(invoices::get_invoice_pdf))
+		.route("/invoices/:id/send", post(invoices::send_invoice))
+		.route("/invoices/:id/payments", post(invoices::record_payment))
+		.route("/vendors", get(vendors::get_vendors).post(vendors::create_vendor))
+		.route("/vendors/:id", get(vendors::get_vendor).put(vendors::update_vendor).delete(vendors::deactivate_vendor))
+		.route("/customers", get(crm::get_customers).post(crm::create_customer))
+		.route("/customers/:id", get(crm::get_customer).put(crm::update_customer).delete(crm::deactivate_customer))
+		.route("/customers/:id/contacts", post(crm::add_contact))
+		.route("/customers/:id/contacts/:contact_id", put(crm::update_contact).delete(crm::delete_contact))
+		.route("/opportunities", get(crm::get_opportunities).post(crm::create_opportunity))
+		.route("/opportunities/:id", get(crm::get_opportunity).put(crm::update_opportunity))
+		.route("/opportunities/:id/stage", post(crm::move_opportunity))
+		.route("/assets", get(assets::get_assets).post(assets::create_asset))
+		.route("/assets/:id", get(assets::get_asset).put(assets::update_asset).delete(assets::retire_asset))
+		.route("/asset_requests", get(assets::get_asset_requests).post(assets::create_asset_request))
+		.route("/asset_requests/:id", get(assets::get_asset_request))
+		.route("/users/:id/assets", get(assets::get_user_assets))
+		.route("/timesheets", get(timesheets::get_timesheets))
+		.route("/timesheets/:week", get(timesheets::get_timesheet).put(timesheets::save_timesheet))
+		.route("/timesheets/:week/submit", post(timesheets::submit_timesheet))
+		.route("/documents", get(documents::get_documents).post(documents::create_document))
+		.route("/documents/:id", get(documents::get_document))
+		.route("/documents/:id/checkout", post(documents::check_out).delete(documents::undo_check_out))
+		.route("/documents/:id/versions", post(documents::check_in).layer(DefaultBodyLimit::max(documents::max_bytes())))
+		.route("/documents/:id/versions/:version", get(documents::get_version))
+		.route("/documents/:id/submit", post(documents::submit_document))
+		.route("/documents/:id/acl", put(documents::set_acl))
+		.route("/is_admin", get(users::is_admin))
+		.route("/roles", post(roles::create_role).route_layer(middleware::from_fn(ratelimit::limit_writes)))
+		.route("/roles", get(roles::get_all_roles))
+		.route("/roles/assign", post(roles::assign_role).delete(roles::unassign_role))
+		.route("/roles/:role/users", get(roles::get_role_users))
+		.route("/roles/:id", put(roles::update_role).delete(roles::delete_role))
+		.route("/roles/:role/permissions", put(rbac::set_role_permissions))
+		.route("/roles/:role/limit", put(limits::set_role_limit).delete(limits::remove_role_limit))
+		.route("/role_limits", get(limits::get_role_limits))
+		.route("/work_calendars", get(working_time::get_calendars))
+		.route("/work_calendars/default", put(working_time::save_default_calendar))
+		.route("/departments/:id/calendar", put(working_time::save_department_calendar).delete(working_time::remove_department_calendar))
+		.route("/holidays", get(working_time::get_holidays).post(working_time::create_holiday))
+		.route("/holidays/:id", delete(working_time::delete_holiday))
+		.route("/new_user", post(users::register_new_user))
+		.route("/new_user", get(users::get_all_new_users))
+		.route("/new_user/approved", get(users::check_user_approved))
+		.route("/ticket", post(ticket::create_ticket).route_layer(middleware::from_fn(ratelimit::limit_writes)))
+		.route("/ticket/user", get(ticket::get_user_tickets))
+		.route("/ticket/update", post(ticket::update_ticket).route_layer(middleware::from_fn(ratelimit::limit_writes)))
+		.route("/ticket/cancel", post(ticket::cancel_ticket))
+		.route("/ticket/:id/submit", post(ticket::submit_ticket))
+		.route("/ticket/:id", get(ticket::get_ticket))
+		.route("/ticket/:id/history", get(ticket::get_ticket_history))
+		.route("/ticket/:id/callbacks", get(ticket::get_ticket_callbacks))
+		.route("/ticket/:id/tags", post(tags::update_tags))
+		.route("/ticket/:id/watch", post(watchers::watch_ticket).delete(watchers::unwatch_ticket))
+		.route("/ticket/:id/signal/:signal_name", post(signals::signal_ticket))
+		.route("/ticket/:id/callback_result", post(callbacks::callback_result))
+		.route("/ticket/:id/documents", get(documents::get_ticket_documents).post(documents::attach_document))
+		.route("/ticket/:id/documents/:document_id", delete(documents::detach_document))
+		.route("/admin/ticket/:id/reassign", post(admin::reassign_ticket))
+		.route("/admin/ticket/:id/events", get(ticket_events::get_ticket_events))
+		.route("/admin/ticket/:id/replay", post(ticket_events::replay_ticket))
+		.route("/tickets/overdue", get(admin::get_overdue_tickets))
+		.route("/tickets/export", get(export::export_tickets))
+		.route("/tickets/public", get(ticket::get_public_tickets))
+		.route("/admin/callbacks/dead", get(callbacks::get_dead_jobs))
+		.route("/admin/callbacks/:id/retry", post(callbacks::retry_dead_job))
+		.route("/admin/callbacks/breakers", get(breaker::get_breakers))
+		.route("/admin/jobs", get(jobs::get_jobs))
+		.route("/admin/dashboard", get(dashboard::get_dashboard))
+		.route("/admin/reports/aging", get(dashboard::get_aging))
+		.route("/admin/reports/refresh", post(reports::refresh_reports))
+		.route("/admin/callback_endpoints", put(callback_endpoints::save_endpoint).get(callback_endpoints::get_endpoints))
+		.route("/admin/callback_endpoints/:id", delete(callback_endpoints::delete_endpoint))
+		.route("/admin/webhooks", post(webhooks::create_subscription).get(webhooks::get_subscriptions))
+		.route("/admin/webhooks/:id", delete(webhooks::delete_subscription))
+		.route("/admin/webhooks/:id/deliveries", get(webhooks::get_deliveries))
+		.route("/admin/webhook_deliveries/:id/retry", post(webhooks::retry_delivery))
+		.route("/admin/tickets/archive", post(admin::archive_tickets))
+		.route("/admin/tickets/import", post(import::import_tickets))
+		.route("/admin/purchase_orders", get(purchase_orders::get_all_purchase_orders))
+		.route("/admin/leave_balances", put(leave::set_leave_balance))
+		.route("/admin/asset_requests/:id/fulfil", post(assets::retry_fulfilment))
+		.route("/admin/timesheets", get(timesheets::get_all_timesheets))
+		.route("/admin/timesheets/projects", get(timesheets::get_project_hours))
+		.route("/admin/logs", get(admin::get_logs))
+		.route("/admin/logs/metrics", get(admin::get_log_metrics))
+		.route("/admin/audit", get(audit::get_audit_events))
+		.route("/admin/audit/verify", get(audit::verify_audit_chain))
+		.route("/admin/users", post(users::add_user).get(users::list_users))
+		.route("/admin/users/:id", get(users::get_user).put(users::update_user))
+		.route("/admin/users/:id/deactivate", post(users::deactivate_user))
+		.route("/admin/users/:id/activate", post(users::activate_user))
+		.route("/admin/users/:id/slack", put(slack::link_slack_user))
+		.route("/admin/users/:id/sessions", delete(admin::revoke_sessions))
+		.route("/admin/api_keys", post(api_keys::create_api_key).get(api_keys::get_api_keys))
+		.route("/admin/api_keys/:id/rotate", post(api_keys::rotate_api_key))
+		.route("/admin/api_keys/:id", delete(api_keys::revoke_api_key))
+		.route("/slack/interactions", post(slack::slack_interaction))
+		.route("/service/ticket/update", post(api_keys::complete_task))
+		.route("/service/assets/fulfil", post(assets::fulfil_task))
+		.route("/service/onboarding/equipment", post(onboarding::equipment_task))
+		.route("/service/onboarding/:ticket_id", get(onboarding::get_employee))
+		.route("/departments", post(departments::create_department).get(departments::get_departments))
+		.route("/departments/:id", put(departments::update_department).delete(departments::delete_department))
+		.route("/departments/:id/members", get(departments::get_members).post(departments::add_member))
+		.route("/departments/:id/members/:userid", delete(departments::remove_member))
+		.route("/departments/:id/onboarding/:kind", get(onboarding::get_config).put(onboarding::save_config))
+		.route("/onboardings", get(onboarding::get_onboardings).post(onboarding::create_onboarding))
+		.route("/onboardings/:id", get(onboarding::get_onboarding))
+		.route("/onboardings/:id/acknowledgments", post(onboarding::acknowledge_policies))
+		.route("/delegations", post(delegation::create_delegation))
+		.route("/delegations", get(delegation::get_delegations))
+		.route("/schedules", post(schedules::create_schedule))
+		.route("/schedules", get(schedules::get_schedules))
+		.route("/schedules/:id", delete(schedules::delete_schedule))
+		.route("/notifier/request_token", post(notif_handler::gen_token))
+		.route("/user/locale", put(templates::set_locale))
+		.route("/admin/notification_templates", put(templates::save_template).get(templates::get_templates))
+		.route("/admin/notification_templates/:id", delete(templates::delete_template))
+		.route("/notifications/settings", get(notifications::get_settings).put(notifications::update_settings))
+		.route("/devices", post(push::register_device).get(push::get_devices))
+		.route("/devices/:id", delete(push::delete_device))
+		.route("/notifications", get(notifications::get_notifications))
+		.route("/notifications/unread_count", get(notifications::get_unread_count))
+		.route("/notifications/read_all", post(notifications::mark_all_read))
+		.route("/notifications/:id/read", post(notifications::mark_read))
+		.route("/ws/notifications", get(ws::notifications_ws))
+		.layer(cors)
+		.layer(middleware::from_fn_with_state(pool.clone(), tenant::scope))
+		.layer(TraceLayer::new_for_http().make_span_with(logger::request_span))
+		.layer(middleware::from_fn(logger::request_id))
+		.with_state(pool);
+}
+
+async fn say_hello() -> &'static str {
+	"Hello, world!"
+}
\ No newline at end of file
@@ -0,0 +1,234 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::audit::{self, AuditAction, AuditEvent};
+use crate::departments;
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageRoles};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetLimit {
+	pub max_amount: f64
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct RoleLimit {
+	pub role_: String,
+	pub max_amount: f64,
+	pub updated_by: Option<uuid::Uuid>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+// the manager an approval over the limit of the approver goes to instead
+pub struct Escalation {
+	pub amount: f64,
+	pub max_amount: f64,
+	pub userid: uuid::Uuid,
+	pub username: String
+}
+
+#[derive(Debug)]
+pub enum EscalateErr {
+	Db(sqlx::Error),
+	// the amount is over the limit of the approver and there is no manager above them
+	NoManager
+}
+
+impl From<sqlx::Error> for EscalateErr {
+	fn from(e: sqlx::Error) -> Self {
+		return EscalateErr::Db(e);
+	}
+}
+
+// what is approved, in the unit of the limits. tickets without a numeric amount are not limited
+pub fn state_amount(state: &serde_json::Value) -> Option<f64> {
+	return state.get("amount").and_then(|a| a.as_f64());
+}
+
+pub fn exceeds_limit(amount: Option<f64>, max_amount: Option<f64>) -> bool {
+	return matches!((amount, max_amount), (Some(amount), Some(max_amount)) if amount > max_amount);
+}
+
+// the highest limit among the roles of the user that have one. None if none of them is limited
+pub async fn approval_limit(conn: &mut PgConnection, userid: uuid::Uuid) -> Result<Option<f64>, sqlx::Error> {
+	let limit: (Option<f64>,) = sqlx::query_as(
+		"select max(rl.max_amount)::float8 from user_roles ur join role_limits rl on ur.tenant_id=rl.tenant_id and ur.role_=rl.role_ where ur.userid=$1")
+		.bind(userid)
+		.fetch_one(&mut *conn)
+		.await?;
+	return Ok(limit.0);
+}
+
+// None if `approver` can approve the amount in `state`, otherwise the manager the approval goes to next
+pub async fn escalation(conn: &mut PgConnection, approver: uuid::Uuid, state: &serde_json::Value) -> Result<Option<Escalation>, EscalateErr> {
+	let amount = state_amount(state);
+	if amount.is_none() {
+		return Ok(None);
+	}
+	let max_amount = approval_limit(&mut *conn, approver).await?;
+	if !exceeds_limit(amount, max_amount) {
+		return Ok(None);
+	}
+
+	let Some(manager) = departments::department_manager(&mut *conn, approver).await? else {
+		return Err(EscalateErr::NoManager);
+	};
+	let username: (String,) = sqlx::query_as("select username from users where userid=$1")
+		.bind(manager)
+		.fetch_one(&mut *conn)
+		.await?;
+	return Ok(Some(Escalation { amount: amount.unwrap(), max_amount: max_amount.unwrap(), userid: manager, username: username.0 }));
+}
+
+// kept next to the approval requests so the chain can be followed
+pub async fn record_escalation(
+	conn: &mut PgConnection,
+	ticket_id: i32,
+	node: i32,
+	instance: Option<i32>,
+	approver: uuid::Uuid,
+	escalation: &Escalation
+) -> Result<(), sqlx::Error> {
+	sqlx::query(
+		r#"insert into approval_escalations (ticket_id, node_number, instance, approver, escalated_to, amount, max_amount, created_at)
+			values ($1, $2, $3, $4, $5, $6::float8::numeric, $7::float8::numeric, $8)"#)
+		.bind(ticket_id)
+		.bind(node)
+		.bind(instance)
+		.bind(approver)
+		.bind(escalation.userid)
+		.bind(escalation.amount)
+		.bind(escalation.max_amount)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	return Ok(());
+}
+
+#[utoipa::path(get, path = "/role_limits", tag = "roles", responses(
+	(status = 200, description = "Approval limits of every role", body = Vec<RoleLimit>),
+	(status = 403, description = "Missing the manage_roles permission")
+))]
+pub async fn get_role_limits(
+	_auth: Authorized<ManageRoles>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<RoleLimit>>, StatusCode> {
+	let limits: Result<Vec<RoleLimit>, _> = sqlx::query_as(
+		"select role_, max_amount::float8 as max_amount, updated_by, updated_at from role_limits order by max_amount, role_")
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = limits {
+		admin_logger(LogType::Error, &format!("Error reading role limits: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(limits.unwrap()));
+}
+
+// sets the highest amount holders of the role can approve
+#[utoipa::path(put, path = "/roles/{role}/limit", tag = "roles", params(("role" = String, Path, description = "Role name")), request_body = SetLimit, responses(
+	(status = 200, description = "Saved", body = RoleLimit),
+	(status = 403, description = "Missing the manage_roles permission"),
+	(status = 422, description = "Unknown role or negative amount")
+))]
+pub async fn set_role_limit(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(role): extract::Path<String>,
+	Json(payload): Json<SetLimit>
+) -> Result<Json<RoleLimit>, (StatusCode, String)> {
+	if !payload.max_amount.is_finite() || payload.max_amount < 0.0 || payload.max_amount >= 1e16 {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "Expected an amount of 0 or more".to_string()));
+	}
+	let limit = save_limit(&pool, auth.user.userid, &role, Some(payload.max_amount)).await?;
+	return limit.map(Json).ok_or((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+}
+
+// holders of the role are no longer limited by it
+#[utoipa::path(delete, path = "/roles/{role}/limit", tag = "roles", params(("role" = String, Path, description = "Role name")), responses(
+	(status = 204, description = "Removed, approvers with the role have no limit from it"),
+	(status = 403, description = "Missing the manage_roles permission")
+))]
+pub async fn remove_role_limit(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(role): extract::Path<String>
+) -> Result<StatusCode, (StatusCode, String)> {
+	save_limit(&pool, auth.user.userid, &role, None).await?;
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+async fn save_limit(pool: &PgPool, userid: uuid::Uuid, role: &str, max_amount: Option<f64>) -> Result<Option<RoleLimit>, (StatusCode, String)> {
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+	let role_id: Result<Option<(i32,)>, _> = sqlx::query_as("select id from role_defs where role_=$1")
+		.bind(role)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = role_id {
+		admin_logger(LogType::Error, &format!("Error reading role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let (role_id,) = role_id.unwrap().ok_or((StatusCode::NOT_FOUND, format!("Role {} does not exist", role)))?;
+
+	let previous: Result<Option<(f64,)>, _> = sqlx::query_as("delete from role_limits where role_=$1 returning max_amount::float8")
+		.bind(role)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = previous {
+		admin_logger(LogType::Error, &format!("Error removing the limit of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let previous = previous.unwrap().map(|p| p.0);
+
+	let mut limit = None;
+	if let Some(max_amount) = max_amount {
+		let saved: Result<RoleLimit, _> = sqlx::query_as(
+			r#"insert into role_limits (role_, max_amount, updated_by, updated_at) values ($1, $2::float8::numeric, $3, $4)
+				returning role_, max_amount::float8 as max_amount, updated_by, updated_at"#)
+			.bind(role)
+			.bind(max_amount)
+			.bind(userid)
+			.bind(chrono::Utc::now())
+			.fetch_one(&mut *tx)
+			.await;
+		if let Err(e) = saved {
+			admin_logger(LogType::Error, &format!("Error saving the limit of role {}: {}", role, e), None);
+			return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+		}
+		limit = Some(saved.unwrap());
+	}
+
+	let event = AuditEvent::new(Some(userid), AuditAction::SetLimit, "role", role_id,
+		Some(serde_json::json!({"role_": role, "max_amount": previous})),
+		Some(serde_json::json!({"role_": role, "max_amount": limit.as_ref().map(|l| l.max_amount)})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing the limit of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the limit of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} set the limit of role {} to {:?}", userid, role, max_amount), None);
+	return Ok(limit);
+}
+
+#[cfg(test)]
+mod limits_tests {
+	use serde_json::json;
+	use super::{exceeds_limit, state_amount};
+
+	#[test]
+	fn amounts_over_the_limit_are_escalated() {
+		assert_eq!(state_amount(&json!({"amount": 1250.5})), Some(1250.5));
+		assert_eq!(state_amount(&json!({"amount": "1250"})), None);
+		assert_eq!(state_amount(&json!({"total_cents": 100})), None);
+
+		assert!(exceeds_limit(Some(5000.0), Some(1000.0)));
+		assert!(!exceeds_limit(Some(1000.0), Some(1000.0)));
+		// not limited, or nothing to limit
+		assert!(!exceeds_limit(Some(5000.0), None));
+		assert!(!exceeds_limit(None, Some(0.0)));
+	}
+}
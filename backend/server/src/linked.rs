@@ -0,0 +1,161 @@
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
+use sqlx::PgConnection;
+use crate::auth::AuthUser;
+use crate::db_types::Ticket;
+use crate::events::{EngineEvent, WorkflowEvent};
+use crate::logger::{admin_logger, LogType};
+use crate::process::{read_process_data, Step};
+use crate::rbac;
+use crate::ticket::{self, CreateTicket};
+use crate::ws::LiveEvent;
+use crate::{assets, crm, documents, invoices, leave, onboarding, purchase_orders, timesheets, vendors};
+
+#[derive(Debug)]
+pub enum FollowErr {
+	Db(sqlx::Error),
+	// the record cannot move the way the ticket asks, the transition is undone. the message is shown to the user
+	Refused(String)
+}
+
+impl From<sqlx::Error> for FollowErr {
+	fn from(e: sqlx::Error) -> Self {
+		return FollowErr::Db(e);
+	}
+}
+
+// a saved row of a table with a ticket_id column, e.g. ("purchase_orders", 4)
+pub struct Record {
+	pub table: &'static str,
+	pub id: i32
+}
+
+pub struct NewTicket<'a> {
+	pub process_id: String,
+	// the ticket state. approvers see it, auto_approve_if rules can use it
+	pub data: Map<String, Value>,
+	pub tag: &'a str,
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+// the statuses the transitions of a ticket ask of the record it was started for, in the order they happened.
+// nodes name theirs with record_status, the end of the ticket closes, rejects or cancels the record
+pub fn record_statuses(events: &[WorkflowEvent], steps: &[Step]) -> Vec<String> {
+	return events.iter()
+		.filter_map(|event| match event.kind {
+			EngineEvent::NodeCompleted => event.node
+				.and_then(|node| steps.get(node as usize))
+				.and_then(|step| step.record_status.clone()),
+			EngineEvent::TicketClosed => Some("closed".to_string()),
+			EngineEvent::TicketRejected => Some("rejected".to_string()),
+			EngineEvent::TicketCancelled => Some("cancelled".to_string()),
+			EngineEvent::ApprovalRequested => None
+		})
+		.collect();
+}
+
+// starts the ticket of a record in the transaction that saved it. the record is linked before node 0 runs,
+// which may already move it. the events go to ticket::after_commit once the transaction is commited
+pub async fn start_ticket(
+	conn: &mut PgConnection,
+	user: &AuthUser,
+	record: Record,
+	new: NewTicket<'_>
+) -> Result<(Ticket, Vec<(uuid::Uuid, LiveEvent)>), StatusCode> {
+	match rbac::can_use_process(&mut *conn, user.userid, &new.process_id).await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking roles of {} for process {}: {}", user.userid, new.process_id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Ok(false) => {
+			admin_logger(LogType::Warning, &format!("User {} is not allowed to start {} tickets for {}", user.userid, new.process_id, record.table), None);
+			return Err(StatusCode::FORBIDDEN);
+		}
+		Ok(true) => {}
+	}
+
+	let create = CreateTicket {
+		process_id: new.process_id,
+		owner_id: user.userid,
+		owner_name: user.username.clone(),
+		is_public: false,
+		priority: None,
+		due_at: new.due_at,
+		tags: Some(vec![new.tag.to_string()]),
+		draft: false,
+		data: Some(new.data.clone())
+	};
+	let mut ticket = ticket::insert_ticket(&mut *conn, &create).await?;
+
+	let query = sqlx::query(&format!("update {} set ticket_id=$2 where id=$1", record.table))
+		.bind(record.id)
+		.bind(ticket.id)
+		.execute(&mut *conn)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error linking {} {} to ticket {}: {}", record.table, record.id, ticket.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let mut events = Vec::new();
+	ticket::initiate_ticket(&mut *conn, &mut ticket, Some(new.data), &mut events).await?;
+	return Ok((ticket, events));
+}
+
+// moves the record a ticket was started for along with it. call in the ticket transaction with its transitions
+pub async fn follow(conn: &mut PgConnection, events: &[WorkflowEvent]) -> Result<(), FollowErr> {
+	let Some(first) = events.first() else {
+		return Ok(());
+	};
+	let mut steps = Vec::new();
+	if events.iter().any(|e| e.kind == EngineEvent::NodeCompleted) {
+		match read_process_data(first.process_id.clone()).await {
+			Ok(process) => steps = process.steps,
+			Err(e) => admin_logger(LogType::Error, &format!("Error reading process {} for the record of ticket {}: {}", first.process_id, first.ticket_id, e), None)
+		}
+	}
+	let statuses = record_statuses(events, &steps);
+	if statuses.is_empty() {
+		return Ok(());
+	}
+
+	purchase_orders::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	leave::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	invoices::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	vendors::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	assets::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	timesheets::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	documents::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	onboarding::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	crm::follow_ticket(&mut *conn, first.ticket_id, &statuses).await?;
+	return Ok(());
+}
+
+#[cfg(test)]
+mod linked_tests {
+	use serde_json::json;
+	use crate::events::{EngineEvent, WorkflowEvent};
+	use crate::process::Step;
+	use super::record_statuses;
+
+	fn event(kind: EngineEvent, node: Option<i32>) -> WorkflowEvent {
+		return WorkflowEvent { kind, ticket_id: 1, process_id: "purchase_order".to_string(), node, detail: json!({}) };
+	}
+
+	#[test]
+	fn nodes_and_the_end_of_the_ticket_set_statuses() {
+		let steps: Vec<Step> = serde_json::from_value(json!([
+			{ "event": "initiate", "args": null, "next": [1], "required": [], "callbacks": null },
+			{ "event": "approve", "args": ["finance"], "next": [2], "required": [0], "callbacks": null, "record_status": "approved" },
+			{ "event": "complete", "args": null, "next": [], "required": [1], "callbacks": null }
+		])).unwrap();
+		let events = vec![
+			event(EngineEvent::NodeCompleted, Some(0)),
+			event(EngineEvent::NodeCompleted, Some(1)),
+			event(EngineEvent::ApprovalRequested, Some(2)),
+			event(EngineEvent::TicketClosed, Some(2))
+		];
+		assert_eq!(record_statuses(&events, &steps), vec!["approved", "closed"]);
+		assert_eq!(record_statuses(&[event(EngineEvent::TicketRejected, Some(1))], &steps), vec!["rejected"]);
+	}
+}
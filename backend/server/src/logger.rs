@@ -1,7 +1,70 @@
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 
-use axum::http::StatusCode;
+use std::sync::{atomic::{AtomicU64, Ordering}, Mutex};
+
+use axum::{body::Body, http::{HeaderValue, Request}, middleware::Next, response::Response};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{Level, Span};
+use tracing_appender::non_blocking::{ErrorCounter, WorkerGuard};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use crate::redact;
+use utoipa::ToSchema;
+
+// json lines of every admin log entry, next to the per ticket public logs
+pub const SERVER_LOG: &str = "server.log";
+pub use erp_api_types::REQUEST_ID_HEADER;
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+	static REQUEST_ID: String;
+}
+
+// lines of the public logs waiting for the writer. further lines are dropped while it is full
+fn log_queue_capacity() -> usize {
+	return std::env::var("LOG_QUEUE_CAPACITY")
+		.ok()
+		.and_then(|s| s.parse::<usize>().ok())
+		.unwrap_or(10_000);
+}
+
+struct PublicLine {
+	log_id: uuid::Uuid,
+	line: String
+}
+
+struct LogCounters {
+	queued: AtomicU64,
+	written: AtomicU64,
+	dropped: AtomicU64,
+	write_errors: AtomicU64
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LogMetrics {
+	pub public_queued: u64,
+	pub public_written: u64,
+	// the queue was full or the writer was not running
+	pub public_dropped: u64,
+	pub public_write_errors: u64,
+	pub public_queue_depth: usize,
+	// lines the server.log writer could not keep up with
+	pub server_dropped: u64
+}
+
+// taken on shutdown so the writer finishes the queue and stops
+static PUBLIC_LOG: Mutex<Option<mpsc::Sender<PublicLine>>> = Mutex::new(None);
+static PUBLIC_COUNTERS: LogCounters = LogCounters {
+	queued: AtomicU64::new(0),
+	written: AtomicU64::new(0),
+	dropped: AtomicU64::new(0),
+	write_errors: AtomicU64::new(0)
+};
+static SERVER_LOG_ERRORS: OnceCell<ErrorCounter> = OnceCell::new();
 
 #[derive(Copy, Clone)]
 pub enum LogType {
@@ -18,117 +81,410 @@ pub enum LogType {
 	FailedToSendTask
 }
 
-fn public_logger(type_: LogType, data: &String, log_id: &uuid::Uuid) -> Result<(), std::io::Error>  {
+impl LogType {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			LogType::Info => "INFO",
+			LogType::Warning => "WARNING",
+			LogType::Error => "ERROR",
+			LogType::Approval => "APPROVAL",
+			LogType::Rejection => "REJECTION",
+			LogType::UploadSuccess => "UPLOAD_SUCCESS",
+			LogType::Request => "REQUEST",
+			LogType::Completion => "COMPLETION",
+			LogType::FailedToPing => "FAILED_TO_PING",
+			LogType::NotificationSuccess => "NOTIFICATION_SUCCESS",
+			LogType::FailedToSendTask => "FAILED_TO_SEND_TASK",
+		};
+	}
+
+	pub fn level(&self) -> Level {
+		return match self {
+			LogType::Error => Level::ERROR,
+			LogType::Warning | LogType::FailedToPing | LogType::FailedToSendTask => Level::WARN,
+			_ => Level::INFO
+		};
+	}
+
+	// these also end up in the history of the ticket
+	pub fn is_public(&self) -> bool {
+		return !matches!(self, LogType::Warning | LogType::Error | LogType::FailedToPing);
+	}
+}
+
+// json on stdout and in admin_logs/server.log, filtered by RUST_LOG. the guard flushes the file and has to live as long as the server
+pub fn init_tracing(admin_log_dir: &Path) -> WorkerGuard {
+	let (file, guard) = tracing_appender::non_blocking(tracing_appender::rolling::never(admin_log_dir, SERVER_LOG));
+	let _ = SERVER_LOG_ERRORS.set(file.error_counter());
+	tracing_subscriber::registry()
+		.with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+		.with(fmt::layer().json().flatten_event(true).with_current_span(true).with_span_list(false))
+		.with(fmt::layer().json().flatten_event(true).with_current_span(true).with_span_list(false).with_writer(file))
+		.init();
+	return guard;
+}
+
+// ids sent by clients are kept if they are short and printable, anything else gets a new one
+pub fn accept_request_id(header: Option<&HeaderValue>) -> String {
+	return header
+		.and_then(|v| v.to_str().ok())
+		.filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_graphic()))
+		.map(str::to_string)
+		.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+}
+
+// has to run before the trace layer so the span of the request gets the id. the id is returned in the response
+// and is readable with current_request_id while the request is handled
+pub async fn request_id(mut request: Request<Body>, next: Next<Body>) -> Response {
+	let id = accept_request_id(request.headers().get(REQUEST_ID_HEADER));
+	let value = HeaderValue::from_str(&id).unwrap();
+	request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
 
+	let mut response = REQUEST_ID.scope(id, next.run(request)).await;
+	response.headers_mut().insert(REQUEST_ID_HEADER, value);
+	return response;
+}
+
+// none outside of a request and in tasks spawned by it
+pub fn current_request_id() -> Option<String> {
+	return REQUEST_ID.try_with(|id| id.clone()).ok();
+}
+
+// one span per request. the ticket and the user are filled in once they are known
+pub fn request_span<B>(request: &Request<B>) -> Span {
+	return tracing::info_span!(
+		"request",
+		method = %request.method(),
+		uri = %redact::redact(&request.uri().to_string()),
+		request_id = request.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()),
+		tenant_id = tracing::field::Empty,
+		user_id = tracing::field::Empty,
+		ticket_id = tracing::field::Empty
+	);
+}
+
+pub fn record_tenant(tenant_id: i32) {
+	Span::current().record("tenant_id", tenant_id);
+}
+
+pub fn record_user(userid: uuid::Uuid) {
+	Span::current().record("user_id", tracing::field::display(userid));
+}
+
+pub fn record_ticket(ticket_id: i32) {
+	Span::current().record("ticket_id", ticket_id);
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LogEntry {
+	pub type_: String,
+	pub timestamp: String,
+	pub message: String
+}
+
+// lines are written as "[TYPE] [timestamp] message"
+fn parse_log_line(line: &str) -> Option<LogEntry> {
+	let (type_, rest) = line.strip_prefix('[')?.split_once("] [")?;
+	let (timestamp, message) = rest.split_once("] ")?;
+	return Some(LogEntry {
+		type_: type_.to_string(),
+		timestamp: timestamp.to_string(),
+		message: message.to_string()
+	});
+}
+
+// reads the public log of a ticket in the order it was written
+pub fn read_public_log(log_id: &uuid::Uuid) -> Result<Vec<LogEntry>, std::io::Error> {
 	let data_dir = std::env::var("PROCESS_DATA_PATH").expect("PROCESS_DATA_PATH not defined");
 	let log_file_path = PathBuf::from(data_dir).join("public_logs").join(log_id.to_string());
 
-	// FIXME: Assume all io errors are fatal. maybe not a good idea?
-	tokio::spawn({
-		let data = data.clone();
-		async move {
-			let log_file = std::fs::OpenOptions::new()
-				.append(true)
-				.create(true)
-				.open(&log_file_path);
-
-			if let Err(e) = log_file {
-				panic!("[FATAL] [{}] Failed to open log_file (public_logger): File: {:?}, e: {}", chrono::Local::now(), log_file_path, e);
-			}
-			let mut log_file = log_file.unwrap();
-
-			let log_type = match type_ {
-				LogType::Approval => "APPROVAL",
-				LogType::Rejection => "REJECTION",
-				LogType::UploadSuccess => "UPLOAD_SUCCESS",
-				LogType::Request => "REQUEST",
-				LogType::Completion => "COMPLETION",
-				LogType::Info => "INFO",
-				LogType::NotificationSuccess => "NOTIFICATION_SUCCESS",
-				LogType::FailedToSendTask => "FAILED_TO_SEND_TASK",
-				_ => unreachable!()
-			};
-
-			let log = format!("[{}] [{}] {}\n", log_type, chrono::Local::now().to_rfc3339(), data);
-			if let Err(e) =  log_file.write_all(log.as_bytes()) {
-				panic!("[FATAL] [{}] Failed to write to log_file. (public_logger): File: {:?}, e: {}", chrono::Local::now(), log_file_path, e);
-			}
-		}
+	let contents = match std::fs::read_to_string(&log_file_path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(e)
+	};
+
+	return Ok(contents.lines().filter_map(parse_log_line).collect());
+}
+
+// one line of server.log
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct AdminLogEntry {
+	pub timestamp: chrono::DateTime<chrono::Utc>,
+	pub level: String,
+	// the LogType of entries written with admin_logger or log
+	pub category: Option<String>,
+	pub message: String,
+	pub log_id: Option<uuid::Uuid>,
+	// from the request span
+	pub request_id: Option<String>,
+	pub tenant_id: Option<i32>,
+	pub ticket_id: Option<i32>,
+	pub user_id: Option<uuid::Uuid>
+}
+
+#[derive(Default)]
+pub struct LogFilter {
+	// entries of other tenants are left out when set, and those of work outside of a request unless they have the log_id
+	pub tenant_id: Option<i32>,
+	pub request_id: Option<String>,
+	pub log_id: Option<uuid::Uuid>,
+	pub ticket_id: Option<i32>,
+	pub category: Option<String>,
+	pub from: Option<chrono::DateTime<chrono::Utc>>,
+	pub to: Option<chrono::DateTime<chrono::Utc>>
+}
+
+pub fn parse_server_log_line(line: &str) -> Option<AdminLogEntry> {
+	let value: serde_json::Value = serde_json::from_str(line).ok()?;
+	let text = |v: &serde_json::Value| v.as_str().map(str::to_string);
+	let span = &value["span"];
+	return Some(AdminLogEntry {
+		timestamp: chrono::DateTime::parse_from_rfc3339(value["timestamp"].as_str()?).ok()?.with_timezone(&chrono::Utc),
+		level: text(&value["level"])?,
+		category: text(&value["category"]),
+		message: text(&value["message"]).unwrap_or_default(),
+		log_id: value["log_id"].as_str().and_then(|id| uuid::Uuid::parse_str(id).ok()),
+		request_id: text(&span["request_id"]),
+		tenant_id: span["tenant_id"].as_i64().map(|id| id as i32),
+		ticket_id: span["ticket_id"].as_i64().map(|id| id as i32),
+		user_id: span["user_id"].as_str().and_then(|id| uuid::Uuid::parse_str(id).ok())
 	});
-	
-	Ok(())
 }
 
-pub fn admin_logger(type_: LogType, data: &String, log_id: Option<&uuid::Uuid>) -> Result<(), std::io::Error>  {
+impl LogFilter {
+	// the ticket filter also matches entries of requests on the ticket that were written without its log_id
+	pub fn matches(&self, entry: &AdminLogEntry) -> bool {
+		let keyed = match (self.log_id, self.ticket_id) {
+			(None, None) => true,
+			(log_id, ticket_id) => (log_id.is_some() && entry.log_id == log_id) || (ticket_id.is_some() && entry.ticket_id == ticket_id)
+		};
+		return keyed
+			&& self.tenant_id.map(|tenant| entry.tenant_id == Some(tenant) || (entry.tenant_id.is_none() && self.log_id.is_some() && entry.log_id == self.log_id)).unwrap_or(true)
+			&& self.request_id.as_ref().map(|id| entry.request_id.as_ref() == Some(id)).unwrap_or(true)
+			&& self.category.as_ref().map(|c| entry.category.as_ref() == Some(c)).unwrap_or(true)
+			&& self.from.map(|from| entry.timestamp >= from).unwrap_or(true)
+			&& self.to.map(|to| entry.timestamp < to).unwrap_or(true);
+	}
+}
 
+// the newest `limit` matching entries of server.log, oldest first
+pub fn read_server_log(filter: &LogFilter, limit: usize) -> Result<Vec<AdminLogEntry>, std::io::Error> {
 	let data_dir = std::env::var("PROCESS_DATA_PATH").expect("PROCESS_DATA_PATH not defined");
-	let mut log_file_path = PathBuf::from(&data_dir).join("admin_logs");
-
-	match log_id {
-		Some(id) => {
-			log_file_path = log_file_path.join(id.to_string());
-		},
-		None => {
-			log_file_path = log_file_path.join("common_log");
+	let log_file_path = PathBuf::from(data_dir).join("admin_logs").join(SERVER_LOG);
+
+	let file = match std::fs::File::open(&log_file_path) {
+		Ok(file) => file,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(e)
+	};
+	let mut entries = std::collections::VecDeque::new();
+	for line in std::io::BufReader::new(file).lines() {
+		let entry = match parse_server_log_line(&line?) {
+			Some(entry) if filter.matches(&entry) => entry,
+			_ => continue
+		};
+		if entries.len() == limit {
+			entries.pop_front();
 		}
+		entries.push_back(entry);
 	}
+	return Ok(entries.into());
+}
+
+// queues the line for the writer task. a line that does not fit is dropped and counted,
+// log also sends it to server.log so it is not lost entirely
+fn public_logger(type_: LogType, data: &str, log_id: &uuid::Uuid) {
+	let line = PublicLine {
+		log_id: *log_id,
+		line: format!("[{}] [{}] {}\n", type_.as_str(), chrono::Local::now().to_rfc3339(), data)
+	};
+	let queued = match PUBLIC_LOG.lock().unwrap().as_ref() {
+		Some(sender) => sender.try_send(line).is_ok(),
+		None => false
+	};
+	if queued {
+		PUBLIC_COUNTERS.queued.fetch_add(1, Ordering::Relaxed);
+	}
+	else {
+		PUBLIC_COUNTERS.dropped.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+// starts the task that appends to the public logs. requests only queue their lines
+// so a slow or failing disk never holds them up or fails them
+pub fn spawn_public_writer(log_dir: PathBuf) -> JoinHandle<()> {
+	let (sender, receiver) = mpsc::channel(log_queue_capacity());
+	*PUBLIC_LOG.lock().unwrap() = Some(sender);
+	return tokio::spawn(write_public_logs(log_dir, receiver));
+}
+
+// lines logged after this are dropped. returns once the queued ones are written
+pub async fn close_public_log(writer: JoinHandle<()>) {
+	PUBLIC_LOG.lock().unwrap().take();
+	let _ = writer.await;
+}
 
-	tokio::spawn({
-		let data = data.clone();
-		async move {
-			let log_file = std::fs::OpenOptions::new()
-				.append(true)
-				.create(true)
-				.open(&log_file_path);
+async fn append_line(path: &Path, line: &str) -> Result<(), std::io::Error> {
+	let mut file = tokio::fs::OpenOptions::new()
+		.append(true)
+		.create(true)
+		.open(path)
+		.await?;
+	file.write_all(line.as_bytes()).await?;
+	file.flush().await?;
+	return Ok(());
+}
 
-			if let Err(e) = log_file {
-				panic!("[FATAL] [{}] Failed to open log_file (admin_logger): File: {:?}, e: {}", chrono::Local::now(), log_file_path, e);
+// a single writer keeps the lines of every ticket in the order they were logged
+async fn write_public_logs(log_dir: PathBuf, mut receiver: mpsc::Receiver<PublicLine>) {
+	while let Some(entry) = receiver.recv().await {
+		let log_file_path = log_dir.join(entry.log_id.to_string());
+		match append_line(&log_file_path, &entry.line).await {
+			Ok(()) => {
+				PUBLIC_COUNTERS.written.fetch_add(1, Ordering::Relaxed);
+			}
+			Err(e) => {
+				PUBLIC_COUNTERS.write_errors.fetch_add(1, Ordering::Relaxed);
+				admin_logger(LogType::Error, &format!("Failed to write public log {:?}: {}", log_file_path, e), Some(&entry.log_id));
 			}
+		}
+	}
+}
+
+pub fn log_metrics() -> LogMetrics {
+	return LogMetrics {
+		public_queued: PUBLIC_COUNTERS.queued.load(Ordering::Relaxed),
+		public_written: PUBLIC_COUNTERS.written.load(Ordering::Relaxed),
+		public_dropped: PUBLIC_COUNTERS.dropped.load(Ordering::Relaxed),
+		public_write_errors: PUBLIC_COUNTERS.write_errors.load(Ordering::Relaxed),
+		public_queue_depth: PUBLIC_LOG.lock().unwrap().as_ref().map(|s| s.max_capacity() - s.capacity()).unwrap_or(0),
+		server_dropped: SERVER_LOG_ERRORS.get().map(|c| c.dropped_lines() as u64).unwrap_or(0)
+	};
+}
+
+// the category becomes a field of the event, entries of a ticket carry its log_id so they can be found again.
+// the server.log writer drops lines instead of blocking when it falls behind
+pub fn admin_logger(type_: LogType, data: &str, log_id: Option<&uuid::Uuid>) {
+	emit(type_, &redact::redact(data), log_id);
+}
 
-			let mut log_file = log_file.unwrap();
-
-			let log_type = match type_ {
-				LogType::Info => "INFO",
-				LogType::Warning => "WARNING",
-				LogType::Error => "ERROR",
-				LogType::Approval => "APPROVAL",
-				LogType::Rejection => "REJECTION",
-				LogType::UploadSuccess => "UPLOAD_SUCCESS",
-				LogType::Request => "REQUEST",
-				LogType::Completion => "COMPLETION",
-				LogType::FailedToPing => "FAILED_TO_PING",
-				LogType::NotificationSuccess => "NOTIFICATION_SUCCESS",
-				LogType::FailedToSendTask => "FAILED_TO_SEND_TASK",
-			};
-
-			let log = format!("[{}] [{}] {}\n", log_type, chrono::Local::now().to_rfc3339(), data);
-			if let Err(e) = log_file.write_all(log.as_bytes()) {
-				panic!("[FATAL] [{}] Failed to write to log_file. (admin_logger): File: {:?}, e: {}", chrono::Local::now(), log_file_path, e);
+fn emit(type_: LogType, data: &str, log_id: Option<&uuid::Uuid>) {
+	let category = type_.as_str();
+	let log_id = log_id.map(|id| id.to_string());
+	match type_.level() {
+		Level::ERROR => tracing::error!(category, log_id, "{}", data),
+		Level::WARN => tracing::warn!(category, log_id, "{}", data),
+		_ => tracing::info!(category, log_id, "{}", data)
+	}
+}
+
+// never fails or waits, whatever happens to the log files
+pub fn log(type_: LogType, data: String, log_id: uuid::Uuid) {
+	let data = redact::redact(&data);
+	if type_.is_public() {
+		public_logger(type_, &data, &log_id);
+	}
+	emit(type_, &data, Some(&log_id));
+}
+
+#[cfg(test)]
+mod logger_tests {
+	use tracing::Level;
+	use axum::http::HeaderValue;
+	use super::{accept_request_id, log, log_metrics, parse_log_line, parse_server_log_line, spawn_public_writer, LogFilter, LogType};
+
+	#[test]
+	fn parses_written_log_lines() {
+		let entry = parse_log_line("[APPROVAL] [2024-04-28T10:15:00+05:30] Ticket 4 approved by [admin]").unwrap();
+		assert_eq!(entry.type_, "APPROVAL");
+		assert_eq!(entry.timestamp, "2024-04-28T10:15:00+05:30");
+		assert_eq!(entry.message, "Ticket 4 approved by [admin]");
+
+		assert!(parse_log_line("garbage").is_none());
+	}
+
+	#[test]
+	fn categories_keep_their_names_and_levels() {
+		assert_eq!(LogType::FailedToSendTask.as_str(), "FAILED_TO_SEND_TASK");
+		assert_eq!(LogType::Error.level(), Level::ERROR);
+		assert_eq!(LogType::FailedToPing.level(), Level::WARN);
+		assert_eq!(LogType::Approval.level(), Level::INFO);
+		assert!(LogType::Approval.is_public());
+		assert!(!LogType::Warning.is_public());
+	}
+
+	#[test]
+	fn server_log_lines_are_filtered() {
+		let log_id = uuid::Uuid::new_v4();
+		let line = format!(
+			r#"{{"timestamp":"2024-05-22T09:30:00.123456Z","level":"WARN","category":"FAILED_TO_PING","log_id":"{}","message":"Notifier down","target":"server::logger","span":{{"method":"POST","request_id":"req-1","ticket_id":7,"name":"request"}}}}"#,
+			log_id);
+		let entry = parse_server_log_line(&line).unwrap();
+		assert_eq!(entry.level, "WARN");
+		assert_eq!(entry.category.as_deref(), Some("FAILED_TO_PING"));
+		assert_eq!(entry.log_id, Some(log_id));
+		assert_eq!(entry.request_id.as_deref(), Some("req-1"));
+		assert_eq!(entry.ticket_id, Some(7));
+		assert_eq!(entry.user_id, None);
+		assert!(parse_server_log_line("[INFO] [2024-05-22] old format").is_none());
+
+		let tenant_line = line.replace(r#""request_id":"req-1","#, r#""request_id":"req-1","tenant_id":3,"#);
+		let tenant_entry = parse_server_log_line(&tenant_line).unwrap();
+		assert_eq!(tenant_entry.tenant_id, Some(3));
+		assert!(LogFilter { tenant_id: Some(3), ..Default::default() }.matches(&tenant_entry));
+		assert!(!LogFilter { tenant_id: Some(2), log_id: Some(log_id), ..Default::default() }.matches(&tenant_entry));
+
+		assert!(LogFilter::default().matches(&entry));
+		assert!(LogFilter { log_id: Some(log_id), ..Default::default() }.matches(&entry));
+		assert!(LogFilter { log_id: Some(uuid::Uuid::new_v4()), ticket_id: Some(7), ..Default::default() }.matches(&entry));
+		assert!(!LogFilter { ticket_id: Some(8), ..Default::default() }.matches(&entry));
+		assert!(LogFilter { request_id: Some("req-1".to_string()), ..Default::default() }.matches(&entry));
+		assert!(!LogFilter { request_id: Some("req-2".to_string()), ..Default::default() }.matches(&entry));
+		assert!(!LogFilter { category: Some("ERROR".to_string()), ..Default::default() }.matches(&entry));
+		assert!(!LogFilter { tenant_id: Some(2), ..Default::default() }.matches(&entry));
+		assert!(LogFilter { tenant_id: Some(2), log_id: Some(log_id), ..Default::default() }.matches(&entry));
+		let from = chrono::DateTime::parse_from_rfc3339("2024-05-22T10:00:00Z").unwrap().with_timezone(&chrono::Utc);
+		assert!(!LogFilter { from: Some(from), ..Default::default() }.matches(&entry));
+		assert!(LogFilter { to: Some(from), ..Default::default() }.matches(&entry));
+	}
+
+	#[tokio::test]
+	async fn public_lines_are_written_in_order() {
+		let log_dir = std::env::temp_dir().join(format!("public_logs_{}", uuid::Uuid::new_v4()));
+		std::fs::create_dir_all(&log_dir).unwrap();
+		spawn_public_writer(log_dir.clone());
+
+		let log_id = uuid::Uuid::new_v4();
+		for i in 0..20 {
+			log(LogType::Request, format!("Request {}", i), log_id);
+		}
+		// admin only
+		log(LogType::Error, "Error".to_string(), log_id);
+
+		let path = log_dir.join(log_id.to_string());
+		let mut lines = Vec::new();
+		for _ in 0..100 {
+			lines = std::fs::read_to_string(&path).unwrap_or_default().lines().filter_map(parse_log_line).collect();
+			if lines.len() == 20 {
+				break;
 			}
+			tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 		}
-	});
-	Ok(())
-}
-
-pub fn log(type_: LogType, data: String, log_id: uuid::Uuid) -> Result<(), StatusCode> {
-	match type_ {
-		LogType::Approval | LogType::Rejection | LogType::UploadSuccess | LogType::Request | 
-		LogType::Completion | LogType::Info | LogType::NotificationSuccess | LogType::FailedToSendTask => {
-			public_logger(type_, &data, &log_id).map_err(|e| {
-				eprintln!("Unable to write to log file: {}, log_id: {}", e, log_id);
-				return StatusCode::INTERNAL_SERVER_ERROR;
-			})?;
-			admin_logger(type_, &data, Some(&log_id)).map_err(|e| {
-				eprintln!("Unable to write to log file: {}, log_id: {}", e, log_id);
-				return StatusCode::INTERNAL_SERVER_ERROR;
-			})?;
-		},
-		_ => {
-			admin_logger(type_, &data, Some(&log_id)).map_err(|e| {
-				eprintln!("Unable to write to log file: {}, log_id: {}", e, log_id);
-				return StatusCode::INTERNAL_SERVER_ERROR;
-			})?;
+		assert_eq!(lines.len(), 20);
+		assert!(lines.iter().enumerate().all(|(i, l)| l.message == format!("Request {}", i)));
+		assert!(log_metrics().public_written >= 20);
+		std::fs::remove_dir_all(log_dir).unwrap();
+	}
+
+	#[test]
+	fn request_ids_are_accepted_or_generated() {
+		assert_eq!(accept_request_id(Some(&HeaderValue::from_static("lb-4f2a-01"))), "lb-4f2a-01");
+		let generated = accept_request_id(None);
+		assert!(uuid::Uuid::parse_str(&generated).is_ok());
+		for rejected in ["", "has space", &"x".repeat(129)] {
+			let id = accept_request_id(Some(&HeaderValue::from_str(rejected).unwrap()));
+			assert!(uuid::Uuid::parse_str(&id).is_ok(), "{:?} should be replaced", rejected);
 		}
 	}
-	Ok(())
-}
\ No newline at end of file
+}
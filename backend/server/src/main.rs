@@ -2,22 +2,15 @@
 #![allow(clippy::needless_return)]
 
 
-use axum::{routing::{get, post}, Router, http::{Method, HeaderValue}};
+use axum::http::{HeaderName, Method, HeaderValue};
 use std::{net::SocketAddr, path::PathBuf};
-use sqlx::postgres::PgPoolOptions;
 use tower_http::cors::CorsLayer;
-use axum::http::header::CONTENT_TYPE;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
 use dotenv::dotenv;
-
-pub mod process;
-pub mod users;
-pub mod roles;
-pub mod db_types;
-pub mod ticket;
-pub mod utils;
-pub mod logger;
-pub mod notif_handler;
-pub mod callbacks;
+use server::{
+	app, archive, auth, callbacks, digests, events, grpc, invoices, logger, migrations, outbox, process, reminders, replica,
+	reports, schedules, shutdown, tenant, webhooks
+};
 
 
 #[tokio::main]
@@ -25,6 +18,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 	dotenv().ok();
 
+	// `server migrate` applies the pending migrations and exits
+	if std::env::args().nth(1).as_deref() == Some("migrate") {
+		let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not defined");
+		// outside of a request, so the migrations see the rows of every tenant
+		let pool = tenant::pool_options().max_connections(1).connect(&db_url).await.expect("Unable to connect to db");
+		let applied = match migrations::check_schema(&pool).await {
+			Ok(status) => migrations::run(&pool).await.map(|_| status.pending.len()),
+			Err(e) => Err(e)
+		};
+		match applied {
+			Ok(count) => println!("Applied {} migrations", count),
+			Err(e) => {
+				eprintln!("{}", e);
+				std::process::exit(1);
+			}
+		}
+		return Ok(());
+	}
+
 	// check if the data dir exists or not
 	let data_dir = std::env::var("PROCESS_DATA_PATH").expect("PROCESS_DATA_PATH not defined");
 	let process_data_dir = PathBuf::from(&data_dir);
@@ -36,7 +48,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let log_dir = PathBuf::from(&data_dir).join("public_logs");
 	if !log_dir.try_exists().unwrap() {
 		println!("Public log dir not found. Creating...");
-		std::fs::create_dir_all(log_dir).unwrap();
+		std::fs::create_dir_all(&log_dir).unwrap();
 	}
 
 	let admin_log_dir = PathBuf::from(&data_dir).join("admin_logs");
@@ -44,6 +56,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		println!("Admin log dir not found. Creating...");
 		std::fs::create_dir_all(&admin_log_dir).unwrap();
 	}
+	let _log_guard = logger::init_tracing(&admin_log_dir);
+	let public_log = logger::spawn_public_writer(log_dir);
 
 
 
@@ -51,48 +65,62 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let port = port.parse::<u16>().unwrap();
 
 	let cors = CorsLayer::new()
-		.allow_headers([CONTENT_TYPE])
-		.allow_methods([Method::GET, Method::POST])
+		.allow_headers([AUTHORIZATION, CONTENT_TYPE, HeaderName::from_static(logger::REQUEST_ID_HEADER)])
+		.expose_headers([HeaderName::from_static(logger::REQUEST_ID_HEADER), RETRY_AFTER])
+		.allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
 		.allow_origin(std::env::var("FRONTEND_URL")?.parse::<HeaderValue>().unwrap());
 
+	// fail at startup instead of at the first authenticated request
+	once_cell::sync::Lazy::force(&auth::JWT_SECRET);
+
 	let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not defined");
 
-	let pool = PgPoolOptions::new()
+	let pool = tenant::pool_options()
 		.max_connections(5)
 		.connect(&db_url)
 		.await
 		.expect("Unable to connect to db");
 
-	let app = Router::new()
-		.route("/", get(say_hello))
-		.route("/process/all", get(process::get_all_processes))
-		.route("/process", get(process::get_process_data))
-		.route("/process", post(process::create_process))
-		.route("/users", post(users::create_user))
-		.route("/userid", get(users::get_userid))
-		.route("/is_admin", get(users::is_admin))
-		.route("/roles", post(roles::create_role))
-		.route("/roles", get(roles::get_all_roles))
-		.route("/new_user", post(users::register_new_user))
-		.route("/new_user", get(users::get_all_new_users))
-		.route("/new_user/approved", get(users::check_user_approved))
-		.route("/ticket", post(ticket::create_ticket))
-		.route("/ticket/user", get(ticket::get_user_tickets))
-		.route("/ticket/update", post(ticket::update_ticket))
-		.route("/notifier/request_token", post(notif_handler::gen_token))
-		.layer(cors)
-		.with_state(pool);
+	// fail before serving anything if the schema does not match the code
+	if let Err(e) = migrations::prepare(&pool).await {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
+	replica::connect().await.expect("Unable to connect to the read replica");
+	process::provider::init(pool.clone()).expect("Unable to set up the process provider");
+
+	shutdown::spawn(reminders::run_reminders(pool.clone()));
+	shutdown::spawn(schedules::run_scheduler(pool.clone()));
+	shutdown::spawn(archive::run_archiver(pool.clone()));
+	shutdown::spawn(digests::run_digests(pool.clone()));
+	shutdown::spawn(outbox::run_outbox(pool.clone()));
+	shutdown::spawn(callbacks::run_callback_jobs(pool.clone()));
+	shutdown::spawn(webhooks::run_webhooks(pool.clone()));
+	shutdown::spawn(events::run_event_publisher(pool.clone()));
+	shutdown::spawn(invoices::run_overdue_check(pool.clone()));
+	shutdown::spawn(reports::run_report_refresh(pool.clone()));
+	if let Some(grpc_port) = grpc::port() {
+		shutdown::spawn(grpc::serve(pool.clone(), grpc_port));
+	}
+
+	let app = app(pool.clone(), cors);
 
 	let addr = SocketAddr::from(([0, 0, 0, 0], port));
 	println!("Running on {}", addr);
+	// stops taking connections on SIGINT or SIGTERM and returns once the open requests are answered
 	axum::Server::bind(&addr)
 		.serve(app.into_make_service())
+		.with_graceful_shutdown(shutdown::signal())
 		.await
 		.unwrap();
 
+	let timeout = shutdown::drain_timeout();
+	if !shutdown::drain(timeout).await {
+		logger::admin_logger(logger::LogType::Warning, &format!("Background work still running after {}s, shutting down anyway", timeout.as_secs()), None);
+	}
+	logger::close_public_log(public_log).await;
+	replica::close().await;
+	pool.close().await;
+
 	Ok(())
 }
-
-async fn say_hello() -> &'static str {
-	"Hello, world!"
-}
\ No newline at end of file
@@ -0,0 +1,118 @@
+use sqlx::migrate::{AppliedMigration, Migrate, Migration, Migrator};
+use sqlx::PgPool;
+use crate::logger::{admin_logger, LogType};
+
+// every file in backend/migrations, embedded at build time. build.rs rebuilds the server when one is added
+pub static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
+
+// migrations are applied at startup unless RUN_MIGRATIONS=false, in which case they are run with
+// `server migrate` and the server only checks that the schema is up to date
+fn run_at_startup() -> bool {
+	return std::env::var("RUN_MIGRATIONS")
+		.ok()
+		.and_then(|s| s.parse::<bool>().ok())
+		.unwrap_or(true);
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaStatus {
+	// known to the server, not applied to the db
+	pub pending: Vec<i64>,
+	// applied, but the file changed since
+	pub modified: Vec<i64>,
+	// applied by a newer server
+	pub unknown: Vec<i64>
+}
+
+pub fn schema_status(migrations: &[Migration], applied: &[AppliedMigration]) -> SchemaStatus {
+	let mut status = SchemaStatus::default();
+	for migration in migrations.iter().filter(|m| !m.migration_type.is_down_migration()) {
+		match applied.iter().find(|a| a.version == migration.version) {
+			None => status.pending.push(migration.version),
+			Some(a) if a.checksum != migration.checksum => status.modified.push(migration.version),
+			Some(_) => {}
+		}
+	}
+	for a in applied {
+		if !migrations.iter().any(|m| m.version == a.version) {
+			status.unknown.push(a.version);
+		}
+	}
+	return status;
+}
+
+// does not create the migrations table, a db that never had migrations has all of them pending
+pub async fn check_schema(pool: &PgPool) -> Result<SchemaStatus, String> {
+	let mut conn = pool.acquire().await.map_err(|e| format!("Unable to connect to db. e: {}", e))?;
+	let table: (Option<String>,) = sqlx::query_as("select to_regclass('_sqlx_migrations')::text")
+		.fetch_one(&mut *conn)
+		.await
+		.map_err(|e| format!("Unable to read the schema version. e: {}", e))?;
+	if table.0.is_none() {
+		return Ok(schema_status(&MIGRATOR.migrations, &[]));
+	}
+
+	if let Some(version) = conn.dirty_version().await.map_err(|e| format!("Unable to read the schema version. e: {}", e))? {
+		return Err(format!("Migration {} failed part way and has to be fixed by hand", version));
+	}
+	let applied = conn.list_applied_migrations().await
+		.map_err(|e| format!("Unable to read the schema version. e: {}", e))?;
+	return Ok(schema_status(&MIGRATOR.migrations, &applied));
+}
+
+pub async fn run(pool: &PgPool) -> Result<(), String> {
+	let before = check_schema(pool).await?;
+	MIGRATOR.run(pool).await.map_err(|e| format!("Unable to migrate the db. e: {}", e))?;
+	if !before.pending.is_empty() {
+		admin_logger(LogType::Info, &format!("Applied migrations {:?}", before.pending), None);
+	}
+	return Ok(());
+}
+
+// runs the migrations or, when they are run separately, refuses to start on an outdated schema
+pub async fn prepare(pool: &PgPool) -> Result<(), String> {
+	if run_at_startup() {
+		return run(pool).await;
+	}
+
+	let status = check_schema(pool).await?;
+	if !status.modified.is_empty() {
+		return Err(format!("Migrations {:?} were changed after they were applied", status.modified));
+	}
+	if !status.pending.is_empty() {
+		return Err(format!("The db schema is behind, migrations {:?} are pending. Run `server migrate` first", status.pending));
+	}
+	if !status.unknown.is_empty() {
+		admin_logger(LogType::Warning, &format!("The db has migrations {:?} this server does not know", status.unknown), None);
+	}
+	return Ok(());
+}
+
+#[cfg(test)]
+mod migrations_tests {
+	use sqlx::migrate::AppliedMigration;
+	use super::{schema_status, SchemaStatus, MIGRATOR};
+
+	#[test]
+	fn migrations_are_embedded_in_order() {
+		let files = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../migrations")).unwrap().count();
+		assert_eq!(MIGRATOR.migrations.len(), files);
+		assert!(MIGRATOR.migrations.windows(2).all(|m| m[0].version < m[1].version));
+	}
+
+	#[test]
+	fn status_compares_versions_and_checksums() {
+		let migrations = &MIGRATOR.migrations[..3];
+		let applied = |version: i64, checksum: &[u8]| AppliedMigration { version, checksum: checksum.to_vec().into() };
+
+		let up_to_date: Vec<AppliedMigration> = migrations.iter().map(|m| applied(m.version, &m.checksum)).collect();
+		assert_eq!(schema_status(migrations, &up_to_date), SchemaStatus::default());
+
+		let status = schema_status(migrations, &[applied(migrations[0].version, b"edited"), applied(1, b"")]);
+		assert_eq!(status, SchemaStatus {
+			pending: vec![migrations[1].version, migrations[2].version],
+			modified: vec![migrations[0].version],
+			unknown: vec![1]
+		});
+	}
+}
@@ -3,21 +3,22 @@ use axum::{extract, Json};
 use axum::http::StatusCode;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use uuid::Uuid;
-use crate::logger::{LogType, admin_logger};
-use crate::ticket::ExecuteErr::{self, FailedToNotify, FailedToLog};
+use crate::logger::{self, LogType, admin_logger};
+use crate::ticket::ExecuteErr::{self, FailedToNotify};
 use crate::utils;
+use utoipa::ToSchema;
 
 #[derive(PartialEq, Eq)]
 pub enum Ping {CollectNew, Clear, ClientIdTransfer}
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct TokenRequest {
 	userid: Uuid 
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct TokenResponse {
 	token: String
 }
@@ -29,7 +30,10 @@ static NOTIF_ADDR : Lazy<SocketAddr> = Lazy::new(|| {
 	return SocketAddr::from(([127, 0, 0, 1], notifier_port));
 });
 
-pub async fn ping_notifier(type_: Ping, data: Option<(String, String)>) -> Result<(), ExecuteErr> {
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// request_id is the X-Request-Id of the requests the ping is sent for, comma separated
+pub async fn ping_notifier(type_: Ping, data: Option<(String, String)>, request_id: Option<&str>) -> Result<(), ExecuteErr> {
 	let bytes = match type_ {
 		Ping::CollectNew => 1u64.to_le_bytes(),
 		Ping::Clear => 2u64.to_le_bytes(),
@@ -39,8 +43,7 @@ pub async fn ping_notifier(type_: Ping, data: Option<(String, String)>) -> Resul
 	let conn = TcpStream::connect(*NOTIF_ADDR).await;
 
 	if let Err(e) = conn {
-		admin_logger(LogType::FailedToPing, &format!("Failed to ping notifier server. e: {}", e), None)
-			.map_err(|_e| FailedToLog)?;
+		admin_logger(LogType::FailedToPing, &format!("Failed to ping notifier server. e: {}", e), None);
 		return Err(FailedToNotify);
 	}
 
@@ -49,8 +52,7 @@ pub async fn ping_notifier(type_: Ping, data: Option<(String, String)>) -> Resul
 	let res = conn.write(&bytes).await;
 
 	if let Err(e) = res {
-		admin_logger(LogType::FailedToPing, &format!("Failed to write to socket. e: {}", e), None)
-			.map_err(|_e| FailedToLog)?;
+		admin_logger(LogType::FailedToPing, &format!("Failed to write to socket. e: {}", e), None);
 		return Err(FailedToNotify);
 	}
 
@@ -58,27 +60,45 @@ pub async fn ping_notifier(type_: Ping, data: Option<(String, String)>) -> Resul
 		// send the client data to the notifier
 		let (userid, token)= data.unwrap();
 		let bytes = userid.as_bytes();
-		let res = conn.write(&bytes).await;
+		let res = conn.write(bytes).await;
 
 		if let Err(e) = res {
-			admin_logger(LogType::FailedToPing, &format!("Failed to write data to socket after successful ping. e: {}", e), None)
-				.map_err(|_e| FailedToLog)?;
+			admin_logger(LogType::FailedToPing, &format!("Failed to write data to socket after successful ping. e: {}", e), None);
 			return Err(FailedToNotify);
 		}
 
 		let bytes = token.as_bytes();
-		let res = conn.write(&bytes).await;
+		let res = conn.write(bytes).await;
 
 		if let Err(e) = res {
-			admin_logger(LogType::FailedToPing, &format!("Failed to write data to socket after successful ping. e: {}", e), None)
-				.map_err(|_e| FailedToLog)?;
+			admin_logger(LogType::FailedToPing, &format!("Failed to write data to socket after successful ping. e: {}", e), None);
 			return Err(FailedToNotify);
 		}
 	}
 
+	// every ping ends with the request ids, empty when there are none
+	let request_id = request_id.unwrap_or_default().as_bytes();
+	let mut bytes = (request_id.len() as u64).to_le_bytes().to_vec();
+	bytes.extend_from_slice(request_id);
+	if let Err(e) = conn.write_all(&bytes).await {
+		admin_logger(LogType::FailedToPing, &format!("Failed to write request id to socket. e: {}", e), None);
+		return Err(FailedToNotify);
+	}
+
+	// the notifier answers with one byte once it has read the whole ping
+	let mut ack = [0u8; 1];
+	let res = tokio::time::timeout(ACK_TIMEOUT, conn.read_exact(&mut ack)).await;
+	if !matches!(res, Ok(Ok(_))) {
+		admin_logger(LogType::FailedToPing, "Notifier did not acknowledge the ping", None);
+		return Err(FailedToNotify);
+	}
+
 	return Ok(());
 }
 
+#[utoipa::path(post, path = "/notifier/request_token", tag = "notifications", request_body = TokenRequest, responses(
+	(status = 200, description = "Token the notifier service knows the user by", body = TokenResponse)
+))]
 pub async fn gen_token(
 	extract::Json(req) : extract::Json<TokenRequest>
 ) -> Result<Json<TokenResponse>, StatusCode> {
@@ -86,9 +106,9 @@ pub async fn gen_token(
 
 	let token = utils::gen_random_token();
 	// tell notifier about this token
-	if let Err(_) = ping_notifier(Ping::ClientIdTransfer, Some((userid.to_string(), token.clone()))).await {
-		admin_logger(LogType::FailedToPing, &format!("Failed to send client token to notifier. userid: {}.", userid), None)
-			.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let request_id = logger::current_request_id();
+	if ping_notifier(Ping::ClientIdTransfer, Some((userid.to_string(), token.clone())), request_id.as_deref()).await.is_err() {
+		admin_logger(LogType::FailedToPing, &format!("Failed to send client token to notifier. userid: {}.", userid), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
+use crate::auth::AuthUser;
+use crate::users;
+use crate::logger::{admin_logger, LogType};
+use crate::ticket::{make_cursor, parse_cursor, Cursor, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationsReq {
+	pub unread_only: Option<bool>,
+	pub limit: Option<i64>,
+	pub cursor: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Notification {
+	pub id: i32,
+	pub message: String,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub urgent: bool,
+	// set on digest summaries
+	pub digest_count: Option<i32>,
+	// how many identical notifications were collapsed into this one
+	pub occurrences: i32
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Notifications {
+	pub notifications: Vec<Notification>,
+	pub next_cursor: Option<String>
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+pub struct NotificationSettings {
+	// non-urgent notifications are collected into one summary per digest interval
+	pub digest: bool
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UnreadCount {
+	pub unread: i64
+}
+
+fn dedup_window() -> chrono::Duration {
+	let secs = std::env::var("NOTIFICATION_DEDUP_SECS")
+		.ok()
+		.and_then(|s| s.parse::<i64>().ok())
+		.unwrap_or(300);
+	return chrono::Duration::seconds(secs);
+}
+
+pub struct NewNotification<'a> {
+	pub username: &'a str,
+	pub dedup_key: &'a str,
+	pub message: &'a str
+}
+
+// one row per user and dedup key of `notifications`, with the last message and how many times it was sent.
+// notifications to users that do not exist are left out
+fn collapse<'a>(notifications: &[NewNotification<'a>], userids: &HashMap<String, uuid::Uuid>) -> Vec<(uuid::Uuid, &'a str, &'a str, i32)> {
+	let mut rows: Vec<(uuid::Uuid, &str, &str, i32)> = Vec::new();
+	for notification in notifications {
+		let Some(userid) = userids.get(notification.username) else {
+			continue;
+		};
+		match rows.iter_mut().find(|row| row.0 == *userid && row.1 == notification.dedup_key) {
+			Some(row) => {
+				row.2 = notification.message;
+				row.3 += 1;
+			}
+			None => rows.push((*userid, notification.dedup_key, notification.message, 1))
+		}
+	}
+	return rows;
+}
+
+// adds notifications about the ticket, or bumps the count of unread ones from the same template sent within
+// the dedup window. a bumped row is handed to the notifier again. the notifications of a ticket update are written
+// together, one sent twice counts as bumped once. returns the user of each notification and whether a new row was added
+pub async fn add_deduped(
+	conn: &mut PgConnection,
+	ticket_id: i32,
+	notifications: &[NewNotification<'_>]
+) -> Result<Vec<Option<(uuid::Uuid, bool)>>, sqlx::Error> {
+	let usernames: Vec<&str> = notifications.iter().map(|n| n.username).collect();
+	let userids = users::userids(&mut *conn, &usernames).await?;
+	let rows = collapse(notifications, &userids);
+	let now = chrono::Utc::now();
+
+	let bumped: Vec<(uuid::Uuid, String)> = sqlx::query_as(
+		r#"update notifications n set occurrences=n.occurrences+w.occurrences, message=w.message, created_at=$5, delivered_at=null
+			from (
+				select distinct on (n.userid, n.dedup_key) n.id, w.message, w.occurrences
+				from notifications n join unnest($1::uuid[], $2::text[], $3::text[], $4::int4[]) as w(userid, dedup_key, message, occurrences)
+					on n.userid=w.userid and n.dedup_key=w.dedup_key
+				where n.ticket_id=$6 and n.read_at is null and n.created_at > $7
+				order by n.userid, n.dedup_key, n.created_at desc
+			) w
+			where n.id=w.id returning n.userid, n.dedup_key"#)
+		.bind(rows.iter().map(|r| r.0).collect::<Vec<_>>())
+		.bind(rows.iter().map(|r| r.1).collect::<Vec<_>>())
+		.bind(rows.iter().map(|r| r.2).collect::<Vec<_>>())
+		.bind(rows.iter().map(|r| r.3).collect::<Vec<_>>())
+		.bind(now)
+		.bind(ticket_id)
+		.bind(now - dedup_window())
+		.fetch_all(&mut *conn)
+		.await?;
+
+	let fresh: Vec<_> = rows.iter().filter(|r| !bumped.iter().any(|(userid, key)| *userid == r.0 && key == r.1)).collect();
+	if !fresh.is_empty() {
+		sqlx::query(
+			r#"insert into notifications (userid, message, created_at, ticket_id, dedup_key, occurrences)
+				select userid, message, $5, $6, dedup_key, occurrences
+				from unnest($1::uuid[], $2::text[], $3::text[], $4::int4[]) as w(userid, dedup_key, message, occurrences)"#)
+			.bind(fresh.iter().map(|r| r.0).collect::<Vec<_>>())
+			.bind(fresh.iter().map(|r| r.1).collect::<Vec<_>>())
+			.bind(fresh.iter().map(|r| r.2).collect::<Vec<_>>())
+			.bind(fresh.iter().map(|r| r.3).collect::<Vec<_>>())
+			.bind(now)
+			.bind(ticket_id)
+			.execute(&mut *conn)
+			.await?;
+	}
+
+	// only the first notification of an inserted row added it, the ones after it were bumps
+	let mut added = Vec::new();
+	let mut seen: Vec<(uuid::Uuid, &str)> = Vec::new();
+	for notification in notifications {
+		let Some(userid) = userids.get(notification.username).copied() else {
+			added.push(None);
+			continue;
+		};
+		let first = !seen.contains(&(userid, notification.dedup_key));
+		seen.push((userid, notification.dedup_key));
+		let inserted = first && fresh.iter().any(|r| r.0 == userid && r.1 == notification.dedup_key);
+		added.push(Some((userid, inserted)));
+	}
+	return Ok(added);
+}
+
+// unread notifications sort before read ones, so the cursor priority is 1 for unread and 0 for read
+fn page_cursor(notification: &Notification) -> Cursor {
+	let priority = if notification.read_at.is_none() { 1 } else { 0 };
+	return Cursor { priority, time: notification.created_at, id: notification.id };
+}
+
+#[utoipa::path(get, path = "/notifications", tag = "notifications", params(NotificationsReq), responses(
+	(status = 200, description = "Notifications of the user, newest first", body = Notifications),
+	(status = 400, description = "Unknown cursor")
+))]
+pub async fn get_notifications(
+	user: AuthUser,
+	extract::Query(query): extract::Query<NotificationsReq>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Notifications>, StatusCode> {
+	let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+	let cursor = match &query.cursor {
+		Some(c) => Some(parse_cursor(c).ok_or(StatusCode::BAD_REQUEST)?),
+		None => None
+	};
+
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+		"select id, coalesce(message, '') as message, created_at, read_at, urgent, digest_count, occurrences from notifications where userid=");
+	builder.push_bind(user.userid);
+	if query.unread_only.unwrap_or(false) {
+		builder.push(" and read_at is null");
+	}
+	if let Some(cursor) = &cursor {
+		builder.push(" and ((read_at is null)::int, created_at, id) < (")
+			.push_bind(cursor.priority)
+			.push(", ")
+			.push_bind(cursor.time)
+			.push(", ")
+			.push_bind(cursor.id)
+			.push(")");
+	}
+	builder.push(" order by (read_at is null)::int desc, created_at desc, id desc limit ")
+		.push_bind(limit + 1);
+
+	let query: Result<Vec<Notification>, _> = builder.build_query_as()
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading notifications of user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let mut notifications = query.unwrap();
+
+	// one extra row is fetched to know if there is another page
+	let mut next_cursor = None;
+	if notifications.len() as i64 > limit {
+		notifications.truncate(limit as usize);
+		next_cursor = Some(make_cursor(&page_cursor(notifications.last().unwrap())));
+	}
+
+	return Ok(Json(Notifications { notifications, next_cursor }));
+}
+
+#[utoipa::path(get, path = "/notifications/unread_count", tag = "notifications", responses(
+	(status = 200, description = "Unread notifications", body = UnreadCount)
+))]
+pub async fn get_unread_count(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<UnreadCount>, StatusCode> {
+	let query: Result<(i64,), _> = sqlx::query_as("select count(*) from notifications where userid=$1 and read_at is null")
+		.bind(user.userid)
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error counting unread notifications of user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(UnreadCount { unread: query.unwrap().0 }));
+}
+
+// marking a notification that is already read keeps its first read time
+#[utoipa::path(post, path = "/notifications/{id}/read", tag = "notifications", params(("id" = i32, Path, description = "Notification id")), responses(
+	(status = 200, description = "Read"),
+	(status = 404, description = "Unknown notification")
+))]
+pub async fn mark_read(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update notifications set read_at=coalesce(read_at, $3) where id=$1 and userid=$2")
+		.bind(id)
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error marking notification {} read for user {}: {}", id, user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	// notifications of other users are reported as missing
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	return Ok(StatusCode::OK);
+}
+
+// returns the number of notifications that were unread
+#[utoipa::path(post, path = "/notifications/read_all", tag = "notifications", responses(
+	(status = 200, description = "Everything is read", body = UnreadCount)
+))]
+pub async fn mark_all_read(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<UnreadCount>, StatusCode> {
+	let query = sqlx::query("update notifications set read_at=$2 where userid=$1 and read_at is null")
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error marking notifications read for user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(UnreadCount { unread: query.unwrap().rows_affected() as i64 }));
+}
+
+#[utoipa::path(get, path = "/notifications/settings", tag = "notifications", responses(
+	(status = 200, description = "Notification settings of the user", body = NotificationSettings)
+))]
+pub async fn get_settings(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<NotificationSettings>, StatusCode> {
+	let query: Result<NotificationSettings, _> = sqlx::query_as("select notification_digest as digest from users where userid=$1")
+		.bind(user.userid)
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading notification settings of user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// notifications held for a digest when it is turned off are sent by the notifier on its next pull
+#[utoipa::path(put, path = "/notifications/settings", tag = "notifications", request_body = NotificationSettings, responses(
+	(status = 200, description = "Saved", body = NotificationSettings)
+))]
+pub async fn update_settings(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<NotificationSettings>
+) -> Result<Json<NotificationSettings>, StatusCode> {
+	let query = sqlx::query("update users set notification_digest=$2 where userid=$1")
+		.bind(user.userid)
+		.bind(payload.digest)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error updating notification settings of user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(payload));
+}
+
+#[cfg(test)]
+mod notifications_tests {
+	use std::collections::HashMap;
+	use super::{collapse, page_cursor, NewNotification, Notification};
+
+	#[test]
+	fn unread_notifications_page_first() {
+		let mut notification = Notification {
+			id: 3,
+			message: "Ticket 7 needs your approval".to_string(),
+			created_at: chrono::Utc::now(),
+			read_at: None,
+			urgent: false,
+			digest_count: None,
+			occurrences: 1
+		};
+		let unread = page_cursor(&notification);
+		notification.read_at = Some(chrono::Utc::now());
+		let read = page_cursor(&notification);
+
+		assert_eq!(unread.priority, 1);
+		assert_eq!(read.priority, 0);
+		assert!((read.priority, read.time, read.id) < (unread.priority, unread.time, unread.id));
+	}
+
+	#[test]
+	fn repeated_notifications_are_collapsed() {
+		let jdoe = uuid::Uuid::new_v4();
+		let userids = HashMap::from([("jdoe".to_string(), jdoe)]);
+		let notifications = [
+			NewNotification { username: "jdoe", dedup_key: "notify", message: "first" },
+			NewNotification { username: "ghost", dedup_key: "notify", message: "lost" },
+			NewNotification { username: "jdoe", dedup_key: "notify", message: "second" },
+			NewNotification { username: "jdoe", dedup_key: "notify:3", message: "other" }
+		];
+		assert_eq!(collapse(&notifications, &userids), vec![(jdoe, "notify", "second", 2), (jdoe, "notify:3", "other", 1)]);
+	}
+}
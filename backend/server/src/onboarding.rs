@@ -0,0 +1,678 @@
+use axum::{body::Bytes, extract, http::{HeaderMap, StatusCode}, Json};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::api_keys::ApiKey;
+use crate::assets::{self, AssetRequest};
+use crate::auth::AuthUser;
+use crate::callbacks;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::process::{read_process_data, Step};
+use crate::rbac::{self, Authorized, ManageUsers};
+use crate::schema::{FieldError, FieldErrors, field_error, internal_error, status_error};
+use crate::tenant;
+use crate::ticket::{self, Event, UpdateErr, UpdateSource, UpdateTicket};
+use utoipa::{IntoParams, ToSchema};
+
+pub const KINDS: [&str; 2] = ["onboarding", "offboarding"];
+// args of the blocking_task node completed once the employee acknowledged every policy
+pub const POLICY_NODE: &str = "policy_acknowledgment";
+
+// the built-in processes, used by departments without a process of their own
+fn default_process_id(kind: &str) -> String {
+	if kind == "offboarding" {
+		return std::env::var("EMPLOYEE_OFFBOARDING_PROCESS").unwrap_or("employee_offboarding".to_string());
+	}
+	return std::env::var("EMPLOYEE_ONBOARDING_PROCESS").unwrap_or("employee_onboarding".to_string());
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveConfig {
+	// null for the built-in process
+	pub process_id: Option<String>,
+	#[serde(default)]
+	pub asset_categories: Vec<String>,
+	#[serde(default)]
+	pub policy_ids: Vec<i32>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct OnboardingConfig {
+	pub department_id: i32,
+	pub kind: String,
+	pub process_id: Option<String>,
+	pub asset_categories: Vec<String>,
+	pub policy_ids: Vec<i32>,
+	pub updated_by: Option<uuid::Uuid>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+// what an onboarding takes from the configuration of the department
+#[derive(Default, FromRow)]
+struct CopiedConfig {
+	process_id: Option<String>,
+	asset_categories: Vec<String>,
+	policy_ids: Vec<i32>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateOnboarding {
+	// onboarding or offboarding
+	pub kind: String,
+	pub userid: uuid::Uuid,
+	// the primary department of the employee when missing
+	pub department_id: Option<i32>,
+	pub start_date: NaiveDate
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Onboarding {
+	pub id: i32,
+	pub kind: String,
+	pub userid: uuid::Uuid,
+	pub department_id: Option<i32>,
+	pub start_date: NaiveDate,
+	pub process_id: String,
+	pub asset_categories: Vec<String>,
+	pub policy_ids: Vec<i32>,
+	pub status: String,
+	pub ticket_id: Option<i32>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Acknowledgment {
+	pub document_id: i32,
+	pub version: i32,
+	pub acknowledged_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OnboardingDetail {
+	#[serde(flatten)]
+	pub onboarding: Onboarding,
+	pub acknowledgments: Vec<Acknowledgment>,
+	// the equipment handed out or taken back
+	pub asset_requests: Vec<AssetRequest>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OnboardingsQuery {
+	pub kind: Option<String>,
+	pub status: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Acknowledge {
+	pub document_ids: Vec<i32>
+}
+
+// what the systems creating or revoking accounts need to know about the employee
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Employee {
+	pub onboarding_id: i32,
+	pub kind: String,
+	pub userid: uuid::Uuid,
+	pub username: String,
+	pub email: Option<String>,
+	pub department: Option<String>,
+	pub start_date: NaiveDate
+}
+
+pub fn check_config(config: &SaveConfig) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	if config.process_id.as_deref().is_some_and(|p| p.trim().is_empty()) {
+		errors.push(FieldError { field: "/process_id".to_string(), message: "Leave it out for the built-in process".to_string() });
+	}
+	for (i, category) in config.asset_categories.iter().enumerate() {
+		if category.trim().is_empty() {
+			errors.push(FieldError { field: format!("/asset_categories/{}", i), message: "A category is required".to_string() });
+		}
+	}
+	return errors;
+}
+
+// the node the acknowledgments complete
+pub fn policy_node(steps: &[Step]) -> Option<i32> {
+	return steps.iter()
+		.position(|step| step.event == Event::BlockingTask
+			&& step.args.as_ref().and_then(|a| a.first()).is_some_and(|a| a == POLICY_NODE))
+		.map(|i| i as i32);
+}
+
+// the policies still to be acknowledged
+pub fn pending_policies(policy_ids: &[i32], acknowledged: &[i32]) -> Vec<i32> {
+	return policy_ids.iter().filter(|id| !acknowledged.contains(id)).cloned().collect();
+}
+
+// the status an onboarding in `current` moves to when its ticket asks for `target`
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("in_progress", "closed") => Some("completed"),
+		("in_progress", "rejected") => Some("rejected"),
+		("in_progress", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+fn check_kind(kind: &str) -> Result<(), (StatusCode, Json<FieldErrors>)> {
+	if !KINDS.contains(&kind) {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/kind", "Expected onboarding or offboarding".to_string()));
+	}
+	return Ok(());
+}
+
+async fn read_onboarding(conn: &mut PgConnection, id: i32, lock: bool) -> Result<Onboarding, StatusCode> {
+	let sql = if lock { "select * from onboardings where id=$1 for update" } else { "select * from onboardings where id=$1" };
+	let onboarding: Result<Option<Onboarding>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = onboarding {
+		admin_logger(LogType::Error, &format!("Error reading onboarding {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return onboarding.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_detail(conn: &mut PgConnection, onboarding: Onboarding) -> Result<OnboardingDetail, StatusCode> {
+	let acknowledgments: Result<Vec<Acknowledgment>, _> = sqlx::query_as(
+		"select document_id, version, acknowledged_at from policy_acknowledgments where onboarding_id=$1 order by acknowledged_at")
+		.bind(onboarding.id)
+		.fetch_all(&mut *conn)
+		.await;
+	let asset_requests: Result<Vec<AssetRequest>, _> = sqlx::query_as("select * from asset_requests where onboarding_id=$1 order by id")
+		.bind(onboarding.id)
+		.fetch_all(&mut *conn)
+		.await;
+	match (acknowledgments, asset_requests) {
+		(Ok(acknowledgments), Ok(asset_requests)) => return Ok(OnboardingDetail { onboarding, acknowledgments, asset_requests }),
+		(Err(e), _) | (_, Err(e)) => {
+			admin_logger(LogType::Error, &format!("Error reading onboarding {}: {}", onboarding.id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+	}
+}
+
+#[utoipa::path(get, path = "/departments/{id}/onboarding/{kind}", tag = "onboarding", params(("id" = i32, Path, description = "Department id"), ("kind" = String, Path, description = "onboarding or offboarding")), responses(
+	(status = 200, description = "What onboardings of the department use", body = OnboardingConfig),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "The department has no configuration")
+))]
+pub async fn get_config(
+	_auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((department_id, kind)): extract::Path<(i32, String)>
+) -> Result<Json<OnboardingConfig>, StatusCode> {
+	let config: Result<Option<OnboardingConfig>, _> = sqlx::query_as(
+		r#"select department_id, kind, process_id, asset_categories, policy_ids, updated_by, updated_at from onboarding_configs
+			where department_id=$1 and kind=$2"#)
+		.bind(department_id)
+		.bind(&kind)
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = config {
+		admin_logger(LogType::Error, &format!("Error reading the {} configuration of department {}: {}", kind, department_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return config.unwrap().map(Json).ok_or(StatusCode::NOT_FOUND);
+}
+
+// sets the process, the equipment and the policies of the department. runs that already started keep theirs
+#[utoipa::path(put, path = "/departments/{id}/onboarding/{kind}", tag = "onboarding", params(("id" = i32, Path, description = "Department id"), ("kind" = String, Path, description = "onboarding or offboarding")), request_body = SaveConfig, responses(
+	(status = 200, description = "Saved", body = OnboardingConfig),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown department", body = FieldErrors),
+	(status = 422, description = "Unknown kind, process, category or policy", body = FieldErrors)
+))]
+pub async fn save_config(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((department_id, kind)): extract::Path<(i32, String)>,
+	Json(payload): Json<SaveConfig>
+) -> Result<Json<OnboardingConfig>, (StatusCode, Json<FieldErrors>)> {
+	check_kind(&kind)?;
+	let errors = check_config(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+	let process_id = payload.process_id.as_deref().map(str::trim);
+	let categories: Vec<&str> = payload.asset_categories.iter().map(|c| c.trim()).collect();
+
+	let mut conn = pool.acquire().await.map_err(|_| internal_error())?;
+	let found: Result<(bool, bool, i64), _> = sqlx::query_as(
+		r#"select exists(select 1 from departments where id=$1),
+				$2::varchar is null or exists(select 1 from process_defs where process_id=$2),
+				(select count(*) from documents where id=any($3))"#)
+		.bind(department_id)
+		.bind(process_id)
+		.bind(&payload.policy_ids)
+		.fetch_one(&mut *conn)
+		.await;
+	if let Err(e) = found {
+		admin_logger(LogType::Error, &format!("Error checking the {} configuration of department {}: {}", kind, department_id, e), None);
+		return Err(internal_error());
+	}
+	let (department, process, policies) = found.unwrap();
+	if !department {
+		return Err(status_error(StatusCode::NOT_FOUND));
+	}
+	if !process {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/process_id", format!("Process {} does not exist", process_id.unwrap_or(""))));
+	}
+	let mut distinct = payload.policy_ids.clone();
+	distinct.sort();
+	distinct.dedup();
+	if policies != distinct.len() as i64 {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/policy_ids", "Every policy has to be an existing document".to_string()));
+	}
+
+	let config: Result<OnboardingConfig, _> = sqlx::query_as(
+		r#"insert into onboarding_configs (department_id, kind, process_id, asset_categories, policy_ids, updated_by, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7)
+			on conflict (department_id, kind) do update set process_id=$3, asset_categories=$4, policy_ids=$5, updated_by=$6, updated_at=$7
+			returning department_id, kind, process_id, asset_categories, policy_ids, updated_by, updated_at"#)
+		.bind(department_id)
+		.bind(&kind)
+		.bind(process_id)
+		.bind(&categories)
+		.bind(&distinct)
+		.bind(auth.user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *conn)
+		.await;
+	if let Err(e) = config {
+		admin_logger(LogType::Error, &format!("Error saving the {} configuration of department {}: {}", kind, department_id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} changed the {} configuration of department {}", auth.user.userid, kind, department_id), None);
+	return Ok(Json(config.unwrap()));
+}
+
+// starts onboarding or offboarding the employee with the configuration of their department
+#[utoipa::path(post, path = "/onboardings", tag = "onboarding", request_body = CreateOnboarding, responses(
+	(status = 201, description = "Created with its ticket", body = OnboardingDetail),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 409, description = "The employee has an onboarding of this kind running", body = FieldErrors),
+	(status = 422, description = "Unknown kind, user or department", body = FieldErrors)
+))]
+pub async fn create_onboarding(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateOnboarding>
+) -> Result<(StatusCode, Json<OnboardingDetail>), (StatusCode, Json<FieldErrors>)> {
+	check_kind(&payload.kind)?;
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let employee: Result<Option<(String, Option<i32>)>, _> = sqlx::query_as(
+		r#"select u.username, coalesce($2, (select department_id from user_departments where userid=u.userid order by is_primary desc, department_id limit 1))
+			from users u where u.userid=$1"#)
+		.bind(payload.userid)
+		.bind(payload.department_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = employee {
+		admin_logger(LogType::Error, &format!("Error reading employee {}: {}", payload.userid, e), None);
+		return Err(internal_error());
+	}
+	let Some((username, department_id)) = employee.unwrap() else {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/userid", "Unknown user".to_string()));
+	};
+
+	let config: Result<Option<CopiedConfig>, _> = sqlx::query_as(
+		"select process_id, asset_categories, policy_ids from onboarding_configs where department_id=$1 and kind=$2")
+		.bind(department_id)
+		.bind(&payload.kind)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = config {
+		admin_logger(LogType::Error, &format!("Error reading the {} configuration of department {:?}: {}", payload.kind, department_id, e), None);
+		return Err(internal_error());
+	}
+	let CopiedConfig { process_id, asset_categories, policy_ids } = config.unwrap().unwrap_or_default();
+	let process_id = process_id.unwrap_or(default_process_id(&payload.kind));
+
+	let now = chrono::Utc::now();
+	let onboarding: Result<Onboarding, _> = sqlx::query_as(
+		r#"insert into onboardings (kind, userid, department_id, start_date, process_id, asset_categories, policy_ids, created_by, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9) returning *"#)
+		.bind(&payload.kind)
+		.bind(payload.userid)
+		.bind(department_id)
+		.bind(payload.start_date)
+		.bind(&process_id)
+		.bind(&asset_categories)
+		.bind(&policy_ids)
+		.bind(auth.user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = onboarding {
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err(field_error(StatusCode::CONFLICT, "/userid", format!("An {} of {} is in progress", payload.kind, username)));
+		}
+		admin_logger(LogType::Error, &format!("Error saving the {} of {}: {}", payload.kind, payload.userid, e), None);
+		return Err(internal_error());
+	}
+	let onboarding = onboarding.unwrap();
+
+	// the people completing the tasks see who it is for
+	let mut data = Map::new();
+	data.insert("onboarding_id".to_string(), json!(onboarding.id));
+	data.insert("employee_id".to_string(), json!(payload.userid));
+	data.insert("employee".to_string(), json!(username));
+	data.insert("department_id".to_string(), json!(department_id));
+	data.insert("start_date".to_string(), json!(payload.start_date));
+	data.insert("asset_categories".to_string(), json!(asset_categories));
+	data.insert("policy_ids".to_string(), json!(policy_ids));
+	let tag = if payload.kind == "onboarding" { "onboarding" } else { "offboarding" };
+	let new = NewTicket { process_id, data, tag, due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &auth.user, Record { table: "onboardings", id: onboarding.id }, new)
+		.await
+		.map_err(status_error)?;
+
+	// node 0 may already have moved it
+	let onboarding = read_onboarding(&mut tx, onboarding.id, false).await.map_err(status_error)?;
+	let detail = read_detail(&mut tx, onboarding).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting onboarding {}: {}", detail.onboarding.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("User {} started the {} of {} with ticket {}", auth.user.userid, payload.kind, username, ticket.id), ticket.log_id);
+	return Ok((StatusCode::CREATED, Json(detail)));
+}
+
+#[utoipa::path(get, path = "/onboardings", tag = "onboarding", params(OnboardingsQuery), responses(
+	(status = 200, description = "Onboardings and offboardings", body = Vec<Onboarding>),
+	(status = 403, description = "Missing the manage_users permission")
+))]
+pub async fn get_onboardings(
+	_auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<OnboardingsQuery>
+) -> Result<Json<Vec<Onboarding>>, StatusCode> {
+	let onboardings: Result<Vec<Onboarding>, _> = sqlx::query_as(
+		r#"select * from onboardings where ($1::varchar is null or kind=$1) and ($2::varchar is null or status=$2)
+			order by start_date desc, id desc"#)
+		.bind(&query.kind)
+		.bind(&query.status)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = onboardings {
+		admin_logger(LogType::Error, &format!("Error reading onboardings: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(onboardings.unwrap()));
+}
+
+// for the employee and manage_users holders
+#[utoipa::path(get, path = "/onboardings/{id}", tag = "onboarding", params(("id" = i32, Path, description = "Onboarding id")), responses(
+	(status = 200, description = "The onboarding with its acknowledgments and equipment", body = OnboardingDetail),
+	(status = 404, description = "Unknown onboarding or not the employee or a user with manage_users")
+))]
+pub async fn get_onboarding(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<OnboardingDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let onboarding = read_onboarding(&mut conn, id, false).await?;
+	if onboarding.userid != user.userid {
+		match rbac::has_permission(&mut conn, user.userid, "manage_users").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			// the same answer as for onboardings that do not exist
+			Ok(false) => return Err(StatusCode::NOT_FOUND),
+			Ok(true) => {}
+		}
+	}
+	return Ok(Json(read_detail(&mut conn, onboarding).await?));
+}
+
+// the employee acknowledges having read the current approved version of policies. once all of them are acknowledged
+// the policy node of the ticket is completed
+#[utoipa::path(post, path = "/onboardings/{id}/acknowledgments", tag = "onboarding", params(("id" = i32, Path, description = "Onboarding id")), request_body = Acknowledge, responses(
+	(status = 200, description = "Acknowledged", body = OnboardingDetail),
+	(status = 404, description = "Unknown onboarding or not the employee", body = FieldErrors),
+	(status = 409, description = "The onboarding is not running", body = FieldErrors),
+	(status = 422, description = "Not a policy of the onboarding", body = FieldErrors)
+))]
+pub async fn acknowledge_policies(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<Acknowledge>
+) -> Result<Json<OnboardingDetail>, (StatusCode, Json<FieldErrors>)> {
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let onboarding = read_onboarding(&mut tx, id, true).await.map_err(status_error)?;
+	if onboarding.userid != user.userid {
+		return Err(status_error(StatusCode::NOT_FOUND));
+	}
+	if onboarding.kind != "onboarding" || onboarding.status != "in_progress" {
+		return Err(field_error(StatusCode::CONFLICT, "/status", format!("The {} is {}", onboarding.kind, onboarding.status)));
+	}
+
+	let now = chrono::Utc::now();
+	for (i, document_id) in payload.document_ids.iter().enumerate() {
+		if !onboarding.policy_ids.contains(document_id) {
+			return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, &format!("/document_ids/{}", i), format!("Document {} is not a policy of this onboarding", document_id)));
+		}
+		let query = sqlx::query(
+			r#"insert into policy_acknowledgments (onboarding_id, document_id, version, acknowledged_at)
+				select $1, id, current_version, $3 from documents where id=$2 and status='approved'
+				on conflict (onboarding_id, document_id) do nothing"#)
+			.bind(id)
+			.bind(document_id)
+			.bind(now)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error saving the acknowledgment of document {} for onboarding {}: {}", document_id, id, e), None);
+			return Err(internal_error());
+		}
+	}
+	let detail = read_detail(&mut tx, onboarding).await.map_err(status_error)?;
+	let acknowledged: Vec<i32> = detail.acknowledgments.iter().map(|a| a.document_id).collect();
+	let pending = pending_policies(&detail.onboarding.policy_ids, &acknowledged);
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the acknowledgments of onboarding {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	if !pending.is_empty() {
+		return Ok(Json(detail));
+	}
+
+	let steps = read_process_data(detail.onboarding.process_id.clone()).await.map(|p| p.steps);
+	if let Err(e) = steps {
+		admin_logger(LogType::Error, &format!("Error reading process {} of onboarding {}: {}", detail.onboarding.process_id, id, e), None);
+		return Err(internal_error());
+	}
+	let (Some(node), Some(ticket_id)) = (policy_node(&steps.unwrap()), detail.onboarding.ticket_id) else {
+		return Ok(Json(detail));
+	};
+	let update = UpdateTicket {
+		ticket_id,
+		user_id: detail.onboarding.created_by,
+		status: true,
+		node,
+		data: Some(Map::from_iter([("acknowledged_policies".to_string(), json!(acknowledged))])),
+		instance: None,
+		reason: None
+	};
+	match ticket::apply_update(&pool, update, UpdateSource::Service).await {
+		Ok(_) => {}
+		Err(UpdateErr::Status(status)) => return Err(status_error(status)),
+		Err(UpdateErr::Problem(e)) => return Err(status_error(e.status)),
+		Err(UpdateErr::InvalidData(errors) | UpdateErr::InvalidRequest(errors)) => return Err((StatusCode::CONFLICT, Json(FieldErrors { errors })))
+	}
+
+	admin_logger(LogType::Info, &format!("User {} acknowledged every policy of onboarding {}", user.userid, id), None);
+	let mut conn = pool.acquire().await.map_err(|_| internal_error())?;
+	let onboarding = read_onboarding(&mut conn, id, false).await.map_err(status_error)?;
+	return Ok(Json(read_detail(&mut conn, onboarding).await.map_err(status_error)?));
+}
+
+// the webhook of the equipment node. onboardings request an asset of every configured category, offboardings return
+// every asset the employee has. requests that cannot be fulfilled keep their error for /admin/asset_requests/:id/fulfil
+#[utoipa::path(post, path = "/service/onboarding/equipment", tag = "service", security(()), params(("X-Erp-Timestamp" = i64, Header, description = "Unix seconds the body was signed at"), ("X-Erp-Signature" = String, Header, description = "Hex hmac-sha256 of the timestamp and body with the callback secret of the process")), request_body(content = Object, description = "The task payload posted by the webhook callback of the node, unchanged"), responses(
+	(status = 200, description = "The equipment requests of the onboarding are made"),
+	(status = 401, description = "Bad signature"),
+	(status = 404, description = "No onboarding belongs to the ticket"),
+	(status = 409, description = "The onboarding is not running"),
+	(status = 422, description = "Not a task payload")
+))]
+pub async fn equipment_task(
+	extract::State(pool): extract::State<PgPool>,
+	headers: HeaderMap,
+	body: Bytes
+) -> Result<StatusCode, StatusCode> {
+	let tenant = callbacks::task_tenant(&pool, &body).await?;
+	return tenant::scoped(Some(tenant), request_equipment(pool, headers, body)).await;
+}
+
+async fn request_equipment(pool: PgPool, headers: HeaderMap, body: Bytes) -> Result<StatusCode, StatusCode> {
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let ticket_id = callbacks::verify_task(&mut tx, &headers, &body).await?;
+	let onboarding: Result<Option<Onboarding>, _> = sqlx::query_as("select * from onboardings where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = onboarding {
+		admin_logger(LogType::Error, &format!("Error reading the onboarding of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let onboarding = onboarding.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	// the task may be delivered more than once, the requests are only made the first time.
+	// they belong to the tenant of the onboarding, the one the task is handled in
+	let query = if onboarding.kind == "onboarding" {
+		sqlx::query(
+			r#"insert into asset_requests (tenant_id, kind, userid, category, status, onboarding_id, created_at, updated_at)
+				select o.tenant_id, 'request', o.userid, c, 'approved', o.id, $2, $2 from onboardings o, unnest(o.asset_categories) c
+				where o.id=$1 and not exists(select 1 from asset_requests where onboarding_id=$1)"#)
+	} else {
+		sqlx::query(
+			r#"insert into asset_requests (tenant_id, kind, userid, asset_id, status, onboarding_id, created_at, updated_at)
+				select o.tenant_id, 'return', o.userid, a.id, 'approved', o.id, $2, $2 from onboardings o join assets a on a.assigned_to=o.userid
+				where o.id=$1 and not exists(select 1 from asset_requests where onboarding_id=$1)"#)
+	};
+	if let Err(e) = query.bind(onboarding.id).bind(chrono::Utc::now()).execute(&mut *tx).await {
+		admin_logger(LogType::Error, &format!("Error requesting the equipment of onboarding {}: {}", onboarding.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let requests: Result<Vec<(i32,)>, _> = sqlx::query_as("select id from asset_requests where onboarding_id=$1 and fulfilled_at is null order by id")
+		.bind(onboarding.id)
+		.fetch_all(&mut *tx)
+		.await;
+	if let Err(e) = requests {
+		admin_logger(LogType::Error, &format!("Error reading the equipment of onboarding {}: {}", onboarding.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the equipment of onboarding {}: {}", onboarding.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let mut failed = 0;
+	for (request_id,) in requests.unwrap() {
+		match assets::fulfil(&pool, request_id).await {
+			Ok(Ok(_)) => {}
+			Ok(Err(_)) | Err(_) => failed += 1
+		}
+	}
+	if failed > 0 {
+		admin_logger(LogType::Warning, &format!("{} asset requests of onboarding {} could not be fulfilled", failed, onboarding.id), None);
+		return Err(StatusCode::CONFLICT);
+	}
+	return Ok(StatusCode::OK);
+}
+
+// read by the systems creating or revoking the accounts of the employee after their webhook was called with the ticket
+#[utoipa::path(get, path = "/service/onboarding/{ticket_id}", tag = "service", security(("api_key" = [])), params(("ticket_id" = i32, Path, description = "Ticket of the onboarding")), responses(
+	(status = 200, description = "Who the onboarding is for", body = Employee),
+	(status = 403, description = "The key lacks the read_onboarding scope"),
+	(status = 404, description = "No onboarding belongs to the ticket")
+))]
+pub async fn get_employee(
+	key: ApiKey,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>
+) -> Result<Json<Employee>, StatusCode> {
+	key.require("read_onboarding")?;
+	let employee: Result<Option<Employee>, _> = sqlx::query_as(
+		r#"select o.id as onboarding_id, o.kind, o.userid, u.username, u.email, d.name as department, o.start_date
+			from onboardings o join users u on o.userid=u.userid left join departments d on o.department_id=d.id
+			where o.ticket_id=$1"#)
+		.bind(ticket_id)
+		.fetch_optional(&pool)
+		.await;
+	if let Err(e) = employee {
+		admin_logger(LogType::Error, &format!("Error reading the onboarding of ticket {} for api key {}: {}", ticket_id, key.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return employee.unwrap().map(Json).ok_or(StatusCode::NOT_FOUND);
+}
+
+// moves the onboarding of the ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let onboarding: Option<(i32, String)> = sqlx::query_as("select id, status from onboardings where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = onboarding else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update onboardings set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Onboarding {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod onboarding_tests {
+	use crate::process::read_process_data;
+	use super::{check_config, pending_policies, policy_node, SaveConfig};
+
+	#[tokio::test]
+	async fn the_built_in_onboarding_ends_with_the_policies() {
+		let steps = read_process_data("employee_onboarding".to_string()).await.unwrap().steps;
+		assert_eq!(policy_node(&steps), Some(3));
+		let steps = read_process_data("employee_offboarding".to_string()).await.unwrap().steps;
+		assert_eq!(policy_node(&steps), None);
+
+		assert_eq!(pending_policies(&[4, 7, 9], &[9, 4]), vec![7]);
+		assert!(pending_policies(&[], &[]).is_empty());
+	}
+
+	#[test]
+	fn configs_are_checked() {
+		let config = SaveConfig { process_id: Some(" ".to_string()), asset_categories: vec!["laptop".to_string(), "".to_string()], policy_ids: vec![] };
+		let fields: Vec<String> = check_config(&config).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/process_id", "/asset_categories/1"]);
+	}
+}
@@ -0,0 +1,204 @@
+use axum::{response::Html, Json};
+use utoipa::{Modify, OpenApi};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use crate::{admin, api_keys, assets, audit, auth, breaker, calendar, callback_endpoints, callbacks, crm, dashboard, db_types, delegation, departments, documents, export, import, invoices, jobs, leave, limits, logger, notif_handler, notifications, onboarding, process, purchase_orders, push, rbac, reports, roles, schedules, signals, slack, tags, templates, ticket, ticket_events, timesheets, users, vendors, watchers, webhooks, working_time, ws};
+use crate::api_keys::API_KEY_HEADER;
+
+// generated from the #[utoipa::path] of the handlers and the ToSchema of their types. a handler added to the router
+// without being listed here fails openapi_tests
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+	<title>ERP API</title>
+	<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+	<div id="swagger-ui"></div>
+	<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+	<script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>"##;
+
+#[derive(OpenApi)]
+#[openapi(
+	info(title = "ERP API"),
+	paths(
+		admin::reassign_ticket, admin::get_overdue_tickets, admin::archive_tickets, admin::revoke_sessions, admin::get_logs, admin::get_log_metrics,
+		api_keys::create_api_key, api_keys::get_api_keys, api_keys::rotate_api_key, api_keys::revoke_api_key, api_keys::complete_task,
+		assets::create_asset, assets::get_assets, assets::get_asset, assets::update_asset, assets::retire_asset, assets::get_user_assets, assets::create_asset_request, assets::get_asset_requests, assets::get_asset_request, assets::fulfil_task, assets::retry_fulfilment,
+		audit::get_audit_events, audit::verify_audit_chain,
+		auth::login, auth::refresh, auth::logout, auth::change_password,
+		breaker::get_breakers,
+		calendar::create_calendar_token, calendar::delete_calendar_token, calendar::get_calendar,
+		callback_endpoints::save_endpoint, callback_endpoints::get_endpoints, callback_endpoints::delete_endpoint,
+		callbacks::get_dead_jobs, callbacks::retry_dead_job, callbacks::callback_result, callbacks::rotate_callback_secret,
+		crm::create_customer, crm::get_customers, crm::get_customer, crm::update_customer, crm::deactivate_customer, crm::add_contact, crm::update_contact, crm::delete_contact, crm::create_opportunity, crm::get_opportunities, crm::get_opportunity, crm::update_opportunity, crm::move_opportunity,
+		dashboard::get_dashboard, dashboard::get_aging,
+		delegation::create_delegation, delegation::get_delegations,
+		departments::create_department, departments::get_departments, departments::update_department, departments::delete_department, departments::get_members, departments::add_member, departments::remove_member,
+		documents::create_document, documents::get_documents, documents::get_document, documents::get_version, documents::check_out, documents::undo_check_out, documents::check_in, documents::submit_document, documents::set_acl, documents::get_ticket_documents, documents::attach_document, documents::detach_document,
+		export::export_tickets,
+		import::import_tickets,
+		invoices::create_invoice, invoices::get_invoices, invoices::get_invoice, invoices::get_invoice_pdf, invoices::send_invoice, invoices::record_payment,
+		jobs::get_jobs,
+		leave::create_leave_request, leave::get_leave_requests, leave::get_leave_request, leave::get_leave_balances, leave::set_leave_balance,
+		limits::get_role_limits, limits::set_role_limit, limits::remove_role_limit,
+		notif_handler::gen_token,
+		notifications::get_notifications, notifications::get_unread_count, notifications::mark_read, notifications::mark_all_read, notifications::get_settings, notifications::update_settings,
+		onboarding::get_config, onboarding::save_config, onboarding::create_onboarding, onboarding::get_onboardings, onboarding::get_onboarding, onboarding::acknowledge_policies, onboarding::equipment_task, onboarding::get_employee,
+		process::get_all_processes, process::create_process, process::import_bpmn, process::get_process_data,
+		purchase_orders::create_purchase_order, purchase_orders::get_purchase_orders, purchase_orders::get_purchase_order, purchase_orders::get_all_purchase_orders,
+		push::register_device, push::get_devices, push::delete_device,
+		rbac::set_role_permissions,
+		reports::refresh_reports,
+		roles::create_role, roles::get_all_roles, roles::assign_role, roles::unassign_role, roles::get_user_roles, roles::get_role_users, roles::update_role, roles::delete_role,
+		schedules::create_schedule, schedules::get_schedules, schedules::delete_schedule,
+		signals::signal_ticket,
+		slack::slack_interaction, slack::link_slack_user,
+		tags::update_tags,
+		templates::save_template, templates::get_templates, templates::delete_template, templates::set_locale,
+		ticket::create_ticket, ticket::submit_ticket, ticket::update_ticket, ticket::cancel_ticket, ticket::get_user_tickets, ticket::get_public_tickets, ticket::get_ticket, ticket::get_ticket_history, ticket::get_ticket_callbacks,
+		ticket_events::get_ticket_events, ticket_events::replay_ticket,
+		timesheets::get_timesheets, timesheets::get_timesheet, timesheets::save_timesheet, timesheets::submit_timesheet, timesheets::get_all_timesheets, timesheets::get_project_hours,
+		users::create_user, users::register_new_user, users::check_user_approved, users::is_admin, users::get_all_new_users, users::get_userid, users::add_user, users::list_users, users::get_user, users::update_user, users::deactivate_user, users::activate_user,
+		vendors::create_vendor, vendors::get_vendors, vendors::get_vendor, vendors::update_vendor, vendors::deactivate_vendor,
+		watchers::watch_ticket, watchers::unwatch_ticket,
+		webhooks::create_subscription, webhooks::get_subscriptions, webhooks::delete_subscription, webhooks::get_deliveries, webhooks::retry_delivery,
+		working_time::get_calendars, working_time::save_default_calendar, working_time::save_department_calendar, working_time::remove_department_calendar, working_time::get_holidays, working_time::create_holiday, working_time::delete_holiday,
+		ws::notifications_ws
+	),
+	components(schemas(
+		admin::ReassignRequest, admin::ArchiveRequest, admin::ArchiveResponse, admin::RevokedSessions, admin::OverdueTicket, admin::ReassignedNode,
+		api_keys::CreateApiKey, api_keys::ApiKeyInfo, api_keys::MintedKey,
+		assets::AssetInput, assets::Asset, assets::Assignment, assets::AssetDetail, assets::CreateAssetRequest, assets::AssetRequest,
+		audit::AuditEvent, audit::AuditRecord, audit::ChainStatus,
+		auth::LoginRequest, auth::LoginResponse, auth::RefreshRequest, auth::ChangePassword,
+		breaker::BreakerState, breaker::BreakerStatus,
+		calendar::CalendarFeed,
+		callback_endpoints::CallbackEndpoint, callback_endpoints::SaveEndpoint,
+		callbacks::DeadJob, callbacks::CallbackAttempt, callbacks::JobState, callbacks::TicketCallbacks, callbacks::CallbackOutcome, callbacks::CallbackResult, callbacks::CallbackSecret,
+		crm::CustomerInput, crm::Customer, crm::ContactInput, crm::Contact, crm::OpportunityInput, crm::CreateOpportunity, crm::Opportunity, crm::StageChange, crm::CustomerDetail, crm::OpportunityDetail, crm::MoveOpportunity,
+		dashboard::ProcessCounts, dashboard::CallbackFailures, dashboard::ApproverLoad, dashboard::Dashboard, dashboard::AgeBuckets, dashboard::ProcessAging, dashboard::AssigneeAging, dashboard::AgingReport,
+		db_types::Ticket,
+		delegation::CreateDelegation, delegation::Delegation,
+		departments::SaveDepartment, departments::Department, departments::AddMember, departments::DepartmentMember,
+		documents::CreateDocument, documents::AclEntry, documents::Document, documents::Version, documents::DocumentDetail, documents::AttachDocument,
+		import::ImportedTicket, import::ImportResponse,
+		invoices::CreateInvoice, invoices::Invoice, invoices::InvoiceLine, invoices::Payment, invoices::InvoiceDetail, invoices::RecordPayment,
+		jobs::PeriodicJob,
+		leave::CreateLeaveRequest, leave::LeaveRequest, leave::LeaveConflict, leave::LeaveRequestDetail, leave::LeaveBalance, leave::SetLeaveBalance,
+		limits::SetLimit, limits::RoleLimit,
+		logger::LogMetrics, logger::AdminLogEntry, logger::LogEntry,
+		notif_handler::TokenRequest, notif_handler::TokenResponse,
+		notifications::Notification, notifications::Notifications, notifications::NotificationSettings, notifications::UnreadCount,
+		onboarding::SaveConfig, onboarding::OnboardingConfig, onboarding::CreateOnboarding, onboarding::Onboarding, onboarding::Acknowledgment, onboarding::OnboardingDetail, onboarding::Acknowledge, onboarding::Employee,
+		process::Process, process::ProcessGetResponse, process::ProcessDataResponse, process::Step,
+		purchase_orders::NewLine, purchase_orders::CreatePurchaseOrder, purchase_orders::PurchaseOrder, purchase_orders::PurchaseOrderLine, purchase_orders::PurchaseOrderDetail,
+		push::RegisterDevice, push::Device,
+		rbac::SetPermissions,
+		reports::Refreshed,
+		roles::CreateRole, roles::Role, roles::UpdateRole, roles::RoleReferences, roles::AssignRole, roles::RoleUser,
+		schedules::CreateSchedule, schedules::TicketSchedule,
+		slack::SlackReply, slack::ViewReply, slack::SlackResponse, slack::LinkSlackUser,
+		tags::UpdateTags,
+		templates::SaveTemplate, templates::NotificationTemplate, templates::SetLocale,
+		ticket::Event, ticket::PendingAssignee, ticket::NodeProgress, ticket::Rejection, ticket::NodeData, ticket::TicketHistory, ticket::TicketDetail, ticket::PublicTicket, ticket::PublicTickets,
+		ticket_events::TicketEventKind, ticket_events::KeyChanges, ticket_events::Change, ticket_events::Projection, ticket_events::StoredTicketEvent, ticket_events::ReplayRequest, ticket_events::Replayed,
+		timesheets::NewEntry, timesheets::SaveTimesheet, timesheets::Timesheet, timesheets::Entry, timesheets::ProjectHours, timesheets::TimesheetDetail,
+		users::CreateUser, users::NewUser, users::RegisterNewUser, users::UserApprovedMsg, users::IsAdminRes, users::UserIdQuery, users::AddUser, users::UpdateUser, users::DeactivateUser, users::UserInfo, users::DeactivatedUser,
+		vendors::VendorInput, vendors::Vendor,
+		webhooks::WebhookEvent, webhooks::Subscription, webhooks::CreateSubscription, webhooks::CreatedSubscription, webhooks::Delivery,
+		working_time::SaveCalendar, working_time::StoredCalendar, working_time::CreateHoliday, working_time::Holiday,
+		ws::LiveEventKind, ws::LiveEvent,
+		erp_api_types::FieldError, erp_api_types::FieldErrors, erp_api_types::Problem,
+		erp_api_types::callbacks::Callback, erp_api_types::callbacks::PayloadSignature,
+		erp_api_types::tickets::AssignmentType, erp_api_types::tickets::CancelTicket, erp_api_types::tickets::CreateTicket, erp_api_types::tickets::CreatedTicket, erp_api_types::tickets::CurrentTicket, erp_api_types::tickets::NextCursor, erp_api_types::tickets::OwnTicket, erp_api_types::tickets::SubmitTicket, erp_api_types::tickets::TicketStatus, erp_api_types::tickets::UpdateTicket, erp_api_types::tickets::UserTickets
+	)),
+	modifiers(&SecuritySchemes),
+	security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		let components = openapi.components.get_or_insert_with(Default::default);
+		components.add_security_scheme("bearer", SecurityScheme::Http(
+			HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()
+		));
+		components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))));
+	}
+}
+
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+	return Json(ApiDoc::openapi());
+}
+
+pub async fn get_docs() -> Html<&'static str> {
+	return Html(SWAGGER_UI);
+}
+
+#[cfg(test)]
+mod openapi_tests {
+	use std::collections::BTreeSet;
+	use serde_json::Value;
+	use utoipa::OpenApi;
+	use super::ApiDoc;
+
+	fn refs(value: &Value, found: &mut Vec<String>) {
+		match value {
+			Value::Object(map) => {
+				if let Some(Value::String(r)) = map.get("$ref") {
+					found.push(r.trim_start_matches("#/components/schemas/").to_string());
+				}
+				map.values().for_each(|v| refs(v, found));
+			}
+			Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+			_ => {}
+		}
+	}
+
+	#[test]
+	fn every_reference_resolves() {
+		let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+		let mut found = Vec::new();
+		refs(&document, &mut found);
+		assert!(!found.is_empty());
+		let undefined: BTreeSet<String> = found.into_iter().filter(|name| document["components"]["schemas"].get(name).is_none()).collect();
+		assert!(undefined.is_empty(), "not defined: {:?}", undefined);
+	}
+
+	#[test]
+	fn every_route_is_documented() {
+		let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+		let mut missing = BTreeSet::new();
+		for line in include_str!("lib.rs").lines().map(str::trim).filter(|l| l.starts_with(".route(\"")) {
+			let (path, handlers) = line[8..].split_once('"').unwrap();
+			if ["/", "/openapi.json", "/docs"].contains(&path) {
+				continue;
+			}
+			// axum writes /ticket/:id, openapi /ticket/{id}
+			let path = path.split('/').map(|s| match s.strip_prefix(':') {
+				Some(name) => format!("{{{}}}", name),
+				None => s.to_string()
+			}).collect::<Vec<_>>().join("/");
+			for method in ["get", "post", "put", "patch", "delete"] {
+				let routed = handlers.starts_with(&format!(", {}(", method)) || handlers.contains(&format!(".{}(", method));
+				if routed && document["paths"][&path].get(method).is_none() {
+					missing.insert(format!("{} {}", method, path));
+				}
+			}
+		}
+		assert!(missing.is_empty(), "not documented: {:?}", missing);
+	}
+
+	#[test]
+	fn routes_default_to_the_bearer_token() {
+		let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+		assert_eq!(document["security"][0]["bearer"], serde_json::json!([]));
+		assert_eq!(document["components"]["securitySchemes"]["api_key"]["name"], "X-Api-Key");
+		assert_eq!(document["paths"]["/auth/login"]["post"]["security"], serde_json::json!([{}]));
+	}
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+use sqlx::{PgConnection, PgPool};
+use crate::logger::{self, admin_logger, LogType};
+use crate::notif_handler::{ping_notifier, Ping};
+use crate::shutdown;
+
+// failed pings are retried after 2, 4, 8... seconds, at most this far apart
+const MAX_BACKOFF_SECS: i64 = 600;
+
+fn poll_interval() -> Duration {
+	let secs = std::env::var("OUTBOX_POLL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5);
+	return Duration::from_secs(secs);
+}
+
+pub fn backoff(attempts: i32) -> chrono::Duration {
+	let secs = 2i64.checked_pow(attempts.clamp(1, 30) as u32).unwrap_or(MAX_BACKOFF_SECS);
+	return chrono::Duration::seconds(secs.min(MAX_BACKOFF_SECS));
+}
+
+// one ping covers the rows of several requests
+pub fn request_ids<'a>(ids: impl Iterator<Item = &'a str>) -> Option<String> {
+	let mut unique: Vec<&str> = Vec::new();
+	for id in ids {
+		if !unique.contains(&id) {
+			unique.push(id);
+		}
+	}
+	if unique.is_empty() {
+		return None;
+	}
+	return Some(unique.join(","));
+}
+
+// call in the transaction that inserts notifications so the ping is not lost if the server goes down after the commit
+pub async fn enqueue(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+	sqlx::query("insert into notifier_outbox (created_at, request_id) values ($1, $2)")
+		.bind(chrono::Utc::now())
+		.bind(logger::current_request_id())
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+async fn try_flush(pool: &PgPool) -> Result<u64, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	// rows another flush is working on are skipped, one ping covers every pending notification anyway
+	let due: Vec<(i64, i32, Option<String>)> = sqlx::query_as("select id, attempts, request_id from notifier_outbox where next_attempt_at <= $1 for update skip locked")
+		.bind(now)
+		.fetch_all(&mut *tx)
+		.await?;
+	if due.is_empty() {
+		return Ok(0);
+	}
+	let ids = due.iter().map(|(id, _, _)| *id).collect::<Vec<_>>();
+	let request_ids = request_ids(due.iter().filter_map(|(_, _, r)| r.as_deref()));
+
+	if ping_notifier(Ping::CollectNew, None, request_ids.as_deref()).await.is_ok() {
+		sqlx::query("delete from notifier_outbox where id=any($1)")
+			.bind(&ids)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		return Ok(ids.len() as u64);
+	}
+
+	let attempts = due.iter().map(|(_, a, _)| *a).max().unwrap_or(0) + 1;
+	sqlx::query("update notifier_outbox set attempts=attempts+1, next_attempt_at=$2, last_error=$3 where id=any($1)")
+		.bind(&ids)
+		.bind(now + backoff(attempts))
+		.bind("notifier did not acknowledge the ping")
+		.execute(&mut *tx)
+		.await?;
+	tx.commit().await?;
+	return Ok(0);
+}
+
+// pings the notifier if there are due outbox rows. failures are kept for run_outbox to retry, so callers never fail on it
+pub async fn flush(pool: &PgPool) {
+	if let Err(e) = try_flush(pool).await {
+		admin_logger(LogType::Error, &format!("Failed to flush the notifier outbox: {}", e), None);
+	}
+}
+
+pub async fn run_outbox(pool: PgPool) {
+	let mut interval = tokio::time::interval(poll_interval());
+	while shutdown::tick(&mut interval).await {
+		flush(&pool).await;
+	}
+}
+
+#[cfg(test)]
+mod outbox_tests {
+	use super::{backoff, request_ids, MAX_BACKOFF_SECS};
+
+	#[test]
+	fn backoff_doubles_until_capped() {
+		assert_eq!(backoff(1).num_seconds(), 2);
+		assert_eq!(backoff(2).num_seconds(), 4);
+		assert_eq!(backoff(5).num_seconds(), 32);
+		assert_eq!(backoff(10).num_seconds(), MAX_BACKOFF_SECS);
+		assert_eq!(backoff(i32::MAX).num_seconds(), MAX_BACKOFF_SECS);
+		assert_eq!(backoff(0).num_seconds(), 2);
+	}
+
+	#[test]
+	fn request_ids_are_joined_once() {
+		assert_eq!(request_ids(["a", "b", "a"].into_iter()).as_deref(), Some("a,b"));
+		assert_eq!(request_ids(std::iter::empty()), None);
+	}
+}
@@ -3,93 +3,105 @@ use axum::{
 	http::StatusCode,
 	extract
 };
-use once_cell::sync::Lazy;
 use serde::{Serialize, Deserialize};
 use sqlx::{PgPool, FromRow};
-use std::path::PathBuf;
-use crate::{callbacks::Callback, logger::{admin_logger, LogType}, ticket};
+use crate::{auth::new_secret, callbacks::Callback, documents, logger::{admin_logger, LogType}, schema, ticket::{self, Event}, vendors};
+use crate::rbac::{Authorized, ManageProcesses};
+use crate::api_error::db_status;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize, Clone)]
+pub mod bpmn;
+pub mod provider;
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct Process {
 	pub pname: String,
 	pub pid: String,
 	pub steps: Vec<Step>,
 	pub desc: Option<String>,
 	pub roles: Vec<String>,
+	// working hours a ticket is due in when it is created without a due date, see working_time.rs
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sla_hours: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
 pub struct ProcessGetResponse {
 	pub process_id: String,
 	pub description: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ProcessDataQuery {
 	pub process_id: String
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportBpmnQuery {
+	// defaults to the id of the bpmn process
+	pub pid: Option<String>,
+	// comma separated list of allowed roles, defaults to any
+	pub roles: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct ProcessDataResponse {
 	pub active: bool,
 	pub description: Option<String> 
 }
 
 // TODO: step should probably be an enum
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct Step {
-	pub event: ticket::Event,
+	pub event: Event,
 	pub args : Option<Vec<String>>,
 	pub next: Vec<i32>,
 	pub required: Vec<i32>,
-	pub callbacks: Option<Vec<Callback>>
+	pub callbacks: Option<Vec<Callback>>,
+	// JSON Schema for the data submitted when this node is completed by a user
+	#[schema(value_type = Option<Object>)]
+	pub schema: Option<serde_json::Value>,
+	// name of an array in the ticket state. the node runs once for every element of the array
+	pub multi_instance: Option<String>,
+	// rule evaluated against the ticket state when an approve node is reached, e.g. "amount < 500".
+	// if it matches the node is approved without creating an approval request
+	pub auto_approve_if: Option<String>,
+	// status the record that started the ticket (a purchase order, ...) moves to once this node completes, see linked
+	pub record_status: Option<String>,
+	// vendor whose system completes this blocking_task node in procurement processes. must be an active vendor, see vendors
+	pub vendor_id: Option<i32>,
+	// document type the ticket needs an approved document of, attached to it, before this node completes. see documents
+	pub requires_document: Option<String>
 }
 
 impl Step {
 	pub fn is_not_approve(&self) -> bool {
-		match self.event {
-			ticket::Event::Approve => false,
-			_ => true
-		}
+		!matches!(self.event, ticket::Event::Approve)
 	}
 	pub fn is_not_blocking_task(&self) -> bool {
-		match self.event {
-			ticket::Event::BlockingTask => false,
-			_ => true
-		}
+		!matches!(self.event, ticket::Event::BlockingTask)
 	}
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UserName {
 	pub username: String
 }
 
-static CONFIG_DIR: Lazy<PathBuf> = Lazy::new(|| {
-	let path = PathBuf::from(std::env::var("PROCESS_DATA_PATH").unwrap());
-	println!("Config dir initialized to : {:?}", path.as_os_str());
-	return path;
-});
-
-
-pub fn read_process_data(pid: String) -> Result<Process, std::io::Error> {
-	let data_path = CONFIG_DIR.join(format!("{}.json", pid));
-	let process_data = std::fs::read_to_string(data_path)?;
-	let parsed_data = serde_json::from_str::<Process>(&process_data)?;
-
-	return Ok(parsed_data);
-}
-
-fn save_process_data(data: &Process) -> Result<(), std::io::Error> {
-	let pid = data.pid.clone();
-
-	let data_path = CONFIG_DIR.join(format!("{}.json", pid));
-	let serialized = serde_json::to_string::<Process>(data).unwrap();
-
-	std::fs::write(data_path, serialized)?;
-	return Ok(());
+// the definition of a process from the provider, see provider.rs
+pub async fn read_process_data(pid: String) -> Result<Process, String> {
+	return match provider::provider().read(&pid).await? {
+		Some(process) => Ok(process),
+		None => Err(format!("Process {} does not exist", pid))
+	};
 }
 
+#[utoipa::path(get, path = "/process/all", tag = "processes", params(UserName), responses(
+	(status = 200, description = "Processes the user may start", body = Vec<ProcessGetResponse>)
+))]
 pub async fn get_all_processes(
 	extract::Query(query) : extract::Query<UserName>,
 	extract::State(pool) : extract::State<PgPool>
@@ -99,7 +111,7 @@ pub async fn get_all_processes(
 	// query returns all process that have allowed role={any} or there is an overlap in allowed role of process and roles of the user
 	let query = sqlx::query_as(
 		r#"select p.process_id, p.description from process_defs p join 
-			(select array_agg(role_) as user_roles from roles join users on roles.userid=users.userid where users.username=$1) r 
+			(select array_agg(role_) as user_roles from user_roles join users on user_roles.userid=users.userid where users.username=$1) r 
 			on p.allowed_roles='{any}' or p.allowed_roles && r.user_roles;"#
 		)
 		.bind(username)
@@ -107,8 +119,7 @@ pub async fn get_all_processes(
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error fetching process names: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error fetching process names: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
@@ -117,64 +128,129 @@ pub async fn get_all_processes(
 	return Ok(Json(processes));
 }
 
+#[utoipa::path(post, path = "/process", tag = "processes", request_body = Process, responses(
+	(status = 201, description = "Saved"),
+	(status = 400, description = "Invalid process id"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 422, description = "Invalid definition")
+))]
 pub async fn create_process(
+	auth: Authorized<ManageProcesses>,
 	extract::State(pool) : extract::State<PgPool>,
 	Json(payload) : Json<Process>,
 ) -> Result<StatusCode, StatusCode> {
 	let pid = payload.pid.clone();
+	if !provider::valid_pid(&pid) {
+		admin_logger(LogType::Error, &format!("Invalid process id {}", pid), None);
+		return Err(StatusCode::BAD_REQUEST);
+	}
 
-	let config_path = CONFIG_DIR.join(format!("{}.json", pid));
-	match config_path.try_exists() {
+	match provider::provider().read(&pid).await {
 		Err(e) => {
-			admin_logger(LogType::Error, &format!("Error reading saved process data: {}", e), None)
-				.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+			admin_logger(LogType::Error, &format!("Error reading saved process data: {}", e), None);
 			return Err(StatusCode::INTERNAL_SERVER_ERROR);
 		}
-		Ok(true) => {
-			admin_logger(LogType::Error, &format!("Process with pid {} already exists", pid), None)
-				.map_err(|_| StatusCode::FORBIDDEN)?;
+		Ok(Some(_)) => {
+			admin_logger(LogType::Error, &format!("Process with pid {} already exists", pid), None);
 			return Err(StatusCode::FORBIDDEN);
 		}
-		Ok(false) => {
-			{};
+		Ok(None) => {}
+	}
+
+	for (i, step) in payload.steps.iter().enumerate() {
+		if let Some(node_schema) = &step.schema {
+			if let Err(e) = schema::check_schema(node_schema) {
+				admin_logger(LogType::Error, &format!("Invalid schema for node {} in process {}: {}", i, pid, e), None);
+				return Err(StatusCode::BAD_REQUEST);
+			}
 		}
 	}
-	let mut tx = pool.begin().await.unwrap();
 
-	
+	let errors = documents::document_step_errors(&payload.steps);
+	if !errors.is_empty() {
+		let messages: Vec<String> = errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+		admin_logger(LogType::Error, &format!("Invalid document requirements in process {}: {}", pid, messages.join(", ")), None);
+		return Err(StatusCode::UNPROCESSABLE_ENTITY);
+	}
+
+	let mut tx = pool.begin().await.map_err(db_status)?;
 
-	let query = sqlx::query("insert into process_defs (process_id, allowed_roles, description) values ($1, $2, $3)")
+	match vendors::check_process_vendors(&mut tx, &payload.steps).await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking the vendors of process {}: {}", pid, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Ok(errors) if !errors.is_empty() => {
+			let messages: Vec<String> = errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+			admin_logger(LogType::Error, &format!("Invalid vendors in process {}: {}", pid, messages.join(", ")), None);
+			return Err(StatusCode::UNPROCESSABLE_ENTITY);
+		}
+		Ok(_) => {}
+	}
+
+	let query = sqlx::query("insert into process_defs (process_id, allowed_roles, description, callback_secret) values ($1, $2, $3, $4)")
 		.bind(&payload.pid)
 		.bind(&payload.roles)
 		.bind(&payload.desc)
+		.bind(new_secret())
 		.execute(&mut *tx)
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error inserting new process: {} for pid {}", e, payload.pid), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error inserting new process: {} for pid {}", e, payload.pid), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
-	if let Err(e) = save_process_data(&payload) {
-		admin_logger(LogType::Error, &format!("Error saving new process data: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if let Err(e) = provider::provider().save(&payload).await {
+		admin_logger(LogType::Error, &format!("Error saving new process data: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
 	let result = tx.commit().await;
 	if let Err(e) = result {
-		admin_logger(LogType::Error, &format!("Error commiting transaction: {} for pid {}", e, payload.pid), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		std::fs::remove_file(config_path).unwrap();
+		admin_logger(LogType::Error, &format!("Error commiting transaction: {} for pid {}", e, payload.pid), None);
+		if let Err(e) = provider::provider().remove(&payload.pid).await {
+			admin_logger(LogType::Error, &format!("Error removing the data of process {}: {}", payload.pid, e), None);
+		}
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
-	admin_logger(LogType::Info, &format!("Process {} created successfully", payload.pid), None)
-		.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	admin_logger(LogType::Info, &format!("Process {} created successfully by {}", payload.pid, auth.user.userid), None);
 	return Ok(StatusCode::CREATED);
 }
 
+#[utoipa::path(post, path = "/process/import/bpmn", tag = "processes", params(ImportBpmnQuery), request_body(content = String, content_type = "application/xml", description = "A bpmn 2.0 document"), responses(
+	(status = 201, description = "Converted and saved"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 422, description = "The document cannot be converted, the reason is in the body", body = String)
+))]
+pub async fn import_bpmn(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool) : extract::State<PgPool>,
+	extract::Query(query) : extract::Query<ImportBpmnQuery>,
+	body: String
+) -> Result<StatusCode, (StatusCode, String)> {
+	let roles = match query.roles {
+		Some(roles) => roles.split(',').map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect(),
+		None => vec!["any".to_string()]
+	};
+
+	let converted = bpmn::convert(&body, query.pid, roles);
+	if let Err(e) = converted {
+		admin_logger(LogType::Error, &format!("Error importing bpmn process: {}", e), None);
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, e));
+	}
+	let process = converted.unwrap();
+
+	return create_process(auth, extract::State(pool), Json(process))
+		.await
+		.map_err(|code| (code, String::new()));
+}
+
+#[utoipa::path(get, path = "/process", tag = "processes", params(ProcessDataQuery), responses(
+	(status = 200, description = "Whether the process is active and its description", body = ProcessDataResponse),
+	(status = 404, description = "Unknown process")
+))]
 pub async fn get_process_data(
 	extract::Query(query) : extract::Query<ProcessDataQuery>
 ) -> Result<Json<ProcessDataResponse>, StatusCode> {
@@ -185,25 +261,18 @@ pub async fn get_process_data(
 		description: None
 	};
 
-	let config_path = CONFIG_DIR.join(format!("{}.json", pid));
-	match config_path.try_exists() {
+	let process_data = match provider::provider().read(&pid).await {
 		Err(e) => {
-			admin_logger(LogType::Error, &format!("Error reading saved process data: {}", e), None)
-				.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+			admin_logger(LogType::Error, &format!("Error reading saved process data: {}", e), None);
 			return Err(StatusCode::INTERNAL_SERVER_ERROR);
 		}
-		Ok(false) => {
-			admin_logger(LogType::Error, &format!("Process with pid {} does not exist", pid), None)
-				.map_err(|_| StatusCode::NOT_FOUND)?;
+		Ok(None) => {
+			admin_logger(LogType::Error, &format!("Process with pid {} does not exist", pid), None);
 			return Err(StatusCode::NOT_FOUND);
 		}
-		Ok(true) => {
-			{};
-		}
-	}
-
-	let process_data = read_process_data(pid).unwrap();
-	let initiate_args = process_data.steps.get(0).unwrap().args.as_ref().unwrap();
+		Ok(Some(process)) => process
+	};
+	let initiate_args = process_data.steps.first().unwrap().args.as_ref().unwrap();
 	// checkbox was checked on frontend
 	result.active = initiate_args.len() > 1 && initiate_args[0] == "on";
 	if result.active {
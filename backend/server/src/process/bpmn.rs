@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use roxmltree::{Document, Node};
+use crate::process::{Process, Step};
+use crate::ticket::Event;
+
+/*
+	Converts a subset of BPMN 2.0 into the internal step format.
+	Supported elements:
+		startEvent       -> initiate (exactly one, always node 0)
+		userTask         -> approve, the approver is read from the `assignee` attribute (camunda:assignee etc)
+		serviceTask      -> non_blocking_task
+		parallelGateway  -> removed. a join makes the next node require every incoming branch
+		exclusiveGateway -> removed. only merges are supported since the engine has no conditional routing
+		endEvent         -> complete (all end events are merged into the last node)
+*/
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {Start, UserTask, ServiceTask, Parallel, Exclusive, End}
+
+struct FlowNode {
+	kind: Kind,
+	assignee: Option<String>,
+	incoming: Vec<String>,
+	outgoing: Vec<String>
+}
+
+fn kind_of(tag: &str) -> Option<Kind> {
+	return match tag {
+		"startEvent" => Some(Kind::Start),
+		"userTask" => Some(Kind::UserTask),
+		"serviceTask" => Some(Kind::ServiceTask),
+		"parallelGateway" => Some(Kind::Parallel),
+		"exclusiveGateway" => Some(Kind::Exclusive),
+		"endEvent" => Some(Kind::End),
+		_ => None
+	};
+}
+
+fn step(event: Event, args: Option<Vec<String>>, next: Vec<i32>, required: Vec<i32>) -> Step {
+	return Step {
+		event,
+		args,
+		next,
+		required,
+		callbacks: None,
+		schema: None,
+		multi_instance: None,
+		auto_approve_if: None,
+		record_status: None,
+		vendor_id: None,
+		requires_document: None
+	};
+}
+
+fn is_gateway(kind: Kind) -> bool {
+	return kind == Kind::Parallel || kind == Kind::Exclusive;
+}
+
+// attributes are matched by local name so any vendor namespace works
+fn local_attribute(node: &Node, name: &str) -> Option<String> {
+	return node.attributes()
+		.find(|a| a.name() == name)
+		.map(|a| a.value().to_string());
+}
+
+pub fn convert(xml: &str, pid: Option<String>, roles: Vec<String>) -> Result<Process, String> {
+	let doc = Document::parse(xml).map_err(|e| format!("Invalid XML: {}", e))?;
+	let process_el = doc.descendants()
+		.find(|n| n.is_element() && n.tag_name().name() == "process")
+		.ok_or("No process element found")?;
+
+	let mut nodes: HashMap<String, FlowNode> = HashMap::new();
+	// document order of the task nodes, used for the node numbers
+	let mut tasks: Vec<String> = Vec::new();
+	let mut flows: Vec<(String, String)> = Vec::new();
+
+	for el in process_el.children().filter(|n| n.is_element()) {
+		let tag = el.tag_name().name();
+		if tag == "sequenceFlow" {
+			let source = el.attribute("sourceRef").ok_or("sequenceFlow without sourceRef")?;
+			let target = el.attribute("targetRef").ok_or("sequenceFlow without targetRef")?;
+			flows.push((source.to_string(), target.to_string()));
+			continue;
+		}
+
+		let kind = match kind_of(tag) {
+			Some(k) => k,
+			// documentation, extension elements, lanes etc
+			None if !tag.ends_with("Task") && !tag.ends_with("Event") && !tag.ends_with("Gateway") => continue,
+			None => return Err(format!("Unsupported BPMN element: {}", tag))
+		};
+		let id = el.attribute("id").ok_or(format!("{} without id", tag))?.to_string();
+
+		if kind == Kind::UserTask || kind == Kind::ServiceTask {
+			tasks.push(id.clone());
+		}
+		nodes.insert(id, FlowNode {
+			kind,
+			assignee: local_attribute(&el, "assignee"),
+			incoming: Vec::new(),
+			outgoing: Vec::new()
+		});
+	}
+
+	for (source, target) in flows.iter() {
+		if !nodes.contains_key(source) || !nodes.contains_key(target) {
+			return Err(format!("sequenceFlow {} -> {} references an unknown element", source, target));
+		}
+		nodes.get_mut(source).unwrap().outgoing.push(target.clone());
+		nodes.get_mut(target).unwrap().incoming.push(source.clone());
+	}
+
+	let starts = nodes.iter().filter(|(_, n)| n.kind == Kind::Start).map(|(id, _)| id.clone()).collect::<Vec<_>>();
+	if starts.len() != 1 {
+		return Err(format!("Expected exactly one startEvent, found {}", starts.len()));
+	}
+	if !nodes.values().any(|n| n.kind == Kind::End) {
+		return Err("No endEvent found".to_string());
+	}
+	for (id, node) in nodes.iter() {
+		if node.kind == Kind::Exclusive && node.outgoing.len() > 1 {
+			return Err(format!("exclusiveGateway {} splits the flow. conditional routing is not supported", id));
+		}
+		if node.kind == Kind::UserTask && node.assignee.is_none() {
+			return Err(format!("userTask {} has no assignee", id));
+		}
+	}
+
+	// node numbers: start = 0, tasks in document order, all end events share the last node
+	let mut index: HashMap<String, i32> = HashMap::new();
+	index.insert(starts[0].clone(), 0);
+	for (i, id) in tasks.iter().enumerate() {
+		index.insert(id.clone(), i as i32 + 1);
+	}
+	let end_index = tasks.len() as i32 + 1;
+	for (id, node) in nodes.iter() {
+		if node.kind == Kind::End {
+			index.insert(id.clone(), end_index);
+		}
+	}
+
+	let successors = |id: &String| -> Vec<i32> {
+		let mut result = Vec::new();
+		let mut stack = nodes[id].outgoing.clone();
+		let mut seen = Vec::new();
+		while let Some(next) = stack.pop() {
+			if seen.contains(&next) {
+				continue;
+			}
+			seen.push(next.clone());
+			if is_gateway(nodes[&next].kind) {
+				stack.extend(nodes[&next].outgoing.iter().cloned());
+			}
+			else if !result.contains(&index[&next]) {
+				result.push(index[&next]);
+			}
+		}
+		result.sort();
+		return result;
+	};
+
+	// a node requires its direct predecessors and every branch joined by a parallel gateway.
+	// branches merged by an exclusive gateway are not required since only one of them runs
+	let required = |id: &String| -> Vec<i32> {
+		let mut result = Vec::new();
+		let mut stack = nodes[id].incoming.clone();
+		let mut seen = Vec::new();
+		while let Some(prev) = stack.pop() {
+			if seen.contains(&prev) {
+				continue;
+			}
+			seen.push(prev.clone());
+			match nodes[&prev].kind {
+				Kind::Parallel => stack.extend(nodes[&prev].incoming.iter().cloned()),
+				Kind::Exclusive => {},
+				_ => if !result.contains(&index[&prev]) {
+					result.push(index[&prev]);
+				}
+			}
+		}
+		result.sort();
+		return result;
+	};
+
+	let mut steps = Vec::new();
+	steps.push(step(Event::Initiate, Some(vec![]), successors(&starts[0]), vec![]));
+	for id in tasks.iter() {
+		let node = &nodes[id];
+		let converted = match node.kind {
+			Kind::UserTask => step(Event::Approve, Some(vec![node.assignee.clone().unwrap()]), successors(id), required(id)),
+			_ => step(Event::NonBlockingTask, None, successors(id), required(id)),
+		};
+		steps.push(converted);
+	}
+	let mut end_required = Vec::new();
+	for (id, node) in nodes.iter() {
+		if node.kind == Kind::End {
+			end_required.extend(required(id));
+		}
+	}
+	end_required.sort();
+	end_required.dedup();
+	steps.push(step(Event::Complete, None, vec![], end_required));
+
+	let process_id = process_el.attribute("id").unwrap_or_default().to_string();
+	let pid = pid.unwrap_or(process_id.clone());
+	if pid.is_empty() {
+		return Err("Process id missing".to_string());
+	}
+
+	let desc = process_el.children()
+		.find(|n| n.is_element() && n.tag_name().name() == "documentation")
+		.and_then(|n| n.text())
+		.map(|t| t.trim().to_string());
+
+	return Ok(Process {
+		pname: process_el.attribute("name").unwrap_or(&pid).to_string(),
+		pid,
+		steps,
+		desc,
+		roles,
+		sla_hours: None,
+	});
+}
+
+#[cfg(test)]
+mod bpmn_tests {
+	use crate::ticket::Event;
+	use super::convert;
+
+	static PARALLEL: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" xmlns:camunda="http://camunda.org/schema/1.0/bpmn">
+  <bpmn:process id="purchase" name="Purchase approval">
+    <bpmn:documentation>two approvals in parallel</bpmn:documentation>
+    <bpmn:startEvent id="start" />
+    <bpmn:parallelGateway id="split" />
+    <bpmn:userTask id="finance" camunda:assignee="erp_admin" />
+    <bpmn:userTask id="manager" camunda:assignee="erp_admin" />
+    <bpmn:parallelGateway id="join" />
+    <bpmn:serviceTask id="order" />
+    <bpmn:endEvent id="end" />
+    <bpmn:sequenceFlow id="f1" sourceRef="start" targetRef="split" />
+    <bpmn:sequenceFlow id="f2" sourceRef="split" targetRef="finance" />
+    <bpmn:sequenceFlow id="f3" sourceRef="split" targetRef="manager" />
+    <bpmn:sequenceFlow id="f4" sourceRef="finance" targetRef="join" />
+    <bpmn:sequenceFlow id="f5" sourceRef="manager" targetRef="join" />
+    <bpmn:sequenceFlow id="f6" sourceRef="join" targetRef="order" />
+    <bpmn:sequenceFlow id="f7" sourceRef="order" targetRef="end" />
+  </bpmn:process>
+</bpmn:definitions>"#;
+
+	#[test]
+	fn converts_parallel_gateways() {
+		let process = convert(PARALLEL, None, vec!["any".to_string()]).unwrap();
+		assert_eq!(process.pid, "purchase");
+		assert_eq!(process.desc, Some("two approvals in parallel".to_string()));
+		assert_eq!(process.steps.len(), 5);
+
+		assert!(process.steps[0].event == Event::Initiate);
+		assert_eq!(process.steps[0].next, vec![1, 2]);
+		assert!(process.steps[1].event == Event::Approve);
+		assert_eq!(process.steps[1].args, Some(vec!["erp_admin".to_string()]));
+		assert_eq!(process.steps[1].required, vec![0]);
+		// the join requires both approvals
+		assert!(process.steps[3].event == Event::NonBlockingTask);
+		assert_eq!(process.steps[3].required, vec![1, 2]);
+		assert!(process.steps[4].event == Event::Complete);
+		assert_eq!(process.steps[4].required, vec![3]);
+	}
+
+	#[test]
+	fn rejects_exclusive_split() {
+		let xml = PARALLEL.replace("parallelGateway id=\"split\"", "exclusiveGateway id=\"split\"");
+		assert!(convert(&xml, None, vec![]).is_err());
+	}
+
+	#[test]
+	fn rejects_user_task_without_assignee() {
+		let xml = PARALLEL.replace(" camunda:assignee=\"erp_admin\" />\n    <bpmn:parallelGateway", " />\n    <bpmn:parallelGateway");
+		assert!(convert(&xml, None, vec![]).is_err());
+	}
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use axum::async_trait;
+use once_cell::sync::OnceCell;
+use sqlx::PgPool;
+use crate::tenant;
+use super::Process;
+
+// where process definitions are kept. set with PROCESS_PROVIDER, files in PROCESS_DATA_PATH by default.
+// every tenant has processes of its own, the provider keeps them apart by tenant::key_tenant
+#[async_trait]
+pub trait ProcessProvider: Send + Sync {
+	// None if there is no process with the id
+	async fn read(&self, pid: &str) -> Result<Option<Process>, String>;
+	// replaces the definition if the process exists already
+	async fn save(&self, process: &Process) -> Result<(), String>;
+	async fn remove(&self, pid: &str) -> Result<(), String>;
+}
+
+// a <pid>.json file per process, in tenants/<id> for tenants other than the default one
+pub struct FileProvider {
+	dir: PathBuf
+}
+
+impl FileProvider {
+	pub fn new(dir: PathBuf) -> FileProvider {
+		return FileProvider { dir };
+	}
+
+	// None for a pid that would leave the directory of the tenant
+	fn path(&self, pid: &str) -> Option<PathBuf> {
+		if !valid_pid(pid) {
+			return None;
+		}
+		let tenant = tenant::key_tenant();
+		let dir = if tenant == tenant::DEFAULT_TENANT { self.dir.clone() } else { self.dir.join("tenants").join(tenant.to_string()) };
+		return Some(dir.join(format!("{}.json", pid)));
+	}
+}
+
+// process ids are client supplied and end up in file names, only letters, digits, _ and - are allowed
+pub fn valid_pid(pid: &str) -> bool {
+	return !pid.is_empty() && pid.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+}
+
+#[async_trait]
+impl ProcessProvider for FileProvider {
+	async fn read(&self, pid: &str) -> Result<Option<Process>, String> {
+		// no process can be saved under an invalid pid
+		let path = match self.path(pid) {
+			Some(path) => path,
+			None => return Ok(None)
+		};
+		let data = match tokio::fs::read_to_string(path).await {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(format!("Failed to read process {}. e: {}", pid, e))
+		};
+		let process = serde_json::from_str::<Process>(&data)
+			.map_err(|e| format!("Invalid definition of process {}. e: {}", pid, e))?;
+		return Ok(Some(process));
+	}
+
+	async fn save(&self, process: &Process) -> Result<(), String> {
+		let serialized = serde_json::to_string(process).unwrap();
+		let path = self.path(&process.pid).ok_or(format!("Invalid process id {}", process.pid))?;
+		if let Some(dir) = path.parent() {
+			tokio::fs::create_dir_all(dir).await
+				.map_err(|e| format!("Failed to create the directory of process {}. e: {}", process.pid, e))?;
+		}
+		return tokio::fs::write(path, serialized).await
+			.map_err(|e| format!("Failed to write process {}. e: {}", process.pid, e));
+	}
+
+	async fn remove(&self, pid: &str) -> Result<(), String> {
+		let path = self.path(pid).ok_or(format!("Invalid process id {}", pid))?;
+		return tokio::fs::remove_file(path).await
+			.map_err(|e| format!("Failed to remove process {}. e: {}", pid, e));
+	}
+}
+
+// the process_definitions table, shared by every server instance
+pub struct PgProvider {
+	pool: PgPool
+}
+
+impl PgProvider {
+	pub fn new(pool: PgPool) -> PgProvider {
+		return PgProvider { pool };
+	}
+}
+
+#[async_trait]
+impl ProcessProvider for PgProvider {
+	async fn read(&self, pid: &str) -> Result<Option<Process>, String> {
+		let query: Option<(serde_json::Value,)> = sqlx::query_as("select definition from process_definitions where tenant_id=$1 and process_id=$2")
+			.bind(tenant::key_tenant())
+			.bind(pid)
+			.fetch_optional(&self.pool)
+			.await
+			.map_err(|e| format!("Failed to read process {}. e: {}", pid, e))?;
+		return match query {
+			Some((definition,)) => serde_json::from_value::<Process>(definition)
+				.map(Some)
+				.map_err(|e| format!("Invalid definition of process {}. e: {}", pid, e)),
+			None => Ok(None)
+		};
+	}
+
+	async fn save(&self, process: &Process) -> Result<(), String> {
+		sqlx::query(
+			r#"insert into process_definitions (tenant_id, process_id, definition, updated_at) values ($1, $2, $3, $4)
+				on conflict (tenant_id, process_id) do update set definition=excluded.definition, updated_at=excluded.updated_at"#)
+			.bind(tenant::key_tenant())
+			.bind(&process.pid)
+			.bind(serde_json::to_value(process).unwrap())
+			.bind(chrono::Utc::now())
+			.execute(&self.pool)
+			.await
+			.map_err(|e| format!("Failed to save process {}. e: {}", process.pid, e))?;
+		return Ok(());
+	}
+
+	async fn remove(&self, pid: &str) -> Result<(), String> {
+		sqlx::query("delete from process_definitions where tenant_id=$1 and process_id=$2")
+			.bind(tenant::key_tenant())
+			.bind(pid)
+			.execute(&self.pool)
+			.await
+			.map_err(|e| format!("Failed to remove process {}. e: {}", pid, e))?;
+		return Ok(());
+	}
+}
+
+// for tests and fixtures, nothing outlives the process
+#[derive(Default)]
+pub struct MemoryProvider {
+	processes: RwLock<HashMap<(i32, String), Process>>
+}
+
+#[async_trait]
+impl ProcessProvider for MemoryProvider {
+	async fn read(&self, pid: &str) -> Result<Option<Process>, String> {
+		return Ok(self.processes.read().unwrap().get(&(tenant::key_tenant(), pid.to_string())).cloned());
+	}
+
+	async fn save(&self, process: &Process) -> Result<(), String> {
+		self.processes.write().unwrap().insert((tenant::key_tenant(), process.pid.clone()), process.clone());
+		return Ok(());
+	}
+
+	async fn remove(&self, pid: &str) -> Result<(), String> {
+		self.processes.write().unwrap().remove(&(tenant::key_tenant(), pid.to_string()));
+		return Ok(());
+	}
+}
+
+static PROVIDER: OnceCell<Box<dyn ProcessProvider>> = OnceCell::new();
+
+fn data_dir() -> PathBuf {
+	return PathBuf::from(std::env::var("PROCESS_DATA_PATH").unwrap());
+}
+
+fn connect_provider(pool: PgPool) -> Result<Box<dyn ProcessProvider>, String> {
+	let kind = std::env::var("PROCESS_PROVIDER").unwrap_or("files".to_string());
+	return match kind.as_str() {
+		"files" => Ok(Box::new(FileProvider::new(data_dir()))),
+		"postgres" => Ok(Box::new(PgProvider::new(pool))),
+		"memory" => Ok(Box::new(MemoryProvider::default())),
+		_ => Err(format!("Unknown PROCESS_PROVIDER: {}", kind))
+	};
+}
+
+// call once at startup, before anything reads a process
+pub fn init(pool: PgPool) -> Result<(), String> {
+	return set(connect_provider(pool)?);
+}
+
+// init with a provider built by the caller, for benchmarks and fixtures that bring their own definitions
+pub fn set(provider: Box<dyn ProcessProvider>) -> Result<(), String> {
+	return PROVIDER.set(provider).map_err(|_| "The process provider was set already".to_string());
+}
+
+// the files in PROCESS_DATA_PATH until init was called, so tools and tests work without a database
+pub fn provider() -> &'static dyn ProcessProvider {
+	return PROVIDER.get_or_init(|| Box::new(FileProvider::new(data_dir()))).as_ref();
+}
+
+#[cfg(test)]
+mod provider_tests {
+	use super::{FileProvider, MemoryProvider, ProcessProvider};
+	use crate::process::Process;
+	use crate::tenant;
+
+	fn process(pid: &str) -> Process {
+		return Process { pname: "Leave".to_string(), pid: pid.to_string(), steps: Vec::new(), desc: None, roles: vec!["any".to_string()], sla_hours: Some(8) };
+	}
+
+	#[tokio::test]
+	async fn memory_processes_are_replaced_and_removed() {
+		let provider = MemoryProvider::default();
+		assert!(provider.read("leave").await.unwrap().is_none());
+
+		provider.save(&process("leave")).await.unwrap();
+		let mut changed = process("leave");
+		changed.sla_hours = None;
+		provider.save(&changed).await.unwrap();
+		assert_eq!(provider.read("leave").await.unwrap().map(|p| p.sla_hours), Some(None));
+
+		// other tenants do not see it and can have a process with the same id
+		assert!(tenant::scoped(Some(2), provider.read("leave")).await.unwrap().is_none());
+		tenant::scoped(Some(2), provider.save(&process("leave"))).await.unwrap();
+
+		provider.remove("leave").await.unwrap();
+		assert!(provider.read("leave").await.unwrap().is_none());
+		assert!(tenant::scoped(Some(2), provider.read("leave")).await.unwrap().is_some());
+	}
+
+	#[tokio::test]
+	async fn files_round_trip() {
+		let dir = std::env::temp_dir().join(format!("processes-{}", uuid::Uuid::new_v4()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let provider = FileProvider::new(dir.clone());
+
+		assert!(provider.read("leave").await.unwrap().is_none());
+		provider.save(&process("leave")).await.unwrap();
+		assert_eq!(provider.read("leave").await.unwrap().map(|p| p.sla_hours), Some(Some(8)));
+		assert!(tenant::scoped(Some(2), provider.read("leave")).await.unwrap().is_none());
+		tenant::scoped(Some(2), provider.save(&process("leave"))).await.unwrap();
+		assert!(dir.join("tenants").join("2").join("leave.json").exists());
+
+		// pids cannot reach the files of another tenant
+		assert!(provider.read("../leave").await.unwrap().is_none());
+		assert!(tenant::scoped(Some(2), provider.save(&process("../../leave"))).await.is_err());
+		assert!(provider.remove("tenants/2/leave").await.is_err());
+		assert!(dir.join("tenants").join("2").join("leave.json").exists());
+
+		std::fs::write(dir.join("broken.json"), "{").unwrap();
+		assert!(provider.read("broken").await.is_err());
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,403 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, log, LogType};
+use crate::rbac::{self, Authorized, ManagePurchaseOrders};
+use crate::schema::{FieldError, FieldErrors, internal_error};
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::ticket;
+use utoipa::{IntoParams, ToSchema};
+
+// the order purchase orders move in. a later status can be reached directly, an earlier one never again
+pub const STATUS_FLOW: [&str; 5] = ["pending_approval", "approved", "sent", "received", "closed"];
+// reachable from any status of the flow before closed
+pub const END_STATUSES: [&str; 2] = ["rejected", "cancelled"];
+const MAX_LINES: usize = 500;
+
+// the process whose tickets approve purchase orders
+fn process_id() -> String {
+	return std::env::var("PURCHASE_ORDER_PROCESS").unwrap_or("purchase_order".to_string());
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+pub struct NewLine {
+	pub description: String,
+	pub quantity: i32,
+	pub unit_price_cents: i64
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreatePurchaseOrder {
+	pub vendor: String,
+	// iso 4217 code like EUR
+	pub currency: String,
+	pub lines: Vec<NewLine>,
+	pub notes: Option<String>,
+	// of the approval ticket
+	pub due_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct PurchaseOrder {
+	pub id: i32,
+	pub vendor: String,
+	pub currency: String,
+	pub total_cents: i64,
+	pub status: String,
+	pub notes: Option<String>,
+	pub ticket_id: Option<i32>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct PurchaseOrderLine {
+	pub line_number: i32,
+	pub description: String,
+	pub quantity: i32,
+	pub unit_price_cents: i64,
+	pub total_cents: i64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PurchaseOrderDetail {
+	#[serde(flatten)]
+	pub order: PurchaseOrder,
+	pub lines: Vec<PurchaseOrderLine>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PurchaseOrdersQuery {
+	pub status: Option<String>,
+	// every order instead of the own ones, needs manage_purchase_orders
+	pub all: Option<bool>
+}
+
+// the status an order in `current` moves to when its ticket asks for `target`. None leaves it where it is
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	let position = |status: &str| STATUS_FLOW.iter().position(|s| *s == status);
+	let current = position(current)?;
+	// closed orders stay closed
+	if current == STATUS_FLOW.len() - 1 {
+		return None;
+	}
+	if let Some(end) = END_STATUSES.iter().find(|s| **s == target) {
+		return Some(end);
+	}
+	let target = position(target)?;
+	if target <= current {
+		return None;
+	}
+	return Some(STATUS_FLOW[target]);
+}
+
+// the total of every line and of all of them. problems with the lines are added to errors
+pub fn line_totals(lines: &[NewLine], errors: &mut Vec<FieldError>) -> (Vec<i64>, Option<i64>) {
+	let mut error = |field: String, message: &str| errors.push(FieldError { field, message: message.to_string() });
+	if lines.is_empty() || lines.len() > MAX_LINES {
+		error("/lines".to_string(), &format!("Expected between 1 and {} lines", MAX_LINES));
+	}
+
+	let mut totals = Vec::with_capacity(lines.len());
+	let mut total = Some(0i64);
+	for (i, line) in lines.iter().enumerate() {
+		if line.description.trim().is_empty() {
+			error(format!("/lines/{}/description", i), "A description is required");
+		}
+		if line.quantity <= 0 {
+			error(format!("/lines/{}/quantity", i), "Must be at least 1");
+		}
+		if line.unit_price_cents < 0 {
+			error(format!("/lines/{}/unit_price_cents", i), "Cannot be negative");
+		}
+		let line_total = line.unit_price_cents.checked_mul(line.quantity as i64);
+		if line_total.is_none() {
+			error(format!("/lines/{}", i), "Line total is too large");
+		}
+		let line_total = line_total.unwrap_or_default();
+		total = total.and_then(|t| t.checked_add(line_total));
+		totals.push(line_total);
+	}
+	if total.is_none() {
+		error("/lines".to_string(), "Total is too large");
+	}
+	return (totals, total);
+}
+
+pub fn valid_currency(currency: &str) -> bool {
+	return currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase());
+}
+
+// the total of every line and of the order, or what is wrong with the order
+pub fn check_order(order: &CreatePurchaseOrder) -> Result<(Vec<i64>, i64), Vec<FieldError>> {
+	let mut errors = Vec::new();
+	if order.vendor.trim().is_empty() {
+		errors.push(FieldError { field: "/vendor".to_string(), message: "A vendor is required".to_string() });
+	}
+	if !valid_currency(&order.currency) {
+		errors.push(FieldError { field: "/currency".to_string(), message: "Expected a three letter currency code like EUR".to_string() });
+	}
+	let (totals, total) = line_totals(&order.lines, &mut errors);
+
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	return Ok((totals, total.unwrap()));
+}
+
+// saves the order and starts its approval ticket in the same transaction, so every order has a ticket
+#[utoipa::path(post, path = "/purchase_orders", tag = "purchase_orders", request_body = CreatePurchaseOrder, responses(
+	(status = 201, description = "Created with its approval ticket", body = PurchaseOrderDetail),
+	(status = 422, description = "Invalid vendor, currency or lines", body = FieldErrors)
+))]
+pub async fn create_purchase_order(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreatePurchaseOrder>
+) -> Result<(StatusCode, Json<PurchaseOrderDetail>), (StatusCode, Json<FieldErrors>)> {
+	let (line_totals, total) = check_order(&payload)
+		.map_err(|errors| (StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })))?;
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let now = chrono::Utc::now();
+	let order: Result<PurchaseOrder, _> = sqlx::query_as(
+		r#"insert into purchase_orders (vendor, currency, total_cents, notes, created_by, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $6) returning *"#)
+		.bind(payload.vendor.trim())
+		.bind(&payload.currency)
+		.bind(total)
+		.bind(&payload.notes)
+		.bind(user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = order {
+		admin_logger(LogType::Error, &format!("Error saving purchase order of {}: {}", user.userid, e), None);
+		return Err(internal_error());
+	}
+	let mut order = order.unwrap();
+
+	let mut lines = Vec::with_capacity(payload.lines.len());
+	for (i, (line, line_total)) in payload.lines.iter().zip(line_totals).enumerate() {
+		let saved: Result<PurchaseOrderLine, _> = sqlx::query_as(
+			r#"insert into purchase_order_lines (purchase_order_id, line_number, description, quantity, unit_price_cents, total_cents)
+				values ($1, $2, $3, $4, $5, $6) returning line_number, description, quantity, unit_price_cents, total_cents"#)
+			.bind(order.id)
+			.bind(i as i32 + 1)
+			.bind(line.description.trim())
+			.bind(line.quantity)
+			.bind(line.unit_price_cents)
+			.bind(line_total)
+			.fetch_one(&mut *tx)
+			.await;
+		if let Err(e) = saved {
+			admin_logger(LogType::Error, &format!("Error saving line {} of purchase order {}: {}", i + 1, order.id, e), None);
+			return Err(internal_error());
+		}
+		lines.push(saved.unwrap());
+	}
+
+	// approvers see the order in the ticket state, auto_approve_if rules can use the total
+	let mut data = Map::new();
+	data.insert("purchase_order_id".to_string(), json!(order.id));
+	data.insert("vendor".to_string(), json!(order.vendor));
+	data.insert("currency".to_string(), json!(order.currency));
+	data.insert("total_cents".to_string(), json!(order.total_cents));
+	// in major units, checked against the approval limits of the approvers
+	data.insert("amount".to_string(), json!(order.total_cents as f64 / 100.0));
+	data.insert("lines".to_string(), json!(payload.lines));
+	let new = NewTicket { process_id: process_id(), data, tag: "purchase-order", due_at: payload.due_at };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "purchase_orders", id: order.id }, new).await
+		.map_err(|status| (status, Json(FieldErrors { errors: Vec::new() })))?;
+
+	// the first node may have moved the order already
+	let status: Result<(String,), _> = sqlx::query_as("select status from purchase_orders where id=$1")
+		.bind(order.id)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = status {
+		admin_logger(LogType::Error, &format!("Error reading purchase order {}: {}", order.id, e), None);
+		return Err(internal_error());
+	}
+	order.status = status.unwrap().0;
+	order.ticket_id = Some(ticket.id);
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting purchase order {}: {}", order.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("Purchase order {} of {} {} created by {} with ticket {}", order.id, order.total_cents, order.currency, user.userid, ticket.id), ticket.log_id);
+	return Ok((StatusCode::CREATED, Json(PurchaseOrderDetail { order, lines })));
+}
+
+#[utoipa::path(get, path = "/purchase_orders", tag = "purchase_orders", params(PurchaseOrdersQuery), responses(
+	(status = 200, description = "Own orders, or every order with all", body = Vec<PurchaseOrder>),
+	(status = 403, description = "all needs the manage_purchase_orders permission")
+))]
+pub async fn get_purchase_orders(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<PurchaseOrdersQuery>
+) -> Result<Json<Vec<PurchaseOrder>>, StatusCode> {
+	let all = query.all.unwrap_or(false);
+	if all {
+		let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		match rbac::has_permission(&mut conn, user.userid, "manage_purchase_orders").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN),
+			Ok(true) => {}
+		}
+	}
+
+	let orders: Result<Vec<PurchaseOrder>, _> = sqlx::query_as(
+		r#"select * from purchase_orders where ($1 or created_by=$2) and ($3::varchar is null or status=$3)
+			order by id desc"#)
+		.bind(all)
+		.bind(user.userid)
+		.bind(&query.status)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = orders {
+		admin_logger(LogType::Error, &format!("Error reading purchase orders of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(orders.unwrap()));
+}
+
+#[utoipa::path(get, path = "/purchase_orders/{id}", tag = "purchase_orders", params(("id" = i32, Path, description = "Purchase order id")), responses(
+	(status = 200, description = "The order with its lines", body = PurchaseOrderDetail),
+	(status = 404, description = "Unknown order or not visible to the user")
+))]
+pub async fn get_purchase_order(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<PurchaseOrderDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let order: Result<Option<PurchaseOrder>, _> = sqlx::query_as("select * from purchase_orders where id=$1")
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = order {
+		admin_logger(LogType::Error, &format!("Error reading purchase order {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let order = order.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	if order.created_by != user.userid {
+		match rbac::has_permission(&mut conn, user.userid, "manage_purchase_orders").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			// the same answer as for orders that do not exist
+			Ok(false) => return Err(StatusCode::NOT_FOUND),
+			Ok(true) => {}
+		}
+	}
+
+	let lines: Result<Vec<PurchaseOrderLine>, _> = sqlx::query_as(
+		"select line_number, description, quantity, unit_price_cents, total_cents from purchase_order_lines where purchase_order_id=$1 order by line_number")
+		.bind(id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = lines {
+		admin_logger(LogType::Error, &format!("Error reading lines of purchase order {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(PurchaseOrderDetail { order, lines: lines.unwrap() }));
+}
+
+// every purchase order, for admins. same as /purchase_orders?all=true
+#[utoipa::path(get, path = "/admin/purchase_orders", tag = "purchase_orders", params(PurchaseOrdersQuery), responses(
+	(status = 200, description = "Every order", body = Vec<PurchaseOrder>),
+	(status = 403, description = "Missing the manage_purchase_orders permission")
+))]
+pub async fn get_all_purchase_orders(
+	auth: Authorized<ManagePurchaseOrders>,
+	pool: extract::State<PgPool>,
+	extract::Query(mut query): extract::Query<PurchaseOrdersQuery>
+) -> Result<Json<Vec<PurchaseOrder>>, StatusCode> {
+	query.all = Some(true);
+	return get_purchase_orders(auth.user, pool, extract::Query(query)).await;
+}
+
+// moves the order of the ticket through the statuses its transitions ask for, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let order: Option<(i32, String)> = sqlx::query_as("select id, status from purchase_orders where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = order else {
+		return Ok(());
+	};
+
+	let mut status = current.clone();
+	for target in statuses {
+		match next_status(&status, target) {
+			Some(next) => status = next.to_string(),
+			None => admin_logger(LogType::Warning, &format!("Ticket {} cannot move purchase order {} from {} to {}", ticket_id, id, status, target), None)
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update purchase_orders set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(&status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Purchase order {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod purchase_orders_tests {
+	use super::{check_order, next_status, CreatePurchaseOrder, NewLine};
+
+	fn order(lines: Vec<NewLine>) -> CreatePurchaseOrder {
+		return CreatePurchaseOrder { vendor: "Acme".to_string(), currency: "EUR".to_string(), lines, notes: None, due_at: None };
+	}
+
+	fn line(quantity: i32, unit_price_cents: i64) -> NewLine {
+		return NewLine { description: "laptop".to_string(), quantity, unit_price_cents };
+	}
+
+	#[test]
+	fn statuses_only_move_forward() {
+		assert_eq!(next_status("pending_approval", "approved"), Some("approved"));
+		assert_eq!(next_status("approved", "received"), Some("received"));
+		assert_eq!(next_status("sent", "approved"), None);
+		assert_eq!(next_status("sent", "rejected"), Some("rejected"));
+		assert_eq!(next_status("closed", "cancelled"), None);
+		assert_eq!(next_status("rejected", "closed"), None);
+		assert_eq!(next_status("approved", "shipped"), None);
+	}
+
+	#[test]
+	fn totals_are_summed_per_line() {
+		let (lines, total) = check_order(&order(vec![line(2, 15000), line(1, 99)])).unwrap();
+		assert_eq!(lines, vec![30000, 99]);
+		assert_eq!(total, 30099);
+	}
+
+	#[test]
+	fn invalid_orders_are_rejected() {
+		let mut invalid = order(vec![line(0, 10), line(2, i64::MAX), line(1, i64::MAX), line(1, 1)]);
+		invalid.currency = "eur".to_string();
+		let fields: Vec<String> = check_order(&invalid).unwrap_err().into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/currency", "/lines/0/quantity", "/lines/1", "/lines"]);
+		assert!(check_order(&order(vec![])).is_err());
+	}
+}
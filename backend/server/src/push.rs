@@ -0,0 +1,396 @@
+use axum::{extract, http::StatusCode, Json};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+use tokio::sync::Mutex;
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use crate::ws::{LiveEvent, LiveEventKind};
+use crate::shutdown;
+use utoipa::ToSchema;
+
+pub const PLATFORMS: [&str; 2] = ["fcm", "apns"];
+
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+// apple rejects provider tokens older than an hour and refreshing more often than every 20 minutes
+const APNS_TOKEN_TTL_SECS: i64 = 50 * 60;
+
+#[derive(Deserialize, ToSchema)]
+pub struct RegisterDevice {
+	pub platform: String,
+	pub token: String
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Device {
+	pub id: i32,
+	pub platform: String,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub last_seen_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(FromRow)]
+struct DeviceToken {
+	id: i32,
+	platform: String,
+	token: String
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushMessage {
+	pub userid: uuid::Uuid,
+	pub title: String,
+	pub body: String,
+	// a push with the same key replaces the previous one on the phone instead of stacking
+	pub collapse_key: String,
+	pub ticket_id: i32
+}
+
+// the json file of a firebase service account
+#[derive(Deserialize)]
+struct ServiceAccount {
+	client_email: String,
+	private_key: String,
+	token_uri: String
+}
+
+#[derive(Serialize)]
+struct GoogleClaims<'a> {
+	iss: &'a str,
+	scope: &'a str,
+	aud: &'a str,
+	iat: i64,
+	exp: i64
+}
+
+#[derive(Serialize)]
+struct ApnsClaims<'a> {
+	iss: &'a str,
+	iat: i64
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+	access_token: String,
+	expires_in: i64
+}
+
+enum SendResult {Sent, InvalidToken, Failed(String)}
+
+static HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+// (token, expires at) of the fcm access token and the apns provider token
+type CachedToken = Option<(String, chrono::DateTime<chrono::Utc>)>;
+
+static FCM_TOKEN: Lazy<Mutex<CachedToken>> = Lazy::new(|| Mutex::new(None));
+static APNS_TOKEN: Lazy<Mutex<CachedToken>> = Lazy::new(|| Mutex::new(None));
+
+pub fn approval_collapse_key(ticket_id: i32, node: i32) -> String {
+	return format!("approve-{}-{}", ticket_id, node);
+}
+
+// approvers are pushed for new approval requests. the other events are left to the in app notifications
+pub fn push_messages(events: &[(uuid::Uuid, LiveEvent)]) -> Vec<PushMessage> {
+	return events.iter()
+		.filter(|(_, e)| e.kind == LiveEventKind::ApproveRequest)
+		.map(|(userid, e)| PushMessage {
+			userid: *userid,
+			title: "Approval requested".to_string(),
+			body: e.message.clone(),
+			collapse_key: approval_collapse_key(e.ticket_id, e.node),
+			ticket_id: e.ticket_id
+		})
+		.collect();
+}
+
+pub fn fcm_body(message: &PushMessage, token: &str) -> serde_json::Value {
+	return json!({
+		"message": {
+			"token": token,
+			"notification": {"title": message.title, "body": message.body},
+			"data": {"ticket_id": message.ticket_id.to_string()},
+			"android": {"collapse_key": message.collapse_key},
+			"apns": {"headers": {"apns-collapse-id": message.collapse_key}}
+		}
+	});
+}
+
+pub fn apns_body(message: &PushMessage) -> serde_json::Value {
+	return json!({
+		"aps": {"alert": {"title": message.title, "body": message.body}, "sound": "default"},
+		"ticket_id": message.ticket_id
+	});
+}
+
+async fn fcm_access_token() -> Result<String, String> {
+	let mut cached = FCM_TOKEN.lock().await;
+	if let Some((token, expires_at)) = cached.as_ref() {
+		if *expires_at > chrono::Utc::now() {
+			return Ok(token.clone());
+		}
+	}
+
+	let path = std::env::var("FCM_SERVICE_ACCOUNT").map_err(|_| "FCM_SERVICE_ACCOUNT not defined".to_string())?;
+	let account = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+	let account: ServiceAccount = serde_json::from_str(&account).map_err(|e| format!("Invalid service account: {}", e))?;
+
+	let now = chrono::Utc::now().timestamp();
+	let claims = GoogleClaims { iss: &account.client_email, scope: FCM_SCOPE, aud: &account.token_uri, iat: now, exp: now + 3600 };
+	let key = jsonwebtoken::EncodingKey::from_rsa_pem(account.private_key.as_bytes()).map_err(|e| e.to_string())?;
+	let assertion = jsonwebtoken::encode(&jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256), &claims, &key)
+		.map_err(|e| e.to_string())?;
+
+	let response = HTTP.post(&account.token_uri)
+		.form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)])
+		.send()
+		.await
+		.and_then(|r| r.error_for_status())
+		.map_err(|e| e.to_string())?;
+	let token: AccessToken = response.json().await.map_err(|e| e.to_string())?;
+
+	// renewed a minute early so a token never expires in flight
+	let expires_at = chrono::Utc::now() + chrono::Duration::seconds(token.expires_in - 60);
+	*cached = Some((token.access_token.clone(), expires_at));
+	return Ok(token.access_token);
+}
+
+async fn apns_provider_token() -> Result<String, String> {
+	let mut cached = APNS_TOKEN.lock().await;
+	if let Some((token, expires_at)) = cached.as_ref() {
+		if *expires_at > chrono::Utc::now() {
+			return Ok(token.clone());
+		}
+	}
+
+	let key_path = std::env::var("APNS_KEY_PATH").map_err(|_| "APNS_KEY_PATH not defined".to_string())?;
+	let key_id = std::env::var("APNS_KEY_ID").map_err(|_| "APNS_KEY_ID not defined".to_string())?;
+	let team_id = std::env::var("APNS_TEAM_ID").map_err(|_| "APNS_TEAM_ID not defined".to_string())?;
+	let key = std::fs::read(&key_path).map_err(|e| format!("Failed to read {}: {}", key_path, e))?;
+	let key = jsonwebtoken::EncodingKey::from_ec_pem(&key).map_err(|e| e.to_string())?;
+
+	let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
+	header.kid = Some(key_id);
+	let now = chrono::Utc::now();
+	let token = jsonwebtoken::encode(&header, &ApnsClaims { iss: &team_id, iat: now.timestamp() }, &key)
+		.map_err(|e| e.to_string())?;
+
+	*cached = Some((token.clone(), now + chrono::Duration::seconds(APNS_TOKEN_TTL_SECS)));
+	return Ok(token);
+}
+
+async fn send_fcm(message: &PushMessage, token: &str) -> SendResult {
+	let project = match std::env::var("FCM_PROJECT_ID") {
+		Ok(project) => project,
+		Err(_) => return SendResult::Failed("FCM_PROJECT_ID not defined".to_string())
+	};
+	let access_token = match fcm_access_token().await {
+		Ok(token) => token,
+		Err(e) => return SendResult::Failed(e)
+	};
+
+	let response = HTTP.post(format!("https://fcm.googleapis.com/v1/projects/{}/messages:send", project))
+		.bearer_auth(access_token)
+		.json(&fcm_body(message, token))
+		.send()
+		.await;
+	return match response {
+		Ok(r) if r.status().is_success() => SendResult::Sent,
+		// UNREGISTERED
+		Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => SendResult::InvalidToken,
+		Ok(r) => SendResult::Failed(format!("fcm answered {}", r.status())),
+		Err(e) => SendResult::Failed(e.to_string())
+	};
+}
+
+async fn send_apns(message: &PushMessage, token: &str) -> SendResult {
+	let topic = match std::env::var("APNS_TOPIC") {
+		Ok(topic) => topic,
+		Err(_) => return SendResult::Failed("APNS_TOPIC not defined".to_string())
+	};
+	let provider_token = match apns_provider_token().await {
+		Ok(token) => token,
+		Err(e) => return SendResult::Failed(e)
+	};
+	let host = match std::env::var("APNS_SANDBOX").as_deref() {
+		Ok("true") => "api.sandbox.push.apple.com",
+		_ => "api.push.apple.com"
+	};
+
+	let response = HTTP.post(format!("https://{}/3/device/{}", host, token))
+		.bearer_auth(provider_token)
+		.header("apns-topic", topic)
+		.header("apns-push-type", "alert")
+		.header("apns-priority", "10")
+		.header("apns-collapse-id", &message.collapse_key)
+		.json(&apns_body(message))
+		.send()
+		.await;
+	return match response {
+		Ok(r) if r.status().is_success() => SendResult::Sent,
+		// BadDeviceToken and Unregistered
+		Ok(r) if r.status() == reqwest::StatusCode::GONE || r.status() == reqwest::StatusCode::BAD_REQUEST => SendResult::InvalidToken,
+		Ok(r) => SendResult::Failed(format!("apns answered {}", r.status())),
+		Err(e) => SendResult::Failed(e.to_string())
+	};
+}
+
+async fn deliver(pool: &PgPool, messages: Vec<PushMessage>) -> Result<(), sqlx::Error> {
+	for message in messages {
+		let devices: Vec<DeviceToken> = sqlx::query_as("select id, platform, token from device_tokens where userid=$1")
+			.bind(message.userid)
+			.fetch_all(pool)
+			.await?;
+		for device in devices {
+			let result = match device.platform.as_str() {
+				"fcm" => send_fcm(&message, &device.token).await,
+				_ => send_apns(&message, &device.token).await
+			};
+			match result {
+				SendResult::Sent => {}
+				SendResult::InvalidToken => {
+					// the app was removed or the token was rotated by the phone
+					sqlx::query("delete from device_tokens where id=$1")
+						.bind(device.id)
+						.execute(pool)
+						.await?;
+				}
+				SendResult::Failed(e) => {
+					admin_logger(LogType::Warning, &format!("Failed to push to device {} of user {}: {}", device.id, message.userid, e), None);
+				}
+			}
+		}
+	}
+	return Ok(());
+}
+
+// pushes in the background so the request does not wait for the push services. only call after the commit
+pub fn dispatch(pool: &PgPool, messages: Vec<PushMessage>) {
+	if messages.is_empty() {
+		return;
+	}
+	let pool = pool.clone();
+	shutdown::spawn(async move {
+		if let Err(e) = deliver(&pool, messages).await {
+			admin_logger(LogType::Error, &format!("Error sending push notifications: {}", e), None);
+		}
+	});
+}
+
+// registering a token that another user registered before moves it to the current user
+#[utoipa::path(post, path = "/devices", tag = "notifications", request_body = RegisterDevice, responses(
+	(status = 200, description = "Registered, urgent notifications are pushed to it", body = Device),
+	(status = 422, description = "Unknown platform or missing token", body = String)
+))]
+pub async fn register_device(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<RegisterDevice>
+) -> Result<Json<Device>, (StatusCode, String)> {
+	let platform = payload.platform.trim().to_lowercase();
+	if !PLATFORMS.contains(&platform.as_str()) {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown platform: {}", payload.platform)));
+	}
+	let token = payload.token.trim();
+	if token.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "A device token is required".to_string()));
+	}
+
+	let now = chrono::Utc::now();
+	let query: Result<Device, _> = sqlx::query_as(
+		r#"insert into device_tokens (userid, platform, token, created_at, last_seen_at) values ($1, $2, $3, $4, $4)
+			on conflict (token) do update set userid=$1, platform=$2, last_seen_at=$4
+			returning id, platform, created_at, last_seen_at"#)
+		.bind(user.userid)
+		.bind(&platform)
+		.bind(token)
+		.bind(now)
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error registering device of user {}: {}", user.userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+#[utoipa::path(get, path = "/devices", tag = "notifications", responses(
+	(status = 200, description = "Devices of the user", body = Vec<Device>)
+))]
+pub async fn get_devices(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<Device>>, StatusCode> {
+	let query: Result<Vec<Device>, _> = sqlx::query_as(
+		"select id, platform, created_at, last_seen_at from device_tokens where userid=$1 order by created_at")
+		.bind(user.userid)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading devices of user {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+#[utoipa::path(delete, path = "/devices/{id}", tag = "notifications", params(("id" = i32, Path, description = "Device id")), responses(
+	(status = 200, description = "Removed"),
+	(status = 404, description = "Unknown device")
+))]
+pub async fn delete_device(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from device_tokens where id=$1 and userid=$2")
+		.bind(id)
+		.bind(user.userid)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting device {} of user {}: {}", id, user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod push_tests {
+	use super::{apns_body, approval_collapse_key, fcm_body, push_messages};
+	use crate::ws::{LiveEvent, LiveEventKind};
+
+	fn event(kind: LiveEventKind) -> LiveEvent {
+		return LiveEvent {
+			kind,
+			ticket_id: 7,
+			process_id: "leave".to_string(),
+			node: 2,
+			message: "Ticket 7 needs your approval".to_string(),
+			created_at: chrono::Utc::now()
+		};
+	}
+
+	#[test]
+	fn approvals_are_pushed() {
+		let approver = uuid::Uuid::new_v4();
+		let events = vec![(approver, event(LiveEventKind::ApproveRequest)), (uuid::Uuid::new_v4(), event(LiveEventKind::Notify))];
+		let messages = push_messages(&events);
+		assert_eq!(messages.len(), 1);
+		assert_eq!(messages[0].userid, approver);
+		assert_eq!(messages[0].collapse_key, approval_collapse_key(7, 2));
+
+		let fcm = fcm_body(&messages[0], "device");
+		assert_eq!(fcm["message"]["token"], "device");
+		assert_eq!(fcm["message"]["android"]["collapse_key"], "approve-7-2");
+		assert_eq!(fcm["message"]["data"]["ticket_id"], "7");
+
+		let apns = apns_body(&messages[0]);
+		assert_eq!(apns["aps"]["alert"]["body"], "Ticket 7 needs your approval");
+		assert_eq!(apns["ticket_id"], 7);
+	}
+}
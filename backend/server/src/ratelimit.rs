@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::{body::Body, http::{header, HeaderMap, HeaderValue, Request, StatusCode}, middleware::Next, response::{IntoResponse, Response}};
+use once_cell::sync::Lazy;
+use crate::auth::{bearer_token, decode_token, JWT_SECRET};
+use crate::logger::{admin_logger, LogType};
+
+// full buckets are dropped once there are more than this many
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+// writes every identity gets per minute once its burst is used up. 0 turns the limit off
+fn writes_per_minute() -> u32 {
+	return std::env::var("RATE_LIMIT_WRITES_PER_MINUTE")
+		.ok()
+		.and_then(|s| s.parse::<u32>().ok())
+		.unwrap_or(60);
+}
+
+// writes that can be made at once after being idle
+fn write_burst() -> u32 {
+	return std::env::var("RATE_LIMIT_WRITES_BURST")
+		.ok()
+		.and_then(|s| s.parse::<u32>().ok())
+		.unwrap_or(20);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+	pub per_minute: u32,
+	pub burst: u32
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	updated_at: chrono::DateTime<chrono::Utc>
+}
+
+// one token bucket per user or api key, shared by every limited route
+#[derive(Default)]
+pub struct Buckets {
+	buckets: HashMap<String, Bucket>,
+	// pruned again once there are this many, so busy buckets are not scanned on every request
+	prune_at: usize
+}
+
+static WRITE_BUCKETS: Lazy<Mutex<Buckets>> = Lazy::new(|| {
+	return Mutex::new(Buckets::default());
+});
+
+impl Limit {
+	fn refill_per_ms(&self) -> f64 {
+		return self.per_minute as f64 / 60_000.0;
+	}
+}
+
+impl Bucket {
+	fn refill(&mut self, now: chrono::DateTime<chrono::Utc>, limit: Limit) {
+		let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64;
+		self.tokens = (self.tokens + elapsed * limit.refill_per_ms()).min(limit.burst as f64);
+		self.updated_at = now;
+	}
+}
+
+impl Buckets {
+	// takes a token from the bucket of the identity. Err holds the seconds until the next one
+	pub fn take(&mut self, identity: &str, now: chrono::DateTime<chrono::Utc>, limit: Limit) -> Result<(), u64> {
+		if self.buckets.len() > self.prune_at.max(MAX_IDLE_BUCKETS) {
+			self.buckets.retain(|_, bucket| {
+				bucket.refill(now, limit);
+				return bucket.tokens < limit.burst as f64;
+			});
+			self.prune_at = self.buckets.len() * 2;
+		}
+
+		let bucket = self.buckets
+			.entry(identity.to_string())
+			.or_insert(Bucket { tokens: limit.burst as f64, updated_at: now });
+		bucket.refill(now, limit);
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			return Ok(());
+		}
+		let wait_ms = (1.0 - bucket.tokens) / limit.refill_per_ms();
+		return Err((wait_ms / 1000.0).ceil().max(1.0) as u64);
+	}
+}
+
+// the user of a valid access token. api keys are limited by their extractor once the secret is checked,
+// anyone can send the id of a key
+pub fn identity(headers: &HeaderMap, jwt_secret: &str) -> Option<String> {
+	let token = bearer_token(headers)?;
+	return decode_token(token, jwt_secret).ok().map(|claims| format!("user:{}", claims.sub));
+}
+
+pub fn key_identity(id: uuid::Uuid) -> String {
+	return format!("api_key:{}", id);
+}
+
+// takes a write from the bucket of the identity. Err holds the seconds until the next one
+pub fn take_write(identity: &str) -> Result<(), u64> {
+	let limit = Limit { per_minute: writes_per_minute(), burst: write_burst() };
+	if limit.per_minute == 0 || limit.burst == 0 {
+		return Ok(());
+	}
+	return WRITE_BUCKETS.lock().unwrap().take(identity, chrono::Utc::now(), limit);
+}
+
+// route layer for the endpoints that write. requests without an identity are passed on and rejected by the handler
+pub async fn limit_writes(request: Request<Body>, next: Next<Body>) -> Response {
+	let identity = match identity(request.headers(), &JWT_SECRET) {
+		Some(identity) => identity,
+		None => return next.run(request).await
+	};
+
+	if let Err(retry_after) = take_write(&identity) {
+		admin_logger(LogType::Warning, &format!("Rate limited {} on {}, retry after {}s", identity, request.uri().path(), retry_after), None);
+		return too_many_requests(retry_after);
+	}
+	return next.run(request).await;
+}
+
+pub fn too_many_requests(retry_after: u64) -> Response {
+	let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+	response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+	return response;
+}
+
+#[cfg(test)]
+mod ratelimit_tests {
+	use axum::http::{header, HeaderMap, HeaderValue};
+	use crate::auth::{encode_token, Claims};
+	use super::{identity, Buckets, Limit, MAX_IDLE_BUCKETS};
+
+	#[test]
+	fn buckets_refill_over_time() {
+		let limit = Limit { per_minute: 30, burst: 3 };
+		let now = chrono::Utc::now();
+		let mut buckets = Buckets::default();
+
+		for _ in 0..3 {
+			assert_eq!(buckets.take("user:a", now, limit), Ok(()));
+		}
+		// one token every two seconds
+		assert_eq!(buckets.take("user:a", now, limit), Err(2));
+		assert_eq!(buckets.take("user:b", now, limit), Ok(()));
+
+		let later = now + chrono::Duration::milliseconds(1500);
+		assert_eq!(buckets.take("user:a", later, limit), Err(1));
+		let latest = now + chrono::Duration::seconds(2);
+		assert_eq!(buckets.take("user:a", latest, limit), Ok(()));
+		assert_eq!(buckets.take("user:a", latest, limit), Err(2));
+
+		// never more than the burst after being idle
+		let idle = now + chrono::Duration::hours(1);
+		for _ in 0..3 {
+			assert_eq!(buckets.take("user:a", idle, limit), Ok(()));
+		}
+		assert!(buckets.take("user:a", idle, limit).is_err());
+	}
+
+	#[test]
+	fn full_buckets_are_dropped() {
+		let limit = Limit { per_minute: 60, burst: 2 };
+		let now = chrono::Utc::now();
+		let mut buckets = Buckets::default();
+		for i in 0..=MAX_IDLE_BUCKETS {
+			assert_eq!(buckets.take(&format!("user:{}", i), now, limit), Ok(()));
+		}
+		// refilled by the time of the next request, only the bucket in use is left
+		let later = now + chrono::Duration::seconds(2);
+		assert_eq!(buckets.take("user:busy", later, limit), Ok(()));
+		assert_eq!(buckets.buckets.len(), 1);
+	}
+
+	#[test]
+	fn identities_come_from_tokens() {
+		let mut headers = HeaderMap::new();
+		assert_eq!(identity(&headers, "secret"), None);
+
+		// the secret of a key is not checked here, so keys are limited by their extractor
+		let key = uuid::Uuid::new_v4();
+		headers.insert("X-Api-Key", HeaderValue::from_str(&format!("{}.garbage", key)).unwrap());
+		assert_eq!(identity(&headers, "secret"), None);
+
+		let now = chrono::Utc::now().timestamp();
+		let user = uuid::Uuid::new_v4();
+		let claims = Claims { sub: user, username: "asha".to_string(), tid: 1, sid: uuid::Uuid::new_v4(), iat: now, exp: now + 60 };
+		let token = encode_token(&claims, "secret").unwrap();
+		headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
+		assert_eq!(identity(&headers, "secret"), Some(format!("user:{}", user)));
+		// a forged token has no identity, even with a key next to it
+		assert_eq!(identity(&headers, "other"), None);
+	}
+}
@@ -0,0 +1,196 @@
+use std::marker::PhantomData;
+use axum::{async_trait, extract::{self, FromRequestParts}, http::{request::Parts, StatusCode}, Json};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use crate::audit::{self, AuditAction, AuditEvent};
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use utoipa::ToSchema;
+
+// every permission a role can be granted in role_permissions. "*" grants all of them
+pub const PERMISSIONS: [&str; 14] = ["manage_api_keys", "manage_assets", "manage_customers", "manage_documents", "manage_invoices", "manage_leave", "manage_processes", "manage_purchase_orders", "manage_roles", "manage_timesheets", "manage_users", "manage_vendors", "view_audit", "view_logs"];
+
+pub trait Permission {
+	const NAME: &'static str;
+}
+
+pub struct ManageApiKeys;
+pub struct ManageAssets;
+pub struct ManageCustomers;
+pub struct ManageDocuments;
+pub struct ManageInvoices;
+pub struct ManageLeave;
+pub struct ManageProcesses;
+pub struct ManagePurchaseOrders;
+pub struct ManageRoles;
+pub struct ManageTimesheets;
+pub struct ManageUsers;
+pub struct ManageVendors;
+pub struct ViewAudit;
+pub struct ViewLogs;
+
+impl Permission for ManageApiKeys { const NAME: &'static str = "manage_api_keys"; }
+impl Permission for ManageAssets { const NAME: &'static str = "manage_assets"; }
+impl Permission for ManageCustomers { const NAME: &'static str = "manage_customers"; }
+impl Permission for ManageDocuments { const NAME: &'static str = "manage_documents"; }
+impl Permission for ManageInvoices { const NAME: &'static str = "manage_invoices"; }
+impl Permission for ManageLeave { const NAME: &'static str = "manage_leave"; }
+impl Permission for ManageProcesses { const NAME: &'static str = "manage_processes"; }
+impl Permission for ManagePurchaseOrders { const NAME: &'static str = "manage_purchase_orders"; }
+impl Permission for ManageRoles { const NAME: &'static str = "manage_roles"; }
+impl Permission for ManageTimesheets { const NAME: &'static str = "manage_timesheets"; }
+impl Permission for ManageUsers { const NAME: &'static str = "manage_users"; }
+impl Permission for ManageVendors { const NAME: &'static str = "manage_vendors"; }
+impl Permission for ViewAudit { const NAME: &'static str = "view_audit"; }
+impl Permission for ViewLogs { const NAME: &'static str = "view_logs"; }
+
+// an authenticated user holding a role that grants P. rejects the request with 403 otherwise
+pub struct Authorized<P: Permission> {
+	pub user: AuthUser,
+	permission: PhantomData<P>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetPermissions {
+	pub permissions: Vec<String>
+}
+
+pub fn validate_permissions(permissions: &[String]) -> Result<Vec<String>, String> {
+	let mut result = Vec::new();
+	for permission in permissions {
+		let permission = permission.trim();
+		if permission != "*" && !PERMISSIONS.contains(&permission) {
+			return Err(permission.to_string());
+		}
+		if !result.iter().any(|p| p == permission) {
+			result.push(permission.to_string());
+		}
+	}
+	return Ok(result);
+}
+
+pub async fn has_permission(conn: &mut PgConnection, userid: uuid::Uuid, permission: &str) -> Result<bool, sqlx::Error> {
+	let query = sqlx::query(
+		r#"select 1 from user_roles ur join role_permissions rp on ur.tenant_id=rp.tenant_id and ur.role_=rp.role_
+			where ur.userid=$1 and (rp.permission=$2 or rp.permission='*') limit 1"#)
+		.bind(userid)
+		.bind(permission)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(query.is_some());
+}
+
+// a process can be used by everyone if its allowed roles are {any}, otherwise by users holding one of them
+pub async fn can_use_process(conn: &mut PgConnection, userid: uuid::Uuid, process_id: &str) -> Result<bool, sqlx::Error> {
+	let query = sqlx::query(
+		r#"select 1 from process_defs p where p.process_id=$1 and (p.allowed_roles='{any}'
+			or p.allowed_roles && (select array_agg(role_) from user_roles where userid=$2))"#)
+		.bind(process_id)
+		.bind(userid)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(query.is_some());
+}
+
+#[async_trait]
+impl<P: Permission + Send> FromRequestParts<PgPool> for Authorized<P> {
+	type Rejection = StatusCode;
+
+	async fn from_request_parts(parts: &mut Parts, pool: &PgPool) -> Result<Self, Self::Rejection> {
+		let user = AuthUser::from_request_parts(parts, pool).await?;
+
+		let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		match has_permission(&mut conn, user.userid, P::NAME).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permission {} of {}: {}", P::NAME, user.userid, e), None);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => {
+				admin_logger(LogType::Warning, &format!("User {} ({}) lacks permission {} for {}", user.username, user.userid, P::NAME, parts.uri.path()), None);
+				return Err(StatusCode::FORBIDDEN);
+			}
+			Ok(true) => {}
+		}
+
+		return Ok(Authorized { user, permission: PhantomData });
+	}
+}
+
+// replaces the permissions granted by a role
+#[utoipa::path(put, path = "/roles/{role}/permissions", tag = "roles", params(("role" = String, Path, description = "Role name")), request_body = SetPermissions, responses(
+	(status = 200, description = "Permissions the role has now", body = Vec<String>),
+	(status = 403, description = "Missing the manage_roles permission"),
+	(status = 404, description = "Unknown role"),
+	(status = 422, description = "Unknown permission")
+))]
+pub async fn set_role_permissions(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(role): extract::Path<String>,
+	Json(payload): Json<SetPermissions>
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+	let permissions = validate_permissions(&payload.permissions)
+		.map_err(|p| (StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown permission: {}", p)))?;
+
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	let role_id: Result<Option<(i32,)>, _> = sqlx::query_as("select id from role_defs where role_=$1")
+		.bind(&role)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = role_id {
+		admin_logger(LogType::Error, &format!("Error reading role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let (role_id,) = role_id.unwrap().ok_or((StatusCode::NOT_FOUND, format!("Role {} does not exist", role)))?;
+
+	let previous: Result<Vec<(String,)>, _> = sqlx::query_as("delete from role_permissions where role_=$1 returning permission")
+		.bind(&role)
+		.fetch_all(&mut *tx)
+		.await;
+	if let Err(e) = previous {
+		admin_logger(LogType::Error, &format!("Error removing permissions of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let mut previous: Vec<String> = previous.unwrap().into_iter().map(|p| p.0).collect();
+	previous.sort();
+
+	let query = sqlx::query("insert into role_permissions (role_, permission) select $1, unnest($2::varchar[])")
+		.bind(&role)
+		.bind(&permissions)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error adding permissions to role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::SetPermissions, "role", role_id,
+		Some(serde_json::json!({"role_": role, "permissions": previous})),
+		Some(serde_json::json!({"role_": role, "permissions": permissions})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing permissions of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting permissions of role {}: {}", role, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} set the permissions of role {} to {:?}", auth.user.userid, role, permissions), None);
+	return Ok(Json(permissions));
+}
+
+#[cfg(test)]
+mod rbac_tests {
+	use super::validate_permissions;
+
+	#[test]
+	fn permissions_are_validated() {
+		let permissions = vec!["manage_users".to_string(), " manage_users ".to_string(), "*".to_string()];
+		assert_eq!(validate_permissions(&permissions).unwrap(), vec!["manage_users".to_string(), "*".to_string()]);
+
+		assert_eq!(validate_permissions(&["delete_everything".to_string()]), Err("delete_everything".to_string()));
+	}
+}
@@ -0,0 +1,205 @@
+use std::borrow::Cow;
+use once_cell::sync::Lazy;
+
+pub const REDACTED: &str = "\"[REDACTED]\"";
+
+// keys whose values never reach the logs. * matches any run of characters, case is ignored
+const DEFAULT_PATTERNS: [&str; 16] = [
+	"*password*", "*secret*", "*token*", "salary*", "*_salary", "ssn", "*_ssn", "pan", "aadhaar*",
+	"bank_*", "iban", "account_number", "dob", "date_of_birth", "phone*", "address"
+];
+
+// comma separated patterns in LOG_REDACT_KEYS replace the defaults
+static REDACTOR: Lazy<Redactor> = Lazy::new(|| {
+	return match std::env::var("LOG_REDACT_KEYS") {
+		Ok(keys) => Redactor::new(keys.split(',').map(str::trim).filter(|k| !k.is_empty())),
+		Err(_) => Redactor::new(DEFAULT_PATTERNS.into_iter())
+	};
+});
+
+pub struct Redactor {
+	patterns: Vec<String>
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+	return match pattern.split_first() {
+		None => text.is_empty(),
+		Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+		Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..])
+	};
+}
+
+fn is_ident(c: u8) -> bool {
+	return c.is_ascii_alphanumeric() || c == b'_';
+}
+
+fn skip_whitespace(text: &[u8], mut i: usize) -> usize {
+	while i < text.len() && text[i].is_ascii_whitespace() {
+		i += 1;
+	}
+	return i;
+}
+
+// end of the string literal starting at i, after the closing quote
+fn string_end(text: &[u8], i: usize) -> Option<usize> {
+	let mut j = i + 1;
+	while j < text.len() {
+		match text[j] {
+			b'\\' => j += 2,
+			b'"' => return Some(j + 1),
+			_ => j += 1
+		}
+	}
+	return None;
+}
+
+// end of the bracketed group starting at i. unbalanced groups run to the end of the text
+fn group_end(text: &[u8], i: usize) -> usize {
+	let mut depth = 0;
+	let mut j = i;
+	while j < text.len() {
+		match text[j] {
+			b'"' => {
+				j = string_end(text, j).unwrap_or(text.len());
+				continue;
+			}
+			b'{' | b'[' | b'(' => depth += 1,
+			b'}' | b']' | b')' => {
+				depth -= 1;
+				if depth == 0 {
+					return j + 1;
+				}
+			}
+			_ => {}
+		}
+		j += 1;
+	}
+	return text.len();
+}
+
+// a json value or its Debug form like Number(5000), String("x") or Object {..}
+fn value_end(text: &[u8], i: usize) -> usize {
+	match text.get(i) {
+		None => return i,
+		Some(b'"') => return string_end(text, i).unwrap_or(text.len()),
+		Some(b'{' | b'[' | b'(') => return group_end(text, i),
+		Some(_) => {}
+	}
+	let mut j = i;
+	while j < text.len() && !matches!(text[j], b',' | b';' | b' ' | b'\n' | b'&' | b'}' | b']' | b')' | b'(') {
+		j += 1;
+	}
+	if text.get(j) == Some(&b'(') {
+		return group_end(text, j);
+	}
+	if text[j..].starts_with(b" {") && j > i {
+		return group_end(text, j + 1);
+	}
+	return j;
+}
+
+impl Redactor {
+	pub fn new<'a>(patterns: impl Iterator<Item = &'a str>) -> Redactor {
+		return Redactor { patterns: patterns.map(|p| p.to_lowercase()).collect() };
+	}
+
+	pub fn is_sensitive(&self, key: &str) -> bool {
+		let key = key.to_lowercase();
+		return self.patterns.iter().any(|p| glob_match(p.as_bytes(), key.as_bytes()));
+	}
+
+	// replaces the values of sensitive keys in "key": value, key: value and key=value
+	pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+		let bytes = text.as_bytes();
+		let mut redacted = String::new();
+		let mut copied = 0;
+		let mut i = 0;
+		while i < bytes.len() {
+			let (key, key_end) = if bytes[i] == b'"' {
+				match string_end(bytes, i) {
+					Some(end) => (&text[i + 1..end - 1], end),
+					None => break
+				}
+			}
+			else if is_ident(bytes[i]) && (i == 0 || !is_ident(bytes[i - 1])) {
+				let mut end = i;
+				while end < bytes.len() && is_ident(bytes[end]) {
+					end += 1;
+				}
+				(&text[i..end], end)
+			}
+			else {
+				i += 1;
+				continue;
+			};
+
+			let separator = skip_whitespace(bytes, key_end);
+			let separated = matches!(bytes.get(separator), Some(b':' | b'=')) && bytes.get(separator + 1) != Some(&b':');
+			if separated && self.is_sensitive(key) {
+				let start = skip_whitespace(bytes, separator + 1);
+				let end = value_end(bytes, start);
+				if end > start {
+					redacted.push_str(&text[copied..start]);
+					redacted.push_str(REDACTED);
+					copied = end;
+					i = end;
+					continue;
+				}
+			}
+			i = key_end;
+		}
+
+		if copied == 0 {
+			return Cow::Borrowed(text);
+		}
+		redacted.push_str(&text[copied..]);
+		return Cow::Owned(redacted);
+	}
+}
+
+pub fn redact(text: &str) -> Cow<'_, str> {
+	return REDACTOR.redact(text);
+}
+
+#[cfg(test)]
+mod redact_tests {
+	use super::{Redactor, DEFAULT_PATTERNS};
+
+	fn redactor() -> Redactor {
+		return Redactor::new(DEFAULT_PATTERNS.into_iter());
+	}
+
+	#[test]
+	fn json_and_debug_values_are_redacted() {
+		let redactor = redactor();
+		assert_eq!(
+			redactor.redact(r#"state: {"Salary": 5000, "name": "Asha", "bank_account": {"iban": "DE00"}, "tags": ["a"]}"#),
+			r#"state: {"Salary": "[REDACTED]", "name": "Asha", "bank_account": "[REDACTED]", "tags": ["a"]}"#);
+		assert_eq!(
+			redactor.redact(r#"Invalid data: Some({"base_salary": Number(5000), "note": String("ok"), "phone": String("+91 98")})"#),
+			r#"Invalid data: Some({"base_salary": "[REDACTED]", "note": String("ok"), "phone": "[REDACTED]"})"#);
+		assert_eq!(
+			redactor.redact(r#"UpdateTicket { data: Some(Object {"employee_ssn": String("1-2"), "x": Bool(true)}), ssn: 12 }"#),
+			r#"UpdateTicket { data: Some(Object {"employee_ssn": "[REDACTED]", "x": Bool(true)}), ssn: "[REDACTED]" }"#);
+	}
+
+	#[test]
+	fn bare_keys_and_queries_are_redacted() {
+		let redactor = redactor();
+		assert_eq!(redactor.redact("/ws/notifications?token=abc.def&x=1"), r#"/ws/notifications?token="[REDACTED]"&x=1"#);
+		assert_eq!(redactor.redact("userid: 7, token: 8f2a"), r#"userid: 7, token: "[REDACTED]""#);
+		// paths and plain text stay as they are
+		let text = "Ticket 4 rejected by 7 at node 2, reason: password reset::done";
+		assert!(matches!(redactor.redact(text), std::borrow::Cow::Borrowed(t) if t == text));
+		assert_eq!(redactor.redact(r#"oops "password: 1"#), r#"oops "password: 1"#);
+	}
+
+	#[test]
+	fn patterns_are_configurable() {
+		let redactor = Redactor::new(["leave_*", "medical"].into_iter());
+		assert!(redactor.is_sensitive("Leave_Reason"));
+		assert!(redactor.is_sensitive("medical"));
+		assert!(!redactor.is_sensitive("salary"));
+		assert!(!redactor.is_sensitive("medical_note"));
+	}
+}
@@ -0,0 +1,98 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::time::Duration;
+use sqlx::PgPool;
+use crate::logger::{admin_logger, LogType};
+use crate::outbox;
+use crate::push::{self, PushMessage};
+use crate::jobs;
+use crate::working_time::{self, WorkCalendar};
+
+// approvals pending for longer than this, in working hours of the approver, get a reminder. the same approval is reminded again after the same duration
+fn reminder_threshold() -> chrono::Duration {
+	let hours = std::env::var("REMINDER_THRESHOLD_HOURS")
+		.ok()
+		.and_then(|h| h.parse::<i64>().ok())
+		.unwrap_or(24);
+	return chrono::Duration::hours(hours);
+}
+
+fn scan_interval() -> Duration {
+	let secs = std::env::var("REMINDER_INTERVAL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(3600);
+	return Duration::from_secs(secs);
+}
+
+// returns a push per reminder. pushes share the collapse key of the approval request so they replace it on the phone
+pub async fn send_reminders(pool: &PgPool) -> Result<Vec<PushMessage>, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let threshold = reminder_threshold();
+	let mut tx = pool.begin().await?;
+
+	// working hours never add up faster than the clock, approvals waiting less than the threshold are left out early
+	let candidates: Vec<(i32, uuid::Uuid, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+		r#"select id, userid, coalesce(last_reminded_at, created_at) from user_active_tickets
+			where type_='approve' and active=true and userid is not null and coalesce(last_reminded_at, created_at) < $1
+			for update skip locked"#)
+		.bind(now - threshold)
+		.fetch_all(&mut *tx)
+		.await?;
+
+	let mut calendars: HashMap<uuid::Uuid, WorkCalendar> = HashMap::new();
+	let mut due = Vec::new();
+	for (id, userid, since) in candidates {
+		if let Entry::Vacant(entry) = calendars.entry(userid) {
+			entry.insert(working_time::calendar_of(&mut tx, userid, since.date_naive()).await?);
+		}
+		if calendars[&userid].between(since, now) >= threshold {
+			due.push(id);
+		}
+	}
+
+	let reminded: Vec<(uuid::Uuid, i32, i32)> = sqlx::query_as(
+		r#"with stale as (
+			update user_active_tickets set last_reminded_at=$1 where id=any($2)
+			returning userid, ticketid, node_number
+		), inserted as (
+			insert into notifications (userid, message, created_at, urgent)
+			select userid, 'Reminder: ticket ' || ticketid || ' is waiting for your approval', $1, true from stale
+		)
+		select userid, ticketid, node_number from stale"#)
+		.bind(now)
+		.bind(&due)
+		.fetch_all(&mut *tx)
+		.await?;
+	if !reminded.is_empty() {
+		outbox::enqueue(&mut tx).await?;
+	}
+	tx.commit().await?;
+
+	return Ok(reminded.into_iter()
+		.map(|(userid, ticket_id, node)| PushMessage {
+			userid,
+			title: "Approval reminder".to_string(),
+			body: format!("Reminder: ticket {} is waiting for your approval", ticket_id),
+			collapse_key: push::approval_collapse_key(ticket_id, node),
+			ticket_id
+		})
+		.collect());
+}
+
+pub async fn run_reminders(pool: PgPool) {
+	jobs::run_periodic(pool, "reminders", scan_interval(), |pool| async move {
+		match send_reminders(&pool).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Failed to send approval reminders: {}", e), None);
+				return Err(e.to_string());
+			}
+			Ok(pushes) if pushes.is_empty() => {}
+			Ok(pushes) => {
+				admin_logger(LogType::NotificationSuccess, &format!("Sent {} approval reminders", pushes.len()), None);
+				outbox::flush(&pool).await;
+				push::dispatch(&pool, pushes);
+			}
+		}
+		return Ok(());
+	}).await;
+}
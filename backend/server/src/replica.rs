@@ -0,0 +1,42 @@
+use once_cell::sync::OnceCell;
+use sqlx::PgPool;
+use crate::tenant;
+
+// pool of the read-only replica at DATABASE_READ_URL. reads go to the primary when it is not set
+static REPLICA: OnceCell<PgPool> = OnceCell::new();
+
+fn max_connections() -> u32 {
+	return std::env::var("DATABASE_READ_MAX_CONNECTIONS")
+		.ok()
+		.and_then(|s| s.parse::<u32>().ok())
+		.unwrap_or(5);
+}
+
+pub async fn connect() -> Result<(), String> {
+	let url = match std::env::var("DATABASE_READ_URL") {
+		Ok(url) if !url.trim().is_empty() => url,
+		_ => return Ok(())
+	};
+	let pool = tenant::pool_options()
+		.max_connections(max_connections())
+		.connect(&url)
+		.await
+		.map_err(|e| format!("Unable to connect to the read replica. e: {}", e))?;
+	let _ = REPLICA.set(pool);
+	return Ok(());
+}
+
+// for handlers that only read and can show data that is a little behind the primary. the replica
+// lags, so anything read to make a change has to come from the primary
+pub fn read_pool(primary: PgPool) -> PgPool {
+	return match REPLICA.get() {
+		Some(replica) => replica.clone(),
+		None => primary
+	};
+}
+
+pub async fn close() {
+	if let Some(replica) = REPLICA.get() {
+		replica.close().await;
+	}
+}
@@ -0,0 +1,90 @@
+use std::time::Duration;
+use axum::{extract, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use crate::jobs;
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ViewLogs};
+use utoipa::ToSchema;
+
+// the summaries the reports are read from, in the order they are refreshed
+pub const REPORT_VIEWS: [&str; 5] = [
+	"report_ticket_counts",
+	"report_approval_times",
+	"report_approver_decisions",
+	"report_callback_failures",
+	"report_open_tickets"
+];
+
+#[derive(Serialize, ToSchema)]
+pub struct Refreshed {
+	pub refreshed_at: chrono::DateTime<chrono::Utc>
+}
+
+// nightly by default
+fn refresh_interval() -> Duration {
+	let secs = std::env::var("REPORT_REFRESH_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(24 * 3600);
+	return Duration::from_secs(secs);
+}
+
+// rebuilds every summary. concurrently, so the reports keep reading the previous rows meanwhile.
+// has to run outside of a tenant, the views would only hold the rows of the tenant otherwise
+pub async fn refresh(pool: &PgPool) -> Result<chrono::DateTime<chrono::Utc>, sqlx::Error> {
+	let mut conn = pool.acquire().await?;
+	for view in REPORT_VIEWS {
+		sqlx::query(&format!("refresh materialized view concurrently {}", view))
+			.execute(&mut *conn)
+			.await?;
+	}
+	let now = chrono::Utc::now();
+	sqlx::query("update report_refreshes set refreshed_at=$1")
+		.bind(now)
+		.execute(&mut *conn)
+		.await?;
+	return Ok(now);
+}
+
+// how old the summaries are, reports return it with their figures
+pub async fn refreshed_at(conn: &mut PgConnection) -> Result<chrono::DateTime<chrono::Utc>, sqlx::Error> {
+	let refreshed: (chrono::DateTime<chrono::Utc>,) = sqlx::query_as("select refreshed_at from report_refreshes")
+		.fetch_one(conn)
+		.await?;
+	return Ok(refreshed.0);
+}
+
+pub async fn run_report_refresh(pool: PgPool) {
+	jobs::run_periodic(pool, "reports", refresh_interval(), |pool| async move {
+		match refresh(&pool).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Failed to refresh the reports: {}", e), None);
+				return Err(e.to_string());
+			}
+			Ok(_) => admin_logger(LogType::Info, "Refreshed the reports", None)
+		}
+		return Ok(());
+	}).await;
+}
+
+// refreshes the summaries now instead of waiting for the job
+#[utoipa::path(post, path = "/admin/reports/refresh", tag = "reports", responses(
+	(status = 200, description = "Refreshed", body = Refreshed),
+	(status = 403, description = "Missing the view_logs permission")
+))]
+pub async fn refresh_reports(
+	auth: Authorized<ViewLogs>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Refreshed>, StatusCode> {
+	// spawned tasks run outside of the tenant of the request
+	let refreshed = tokio::spawn(async move { refresh(&pool).await }).await
+		.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if let Err(e) = refreshed {
+		admin_logger(LogType::Error, &format!("Error refreshing the reports: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} refreshed the reports", auth.user.userid), None);
+	return Ok(Json(Refreshed { refreshed_at: refreshed.unwrap() }));
+}
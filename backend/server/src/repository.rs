@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use axum::async_trait;
+use sqlx::PgConnection;
+use erp_api_types::tickets::{AssignmentType, TicketStatus};
+use crate::db_types::Ticket;
+use crate::delegation;
+
+// the ticket row and the assignments of a ticket, as the handlers read them and the engine leaves them. the postgres
+// one works in the transaction of the handler, MemoryRepository lets updates, rejections and cancellations run in tests
+// without a database. what an update writes besides, like audit events, webhooks and notifications, goes to the connection
+#[async_trait]
+pub trait TicketRepository: Send {
+	async fn find(&mut self, ticket_id: i32) -> Result<Option<Ticket>, sqlx::Error>;
+	// the state, progress and status the engine left in the ticket
+	async fn save(&mut self, ticket: &Ticket) -> Result<(), sqlx::Error>;
+	// takes the node from the user holding it. false if they do not hold it
+	async fn release(&mut self, ticket_id: i32, userid: uuid::Uuid, node: i32, instance: Option<i32>) -> Result<bool, sqlx::Error>;
+	// takes the ticket from every user holding it. returns the users whose approval was pending
+	async fn deactivate(&mut self, ticket_id: i32) -> Result<Vec<uuid::Uuid>, sqlx::Error>;
+	async fn deactivate_signals(&mut self, ticket_id: i32) -> Result<(), sqlx::Error>;
+	async fn set_status(&mut self, ticket_id: i32, status: TicketStatus, at: chrono::DateTime<chrono::Utc>) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+pub trait UserRepository: Send {
+	async fn username(&mut self, userid: uuid::Uuid) -> Result<Option<String>, sqlx::Error>;
+	// see delegation::active_delegates
+	async fn active_delegates(&mut self, userids: &[uuid::Uuid]) -> Result<HashMap<uuid::Uuid, uuid::Uuid>, sqlx::Error>;
+}
+
+pub struct PgRepository<'c> {
+	conn: &'c mut PgConnection
+}
+
+impl<'c> PgRepository<'c> {
+	pub fn new(conn: &'c mut PgConnection) -> PgRepository<'c> {
+		return PgRepository { conn };
+	}
+}
+
+#[async_trait]
+impl TicketRepository for PgRepository<'_> {
+	async fn find(&mut self, ticket_id: i32) -> Result<Option<Ticket>, sqlx::Error> {
+		// the ticket is written by whoever reads it here
+		return sqlx::query_as("select * from tickets where id=$1 for update")
+			.bind(ticket_id)
+			.fetch_optional(&mut *self.conn)
+			.await;
+	}
+
+	async fn save(&mut self, ticket: &Ticket) -> Result<(), sqlx::Error> {
+		sqlx::query("update tickets set status=$1, complete=$2, updated_at=$3, state=$4, instances=$5 where id=$6")
+			.bind(ticket.status)
+			.bind(ticket.complete)
+			.bind(ticket.updated_at)
+			.bind(&ticket.state)
+			.bind(&ticket.instances)
+			.bind(ticket.id)
+			.execute(&mut *self.conn)
+			.await?;
+		return Ok(());
+	}
+
+	async fn release(&mut self, ticket_id: i32, userid: uuid::Uuid, node: i32, instance: Option<i32>) -> Result<bool, sqlx::Error> {
+		let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1 and userid=$2 and node_number=$3 and instance is not distinct from $4 and active=true")
+			.bind(ticket_id)
+			.bind(userid)
+			.bind(node)
+			.bind(instance)
+			.execute(&mut *self.conn)
+			.await?;
+		return Ok(query.rows_affected() > 0);
+	}
+
+	async fn deactivate(&mut self, ticket_id: i32) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+		let query: Vec<(uuid::Uuid, AssignmentType)> = sqlx::query_as("update user_active_tickets set active=false where ticketid=$1 and active=true returning userid, type_")
+			.bind(ticket_id)
+			.fetch_all(&mut *self.conn)
+			.await?;
+		return Ok(query.into_iter().filter(|(_, type_)| *type_ == AssignmentType::Approve).map(|(userid, _)| userid).collect());
+	}
+
+	async fn deactivate_signals(&mut self, ticket_id: i32) -> Result<(), sqlx::Error> {
+		sqlx::query("update ticket_signals set active=false where ticketid=$1")
+			.bind(ticket_id)
+			.execute(&mut *self.conn)
+			.await?;
+		return Ok(());
+	}
+
+	async fn set_status(&mut self, ticket_id: i32, status: TicketStatus, at: chrono::DateTime<chrono::Utc>) -> Result<(), sqlx::Error> {
+		sqlx::query("update tickets set status=$2, updated_at=$3 where id=$1")
+			.bind(ticket_id)
+			.bind(status)
+			.bind(at)
+			.execute(&mut *self.conn)
+			.await?;
+		return Ok(());
+	}
+}
+
+#[async_trait]
+impl UserRepository for PgRepository<'_> {
+	async fn username(&mut self, userid: uuid::Uuid) -> Result<Option<String>, sqlx::Error> {
+		let query: Option<(String,)> = sqlx::query_as("select username from users where userid=$1")
+			.bind(userid)
+			.fetch_optional(&mut *self.conn)
+			.await?;
+		return Ok(query.map(|q| q.0));
+	}
+
+	async fn active_delegates(&mut self, userids: &[uuid::Uuid]) -> Result<HashMap<uuid::Uuid, uuid::Uuid>, sqlx::Error> {
+		return delegation::active_delegates(&mut *self.conn, userids).await;
+	}
+}
+
+// a row of user_active_tickets
+#[cfg(test)]
+pub struct ActiveRow {
+	pub userid: uuid::Uuid,
+	pub ticket_id: i32,
+	pub node_number: i32,
+	pub instance: Option<i32>,
+	pub type_: AssignmentType,
+	pub active: bool
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryRepository {
+	pub tickets: HashMap<i32, Ticket>,
+	pub active: Vec<ActiveRow>,
+	// ticket and whether the signal is still awaited
+	pub signals: Vec<(i32, bool)>,
+	pub usernames: HashMap<uuid::Uuid, String>,
+	pub delegates: HashMap<uuid::Uuid, uuid::Uuid>
+}
+
+#[cfg(test)]
+#[async_trait]
+impl TicketRepository for MemoryRepository {
+	async fn find(&mut self, ticket_id: i32) -> Result<Option<Ticket>, sqlx::Error> {
+		return Ok(self.tickets.get(&ticket_id).cloned());
+	}
+
+	async fn save(&mut self, ticket: &Ticket) -> Result<(), sqlx::Error> {
+		self.tickets.insert(ticket.id, ticket.clone());
+		return Ok(());
+	}
+
+	async fn release(&mut self, ticket_id: i32, userid: uuid::Uuid, node: i32, instance: Option<i32>) -> Result<bool, sqlx::Error> {
+		let mut released = false;
+		for row in self.active.iter_mut().filter(|r| r.ticket_id == ticket_id && r.userid == userid && r.node_number == node && r.instance == instance && r.active) {
+			row.active = false;
+			released = true;
+		}
+		return Ok(released);
+	}
+
+	async fn deactivate(&mut self, ticket_id: i32) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+		let mut approvers = Vec::new();
+		for row in self.active.iter_mut().filter(|r| r.ticket_id == ticket_id && r.active) {
+			row.active = false;
+			if row.type_ == AssignmentType::Approve {
+				approvers.push(row.userid);
+			}
+		}
+		return Ok(approvers);
+	}
+
+	async fn deactivate_signals(&mut self, ticket_id: i32) -> Result<(), sqlx::Error> {
+		for signal in self.signals.iter_mut().filter(|s| s.0 == ticket_id) {
+			signal.1 = false;
+		}
+		return Ok(());
+	}
+
+	async fn set_status(&mut self, ticket_id: i32, status: TicketStatus, at: chrono::DateTime<chrono::Utc>) -> Result<(), sqlx::Error> {
+		if let Some(ticket) = self.tickets.get_mut(&ticket_id) {
+			ticket.status = status;
+			ticket.updated_at = at;
+		}
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+#[async_trait]
+impl UserRepository for MemoryRepository {
+	async fn username(&mut self, userid: uuid::Uuid) -> Result<Option<String>, sqlx::Error> {
+		return Ok(self.usernames.get(&userid).cloned());
+	}
+
+	async fn active_delegates(&mut self, userids: &[uuid::Uuid]) -> Result<HashMap<uuid::Uuid, uuid::Uuid>, sqlx::Error> {
+		return Ok(userids.iter().filter_map(|u| self.delegates.get(u).map(|d| (*u, *d))).collect());
+	}
+}
@@ -1,24 +1,134 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use axum::{http::StatusCode, extract, Json};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use crate::api_error::{db_error, ApiError};
+use crate::audit::{self, AuditAction, AuditEvent};
+use crate::auth::AuthUser;
+use crate::extractors::{Validate, ValidJson, ValidPath, ValidQuery};
 use crate::logger::{LogType, admin_logger};
+use crate::rbac::{self, Authorized, ManageRoles};
+use crate::schema::FieldError;
+use utoipa::{IntoParams, ToSchema};
 
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateRole {
 	role_: String
 }
 
 #[derive(sqlx::FromRow)]
 pub struct RoleDef {
-	id: i32,
 	role_: String, 
 }
 
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub struct Role {
+	pub id: i32,
+	pub role_: String
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateRole {
+	pub role_: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteRoleQuery {
+	// role that takes over the assignments and process references of the deleted one
+	pub replacement: Option<String>
+}
+
+#[derive(Serialize, sqlx::FromRow, ToSchema)]
+pub struct RoleReferences {
+	pub assignments: i64,
+	pub processes: i64
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AssignRole {
+	pub userid: uuid::Uuid,
+	pub role_: String
+}
+
+#[derive(Serialize, sqlx::FromRow, ToSchema)]
+pub struct RoleUser {
+	pub userid: uuid::Uuid,
+	pub username: String
+}
+
+fn role_name_errors(role: &str) -> Vec<FieldError> {
+	if role.trim().is_empty() {
+		return vec![FieldError { field: "/role_".to_string(), message: "Role name cannot be empty".to_string() }];
+	}
+	return Vec::new();
+}
+
+impl Validate for CreateRole {
+	fn validate(&self) -> Vec<FieldError> {
+		return role_name_errors(&self.role_);
+	}
+}
+
+impl Validate for UpdateRole {
+	fn validate(&self) -> Vec<FieldError> {
+		return role_name_errors(&self.role_);
+	}
+}
+
+impl Validate for AssignRole {
+	fn validate(&self) -> Vec<FieldError> {
+		return role_name_errors(&self.role_);
+	}
+}
+
+// user_is_admin checks this role by name so it cannot be renamed or deleted
+const ADMIN_ROLE: &str = "admin";
+
+fn role_not_found(id: i32) -> ApiError {
+	return ApiError::new(StatusCode::NOT_FOUND, "role_not_found", format!("Role {} does not exist", id));
+}
+
+async fn find_role(conn: &mut PgConnection, id: i32) -> Result<Option<Role>, sqlx::Error> {
+	return sqlx::query_as("select id, role_ from role_defs where id=$1")
+		.bind(id)
+		.fetch_optional(conn)
+		.await;
+}
+
+pub async fn role_references(conn: &mut PgConnection, role: &str) -> Result<RoleReferences, sqlx::Error> {
+	return sqlx::query_as(
+		r#"select (select count(*) from user_roles where role_=$1) as assignments,
+			(select count(*) from process_defs where $1=any(allowed_roles)) as processes"#)
+		.bind(role)
+		.fetch_one(conn)
+		.await;
+}
+
+// true if removing `role` from `userid` leaves nobody with every permission
+async fn is_last_superuser(conn: &mut PgConnection, userid: uuid::Uuid, role: &str) -> Result<bool, sqlx::Error> {
+	let others: (i64,) = sqlx::query_as(
+		r#"select count(distinct ur.userid) from user_roles ur join role_permissions rp on ur.tenant_id=rp.tenant_id and ur.role_=rp.role_ join users u on ur.userid=u.userid
+			where rp.permission='*' and u.active=true and not (ur.userid=$1 and ur.role_=$2)"#)
+		.bind(userid)
+		.bind(role)
+		.fetch_one(conn)
+		.await?;
+	return Ok(others.0 == 0);
+}
+
+#[utoipa::path(post, path = "/roles", tag = "roles", request_body = CreateRole, responses(
+	(status = 201, description = "Created"),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "Role exists", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid name", body = Problem, content_type = "application/problem+json"),
+	(status = 429, description = "Rate limited, see Retry-After")
+))]
 pub async fn create_role(
+	_auth: Authorized<ManageRoles>,
 	extract::State(pool) : extract::State<PgPool>,
-	Json(payload) : Json<CreateRole>
-) -> Result<StatusCode, StatusCode> {
+	ValidJson(payload) : ValidJson<CreateRole>
+) -> Result<StatusCode, ApiError> {
 
 	let role = payload.role_;
 	let insert_into_role = 
@@ -28,25 +138,29 @@ pub async fn create_role(
 		.await;
 
 	if let Err(e) = insert_into_role {
-		admin_logger(LogType::Error, &format!("Error insert into role_defs: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err(ApiError::new(StatusCode::CONFLICT, "role_exists", "The role exists already"));
+		}
+		admin_logger(LogType::Error, &format!("Error insert into role_defs: {}", e), None);
+		return Err(ApiError::internal());
 	}
 
 	return Ok(StatusCode::CREATED);
 }
 
+#[utoipa::path(get, path = "/roles", tag = "roles", responses(
+	(status = 200, description = "Names of every role", body = Vec<String>)
+))]
 pub async fn get_all_roles(
 	extract::State(pool) : extract::State<PgPool>
-) -> Result<(StatusCode, Json<Vec<String>>), StatusCode> {
+) -> Result<(StatusCode, Json<Vec<String>>), ApiError> {
 	let query : Result<Vec<RoleDef>, _> = sqlx::query_as("select * from role_defs")
 		.fetch_all(&pool)
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error in get_current_roles : {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		admin_logger(LogType::Error, &format!("Error in get_current_roles : {}", e), None);
+		return Err(ApiError::internal());
 	}
 	let query = query.unwrap()
 		.iter()
@@ -54,4 +168,366 @@ pub async fn get_all_roles(
 		.collect::<Vec<_>>();
 
 	return Ok((StatusCode::OK, Json(query)));
-}
\ No newline at end of file
+}
+#[utoipa::path(post, path = "/roles/assign", tag = "roles", request_body = AssignRole, responses(
+	(status = 200, description = "The user already has the role"),
+	(status = 201, description = "Assigned"),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown user or role", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid request", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn assign_role(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidJson(payload) : ValidJson<AssignRole>
+) -> Result<StatusCode, ApiError> {
+	// the foreign keys would catch these too, checked here for a readable error
+	let exists: Result<(bool, bool), _> = sqlx::query_as(
+		"select exists (select 1 from users where userid=$1), exists (select 1 from role_defs where role_=$2)")
+		.bind(payload.userid)
+		.bind(&payload.role_)
+		.fetch_one(&pool)
+		.await;
+
+	if let Err(e) = exists {
+		admin_logger(LogType::Error, &format!("Error checking role assignment of {} to {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+	match exists.unwrap() {
+		(false, _) => return Err(ApiError::new(StatusCode::NOT_FOUND, "user_not_found", format!("User {} does not exist", payload.userid))),
+		(_, false) => return Err(ApiError::new(StatusCode::NOT_FOUND, "role_not_found", format!("Role {} does not exist", payload.role_))),
+		_ => {}
+	}
+
+	let mut tx = pool.begin().await.map_err(db_error)?;
+
+	let query = sqlx::query("insert into user_roles (userid, role_) values ($1, $2) on conflict do nothing")
+		.bind(payload.userid)
+		.bind(&payload.role_)
+		.execute(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error assigning role {} to {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Ok(StatusCode::OK);
+	}
+
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::AssignRole, "user", payload.userid,
+		None, Some(serde_json::json!({"role_": payload.role_})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing assignment of role {} to {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting assignment of role {} to {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} assigned role {} to {}", auth.user.userid, payload.role_, payload.userid), None);
+	return Ok(StatusCode::CREATED);
+}
+
+#[utoipa::path(delete, path = "/roles/assign", tag = "roles", request_body = AssignRole, responses(
+	(status = 200, description = "Removed"),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "The user does not have the role", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "The last admin cannot lose the admin role", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid request", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn unassign_role(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidJson(payload) : ValidJson<AssignRole>
+) -> Result<StatusCode, ApiError> {
+	let mut tx = pool.begin().await.map_err(db_error)?;
+
+	match is_last_superuser(&mut tx, payload.userid, &payload.role_).await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking remaining admins: {}", e), None);
+			return Err(ApiError::internal());
+		}
+		Ok(true) => return Err(ApiError::new(StatusCode::CONFLICT, "last_superuser", "Nobody would be left with every permission")),
+		Ok(false) => {}
+	}
+
+	let query = sqlx::query("delete from user_roles where userid=$1 and role_=$2")
+		.bind(payload.userid)
+		.bind(&payload.role_)
+		.execute(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing role {} from {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(ApiError::new(StatusCode::NOT_FOUND, "role_not_assigned", format!("User {} does not have role {}", payload.userid, payload.role_)));
+	}
+
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::UnassignRole, "user", payload.userid,
+		Some(serde_json::json!({"role_": payload.role_})), None);
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing removal of role {} from {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting removal of role {} from {}: {}", payload.role_, payload.userid, e), None);
+		return Err(ApiError::internal());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} removed role {} from {}", auth.user.userid, payload.role_, payload.userid), None);
+	return Ok(StatusCode::OK);
+}
+
+// users can read their own roles, anything else needs manage_roles
+#[utoipa::path(get, path = "/users/{id}/roles", tag = "roles", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Roles of the user", body = Vec<String>),
+	(status = 403, description = "Roles of other users need the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid user id", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_user_roles(
+	user: AuthUser,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidPath(userid) : ValidPath<uuid::Uuid>
+) -> Result<Json<Vec<String>>, ApiError> {
+	let mut conn = pool.acquire().await.map_err(db_error)?;
+
+	if user.userid != userid {
+		match rbac::has_permission(&mut conn, user.userid, "manage_roles").await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking permissions of {}: {}", user.userid, e), None);
+				return Err(ApiError::internal());
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN.into()),
+			Ok(true) => {}
+		}
+	}
+
+	let query : Result<Vec<RoleDef>, _> = sqlx::query_as("select role_ from user_roles where userid=$1 order by role_")
+		.bind(userid)
+		.fetch_all(&mut *conn)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading roles of {}: {}", userid, e), None);
+		return Err(ApiError::internal());
+	}
+
+	return Ok(Json(query.unwrap().into_iter().map(|r| r.role_).collect()));
+}
+
+#[utoipa::path(get, path = "/roles/{role}/users", tag = "roles", params(("role" = String, Path, description = "Role name")), responses(
+	(status = 200, description = "Users holding the role", body = Vec<RoleUser>),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_role_users(
+	_auth: Authorized<ManageRoles>,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidPath(role) : ValidPath<String>
+) -> Result<Json<Vec<RoleUser>>, ApiError> {
+	let query : Result<Vec<RoleUser>, _> = sqlx::query_as(
+		"select u.userid, u.username from user_roles ur join users u on ur.userid=u.userid where ur.role_=$1 order by u.username")
+		.bind(&role)
+		.fetch_all(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading users of role {}: {}", role, e), None);
+		return Err(ApiError::internal());
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// renames a role. assignments and permissions follow through the foreign keys, process definitions are updated here
+#[utoipa::path(put, path = "/roles/{id}", tag = "roles", params(("id" = i32, Path, description = "Role id")), request_body = UpdateRole, responses(
+	(status = 200, description = "Renamed everywhere the role is used", body = Role),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown role", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "Role exists", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid name", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn update_role(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidPath(id) : ValidPath<i32>,
+	ValidJson(payload) : ValidJson<UpdateRole>
+) -> Result<Json<Role>, ApiError> {
+	let new_name = payload.role_.trim().to_string();
+
+	let mut tx = pool.begin().await.map_err(db_error)?;
+
+	let role = find_role(&mut tx, id).await;
+	if let Err(e) = role {
+		admin_logger(LogType::Error, &format!("Error reading role {}: {}", id, e), None);
+		return Err(ApiError::internal());
+	}
+	let role = role.unwrap().ok_or_else(|| role_not_found(id))?;
+	if role.role_ == ADMIN_ROLE {
+		return Err(ApiError::new(StatusCode::CONFLICT, "admin_role", "The admin role cannot be renamed"));
+	}
+
+	let query = sqlx::query("update role_defs set role_=$2 where id=$1")
+		.bind(id)
+		.bind(&new_name)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err(ApiError::new(StatusCode::CONFLICT, "role_exists", format!("Role {} already exists", new_name)));
+		}
+		admin_logger(LogType::Error, &format!("Error renaming role {} to {}: {}", role.role_, new_name, e), None);
+		return Err(ApiError::internal());
+	}
+
+	let query = sqlx::query("update process_defs set allowed_roles=array_replace(allowed_roles, $1, $2) where $1=any(allowed_roles)")
+		.bind(&role.role_)
+		.bind(&new_name)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error renaming role {} in process definitions: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::RenameRole, "role", id,
+		Some(serde_json::json!({"role_": role.role_})), Some(serde_json::json!({"role_": new_name})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing rename of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting rename of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} renamed role {} to {}", auth.user.userid, role.role_, new_name), None);
+	return Ok(Json(Role { id, role_: new_name }));
+}
+
+// a role that is still assigned or used by a process can only be deleted with a replacement,
+// which takes over both in the same transaction
+#[utoipa::path(delete, path = "/roles/{id}", tag = "roles", params(("id" = i32, Path, description = "Role id"), DeleteRoleQuery), responses(
+	(status = 200, description = "Deleted"),
+	(status = 403, description = "Missing the manage_roles permission", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown role", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "The role is still used and no replacement was given", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid replacement", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn delete_role(
+	auth: Authorized<ManageRoles>,
+	extract::State(pool) : extract::State<PgPool>,
+	ValidPath(id) : ValidPath<i32>,
+	ValidQuery(options) : ValidQuery<DeleteRoleQuery>
+) -> Result<StatusCode, ApiError> {
+	let mut tx = pool.begin().await.map_err(db_error)?;
+
+	let role = find_role(&mut tx, id).await;
+	if let Err(e) = role {
+		admin_logger(LogType::Error, &format!("Error reading role {}: {}", id, e), None);
+		return Err(ApiError::internal());
+	}
+	let role = role.unwrap().ok_or_else(|| role_not_found(id))?;
+	if role.role_ == ADMIN_ROLE {
+		return Err(ApiError::new(StatusCode::CONFLICT, "admin_role", "The admin role cannot be deleted"));
+	}
+
+	let references = role_references(&mut tx, &role.role_).await;
+	if let Err(e) = references {
+		admin_logger(LogType::Error, &format!("Error reading references of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+	let references = references.unwrap();
+	let referenced = references.assignments > 0 || references.processes > 0;
+
+	match (&options.replacement, referenced) {
+		(None, true) => {
+			return Err(ApiError::new(StatusCode::CONFLICT, "role_in_use", format!(
+				"Role {} is assigned to {} users and used by {} processes, a replacement role is required",
+				role.role_, references.assignments, references.processes)));
+		}
+		(Some(replacement), _) if *replacement == role.role_ => {
+			return Err(ApiError::field(StatusCode::UNPROCESSABLE_ENTITY, "invalid_data", "/replacement", "A role cannot replace itself"));
+		}
+		(Some(replacement), true) => {
+			let exists = sqlx::query("select id from role_defs where role_=$1")
+				.bind(replacement)
+				.fetch_optional(&mut *tx)
+				.await;
+			if let Err(e) = exists {
+				admin_logger(LogType::Error, &format!("Error reading role {}: {}", replacement, e), None);
+				return Err(ApiError::internal());
+			}
+			if exists.unwrap().is_none() {
+				return Err(ApiError::new(StatusCode::NOT_FOUND, "role_not_found", format!("Replacement role {} does not exist", replacement)));
+			}
+
+			let query = sqlx::query(
+				r#"insert into user_roles (userid, role_) select userid, $2 from user_roles where role_=$1 on conflict do nothing"#)
+				.bind(&role.role_)
+				.bind(replacement)
+				.execute(&mut *tx)
+				.await;
+			if let Err(e) = query {
+				admin_logger(LogType::Error, &format!("Error moving assignments of role {} to {}: {}", role.role_, replacement, e), None);
+				return Err(ApiError::internal());
+			}
+
+			// processes that already allow the replacement just drop the old role
+			let query = sqlx::query(
+				r#"update process_defs set allowed_roles=case when $2=any(allowed_roles) then array_remove(allowed_roles, $1)
+					else array_replace(allowed_roles, $1, $2) end
+					where $1=any(allowed_roles)"#)
+				.bind(&role.role_)
+				.bind(replacement)
+				.execute(&mut *tx)
+				.await;
+			if let Err(e) = query {
+				admin_logger(LogType::Error, &format!("Error moving process references of role {} to {}: {}", role.role_, replacement, e), None);
+				return Err(ApiError::internal());
+			}
+		}
+		_ => {}
+	}
+
+	let query = sqlx::query("delete from user_roles where role_=$1")
+		.bind(&role.role_)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing assignments of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	// permissions cascade
+	let query = sqlx::query("delete from role_defs where id=$1")
+		.bind(id)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	let event = AuditEvent::new(Some(auth.user.userid), AuditAction::DeleteRole, "role", id,
+		Some(serde_json::json!({"role_": role.role_, "assignments": references.assignments, "processes": references.processes})),
+		options.replacement.as_ref().map(|r| serde_json::json!({"replacement": r})));
+	if let Err(e) = audit::record(&mut tx, event).await {
+		admin_logger(LogType::Error, &format!("Error auditing deletion of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting deletion of role {}: {}", role.role_, e), None);
+		return Err(ApiError::internal());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted role {}, replacement: {:?}", auth.user.userid, role.role_, options.replacement), None);
+	return Ok(StatusCode::OK);
+}
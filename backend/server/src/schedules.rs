@@ -0,0 +1,235 @@
+use std::str::FromStr;
+use std::time::Duration;
+use axum::{extract, http::StatusCode, Json};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgPool};
+use crate::auth::AuthUser;
+use crate::logger::{admin_logger, LogType};
+use crate::process::read_process_data;
+use crate::ticket::{self, CreateTicket};
+use crate::jobs;
+use crate::tenant;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateSchedule {
+	pub process_id: String,
+	// cron expression with a seconds field, e.g. "0 0 9 1 * *" for 9:00 UTC on the first of every month
+	pub cron: String,
+	// initial state of every ticket created from this schedule
+	#[schema(value_type = Option<Object>)]
+	pub state: Option<Map<String, Value>>,
+	pub is_public: Option<bool>,
+	pub priority: Option<i32>
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+pub struct TicketSchedule {
+	pub id: i32,
+	pub process_id: String,
+	pub owner_id: uuid::Uuid,
+	pub cron: String,
+	#[schema(value_type = Object)]
+	pub state: Value,
+	pub is_public: bool,
+	pub priority: i32,
+	pub active: bool,
+	pub next_run_at: chrono::DateTime<chrono::Utc>,
+	pub last_run_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(FromRow)]
+struct DueSchedule {
+	id: i32,
+	process_id: String,
+	owner_id: uuid::Uuid,
+	owner_name: String,
+	cron: String,
+	state: Value,
+	is_public: bool,
+	priority: i32,
+	tenant_id: i32
+}
+
+fn scan_interval() -> Duration {
+	let secs = std::env::var("SCHEDULER_INTERVAL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(60);
+	return Duration::from_secs(secs);
+}
+
+// next time the expression fires strictly after `after`
+pub fn next_run(cron: &str, after: &chrono::DateTime<chrono::Utc>) -> Result<chrono::DateTime<chrono::Utc>, String> {
+	let schedule = Schedule::from_str(cron).map_err(|e| e.to_string())?;
+	return schedule.after(after).next().ok_or("Expression never fires".to_string());
+}
+
+// tickets of the schedule are created in the name of the user of the token
+#[utoipa::path(post, path = "/schedules", tag = "schedules", request_body = CreateSchedule, responses(
+	(status = 201, description = "Tickets of the process are created on the schedule, owned by the user", body = TicketSchedule),
+	(status = 404, description = "Unknown process", body = String),
+	(status = 422, description = "Invalid cron expression", body = String)
+))]
+pub async fn create_schedule(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateSchedule>
+) -> Result<(StatusCode, Json<TicketSchedule>), (StatusCode, String)> {
+	let next_run_at = next_run(&payload.cron, &chrono::Utc::now())
+		.map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid cron expression: {}", e)))?;
+
+	if read_process_data(payload.process_id.clone()).await.is_err() {
+		return Err((StatusCode::NOT_FOUND, format!("Process {} does not exist", payload.process_id)));
+	}
+
+	let query: Result<TicketSchedule, _> = sqlx::query_as(
+		r#"insert into ticket_schedules (process_id, owner_id, cron, state, is_public, priority, next_run_at, created_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8) returning id, process_id, owner_id, cron, state, is_public, priority, active, next_run_at, last_run_at"#)
+		.bind(&payload.process_id)
+		.bind(user.userid)
+		.bind(&payload.cron)
+		.bind(Value::Object(payload.state.unwrap_or_default()))
+		.bind(payload.is_public.unwrap_or(false))
+		.bind(payload.priority.unwrap_or(0))
+		.bind(next_run_at)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting schedule for process {} from {}: {}", payload.process_id, user.userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	return Ok((StatusCode::CREATED, Json(query.unwrap())));
+}
+
+#[utoipa::path(get, path = "/schedules", tag = "schedules", responses(
+	(status = 200, description = "Schedules of the user", body = Vec<TicketSchedule>)
+))]
+pub async fn get_schedules(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<TicketSchedule>>, StatusCode> {
+	let result: Result<Vec<TicketSchedule>, _> = sqlx::query_as(
+		r#"select id, process_id, owner_id, cron, state, is_public, priority, active, next_run_at, last_run_at
+			from ticket_schedules where owner_id=$1 order by id"#)
+		.bind(user.userid)
+		.fetch_all(&pool)
+		.await;
+
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error reading schedules of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(result.unwrap()));
+}
+
+#[utoipa::path(delete, path = "/schedules/{id}", tag = "schedules", params(("id" = i32, Path, description = "Schedule id")), responses(
+	(status = 200, description = "Deactivated"),
+	(status = 404, description = "Unknown schedule or not the own")
+))]
+pub async fn delete_schedule(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(schedule_id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	// schedules are deactivated instead of deleted so the tickets they created can be traced back
+	let result = sqlx::query("update ticket_schedules set active=false where id=$1 and owner_id=$2")
+		.bind(schedule_id)
+		.bind(user.userid)
+		.execute(&pool)
+		.await;
+
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error deactivating schedule {}: {}", schedule_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if result.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	return Ok(StatusCode::OK);
+}
+
+// claims every due schedule, moves it to its next run and creates the tickets.
+// the schedule is advanced before the ticket is created so a failure skips a run instead of creating duplicates
+pub async fn run_due_schedules(pool: &PgPool) -> Result<usize, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	let due: Vec<DueSchedule> = sqlx::query_as(
+		r#"select s.id, s.process_id, s.owner_id, u.username as owner_name, s.cron, s.state, s.is_public, s.priority, s.tenant_id
+			from ticket_schedules s join users u on s.owner_id=u.userid
+			where s.active=true and s.next_run_at<=$1 for update of s skip locked"#)
+		.bind(now)
+		.fetch_all(&mut *tx)
+		.await?;
+
+	for schedule in due.iter() {
+		// a schedule whose expression no longer parses is switched off
+		let next = next_run(&schedule.cron, &now).ok();
+		sqlx::query("update ticket_schedules set next_run_at=coalesce($2, next_run_at), active=($2 is not null), last_run_at=$3 where id=$1")
+			.bind(schedule.id)
+			.bind(next)
+			.bind(now)
+			.execute(&mut *tx)
+			.await?;
+	}
+	tx.commit().await?;
+
+	for schedule in due.iter() {
+		let payload = CreateTicket {
+			process_id: schedule.process_id.clone(),
+			owner_id: schedule.owner_id,
+			owner_name: schedule.owner_name.clone(),
+			is_public: schedule.is_public,
+			priority: Some(schedule.priority),
+			due_at: None,
+			tags: None,
+			draft: false,
+			data: serde_json::value::from_value(schedule.state.clone()).ok()
+		};
+		// the ticket is created in the tenant of the schedule, as if its owner had started it
+		match tenant::scoped(Some(schedule.tenant_id), ticket::new_ticket(pool, payload)).await {
+			Ok(ticket) => {
+				admin_logger(LogType::Info, &format!("Schedule {} created ticket {}", schedule.id, ticket.id), None);
+			}
+			Err(status) => {
+				admin_logger(LogType::Error, &format!("Schedule {} failed to create a ticket for process {}: {}", schedule.id, schedule.process_id, status), None);
+			}
+		}
+	}
+
+	return Ok(due.len());
+}
+
+pub async fn run_scheduler(pool: PgPool) {
+	jobs::run_periodic(pool, "schedules", scan_interval(), |pool| async move {
+		if let Err(e) = run_due_schedules(&pool).await {
+			admin_logger(LogType::Error, &format!("Failed to run ticket schedules: {}", e), None);
+			return Err(e.to_string());
+		}
+		return Ok(());
+	}).await;
+}
+
+#[cfg(test)]
+mod schedules_tests {
+	use chrono::TimeZone;
+	use super::next_run;
+
+	#[test]
+	fn next_run_follows_cron_expression() {
+		let after = chrono::Utc.with_ymd_and_hms(2024, 5, 15, 12, 0, 0).unwrap();
+		// 9:00 on the first of every month
+		let next = next_run("0 0 9 1 * *", &after).unwrap();
+		assert_eq!(next, chrono::Utc.with_ymd_and_hms(2024, 6, 1, 9, 0, 0).unwrap());
+
+		assert!(next_run("every monday", &after).is_err());
+	}
+}
@@ -0,0 +1,94 @@
+use axum::{http::StatusCode, Json};
+use jsonschema::JSONSchema;
+use serde_json::{Map, Value};
+
+pub use erp_api_types::{FieldError, FieldErrors};
+
+// the errors of the handlers that answer with field errors
+pub fn internal_error() -> (StatusCode, Json<FieldErrors>) {
+	return (StatusCode::INTERNAL_SERVER_ERROR, Json(FieldErrors { errors: Vec::new() }));
+}
+
+pub fn status_error(status: StatusCode) -> (StatusCode, Json<FieldErrors>) {
+	return (status, Json(FieldErrors { errors: Vec::new() }));
+}
+
+pub fn field_error(status: StatusCode, field: &str, message: String) -> (StatusCode, Json<FieldErrors>) {
+	return (status, Json(FieldErrors { errors: vec![FieldError { field: field.to_string(), message }] }));
+}
+
+pub fn unprocessable(field: &str, message: String) -> (StatusCode, Json<FieldErrors>) {
+	return field_error(StatusCode::UNPROCESSABLE_ENTITY, field, message);
+}
+
+// only checks that the schema itself is valid. used when a process is created so a broken schema
+// is rejected up front instead of failing every update on that node
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+	return JSONSchema::compile(schema)
+		.map(|_| ())
+		.map_err(|e| e.to_string());
+}
+
+pub fn validate_node_data(schema: &Value, data: &Map<String, Value>) -> Result<(), Vec<FieldError>> {
+	let compiled = JSONSchema::compile(schema);
+	if let Err(e) = compiled {
+		return Err(vec![FieldError {
+			field: String::new(),
+			message: format!("Invalid schema for node: {}", e)
+		}]);
+	}
+	let compiled = compiled.unwrap();
+
+	let instance = Value::Object(data.clone());
+	if let Err(errors) = compiled.validate(&instance) {
+		let errors = errors
+			.map(|e| FieldError {
+				field: e.instance_path.to_string(),
+				message: e.to_string()
+			})
+			.collect::<Vec<_>>();
+		return Err(errors);
+	}
+
+	return Ok(());
+}
+
+#[cfg(test)]
+mod schema_tests {
+	use serde_json::{json, Map, Value};
+	use super::*;
+
+	fn as_map(v: Value) -> Map<String, Value> {
+		return serde_json::value::from_value(v).unwrap();
+	}
+
+	#[test]
+	fn valid_data_passes() {
+		let schema = json!({
+			"type": "object",
+			"properties": { "amount": { "type": "number" } },
+			"required": ["amount"]
+		});
+		let data = as_map(json!({ "amount": 120 }));
+		assert!(validate_node_data(&schema, &data).is_ok());
+	}
+
+	#[test]
+	fn invalid_data_returns_field_errors() {
+		let schema = json!({
+			"type": "object",
+			"properties": { "amount": { "type": "number" } },
+			"required": ["amount", "reason"]
+		});
+		let data = as_map(json!({ "amount": "lots" }));
+		let errors = validate_node_data(&schema, &data).unwrap_err();
+		assert_eq!(errors.len(), 2, "one type error and one missing field error expected");
+		assert!(errors.iter().any(|e| e.field == "/amount"), "type error should point at the amount field");
+	}
+
+	#[test]
+	fn broken_schema_is_rejected() {
+		let schema = json!({ "type": "not_a_type" });
+		assert!(check_schema(&schema).is_err());
+	}
+}
@@ -0,0 +1,114 @@
+use rhai::{Dynamic, Engine, Scope};
+use serde_json::Value;
+
+// keeps a broken script (infinite loop etc) from blocking the request forever
+static MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+// and from running the server out of memory or stack, e.g. by doubling a string in a loop
+static MAX_STRING_SIZE: usize = 1024 * 1024;
+static MAX_ARRAY_SIZE: usize = 10_000;
+static MAX_MAP_SIZE: usize = 10_000;
+static MAX_CALL_LEVELS: usize = 32;
+static MAX_EXPR_DEPTH: usize = 64;
+
+fn engine() -> Engine {
+	let mut engine = Engine::new();
+	engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+	engine.set_max_string_size(MAX_STRING_SIZE);
+	engine.set_max_array_size(MAX_ARRAY_SIZE);
+	engine.set_max_map_size(MAX_MAP_SIZE);
+	engine.set_max_call_levels(MAX_CALL_LEVELS);
+	engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+	return engine;
+}
+
+// Runs a rhai script against the ticket state. The state is available to the script as the `state`
+// object map and whatever the script leaves in `state` becomes the new ticket state.
+pub fn run_script(script: &str, state: &Value) -> Result<Value, String> {
+	let engine = engine();
+
+	let state = rhai::serde::to_dynamic(state).map_err(|e| e.to_string())?;
+	let mut scope = Scope::new();
+	scope.push_dynamic("state", state);
+
+	engine.run_with_scope(&mut scope, script).map_err(|e| e.to_string())?;
+
+	let new_state = scope.get_value::<Dynamic>("state");
+	if new_state.is_none() {
+		return Err("script removed the state variable".to_string());
+	}
+	let new_state = rhai::serde::from_dynamic::<Value>(&new_state.unwrap()).map_err(|e| e.to_string())?;
+	if !new_state.is_object() {
+		return Err("state must remain an object".to_string());
+	}
+
+	return Ok(new_state);
+}
+
+// Evaluates a boolean rule like `amount < 500` against the ticket state.
+// top level state fields are available as variables, the whole state as `state`
+pub fn eval_condition(expr: &str, state: &Value) -> Result<bool, String> {
+	let engine = engine();
+
+	let mut scope = Scope::new();
+	if let Some(fields) = state.as_object() {
+		for (key, value) in fields {
+			let value = rhai::serde::to_dynamic(value).map_err(|e| e.to_string())?;
+			scope.push_dynamic(key.as_str(), value);
+		}
+	}
+	let state = rhai::serde::to_dynamic(state).map_err(|e| e.to_string())?;
+	scope.push_dynamic("state", state);
+
+	return engine.eval_expression_with_scope::<bool>(&mut scope, expr).map_err(|e| e.to_string());
+}
+
+#[cfg(test)]
+mod script_tests {
+	use serde_json::json;
+	use super::{eval_condition, run_script};
+
+	#[test]
+	fn computes_total_from_line_items() {
+		let state = json!({
+			"items": [ { "price": 10, "qty": 2 }, { "price": 5, "qty": 1 } ]
+		});
+		let script = r#"
+			let total = 0;
+			for item in state.items {
+				total += item.price * item.qty;
+			}
+			state.total = total;
+		"#;
+		let result = run_script(script, &state).unwrap();
+		assert_eq!(result["total"], json!(25));
+		assert_eq!(result["items"], state["items"], "existing fields should be kept");
+	}
+
+	#[test]
+	fn condition_reads_state_fields() {
+		let state = json!({ "amount": 120, "dept": "it" });
+		assert_eq!(eval_condition("amount < 500", &state), Ok(true));
+		assert_eq!(eval_condition("amount < 500 && dept == \"hr\"", &state), Ok(false));
+		assert!(eval_condition("missing > 5", &state).is_err(), "unknown fields should not silently match");
+	}
+
+	#[test]
+	fn runaway_script_is_stopped() {
+		let state = json!({});
+		assert!(run_script("loop { }", &state).is_err());
+	}
+
+	#[test]
+	fn oversized_string_is_rejected() {
+		let state = json!({});
+		let result = run_script(r#"state.s = "x"; loop { state.s += state.s; }"#, &state);
+		assert!(result.unwrap_err().contains("Length of string"));
+		assert!(eval_condition(r#""x".pad(2000000, "x") == "x""#, &state).unwrap_err().contains("Length of string"));
+	}
+
+	#[test]
+	fn state_must_stay_an_object() {
+		let state = json!({});
+		assert!(run_script("state = 5;", &state).is_err());
+	}
+}
@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tokio::sync::{watch, Notify};
+use tokio::time::Interval;
+
+static STOP: Lazy<watch::Sender<bool>> = Lazy::new(|| {
+	return watch::channel(false).0;
+});
+// background tasks started with spawn that have not finished yet
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: Lazy<Notify> = Lazy::new(Notify::new);
+
+// how long background work gets to finish once the server stopped taking requests
+pub fn drain_timeout() -> Duration {
+	let secs = std::env::var("SHUTDOWN_TIMEOUT_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(30);
+	return Duration::from_secs(secs);
+}
+
+struct Running;
+
+impl Drop for Running {
+	// also runs when the task panics
+	fn drop(&mut self) {
+		if RUNNING.fetch_sub(1, Ordering::SeqCst) == 1 {
+			FINISHED.notify_waiters();
+		}
+	}
+}
+
+pub fn stop() {
+	STOP.send_replace(true);
+}
+
+pub fn is_stopping() -> bool {
+	return *STOP.borrow();
+}
+
+// resolves once shutdown has started
+pub async fn stopped() {
+	let mut receiver = STOP.subscribe();
+	let _ = receiver.wait_for(|stop| *stop).await;
+}
+
+// waits for SIGINT or SIGTERM and starts the shutdown
+pub async fn signal() {
+	let interrupt = tokio::signal::ctrl_c();
+	#[cfg(unix)]
+	let terminate = async {
+		let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("Unable to listen for SIGTERM");
+		terminate.recv().await;
+	};
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = interrupt => {}
+		_ = terminate => {}
+	}
+	stop();
+}
+
+// the next tick of a worker's interval. false once shutdown has started, so the work of a tick
+// is always finished before the worker returns
+pub async fn tick(interval: &mut Interval) -> bool {
+	tokio::select! {
+		_ = interval.tick() => return !is_stopping(),
+		_ = stopped() => return false
+	}
+}
+
+// background work that drain waits for
+pub fn spawn<F>(task: F)
+where
+	F: Future<Output = ()> + Send + 'static
+{
+	RUNNING.fetch_add(1, Ordering::SeqCst);
+	let running = Running;
+	tokio::spawn(async move {
+		let _running = running;
+		task.await;
+	});
+}
+
+// false if tasks were still running at the timeout
+pub async fn drain(timeout: Duration) -> bool {
+	let drained = tokio::time::timeout(timeout, async {
+		loop {
+			// created before the check so a task finishing in between is not missed
+			let finished = FINISHED.notified();
+			if RUNNING.load(Ordering::SeqCst) == 0 {
+				return;
+			}
+			finished.await;
+		}
+	}).await;
+	return drained.is_ok();
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+	use std::time::Duration;
+	use super::{drain, is_stopping, spawn, stop, tick};
+
+	#[tokio::test]
+	async fn workers_finish_their_tick_before_the_drain_ends() {
+		let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+		spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_millis(5));
+			while tick(&mut interval).await {
+				// a tick that is running when the shutdown starts is completed
+				tokio::time::sleep(Duration::from_millis(20)).await;
+				let _ = sender.send(());
+			}
+		});
+		receiver.recv().await.unwrap();
+
+		stop();
+		assert!(is_stopping());
+		assert!(drain(Duration::from_secs(5)).await);
+
+		// the worker is gone and took its sender with it
+		while receiver.try_recv().is_ok() {}
+		assert!(receiver.recv().await.is_none());
+
+		spawn(std::future::pending());
+		assert!(!drain(Duration::from_millis(20)).await);
+	}
+}
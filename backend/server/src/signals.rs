@@ -0,0 +1,95 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgPool};
+use crate::{api_keys::ApiKey, db_types::Ticket, logger::{admin_logger, log, LogType}, process::read_process_data, ticket::{self, Event, UpdateErr, UpdateSource, UpdateTicket}};
+
+#[derive(Serialize, Deserialize, FromRow)]
+struct SignalNode {
+	node_number: i32
+}
+
+#[utoipa::path(post, path = "/ticket/{id}/signal/{signal_name}", tag = "service", security(("api_key" = [])), params(("id" = i32, Path, description = "Ticket id"), ("signal_name" = String, Path, description = "Name the Wait node listens to")), request_body(content = Option<Object>, description = "Node data of the Wait node"), responses(
+	(status = 200, description = "The Wait node is completed"),
+	(status = 403, description = "The key lacks the signal scope"),
+	(status = 404, description = "Unknown ticket or no node waits for the signal"),
+	(status = 409, description = "The ticket is not waiting for the signal"),
+	(status = 422, description = "Node data does not match the schema of the node", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn signal_ticket(
+	key: ApiKey,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path((ticket_id, signal_name)): extract::Path<(i32, String)>,
+	data: Option<Json<Map<String, Value>>>
+) -> Result<StatusCode, UpdateErr> {
+	/*
+		1. Verify the api key of the external system has the signal scope
+		2. Find the wait nodes of the process that listen to this signal
+		3. Find the pending signal row of one of those nodes
+		4. Complete the node through the normal update path, which claims the row so a signal is only consumed once
+	*/
+	key.require("signal")?;
+
+	let query: Result<Ticket, _> = sqlx::query_as("select * from tickets where id=$1")
+		.bind(ticket_id)
+		.fetch_one(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} for signal {}: {}", ticket_id, signal_name, e), None);
+		return Err(StatusCode::NOT_FOUND.into());
+	}
+	let ticket = query.unwrap();
+
+	let process_data = read_process_data(ticket.process_id.clone()).await;
+	if let Err(e) = process_data {
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let process_data = process_data.unwrap();
+
+	let wait_nodes = process_data.steps.iter()
+		.enumerate()
+		.filter(|(_, step)| step.event == Event::Wait)
+		.filter(|(_, step)| step.args.as_ref().and_then(|a| a.first()) == Some(&signal_name))
+		.map(|(i, _)| i as i32)
+		.collect::<Vec<_>>();
+
+	if wait_nodes.is_empty() {
+		log(LogType::Error, format!("Ticket {} has no node waiting for signal {}", ticket.id, signal_name), ticket.log_id);
+		return Err(StatusCode::NOT_FOUND.into());
+	}
+
+	// only one pending row is claimed even if multiple nodes wait for the same signal
+	let pending: Result<Option<SignalNode>, _> = sqlx::query_as(
+		"select node_number from ticket_signals where ticketid=$1 and node_number=any($2) and active=true order by id limit 1")
+		.bind(ticket.id)
+		.bind(&wait_nodes)
+		.fetch_optional(&pool)
+		.await;
+
+	if let Err(e) = pending {
+		log(LogType::Error, format!("Error reading pending signal {} for ticket {}: {}", signal_name, ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let pending = pending.unwrap();
+	if pending.is_none() {
+		// the ticket has not reached the wait node yet or the signal was already received
+		log(LogType::Error, format!("Signal {} for ticket {} is not pending", signal_name, ticket.id), ticket.log_id);
+		return Err(StatusCode::CONFLICT.into());
+	}
+	let node = pending.unwrap().node_number;
+
+	let request = UpdateTicket {
+		ticket_id: ticket.id,
+		user_id: ticket.owner_id,
+		status: true,
+		node,
+		data: data.map(|d| d.0),
+		instance: None,
+		reason: None
+	};
+
+	// the row is claimed by the update, in its transaction
+	return ticket::apply_update(&pool, request, UpdateSource::Signal).await;
+}
@@ -0,0 +1,132 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use crate::{db_types::Ticket, logger::{admin_logger, log, LogType}, rbac};
+use crate::api_error::db_status;
+use crate::auth::AuthUser;
+use utoipa::ToSchema;
+
+const MAX_TAG_LENGTH: usize = 64;
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTags {
+	#[serde(default)]
+	pub add: Vec<String>,
+	#[serde(default)]
+	pub remove: Vec<String>
+}
+
+// tags are case insensitive. returns the offending tag if one is empty or too long
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+	let mut result = Vec::new();
+	for tag in tags {
+		let normalized = tag.trim().to_lowercase();
+		if normalized.is_empty() || normalized.len() > MAX_TAG_LENGTH {
+			return Err(tag.clone());
+		}
+		if !result.contains(&normalized) {
+			result.push(normalized);
+		}
+	}
+	return Ok(result);
+}
+
+pub async fn add_tags(conn: &mut PgConnection, ticket_id: i32, tags: &[String]) -> Result<(), sqlx::Error> {
+	sqlx::query("insert into ticket_tags (ticketid, tag) select $1, unnest($2::varchar[]) on conflict do nothing")
+		.bind(ticket_id)
+		.bind(tags)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+pub async fn get_tags(conn: &mut PgConnection, ticket_id: i32) -> Result<Vec<String>, sqlx::Error> {
+	let tags: Vec<(String,)> = sqlx::query_as("select tag from ticket_tags where ticketid=$1 order by tag")
+		.bind(ticket_id)
+		.fetch_all(conn)
+		.await?;
+	return Ok(tags.into_iter().map(|t| t.0).collect());
+}
+
+#[utoipa::path(post, path = "/ticket/{id}/tags", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), request_body = UpdateTags, responses(
+	(status = 200, description = "Tags of the ticket now", body = Vec<String>),
+	(status = 400, description = "Invalid tag"),
+	(status = 403, description = "Only the owner and users with manage_processes can tag a ticket"),
+	(status = 404, description = "Unknown ticket")
+))]
+pub async fn update_tags(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>,
+	Json(payload): Json<UpdateTags>
+) -> Result<Json<Vec<String>>, StatusCode> {
+	let add = normalize_tags(&payload.add).map_err(|_| StatusCode::BAD_REQUEST)?;
+	let remove = normalize_tags(&payload.remove).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+	let mut tx = pool.begin().await.map_err(db_status)?;
+
+	let query: Result<Option<Ticket>, _> = sqlx::query_as("select * from tickets where id=$1")
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} in update_tags: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let ticket = query.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	// only the owner and users managing processes can label a ticket
+	if ticket.owner_id != user.userid {
+		match rbac::has_permission(&mut tx, user.userid, "manage_processes").await {
+			Err(e) => {
+				log(LogType::Error, format!("Error checking permissions of {}: {}", user.userid, e), ticket.log_id);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			}
+			Ok(false) => return Err(StatusCode::FORBIDDEN),
+			Ok(true) => {}
+		}
+	}
+
+	let query = sqlx::query("delete from ticket_tags where ticketid=$1 and tag=any($2)")
+		.bind(ticket.id)
+		.bind(&remove)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		log(LogType::Error, format!("Error removing tags of ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if let Err(e) = add_tags(&mut tx, ticket.id, &add).await {
+		log(LogType::Error, format!("Error adding tags to ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let tags = get_tags(&mut tx, ticket.id).await;
+	if let Err(e) = tags {
+		log(LogType::Error, format!("Error reading tags of ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	if let Err(e) = tx.commit().await {
+		log(LogType::Error, format!("Error commiting transaction: {} for ticket {}", e, ticket.id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(tags.unwrap()));
+}
+
+#[cfg(test)]
+mod tags_tests {
+	use super::normalize_tags;
+
+	#[test]
+	fn tags_are_normalized() {
+		let tags = vec![" Finance ".to_string(), "finance".to_string(), "Q2".to_string()];
+		assert_eq!(normalize_tags(&tags).unwrap(), vec!["finance".to_string(), "q2".to_string()]);
+
+		assert!(normalize_tags(&["  ".to_string()]).is_err());
+		assert!(normalize_tags(&["x".repeat(65)]).is_err());
+	}
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::db_types::Ticket;
+use crate::logger::{admin_logger, LogType};
+use crate::process::read_process_data;
+use crate::rbac::{Authorized, ManageProcesses};
+use utoipa::{IntoParams, ToSchema};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// placeholders every template can use. state fields are used as {{state.<field>}}
+pub const PLACEHOLDERS: [&str; 5] = ["ticket_id", "node", "owner_name", "process_id", "process_name"];
+
+// used when the process has no template for the node in the user's locale
+const DEFAULT_NOTIFY_TEMPLATES: [(&str, &str); 4] = [
+	("en", "Ticket {{ticket_id}} created by {{owner_name}}. Process: {{process_name}}"),
+	("de", "Ticket {{ticket_id}} erstellt von {{owner_name}}. Prozess: {{process_name}}"),
+	("es", "Ticket {{ticket_id}} creado por {{owner_name}}. Proceso: {{process_name}}"),
+	("fr", "Ticket {{ticket_id}} créé par {{owner_name}}. Processus : {{process_name}}")
+];
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveTemplate {
+	pub process_id: String,
+	pub node: Option<i32>,
+	pub locale: String,
+	pub template: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TemplatesQuery {
+	pub process_id: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct NotificationTemplate {
+	pub id: i32,
+	pub process_id: String,
+	pub node: Option<i32>,
+	pub locale: String,
+	pub template: String,
+	pub updated_by: Option<uuid::Uuid>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+pub struct NotifyMessage {
+	// which template the message was rendered from, notifications from the same template are deduplicated
+	pub template_key: String,
+	pub message: String
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetLocale {
+	pub locale: String
+}
+
+// "en", "pt-br" and the like. locales are stored lowercase
+pub fn check_locale(locale: &str) -> Option<String> {
+	let locale = locale.trim().to_lowercase();
+	let mut parts = locale.split('-');
+	let language = parts.next()?;
+	if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+		return None;
+	}
+	if let Some(region) = parts.next() {
+		if !(2..=4).contains(&region.len()) || !region.chars().all(|c| c.is_ascii_alphanumeric()) {
+			return None;
+		}
+	}
+	if parts.next().is_some() {
+		return None;
+	}
+	return Some(locale);
+}
+
+fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+	let mut result = Vec::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		let end = rest[start..].find("}}").ok_or("Unclosed placeholder".to_string())?;
+		result.push(rest[start + 2..start + end].trim());
+		rest = &rest[start + end + 2..];
+	}
+	return Ok(result);
+}
+
+// rejects templates with placeholders that would never be filled
+pub fn check_template(template: &str) -> Result<(), String> {
+	if template.trim().is_empty() {
+		return Err("The template is empty".to_string());
+	}
+	for name in placeholders(template)? {
+		let known = PLACEHOLDERS.contains(&name)
+			|| name.strip_prefix("state.").map(|f| !f.is_empty()).unwrap_or(false);
+		if !known {
+			return Err(format!("Unknown placeholder: {}", name));
+		}
+	}
+	return Ok(());
+}
+
+fn state_value(state: &serde_json::Value, path: &str) -> String {
+	let mut value = state;
+	for field in path.split('.') {
+		value = match value.get(field) {
+			Some(v) => v,
+			None => return String::new()
+		};
+	}
+	return match value {
+		serde_json::Value::Null => String::new(),
+		serde_json::Value::String(s) => s.clone(),
+		other => other.to_string()
+	};
+}
+
+// fills the placeholders of a checked template. missing values render as nothing
+pub fn render(template: &str, vars: &HashMap<&str, String>, state: &serde_json::Value) -> String {
+	let mut result = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		let end = match rest[start..].find("}}") {
+			Some(end) => end,
+			None => break
+		};
+		result.push_str(&rest[..start]);
+		let name = rest[start + 2..start + end].trim();
+		match name.strip_prefix("state.") {
+			Some(path) => result.push_str(&state_value(state, path)),
+			None => result.push_str(vars.get(name).map(String::as_str).unwrap_or(""))
+		}
+		rest = &rest[start + end + 2..];
+	}
+	result.push_str(rest);
+	return result;
+}
+
+pub fn template_key(stored_id: Option<i32>, locale: &str) -> String {
+	return match stored_id {
+		Some(id) => format!("template:{}", id),
+		None => format!("default:{}", locale)
+	};
+}
+
+fn default_template(locale: &str) -> &'static str {
+	// "pt-br" falls back to "pt" before the default locale
+	let language = locale.split('-').next().unwrap_or(DEFAULT_LOCALE);
+	return DEFAULT_NOTIFY_TEMPLATES.iter()
+		.find(|(l, _)| *l == locale)
+		.or_else(|| DEFAULT_NOTIFY_TEMPLATES.iter().find(|(l, _)| *l == language))
+		.or_else(|| DEFAULT_NOTIFY_TEMPLATES.iter().find(|(l, _)| *l == DEFAULT_LOCALE))
+		.unwrap()
+		.1;
+}
+
+// the message a notify node sends to `notified_username`, in the user's locale
+pub async fn notify_message(
+	conn: &mut PgConnection,
+	ticket: &Ticket,
+	node: i32,
+	owner_name: &str,
+	notified_username: &str
+) -> Result<NotifyMessage, sqlx::Error> {
+	let locale: Option<(String,)> = sqlx::query_as("select locale from users where username=$1")
+		.bind(notified_username)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let locale = locale.map(|l| l.0).unwrap_or(DEFAULT_LOCALE.to_string());
+
+	// a node template beats a process template, the user's locale beats the default locale
+	let stored: Option<(i32, String)> = sqlx::query_as(
+		r#"select id, template from notification_templates
+			where process_id=$1 and (node=$2 or node is null) and locale in ($3, $4)
+			order by (locale=$3) desc, node nulls last limit 1"#)
+		.bind(&ticket.process_id)
+		.bind(node)
+		.bind(&locale)
+		.bind(DEFAULT_LOCALE)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let template = match &stored {
+		Some((_, template)) => template.as_str(),
+		None => default_template(&locale)
+	};
+	let template_key = template_key(stored.as_ref().map(|(id, _)| *id), &locale);
+
+	let process_name = read_process_data(ticket.process_id.clone()).await
+		.map(|p| p.pname)
+		.unwrap_or(ticket.process_id.clone());
+	let vars = HashMap::from([
+		("ticket_id", ticket.id.to_string()),
+		("node", node.to_string()),
+		("owner_name", owner_name.to_string()),
+		("process_id", ticket.process_id.clone()),
+		("process_name", process_name)
+	]);
+	return Ok(NotifyMessage { template_key, message: render(template, &vars, &ticket.state) });
+}
+
+#[utoipa::path(put, path = "/admin/notification_templates", tag = "notifications", request_body = SaveTemplate, responses(
+	(status = 200, description = "Saved", body = NotificationTemplate),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown process", body = String),
+	(status = 422, description = "Invalid locale or template", body = String)
+))]
+pub async fn save_template(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SaveTemplate>
+) -> Result<Json<NotificationTemplate>, (StatusCode, String)> {
+	let locale = check_locale(&payload.locale)
+		.ok_or((StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid locale: {}", payload.locale)))?;
+	check_template(&payload.template).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+	let query: Result<NotificationTemplate, _> = sqlx::query_as(
+		r#"insert into notification_templates (process_id, node, locale, template, updated_by, updated_at)
+			values ($1, $2, $3, $4, $5, $6)
+			on conflict (tenant_id, process_id, coalesce(node, -1), locale) do update set template=$4, updated_by=$5, updated_at=$6
+			returning id, process_id, node, locale, template, updated_by, updated_at"#)
+		.bind(&payload.process_id)
+		.bind(payload.node)
+		.bind(&locale)
+		.bind(&payload.template)
+		.bind(auth.user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::NOT_FOUND, format!("Unknown process: {}", payload.process_id)));
+		}
+		admin_logger(LogType::Error, &format!("Error saving notification template for {}: {}", payload.process_id, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} saved the {} notification template of {} node {:?}", auth.user.userid, locale, payload.process_id, payload.node), None);
+	return Ok(Json(query.unwrap()));
+}
+
+#[utoipa::path(get, path = "/admin/notification_templates", tag = "notifications", params(TemplatesQuery), responses(
+	(status = 200, description = "Notification templates", body = Vec<NotificationTemplate>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_templates(
+	_auth: Authorized<ManageProcesses>,
+	extract::Query(query): extract::Query<TemplatesQuery>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<NotificationTemplate>>, StatusCode> {
+	let query: Result<Vec<NotificationTemplate>, _> = sqlx::query_as(
+		r#"select id, process_id, node, locale, template, updated_by, updated_at from notification_templates
+			where $1::varchar is null or process_id=$1 order by process_id, node nulls first, locale"#)
+		.bind(query.process_id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading notification templates: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+#[utoipa::path(delete, path = "/admin/notification_templates/{id}", tag = "notifications", params(("id" = i32, Path, description = "Template id")), responses(
+	(status = 200, description = "Deleted"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown template")
+))]
+pub async fn delete_template(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from notification_templates where id=$1")
+		.bind(id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting notification template {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted notification template {}", auth.user.userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+// users pick the language of their own notifications
+#[utoipa::path(put, path = "/user/locale", tag = "notifications", request_body = SetLocale, responses(
+	(status = 200, description = "Notifications are rendered in the locale"),
+	(status = 422, description = "Invalid locale", body = String)
+))]
+pub async fn set_locale(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SetLocale>
+) -> Result<StatusCode, (StatusCode, String)> {
+	let locale = check_locale(&payload.locale)
+		.ok_or((StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid locale: {}", payload.locale)))?;
+
+	let query = sqlx::query("update users set locale=$2 where userid=$1")
+		.bind(user.userid)
+		.bind(&locale)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error setting locale of user {}: {}", user.userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod templates_tests {
+	use std::collections::HashMap;
+	use super::{check_locale, check_template, default_template, render, template_key};
+
+	#[test]
+	fn templates_are_rendered() {
+		let vars = HashMap::from([("ticket_id", "7".to_string()), ("owner_name", "alice".to_string())]);
+		let state = serde_json::json!({"amount": 120, "trip": {"city": "Pune"}, "note": "urgent"});
+		assert_eq!(
+			render("{{ owner_name }} asks {{state.amount}} for {{state.trip.city}} ({{state.note}}{{state.missing}}) #{{ticket_id}}", &vars, &state),
+			"alice asks 120 for Pune (urgent) #7");
+		assert_eq!(render("no placeholders", &vars, &state), "no placeholders");
+	}
+
+	#[test]
+	fn templates_are_checked() {
+		assert!(check_template("Ticket {{ticket_id}} of {{process_name}} for {{state.amount}}").is_ok());
+		assert_eq!(check_template("{{owner}}"), Err("Unknown placeholder: owner".to_string()));
+		assert!(check_template("{{ticket_id").is_err());
+		assert!(check_template("{{state.}}").is_err());
+		assert!(check_template("  ").is_err());
+	}
+
+	#[test]
+	fn locales() {
+		assert_eq!(check_locale(" pt-BR "), Some("pt-br".to_string()));
+		assert_eq!(check_locale("de"), Some("de".to_string()));
+		assert_eq!(check_locale("english"), None);
+		assert_eq!(check_locale("en-us-x"), None);
+
+		assert!(default_template("de-at").starts_with("Ticket {{ticket_id}} erstellt"));
+		assert!(default_template("ja").contains("created by"));
+	}
+
+	#[test]
+	fn template_keys() {
+		assert_eq!(template_key(Some(4), "de"), "template:4");
+		assert_eq!(template_key(None, "de"), "default:de");
+	}
+}
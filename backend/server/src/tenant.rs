@@ -0,0 +1,158 @@
+use std::future::Future;
+use axum::{body::Body, extract::State, http::{HeaderMap, Request}, middleware::Next, response::Response};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgConnection, PgPool};
+use crate::api_keys::API_KEY_HEADER;
+use crate::auth::{bearer_token, decode_token, parse_secret_token, JWT_SECRET};
+use crate::logger::{self, admin_logger, LogType};
+
+// the rows the queries of a task can see
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+	Tenant(i32),
+	// background work, and lookups that find the tenant of an unauthenticated request
+	AllTenants,
+	// requests without a valid access token or api key
+	Denied
+}
+
+// no tenant has this id, so the policies of the db hide every row
+const DENIED_TENANT: i32 = -1;
+// everything that existed before tenants belongs to it
+pub const DEFAULT_TENANT: i32 = 1;
+
+tokio::task_local! {
+	static SCOPE: Scope;
+}
+
+// every request is scoped by `scope`. work outside of a request, including tasks spawned by one, is not
+pub fn current_scope() -> Scope {
+	return SCOPE.try_with(|scope| *scope).unwrap_or(Scope::AllTenants);
+}
+
+// the tenant the request is scoped to
+pub fn current_tenant() -> Option<i32> {
+	return match current_scope() {
+		Scope::Tenant(tenant) => Some(tenant),
+		Scope::AllTenants | Scope::Denied => None
+	};
+}
+
+// for what is kept per tenant outside of the db, like the process files. work outside of a request uses the default tenant
+pub fn key_tenant() -> i32 {
+	return current_tenant().unwrap_or(DEFAULT_TENANT);
+}
+
+// row level security in the db filters every query by the app.tenant_id setting of the connection, unless
+// app.all_tenants is on. both are set whenever a connection is handed out
+pub async fn apply(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+	let (tenant, all_tenants) = match current_scope() {
+		Scope::Tenant(tenant) => (tenant.to_string(), "off"),
+		Scope::AllTenants => (String::new(), "on"),
+		Scope::Denied => (DENIED_TENANT.to_string(), "off")
+	};
+	sqlx::query("select set_config('app.tenant_id', $1, false), set_config('app.all_tenants', $2, false)")
+		.bind(tenant)
+		.bind(all_tenants)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+// options for every pool of the server
+pub fn pool_options() -> PgPoolOptions {
+	return PgPoolOptions::new()
+		.after_connect(|conn, _| Box::pin(async move {
+			return apply(conn).await;
+		}))
+		.before_acquire(|conn, _| Box::pin(async move {
+			apply(conn).await?;
+			return Ok(true);
+		}));
+}
+
+// the tenant claim of a valid access token. tokens are checked again by the handler
+pub fn token_tenant(headers: &HeaderMap, jwt_secret: &str) -> Option<i32> {
+	let token = bearer_token(headers)?;
+	return decode_token(token, jwt_secret).ok().map(|claims| claims.tid);
+}
+
+async fn api_key_tenant(pool: &PgPool, headers: &HeaderMap) -> Result<Option<i32>, sqlx::Error> {
+	let id = headers
+		.get(API_KEY_HEADER)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|h| parse_secret_token(h.trim()))
+		.map(|(id, _)| id);
+	if id.is_none() {
+		return Ok(None);
+	}
+	let tenant: Option<(i32,)> = sqlx::query_as("select tenant_id from api_keys where id=$1")
+		.bind(id)
+		.fetch_optional(pool)
+		.await?;
+	return Ok(tenant.map(|t| t.0));
+}
+
+// the tenant of the access token or api key of a request. unauthenticated requests have none
+pub async fn request_tenant(pool: &PgPool, headers: &HeaderMap) -> Option<i32> {
+	let tenant = token_tenant(headers, &JWT_SECRET);
+	if tenant.is_some() || bearer_token(headers).is_some() {
+		return tenant;
+	}
+	return match api_key_tenant(pool, headers).await {
+		Ok(tenant) => tenant,
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error reading the tenant of an api key: {}", e), None);
+			None
+		}
+	};
+}
+
+// runs the future with its queries limited to `scope`
+pub async fn within<F: Future>(scope: Scope, future: F) -> F::Output {
+	return SCOPE.scope(scope, future).await;
+}
+
+// runs the future with its queries scoped to the tenant. without one they see no rows of any tenant
+pub async fn scoped<F: Future>(tenant: Option<i32>, future: F) -> F::Output {
+	return within(tenant.map_or(Scope::Denied, Scope::Tenant), future).await;
+}
+
+// for the queries of unauthenticated requests that find the user and with it the tenant, like logins
+pub async fn across_tenants<F: Future>(future: F) -> F::Output {
+	return within(Scope::AllTenants, future).await;
+}
+
+// scopes the request to the tenant of its access token or api key. unauthenticated requests see no rows
+pub async fn scope(State(pool): State<PgPool>, request: Request<Body>, next: Next<Body>) -> Response {
+	let tenant = request_tenant(&pool, request.headers()).await;
+	if let Some(tenant) = tenant {
+		logger::record_tenant(tenant);
+	}
+	return scoped(tenant, next.run(request)).await;
+}
+
+#[cfg(test)]
+mod tenant_tests {
+	use axum::http::{header, HeaderMap, HeaderValue};
+	use crate::auth::{encode_token, Claims};
+	use super::{current_scope, current_tenant, scoped, token_tenant, Scope};
+
+	#[tokio::test]
+	async fn tenants_come_from_the_token() {
+		let now = chrono::Utc::now().timestamp();
+		let claims = Claims { sub: uuid::Uuid::new_v4(), username: "asha".to_string(), tid: 3, sid: uuid::Uuid::new_v4(), iat: now, exp: now + 60 };
+		let mut headers = HeaderMap::new();
+		headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", encode_token(&claims, "secret").unwrap())).unwrap());
+		assert_eq!(token_tenant(&headers, "secret"), Some(3));
+		assert_eq!(token_tenant(&headers, "other"), None);
+
+		assert_eq!(current_scope(), Scope::AllTenants);
+		assert_eq!(scoped(Some(3), async { current_tenant() }).await, Some(3));
+		// requests without a tenant are denied instead of seeing every tenant
+		assert_eq!(scoped(None, async { current_scope() }).await, Scope::Denied);
+		// spawned work is not scoped
+		let spawned = scoped(Some(3), async { tokio::spawn(async { current_scope() }).await.unwrap() }).await;
+		assert_eq!(spawned, Scope::AllTenants);
+	}
+}
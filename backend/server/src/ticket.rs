@@ -1,538 +1,1389 @@
-use axum::{Json, http::StatusCode, extract};
+use axum::{Json, http::StatusCode, extract, response::{IntoResponse, Response}};
 use serde::{Serialize, Deserialize};
 use serde_json::Map;
-use sqlx::FromRow;
-use crate::{callbacks::send_task, db_types::Ticket, process::{read_process_data, Process}};
-use std::collections::VecDeque;
-use crate::{utils, logger::{LogType, log, admin_logger}};
-use crate::notif_handler::{Ping, ping_notifier};
-
-#[derive(Eq, PartialEq, Clone, Serialize, Deserialize, Hash)]
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use crate::{callbacks::{self, CallbackJob, TicketCallbacks}, db_types::Ticket, process::{read_process_data, Process}, script};
+use std::collections::{HashMap, VecDeque};
+use crate::{utils, logger::{self, LogType, LogEntry, log, admin_logger, read_public_log}};
+use crate::schema::{self, FieldError};
+use crate::departments;
+use crate::visibility::{self, TicketAccess};
+use crate::ws::{LiveEvent, LiveEventKind};
+use crate::digests;
+use crate::auth::AuthUser;
+use crate::rbac;
+use crate::tags;
+use crate::templates;
+use crate::watchers;
+use crate::outbox;
+use crate::notifications;
+use crate::push;
+use crate::replica;
+use crate::audit::{self, AuditAction, AuditEvent};
+use crate::webhooks::{self, WebhookEvent};
+use crate::events::{self, EngineEvent, WorkflowEvent};
+use crate::{documents, linked};
+use crate::limits::{self, EscalateErr};
+use crate::api_error::{db_error, db_status, ApiError};
+use crate::extractors::{ValidJson, ValidPath, ValidQuery};
+use crate::working_time;
+use crate::repository::{PgRepository, TicketRepository, UserRepository};
+use crate::ticket_events::{self, Change, Projection, TicketEvent, TicketEventKind};
+use utoipa::{IntoParams, ToSchema};
+pub use erp_api_types::tickets::{
+	AssignmentType, CancelTicket, CreateTicket, CreatedTicket, CurrentTicket, GetUserTicketsReq, NextCursor, OwnTicket, SubmitTicket, TicketStatus, UpdateTicket, UserTickets
+};
+
+// first key of the advisory locks on tickets. the audit chain lock uses the single key form, which does not overlap
+const TICKET_LOCK_SPACE: i32 = 1;
+
+#[derive(Eq, PartialEq, Clone, Serialize, Deserialize, Hash, ToSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum Event {Initiate, Approve, Notify, NonBlockingTask, BlockingTask, Complete}
-#[derive(Debug)]
-pub enum TicketStatus {Open, Closed, Rejected}
+pub enum Event {Initiate, Approve, Notify, NonBlockingTask, BlockingTask, Wait, Script, Complete}
 
 #[derive(Debug)]
-pub enum NewUserTicketType {ApproveRequest, Notify, Completion}
+// Escalation is an approval request handed up by `from` because the amount is over their limit, see limits
+pub enum NewUserTicketType {ApproveRequest, Escalation { from: uuid::Uuid }, Notify, AwaitSignal, Completion}
 #[derive(Debug)]
 pub struct NewUserTicket {
 	pub type_ : NewUserTicketType,
 	pub ticket_id: i32,
 	pub node: i32,
-	pub username: Option<String>
+	pub username: Option<String>,
+	// set for nodes that run once per element of a state array
+	pub instance: Option<i32>
 }
 #[derive(Debug)]
 pub struct SingleExecState {
 	pub status: TicketStatus,
-	pub new_ticket : Option<NewUserTicket>,
+	pub new_tickets : Vec<NewUserTicket>,
 	pub completable_steps : Vec<i32>,
+	pub callback_jobs: Vec<CallbackJob>
 }
 #[derive(Debug)]
-pub enum ExecuteErr {InvalidTicket, FailedToExecute, InvalidEvent, FailedToReadProcessData, FailedToLog, FailedToNotify, FailedToExecuteCallback}
-#[derive(Serialize, Deserialize)]
-pub struct CreateTicket {
-	pub process_id: String,
-	pub owner_id: uuid::Uuid,
-	pub owner_name: String,
-	pub is_public: bool,
-	pub data: Option<Map<String, serde_json::Value>>
+pub enum ExecuteErr {InvalidTicket, FailedToExecute, InvalidEvent, FailedToReadProcessData, FailedToNotify, FailedToExecuteCallback}
+#[derive(Debug)]
+pub enum UpdateErr {Status(StatusCode), InvalidData(Vec<FieldError>), InvalidRequest(Vec<FieldError>), Problem(ApiError)}
+// who is completing the node. users only complete the approve nodes they hold, wait nodes are only completed by signals
+// and blocking task nodes only by services holding an api key or a callback result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateSource {User, Signal, Service}
+
+impl From<StatusCode> for UpdateErr {
+	fn from(status: StatusCode) -> Self {
+		return UpdateErr::Status(status);
+	}
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct UpdateTicket {
-	pub ticket_id: i32,
-	pub user_id: uuid::Uuid,
-	pub	status: bool,
-	pub node: i32,
-	pub data: Option<Map<String, serde_json::Value>>
+impl From<ApiError> for UpdateErr {
+	fn from(e: ApiError) -> Self {
+		return UpdateErr::Problem(e);
+	}
+}
+
+impl IntoResponse for UpdateErr {
+	fn into_response(self) -> Response {
+		match self {
+			UpdateErr::Status(status) => ApiError::from(status).into_response(),
+			UpdateErr::InvalidData(errors) => ApiError::fields(StatusCode::UNPROCESSABLE_ENTITY, "invalid_data", errors).into_response(),
+			UpdateErr::InvalidRequest(errors) => ApiError::fields(StatusCode::BAD_REQUEST, "invalid_request", errors).into_response(),
+			UpdateErr::Problem(e) => e.into_response()
+		}
+	}
 }
+pub(crate) fn ticket_not_found(ticket_id: i32) -> ApiError {
+	return ApiError::new(StatusCode::NOT_FOUND, "ticket_not_found", format!("Ticket {} does not exist", ticket_id));
+}
+
+// open tickets are the only ones that move. drafts can still be cancelled
+fn ticket_not_open(ticket: &Ticket) -> ApiError {
+	return ApiError::new(StatusCode::CONFLICT, "ticket_not_open", format!("Ticket {} is {}", ticket.id, ticket.status));
+}
+
+fn not_owner(ticket: &Ticket) -> ApiError {
+	return ApiError::new(StatusCode::FORBIDDEN, "not_ticket_owner", format!("Only the owner of ticket {} can do this", ticket.id));
+}
+
 #[derive(Serialize, Deserialize, FromRow)]
 pub struct UserIdQueryRes {
 	userid: uuid::Uuid
 }
-#[derive(Serialize, Deserialize)]
-pub struct UserTickets {
-	current_tickets: Vec<CurrentTicket>,
-	own_tickets: Vec<OwnTicket>
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+pub struct PendingAssignee {
+	node_number: i32,
+	instance: Option<i32>,
+	type_: AssignmentType,
+	userid: uuid::Uuid,
+	username: String
 }
-#[derive(Serialize, Deserialize, FromRow)]
-pub struct CurrentTicket {
-	type_: String,
-	ticketid: i32,
-	active: bool,
+#[derive(Serialize, ToSchema)]
+pub struct NodeProgress {
+	node: i32,
+	event: Event,
+	complete: bool,
+	pending: Vec<PendingAssignee>
+}
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Rejection {
 	node_number: i32,
-	process_id: String,
-	owner_name: String
+	userid: uuid::Uuid,
+	reason: String,
+	created_at: chrono::DateTime<chrono::Utc>
 }
-#[derive(Serialize, Deserialize, FromRow)]
-pub struct OwnTicket {
+// data sent with one submission of a node
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct NodeData {
+	node_number: i32,
+	instance: Option<i32>,
+	userid: uuid::Uuid,
+	username: String,
+	#[schema(value_type = Object)]
+	data: serde_json::Value,
+	created_at: chrono::DateTime<chrono::Utc>
+}
+#[derive(Serialize, ToSchema)]
+pub struct TicketHistory {
+	log: Vec<LogEntry>,
+	// oldest first, a node submitted more than once has a row for every submission
+	node_data: Vec<NodeData>
+}
+#[derive(FromRow)]
+struct ArchivedTicket {
+	#[sqlx(flatten)]
+	ticket: Ticket,
+	tags: Vec<String>
+}
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTicketQuery {
+	pub include_archived: Option<bool>
+}
+#[derive(Serialize, ToSchema)]
+pub struct TicketDetail {
+	ticket: Ticket,
+	tags: Vec<String>,
+	rejections: Vec<Rejection>,
+	nodes: Vec<NodeProgress>
+}
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PublicTicketsReq {
+	// matched against the ticket id, the process id and the owner name
+	pub search: Option<String>,
+	pub status: Option<TicketStatus>,
+	pub limit: Option<i64>,
+	pub cursor: Option<String>
+}
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct PublicTicket {
 	pub id: i32,
 	pub process_id: String,
-	pub is_public: bool,
-	pub created_at: chrono::DateTime<chrono::Utc>,
-	pub updated_at: chrono::DateTime<chrono::Utc>,
-	pub status: String,
-}
-#[derive(Serialize, Deserialize)]
-pub struct GetUserTicketsReq {
-	pub userid: String 
+	pub owner_name: String,
+	pub status: TicketStatus,
+	pub created_at: chrono::DateTime<chrono::Utc>
 }
-
-#[derive(Serialize, FromRow, Deserialize)]
-struct Userid {
-	userid: uuid::Uuid
+#[derive(Serialize, ToSchema)]
+pub struct PublicTickets {
+	pub tickets: Vec<PublicTicket>,
+	pub next_cursor: Option<String>
 }
 
-#[derive(Serialize, FromRow, Deserialize)]
-struct Username {
-	username: String
-}
+pub(crate) const DEFAULT_PAGE_SIZE: i64 = 50;
+pub(crate) const MAX_PAGE_SIZE: i64 = 200;
 
 
+#[utoipa::path(post, path = "/ticket", tag = "tickets", request_body = CreateTicket, responses(
+	(status = 201, description = "Created, the first node ran", body = CreatedTicket),
+	(status = 403, description = "Not allowed to use the process", body = Problem, content_type = "application/problem+json"),
+	(status = 422, description = "Invalid ticket", body = Problem, content_type = "application/problem+json"),
+	(status = 429, description = "Rate limited, see Retry-After")
+))]
 pub async fn create_ticket(
+	user: AuthUser,
 	extract::State(pool): extract::State<sqlx::PgPool>,
-	Json(payload) : Json<CreateTicket>
-) -> Result<StatusCode, StatusCode> {
+	ValidJson(payload) : ValidJson<CreateTicket>
+) -> Result<(StatusCode, Json<CreatedTicket>), ApiError> {
+	let created = create_for_user(&pool, user, payload).await?;
+	return Ok((StatusCode::CREATED, Json(created)));
+}
+
+// shared by create_ticket and the grpc service
+pub async fn create_for_user(pool: &sqlx::PgPool, user: AuthUser, mut payload: CreateTicket) -> Result<CreatedTicket, StatusCode> {
+	payload.owner_id = user.userid;
+	payload.owner_name = user.username;
+
+	let mut conn = pool.acquire().await.map_err(db_status)?;
+	match rbac::can_use_process(&mut conn, payload.owner_id, &payload.process_id).await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking roles of {} for process {}: {}", payload.owner_id, payload.process_id, e), None);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Ok(false) => {
+			admin_logger(LogType::Warning, &format!("User {} is not allowed to create tickets for process {}", payload.owner_id, payload.process_id), None);
+			return Err(StatusCode::FORBIDDEN);
+		}
+		Ok(true) => {}
+	}
+	drop(conn);
+	let ticket = new_ticket(pool, payload).await?;
+
+	return Ok(CreatedTicket {
+		id: ticket.id,
+		log_id: ticket.log_id,
+		status: ticket.status
+	});
+}
+
+// shared by create_ticket and the ticket scheduler
+pub async fn new_ticket(pool: &sqlx::PgPool, payload: CreateTicket) -> Result<Ticket, StatusCode> {
 	/*
-		1. create a new ticket with the request data and add it to the database;
-		2. Fetch the ticket back from the database because we dont know its id from the first step.
-		3. Insert a new ticket into user_active_tickets with userid=ticket.owner_id and node=0
-		4. Execute the first node of the process (always Event::Initiate)
-		5. Add all tickets returned by update_internal
-		6. Update the ticket in tickets table with the new values
-		7. Commit the transaction
+		1. create a new ticket with the request data and add it to the database, the inserted row is returned
+		2. Insert a new ticket into user_active_tickets with userid=ticket.owner_id and node=0
+		3. Execute the first node of the process (always Event::Initiate). skipped for drafts, they are initiated by submit_ticket
+		4. Add all tickets returned by update_internal
+		5. Update the ticket in tickets table with the new values
+		6. Return the live events, the caller commits and sends them
 	*/
 
-	let mut tx = pool.begin().await.unwrap();
+	let mut tx = pool.begin().await.map_err(db_status)?;
+	let mut ticket = insert_ticket(&mut tx, &payload).await?;
+
+	let mut events = Vec::new();
+	if payload.draft {
+		log(LogType::Info, format!("Ticket {} saved as draft", ticket.id), ticket.log_id);
+	}
+	else {
+		initiate_ticket(&mut tx, &mut ticket, payload.data, &mut events).await?;
+	}
+
+	log(LogType::Info, format!("Ticket {} created successfully", ticket.id), ticket.log_id);
+	// commit the transaction
+	if let Err(e) = tx.commit().await {
+		log(LogType::Error, format!("Error commiting transaction: {} for pid {}", e, ticket.process_id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	after_commit(pool, events).await;
+	return Ok(ticket);
+}
+
+// the sla of the process counted in the working hours of the owner, None for processes without one
+async fn sla_due_at(conn: &mut sqlx::PgConnection, payload: &CreateTicket, now: chrono::DateTime<chrono::Utc>) -> Result<Option<chrono::DateTime<chrono::Utc>>, sqlx::Error> {
+	let Some(sla_hours) = read_process_data(payload.process_id.clone()).await.ok().and_then(|p| p.sla_hours) else {
+		return Ok(None);
+	};
+	let calendar = working_time::calendar_of(&mut *conn, payload.owner_id, now.date_naive()).await?;
+	return Ok(Some(calendar.add(now, chrono::Duration::hours(sla_hours as i64))));
+}
+
+// the ticket row, its tags and the own row of the owner. nothing is executed, the ticket is locked until the transaction ends
+pub(crate) async fn insert_ticket(conn: &mut sqlx::PgConnection, payload: &CreateTicket) -> Result<Ticket, StatusCode> {
+	let tags = tags::normalize_tags(&payload.tags.clone().unwrap_or_default()).map_err(|_| StatusCode::BAD_REQUEST)?;
+	let status = if payload.draft { TicketStatus::Draft } else { TicketStatus::Open };
+
 	let log_id = uuid::Uuid::new_v4();
 	let state = payload.data.clone().unwrap_or_default();
+	let now = chrono::Utc::now();
+	let due_at = match payload.due_at {
+		Some(due_at) => Some(due_at),
+		None => sla_due_at(&mut *conn, payload, now).await.map_err(|e| {
+			log(LogType::Error, format!("Error reading the work calendar of {}: {}", payload.owner_id, e), log_id);
+			return StatusCode::INTERNAL_SERVER_ERROR;
+		})?
+	};
 
-
-	let query = sqlx::query("insert into tickets (owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, state) values ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
+	let query: Result<Ticket, _> = sqlx::query_as(
+		r#"insert into tickets (owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, state, priority, due_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) returning *"#)
 		.bind(payload.owner_id)
 		.bind(&payload.process_id)
 		.bind(log_id)
 		.bind(payload.is_public)
-		.bind(chrono::Utc::now())
-		.bind(chrono::Utc::now())
-		.bind("open")
+		.bind(now)
+		.bind(now)
+		.bind(status)
 		// no nodes have been completed at this stage
 		.bind(0i32)
 		.bind(serde_json::Value::Object(state))
-		.execute(&mut *tx)
+		.bind(payload.priority.unwrap_or(0))
+		.bind(due_at)
+		.fetch_one(&mut *conn)
 		.await;
 
-
 	if let Err(e) = query {
-		log(LogType::Error, format!("Error adding ticket: process {} from {}: {}", payload.process_id, payload.owner_id, e), log_id)?;
+		log(LogType::Error, format!("Error adding ticket: process {} from {}: {}", payload.process_id, payload.owner_id, e), log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let ticket = query.unwrap();
+	logger::record_ticket(ticket.id);
+	// signals and callbacks can only reach the ticket once it is committed, they wait for the initiation
+	if let Err(e) = lock_ticket(&mut *conn, ticket.id).await {
+		log(LogType::Error, format!("Error locking ticket {}: {}", ticket.id, e), log_id);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
-	// FIXME: should not use log_id for determining the ticket id
-	let query: Result<Ticket, _> = sqlx::query_as("select * from tickets where log_id=$1")
-		.bind(log_id)
-		.fetch_one(&mut *tx)
-		.await;
-
-	if let Err(e) = query {
-		log(LogType::Error, format!("Error reading ticket id from db: {}", e), log_id)?;
+	let created = TicketEvent::inserted(TicketEventKind::Created, &Projection::of(&ticket), Some(ticket.owner_id));
+	if let Err(e) = ticket_events::record(&mut *conn, ticket.id, &[created]).await {
+		log(LogType::Error, format!("Error recording the creation of ticket {}: {}", ticket.id, e), log_id);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
-	let mut ticket = query.unwrap();
 
-	log(LogType::Info, format!("Ticket {} created by {}", ticket.id, ticket.owner_id), log_id)?;
+	log(LogType::Info, format!("Ticket {} created by {}", ticket.id, ticket.owner_id), log_id);
+
+	if let Err(e) = tags::add_tags(&mut *conn, ticket.id, &tags).await {
+		log(LogType::Error, format!("Error adding tags to ticket {}: {}", ticket.id, e), log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
 
 	let query = sqlx::query("insert into user_active_tickets (userid, ticketid, active, node_number, type_) values ($1, $2, $3, $4, $5)")
 		.bind(payload.owner_id)
 		.bind(ticket.id)
 		.bind(true)
 		.bind(0i32)
-		.bind("own")
-		.execute(&mut *tx)
+		.bind(AssignmentType::Own)
+		.execute(&mut *conn)
 		.await;
 
 	if let Err(e) = query {
-		log(LogType::Error, format!("Error adding ticket: process {} from {}: {}", payload.process_id, payload.owner_id, e), log_id)?;
+		log(LogType::Error, format!("Error adding ticket: process {} from {}: {}", payload.process_id, payload.owner_id, e), log_id);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
-	// execute the 1 st node of the ticket (always Event::Initiate)
-	// TODO: Initiate Step should also be able to execute callbacks
-	let request = &UpdateTicket { ticket_id: ticket.id, user_id: payload.owner_id, status: true, node: 0, data: payload.data };
+	// the data of a new ticket is what its owner sent for node 0
+	if let Err(e) = save_node_data(&mut *conn, ticket.id, 0, None, payload.owner_id, payload.data.as_ref()).await {
+		log(LogType::Error, format!("Error saving the data of ticket {}: {}", ticket.id, e), log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
 
-	let result = update_internal(&mut ticket, request).await;
-	if let Err(e) = result {
-		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), log_id)?;
+	return Ok(ticket);
+}
+
+// side effects of a committed ticket transaction. nothing outside the database may learn about a ticket change before
+// its transaction committed, so notifications and callback jobs are written in the transaction and only sent from here.
+// whatever fails here stays in notifier_outbox or callback_jobs and is retried in the background
+pub(crate) async fn after_commit(pool: &sqlx::PgPool, events: Vec<(uuid::Uuid, LiveEvent)>) {
+	// clients on /ws/notifications get the events right away unless they are held for a digest. the ping is kept for clients of the notifier server
+	push::dispatch(pool, push::push_messages(&events));
+	digests::publish(pool, events).await;
+	outbox::flush(pool).await;
+	callbacks::dispatch(pool);
+	webhooks::dispatch(pool);
+	events::dispatch(pool);
+}
+// what applying new user tickets leaves for the caller, sent once its transaction is committed
+#[derive(Default)]
+pub(crate) struct Applied {
+	// pushed to connected users
+	pub events: Vec<(uuid::Uuid, LiveEvent)>,
+	// published to the event stream
+	pub engine_events: Vec<WorkflowEvent>,
+	// sent to the watchers of the ticket
+	pub watcher_messages: Vec<String>,
+	// recorded in ticket_events
+	pub ticket_events: Vec<TicketEvent>
+}
+
+// who the approvals of `userids` go to, the delegate of each approver that is out of office, and the delegations
+async fn assignees<R: UserRepository>(repo: &mut R, userids: &[uuid::Uuid]) -> Result<(Vec<uuid::Uuid>, HashMap<uuid::Uuid, uuid::Uuid>), sqlx::Error> {
+	let delegates = repo.active_delegates(userids).await?;
+	let assignees = userids.iter().map(|userid| delegates.get(userid).copied().unwrap_or(*userid)).collect();
+	return Ok((assignees, delegates));
+}
+
+// the approval rows of the reached approve nodes, handed to the delegates of approvers that are out of office.
+// the approvers, their delegates and the rows take a statement each however many nodes were reached
+async fn request_approvals(
+	conn: &mut sqlx::PgConnection,
+	ticket: &Ticket,
+	approvals: &[NewUserTicket],
+	applied: &mut Applied
+) -> Result<(), StatusCode> {
+	if approvals.is_empty() {
+		return Ok(());
+	}
+	// usernames or rules like dept_manager_of(owner)
+	let approvers: Vec<&str> = approvals.iter().map(|a| a.username.as_deref().unwrap_or_default()).collect();
+	let resolved = departments::resolve_approvers(&mut *conn, &approvers, ticket.owner_id).await;
+	if let Err(e) = resolved {
+		log(LogType::Error, format!("Error resolving approvers {:?} from db: {}", approvers, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let resolved = resolved.unwrap();
+	let mut userids = Vec::new();
+	for approver in approvers.iter() {
+		match resolved.get(*approver).copied().flatten() {
+			Some(userid) => userids.push(userid),
+			None => {
+				log(LogType::Error, format!("No user found for approver {} of ticket {}", approver, ticket.id), ticket.log_id);
+				return Err(StatusCode::UNPROCESSABLE_ENTITY);
+			}
+		}
+	}
+
+	let assigned = assignees(&mut PgRepository::new(&mut *conn), &userids).await;
+	if let Err(e) = assigned {
+		log(LogType::Error, format!("Error reading delegations from db: {}", e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let (assignees, delegates) = assigned.unwrap();
+
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("insert into user_active_tickets (userid, ticketid, active, node_number, type_, instance) ");
+	builder.push_values(approvals.iter().zip(assignees.iter()), |mut b, (approval, assignee)| {
+		b.push_bind(*assignee)
+			.push_bind(approval.ticket_id)
+			.push_bind(true)
+			.push_bind(approval.node)
+			.push_bind(AssignmentType::Approve)
+			.push_bind(approval.instance);
+	});
+	if let Err(e) = builder.build().execute(&mut *conn).await {
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
-	for new_ticket in result.unwrap() {
-		match new_ticket.type_ {
-			NewUserTicketType::ApproveRequest => {
-				// this query should always return 1 row as process always contains valid usernames
-				let new_ticket_username = new_ticket.username.unwrap();
-				let userid_query: Result<Userid, _> = sqlx::query_as("select userid from users where username=$1")
-				.bind(&new_ticket_username)
-				.fetch_one(&mut *tx)
-				.await;
-
-				if let Err(e) = userid_query {
-					log(LogType::Error, format!("Error reading userid from db: {}", e), log_id)?;
-					return Err(StatusCode::INTERNAL_SERVER_ERROR);
-				}
-				let userid = userid_query.unwrap();
 
-				let query = sqlx::query("insert into user_active_tickets (userid, ticketid, active, node_number, type_) values ($1, $2, $3, $4, $5)")
-					.bind(userid.userid)
+	for ((approval, userid), assignee) in approvals.iter().zip(userids).zip(assignees) {
+		let escalated_from = match approval.type_ {
+			NewUserTicketType::Escalation { from } => Some(from),
+			_ => None
+		};
+		match (delegates.get(&userid), escalated_from) {
+			(Some(delegate_id), _) => log(LogType::Request, format!("Ticket {} approval requested from {} on behalf of {} (delegated)", ticket.id, delegate_id, userid), ticket.log_id),
+			(None, Some(from)) => log(LogType::Request, format!("Ticket {} approval requested from {}, escalated by {}", ticket.id, userid, from), ticket.log_id),
+			(None, None) => log(LogType::Request, format!("Ticket {} approval requested from {}", ticket.id, userid), ticket.log_id)
+		}
+		applied.engine_events.push(WorkflowEvent::new(EngineEvent::ApprovalRequested, ticket, Some(approval.node),
+			serde_json::json!({"approver": userid, "assignee": assignee, "instance": approval.instance, "escalated_from": escalated_from})));
+		let message = match escalated_from {
+			Some(_) => format!("Ticket {} was escalated to you for approval. Process Id: {}", ticket.id, ticket.process_id),
+			None => format!("Ticket {} needs your approval. Process Id: {}", ticket.id, ticket.process_id)
+		};
+		applied.events.push((assignee, LiveEvent::new(LiveEventKind::ApproveRequest, ticket, approval.node, message)));
+	}
+	return Ok(());
+}
+
+// the notifications of the reached notify nodes, written together, and one ping of the notifier server for all of them
+async fn notify_users(
+	conn: &mut sqlx::PgConnection,
+	ticket: &Ticket,
+	notifies: &[NewUserTicket],
+	applied: &mut Applied
+) -> Result<(), StatusCode> {
+	if notifies.is_empty() {
+		return Ok(());
+	}
+	let owner_name = PgRepository::new(&mut *conn).username(ticket.owner_id).await;
+	if let Err(e) = owner_name {
+		log(LogType::Error, format!("Error reading the owner {} of ticket {} for a notification: {}", ticket.owner_id, ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let Some(owner_name) = owner_name.unwrap() else {
+		log(LogType::Error, format!("Owner {} of ticket {} does not exist", ticket.owner_id, ticket.id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	};
+
+	let mut messages = Vec::new();
+	for notify in notifies {
+		let notified_username = notify.username.as_deref().unwrap_or_default();
+		let message = templates::notify_message(&mut *conn, ticket, notify.node, &owner_name, notified_username).await;
+		if let Err(e) = message {
+			log(LogType::Error, format!("Error rendering the notification of ticket {}: {}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		messages.push(message.unwrap());
+	}
+
+	let batch: Vec<notifications::NewNotification> = notifies.iter().zip(messages.iter())
+		.map(|(notify, message)| notifications::NewNotification {
+			username: notify.username.as_deref().unwrap_or_default(),
+			dedup_key: &message.template_key,
+			message: &message.message
+		})
+		.collect();
+	let added = notifications::add_deduped(&mut *conn, ticket.id, &batch).await;
+	if let Err(e) = added {
+		log(LogType::Error, format!("Error adding the notifications of ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if let Err(e) = outbox::enqueue(&mut *conn).await {
+		log(LogType::Error, format!("Error queueing notifier ping for ticket {}: {}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	for ((notify, message), added) in notifies.iter().zip(messages).zip(added.unwrap()) {
+		// a collapsed notification was already pushed to the user's sockets
+		if let Some((notified_userid, true)) = added {
+			applied.events.push((notified_userid, LiveEvent::new(LiveEventKind::Notify, ticket, notify.node, message.message)));
+		}
+		log(LogType::NotificationSuccess, format!("Notification sent to notifier for user {} notified for ticket {}", notify.username.as_deref().unwrap_or_default(), ticket.id), ticket.log_id);
+	}
+	return Ok(());
+}
+
+// writes what update_internal asked for, in the transaction of the caller. shared by every path that executes the engine
+// so a node reached at creation behaves like one reached by an update
+pub(crate) async fn apply_new_tickets(
+	conn: &mut sqlx::PgConnection,
+	ticket: &mut Ticket,
+	new_tickets: Vec<NewUserTicket>
+) -> Result<Applied, StatusCode> {
+	let mut applied = Applied::default();
+	let mut approvals = Vec::new();
+	let mut notifies = Vec::new();
+	let mut completions = Vec::new();
+	for new_ticket in new_tickets {
+		match new_ticket.type_ {
+			NewUserTicketType::ApproveRequest | NewUserTicketType::Escalation { .. } => approvals.push(new_ticket),
+			NewUserTicketType::Notify => notifies.push(new_ticket),
+			// always the last ones, they need every other node to be executed first
+			NewUserTicketType::Completion => completions.push(new_ticket),
+			NewUserTicketType::AwaitSignal => {
+				let query = sqlx::query("insert into ticket_signals (ticketid, node_number, active, created_at) values ($1, $2, $3, $4)")
 					.bind(new_ticket.ticket_id)
-					.bind(true)
 					.bind(new_ticket.node)
-					.bind("approve")
-					.execute(&mut *tx)
+					.bind(true)
+					.bind(chrono::Utc::now())
+					.execute(&mut *conn)
 					.await;
 				if let Err(e) = query {
-					log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), log_id)?;
+					log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
 					return Err(StatusCode::INTERNAL_SERVER_ERROR);
 				}
-				log(LogType::Request, format!("Ticket {} approval requested from {}", ticket.id, userid.userid), ticket.log_id)?;
+				log(LogType::Request, format!("Ticket {} waiting for signal at node {}", ticket.id, new_ticket.node), ticket.log_id);
 			}
-			NewUserTicketType::Notify => {
-				// insert a new ticket into notifications table and ping the notifier server
+		}
+	}
 
-				let owner_name_query: Result<Username, _> = sqlx::query_as("select username from users where userid=$1")
-					.bind(ticket.owner_id)
-					.fetch_one(&mut *tx)
-					.await;
+	request_approvals(&mut *conn, ticket, &approvals, &mut applied).await?;
+	notify_users(&mut *conn, ticket, &notifies, &mut applied).await?;
+	for new_ticket in completions {
+		let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1")
+			.bind(ticket.id)
+			.execute(&mut *conn)
+			.await;
+		if let Err(e) = query {
+			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		ticket.status = TicketStatus::Closed;
+		applied.ticket_events.push(TicketEvent {
+			kind: TicketEventKind::Closed,
+			node: Some(new_ticket.node),
+			instance: None,
+			actor: None,
+			change: Change::status(TicketStatus::Closed)
+		});
+		log(LogType::Completion, format!("Ticket {} completed", ticket.id), ticket.log_id);
+		if let Err(e) = webhooks::enqueue(&mut *conn, WebhookEvent::Completed, ticket, serde_json::json!({"node": new_ticket.node})).await {
+			log(LogType::Error, format!("Error queueing webhooks of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		applied.engine_events.push(WorkflowEvent::new(EngineEvent::TicketClosed, ticket, Some(new_ticket.node), serde_json::json!({})));
+		applied.events.push((ticket.owner_id, LiveEvent::new(LiveEventKind::Completion, ticket, new_ticket.node,
+			format!("Ticket {} was completed. Process Id: {}", ticket.id, ticket.process_id))));
+		applied.watcher_messages.push(format!("Ticket {} was completed. Process Id: {}", ticket.id, ticket.process_id));
+	}
+	return Ok(applied);
+}
 
-				if let Err(e) = owner_name_query {
-					admin_logger(LogType::Error, &format!("failed to get owner name in notification NewUserTicket. create request from {}. Error: {}", ticket.owner_id, e), None)
-						.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-					return Err(StatusCode::INTERNAL_SERVER_ERROR);
-				}
-				let message = format!("Ticket created by {}. Process Id: {}", ticket.owner_id, ticket.process_id);
+// serializes the engine per ticket across server instances. held until the transaction ends so two updates of the
+// same ticket never execute its completable steps twice
+pub async fn lock_ticket(conn: &mut sqlx::PgConnection, ticket_id: i32) -> Result<(), sqlx::Error> {
+	sqlx::query("select pg_advisory_xact_lock($1, $2)")
+		.bind(TICKET_LOCK_SPACE)
+		.bind(ticket_id)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
 
-				let notified_username = new_ticket.username.as_ref().unwrap();
+// keeps what a submission sent for a node next to the merged state. nothing is saved for a submission without data
+async fn save_node_data(
+	conn: &mut sqlx::PgConnection,
+	ticket_id: i32,
+	node: i32,
+	instance: Option<i32>,
+	userid: uuid::Uuid,
+	data: Option<&Map<String, serde_json::Value>>
+) -> Result<(), sqlx::Error> {
+	let Some(data) = data.filter(|d| !d.is_empty()) else {
+		return Ok(());
+	};
+	sqlx::query("insert into ticket_node_data (ticketid, node_number, instance, userid, data, created_at) values ($1, $2, $3, $4, $5, $6)")
+		.bind(ticket_id)
+		.bind(node)
+		.bind(instance)
+		.bind(userid)
+		.bind(serde_json::Value::Object(data.clone()))
+		.bind(chrono::Utc::now())
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
 
-				// add notification
-				let query = sqlx::query("insert into notifications (userid, message, created_at) values ((select userid from users where username=$1), $2, $3)")
-					.bind(notified_username)
-					.bind(message)
-					.bind(chrono::Utc::now())
-					.execute(&mut *tx)
-					.await;
+// executes node 0 (always Event::Initiate) and everything it unlocks. used when a ticket is created and when a draft is submitted
+pub(crate) async fn initiate_ticket(
+	conn: &mut sqlx::PgConnection,
+	ticket: &mut Ticket,
+	data: Option<Map<String, serde_json::Value>>,
+	events: &mut Vec<(uuid::Uuid, LiveEvent)>
+) -> Result<(), StatusCode> {
+	if let Err(e) = webhooks::enqueue(&mut *conn, WebhookEvent::Created, ticket, serde_json::json!({})).await {
+		log(LogType::Error, format!("Error queueing webhooks of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	// TODO: Initiate Step should also be able to execute callbacks
+	let request = &UpdateTicket { ticket_id: ticket.id, user_id: ticket.owner_id, status: true, node: 0, data, instance: None, reason: None };
 
-				if let Err(e) = query {
-					admin_logger(LogType::Error, &format!("failed to add notification in NewUserTicket. create request from {}, Error: {}", ticket.owner_id, e), None)
-						.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-					return Err(StatusCode::INTERNAL_SERVER_ERROR);
-				}
-				
-				log(LogType::NotificationSuccess, format!("Notification sent to notifier for user {} notified for ticket {}", notified_username, ticket.id), ticket.log_id)
-					.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-			}
-			NewUserTicketType::Completion => {
-				// this ticket is always the last in the new_ticket_queue because it requires all other nodes to be executed first
-				let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1")
-					.bind(ticket.id)
-					.execute(&mut *tx)
-					.await;
-				if let Err(e) = query {
-					log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), log_id)?;
-					return Err(StatusCode::INTERNAL_SERVER_ERROR);
-				}
-				ticket.status = "closed".to_string();
-				log(LogType::Completion, format!("Ticket {} completed", ticket.id), ticket.log_id)?;
-			}	
+	let mut jobs = Vec::new();
+	let mut transitions = Vec::new();
+	let before = ticket.complete;
+	let result = update_internal(ticket, request, &mut jobs, &mut transitions).await;
+	if let Err(e) = result {
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let mut engine_events: Vec<WorkflowEvent> = events::completed_nodes(before, ticket.complete).into_iter()
+		.map(|node| WorkflowEvent::new(EngineEvent::NodeCompleted, ticket, Some(node), serde_json::json!({})))
+		.collect();
+	if let Err(e) = callbacks::enqueue_jobs(&mut *conn, &jobs).await {
+		log(LogType::Error, format!("Error saving callbacks of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let applied = apply_new_tickets(&mut *conn, ticket, result.unwrap()).await?;
+	events.extend(applied.events);
+	engine_events.extend(applied.engine_events);
+	transitions.extend(applied.ticket_events);
+	for message in applied.watcher_messages.iter() {
+		if let Err(e) = watchers::notify_watchers(&mut *conn, ticket.id, message).await {
+			log(LogType::Error, format!("Error notifying watchers of ticket {}: {}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
 		}
 	}
 
-	// update all fields of the ticket
-	let query = sqlx::query("update tickets set status=$1, complete=$2, updated_at=$3 where id=$4")
-		.bind(&ticket.status)
+	match linked::follow(&mut *conn, &engine_events).await {
+		Err(linked::FollowErr::Db(e)) => {
+			log(LogType::Error, format!("Error moving the record of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Err(linked::FollowErr::Refused(message)) => {
+			log(LogType::Warning, format!("Record of ticket {} refused its transition: {}", ticket.id, message), ticket.log_id);
+			return Err(StatusCode::CONFLICT);
+		}
+		Ok(()) => {}
+	}
+	if let Err(e) = events::record(&mut *conn, &engine_events).await {
+		log(LogType::Error, format!("Error recording events of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if let Err(e) = ticket_events::record(&mut *conn, ticket.id, &transitions).await {
+		log(LogType::Error, format!("Error recording the transitions of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	// update all fields of the ticket. script nodes may have changed the state
+	let query = sqlx::query("update tickets set status=$1, complete=$2, updated_at=$3, state=$4, instances=$5 where id=$6")
+		.bind(ticket.status)
 		.bind(ticket.complete)
 		.bind(ticket.updated_at)
+		.bind(&ticket.state)
+		.bind(&ticket.instances)
 		.bind(ticket.id)
-		.execute(&mut *tx)
+		.execute(&mut *conn)
 		.await;
 
 	if let Err(e) = query {
-		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), log_id)?;
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
+	return Ok(());
+}
 
+#[utoipa::path(post, path = "/ticket/{id}/submit", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), request_body = SubmitTicket, responses(
+	(status = 200, description = "Submitted", body = CreatedTicket),
+	(status = 403, description = "Not the owner", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "Ticket is not a draft", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn submit_ticket(
+	user: AuthUser,
+	extract::State(pool): extract::State<sqlx::PgPool>,
+	ValidPath(ticket_id): ValidPath<i32>,
+	ValidJson(payload) : ValidJson<SubmitTicket>,
+) -> Result<(StatusCode, Json<CreatedTicket>), ApiError> {
+	logger::record_ticket(ticket_id);
+	let mut tx = pool.begin().await.map_err(db_error)?;
+	if let Err(e) = lock_ticket(&mut tx, ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
 
-	log(LogType::Info, format!("Ticket {} created successfully", ticket.id), log_id)?;
-	// commit the transaction
-	if let Err(e) = tx.commit().await {
-		log(LogType::Error, format!("Error commiting transaction: {} for pid {}", e, ticket.process_id), log_id)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	let query: Result<Option<Ticket>, _> = sqlx::query_as("select * from tickets where id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket from db: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 	}
+	let mut ticket = query.unwrap().ok_or_else(|| ticket_not_found(ticket_id))?;
 
-	// notifier server should be pinged only after completing the transaction.
-	// failure to ping is recoverable so dont return 500 if it fails
-	// when the server is successfully pinged later current notifications will be sent
-	if let Err(_e) = ping_notifier(Ping::CollectNew, None).await {
-		// FIXME: this might silently fail
-		let _ = admin_logger(LogType::FailedToPing, 
-			&format!("Failed to ping server for new notification node in NewUserTicket. create request from {}", ticket.owner_id), 
-			None
-		);
+	if ticket.owner_id != user.userid {
+		log(LogType::Error, format!("Attempt to submit ticket {} by {} who is not the owner", ticket.id, user.userid), ticket.log_id);
+		return Err(not_owner(&ticket));
+	}
+	if ticket.status != TicketStatus::Draft {
+		log(LogType::Error, format!("Attempt to submit {} ticket {}", ticket.status, ticket.id), ticket.log_id);
+		return Err(ApiError::new(StatusCode::CONFLICT, "ticket_not_draft", format!("Ticket {} was submitted already", ticket.id)));
 	}
-	return Ok(StatusCode::CREATED);
+
+	let draft = Projection::of(&ticket);
+	if let Err(e) = save_node_data(&mut tx, ticket.id, 0, None, user.userid, payload.data.as_ref()).await {
+		log(LogType::Error, format!("Error saving the data of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	// merged like the data of /ticket/update, so submitting a draft does not replace its nested objects
+	if let Some(data) = payload.data.as_ref() {
+		utils::merge_patch(&mut ticket.state, data);
+	}
+	ticket.status = TicketStatus::Open;
+	let submitted = TicketEvent {
+		kind: TicketEventKind::Submitted,
+		node: None,
+		instance: None,
+		actor: Some(user.userid),
+		change: draft.change_to(&Projection::of(&ticket))
+	};
+	if let Err(e) = ticket_events::record(&mut tx, ticket.id, &[submitted]).await {
+		log(LogType::Error, format!("Error recording the submission of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let mut events = Vec::new();
+	initiate_ticket(&mut tx, &mut ticket, payload.data, &mut events).await?;
+
+	log(LogType::Info, format!("Draft ticket {} submitted", ticket.id), ticket.log_id);
+	if let Err(e) = tx.commit().await {
+		log(LogType::Error, format!("Error commiting transaction: {} for ticket {}", e, ticket.id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	after_commit(&pool, events).await;
+	return Ok((StatusCode::OK, Json(CreatedTicket {
+		id: ticket.id,
+		log_id: ticket.log_id,
+		status: ticket.status
+	})));
 }
+
 #[axum::debug_handler]
+#[utoipa::path(post, path = "/ticket/update", tag = "tickets", request_body = UpdateTicket, responses(
+	(status = 200, description = "Node completed or ticket rejected"),
+		(status = 400, description = "The node cannot be completed this way", body = Problem, content_type = "application/problem+json"),
+		(status = 403, description = "Not allowed to complete the node", body = Problem, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json"),
+		(status = 409, description = "Ticket is not open", body = Problem, content_type = "application/problem+json"),
+		(status = 422, description = "Data does not match the schema of the node", body = Problem, content_type = "application/problem+json"),
+	(status = 429, description = "Rate limited, see Retry-After")
+))]
 pub async fn update_ticket(
+	user: AuthUser,
 	extract::State(pool): extract::State<sqlx::PgPool>,
-	Json(payload) : Json<UpdateTicket>,
-) -> Result<StatusCode, StatusCode> {
+	ValidJson(mut payload) : ValidJson<UpdateTicket>,
+) -> Result<StatusCode, UpdateErr> {
+	payload.user_id = user.userid;
+	return apply_update(&pool, payload, UpdateSource::User).await;
+}
+
+#[utoipa::path(post, path = "/ticket/cancel", tag = "tickets", request_body = CancelTicket, responses(
+	(status = 200, description = "Cancelled"),
+	(status = 403, description = "Not the owner", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "Ticket is not open", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn cancel_ticket(
+	user: AuthUser,
+	extract::State(pool): extract::State<sqlx::PgPool>,
+	ValidJson(payload) : ValidJson<CancelTicket>,
+) -> Result<StatusCode, ApiError> {
+	/*
+		1. Only the owner can cancel and only while the ticket is open
+		2. Deactivate every row of the ticket in user_active_tickets and every pending signal
+		3. Notify the users that still had an approval pending
+	*/
+	logger::record_ticket(payload.ticket_id);
+	let mut tx = pool.begin().await.map_err(db_error)?;
+	if let Err(e) = lock_ticket(&mut tx, payload.ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {}: {}", payload.ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let (ticket, approvers) = cancel(&mut PgRepository::new(&mut tx), payload.ticket_id, user.userid).await?;
+	let transition = TicketEvent {
+		kind: TicketEventKind::Cancelled,
+		node: None,
+		instance: None,
+		actor: Some(user.userid),
+		change: Change::status(TicketStatus::Cancelled)
+	};
+	if let Err(e) = ticket_events::record(&mut tx, ticket.id, &[transition]).await {
+		log(LogType::Error, format!("Error recording the cancellation of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	if let Err(e) = callbacks::cancel_jobs(&mut tx, ticket.id).await {
+		log(LogType::Error, format!("Error cancelling the callback jobs of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let cancelled = WorkflowEvent::new(EngineEvent::TicketCancelled, &ticket, None, serde_json::json!({"user_id": user.userid, "reason": payload.reason}));
+	match linked::follow(&mut tx, std::slice::from_ref(&cancelled)).await {
+		Err(linked::FollowErr::Db(e)) => {
+			log(LogType::Error, format!("Error moving the record of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		Err(linked::FollowErr::Refused(message)) => {
+			log(LogType::Warning, format!("Record of ticket {} refused its transition: {}", ticket.id, message), ticket.log_id);
+			return Err(ApiError::new(StatusCode::CONFLICT, "record_refused", message));
+		}
+		Ok(()) => {}
+	}
+	if let Err(e) = events::record(&mut tx, &[cancelled]).await {
+		log(LogType::Error, format!("Error recording events of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let message = format!("Ticket {} was cancelled by its owner. Process Id: {}", ticket.id, ticket.process_id);
+	for approver in approvers.iter() {
+		let query = sqlx::query("insert into notifications (userid, message, created_at) values ($1, $2, $3)")
+			.bind(approver)
+			.bind(&message)
+			.bind(chrono::Utc::now())
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			log(LogType::Error, format!("Error notifying {} of cancellation: {:?}", approver, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+	}
+	if !approvers.is_empty() {
+		if let Err(e) = outbox::enqueue(&mut tx).await {
+			log(LogType::Error, format!("Error queueing notifier ping for ticket {}: {:?}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+	}
+
+	if let Err(e) = tx.commit().await {
+		log(LogType::Error, format!("Error commiting transaction: {} for ticket {}", e, ticket.id), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	log(LogType::Info, format!("Ticket {} cancelled by {}, reason: {:?}", ticket.id, user.userid, payload.reason), ticket.log_id);
+
+	outbox::flush(&pool).await;
+	events::dispatch(&pool);
+
+	return Ok(StatusCode::OK);
+}
+
+// reads the ticket an update or a cancellation works on
+async fn find_ticket<R: TicketRepository>(repo: &mut R, ticket_id: i32) -> Result<Ticket, ApiError> {
+	let ticket = repo.find(ticket_id).await;
+	if let Err(e) = ticket {
+		admin_logger(LogType::Error, &format!("Error reading ticket from db: {}", e), None);
+		return Err(ApiError::internal());
+	}
+	return ticket.unwrap().ok_or_else(|| ticket_not_found(ticket_id));
+}
+
+// the rows cancel_ticket changes. returns the cancelled ticket as it was before and the users whose approval was pending
+pub(crate) async fn cancel<R: TicketRepository>(repo: &mut R, ticket_id: i32, user_id: uuid::Uuid) -> Result<(Ticket, Vec<uuid::Uuid>), ApiError> {
+	let ticket = find_ticket(repo, ticket_id).await?;
+	if ticket.owner_id != user_id {
+		log(LogType::Error, format!("Attempt to cancel ticket {} by {} who is not the owner", ticket.id, user_id), ticket.log_id);
+		return Err(not_owner(&ticket));
+	}
+	if !matches!(ticket.status, TicketStatus::Open | TicketStatus::Draft) {
+		log(LogType::Error, format!("Attempt to cancel {} ticket {}", ticket.status, ticket.id), ticket.log_id);
+		return Err(ticket_not_open(&ticket));
+	}
+
+	let approvers = repo.deactivate(ticket.id).await;
+	if let Err(e) = approvers {
+		log(LogType::Error, format!("Error cancelling ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(ApiError::internal());
+	}
+	if let Err(e) = repo.deactivate_signals(ticket.id).await {
+		log(LogType::Error, format!("Error cancelling ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(ApiError::internal());
+	}
+	if let Err(e) = repo.set_status(ticket.id, TicketStatus::Cancelled, chrono::Utc::now()).await {
+		log(LogType::Error, format!("Error cancelling ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(ApiError::internal());
+	}
+	return Ok((ticket, approvers.unwrap()));
+}
+
+// step 1 of apply_update_in, takes the node from the user completing it. approve nodes are only completed by the user
+// the approval is pending with
+pub(crate) async fn release<R: TicketRepository>(repo: &mut R, ticket: &Ticket, payload: &UpdateTicket, event: Option<&Event>) -> Result<(), UpdateErr> {
+	let released = repo.release(ticket.id, payload.user_id, payload.node, payload.instance).await;
+	if let Err(e) = released {
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	if event == Some(&Event::Approve) && !released.unwrap() {
+		log(LogType::Error, format!("User {} does not hold node {} of ticket {}", payload.user_id, payload.node, ticket.id), ticket.log_id);
+		return Err(UpdateErr::Problem(ApiError::new(StatusCode::FORBIDDEN, "not_assigned",
+			format!("Node {} of ticket {} is not waiting for your approval", payload.node, ticket.id))));
+	}
+	return Ok(());
+}
+
+// step 2 of apply_update_in, the ticket is rejected and taken from everyone holding it
+pub(crate) async fn reject<R: TicketRepository>(repo: &mut R, ticket: &mut Ticket) -> Result<(), UpdateErr> {
+	ticket.update_time();
+	if let Err(e) = repo.set_status(ticket.id, TicketStatus::Rejected, ticket.updated_at).await {
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	if let Err(e) = repo.deactivate(ticket.id).await {
+		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}", ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	return Ok(());
+}
+
+// shared by update_ticket and the other handlers that complete nodes (signals, api_keys)
+// checks that the node exists in the process and can be completed by `source`
+fn validate_node(process: &Process, node: i32, status: bool, source: UpdateSource) -> Result<(), UpdateErr> {
+	let step = if node < 0 { None } else { process.steps.get(node as usize) };
+	if step.is_none() {
+		return Err(UpdateErr::InvalidData(vec![FieldError {
+			field: "/node".to_string(),
+			message: format!("Node {} does not exist in process {}", node, process.pid)
+		}]));
+	}
+	let event = &step.unwrap().event;
+
+	let allowed = match source {
+		UpdateSource::User => *event == Event::Approve,
+		UpdateSource::Signal => *event == Event::Wait && status,
+		UpdateSource::Service => *event == Event::BlockingTask
+	};
+	if !allowed {
+		return Err(UpdateErr::InvalidRequest(vec![FieldError {
+			field: "/node".to_string(),
+			message: format!("Node {} of process {} cannot be completed by this request", node, process.pid)
+		}]));
+	}
+	return Ok(());
+}
+
+pub async fn apply_update(pool: &sqlx::PgPool, payload: UpdateTicket, source: UpdateSource) -> Result<StatusCode, UpdateErr> {
+	let mut tx = pool.begin().await.map_err(db_error)?;
+	let ticket_id = payload.ticket_id;
+	let events = apply_update_in(&mut tx, payload, source).await?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting transaction: {} for pid {}", e, ticket_id), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	after_commit(pool, events).await;
+	return Ok(StatusCode::ACCEPTED);
+}
+
+// apply_update in the transaction of the caller, for callers that write more in it like the result of a callback job.
+// the events are sent with after_commit once the caller committed
+pub(crate) async fn apply_update_in(
+	conn: &mut sqlx::PgConnection,
+	payload: UpdateTicket,
+	source: UpdateSource
+) -> Result<Vec<(uuid::Uuid, LiveEvent)>, UpdateErr> {
 	/*
 		INFO: user always receives the ticket from user_active_tickets unless they are the owner of the specific ticket
-		1. Set the status of the ticket in user_active_tickets to false.
-		2. If the user rejected the ticket (only possible in Event::Approve or Event::BlockingTask) then set the status of the ticket in tickets table to rejected
+		1. Set the status of the ticket in user_active_tickets to false. approve nodes can only be completed by the user holding them
+		2. If the user rejected the ticket (only possible in Event::Approve, or Event::BlockingTask for services) then set the status of the ticket in tickets table to rejected
 			and set the status of all tickets with the same ticket_id to false.
 		3. If the user accepted the ticket then fetch the complete ticket from tickets table and call update_internal
 		4. Add all tickets returned by update_internal
 		5. Update the ticket in tickets table with the new values
-		6. Commit the transaction
+		6. Return the live events, the caller commits and sends them
 	*/
 
-	let mut tx = pool.begin().await.unwrap();
 	let ticket_id = payload.ticket_id;
+	logger::record_ticket(ticket_id);
+	if let Err(e) = lock_ticket(&mut *conn, ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
 
-	let query: Result<Ticket, _> = sqlx::query_as("select * from tickets where id=$1")
-		.bind(ticket_id)
-		.fetch_one(&pool)
-		.await;
+	// read after the lock so the update starts from what the previous one committed
+	let mut ticket = find_ticket(&mut PgRepository::new(&mut *conn), ticket_id).await?;
 
-	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error reading ticket from db: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	if ticket.status != TicketStatus::Open {
+		admin_logger(LogType::Error, 
+			&format!("Attempt to update {} ticket. id: {}, user_id: {}", ticket.status, ticket.id, payload.user_id),
+			None);
+		return Err(UpdateErr::Problem(ticket_not_open(&ticket)));
 	}
-	let mut ticket = query.unwrap();
 
-	if ticket.status == "closed" {
-		admin_logger(LogType::Error, 
-			&format!("Attempt to update closed ticket. id: {}, user_id: {}", ticket.id, payload.user_id),
-			None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::FORBIDDEN);
+	let process_data = read_process_data(ticket.process_id.clone()).await;
+	if let Err(e) = process_data {
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 	}
+	let process_data = process_data.unwrap();
 
-	// remove the ticket from user_active_tickets
-	let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1 and userid=$2")
-		.bind(ticket_id)
-		.bind(payload.user_id)
-		.execute(&mut *tx)
-		.await;
+	if let Err(e) = validate_node(&process_data, payload.node, payload.status, source) {
+		log(LogType::Error, format!("Invalid node {} in update of ticket {} from {}: {:?}", payload.node, ticket.id, payload.user_id, e), ticket.log_id);
+		return Err(e);
+	}
+	// event of the node being completed
+	let completed_event = process_data.steps.get(payload.node as usize).map(|s| s.event.clone());
+
+	let reason = payload.reason.as_deref().map(str::trim).unwrap_or_default().to_string();
+	if !payload.status && reason.is_empty() {
+		return Err(UpdateErr::InvalidData(vec![FieldError {
+			field: "/reason".to_string(),
+			message: "A reason is required to reject a ticket".to_string()
+		}]));
+	}
 
-	if let Err(e) = query {
-		log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket_id, e), ticket.log_id)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	// validate the submitted data against the schema of the node being completed
+	if payload.status {
+		if let Some(node_schema) = process_data.steps.get(payload.node as usize).and_then(|s| s.schema.as_ref()) {
+			let data = payload.data.clone().unwrap_or_default();
+			if let Err(errors) = schema::validate_node_data(node_schema, &data) {
+				log(LogType::Error, format!("Invalid data for node {} of ticket {} from {}: {:?}", payload.node, ticket.id, payload.user_id, errors), ticket.log_id);
+				return Err(UpdateErr::InvalidData(errors));
+			}
+		}
+	}
+
+	// an approved document of the type the node requires has to be attached first
+	if payload.status {
+		if let Some(doc_type) = process_data.steps.get(payload.node as usize).and_then(|s| s.requires_document.as_ref()) {
+			match documents::has_approved_document(&mut *conn, ticket_id, doc_type).await {
+				Err(e) => {
+					log(LogType::Error, format!("Error reading the documents of ticket {}: {}", ticket_id, e), ticket.log_id);
+					return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+				}
+				Ok(false) => {
+					return Err(UpdateErr::InvalidRequest(vec![FieldError {
+						field: "/node".to_string(),
+						message: format!("Node {} needs an approved {} document attached to the ticket", payload.node, doc_type)
+					}]));
+				}
+				Ok(true) => {}
+			}
+		}
+	}
+
+	let mut watcher_messages = Vec::new();
+	// pushed to connected users once the transaction is committed
+	let mut events = Vec::new();
+	// published to the event stream
+	let mut engine_events = Vec::new();
+	// recorded in ticket_events
+	let mut transitions = Vec::new();
+
+	release(&mut PgRepository::new(&mut *conn), &ticket, &payload, completed_event.as_ref()).await?;
+
+	// the signal is claimed in the same transaction, so it stays pending for a retry if the update fails
+	if source == UpdateSource::Signal {
+		let query = sqlx::query(
+			r#"update ticket_signals set active=false, received_at=$3 where id=(
+				select id from ticket_signals where ticketid=$1 and node_number=$2 and active=true order by id limit 1
+			)"#)
+			.bind(ticket_id)
+			.bind(payload.node)
+			.bind(chrono::Utc::now())
+			.execute(&mut *conn)
+			.await;
+		if let Err(e) = query {
+			log(LogType::Error, format!("Error claiming the signal of node {} of ticket {}: {}", payload.node, ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		if query.unwrap().rows_affected() == 0 {
+			log(LogType::Error, format!("Signal of node {} of ticket {} is not pending", payload.node, ticket_id), ticket.log_id);
+			return Err(StatusCode::CONFLICT.into());
+		}
 	}
 
 	// user rejected the ticket
 	if !payload.status {
-		let query = sqlx::query("update tickets set status='rejected' where id=$1")
+		reject(&mut PgRepository::new(&mut *conn), &mut ticket).await?;
+
+		if let Err(e) = callbacks::cancel_jobs(&mut *conn, ticket_id).await {
+			log(LogType::Error, format!("Error cancelling the callback jobs of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+
+		let query = sqlx::query("insert into ticket_rejections (ticketid, node_number, userid, reason, created_at) values ($1, $2, $3, $4, $5)")
 			.bind(ticket_id)
-			.execute(&mut *tx)
+			.bind(payload.node)
+			.bind(payload.user_id)
+			.bind(&reason)
+			.bind(chrono::Utc::now())
+			.execute(&mut *conn)
 			.await;
 		if let Err(e) = query {
-			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket_id, e), ticket.log_id)?;
-			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			log(LogType::Error, format!("Error saving rejection of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 		}
 
-		let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1")
-			.bind(ticket_id)
-			.execute(&mut *tx)
+		let query = sqlx::query("insert into notifications (userid, message, created_at) values ($1, $2, $3)")
+			.bind(ticket.owner_id)
+			.bind(format!("Your ticket {} was rejected at node {}. Reason: {}", ticket.id, payload.node, reason))
+			.bind(chrono::Utc::now())
+			.execute(&mut *conn)
 			.await;
 		if let Err(e) = query {
-			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket_id, e), ticket.log_id)?;
-			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+			log(LogType::Error, format!("Error notifying owner of rejected ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		if let Err(e) = outbox::enqueue(&mut *conn).await {
+			log(LogType::Error, format!("Error queueing notifier ping for ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 		}
+		let event = AuditEvent::new(Some(payload.user_id), AuditAction::Reject, "ticket", ticket.id,
+			Some(serde_json::json!({"status": ticket.status, "node": payload.node, "instance": payload.instance})),
+			Some(serde_json::json!({"status": "rejected", "node": payload.node, "instance": payload.instance, "reason": reason})));
+		if let Err(e) = audit::record(&mut *conn, event).await {
+			log(LogType::Error, format!("Error auditing rejection of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		ticket.status = TicketStatus::Rejected;
+		transitions.push(TicketEvent {
+			kind: TicketEventKind::Rejected,
+			node: Some(payload.node),
+			instance: payload.instance,
+			actor: Some(payload.user_id),
+			change: Change::status(TicketStatus::Rejected)
+		});
+		let detail = serde_json::json!({"node": payload.node, "instance": payload.instance, "user_id": payload.user_id, "reason": reason});
+		engine_events.push(WorkflowEvent::new(EngineEvent::TicketRejected, &ticket, Some(payload.node), detail.clone()));
+		if let Err(e) = webhooks::enqueue(&mut *conn, WebhookEvent::Rejected, &ticket, detail).await {
+			log(LogType::Error, format!("Error queueing webhooks of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+
 		log(LogType::Rejection, 
-			format!("Ticket {} rejected by {}, message: {:?}", ticket.id, payload.user_id, payload.data),
-			ticket.log_id)?;
+			format!("Ticket {} rejected by {} at node {}, reason: {}", ticket.id, payload.user_id, payload.node, reason),
+			ticket.log_id);
+		watcher_messages.push(format!("Ticket {} was rejected. Process Id: {}", ticket.id, ticket.process_id));
 	}
 	else {
 		// user accepted the ticket
 		// update the state
-		match payload.data.clone() {
-			Some(mut data) => {
-				let mut new_state = serde_json::value::from_value::<Map<String, serde_json::Value>>(ticket.state).unwrap();
-				new_state.append(&mut data);
-				ticket.state = serde_json::Value::Object(new_state);
-			}
-			None => {}
+		let start = Projection::of(&ticket);
+		if let Err(e) = save_node_data(&mut *conn, ticket.id, payload.node, payload.instance, payload.user_id, payload.data.as_ref()).await {
+			log(LogType::Error, format!("Error saving the data of node {} of ticket {}: {:?}", payload.node, ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 		}
-		// process the update
-		let result = update_internal(&mut ticket, &payload).await;
-		if let Err(e) = result {
-			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id)?;
-			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		// the data is a merge patch, nested objects are merged and a null removes the key
+		if let Some(data) = payload.data.as_ref() {
+			utils::merge_patch(&mut ticket.state, data);
 		}
-
-		for new_ticket in result.unwrap() {
-			match new_ticket.type_ {
-				NewUserTicketType::ApproveRequest => {
-					let new_ticket_username = new_ticket.username.unwrap();
-					let userid_query: Result<Userid, _> = sqlx::query_as("select userid from users where username=$1")
-						.bind(&new_ticket_username)
-						.fetch_one(&mut *tx)
-						.await;
-
-					if let Err(e) = userid_query {
-						log(LogType::Error, format!("Error reading userid from db: {}", e), ticket.log_id)?;
-						return Err(StatusCode::INTERNAL_SERVER_ERROR);
-					}
-					let userid = userid_query.unwrap();
-
-
-					let query = sqlx::query("insert into user_active_tickets (userid, ticketid, active, node_number, type_) values ($1, $2, $3, $4, $5)")
-						.bind(userid.userid)
-						.bind(new_ticket.ticket_id)
-						.bind(true)
-						.bind(new_ticket.node)
-						.bind("approve")
-						.execute(&mut *tx)
-						.await;
-					if let Err(e) = query {
-						log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id)?;
-						return Err(StatusCode::INTERNAL_SERVER_ERROR);
-					}
-					log(LogType::Request, format!("Ticket {} approval requested from {}", ticket.id, userid.userid), ticket.log_id)?;
+		transitions.push(TicketEvent {
+			kind: TicketEventKind::Updated,
+			node: Some(payload.node),
+			instance: payload.instance,
+			actor: Some(payload.user_id),
+			change: start.change_to(&Projection::of(&ticket))
+		});
+		// an approver whose limit is below state.amount hands the node to the next manager up instead of completing it
+		let mut escalation = None;
+		if completed_event == Some(Event::Approve) {
+			match limits::escalation(&mut *conn, payload.user_id, &ticket.state).await {
+				Ok(found) => escalation = found,
+				Err(EscalateErr::NoManager) => {
+					return Err(UpdateErr::InvalidRequest(vec![FieldError {
+						field: "/node".to_string(),
+						message: "The amount is over your approval limit and there is no manager above you to approve it".to_string()
+					}]));
 				}
-				NewUserTicketType::Notify => {
-					// insert a new ticket into notifications table and ping the notifier server
-
-					let owner_name_query: Result<Username, _> = sqlx::query_as("select username from users where userid=$1")
-						.bind(ticket.owner_id)
-						.fetch_one(&mut *tx)
-						.await;
-
-					if let Err(e) = owner_name_query {
-						admin_logger(LogType::Error, &format!("failed to get owner name in notification NewUserTicket. create request from {}. Error: {}", ticket.owner_id, e), None)
-							.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-						return Err(StatusCode::INTERNAL_SERVER_ERROR);
-					}
-					let message = format!("Ticket created by {}. Process Id: {}", ticket.owner_id, ticket.process_id);
-
-					let notified_username = new_ticket.username.as_ref().unwrap();
-
-					// add notification
-					let query = sqlx::query("insert into notifications (userid, message, created_at) values ((select userid from users where username=$1), $2, $3)")
-						.bind(notified_username)
-						.bind(message)
-						.bind(chrono::Utc::now())
-						.execute(&mut *tx)
-						.await;
-
-					if let Err(e) = query {
-						admin_logger(LogType::Error, &format!("failed to add notification in NewUserTicket. create request from {}, Error: {}", ticket.owner_id, e), None)
-							.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-						return Err(StatusCode::INTERNAL_SERVER_ERROR);
-					}
-					
-					log(LogType::NotificationSuccess, format!("Notification sent to notifier for user {} notified for ticket {}", notified_username, ticket.id), ticket.log_id)
-						.map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
+				Err(EscalateErr::Db(e)) => {
+					log(LogType::Error, format!("Error reading the approval limit of {} for ticket {}: {:?}", payload.user_id, ticket.id, e), ticket.log_id);
+					return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 				}
-				NewUserTicketType::Completion => {
-					// this ticket is always the last in the new_ticket_queue because it requires all other nodes to be executed first
-					let query = sqlx::query("update user_active_tickets set active=false where ticketid=$1")
-						.bind(ticket_id)
-						.execute(&mut *tx)
-						.await;
-					if let Err(e) = query {
-						log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id)?;
-						return Err(StatusCode::INTERNAL_SERVER_ERROR);
-					}
-					ticket.status = "closed".to_string();
-					log(LogType::Completion, format!("Ticket {} completed", ticket.id), ticket.log_id)?;
-				}	
 			}
 		}
+		if completed_event == Some(Event::Approve) {
+			let escalated_to = escalation.as_ref().map(|e| e.userid);
+			let event = AuditEvent::new(Some(payload.user_id), AuditAction::Approve, "ticket", ticket.id,
+				Some(serde_json::json!({"node": payload.node, "instance": payload.instance, "approved": false})),
+				Some(serde_json::json!({"node": payload.node, "instance": payload.instance, "approved": true, "data": payload.data, "escalated_to": escalated_to})));
+			if let Err(e) = audit::record(&mut *conn, event).await {
+				log(LogType::Error, format!("Error auditing approval of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+			}
+		}
+		if completed_event == Some(Event::Approve) && escalation.is_none() {
+			watcher_messages.push(format!("Ticket {} was approved at node {}. Process Id: {}", ticket.id, payload.node, ticket.process_id));
+			let detail = serde_json::json!({"node": payload.node, "instance": payload.instance, "user_id": payload.user_id});
+			if let Err(e) = webhooks::enqueue(&mut *conn, WebhookEvent::Approved, &ticket, detail).await {
+				log(LogType::Error, format!("Error queueing webhooks of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+				return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+			}
+		}
+		// process the update
+		let mut jobs = Vec::new();
+		let before = ticket.complete;
+		let result = match &escalation {
+			// the node stays open and is requested from the manager like any other approval
+			Some(escalation) => {
+				if let Err(e) = limits::record_escalation(&mut *conn, ticket.id, payload.node, payload.instance, payload.user_id, escalation).await {
+					log(LogType::Error, format!("Error saving the escalation of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+					return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+				}
+				log(LogType::Approval,
+					format!("Ticket {} node {} approved by {} up to their limit of {}, the amount of {} goes to {}", ticket.id, payload.node, payload.user_id, escalation.max_amount, escalation.amount, escalation.username),
+					ticket.log_id);
+				watcher_messages.push(format!("Ticket {} was escalated to {} at node {}. Process Id: {}", ticket.id, escalation.username, payload.node, ticket.process_id));
+				ticket.update_time();
+				Ok(vec![NewUserTicket {
+					type_: NewUserTicketType::Escalation { from: payload.user_id },
+					ticket_id: ticket.id,
+					node: payload.node,
+					username: Some(escalation.username.clone()),
+					instance: payload.instance
+				}])
+			}
+			None => update_internal(&mut ticket, &payload, &mut jobs, &mut transitions).await
+		};
+		if let Err(e) = result {
+			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		for node in events::completed_nodes(before, ticket.complete) {
+			let detail = if node == payload.node { serde_json::json!({"user_id": payload.user_id, "instance": payload.instance}) } else { serde_json::json!({}) };
+			engine_events.push(WorkflowEvent::new(EngineEvent::NodeCompleted, &ticket, Some(node), detail));
+		}
+		if let Err(e) = callbacks::enqueue_jobs(&mut *conn, &jobs).await {
+			log(LogType::Error, format!("Error saving callbacks of ticket {}: {:?}", ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
 
-		// update all fields of the ticket
-		// TODO: there may be a better way of doing this, serializing multiple times here i think.
-		let final_state = serde_json::value::from_value::<Map<String, serde_json::Value>>(ticket.state).unwrap();
-		let query = sqlx::query("update tickets set status=$1, complete=$2, updated_at=$3, state=$4 where id=$5")
-			.bind(&ticket.status)
-			.bind(ticket.complete)
-			.bind(ticket.updated_at)
-			.bind(&serde_json::Value::Object(final_state))
-			.bind(ticket_id)
-			.execute(&mut *tx)
-			.await;
+		let applied = apply_new_tickets(&mut *conn, &mut ticket, result.unwrap()).await?;
+		events.extend(applied.events);
+		engine_events.extend(applied.engine_events);
+		watcher_messages.extend(applied.watcher_messages);
+		transitions.extend(applied.ticket_events);
 
-		if let Err(e) = query {
-			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id)?;
-			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		// update all fields of the ticket
+		if let Err(e) = PgRepository::new(&mut *conn).save(&ticket).await {
+			log(LogType::Error, format!("Error updating ticket: id = {} :  {:?}",ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 		}
 	}
 
-
-	if let Err(e) = tx.commit().await {
-		log(LogType::Error, format!("Error commiting transaction: {} for pid {}", e, ticket_id), ticket.log_id)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
-
+	for message in watcher_messages.iter() {
+		if let Err(e) = watchers::notify_watchers(&mut *conn, ticket_id, message).await {
+			log(LogType::Error, format!("Error notifying watchers of ticket {}: {}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
 	}
-	return Ok(StatusCode::ACCEPTED);
+	match linked::follow(&mut *conn, &engine_events).await {
+		Err(linked::FollowErr::Db(e)) => {
+			log(LogType::Error, format!("Error moving the record of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+		}
+		// e.g. the leave balance ran out since the request was made
+		Err(linked::FollowErr::Refused(message)) => {
+			log(LogType::Warning, format!("Record of ticket {} refused its transition: {}", ticket_id, message), ticket.log_id);
+			return Err(UpdateErr::InvalidRequest(vec![FieldError { field: "/node".to_string(), message }]));
+		}
+		Ok(()) => {}
+	}
+	if let Err(e) = events::record(&mut *conn, &engine_events).await {
+		log(LogType::Error, format!("Error recording events of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	if let Err(e) = ticket_events::record(&mut *conn, ticket_id, &transitions).await {
+		log(LogType::Error, format!("Error recording the transitions of ticket {}: {:?}", ticket_id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	return Ok(events);
 }
 
-async fn update_internal(ticket: &mut Ticket, request: &UpdateTicket) -> Result<Vec<NewUserTicket>, ExecuteErr> {
+// the callbacks of the reached nodes are added to `jobs` and what every executed node changed to `transitions`, the caller
+// saves them in its transaction. pub for benches/engine.rs
+pub async fn update_internal(
+	ticket: &mut Ticket,
+	request: &UpdateTicket,
+	jobs: &mut Vec<CallbackJob>,
+	transitions: &mut Vec<TicketEvent>
+) -> Result<Vec<NewUserTicket>, ExecuteErr> {
 	let mut node_queue = VecDeque::new();
 	let mut ticket_queue = Vec::new();
-	let process_data = read_process_data(ticket.process_id.clone());
+	let process_data = read_process_data(ticket.process_id.clone()).await;
 	if let Err(e) = process_data {
-		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id)
-			.map_err(|_| ExecuteErr::FailedToLog)?;
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
 		return Err(ExecuteErr::FailedToReadProcessData);
 	}
 	let process_data = process_data.unwrap();
 	// process the first request
 	// TODO: currently exec_user_request will not return any new ticket that has to be added. this may change later
-	let result = execute_user_request(ticket, request.node, request.data.as_ref()).await?;
+	let before = Projection::of(ticket);
+	let result = execute_user_request(ticket, request.node, request.instance, request.data.as_ref()).await?;
+	transitions.push(TicketEvent {
+		kind: TicketEventKind::NodeExecuted,
+		node: Some(request.node),
+		instance: request.instance,
+		actor: Some(request.user_id),
+		change: before.change_to(&Projection::of(ticket))
+	});
 	node_queue.extend(result.completable_steps.iter());
+	jobs.extend(result.callback_jobs);
 
 	// FIXME: cleanup this code
 	while let Some(node) = node_queue.pop_front() {
+		let before = Projection::of(ticket);
 		let result = execute_completable(ticket, node, &process_data).await?;
+		transitions.push(TicketEvent {
+			kind: TicketEventKind::NodeExecuted,
+			node: Some(node),
+			instance: None,
+			actor: None,
+			change: before.change_to(&Projection::of(ticket))
+		});
 		if !result.completable_steps.is_empty() {
 			node_queue.extend(result.completable_steps.iter());
 		}
+		jobs.extend(result.callback_jobs);
 
-		if let Some(t) = result.new_ticket {
-			ticket_queue.push(t);
-		}
+		ticket_queue.extend(result.new_tickets);
 	}
 	return Ok(ticket_queue);
 }
 
-async fn execute_user_request(ticket: &mut Ticket, current_node: i32, data: Option<&Map<String, serde_json::Value>>) -> Result<SingleExecState, ExecuteErr>{
-	let process_data = read_process_data(ticket.process_id.clone());
+async fn execute_user_request(ticket: &mut Ticket, current_node: i32, instance: Option<i32>, data: Option<&Map<String, serde_json::Value>>) -> Result<SingleExecState, ExecuteErr>{
+	let process_data = read_process_data(ticket.process_id.clone()).await;
 	if let Err(e) = process_data {
-		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id)
-			.map_err(|_| ExecuteErr::FailedToLog)?;
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
 		return Err(ExecuteErr::FailedToReadProcessData);
 	}
 
 	let process_data = process_data.unwrap();
-	let current_job = process_data.steps[current_node as usize].clone();
+	// apply_update validates the node, this only guards against other callers
+	let current_job = process_data.steps.get(current_node as usize).cloned();
+	if current_job.is_none() {
+		log(LogType::Error, format!("Node {} does not exist in process {}", current_node, process_data.pid), ticket.log_id);
+		return Err(ExecuteErr::InvalidTicket);
+	}
+	let current_job = current_job.unwrap();
 
 	// execute the callback for the current node
 	// if the current step is a BlockingTask then the callbacks have already been completed,
 	// this request comes from callback server
+	let mut callback_jobs = Vec::new();
 	if current_job.is_not_blocking_task() {
 		let current_callbacks = current_job.callbacks.unwrap_or(vec![]);
 		if !current_callbacks.is_empty() {
-			let ticket_id = ticket.id;
-			let data = match data {
-				Some(d) => Some(d.clone()),
-				None => None
-			};
-			tokio::spawn(async move {
-				send_task(ticket_id, current_node,&data, &current_callbacks).await;
-			});
+			callback_jobs.push(CallbackJob { ticket_id: ticket.id, node: current_node, payload: data.cloned(), callbacks: current_callbacks });
 		}
 	}
 	
@@ -541,7 +1392,8 @@ async fn execute_user_request(ticket: &mut Ticket, current_node: i32, data: Opti
 	let mut result = SingleExecState {
 		status: TicketStatus::Open,
 		completable_steps: Vec::new(),
-		new_ticket: None
+		new_tickets: Vec::new(),
+		callback_jobs
 	};
 
 	let next_steps = current_job.next;
@@ -553,34 +1405,46 @@ async fn execute_user_request(ticket: &mut Ticket, current_node: i32, data: Opti
 			if next_steps.is_empty() {
 				result.status = TicketStatus::Closed;
 			}
-			log(LogType::Info, format!("Ticket {} initiated succssfully", ticket.id), ticket.log_id)
-				.map_err(|_| ExecuteErr::FailedToLog)?;
+			log(LogType::Info, format!("Ticket {} initiated succssfully", ticket.id), ticket.log_id);
 		}
 		Event::Approve => {
+			if current_job.multi_instance.is_some() {
+				// the node is only completed once every instance has been approved
+				match instance.and_then(|i| utils::complete_instance(&mut ticket.instances, current_node, i)) {
+					None => {
+						log(LogType::Error, format!("Invalid instance {:?} for node {} of ticket {}", instance, current_node, ticket.id), ticket.log_id);
+						return Err(ExecuteErr::InvalidTicket);
+					}
+					Some(false) => {
+						ticket.update_time();
+						log(LogType::Approval,
+							format!("Ticket {} instance {} of node {} approved by {}", ticket.id, instance.unwrap(), current_node, current_job.args.unwrap()[0]),
+							ticket.log_id);
+						return Ok(result);
+					}
+					Some(true) => {}
+				}
+			}
 			ticket.complete |= 1 << current_node;
 			ticket.update_time();
 			log(LogType::Approval, 
 				format!("Ticket {} approved by {}", ticket.id, current_job.args.unwrap()[0]),
-				ticket.log_id)
-				.map_err(|_| ExecuteErr::FailedToLog)?;
+				ticket.log_id);
 		}
 		Event::Notify => {
 			// this event cannot be reached through user request.
 			// Notify nodes in a process are completed instantly and only send a notification about the ticket to the given user
-			log(LogType::Error, format!("Attempt to complete notify node for tickt {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id)
-				.map_err(|_e| ExecuteErr::FailedToLog)?;
+			log(LogType::Error, format!("Attempt to complete notify node for tickt {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id);
 			return Err(ExecuteErr::InvalidTicket);
 		}
 		Event::Complete => {
 			// no user should be able to complete this event
-			log(LogType::Error, format!("Attempt to complete ticket {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id)
-				.map_err(|_| ExecuteErr::FailedToLog)?;
+			log(LogType::Error, format!("Attempt to complete ticket {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id);
 			return Err(ExecuteErr::InvalidTicket);
 		},
 		Event::NonBlockingTask => {
 			// Same as Event::Notify. cannot be reached through user request
-			log(LogType::Error, format!("Attempt to execute NonBlockingTask node ticket {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id)
-				.map_err(|_| ExecuteErr::FailedToLog)?;
+			log(LogType::Error, format!("Attempt to execute NonBlockingTask node ticket {} from {}", ticket.id, current_job.args.unwrap()[0]), ticket.log_id);
 			return Err(ExecuteErr::InvalidTicket);
 		},
 		Event::BlockingTask => {
@@ -589,8 +1453,20 @@ async fn execute_user_request(ticket: &mut Ticket, current_node: i32, data: Opti
 			ticket.update_time();
 			log(LogType::Approval, 
 				format!("Ticket {} approved from callback", ticket.id),
-				ticket.log_id)
-				.map_err(|_| ExecuteErr::FailedToLog)?;
+				ticket.log_id);
+		}
+		Event::Script => {
+			// Same as Event::Notify. scripts run as soon as the node is reached
+			log(LogType::Error, format!("Attempt to execute Script node ticket {} from {}", ticket.id, current_node), ticket.log_id);
+			return Err(ExecuteErr::InvalidTicket);
+		},
+		Event::Wait => {
+			// only reachable through the signal endpoint
+			ticket.complete |= 1 << current_node;
+			ticket.update_time();
+			log(LogType::Approval,
+				format!("Ticket {} received signal {}", ticket.id, current_job.args.unwrap()[0]),
+				ticket.log_id);
 		}
 	}
 
@@ -614,7 +1490,8 @@ async fn execute_completable(ticket: &mut Ticket, current_node: i32, process: &P
 	let mut result = SingleExecState {
 		status: TicketStatus::Open,
 		completable_steps: Vec::new(),
-		new_ticket: None
+		new_tickets: Vec::new(),
+		callback_jobs: Vec::new()
 	};
 	let current_job = process.steps[current_node as usize].clone();
 	// TODO: callbacks with data for completable steps
@@ -623,10 +1500,7 @@ async fn execute_completable(ticket: &mut Ticket, current_node: i32, process: &P
 	if current_job.is_not_approve() {
 		let callbacks = current_job.callbacks.unwrap_or(vec![]);
 		if !callbacks.is_empty() {
-			let ticket_id = ticket.id;
-			tokio::spawn(async move {
-				send_task(ticket_id, current_node,&None, &callbacks).await;
-			});
+			result.callback_jobs.push(CallbackJob { ticket_id: ticket.id, node: current_node, payload: None, callbacks });
 		}
 	}	
 	
@@ -636,30 +1510,79 @@ async fn execute_completable(ticket: &mut Ticket, current_node: i32, process: &P
 			return Err(ExecuteErr::InvalidEvent);
 		}
 		Event::Approve => {
-			result.new_ticket = Some(NewUserTicket {
-				type_: NewUserTicketType::ApproveRequest,
-				ticket_id: ticket.id,
-				node: current_node,
-				username: Some(current_job.args.unwrap()[0].clone())
-			});
+			let auto_approved = match &current_job.auto_approve_if {
+				Some(rule) => match script::eval_condition(rule, &ticket.state) {
+					Ok(matched) => matched,
+					Err(e) => {
+						// a broken rule falls back to asking the approver
+						log(LogType::Warning, format!("Auto approve rule '{}' of node {} failed for ticket {}: {}", rule, current_node, ticket.id, e), ticket.log_id);
+						false
+					}
+				},
+				None => false
+			};
+			let username = current_job.args.unwrap()[0].clone();
+			if auto_approved {
+				ticket.complete |= 1 << current_node;
+				ticket.update_time();
+				log(LogType::Approval,
+					format!("Ticket {} node {} auto-approved on behalf of {} (rule: {})", ticket.id, current_node, username, current_job.auto_approve_if.as_ref().unwrap()),
+					ticket.log_id);
+			}
+			else {
+				match &current_job.multi_instance {
+					Some(key) => {
+						// one approval request per element of the array
+						let total = ticket.state.get(key).and_then(|v| v.as_array()).map(|a| a.len()).unwrap_or(0);
+						if total == 0 {
+							// nothing to approve
+							ticket.complete |= 1 << current_node;
+						}
+						else {
+							utils::start_instances(&mut ticket.instances, current_node, total);
+						}
+						ticket.update_time();
+						for i in 0..total {
+							result.new_tickets.push(NewUserTicket {
+								type_: NewUserTicketType::ApproveRequest,
+								ticket_id: ticket.id,
+								node: current_node,
+								username: Some(username.clone()),
+								instance: Some(i as i32)
+							});
+						}
+					}
+					None => {
+						result.new_tickets.push(NewUserTicket {
+							type_: NewUserTicketType::ApproveRequest,
+							ticket_id: ticket.id,
+							node: current_node,
+							username: Some(username),
+							instance: None
+						});
+					}
+				}
+			}
 		}
 		Event::Notify => {
 			// this step can be completed right now
 			ticket.complete |= 1 << current_node;
-			result.new_ticket = Some(NewUserTicket {
+			result.new_tickets.push(NewUserTicket {
 				type_: NewUserTicketType::Notify,
 				ticket_id: ticket.id,
 				node: current_node,
-				username: Some(current_job.args.unwrap()[0].clone())
+				username: Some(current_job.args.unwrap()[0].clone()),
+				instance: None
 			});
 		}
 		Event::Complete => {
 			ticket.update_time();
-			result.new_ticket = Some(NewUserTicket {
+			result.new_tickets.push(NewUserTicket {
 				type_: NewUserTicketType::Completion,
 				ticket_id: ticket.id,
 				node: current_node,
-				username: None
+				username: None,
+				instance: None
 			});
 
 			return Ok(result);
@@ -672,6 +1595,32 @@ async fn execute_completable(ticket: &mut Ticket, current_node: i32, process: &P
 			// Do nothing. callbacks are already sent so just wait for them to move this node forward
 			ticket.update_time();
 		},
+		Event::Script => {
+			// args[0] is the script source. the script can read and write ticket.state
+			let source = current_job.args.as_ref().and_then(|a| a.first()).cloned().unwrap_or_default();
+			match script::run_script(&source, &ticket.state) {
+				Ok(new_state) => {
+					ticket.state = new_state;
+				}
+				Err(e) => {
+					log(LogType::Error, format!("Script node {} of ticket {} failed: {}", current_node, ticket.id, e), ticket.log_id);
+					return Err(ExecuteErr::FailedToExecute);
+				}
+			}
+			ticket.update_time();
+			ticket.complete |= 1 << current_node;
+		}
+		Event::Wait => {
+			// park the ticket until an external system sends the signal named in args[0]
+			ticket.update_time();
+			result.new_tickets.push(NewUserTicket {
+				type_: NewUserTicketType::AwaitSignal,
+				ticket_id: ticket.id,
+				node: current_node,
+				username: None,
+				instance: None
+			});
+		}
 	}
 	let next_steps = current_job.next;
 	for step in next_steps {
@@ -685,60 +1634,554 @@ async fn execute_completable(ticket: &mut Ticket, current_node: i32, process: &P
 			result.completable_steps.push(step);
 		}
 	}
-	return Ok(result);
+	return Ok(result);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {Priority, CreatedAt, UpdatedAt}
+
+// position of the last row of the previous page, sent to clients as "<priority>,<sort time>,<row id>"
+#[derive(Debug, PartialEq)]
+pub(crate) struct Cursor {
+	pub priority: i32,
+	pub time: chrono::DateTime<chrono::Utc>,
+	pub id: i32
+}
+
+pub(crate) fn parse_cursor(cursor: &str) -> Option<Cursor> {
+	let mut parts = cursor.split(',');
+	let priority = parts.next()?.parse().ok()?;
+	let time = chrono::DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+	let id = parts.next()?.parse().ok()?;
+	if parts.next().is_some() {
+		return None;
+	}
+	return Some(Cursor { priority, time: time.with_timezone(&chrono::Utc), id });
+}
+
+pub(crate) fn make_cursor(cursor: &Cursor) -> String {
+	return format!("{},{},{}", cursor.priority, cursor.time.to_rfc3339(), cursor.id);
+}
+
+// adds the filters of the ticket lists. `t` is the alias of the tickets table
+pub fn push_ticket_filters(builder: &mut QueryBuilder<'_, Postgres>, query: &GetUserTicketsReq) {
+	if let Some(status) = &query.status {
+		builder.push(" and t.status=").push_bind(*status);
+	}
+	if let Some(process_id) = &query.process_id {
+		builder.push(" and t.process_id=").push_bind(process_id.clone());
+	}
+	if let Some(priority) = query.priority {
+		builder.push(" and t.priority=").push_bind(priority);
+	}
+	if let Some(tag) = &query.tag {
+		builder.push(" and exists (select 1 from ticket_tags tt where tt.ticketid=t.id and tt.tag=")
+			.push_bind(tag.trim().to_lowercase())
+			.push(")");
+	}
+	if let Some(from) = query.from {
+		builder.push(" and t.created_at>=").push_bind(from);
+	}
+	if let Some(to) = query.to {
+		builder.push(" and t.created_at<").push_bind(to);
+	}
+}
+
+// adds the filters, keyset condition, ordering and limit shared by both ticket lists.
+// `row_id` is the column the cursor refers to
+fn push_page_clauses(
+	builder: &mut QueryBuilder<'_, Postgres>,
+	query: &GetUserTicketsReq,
+	sort: SortKey,
+	desc: bool,
+	row_id: &str,
+	cursor: Option<&Cursor>,
+	limit: i64
+) {
+	push_ticket_filters(builder, query);
+
+	// descending priority means the most urgent and then the oldest tickets first
+	let (keys, ascending) = match sort {
+		SortKey::Priority => (vec!["-t.priority", "t.created_at", row_id], desc),
+		SortKey::CreatedAt => (vec!["t.created_at", row_id], !desc),
+		SortKey::UpdatedAt => (vec!["t.updated_at", row_id], !desc)
+	};
+	if let Some(cursor) = cursor {
+		builder.push(format!(" and ({}) {} (", keys.join(", "), if ascending { ">" } else { "<" }));
+		if sort == SortKey::Priority {
+			builder.push_bind(-cursor.priority).push(", ");
+		}
+		builder.push_bind(cursor.time)
+			.push(", ")
+			.push_bind(cursor.id)
+			.push(")");
+	}
+	let direction = if ascending { "asc" } else { "desc" };
+	let order = keys.iter().map(|k| format!("{} {}", k, direction)).collect::<Vec<_>>().join(", ");
+	builder.push(format!(" order by {} limit ", order))
+		.push_bind(limit + 1);
 }
 
+#[utoipa::path(get, path = "/ticket/user", tag = "tickets", params(GetUserTicketsReq), responses(
+	(status = 200, description = "Tickets waiting on the user and tickets they own, paged", body = UserTickets),
+	(status = 400, description = "Unknown sort, order or cursor", body = Problem, content_type = "application/problem+json")
+))]
 pub async fn get_user_tickets(
-	query: extract::Query<GetUserTicketsReq>,
+	user: AuthUser,
+	ValidQuery(query): ValidQuery<GetUserTicketsReq>,
 	extract::State(pool): extract::State<sqlx::PgPool>
-) -> Result<(StatusCode, Json<UserTickets>), StatusCode> {
-	let userid = uuid::Uuid::parse_str(&query.0.userid).unwrap();
+) -> Result<(StatusCode, Json<UserTickets>), ApiError> {
+	let pool = replica::read_pool(pool);
+	let userid = user.userid;
 	let mut result = UserTickets {
 		current_tickets: Vec::new(),
-		own_tickets: Vec::new()
+		own_tickets: Vec::new(),
+		next_cursor: NextCursor::default()
+	};
+
+	// sort column and direction are pasted into the query so only known values are accepted.
+	// approvers see urgent tickets first unless they ask for something else
+	let (current_sort, own_sort) = match query.sort.as_deref() {
+		None => (SortKey::Priority, SortKey::UpdatedAt),
+		Some("priority") => (SortKey::Priority, SortKey::Priority),
+		Some("created_at") => (SortKey::CreatedAt, SortKey::CreatedAt),
+		Some("updated_at") => (SortKey::UpdatedAt, SortKey::UpdatedAt),
+		Some(_) => return Err(ApiError::field(StatusCode::BAD_REQUEST, "invalid_query", "/sort", "Expected priority, created_at or updated_at"))
+	};
+	let desc = match query.order.as_deref() {
+		None | Some("desc") => true,
+		Some("asc") => false,
+		Some(_) => return Err(ApiError::field(StatusCode::BAD_REQUEST, "invalid_query", "/order", "Expected asc or desc"))
+	};
+	let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+	let current_cursor = match &query.current_cursor {
+		Some(c) => Some(parse_cursor(c).ok_or_else(|| ApiError::field(StatusCode::BAD_REQUEST, "invalid_query", "/current_cursor", "Unknown cursor"))?),
+		None => None
+	};
+	let own_cursor = match &query.own_cursor {
+		Some(c) => Some(parse_cursor(c).ok_or_else(|| ApiError::field(StatusCode::BAD_REQUEST, "invalid_query", "/own_cursor", "Unknown cursor"))?),
+		None => None
 	};
 
 	// select all tickets from user_active_tickets of type_!="own"
-	let current_ticket_query: Result<Vec<CurrentTicket>, _> = 
-		sqlx::query_as(r#"select type_, node_number, ticketid, active, user_active_tickets.userid, process_id, username as owner_name 
-			from user_active_tickets join tickets on user_active_tickets.ticketid=tickets.id 
-			join users on tickets.owner_id=users.userid
-			where user_active_tickets.type_!='own' and user_active_tickets.active='true' and user_active_tickets.userid=$1;"#)
-		.bind(userid)
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+		r#"select u.id, u.type_, u.node_number, u.instance, u.ticketid, u.active, u.userid, t.process_id, users.username as owner_name,
+			t.status, t.priority, t.created_at, t.updated_at, t.due_at, coalesce(t.status='open' and t.due_at<now(), false) as overdue
+			from user_active_tickets u join tickets t on u.ticketid=t.id
+			join users on t.owner_id=users.userid
+			where u.type_!='own' and u.active='true' and u.userid="#);
+	builder.push_bind(userid);
+	push_page_clauses(&mut builder, &query, current_sort, desc, "u.id", current_cursor.as_ref(), limit);
+
+	let current_ticket_query: Result<Vec<CurrentTicket>, _> = builder.build_query_as()
 		.fetch_all(&pool)
 		.await;
 	if let Err(e) = current_ticket_query {
-		admin_logger(LogType::Error, &format!("Error reading current tickets: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		admin_logger(LogType::Error, &format!("Error reading current tickets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 	}
-	result.current_tickets = current_ticket_query.unwrap();
+	let mut current_tickets = current_ticket_query.unwrap();
+	// one extra row is fetched to know if there is another page
+	if current_tickets.len() as i64 > limit {
+		current_tickets.truncate(limit as usize);
+		let last = current_tickets.last().unwrap();
+		let time = if current_sort == SortKey::UpdatedAt { last.updated_at } else { last.created_at };
+		result.next_cursor.current_tickets = Some(make_cursor(&Cursor { priority: last.priority, time, id: last.id }));
+	}
+	result.current_tickets = current_tickets;
 
 	// select all tickets from tickets where owner_id=userid
-	let own_ticket_query: Result<Vec<OwnTicket>, _> = 
-		sqlx::query_as("select id, process_id, is_public, created_at, updated_at, status from tickets where owner_id=$1;")
-		.bind(userid)
+	let tickets_source = if query.include_archived.unwrap_or(false) {
+		r#"(select id, owner_id, process_id, is_public, created_at, updated_at, status, priority, due_at from tickets
+			union all select id, owner_id, process_id, is_public, created_at, updated_at, status, priority, due_at from tickets_archive)"#
+	}
+	else {
+		"tickets"
+	};
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+		r#"select t.id, t.process_id, t.is_public, t.created_at, t.updated_at, t.status, t.priority,
+			t.due_at, coalesce(t.status='open' and t.due_at<now(), false) as overdue from {} t where t.owner_id="#, tickets_source));
+	builder.push_bind(userid);
+	push_page_clauses(&mut builder, &query, own_sort, desc, "t.id", own_cursor.as_ref(), limit);
+
+	let own_ticket_query: Result<Vec<OwnTicket>, _> = builder.build_query_as()
 		.fetch_all(&pool)
 		.await;
 
 	if let Err(e) = own_ticket_query {
-		admin_logger(LogType::Error, &format!("Error reading own tickets: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		admin_logger(LogType::Error, &format!("Error reading own tickets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
 	}
 
-	result.own_tickets = own_ticket_query.unwrap();
+	let mut own_tickets = own_ticket_query.unwrap();
+	if own_tickets.len() as i64 > limit {
+		own_tickets.truncate(limit as usize);
+		let last = own_tickets.last().unwrap();
+		let time = if own_sort == SortKey::UpdatedAt { last.updated_at } else { last.created_at };
+		result.next_cursor.own_tickets = Some(make_cursor(&Cursor { priority: last.priority, time, id: last.id }));
+	}
+	result.own_tickets = own_tickets;
 
 	return Ok((StatusCode::OK, Json(result)));
 }
 
+// escapes the wildcards of a user supplied search term for a like pattern
+fn like_pattern(search: &str) -> String {
+	let escaped = search.trim()
+		.replace('\\', "\\\\")
+		.replace('%', "\\%")
+		.replace('_', "\\_");
+	return format!("%{}%", escaped);
+}
+
+// public tickets of every user, newest first. drafts are never listed
+#[utoipa::path(get, path = "/tickets/public", tag = "tickets", params(PublicTicketsReq), responses(
+	(status = 200, description = "Public tickets of every user, newest first", body = PublicTickets),
+	(status = 400, description = "Unknown cursor", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_public_tickets(
+	_user: AuthUser,
+	ValidQuery(query): ValidQuery<PublicTicketsReq>,
+	extract::State(pool): extract::State<sqlx::PgPool>
+) -> Result<Json<PublicTickets>, ApiError> {
+	let pool = replica::read_pool(pool);
+	let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+	let cursor = match &query.cursor {
+		Some(c) => Some(parse_cursor(c).ok_or(StatusCode::BAD_REQUEST)?),
+		None => None
+	};
+
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+		r#"select t.id, t.process_id, users.username as owner_name, t.status, t.created_at
+			from tickets t join users on t.owner_id=users.userid
+			where t.is_public=true and t.status!='draft'"#);
+	if let Some(status) = &query.status {
+		builder.push(" and t.status=").push_bind(*status);
+	}
+	if let Some(search) = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+		let pattern = like_pattern(search);
+		builder.push(" and (t.process_id ilike ")
+			.push_bind(pattern.clone())
+			.push(" or users.username ilike ")
+			.push_bind(pattern)
+			.push(" or t.id::text=")
+			.push_bind(search.to_string())
+			.push(")");
+	}
+	if let Some(cursor) = &cursor {
+		builder.push(" and (t.created_at, t.id) < (")
+			.push_bind(cursor.time)
+			.push(", ")
+			.push_bind(cursor.id)
+			.push(")");
+	}
+	builder.push(" order by t.created_at desc, t.id desc limit ")
+		.push_bind(limit + 1);
+
+	let query: Result<Vec<PublicTicket>, _> = builder.build_query_as()
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading public tickets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let mut tickets = query.unwrap();
+
+	// one extra row is fetched to know if there is another page
+	let mut next_cursor = None;
+	if tickets.len() as i64 > limit {
+		tickets.truncate(limit as usize);
+		let last = tickets.last().unwrap();
+		next_cursor = Some(make_cursor(&Cursor { priority: 0, time: last.created_at, id: last.id }));
+	}
+
+	return Ok(Json(PublicTickets { tickets, next_cursor }));
+}
+
+fn node_progress(process: &Process, complete: i32) -> Vec<NodeProgress> {
+	return process.steps.iter()
+		.enumerate()
+		.map(|(i, step)| NodeProgress {
+			node: i as i32,
+			event: step.event.clone(),
+			complete: utils::check_required_complete(complete, &vec![i as i32]),
+			pending: Vec::new()
+		})
+		.collect::<Vec<_>>();
+}
+
+// archived tickets have nothing pending. their tags and rejections are stored on the archive row
+async fn archived_ticket_detail(pool: &sqlx::PgPool, ticket_id: i32) -> Result<TicketDetail, StatusCode> {
+	let query: Result<Option<ArchivedTicket>, _> = sqlx::query_as(
+		r#"select id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, priority, due_at, state, instances, tags
+			from tickets_archive where id=$1"#)
+		.bind(ticket_id)
+		.fetch_optional(pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading archived ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let ArchivedTicket { ticket, tags } = query.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	let rejections: Result<Vec<Rejection>, _> = sqlx::query_as(
+		r#"select r.node_number, r.userid, r.reason, r.created_at from tickets_archive a,
+			jsonb_to_recordset(a.rejections) as r(node_number int, userid uuid, reason text, created_at timestamptz)
+			where a.id=$1 order by r.created_at"#)
+		.bind(ticket_id)
+		.fetch_all(pool)
+		.await;
+	if let Err(e) = rejections {
+		admin_logger(LogType::Error, &format!("Error reading rejections of archived ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	let process_data = read_process_data(ticket.process_id.clone()).await;
+	if let Err(e) = process_data {
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let nodes = node_progress(&process_data.unwrap(), ticket.complete);
+
+	return Ok(TicketDetail { ticket, tags, rejections: rejections.unwrap(), nodes });
+}
+
+// checks what the user may see of the ticket and rejects the request with 403 if nothing
+async fn authorize_read(conn: &mut sqlx::PgConnection, ticket: &Ticket, user: &AuthUser) -> Result<TicketAccess, StatusCode> {
+	logger::record_ticket(ticket.id);
+	let access = visibility::ticket_access(conn, ticket, user.userid).await;
+	if let Err(e) = access {
+		admin_logger(LogType::Error, &format!("Error checking access of {} to ticket {}: {}", user.userid, ticket.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let access = access.unwrap();
+	if access == TicketAccess::Denied {
+		admin_logger(LogType::Warning, &format!("User {} denied access to ticket {}", user.userid, ticket.id), None);
+		return Err(StatusCode::FORBIDDEN);
+	}
+	return Ok(access);
+}
+
+// what a user that is not involved in a public ticket gets to see
+fn public_detail(mut detail: TicketDetail) -> TicketDetail {
+	detail.ticket.state = serde_json::Value::Object(Map::new());
+	detail.rejections.clear();
+	for node in detail.nodes.iter_mut() {
+		node.pending.clear();
+	}
+	return detail;
+}
+
+#[utoipa::path(get, path = "/ticket/{id}", tag = "tickets", params(("id" = i32, Path, description = "Ticket id"), GetTicketQuery), responses(
+	(status = 200, description = "The ticket with its progress. users that only see it because it is public get no state", body = TicketDetail),
+	(status = 403, description = "No access", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_ticket(
+	user: AuthUser,
+	ValidPath(ticket_id): ValidPath<i32>,
+	ValidQuery(query): ValidQuery<GetTicketQuery>,
+	extract::State(pool): extract::State<sqlx::PgPool>
+) -> Result<Json<TicketDetail>, ApiError> {
+	let pool = replica::read_pool(pool);
+	let include_archived = query.include_archived.unwrap_or(false);
+	let query: Result<Option<Ticket>, _> = sqlx::query_as("select * from tickets where id=$1")
+		.bind(ticket_id)
+		.fetch_optional(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let ticket = query.unwrap();
+	let mut conn = pool.acquire().await.map_err(db_error)?;
+	if ticket.is_none() {
+		if include_archived {
+			let detail = archived_ticket_detail(&pool, ticket_id).await?;
+			return match authorize_read(&mut conn, &detail.ticket, &user).await? {
+				TicketAccess::Public => Ok(Json(public_detail(detail))),
+				_ => Ok(Json(detail))
+			};
+		}
+		return Err(ticket_not_found(ticket_id));
+	}
+	let ticket = ticket.unwrap();
+	let access = authorize_read(&mut conn, &ticket, &user).await?;
+
+	let process_data = read_process_data(ticket.process_id.clone()).await;
+	if let Err(e) = process_data {
+		log(LogType::Error, format!("Error reading process data: {}", e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let process_data = process_data.unwrap();
+
+	// everyone the ticket is currently waiting on
+	let pending_query: Result<Vec<PendingAssignee>, _> = sqlx::query_as(
+		r#"select node_number, instance, type_, user_active_tickets.userid, username
+			from user_active_tickets join users on user_active_tickets.userid=users.userid
+			where ticketid=$1 and user_active_tickets.active=true and type_!='own' order by node_number, instance"#)
+		.bind(ticket.id)
+		.fetch_all(&pool)
+		.await;
+
+	if let Err(e) = pending_query {
+		admin_logger(LogType::Error, &format!("Error reading pending nodes of ticket {}: {}", ticket.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let pending = pending_query.unwrap();
+
+	let tags = tags::get_tags(&mut conn, ticket.id).await;
+	if let Err(e) = tags {
+		admin_logger(LogType::Error, &format!("Error reading tags of ticket {}: {}", ticket.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let rejections: Result<Vec<Rejection>, _> = sqlx::query_as("select node_number, userid, reason, created_at from ticket_rejections where ticketid=$1 order by created_at")
+		.bind(ticket.id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = rejections {
+		admin_logger(LogType::Error, &format!("Error reading rejections of ticket {}: {}", ticket.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let mut nodes = node_progress(&process_data, ticket.complete);
+
+	for assignee in pending {
+		if let Some(node) = nodes.get_mut(assignee.node_number as usize) {
+			node.pending.push(assignee);
+		}
+	}
+
+	let detail = TicketDetail { ticket, tags: tags.unwrap(), rejections: rejections.unwrap(), nodes };
+	if access == TicketAccess::Public {
+		return Ok(Json(public_detail(detail)));
+	}
+	return Ok(Json(detail));
+}
+
+// the history names the assignees of every node and what they sent so it needs full access
+#[utoipa::path(get, path = "/ticket/{id}/history", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 200, description = "Log and node data of the ticket", body = TicketHistory),
+	(status = 403, description = "No access", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_ticket_history(
+	user: AuthUser,
+	ValidPath(ticket_id): ValidPath<i32>,
+	extract::State(pool): extract::State<sqlx::PgPool>
+) -> Result<Json<TicketHistory>, ApiError> {
+	let pool = replica::read_pool(pool);
+	// archived tickets keep their log
+	let query: Result<Option<Ticket>, _> = sqlx::query_as(
+		r#"select id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, priority, due_at, state, instances from tickets where id=$1
+			union all select id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, priority, due_at, state, instances from tickets_archive where id=$1"#)
+		.bind(ticket_id)
+		.fetch_optional(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let ticket = query.unwrap().ok_or_else(|| ticket_not_found(ticket_id))?;
+	let log_id = ticket.log_id;
+
+	let mut conn = pool.acquire().await.map_err(db_error)?;
+	if authorize_read(&mut conn, &ticket, &user).await? != TicketAccess::Full {
+		return Err(StatusCode::FORBIDDEN.into());
+	}
+
+	// only the public log is returned, errors and warnings stay in the admin log
+	let history = read_public_log(&log_id);
+	if let Err(e) = history {
+		admin_logger(LogType::Error, &format!("Error reading log of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	let node_data: Result<Vec<NodeData>, _> = sqlx::query_as(
+		r#"select node_number, instance, d.userid, username, data, d.created_at
+			from ticket_node_data d join users on d.userid=users.userid where ticketid=$1 order by d.id"#)
+		.bind(ticket_id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = node_data {
+		admin_logger(LogType::Error, &format!("Error reading the node data of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	return Ok(Json(TicketHistory { log: history.unwrap(), node_data: node_data.unwrap() }));
+}
+
+// the callback jobs of the ticket and their send attempts, for finding out why a BlockingTask is not completed
+#[utoipa::path(get, path = "/ticket/{id}/callbacks", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 200, description = "Callback jobs of the ticket and every attempt to send them", body = TicketCallbacks),
+	(status = 403, description = "No access", body = Problem, content_type = "application/problem+json"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_ticket_callbacks(
+	user: AuthUser,
+	ValidPath(ticket_id): ValidPath<i32>,
+	extract::State(pool): extract::State<sqlx::PgPool>
+) -> Result<Json<TicketCallbacks>, ApiError> {
+	let query: Result<Option<Ticket>, _> = sqlx::query_as(
+		r#"select id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, priority, due_at, state, instances from tickets where id=$1
+			union all select id, owner_id, process_id, log_id, is_public, created_at, updated_at, status, complete, priority, due_at, state, instances from tickets_archive where id=$1"#)
+		.bind(ticket_id)
+		.fetch_optional(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+	let ticket = query.unwrap().ok_or_else(|| ticket_not_found(ticket_id))?;
+
+	let mut conn = pool.acquire().await.map_err(db_error)?;
+	if authorize_read(&mut conn, &ticket, &user).await? != TicketAccess::Full {
+		return Err(StatusCode::FORBIDDEN.into());
+	}
+
+	let callbacks = callbacks::ticket_callbacks(&mut conn, ticket_id).await;
+	if let Err(e) = callbacks {
+		admin_logger(LogType::Error, &format!("Error reading callbacks of ticket {}: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+	}
+
+	return Ok(Json(callbacks.unwrap()));
+}
+
 #[cfg(test)]
 mod ticket_tests {
 	use crate::db_types::Ticket;
 	use dotenv;
 use serde_json::Map;
 
-	use super::{update_internal, NewUserTicketType};
+	use crate::repository::{ActiveRow, MemoryRepository};
+	use axum::http::StatusCode;
+	use super::{update_internal, NewUserTicketType, make_cursor, parse_cursor, Cursor, validate_node, UpdateErr, UpdateSource, like_pattern, cancel, assignees, release, reject, AssignmentType, Event, TicketStatus, UpdateTicket};
+	use crate::repository::TicketRepository;
+
+	fn ticket(id: i32, owner_id: uuid::Uuid, status: TicketStatus) -> Ticket {
+		return Ticket {
+			id,
+			owner_id,
+			process_id: "initiate_test".to_string(),
+			log_id: uuid::Uuid::new_v4(),
+			is_public: false,
+			priority: 0,
+			due_at: None,
+			created_at: chrono::Utc::now(),
+			updated_at: chrono::Utc::now(),
+			status,
+			complete: 0,
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
+		};
+	}
 
 	#[tokio::test]
 	async fn check_2_node_process() {
@@ -749,25 +2192,66 @@ use serde_json::Map;
 			process_id: "initiate_test".to_string(),
 			log_id: uuid::Uuid::new_v4(),
 			is_public: false,
+			priority: 0,
+			due_at: None,
 			created_at: chrono::Utc::now(),
 			updated_at: chrono::Utc::now(),
-			status: "open".to_string(),
+			status: TicketStatus::Open,
 			complete: 0,
-			state: serde_json::Value::Object(Map::new())
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
 		};
 		let request = crate::ticket::UpdateTicket {
 			ticket_id: 0,
 			user_id: uuid::Uuid::new_v4(),
 			status: true,
 			node: 0,
-			data: None
+			data: None,
+			instance: None,
+			reason: None
 		};
 
-		let result = update_internal(&mut ticket, &request).await;
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
 		assert!(result.is_ok(), "update_internal failed");
 		assert_eq!(ticket.complete, 1i32, "ticket complete mask is wrong");
 	}
 
+	#[tokio::test]
+	async fn callbacks_are_returned_as_jobs() {
+		dotenv::dotenv().ok();
+		let mut ticket = Ticket {
+			id: 3,
+			owner_id: uuid::Uuid::new_v4(),
+			process_id: "nonblocking_task_test".to_string(),
+			log_id: uuid::Uuid::new_v4(),
+			is_public: false,
+			priority: 0,
+			due_at: None,
+			created_at: chrono::Utc::now(),
+			updated_at: chrono::Utc::now(),
+			status: TicketStatus::Open,
+			complete: 0,
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
+		};
+		let request = crate::ticket::UpdateTicket {
+			ticket_id: 3,
+			user_id: uuid::Uuid::new_v4(),
+			status: true,
+			node: 0,
+			data: None,
+			instance: None,
+			reason: None
+		};
+
+		// nothing is sent while executing, the jobs are saved by the caller
+		let mut jobs = Vec::new();
+		let result = update_internal(&mut ticket, &request, &mut jobs, &mut Vec::new()).await;
+		assert!(result.is_ok(), "update_internal failed");
+		assert_eq!(jobs.len(), 1);
+		assert_eq!((jobs[0].ticket_id, jobs[0].node, jobs[0].callbacks.len()), (3, 1, 1));
+	}
+
 	#[tokio::test]
 	async fn check_approve_user_request_works() {
 		dotenv::dotenv().ok();
@@ -777,28 +2261,33 @@ use serde_json::Map;
 			process_id: "approve_test".to_string(),
 			log_id: uuid::Uuid::new_v4(),
 			is_public: false,
+			priority: 0,
+			due_at: None,
 			created_at: chrono::Utc::now(),
 			updated_at: chrono::Utc::now(),
-			status: "open".to_string(),
+			status: TicketStatus::Open,
 			// initiate step is already completed
 			complete: 1,
-			state: serde_json::Value::Object(Map::new())
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
 		};
 		let request = crate::ticket::UpdateTicket {
 			ticket_id: 0,
 			user_id: uuid::Uuid::new_v4(),
 			status: true,
 			node: 1,
-			data: None
+			data: None,
+			instance: None,
+			reason: None
 		};
 		// in this case the user request is completing approve event so the entire process should complete
-		let result = update_internal(&mut ticket, &request).await;
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
 		assert!(result.is_ok(), "update_internal failed");
 		assert_eq!(ticket.complete, 3i32, "ticket complete mask is wrong");
 
 		let result = result.unwrap();
 		assert_eq!(result.len(), 1, "there should be one new ticket in the ticket queue");
-		let new_user_ticket = result.get(0).unwrap();
+		let new_user_ticket = result.first().unwrap();
 		match new_user_ticket.type_ {
 			NewUserTicketType::Completion => {},
 			_ => {
@@ -815,27 +2304,32 @@ use serde_json::Map;
 			process_id: "approve_test".to_string(),
 			log_id: uuid::Uuid::new_v4(),
 			is_public: false,
+			priority: 0,
+			due_at: None,
 			created_at: chrono::Utc::now(),
 			updated_at: chrono::Utc::now(),
-			status: "open".to_string(),
+			status: TicketStatus::Open,
 			complete: 0,
-			state: serde_json::Value::Object(Map::new())
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
 		};
 		let request = crate::ticket::UpdateTicket {
 			ticket_id: 0,
 			user_id: uuid::Uuid::new_v4(),
 			status: true,
 			node: 0,
-			data: None
+			data: None,
+			instance: None,
+			reason: None
 		};
 		// in this case the user request is completing approve event so the entire process should complete
-		let result = update_internal(&mut ticket, &request).await;
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
 		assert!(result.is_ok(), "update_internal failed");
 		assert_eq!(ticket.complete, 1i32, "ticket complete mask is wrong");
 
 		let result = result.unwrap();
 		assert_eq!(result.len(), 1, "there should be one new ticket in the ticket queue");
-		let new_user_ticket = result.get(0).unwrap();
+		let new_user_ticket = result.first().unwrap();
 		match new_user_ticket.type_ {
 			NewUserTicketType::ApproveRequest => {},
 			_ => {
@@ -853,21 +2347,26 @@ use serde_json::Map;
 			process_id: "simple_branch_test".to_string(),
 			log_id: uuid::Uuid::new_v4(),
 			is_public: false,
+			priority: 0,
+			due_at: None,
 			created_at: chrono::Utc::now(),
 			updated_at: chrono::Utc::now(),
-			status: "open".to_string(),
+			status: TicketStatus::Open,
 			complete: 0,
-			state: serde_json::Value::Object(Map::new())
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
 		};
 		let request = crate::ticket::UpdateTicket {
 			ticket_id: 0,
 			user_id: uuid::Uuid::new_v4(),
 			status: true,
 			node: 0,
-			data: None
+			data: None,
+			instance: None,
+			reason: None
 		};
 
-		let result = update_internal(&mut ticket, &request).await;
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
 		assert!(result.is_ok(), "update_internal failed");
 		let result = result.unwrap();
 		assert_eq!(ticket.complete, 1i32, "ticket complete mask is wrong");
@@ -893,27 +2392,32 @@ use serde_json::Map;
 			process_id: "simple_branch_test".to_string(),
 			log_id: uuid::Uuid::new_v4(),
 			is_public: false,
+			priority: 0,
+			due_at: None,
 			created_at: chrono::Utc::now(),
 			updated_at: chrono::Utc::now(),
-			status: "open".to_string(),
+			status: TicketStatus::Open,
 			complete: 3i32,
-			state: serde_json::Value::Object(Map::new())
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
 		};
 		let request = crate::ticket::UpdateTicket {
 			ticket_id: 0,
 			user_id: uuid::Uuid::new_v4(),
 			status: true,
 			node: 2,
-			data: None
+			data: None,
+			instance: None,
+			reason: None
 		};
 
-		let result = update_internal(&mut ticket, &request).await;
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
 		assert!(result.is_ok(), "update_internal failed");
 		let result = result.unwrap();
 		// assert_eq!(ticket.complete, , "ticket complete mask is wrong");
 
 		assert_eq!(result.len(), 1, "only 1 tickets should be added");
-		let t = result.get(0).unwrap();
+		let t = result.first().unwrap();
 		match t.type_ {
 			NewUserTicketType::ApproveRequest => {},
 			_ => {
@@ -923,4 +2427,298 @@ use serde_json::Map;
 		assert!(t.username.is_some(), "ticket should have a username");
 		assert_eq!(t.username, Some("erp_admin".to_string()), "wrong username added for the approve request");
 	}
-}
\ No newline at end of file
+	#[tokio::test]
+	async fn check_wait_node_parks_ticket() {
+		dotenv::dotenv().ok();
+		let mut ticket = Ticket {
+			id: 0,
+			owner_id: uuid::Uuid::new_v4(),
+			process_id: "wait_test".to_string(),
+			log_id: uuid::Uuid::new_v4(),
+			is_public: false,
+			priority: 0,
+			due_at: None,
+			created_at: chrono::Utc::now(),
+			updated_at: chrono::Utc::now(),
+			status: TicketStatus::Open,
+			complete: 0,
+			state: serde_json::Value::Object(Map::new()),
+			instances: serde_json::Value::Object(Map::new())
+		};
+		let request = crate::ticket::UpdateTicket {
+			ticket_id: 0,
+			user_id: uuid::Uuid::new_v4(),
+			status: true,
+			node: 0,
+			data: None,
+			instance: None,
+			reason: None
+		};
+
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
+		assert!(result.is_ok(), "update_internal failed");
+		let result = result.unwrap();
+		assert_eq!(ticket.complete, 1i32, "wait node should not be completed before the signal");
+		assert_eq!(result.len(), 1, "only the wait ticket should be added");
+		match result.first().unwrap().type_ {
+			NewUserTicketType::AwaitSignal => {},
+			_ => {
+				panic!("new ticket should be of type await_signal");
+			}
+		}
+
+		// signal received
+		let request = crate::ticket::UpdateTicket {
+			ticket_id: 0,
+			user_id: uuid::Uuid::new_v4(),
+			status: true,
+			node: 1,
+			data: None,
+			instance: None,
+			reason: None
+		};
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await;
+		assert!(result.is_ok(), "update_internal failed");
+		let result = result.unwrap();
+		assert_eq!(ticket.complete, 3i32, "ticket complete mask is wrong");
+		match result.first().unwrap().type_ {
+			NewUserTicketType::Completion => {},
+			_ => {
+				panic!("new ticket should be of type completion");
+			}
+		}
+	}
+	#[tokio::test]
+	async fn check_multi_instance_approve() {
+		dotenv::dotenv().ok();
+		let mut state = Map::new();
+		state.insert("items".to_string(), serde_json::json!([{"name": "laptop"}, {"name": "monitor"}]));
+		let mut ticket = Ticket {
+			id: 0,
+			owner_id: uuid::Uuid::new_v4(),
+			process_id: "multi_instance_test".to_string(),
+			log_id: uuid::Uuid::new_v4(),
+			is_public: false,
+			priority: 0,
+			due_at: None,
+			created_at: chrono::Utc::now(),
+			updated_at: chrono::Utc::now(),
+			status: TicketStatus::Open,
+			complete: 0,
+			state: serde_json::Value::Object(state),
+			instances: serde_json::Value::Object(Map::new())
+		};
+		let mut request = crate::ticket::UpdateTicket {
+			ticket_id: 0,
+			user_id: uuid::Uuid::new_v4(),
+			status: true,
+			node: 0,
+			data: None,
+			instance: None,
+			reason: None
+		};
+
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await.unwrap();
+		assert_eq!(result.len(), 2, "one approval per item should be requested");
+		assert_eq!(result.iter().map(|t| t.instance).collect::<Vec<_>>(), vec![Some(0), Some(1)]);
+
+		request.node = 1;
+		request.instance = Some(1);
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await.unwrap();
+		assert!(result.is_empty(), "node should wait for the other instance");
+		assert_eq!(ticket.complete, 1i32, "node should not be completed yet");
+
+		request.instance = Some(0);
+		let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await.unwrap();
+		assert_eq!(ticket.complete, 3i32, "ticket complete mask is wrong");
+		match result.first().unwrap().type_ {
+			NewUserTicketType::Completion => {},
+			_ => {
+				panic!("new ticket should be of type completion");
+			}
+		}
+	}
+	#[tokio::test]
+	async fn check_auto_approve_rule() {
+		dotenv::dotenv().ok();
+		for (amount, expect_auto) in [(100, true), (1000, false)] {
+			let mut state = Map::new();
+			state.insert("amount".to_string(), serde_json::json!(amount));
+			let mut ticket = Ticket {
+				id: 0,
+				owner_id: uuid::Uuid::new_v4(),
+				process_id: "auto_approve_test".to_string(),
+				log_id: uuid::Uuid::new_v4(),
+				is_public: false,
+				priority: 0,
+				due_at: None,
+				created_at: chrono::Utc::now(),
+				updated_at: chrono::Utc::now(),
+				status: TicketStatus::Open,
+				complete: 0,
+				state: serde_json::Value::Object(state),
+				instances: serde_json::Value::Object(Map::new())
+			};
+			let request = crate::ticket::UpdateTicket {
+				ticket_id: 0,
+				user_id: uuid::Uuid::new_v4(),
+				status: true,
+				node: 0,
+				data: None,
+				instance: None,
+				reason: None
+			};
+
+			let result = update_internal(&mut ticket, &request, &mut Vec::new(), &mut Vec::new()).await.unwrap();
+			assert_eq!(result.len(), 1, "there should be one new ticket in the ticket queue");
+			match (&result.first().unwrap().type_, expect_auto) {
+				(NewUserTicketType::Completion, true) => assert_eq!(ticket.complete, 3i32),
+				(NewUserTicketType::ApproveRequest, false) => assert_eq!(ticket.complete, 1i32),
+				(t, _) => panic!("unexpected new ticket {:?} for amount {}", t, amount)
+			}
+		}
+	}
+
+	#[test]
+	fn cursor_round_trip() {
+		let cursor = Cursor { priority: 2, time: chrono::Utc::now(), id: 42 };
+		assert_eq!(parse_cursor(&make_cursor(&cursor)), Some(cursor));
+
+		assert!(parse_cursor("42").is_none());
+		assert!(parse_cursor("0,yesterday,42").is_none());
+		assert!(parse_cursor("0,2024-04-28T10:15:00+00:00,42,1").is_none());
+	}
+
+	#[test]
+	fn search_wildcards_are_escaped() {
+		assert_eq!(like_pattern(" travel "), "%travel%");
+		assert_eq!(like_pattern("100%_off"), "%100\\%\\_off%");
+		assert_eq!(like_pattern("a\\b"), "%a\\\\b%");
+	}
+
+	#[tokio::test]
+	async fn check_node_validation() {
+		dotenv::dotenv().ok();
+		let process = crate::process::read_process_data("wait_test".to_string()).await.unwrap();
+
+		// out of range nodes are unprocessable instead of panicking
+		assert!(matches!(validate_node(&process, 7, true, UpdateSource::User), Err(UpdateErr::InvalidData(_))));
+		assert!(matches!(validate_node(&process, -1, true, UpdateSource::User), Err(UpdateErr::InvalidData(_))));
+		// wait nodes can only be completed by a signal
+		assert!(matches!(validate_node(&process, 1, true, UpdateSource::User), Err(UpdateErr::InvalidRequest(_))));
+		assert!(matches!(validate_node(&process, 2, true, UpdateSource::Signal), Err(UpdateErr::InvalidRequest(_))));
+		assert!(validate_node(&process, 1, true, UpdateSource::Signal).is_ok());
+		assert!(matches!(validate_node(&process, 1, true, UpdateSource::Service), Err(UpdateErr::InvalidRequest(_))));
+
+		// blocking tasks have no user_active_tickets row, only services complete or fail them
+		let process = crate::process::read_process_data("blocking_task_test".to_string()).await.unwrap();
+		assert!(matches!(validate_node(&process, 1, true, UpdateSource::User), Err(UpdateErr::InvalidRequest(_))));
+		assert!(matches!(validate_node(&process, 1, false, UpdateSource::User), Err(UpdateErr::InvalidRequest(_))));
+		assert!(validate_node(&process, 1, true, UpdateSource::Service).is_ok());
+		assert!(validate_node(&process, 1, false, UpdateSource::Service).is_ok());
+	}
+
+	#[tokio::test]
+	async fn owners_cancel_open_tickets() {
+		let (owner, approver) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let mut repo = MemoryRepository::default();
+		repo.tickets.insert(1, ticket(1, owner, TicketStatus::Open));
+		repo.active.push(ActiveRow { userid: owner, ticket_id: 1, node_number: 0, instance: None, type_: AssignmentType::Own, active: true });
+		repo.active.push(ActiveRow { userid: approver, ticket_id: 1, node_number: 1, instance: None, type_: AssignmentType::Approve, active: true });
+		repo.signals.push((1, true));
+
+		let (cancelled, approvers) = cancel(&mut repo, 1, owner).await.unwrap();
+		assert_eq!(cancelled.id, 1);
+		assert_eq!(approvers, vec![approver]);
+		assert_eq!(repo.tickets[&1].status, TicketStatus::Cancelled);
+		assert!(repo.active.iter().all(|r| !r.active));
+		assert_eq!(repo.signals, vec![(1, false)]);
+	}
+
+	#[tokio::test]
+	async fn only_owners_cancel_and_only_open_tickets() {
+		let owner = uuid::Uuid::new_v4();
+		let mut repo = MemoryRepository::default();
+		repo.tickets.insert(1, ticket(1, owner, TicketStatus::Open));
+		repo.tickets.insert(2, ticket(2, owner, TicketStatus::Closed));
+
+		let e = cancel(&mut repo, 1, uuid::Uuid::new_v4()).await.err().unwrap();
+		assert_eq!((e.status, e.code), (StatusCode::FORBIDDEN, "not_ticket_owner"));
+		let e = cancel(&mut repo, 2, owner).await.err().unwrap();
+		assert_eq!((e.status, e.code), (StatusCode::CONFLICT, "ticket_not_open"));
+		let e = cancel(&mut repo, 3, owner).await.err().unwrap();
+		assert_eq!((e.status, e.code), (StatusCode::NOT_FOUND, "ticket_not_found"));
+		assert_eq!(repo.tickets[&1].status, TicketStatus::Open);
+	}
+
+	fn approval(ticket_id: i32, user_id: uuid::Uuid, node: i32, status: bool) -> UpdateTicket {
+		return UpdateTicket { ticket_id, user_id, status, node, data: None, instance: None, reason: None };
+	}
+
+	#[tokio::test]
+	async fn approvals_complete_the_node_of_their_holder() {
+		dotenv::dotenv().ok();
+		let (owner, approver) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let mut repo = MemoryRepository::default();
+		let mut pending = ticket(1, owner, TicketStatus::Open);
+		pending.process_id = "approve_test".to_string();
+		// initiate step is already completed
+		pending.complete = 1;
+		repo.tickets.insert(1, pending);
+		repo.active.push(ActiveRow { userid: approver, ticket_id: 1, node_number: 1, instance: None, type_: AssignmentType::Approve, active: true });
+
+		// the steps of apply_update_in that write the ticket and its assignments
+		let mut loaded = repo.find(1).await.unwrap().unwrap();
+		let payload = approval(1, approver, 1, true);
+		release(&mut repo, &loaded, &payload, Some(&Event::Approve)).await.unwrap();
+		update_internal(&mut loaded, &payload, &mut Vec::new(), &mut Vec::new()).await.unwrap();
+		repo.save(&loaded).await.unwrap();
+
+		assert!(!repo.active[0].active);
+		assert_eq!(repo.tickets[&1].complete, 3);
+
+		// the node is not pending with anyone any more
+		let e = release(&mut repo, &loaded, &payload, Some(&Event::Approve)).await.err().unwrap();
+		assert!(matches!(e, UpdateErr::Problem(e) if e.status == StatusCode::FORBIDDEN && e.code == "not_assigned"));
+		// other nodes are completed without an assignment, e.g. by a signal
+		assert!(release(&mut repo, &loaded, &approval(1, approver, 2, true), Some(&Event::Wait)).await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn rejections_take_the_ticket_from_everyone() {
+		let (owner, approver, other) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let mut repo = MemoryRepository::default();
+		repo.tickets.insert(1, ticket(1, owner, TicketStatus::Open));
+		repo.tickets.insert(2, ticket(2, owner, TicketStatus::Open));
+		repo.active.push(ActiveRow { userid: owner, ticket_id: 1, node_number: 0, instance: None, type_: AssignmentType::Own, active: true });
+		repo.active.push(ActiveRow { userid: approver, ticket_id: 1, node_number: 1, instance: None, type_: AssignmentType::Approve, active: true });
+		repo.active.push(ActiveRow { userid: other, ticket_id: 1, node_number: 1, instance: Some(2), type_: AssignmentType::Approve, active: true });
+		repo.active.push(ActiveRow { userid: other, ticket_id: 2, node_number: 1, instance: None, type_: AssignmentType::Approve, active: true });
+
+		let mut loaded = repo.find(1).await.unwrap().unwrap();
+		release(&mut repo, &loaded, &approval(1, approver, 1, false), Some(&Event::Approve)).await.unwrap();
+		reject(&mut repo, &mut loaded).await.unwrap();
+
+		assert_eq!(repo.tickets[&1].status, TicketStatus::Rejected);
+		assert_eq!(repo.tickets[&1].updated_at, loaded.updated_at);
+		assert!(repo.active.iter().filter(|r| r.ticket_id == 1).all(|r| !r.active));
+		assert_eq!(repo.tickets[&2].status, TicketStatus::Open);
+		assert!(repo.active[3].active);
+	}
+
+	#[tokio::test]
+	async fn approvals_go_to_delegates() {
+		let (away, delegate, present) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let mut repo = MemoryRepository::default();
+		repo.delegates.insert(away, delegate);
+
+		let (assigned, delegates) = assignees(&mut repo, &[away, present]).await.unwrap();
+		assert_eq!(assigned, vec![delegate, present]);
+		assert_eq!(delegates.len(), 1);
+
+		// a delegate that is away as well keeps the approval, delegations are not followed further
+		repo.delegates.insert(delegate, present);
+		let (assigned, _) = assignees(&mut repo, &[away]).await.unwrap();
+		assert_eq!(assigned, vec![delegate]);
+	}
+}
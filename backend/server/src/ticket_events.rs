@@ -0,0 +1,399 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{types::Json as DbJson, FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
+use erp_api_types::tickets::TicketStatus;
+use crate::api_error::{db_error, ApiError};
+use crate::audit::{self, AuditAction, AuditEvent};
+use crate::db_types::Ticket;
+use crate::events::completed_nodes;
+use crate::extractors::ValidPath;
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageProcesses, ViewAudit};
+use crate::ticket::{lock_ticket, ticket_not_found};
+use utoipa::ToSchema;
+
+// every transition of a ticket is saved in ticket_events with what it changed. status, complete, state and instances
+// of the ticket row are a projection of them: the changes applied one after the other from an empty ticket give the
+// row, which is what replay checks and repairs
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketEventKind {
+	Created,
+	Imported,
+	// the row of a ticket from before the events, written by the migration
+	Snapshot,
+	Submitted,
+	// a user or a service completing a node, with the data they sent
+	Updated,
+	// a node the engine executed, the one that was completed and every one it unlocked
+	NodeExecuted,
+	Rejected,
+	Closed,
+	Cancelled
+}
+
+impl TicketEventKind {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			TicketEventKind::Created => "created",
+			TicketEventKind::Imported => "imported",
+			TicketEventKind::Snapshot => "snapshot",
+			TicketEventKind::Submitted => "submitted",
+			TicketEventKind::Updated => "updated",
+			TicketEventKind::NodeExecuted => "node_executed",
+			TicketEventKind::Rejected => "rejected",
+			TicketEventKind::Closed => "closed",
+			TicketEventKind::Cancelled => "cancelled"
+		};
+	}
+}
+
+// top level keys of a json object that were set or removed. a change deeper down sets the whole key again
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, ToSchema)]
+pub struct KeyChanges {
+	#[serde(default, skip_serializing_if = "Map::is_empty")]
+	#[schema(value_type = Object)]
+	pub set: Map<String, Value>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub unset: Vec<String>
+}
+
+impl KeyChanges {
+	fn between(before: &Value, after: &Value) -> KeyChanges {
+		let empty = Map::new();
+		let before = before.as_object().unwrap_or(&empty);
+		let after = after.as_object().unwrap_or(&empty);
+		let set = after.iter()
+			.filter(|(key, value)| before.get(*key) != Some(*value))
+			.map(|(key, value)| (key.clone(), value.clone()))
+			.collect();
+		let unset = before.keys().filter(|key| !after.contains_key(*key)).cloned().collect();
+		return KeyChanges { set, unset };
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.set.is_empty() && self.unset.is_empty();
+	}
+
+	fn apply(&self, value: &mut Value) {
+		if !value.is_object() {
+			*value = Value::Object(Map::new());
+		}
+		let object = value.as_object_mut().unwrap();
+		for key in self.unset.iter() {
+			object.remove(key);
+		}
+		for (key, set) in self.set.iter() {
+			object.insert(key.clone(), set.clone());
+		}
+	}
+}
+
+// what one transition changed. the engine only ever sets bits of complete
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, ToSchema)]
+pub struct Change {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub status: Option<TicketStatus>,
+	// nodes whose bit was set
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub completed: Vec<i32>,
+	#[serde(default, skip_serializing_if = "KeyChanges::is_empty")]
+	pub state: KeyChanges,
+	#[serde(default, skip_serializing_if = "KeyChanges::is_empty")]
+	pub instances: KeyChanges
+}
+
+impl Change {
+	pub fn status(status: TicketStatus) -> Change {
+		return Change { status: Some(status), ..Default::default() };
+	}
+}
+
+// the columns of a ticket the events rebuild
+#[derive(Serialize, FromRow, Clone, Debug, PartialEq, ToSchema)]
+pub struct Projection {
+	pub status: TicketStatus,
+	pub complete: i32,
+	#[schema(value_type = Object)]
+	pub state: Value,
+	#[schema(value_type = Object)]
+	pub instances: Value
+}
+
+impl Projection {
+	// the defaults of a new row
+	pub fn empty() -> Projection {
+		return Projection { status: TicketStatus::Open, complete: 0, state: Value::Object(Map::new()), instances: Value::Object(Map::new()) };
+	}
+
+	pub fn of(ticket: &Ticket) -> Projection {
+		return Projection { status: ticket.status, complete: ticket.complete, state: ticket.state.clone(), instances: ticket.instances.clone() };
+	}
+
+	// what turns this one into `after`
+	pub fn change_to(&self, after: &Projection) -> Change {
+		return Change {
+			status: if self.status != after.status { Some(after.status) } else { None },
+			completed: completed_nodes(self.complete, after.complete),
+			state: KeyChanges::between(&self.state, &after.state),
+			instances: KeyChanges::between(&self.instances, &after.instances)
+		};
+	}
+
+	pub fn apply(&mut self, change: &Change) {
+		if let Some(status) = change.status {
+			self.status = status;
+		}
+		for node in change.completed.iter() {
+			self.complete |= 1 << node;
+		}
+		change.state.apply(&mut self.state);
+		change.instances.apply(&mut self.instances);
+	}
+}
+
+// a transition waiting to be recorded in the ticket transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TicketEvent {
+	pub kind: TicketEventKind,
+	pub node: Option<i32>,
+	pub instance: Option<i32>,
+	// None when the engine moved on by itself
+	pub actor: Option<uuid::Uuid>,
+	pub change: Change
+}
+
+impl TicketEvent {
+	// the whole row of a ticket that was just inserted, so the replay does not depend on the defaults
+	pub fn inserted(kind: TicketEventKind, row: &Projection, actor: Option<uuid::Uuid>) -> TicketEvent {
+		let mut change = Projection::empty().change_to(row);
+		change.status = Some(row.status);
+		return TicketEvent { kind, node: None, instance: None, actor, change };
+	}
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct StoredTicketEvent {
+	pub id: i64,
+	pub seq: i32,
+	pub kind: String,
+	pub node: Option<i32>,
+	pub instance: Option<i32>,
+	pub actor: Option<uuid::Uuid>,
+	#[schema(value_type = Change)]
+	pub change: DbJson<Change>,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReplayRequest {
+	// only compare, the ticket is left as it is
+	#[serde(default)]
+	pub dry_run: bool
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Replayed {
+	pub ticket_id: i32,
+	pub events: usize,
+	pub current: Projection,
+	pub rebuilt: Projection,
+	// the ticket was not what its events add up to
+	pub differs: bool,
+	// the rebuilt projection was written to the ticket
+	pub applied: bool
+}
+
+// call in the ticket transaction, after lock_ticket so two transactions never take the same seq
+pub async fn record(conn: &mut PgConnection, ticket_id: i32, events: &[TicketEvent]) -> Result<(), sqlx::Error> {
+	if events.is_empty() {
+		return Ok(());
+	}
+	let last: (Option<i32>,) = sqlx::query_as("select max(seq) from ticket_events where ticket_id=$1")
+		.bind(ticket_id)
+		.fetch_one(&mut *conn)
+		.await?;
+	let first = last.0.unwrap_or(0) + 1;
+	let now = chrono::Utc::now();
+
+	let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("insert into ticket_events (ticket_id, seq, kind, node, instance, actor, change, created_at) ");
+	builder.push_values(events.iter().enumerate(), |mut b, (i, event)| {
+		b.push_bind(ticket_id)
+			.push_bind(first + i as i32)
+			.push_bind(event.kind.as_str())
+			.push_bind(event.node)
+			.push_bind(event.instance)
+			.push_bind(event.actor)
+			.push_bind(DbJson(&event.change))
+			.push_bind(now);
+	});
+	builder.build().execute(&mut *conn).await?;
+	return Ok(());
+}
+
+// the changes applied in order, None for a ticket without events
+pub fn replay<'a>(changes: impl IntoIterator<Item = &'a Change>) -> Option<Projection> {
+	let mut projection = None;
+	for change in changes {
+		projection.get_or_insert_with(Projection::empty).apply(change);
+	}
+	return projection;
+}
+
+// also of archived tickets. the ticket has to be visible in the tenant of the request
+async fn read_events(conn: &mut PgConnection, ticket_id: i32) -> Result<Option<Vec<StoredTicketEvent>>, sqlx::Error> {
+	let exists: (bool,) = sqlx::query_as("select exists(select 1 from tickets where id=$1) or exists(select 1 from tickets_archive where id=$1)")
+		.bind(ticket_id)
+		.fetch_one(&mut *conn)
+		.await?;
+	if !exists.0 {
+		return Ok(None);
+	}
+	let events = sqlx::query_as("select * from ticket_events where ticket_id=$1 order by seq")
+		.bind(ticket_id)
+		.fetch_all(&mut *conn)
+		.await?;
+	return Ok(Some(events));
+}
+
+#[utoipa::path(get, path = "/admin/ticket/{id}/events", tag = "admin", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 200, description = "Events of the ticket in order", body = Vec<StoredTicketEvent>),
+	(status = 403, description = "Missing the view_audit permission"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn get_ticket_events(
+	_auth: Authorized<ViewAudit>,
+	extract::State(pool): extract::State<PgPool>,
+	ValidPath(ticket_id): ValidPath<i32>
+) -> Result<Json<Vec<StoredTicketEvent>>, ApiError> {
+	let mut conn = pool.acquire().await.map_err(db_error)?;
+	let events = read_events(&mut conn, ticket_id).await;
+	if let Err(e) = events {
+		admin_logger(LogType::Error, &format!("Error reading the events of ticket {}: {}", ticket_id, e), None);
+		return Err(ApiError::internal());
+	}
+	return events.unwrap().map(Json).ok_or_else(|| ticket_not_found(ticket_id));
+}
+
+// rebuilds the ticket from its events and writes it unless dry_run is set. archived tickets are not replayed
+#[utoipa::path(post, path = "/admin/ticket/{id}/replay", tag = "admin", params(("id" = i32, Path, description = "Ticket id")), request_body = ReplayRequest, responses(
+	(status = 200, description = "The ticket compared with what its events add up to, rebuilt unless dry_run", body = Replayed),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown ticket", body = Problem, content_type = "application/problem+json"),
+	(status = 409, description = "The ticket has no events to rebuild it from", body = Problem, content_type = "application/problem+json")
+))]
+pub async fn replay_ticket(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	ValidPath(ticket_id): ValidPath<i32>,
+	Json(payload): Json<ReplayRequest>
+) -> Result<Json<Replayed>, ApiError> {
+	let mut tx = pool.begin().await.map_err(db_error)?;
+	// nothing else may execute the ticket until the rebuilt projection is written
+	if let Err(e) = lock_ticket(&mut tx, ticket_id).await {
+		admin_logger(LogType::Error, &format!("Error locking ticket {}: {}", ticket_id, e), None);
+		return Err(ApiError::internal());
+	}
+
+	let current: Result<Option<Projection>, _> = sqlx::query_as("select status, complete, state, instances from tickets where id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = current {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} for replay: {}", ticket_id, e), None);
+		return Err(ApiError::internal());
+	}
+	let current = current.unwrap().ok_or_else(|| ticket_not_found(ticket_id))?;
+
+	let events = read_events(&mut tx, ticket_id).await;
+	if let Err(e) = events {
+		admin_logger(LogType::Error, &format!("Error reading the events of ticket {}: {}", ticket_id, e), None);
+		return Err(ApiError::internal());
+	}
+	let events = events.unwrap().unwrap_or_default();
+	// every ticket has at least its created or snapshot event, one without cannot be rebuilt
+	let rebuilt = replay(events.iter().map(|e| &e.change.0))
+		.ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "ticket_has_no_events", format!("Ticket {} has no events to rebuild it from", ticket_id)))?;
+	let differs = rebuilt != current;
+	let applied = differs && !payload.dry_run;
+
+	if applied {
+		let query = sqlx::query("update tickets set status=$2, complete=$3, state=$4, instances=$5 where id=$1")
+			.bind(ticket_id)
+			.bind(rebuilt.status)
+			.bind(rebuilt.complete)
+			.bind(&rebuilt.state)
+			.bind(&rebuilt.instances)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error writing the replayed ticket {}: {}", ticket_id, e), None);
+			return Err(ApiError::internal());
+		}
+		let event = AuditEvent::new(Some(auth.user.userid), AuditAction::Replay, "ticket", ticket_id,
+			Some(serde_json::to_value(&current).unwrap()), Some(serde_json::to_value(&rebuilt).unwrap()));
+		if let Err(e) = audit::record(&mut tx, event).await {
+			admin_logger(LogType::Error, &format!("Error auditing the replay of ticket {}: {}", ticket_id, e), None);
+			return Err(ApiError::internal());
+		}
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error committing the replay of ticket {}: {}", ticket_id, e), None);
+		return Err(ApiError::internal());
+	}
+	if differs {
+		admin_logger(LogType::Warning,
+			&format!("Ticket {} differed from its {} events, replayed by {} (dry run: {})", ticket_id, events.len(), auth.user.userid, payload.dry_run),
+			None);
+	}
+
+	return Ok(Json(Replayed { ticket_id, events: events.len(), current, rebuilt, differs, applied }));
+}
+
+#[cfg(test)]
+mod ticket_events_tests {
+	use serde_json::json;
+	use erp_api_types::tickets::TicketStatus;
+	use super::{replay, Change, Projection};
+
+	fn projection(status: TicketStatus, complete: i32, state: serde_json::Value, instances: serde_json::Value) -> Projection {
+		return Projection { status, complete, state, instances };
+	}
+
+	#[test]
+	fn changes_hold_only_what_moved() {
+		let before = projection(TicketStatus::Open, 0b01, json!({ "amount": 10, "items": [1], "note": "x" }), json!({}));
+		let after = projection(TicketStatus::Open, 0b111, json!({ "amount": 10, "items": [1, 2], "manager": null }), json!({ "2": [true, false] }));
+		let change = before.change_to(&after);
+		assert_eq!(serde_json::to_value(&change).unwrap(), json!({
+			"completed": [1, 2],
+			"state": { "set": { "items": [1, 2], "manager": null }, "unset": ["note"] },
+			"instances": { "set": { "2": [true, false] } }
+		}));
+
+		let mut applied = before.clone();
+		applied.apply(&change);
+		assert_eq!(applied, after);
+		assert_eq!(after.change_to(&after), Change::default());
+	}
+
+	#[test]
+	fn replay_folds_the_changes_in_order() {
+		assert_eq!(replay(&[]), None);
+
+		let created = projection(TicketStatus::Draft, 0, json!({ "amount": 5 }), json!({}));
+		let mut changes = vec![Projection::empty().change_to(&created)];
+		changes[0].status = Some(TicketStatus::Draft);
+		changes.push(Change::status(TicketStatus::Open));
+		changes.push(serde_json::from_value(json!({ "completed": [0], "state": { "set": { "amount": 7 } } })).unwrap());
+		changes.push(Change::status(TicketStatus::Closed));
+
+		let rebuilt = replay(&changes).unwrap();
+		assert_eq!(rebuilt, projection(TicketStatus::Closed, 1, json!({ "amount": 7 }), json!({})));
+		// the status of the first change is what the ticket started with even when it is the default
+		assert_eq!(replay(&changes[..1]).unwrap().status, TicketStatus::Draft);
+	}
+}
@@ -0,0 +1,485 @@
+use std::collections::{BTreeMap, HashSet};
+use axum::{extract, http::StatusCode, Json};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::rbac::{Authorized, ManageTimesheets};
+use crate::schema::{FieldError, FieldErrors, field_error, internal_error, status_error};
+use crate::ticket;
+use utoipa::{IntoParams, ToSchema};
+
+const MAX_ENTRIES: usize = 200;
+const MINUTES_PER_DAY: i32 = 24 * 60;
+
+// the process whose tickets approve timesheets
+fn process_id() -> String {
+	return std::env::var("TIMESHEET_PROCESS").unwrap_or("timesheet".to_string());
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+pub struct NewEntry {
+	pub project: String,
+	pub work_date: NaiveDate,
+	pub minutes: i32,
+	pub note: Option<String>
+}
+
+// replaces all entries of the week
+#[derive(Deserialize, ToSchema)]
+pub struct SaveTimesheet {
+	pub entries: Vec<NewEntry>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Timesheet {
+	pub id: i32,
+	pub userid: uuid::Uuid,
+	pub week_start: NaiveDate,
+	pub status: String,
+	pub ticket_id: Option<i32>,
+	pub submitted_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Entry {
+	pub id: i32,
+	pub project: String,
+	pub work_date: NaiveDate,
+	pub minutes: i32,
+	pub note: Option<String>
+}
+
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct ProjectHours {
+	pub project: String,
+	pub minutes: i64,
+	pub hours: f64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TimesheetDetail {
+	#[serde(flatten)]
+	pub timesheet: Timesheet,
+	pub entries: Vec<Entry>,
+	pub projects: Vec<ProjectHours>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimesheetsQuery {
+	// weeks starting in the range, both included
+	pub from: Option<NaiveDate>,
+	pub to: Option<NaiveDate>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AllTimesheetsQuery {
+	pub week: Option<NaiveDate>,
+	pub status: Option<String>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProjectHoursQuery {
+	// days worked in the range, both included
+	pub from: NaiveDate,
+	pub to: NaiveDate,
+	pub userid: Option<uuid::Uuid>
+}
+
+// the monday of the week of `day`
+pub fn week_start(day: NaiveDate) -> NaiveDate {
+	return day - Duration::days(day.weekday().num_days_from_monday() as i64);
+}
+
+// what is wrong with the entries of the week starting on `week`
+pub fn check_entries(week: NaiveDate, entries: &[NewEntry]) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: String, message: String| errors.push(FieldError { field, message });
+	if entries.len() > MAX_ENTRIES {
+		error("/entries".to_string(), format!("At most {} entries", MAX_ENTRIES));
+		return errors;
+	}
+
+	let mut seen = HashSet::new();
+	let mut days: BTreeMap<NaiveDate, i32> = BTreeMap::new();
+	for (i, entry) in entries.iter().enumerate() {
+		let project = entry.project.trim();
+		if project.is_empty() {
+			error(format!("/entries/{}/project", i), "A project is required".to_string());
+		}
+		if week_start(entry.work_date) != week {
+			error(format!("/entries/{}/work_date", i), format!("Not in the week of {}", week));
+		}
+		if entry.minutes <= 0 || entry.minutes > MINUTES_PER_DAY {
+			error(format!("/entries/{}/minutes", i), "Must be between 1 and 1440".to_string());
+		} else {
+			*days.entry(entry.work_date).or_default() += entry.minutes;
+		}
+		if !seen.insert((project, entry.work_date)) {
+			error(format!("/entries/{}", i), "Another entry has the same project and day".to_string());
+		}
+	}
+	for (day, minutes) in days {
+		if minutes > MINUTES_PER_DAY {
+			error("/entries".to_string(), format!("More than 24 hours on {}", day));
+		}
+	}
+	return errors;
+}
+
+// minutes per project, in the order of the projects
+pub fn project_hours(minutes: impl IntoIterator<Item = (String, i64)>) -> Vec<ProjectHours> {
+	let mut projects: BTreeMap<String, i64> = BTreeMap::new();
+	for (project, minutes) in minutes {
+		*projects.entry(project).or_default() += minutes;
+	}
+	return projects.into_iter()
+		.map(|(project, minutes)| ProjectHours { project, minutes, hours: minutes as f64 / 60.0 })
+		.collect();
+}
+
+// the status a timesheet in `current` moves to when its ticket asks for `target`. a closed ticket approves the week
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("submitted", "approved" | "closed") => Some("approved"),
+		("submitted", "rejected") => Some("rejected"),
+		("submitted", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+fn check_week(week: NaiveDate) -> Result<(), (StatusCode, Json<FieldErrors>)> {
+	if week.weekday() != Weekday::Mon {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/week", format!("Weeks start on a monday, {} is a {}", week, week.weekday())));
+	}
+	return Ok(());
+}
+
+async fn read_timesheet(conn: &mut PgConnection, userid: uuid::Uuid, week: NaiveDate, lock: bool) -> Result<Timesheet, StatusCode> {
+	let sql = if lock {
+		"select * from timesheets where userid=$1 and week_start=$2 for update"
+	} else {
+		"select * from timesheets where userid=$1 and week_start=$2"
+	};
+	let timesheet: Result<Option<Timesheet>, _> = sqlx::query_as(sql)
+		.bind(userid)
+		.bind(week)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = timesheet {
+		admin_logger(LogType::Error, &format!("Error reading the timesheet of {} for {}: {}", userid, week, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return timesheet.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+async fn read_detail(conn: &mut PgConnection, timesheet: Timesheet) -> Result<TimesheetDetail, StatusCode> {
+	let entries: Result<Vec<Entry>, _> = sqlx::query_as(
+		"select id, project, work_date, minutes, note from timesheet_entries where timesheet_id=$1 order by work_date, project")
+		.bind(timesheet.id)
+		.fetch_all(&mut *conn)
+		.await;
+	if let Err(e) = entries {
+		admin_logger(LogType::Error, &format!("Error reading the entries of timesheet {}: {}", timesheet.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let entries = entries.unwrap();
+	let projects = project_hours(entries.iter().map(|e| (e.project.clone(), e.minutes as i64)));
+	return Ok(TimesheetDetail { timesheet, entries, projects });
+}
+
+#[utoipa::path(get, path = "/timesheets", tag = "timesheets", params(TimesheetsQuery), responses(
+	(status = 200, description = "Own timesheets", body = Vec<Timesheet>)
+))]
+pub async fn get_timesheets(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<TimesheetsQuery>
+) -> Result<Json<Vec<Timesheet>>, StatusCode> {
+	let timesheets: Result<Vec<Timesheet>, _> = sqlx::query_as(
+		r#"select * from timesheets where userid=$1 and ($2::date is null or week_start>=$2) and ($3::date is null or week_start<=$3)
+			order by week_start desc"#)
+		.bind(user.userid)
+		.bind(query.from)
+		.bind(query.to)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = timesheets {
+		admin_logger(LogType::Error, &format!("Error reading timesheets of {}: {}", user.userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(timesheets.unwrap()));
+}
+
+#[utoipa::path(get, path = "/timesheets/{week}", tag = "timesheets", params(("week" = NaiveDate, Path, description = "Monday the week starts on")), responses(
+	(status = 200, description = "The own timesheet of the week with its entries and hours by project", body = TimesheetDetail),
+	(status = 404, description = "No timesheet for the week")
+))]
+pub async fn get_timesheet(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(week): extract::Path<NaiveDate>
+) -> Result<Json<TimesheetDetail>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let timesheet = read_timesheet(&mut conn, user.userid, week, false).await?;
+	return Ok(Json(read_detail(&mut conn, timesheet).await?));
+}
+
+// replaces the entries of the week. rejected and cancelled weeks go back to draft, submitted and approved ones are locked
+#[utoipa::path(put, path = "/timesheets/{week}", tag = "timesheets", params(("week" = NaiveDate, Path, description = "Monday the week starts on")), request_body = SaveTimesheet, responses(
+	(status = 200, description = "Saved as a draft", body = TimesheetDetail),
+	(status = 409, description = "The week is submitted or approved", body = FieldErrors),
+	(status = 422, description = "Not a monday or invalid entries", body = FieldErrors)
+))]
+pub async fn save_timesheet(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(week): extract::Path<NaiveDate>,
+	Json(payload): Json<SaveTimesheet>
+) -> Result<Json<TimesheetDetail>, (StatusCode, Json<FieldErrors>)> {
+	check_week(week)?;
+	let errors = check_entries(week, &payload.entries);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let now = chrono::Utc::now();
+	let query = sqlx::query(
+		r#"insert into timesheets (userid, week_start, created_at, updated_at) values ($1, $2, $3, $3)
+			on conflict (tenant_id, userid, week_start) do nothing"#)
+		.bind(user.userid)
+		.bind(week)
+		.bind(now)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error saving the timesheet of {} for {}: {}", user.userid, week, e), None);
+		return Err(internal_error());
+	}
+	let timesheet = read_timesheet(&mut tx, user.userid, week, true).await.map_err(status_error)?;
+	match timesheet.status.as_str() {
+		"submitted" => return Err(field_error(StatusCode::CONFLICT, "/status", "The week is being approved, cancel its ticket to change it".to_string())),
+		"approved" => return Err(field_error(StatusCode::CONFLICT, "/status", "The week is approved and locked".to_string())),
+		_ => {}
+	}
+
+	let query = sqlx::query("delete from timesheet_entries where timesheet_id=$1")
+		.bind(timesheet.id)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error replacing the entries of timesheet {}: {}", timesheet.id, e), None);
+		return Err(internal_error());
+	}
+	for entry in &payload.entries {
+		let query = sqlx::query("insert into timesheet_entries (timesheet_id, project, work_date, minutes, note) values ($1, $2, $3, $4, $5)")
+			.bind(timesheet.id)
+			.bind(entry.project.trim())
+			.bind(entry.work_date)
+			.bind(entry.minutes)
+			.bind(&entry.note)
+			.execute(&mut *tx)
+			.await;
+		if let Err(e) = query {
+			admin_logger(LogType::Error, &format!("Error saving an entry of timesheet {}: {}", timesheet.id, e), None);
+			return Err(internal_error());
+		}
+	}
+
+	let timesheet: Result<Timesheet, _> = sqlx::query_as("update timesheets set status='draft', updated_at=$2 where id=$1 returning *")
+		.bind(timesheet.id)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = timesheet {
+		admin_logger(LogType::Error, &format!("Error updating the timesheet of {} for {}: {}", user.userid, week, e), None);
+		return Err(internal_error());
+	}
+	let detail = read_detail(&mut tx, timesheet.unwrap()).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting timesheet {}: {}", detail.timesheet.id, e), None);
+		return Err(internal_error());
+	}
+	return Ok(Json(detail));
+}
+
+// sends the week to the manager of the department of the user. it is locked until the ticket ends
+#[utoipa::path(post, path = "/timesheets/{week}/submit", tag = "timesheets", params(("week" = NaiveDate, Path, description = "Monday the week starts on")), responses(
+	(status = 200, description = "Submitted with its approval ticket", body = TimesheetDetail),
+	(status = 404, description = "No timesheet for the week", body = FieldErrors),
+	(status = 409, description = "The week is not a draft", body = FieldErrors),
+	(status = 422, description = "Not a monday or no entries", body = FieldErrors)
+))]
+pub async fn submit_timesheet(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(week): extract::Path<NaiveDate>
+) -> Result<Json<TimesheetDetail>, (StatusCode, Json<FieldErrors>)> {
+	check_week(week)?;
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let timesheet = read_timesheet(&mut tx, user.userid, week, true).await.map_err(status_error)?;
+	if !matches!(timesheet.status.as_str(), "draft" | "rejected" | "cancelled") {
+		return Err(field_error(StatusCode::CONFLICT, "/status", format!("The week is {}", timesheet.status)));
+	}
+	let detail = read_detail(&mut tx, timesheet).await.map_err(status_error)?;
+	if detail.entries.is_empty() {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/entries", "The week has no entries".to_string()));
+	}
+
+	let now = chrono::Utc::now();
+	let query = sqlx::query("update timesheets set status='submitted', submitted_at=$2, updated_at=$2 where id=$1")
+		.bind(detail.timesheet.id)
+		.bind(now)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error submitting timesheet {}: {}", detail.timesheet.id, e), None);
+		return Err(internal_error());
+	}
+
+	// the manager approves what is in the ticket
+	let total: i64 = detail.projects.iter().map(|p| p.minutes).sum();
+	let mut data = Map::new();
+	data.insert("timesheet_id".to_string(), json!(detail.timesheet.id));
+	data.insert("week_start".to_string(), json!(week));
+	data.insert("minutes".to_string(), json!(total));
+	data.insert("projects".to_string(), json!(detail.projects));
+	data.insert("entries".to_string(), json!(detail.entries));
+	let new = NewTicket { process_id: process_id(), data, tag: "timesheet", due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut tx, &user, Record { table: "timesheets", id: detail.timesheet.id }, new)
+		.await
+		.map_err(status_error)?;
+
+	// node 0 may already have moved it
+	let timesheet = read_timesheet(&mut tx, user.userid, week, false).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting timesheet {}: {}", timesheet.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	log(LogType::Info, format!("User {} submitted the timesheet of {} with ticket {}", user.userid, week, ticket.id), ticket.log_id);
+	return Ok(Json(TimesheetDetail { timesheet, ..detail }));
+}
+
+#[utoipa::path(get, path = "/admin/timesheets", tag = "timesheets", params(AllTimesheetsQuery), responses(
+	(status = 200, description = "Timesheets of every user", body = Vec<Timesheet>),
+	(status = 403, description = "Missing the manage_timesheets permission")
+))]
+pub async fn get_all_timesheets(
+	_auth: Authorized<ManageTimesheets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<AllTimesheetsQuery>
+) -> Result<Json<Vec<Timesheet>>, StatusCode> {
+	let timesheets: Result<Vec<Timesheet>, _> = sqlx::query_as(
+		r#"select * from timesheets where ($1::date is null or week_start=$1) and ($2::varchar is null or status=$2)
+			order by week_start desc, userid"#)
+		.bind(query.week)
+		.bind(&query.status)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = timesheets {
+		admin_logger(LogType::Error, &format!("Error reading timesheets: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(timesheets.unwrap()));
+}
+
+// hours worked on each project in the range, from approved timesheets only
+#[utoipa::path(get, path = "/admin/timesheets/projects", tag = "timesheets", params(ProjectHoursQuery), responses(
+	(status = 200, description = "Hours worked by project in the range", body = Vec<ProjectHours>),
+	(status = 403, description = "Missing the manage_timesheets permission")
+))]
+pub async fn get_project_hours(
+	_auth: Authorized<ManageTimesheets>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<ProjectHoursQuery>
+) -> Result<Json<Vec<ProjectHours>>, StatusCode> {
+	let minutes: Result<Vec<(String, i64)>, _> = sqlx::query_as(
+		r#"select e.project, sum(e.minutes)::bigint from timesheet_entries e join timesheets t on e.timesheet_id=t.id
+			where t.status='approved' and e.work_date between $1 and $2 and ($3::uuid is null or t.userid=$3)
+			group by e.project"#)
+		.bind(query.from)
+		.bind(query.to)
+		.bind(query.userid)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = minutes {
+		admin_logger(LogType::Error, &format!("Error reading project hours from {} to {}: {}", query.from, query.to, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(project_hours(minutes.unwrap())));
+}
+
+// moves the timesheet of the ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let timesheet: Option<(i32, String)> = sqlx::query_as("select id, status from timesheets where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = timesheet else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update timesheets set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Timesheet {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod timesheets_tests {
+	use chrono::NaiveDate;
+	use super::{check_entries, next_status, project_hours, week_start, NewEntry, ProjectHours};
+
+	fn entry(project: &str, day: u32, minutes: i32) -> NewEntry {
+		return NewEntry { project: project.to_string(), work_date: NaiveDate::from_ymd_opt(2024, 6, day).unwrap(), minutes, note: None };
+	}
+
+	#[test]
+	fn entries_stay_in_their_week() {
+		let week = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap();
+		assert_eq!(week_start(NaiveDate::from_ymd_opt(2024, 6, 9).unwrap()), week);
+		assert_eq!(week_start(week), week);
+
+		assert!(check_entries(week, &[entry("erp", 3, 480), entry("crm", 3, 120)]).is_empty());
+		let entries = vec![entry("erp", 3, 480), entry(" ", 4, 60), entry("erp", 10, 60), entry("crm", 5, 0), entry("erp ", 3, 1000), entry("crm", 4, 1440)];
+		let fields: Vec<String> = check_entries(week, &entries).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/entries/1/project", "/entries/2/work_date", "/entries/3/minutes", "/entries/4", "/entries", "/entries"]);
+	}
+
+	#[test]
+	fn hours_add_up_per_project() {
+		let hours = project_hours(vec![("erp".to_string(), 90), ("crm".to_string(), 30), ("erp".to_string(), 60)]);
+		assert_eq!(hours, vec![
+			ProjectHours { project: "crm".to_string(), minutes: 30, hours: 0.5 },
+			ProjectHours { project: "erp".to_string(), minutes: 150, hours: 2.5 }
+		]);
+		assert_eq!(next_status("submitted", "closed"), Some("approved"));
+		// approved weeks are locked
+		assert_eq!(next_status("approved", "cancelled"), None);
+	}
+}
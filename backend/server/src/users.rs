@@ -1,26 +1,33 @@
+use std::collections::HashMap;
 use axum::{http::StatusCode, Json, extract};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, QueryBuilder, Postgres};
+use sqlx::{PgConnection, PgPool, QueryBuilder, Postgres};
 use crate::logger::{LogType, admin_logger};
-#[derive(Deserialize)]
+use crate::auth;
+use crate::rbac::{Authorized, ManageUsers};
+use crate::api_error::db_status;
+use utoipa::{IntoParams, ToSchema};
+#[derive(Deserialize, ToSchema)]
 pub struct CreateUser {
 	username: String,
 }
-#[derive(Deserialize, sqlx::FromRow, Clone, Serialize)]
+#[derive(Deserialize, sqlx::FromRow, Clone, Serialize, ToSchema)]
 pub struct NewUser {
 	username: String,
 	roles: String,
 	email: String
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 // TODO: roles should be an array that is added directly to the new_users table after verification of roles
 pub struct RegisterNewUser {
 	username: String,
 	roles: String,
 	email: String,
+	password: String
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CheckUserApproved {
 	username: String
 }
@@ -30,15 +37,16 @@ pub struct CountQuery {
 }
 
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UserApprovedMsg {
 	pub status: bool
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct IsAdminReq {
 	username: String,
 }
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct IsAdminRes {
 	value: bool 
 }
@@ -51,21 +59,72 @@ impl UserApprovedMsg {
 	}
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetUserId {
 	username: String
 }
-#[derive(Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct UserIdQuery {
 	userid: Option<uuid::Uuid>
 }
 
+// users added directly by an admin, without going through new_users
+#[derive(Deserialize, ToSchema)]
+pub struct AddUser {
+	pub username: String,
+	pub email: Option<String>,
+	pub password: String,
+	pub roles: Vec<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateUser {
+	pub username: Option<String>,
+	pub email: Option<String>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersQuery {
+	pub include_inactive: Option<bool>
+}
 
+#[derive(Deserialize, ToSchema)]
+pub struct DeactivateUser {
+	// user that takes over the pending approvals and delegations. without it deactivation is refused while there are any
+	pub reassign_to: Option<uuid::Uuid>
+}
+
+#[derive(Serialize, sqlx::FromRow, ToSchema)]
+pub struct UserInfo {
+	pub userid: uuid::Uuid,
+	pub username: String,
+	pub email: Option<String>,
+	pub active: bool,
+	pub deactivated_at: Option<chrono::DateTime<chrono::Utc>>,
+	pub roles: Vec<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeactivatedUser {
+	pub reassigned_tickets: u64,
+	pub reassigned_delegations: u64,
+	pub revoked_sessions: u64
+}
+
+
+#[utoipa::path(post, path = "/users", tag = "users", request_body = CreateUser, responses(
+	(status = 201, description = "Approved the registration of the user"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 409, description = "Already approved")
+))]
 pub async fn create_user(
+	_auth: Authorized<ManageUsers>,
 	extract::State(pool) : extract::State<PgPool>,
 	Json(payload) : Json<CreateUser>
 ) -> Result<StatusCode, StatusCode> {
-	let mut tx = pool.begin().await.unwrap();
+	let mut tx = pool.begin().await.map_err(db_status)?;
 	let username = payload.username;
 
 	// get the userdata from new_users
@@ -76,8 +135,7 @@ pub async fn create_user(
 		.await;
 
 	if let Err(e) = new_user_query {
-		admin_logger(LogType::Error, &format!("Failed to get existing user data at create_user, username: {}, e: {}", username, e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Failed to get existing user data at create_user, username: {}, e: {}", username, e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
@@ -95,11 +153,30 @@ pub async fn create_user(
 		.await;
 
 	if let Err(e) = insert_into_user {
-		admin_logger(LogType::Error, &format!("Error inserting into user: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		// the username is taken in the tenant of the admin
+		if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) {
+			admin_logger(LogType::Warning, &format!("Username {} is already taken", new_user.username), None);
+			return Err(StatusCode::CONFLICT);
+		}
+		admin_logger(LogType::Error, &format!("Error inserting into user: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 	
+	// the password chosen at registration
+	let credentials_query = sqlx::query(
+		r#"insert into credentials (userid, password_hash, updated_at)
+			select $1, password_hash, $2 from new_users where username=$3 and password_hash is not null"#)
+		.bind(userid)
+		.bind(chrono::Utc::now())
+		.bind(&username)
+		.execute(&mut *tx)
+		.await;
+
+	if let Err(e) = credentials_query {
+		admin_logger(LogType::Error, &format!("Error inserting credentials in create_user, username: {}, e: {}", username, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
 	// insert roles
 	// verify correct roles
 	let roles = new_user.roles.split(',').map(|r| r.trim()).collect::<Vec<_>>();
@@ -109,21 +186,19 @@ pub async fn create_user(
 		.await;
 
 	if let Err(e) = role_query {
-		admin_logger(LogType::Error, &format!("Error in register_new_user in role_checking: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error in register_new_user in role_checking: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
 	let role_query = role_query.unwrap();
 	
 	if role_query[0].count != roles.len() as i64 {
-		admin_logger(LogType::Error, &format!("Error in create_new_user. Request contains non-existing roles: username: {}", username), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error in create_new_user. Request contains non-existing roles: username: {}", username), None);
 		return Err(StatusCode::CONFLICT);
 	}
 
 	// !!!! look at the trailing space
-	let mut role_query_builder : QueryBuilder<Postgres> = QueryBuilder::new("insert into roles (userid, role_) ");
+	let mut role_query_builder : QueryBuilder<Postgres> = QueryBuilder::new("insert into user_roles (userid, role_) ");
 	let insert_roles_query = 
 		role_query_builder.
 		push_values(roles.iter(), |mut b, role| {
@@ -133,8 +208,7 @@ pub async fn create_user(
 		.build();
 
 	if let Err(e) = insert_roles_query.execute(&mut *tx).await {
-		admin_logger(LogType::Error, &format!("Error inserting roles in create_user: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error inserting roles in create_user: {}", e), None);
 		return Err(StatusCode::CONFLICT);
 	}
 
@@ -145,19 +219,22 @@ pub async fn create_user(
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error removing user from new_users. username: {}, e: {}", username, e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error removing user from new_users. username: {}, e: {}", username, e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 	let result = tx.commit().await;
 	if let Err(e) = result {
-		admin_logger(LogType::Error, &format!("failed to commit transaction in create_user, username: {}, e: {}", username, e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("failed to commit transaction in create_user, username: {}, e: {}", username, e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 	return Ok(StatusCode::CREATED);
 }
 
+#[utoipa::path(post, path = "/new_user", tag = "users", security(()), request_body = RegisterNewUser, responses(
+	(status = 200, description = "Registered, waiting for approval"),
+	(status = 409, description = "Username taken"),
+	(status = 422, description = "Password too weak")
+))]
 pub async fn register_new_user(
 	extract::State(pool) : extract::State<PgPool>,
 	Json(payload) : Json<RegisterNewUser>
@@ -166,38 +243,39 @@ pub async fn register_new_user(
 	let roles = payload.roles;
 	let email = payload.email;
 
-	// check if the user has already registered;
-	let check_user_query : Result<Vec<CountQuery>, _> = sqlx::query_as("select count(*) from (select un.username from new_users un where username=$1 union select u.username from users u where username=$2) all_users")
-		.bind(&username)
+	auth::check_password_strength(&payload.password)?;
+	let password_hash = auth::hash_password(&payload.password).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+	// check if the user has already registered. usernames are unique per tenant, the tenant is the one of the admin
+	// who creates the user, so only the pending registrations can be checked here
+	let check_user_query : Result<Vec<CountQuery>, _> = sqlx::query_as("select count(*) from new_users where username=$1")
 		.bind(&username)
 		.fetch_all(&pool)
 		.await;
 
 	if let Err(e) = check_user_query {
-		admin_logger(LogType::Error, &format!("Error in register_new_user, username: {}, : {}", username, e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error in register_new_user, username: {}, : {}", username, e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
 	let check_user_query = check_user_query.unwrap();
 
 	if check_user_query[0].count != 0 {
-		admin_logger(LogType::Error, &format!("Error in register_new_user, username: {}", username), None)
-			.map_err(|_| StatusCode::CONFLICT)?;
+		admin_logger(LogType::Error, &format!("Error in register_new_user, username: {}", username), None);
 		return Err(StatusCode::CONFLICT);
 	}
 
 	// insert into new_users
-	let query = sqlx::query("insert into new_users (username, roles, email) values ($1, $2, $3)")
+	let query = sqlx::query("insert into new_users (username, roles, email, password_hash) values ($1, $2, $3, $4)")
 		.bind(&username)
 		.bind(&roles)
 		.bind(&email)
+		.bind(&password_hash)
 		.execute(&pool)
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error registering new user: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error registering new user: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
@@ -205,6 +283,9 @@ pub async fn register_new_user(
 }
 
 
+#[utoipa::path(get, path = "/new_user/approved", tag = "users", security(()), params(CheckUserApproved), responses(
+	(status = 200, description = "Whether the registration was approved", body = UserApprovedMsg)
+))]
 pub async fn check_user_approved(
 	extract::State(pool) : extract::State<PgPool>,
 	payload : extract::Query<CheckUserApproved>
@@ -217,8 +298,7 @@ pub async fn check_user_approved(
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error checking new user status: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error checking new user status: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 	let query = query.unwrap();
@@ -229,20 +309,40 @@ pub async fn check_user_approved(
 	return Ok((StatusCode::OK, Json(UserApprovedMsg::get(false))));
 }
 
+// the userids of `usernames` in one query. usernames that do not exist are left out
+pub async fn userids(conn: &mut PgConnection, usernames: &[&str]) -> Result<HashMap<String, uuid::Uuid>, sqlx::Error> {
+	let query: Vec<(String, uuid::Uuid)> = sqlx::query_as("select username, userid from users where username=any($1)")
+		.bind(usernames)
+		.fetch_all(conn)
+		.await?;
+	return Ok(query.into_iter().collect());
+}
+
+pub async fn user_is_admin(conn: &mut PgConnection, userid: uuid::Uuid) -> Result<bool, sqlx::Error> {
+	let query = sqlx::query("select role_ from user_roles where userid=$1 and role_='admin'")
+		.bind(userid)
+		.fetch_optional(conn)
+		.await?;
+
+	return Ok(query.is_some());
+}
+
+#[utoipa::path(get, path = "/is_admin", tag = "users", params(IsAdminReq), responses(
+	(status = 200, description = "Whether the user has the admin role", body = IsAdminRes)
+))]
 pub async fn is_admin(
 	payload : extract::Query<IsAdminReq>,
 	extract::State(pool) : extract::State<PgPool>
 ) -> Result<(StatusCode, Json<IsAdminRes>), StatusCode> {
 
 	let username = payload.0.username;
-	let query = sqlx::query("select role_ from users u join roles r on u.userid = r.userid where u.username=$1 and role_='admin'")
+	let query = sqlx::query("select role_ from users u join user_roles r on u.userid = r.userid where u.username=$1 and role_='admin'")
 		.bind(&username)
 		.fetch_all(&pool)
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error in is_admin: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error in is_admin: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 	
@@ -256,7 +356,12 @@ pub async fn is_admin(
 	return Ok((StatusCode::OK, Json(IsAdminRes { value: false })));
 }
 
+#[utoipa::path(get, path = "/new_user", tag = "users", responses(
+	(status = 200, description = "Registrations waiting for approval", body = Vec<NewUser>),
+	(status = 403, description = "Missing the manage_users permission")
+))]
 pub async fn get_all_new_users(
+	_auth: Authorized<ManageUsers>,
 	extract::State(pool) : extract::State<PgPool>
 ) -> Result<(StatusCode, Json<Vec<NewUser>>), StatusCode> {
 
@@ -265,8 +370,7 @@ pub async fn get_all_new_users(
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error fetching new users at get_all_new_users. e: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error fetching new users at get_all_new_users. e: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
@@ -274,6 +378,9 @@ pub async fn get_all_new_users(
 	return Ok((StatusCode::OK, Json(query)));
 }
 #[axum::debug_handler]
+#[utoipa::path(get, path = "/userid", tag = "users", params(GetUserId), responses(
+	(status = 200, description = "Id of the user, null for unknown users", body = UserIdQuery)
+))]
 pub async fn get_userid(
 	extract::State(pool) : extract::State<PgPool>,
 	payload : extract::Query<GetUserId>
@@ -286,12 +393,429 @@ pub async fn get_userid(
 		.await;
 
 	if let Err(e) = query {
-		admin_logger(LogType::Error, &format!("Error fetching userid at get_userid. e: {}", e), None)
-			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+		admin_logger(LogType::Error, &format!("Error fetching userid at get_userid. e: {}", e), None);
 		return Err(StatusCode::INTERNAL_SERVER_ERROR);
 	}
 
 	let query = query.unwrap();
 
 	return Ok((StatusCode::OK, Json(query)));
-}
\ No newline at end of file
+}
+pub fn check_username(username: &str) -> Result<String, String> {
+	let username = username.trim();
+	if username.is_empty() {
+		return Err("A username is required".to_string());
+	}
+	if username.chars().any(|c| c.is_whitespace() || c == ',') {
+		return Err(format!("Username {} contains whitespace or commas", username));
+	}
+	return Ok(username.to_string());
+}
+
+// usernames are unique across the users of the tenant and pending registrations
+async fn username_taken(conn: &mut PgConnection, username: &str, except: Option<uuid::Uuid>) -> Result<bool, sqlx::Error> {
+	let query = sqlx::query(
+		r#"select 1 from users where username=$1 and userid is distinct from $2
+			union all select 1 from new_users where username=$1 limit 1"#)
+		.bind(username)
+		.bind(except)
+		.fetch_optional(conn)
+		.await?;
+	return Ok(query.is_some());
+}
+
+async fn read_user(conn: &mut PgConnection, userid: uuid::Uuid) -> Result<Option<UserInfo>, sqlx::Error> {
+	return sqlx::query_as(
+		r#"select u.userid, u.username, u.email, u.active, u.deactivated_at,
+				coalesce((select array_agg(role_ order by role_) from user_roles where userid=u.userid), '{}') as roles
+			from users u where u.userid=$1"#)
+		.bind(userid)
+		.fetch_optional(conn)
+		.await;
+}
+
+#[utoipa::path(post, path = "/admin/users", tag = "users", request_body = AddUser, responses(
+	(status = 201, description = "Created", body = UserInfo),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 409, description = "Username taken"),
+	(status = 422, description = "Invalid username, password or role")
+))]
+pub async fn add_user(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool) : extract::State<PgPool>,
+	Json(payload) : Json<AddUser>
+) -> Result<(StatusCode, Json<UserInfo>), (StatusCode, String)> {
+	let username = check_username(&payload.username).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+	auth::check_password_strength(&payload.password).map_err(|s| (s, "Password is too short".to_string()))?;
+	let password_hash = auth::hash_password(&payload.password).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+	let roles = payload.roles.iter().map(|r| r.trim().to_string()).collect::<Vec<_>>();
+
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	match username_taken(&mut tx, &username, None).await {
+		Err(e) => {
+			admin_logger(LogType::Error, &format!("Error checking username {}: {}", username, e), None);
+			return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+		}
+		Ok(true) => return Err((StatusCode::CONFLICT, format!("Username {} is taken", username))),
+		Ok(false) => {}
+	}
+
+	let known_roles: Result<(i64,), _> = sqlx::query_as("select count(*) from role_defs where role_=any($1)")
+		.bind(&roles)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = known_roles {
+		admin_logger(LogType::Error, &format!("Error checking roles of new user {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let mut unique_roles = roles.clone();
+	unique_roles.sort();
+	unique_roles.dedup();
+	if known_roles.unwrap().0 != unique_roles.len() as i64 {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "Request contains roles that do not exist".to_string()));
+	}
+
+	let userid = uuid::Uuid::new_v4();
+	let query = sqlx::query("insert into users (userid, username, email) values ($1, $2, $3)")
+		.bind(userid)
+		.bind(&username)
+		.bind(&payload.email)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting user {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	let query = sqlx::query("insert into credentials (userid, password_hash, updated_at) values ($1, $2, $3)")
+		.bind(userid)
+		.bind(&password_hash)
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting credentials of {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	let query = sqlx::query("insert into user_roles (userid, role_) select $1, unnest($2::varchar[])")
+		.bind(userid)
+		.bind(&unique_roles)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error inserting roles of {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	let user = read_user(&mut tx, userid).await;
+	if let Err(e) = user {
+		admin_logger(LogType::Error, &format!("Error reading new user {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting new user {}: {}", username, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} added user {} ({}) with roles {:?}", auth.user.userid, username, userid, unique_roles), None);
+	return Ok((StatusCode::CREATED, Json(user.unwrap().unwrap())));
+}
+
+#[utoipa::path(get, path = "/admin/users", tag = "users", params(ListUsersQuery), responses(
+	(status = 200, description = "Users with their roles", body = Vec<UserInfo>),
+	(status = 403, description = "Missing the manage_users permission")
+))]
+pub async fn list_users(
+	_auth: Authorized<ManageUsers>,
+	extract::Query(query): extract::Query<ListUsersQuery>,
+	extract::State(pool) : extract::State<PgPool>
+) -> Result<Json<Vec<UserInfo>>, StatusCode> {
+	let result: Result<Vec<UserInfo>, _> = sqlx::query_as(
+		r#"select u.userid, u.username, u.email, u.active, u.deactivated_at,
+				coalesce((select array_agg(role_ order by role_) from user_roles where userid=u.userid), '{}') as roles
+			from users u where u.active=true or $1 order by u.username"#)
+		.bind(query.include_inactive.unwrap_or(false))
+		.fetch_all(&pool)
+		.await;
+
+	if let Err(e) = result {
+		admin_logger(LogType::Error, &format!("Error reading users: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(result.unwrap()));
+}
+
+#[utoipa::path(get, path = "/admin/users/{id}", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "The user with their roles", body = UserInfo),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown user")
+))]
+pub async fn get_user(
+	_auth: Authorized<ManageUsers>,
+	extract::State(pool) : extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<Json<UserInfo>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(db_status)?;
+
+	let user = read_user(&mut conn, userid).await;
+	if let Err(e) = user {
+		admin_logger(LogType::Error, &format!("Error reading user {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(user.unwrap().ok_or(StatusCode::NOT_FOUND)?));
+}
+
+// processes name their approvers by username, so renaming a user that is still referenced
+// by a process definition reroutes those approvals. the caller is expected to update the processes too
+#[utoipa::path(put, path = "/admin/users/{id}", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), request_body = UpdateUser, responses(
+	(status = 200, description = "Updated", body = UserInfo),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown user"),
+	(status = 409, description = "Username taken"),
+	(status = 422, description = "Invalid username or email")
+))]
+pub async fn update_user(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool) : extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>,
+	Json(payload) : Json<UpdateUser>
+) -> Result<Json<UserInfo>, (StatusCode, String)> {
+	let username = match payload.username.as_deref() {
+		Some(u) => Some(check_username(u).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?),
+		None => None
+	};
+
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	if let Some(username) = username.as_deref() {
+		match username_taken(&mut tx, username, Some(userid)).await {
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error checking username {}: {}", username, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			Ok(true) => return Err((StatusCode::CONFLICT, format!("Username {} is taken", username))),
+			Ok(false) => {}
+		}
+	}
+
+	let query = sqlx::query("update users set username=coalesce($2, username), email=coalesce($3, email) where userid=$1")
+		.bind(userid)
+		.bind(&username)
+		.bind(&payload.email)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error updating user {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err((StatusCode::NOT_FOUND, format!("User {} does not exist", userid)));
+	}
+
+	let user = read_user(&mut tx, userid).await;
+	if let Err(e) = user {
+		admin_logger(LogType::Error, &format!("Error reading user {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting update of user {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info, &format!("User {} updated user {}", auth.user.userid, userid), None);
+	return Ok(Json(user.unwrap().unwrap()));
+}
+
+#[utoipa::path(post, path = "/admin/users/{id}/deactivate", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), request_body = DeactivateUser, responses(
+	(status = 200, description = "Deactivated, their open work is reassigned and sessions revoked", body = DeactivatedUser),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown user"),
+	(status = 409, description = "Already inactive"),
+	(status = 422, description = "Invalid reassign_to")
+))]
+pub async fn deactivate_user(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool) : extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>,
+	Json(payload) : Json<DeactivateUser>
+) -> Result<Json<DeactivatedUser>, (StatusCode, String)> {
+	/*
+		1. Refuse to deactivate the last active user holding every permission
+		2. Without reassign_to, refuse while the user holds pending approvals or is the delegate of a running delegation
+		3. With reassign_to, move the approvals and delegations to that user
+		4. Mark the user inactive and end their sessions. login is refused for inactive users
+	*/
+	if payload.reassign_to == Some(userid) {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, "A user cannot take over their own approvals".to_string()));
+	}
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+
+	let user: Result<Option<(bool,)>, _> = sqlx::query_as("select active from users where userid=$1 for update")
+		.bind(userid)
+		.fetch_optional(&mut *tx)
+		.await;
+	if let Err(e) = user {
+		admin_logger(LogType::Error, &format!("Error reading user {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	match user.unwrap() {
+		None => return Err((StatusCode::NOT_FOUND, format!("User {} does not exist", userid))),
+		Some((false,)) => return Err((StatusCode::CONFLICT, format!("User {} is already inactive", userid))),
+		Some((true,)) => {}
+	}
+
+	let superusers: Result<(i64,), _> = sqlx::query_as(
+		r#"select count(distinct ur.userid) from user_roles ur join role_permissions rp on ur.tenant_id=rp.tenant_id and ur.role_=rp.role_ join users u on ur.userid=u.userid
+			where rp.permission='*' and u.active=true and ur.userid<>$1"#)
+		.bind(userid)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = superusers {
+		admin_logger(LogType::Error, &format!("Error checking remaining admins: {}", e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	if superusers.unwrap().0 == 0 {
+		return Err((StatusCode::CONFLICT, "Nobody would be left with every permission".to_string()));
+	}
+
+	let (reassigned_tickets, reassigned_delegations) = match payload.reassign_to {
+		None => {
+			let pending: Result<(i64, i64), _> = sqlx::query_as(
+				r#"select (select count(*) from user_active_tickets where userid=$1 and type_='approve' and active=true),
+					(select count(*) from user_delegations where delegate_id=$1 and ends_at>$2)"#)
+				.bind(userid)
+				.bind(now)
+				.fetch_one(&mut *tx)
+				.await;
+			if let Err(e) = pending {
+				admin_logger(LogType::Error, &format!("Error counting pending work of {}: {}", userid, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			let (tickets, delegations) = pending.unwrap();
+			if tickets > 0 || delegations > 0 {
+				return Err((StatusCode::CONFLICT,
+					format!("User {} has {} pending approvals and {} delegations. reassign them with reassign_to", userid, tickets, delegations)));
+			}
+			(0, 0)
+		}
+		Some(target) => {
+			let target_active: Result<Option<(bool,)>, _> = sqlx::query_as("select active from users where userid=$1")
+				.bind(target)
+				.fetch_optional(&mut *tx)
+				.await;
+			if let Err(e) = target_active {
+				admin_logger(LogType::Error, &format!("Error reading user {}: {}", target, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			if target_active.unwrap() != Some((true,)) {
+				return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("User {} does not exist or is inactive", target)));
+			}
+
+			let tickets = sqlx::query("update user_active_tickets set userid=$2 where userid=$1 and type_='approve' and active=true")
+				.bind(userid)
+				.bind(target)
+				.execute(&mut *tx)
+				.await;
+			if let Err(e) = tickets {
+				admin_logger(LogType::Error, &format!("Error reassigning approvals of {} to {}: {}", userid, target, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+
+			// a delegation from the new delegate to themselves makes no sense, those are ended instead
+			let ended = sqlx::query("update user_delegations set ends_at=$3 where delegate_id=$1 and userid=$2 and ends_at>$3")
+				.bind(userid)
+				.bind(target)
+				.bind(now)
+				.execute(&mut *tx)
+				.await;
+			if let Err(e) = ended {
+				admin_logger(LogType::Error, &format!("Error ending delegations of {} to {}: {}", target, userid, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			let delegations = sqlx::query("update user_delegations set delegate_id=$2 where delegate_id=$1 and ends_at>$3")
+				.bind(userid)
+				.bind(target)
+				.bind(now)
+				.execute(&mut *tx)
+				.await;
+			if let Err(e) = delegations {
+				admin_logger(LogType::Error, &format!("Error reassigning delegations of {} to {}: {}", userid, target, e), None);
+				return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+			}
+			(tickets.unwrap().rows_affected(), delegations.unwrap().rows_affected() + ended.unwrap().rows_affected())
+		}
+	};
+
+	let query = sqlx::query("update users set active=false, deactivated_at=$2 where userid=$1")
+		.bind(userid)
+		.bind(now)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deactivating user {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	let revoked_sessions = auth::revoke_sessions(&mut tx, userid, None).await;
+	if let Err(e) = revoked_sessions {
+		admin_logger(LogType::Error, &format!("Error revoking sessions of {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting deactivation of {}: {}", userid, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+
+	admin_logger(LogType::Info,
+		&format!("User {} deactivated {}. reassigned {} approvals and {} delegations to {:?}", auth.user.userid, userid, reassigned_tickets, reassigned_delegations, payload.reassign_to),
+		None);
+	return Ok(Json(DeactivatedUser { reassigned_tickets, reassigned_delegations, revoked_sessions: revoked_sessions.unwrap() }));
+}
+
+#[utoipa::path(post, path = "/admin/users/{id}/activate", tag = "users", params(("id" = uuid::Uuid, Path, description = "User id")), responses(
+	(status = 200, description = "Active again"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown user")
+))]
+pub async fn activate_user(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool) : extract::State<PgPool>,
+	extract::Path(userid): extract::Path<uuid::Uuid>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("update users set active=true, deactivated_at=null where userid=$1 and active=false")
+		.bind(userid)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error activating user {}: {}", userid, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} activated {}", auth.user.userid, userid), None);
+	return Ok(StatusCode::OK);
+}
+
+#[cfg(test)]
+mod users_tests {
+	use super::check_username;
+
+	#[test]
+	fn usernames_are_checked() {
+		assert_eq!(check_username("  jdoe "), Ok("jdoe".to_string()));
+		assert!(check_username("   ").is_err());
+		// roles and approver lists are comma separated
+		assert!(check_username("j,doe").is_err());
+		assert!(check_username("j doe").is_err());
+	}
+}
@@ -1,8 +1,10 @@
 use serde::Serialize;
-use serde_json::{Map, Value};
+use serde_json::{json, Map, Value};
 
 #[derive(Serialize)]
 pub struct TaskPayload<'a> {
+	// echoed back on /ticket/:id/callback_result
+	job_id: i64,
 	ticket_id: i32,
 	node: i32,
 	cur_node_payload: &'a Option<Map<String, Value>>
@@ -24,13 +26,58 @@ pub fn check_n_complete(complete_mask: i32, num_nodes: i32) -> bool {
 	return complete_mask.trailing_ones() == (num_nodes - 1) as u32;
 }
 
+pub fn start_instances(instances: &mut Value, node: i32, total: usize) {
+	if !instances.is_object() {
+		*instances = Value::Object(Map::new());
+	}
+	instances[node.to_string()] = json!({ "total": total, "done": [] });
+}
+
+// marks one instance of a multi instance node as done.
+// returns None if the instance is not pending, otherwise whether all instances of the node are done
+pub fn complete_instance(instances: &mut Value, node: i32, instance: i32) -> Option<bool> {
+	let progress = instances.get_mut(node.to_string())?;
+	let total = progress.get("total")?.as_i64()?;
+	if instance < 0 || instance as i64 >= total {
+		return None;
+	}
+
+	let done = progress.get_mut("done")?.as_array_mut()?;
+	if done.contains(&json!(instance)) {
+		return None;
+	}
+	done.push(json!(instance));
+
+	return Some(done.len() as i64 == total);
+}
+
+// RFC 7396 json merge patch. objects are merged key by key, a null removes the key and any other value replaces it
+pub fn merge_patch(target: &mut Value, patch: &Map<String, Value>) {
+	if !target.is_object() {
+		*target = Value::Object(Map::new());
+	}
+	let target = target.as_object_mut().unwrap();
+	for (key, value) in patch {
+		match value {
+			Value::Null => {
+				target.remove(key);
+			}
+			Value::Object(patch) => merge_patch(target.entry(key.clone()).or_insert(Value::Null), patch),
+			_ => {
+				target.insert(key.clone(), value.clone());
+			}
+		}
+	}
+}
+
 pub fn gen_random_token() -> String {
 	// TODO: maybe use something else
 	return uuid::Uuid::new_v4().to_string();
 }
 
-pub fn make_task_payload(ticket_id: i32, node: i32, data: &Option<Map<String, Value>>) -> String {
+pub fn make_task_payload(job_id: i64, ticket_id: i32, node: i32, data: &Option<Map<String, Value>>) -> String {
 	return serde_json::to_string(&TaskPayload {
+		job_id,
 		ticket_id,
 		node,
 		cur_node_payload: data,
@@ -44,7 +91,7 @@ mod utils_test {
 	fn complete_mask_check_true_test(){
 		let steps: Vec<i32> = vec![0, 3, 7];
 		let complete_mask = 0x89i32;
-		assert_eq!(check_required_complete(complete_mask, &steps), true);
+		assert!(check_required_complete(complete_mask, &steps));
 	}
 
 	#[test]
@@ -52,7 +99,7 @@ mod utils_test {
 		// 1 is not completed
 		let steps: Vec<i32> = vec![0, 1, 3, 7];
 		let complete_mask = 0x89i32;
-		assert_eq!(check_required_complete(complete_mask, &steps), false);
+		assert!(!check_required_complete(complete_mask, &steps));
 	}
 
 	#[test]
@@ -60,15 +107,69 @@ mod utils_test {
 		// 1st node is initiate and 4th node is complete
 		let num_nodes = 4;
 		let complete_mask = 0x7i32;
-		assert_eq!(check_n_complete(complete_mask, num_nodes), true);
+		assert!(check_n_complete(complete_mask, num_nodes));
 
 		let complete_mask = 0x5i32;
-		assert_eq!(check_n_complete(complete_mask, num_nodes), false);
+		assert!(!check_n_complete(complete_mask, num_nodes));
+	}
+
+	#[test]
+	fn instances_complete_once_all_done() {
+		let mut instances = json!({});
+		start_instances(&mut instances, 2, 2);
+		assert_eq!(complete_instance(&mut instances, 2, 0), Some(false));
+		// same instance cannot be completed twice
+		assert_eq!(complete_instance(&mut instances, 2, 0), None);
+		assert_eq!(complete_instance(&mut instances, 2, 5), None);
+		assert_eq!(complete_instance(&mut instances, 3, 0), None);
+		assert_eq!(complete_instance(&mut instances, 2, 1), Some(true));
+	}
+
+	fn patched(target: Value, patch: Value) -> Value {
+		let mut target = target;
+		merge_patch(&mut target, patch.as_object().unwrap());
+		return target;
+	}
+
+	#[test]
+	fn merge_patch_merges_nested_objects() {
+		let state = json!({ "amount": 10, "vendor": { "name": "Acme", "address": { "city": "Pune", "pin": "411001" } }, "items": [1, 2] });
+		let patch = json!({ "vendor": { "address": { "city": "Mumbai", "pin": null }, "gst": "27AAA" }, "items": [3] });
+		assert_eq!(patched(state, patch), json!({
+			"amount": 10,
+			"vendor": { "name": "Acme", "gst": "27AAA", "address": { "city": "Mumbai" } },
+			// arrays are replaced, not merged
+			"items": [3]
+		}));
+
+		// an object replaces a value that was not one, and its nulls are not kept
+		let patch = json!({ "vendor": { "name": "Acme", "gst": null }, "note": null });
+		assert_eq!(patched(json!({ "vendor": "Acme", "note": "x" }), patch), json!({ "vendor": { "name": "Acme" } }));
+	}
+
+	#[test]
+	fn merge_patch_follows_the_rfc_examples() {
+		// test cases of appendix A of RFC 7396 whose patch is an object
+		let cases = [
+			(json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+			(json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+			(json!({"a": "b"}), json!({"a": null}), json!({})),
+			(json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+			(json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+			(json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+			(json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+			(json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+			(json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+			(json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+			(json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}}))
+		];
+		for (target, patch, expected) in cases {
+			assert_eq!(patched(target.clone(), patch.clone()), expected, "{} patched with {}", target, patch);
+		}
 	}
 
 	#[test]
 	fn check_token_gen() {
-		let userid = uuid::Uuid::new_v4();
 		let res = gen_random_token();
 		// 36+1+36 chars
 		assert_eq!(res.len(), 36);
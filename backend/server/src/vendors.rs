@@ -0,0 +1,494 @@
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::AuthUser;
+use crate::linked::{self, FollowErr, NewTicket, Record};
+use crate::logger::{admin_logger, log, LogType};
+use crate::process::{provider, Step};
+use crate::rbac::{Authorized, ManageVendors};
+use crate::schema::{FieldError, FieldErrors, internal_error, status_error};
+use crate::ticket::{self, Event};
+use crate::ws::LiveEvent;
+use utoipa::{IntoParams, ToSchema};
+
+// the process whose tickets onboard vendors
+fn process_id() -> String {
+	return std::env::var("VENDOR_ONBOARDING_PROCESS").unwrap_or("vendor_onboarding".to_string());
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct VendorInput {
+	pub name: String,
+	pub tax_id: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	pub address: Option<String>,
+	pub bank_account_holder: String,
+	// spaces are allowed
+	pub iban: String,
+	pub bic: Option<String>
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Vendor {
+	pub id: i32,
+	pub name: String,
+	pub tax_id: Option<String>,
+	pub email: Option<String>,
+	pub phone: Option<String>,
+	pub address: Option<String>,
+	pub bank_account_holder: String,
+	pub iban: String,
+	pub bic: Option<String>,
+	pub status: String,
+	pub ticket_id: Option<i32>,
+	pub created_by: uuid::Uuid,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VendorsQuery {
+	pub status: Option<String>
+}
+
+// "DE89 3704 0044 0532 0130 00" -> "DE89370400440532013000"
+pub fn normalize_iban(iban: &str) -> String {
+	return iban.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+}
+
+// country code, check digits and up to 30 letters or digits, with the checksum of iso 13616
+pub fn valid_iban(iban: &str) -> bool {
+	let bytes = iban.as_bytes();
+	if bytes.len() < 15 || bytes.len() > 34 || !bytes.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+		return false;
+	}
+	if !bytes[..2].iter().all(|b| b.is_ascii_uppercase()) || !bytes[2..4].iter().all(|b| b.is_ascii_digit()) {
+		return false;
+	}
+	// the first four characters move to the end, letters count as 10 to 35
+	let remainder = bytes[4..].iter().chain(bytes[..4].iter()).fold(0u32, |remainder, b| {
+		let value = if b.is_ascii_digit() { (b - b'0') as u32 } else { (b - b'A') as u32 + 10 };
+		let factor = if value < 10 { 10 } else { 100 };
+		return (remainder * factor + value) % 97;
+	});
+	return remainder == 1;
+}
+
+// bank code and country in letters, then location and optionally branch
+pub fn valid_bic(bic: &str) -> bool {
+	let bytes = bic.as_bytes();
+	return (bytes.len() == 8 || bytes.len() == 11)
+		&& bytes[..6].iter().all(|b| b.is_ascii_uppercase())
+		&& bytes[6..].iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+}
+
+// what is wrong with the vendor. the iban is checked after normalize_iban
+pub fn check_vendor(vendor: &VendorInput) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if vendor.name.trim().is_empty() {
+		error("/name", "A name is required");
+	}
+	if vendor.bank_account_holder.trim().is_empty() {
+		error("/bank_account_holder", "An account holder is required");
+	}
+	if !valid_iban(&normalize_iban(&vendor.iban)) {
+		error("/iban", "Not a valid IBAN");
+	}
+	if vendor.bic.as_deref().is_some_and(|bic| !valid_bic(bic.trim())) {
+		error("/bic", "Expected 8 or 11 characters like DEUTDEFF");
+	}
+	if vendor.email.as_deref().is_some_and(|email| !email.contains('@')) {
+		error("/email", "Not an email address");
+	}
+	return errors;
+}
+
+// the status a vendor in `current` moves to when its onboarding ticket asks for `target`
+pub fn next_status(current: &str, target: &str) -> Option<&'static str> {
+	return match (current, target) {
+		("pending_onboarding", "active" | "closed") => Some("active"),
+		("pending_onboarding", "rejected") => Some("rejected"),
+		("pending_onboarding", "cancelled") => Some("cancelled"),
+		_ => None
+	};
+}
+
+// problems with the vendors referenced by the steps of a process, given the vendors that are active
+pub fn vendor_errors(steps: &[Step], active: &[i32]) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	for (i, step) in steps.iter().enumerate() {
+		let Some(vendor_id) = step.vendor_id else {
+			continue;
+		};
+		let field = format!("/steps/{}/vendor_id", i);
+		if !matches!(step.event, Event::BlockingTask) {
+			errors.push(FieldError { field, message: "Only blocking_task nodes can reference a vendor".to_string() });
+		} else if !active.contains(&vendor_id) {
+			errors.push(FieldError { field, message: format!("Vendor {} does not exist or is not active", vendor_id) });
+		}
+	}
+	return errors;
+}
+
+// checks the vendors a process references before it is saved
+pub async fn check_process_vendors(conn: &mut PgConnection, steps: &[Step]) -> Result<Vec<FieldError>, sqlx::Error> {
+	let ids: Vec<i32> = steps.iter().filter_map(|s| s.vendor_id).collect();
+	if ids.is_empty() {
+		return Ok(Vec::new());
+	}
+	// a deactivation running at the same time waits for the process to be saved and finds it
+	let active: Vec<(i32,)> = sqlx::query_as("select id from vendors where id=any($1) and status='active' for share")
+		.bind(&ids)
+		.fetch_all(&mut *conn)
+		.await?;
+	let active: Vec<i32> = active.into_iter().map(|(id,)| id).collect();
+	return Ok(vendor_errors(steps, &active));
+}
+
+// the processes with a blocking_task node the vendor completes. read through the provider, so definitions that were
+// not saved by create_process are found too
+async fn referencing_processes(conn: &mut PgConnection, vendor_id: i32) -> Result<Vec<String>, String> {
+	let pids: Vec<(String,)> = sqlx::query_as("select process_id from process_defs order by process_id")
+		.fetch_all(&mut *conn)
+		.await
+		.map_err(|e| e.to_string())?;
+	let mut referencing = Vec::new();
+	for (pid,) in pids {
+		let Some(process) = provider::provider().read(&pid).await? else {
+			continue;
+		};
+		if process.steps.iter().any(|s| s.vendor_id == Some(vendor_id)) {
+			referencing.push(pid);
+		}
+	}
+	return Ok(referencing);
+}
+
+fn name_taken(e: &sqlx::Error) -> bool {
+	return e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false);
+}
+
+async fn read_vendor(conn: &mut PgConnection, id: i32, lock: bool) -> Result<Vendor, StatusCode> {
+	let sql = if lock { "select * from vendors where id=$1 for update" } else { "select * from vendors where id=$1" };
+	let vendor: Result<Option<Vendor>, _> = sqlx::query_as(sql)
+		.bind(id)
+		.fetch_optional(&mut *conn)
+		.await;
+	if let Err(e) = vendor {
+		admin_logger(LogType::Error, &format!("Error reading vendor {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return vendor.unwrap().ok_or(StatusCode::NOT_FOUND);
+}
+
+// starts the onboarding ticket of a saved vendor and returns its status after node 0
+async fn onboard(conn: &mut PgConnection, user: &AuthUser, vendor: &mut Vendor) -> Result<Vec<(uuid::Uuid, LiveEvent)>, StatusCode> {
+	// compliance and finance check the details they approve in the ticket
+	let mut data = Map::new();
+	data.insert("vendor_id".to_string(), json!(vendor.id));
+	data.insert("name".to_string(), json!(vendor.name));
+	data.insert("tax_id".to_string(), json!(vendor.tax_id));
+	data.insert("bank_account_holder".to_string(), json!(vendor.bank_account_holder));
+	data.insert("iban".to_string(), json!(vendor.iban));
+	data.insert("bic".to_string(), json!(vendor.bic));
+	let new = NewTicket { process_id: process_id(), data, tag: "vendor", due_at: None };
+	let (ticket, events) = linked::start_ticket(&mut *conn, user, Record { table: "vendors", id: vendor.id }, new).await?;
+
+	let status: Result<(String,), _> = sqlx::query_as("select status from vendors where id=$1")
+		.bind(vendor.id)
+		.fetch_one(&mut *conn)
+		.await;
+	if let Err(e) = status {
+		admin_logger(LogType::Error, &format!("Error reading vendor {}: {}", vendor.id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	vendor.status = status.unwrap().0;
+	vendor.ticket_id = Some(ticket.id);
+	log(LogType::Info, format!("Onboarding of vendor {} started with ticket {}", vendor.id, ticket.id), ticket.log_id);
+	return Ok(events);
+}
+
+// saves the vendor and starts its onboarding. it can be referenced by processes once compliance and finance approved it
+#[utoipa::path(post, path = "/vendors", tag = "vendors", request_body = VendorInput, responses(
+	(status = 201, description = "Created with its onboarding ticket", body = Vendor),
+	(status = 403, description = "Missing the manage_vendors permission"),
+	(status = 409, description = "A vendor with this name exists", body = FieldErrors),
+	(status = 422, description = "Invalid details, iban or bic", body = FieldErrors)
+))]
+pub async fn create_vendor(
+	auth: Authorized<ManageVendors>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<VendorInput>
+) -> Result<(StatusCode, Json<Vendor>), (StatusCode, Json<FieldErrors>)> {
+	let errors = check_vendor(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let now = chrono::Utc::now();
+	let vendor: Result<Vendor, _> = sqlx::query_as(
+		r#"insert into vendors (name, tax_id, email, phone, address, bank_account_holder, iban, bic, created_by, created_at, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $10) returning *"#)
+		.bind(payload.name.trim())
+		.bind(&payload.tax_id)
+		.bind(&payload.email)
+		.bind(&payload.phone)
+		.bind(&payload.address)
+		.bind(payload.bank_account_holder.trim())
+		.bind(normalize_iban(&payload.iban))
+		.bind(payload.bic.as_deref().map(str::trim))
+		.bind(auth.user.userid)
+		.bind(now)
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = vendor {
+		if name_taken(&e) {
+			return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError { field: "/name".to_string(), message: "A vendor with this name exists".to_string() }] })));
+		}
+		admin_logger(LogType::Error, &format!("Error saving vendor of {}: {}", auth.user.userid, e), None);
+		return Err(internal_error());
+	}
+	let mut vendor = vendor.unwrap();
+
+	let events = onboard(&mut tx, &auth.user, &mut vendor).await.map_err(status_error)?;
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting vendor {}: {}", vendor.id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	admin_logger(LogType::Info, &format!("User {} created vendor {}", auth.user.userid, vendor.id), None);
+	return Ok((StatusCode::CREATED, Json(vendor)));
+}
+
+#[utoipa::path(get, path = "/vendors", tag = "vendors", params(VendorsQuery), responses(
+	(status = 200, description = "Vendors by name", body = Vec<Vendor>),
+	(status = 403, description = "Missing the manage_vendors permission")
+))]
+pub async fn get_vendors(
+	_auth: Authorized<ManageVendors>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<VendorsQuery>
+) -> Result<Json<Vec<Vendor>>, StatusCode> {
+	let vendors: Result<Vec<Vendor>, _> = sqlx::query_as("select * from vendors where ($1::varchar is null or status=$1) order by name")
+		.bind(&query.status)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = vendors {
+		admin_logger(LogType::Error, &format!("Error reading vendors: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(vendors.unwrap()));
+}
+
+#[utoipa::path(get, path = "/vendors/{id}", tag = "vendors", params(("id" = i32, Path, description = "Vendor id")), responses(
+	(status = 200, description = "The vendor", body = Vendor),
+	(status = 403, description = "Missing the manage_vendors permission"),
+	(status = 404, description = "Unknown vendor")
+))]
+pub async fn get_vendor(
+	_auth: Authorized<ManageVendors>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Vendor>, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	return Ok(Json(read_vendor(&mut conn, id, false).await?));
+}
+
+// replaces the details of the vendor. new banking details, or a vendor that is not active, go through onboarding again
+#[utoipa::path(put, path = "/vendors/{id}", tag = "vendors", params(("id" = i32, Path, description = "Vendor id")), request_body = VendorInput, responses(
+	(status = 200, description = "Updated", body = Vendor),
+	(status = 403, description = "Missing the manage_vendors permission"),
+	(status = 404, description = "Unknown vendor", body = FieldErrors),
+	(status = 409, description = "The vendor is being onboarded or the name is taken", body = FieldErrors),
+	(status = 422, description = "Invalid details, iban or bic", body = FieldErrors)
+))]
+pub async fn update_vendor(
+	auth: Authorized<ManageVendors>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>,
+	Json(payload): Json<VendorInput>
+) -> Result<Json<Vendor>, (StatusCode, Json<FieldErrors>)> {
+	let errors = check_vendor(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	let current = read_vendor(&mut tx, id, true).await.map_err(status_error)?;
+	if current.status == "pending_onboarding" {
+		// the approvers would approve details that are not in their ticket
+		return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError {
+			field: "/status".to_string(),
+			message: "The vendor is being onboarded, cancel its ticket to change it".to_string()
+		}] })));
+	}
+	let iban = normalize_iban(&payload.iban);
+	let bic = payload.bic.as_deref().map(str::trim);
+	let bank_changed = current.iban != iban || current.bic.as_deref() != bic || current.bank_account_holder != payload.bank_account_holder.trim();
+	let reonboard = bank_changed || current.status != "active";
+
+	let vendor: Result<Vendor, _> = sqlx::query_as(
+		r#"update vendors set name=$2, tax_id=$3, email=$4, phone=$5, address=$6, bank_account_holder=$7, iban=$8, bic=$9,
+				status=case when $10 then 'pending_onboarding' else status end, updated_at=$11
+			where id=$1 returning *"#)
+		.bind(id)
+		.bind(payload.name.trim())
+		.bind(&payload.tax_id)
+		.bind(&payload.email)
+		.bind(&payload.phone)
+		.bind(&payload.address)
+		.bind(payload.bank_account_holder.trim())
+		.bind(&iban)
+		.bind(bic)
+		.bind(reonboard)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = vendor {
+		if name_taken(&e) {
+			return Err((StatusCode::CONFLICT, Json(FieldErrors { errors: vec![FieldError { field: "/name".to_string(), message: "A vendor with this name exists".to_string() }] })));
+		}
+		admin_logger(LogType::Error, &format!("Error updating vendor {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	let mut vendor = vendor.unwrap();
+
+	let mut events = Vec::new();
+	if reonboard {
+		events = onboard(&mut tx, &auth.user, &mut vendor).await.map_err(status_error)?;
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting vendor {}: {}", id, e), None);
+		return Err(internal_error());
+	}
+	ticket::after_commit(&pool, events).await;
+
+	admin_logger(LogType::Info, &format!("User {} updated vendor {}{}", auth.user.userid, id, if reonboard { ", onboarding again" } else { "" }), None);
+	return Ok(Json(vendor));
+}
+
+// vendors stay for the records that name them, they are only deactivated. not while a process still sends tasks to them
+#[utoipa::path(delete, path = "/vendors/{id}", tag = "vendors", params(("id" = i32, Path, description = "Vendor id")), responses(
+	(status = 204, description = "Deactivated"),
+	(status = 403, description = "Missing the manage_vendors permission"),
+	(status = 404, description = "Unknown vendor"),
+	(status = 409, description = "The vendor is being onboarded or processes send tasks to it")
+))]
+pub async fn deactivate_vendor(
+	auth: Authorized<ManageVendors>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	let query = sqlx::query("update vendors set status='inactive', updated_at=$2 where id=$1 and status<>'pending_onboarding'")
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deactivating vendor {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		// exists, so it is being onboarded
+		read_vendor(&mut tx, id, false).await?;
+		return Err(StatusCode::CONFLICT);
+	}
+
+	// the update locks the vendor, a process saved at the same time is either found here or refused by check_process_vendors
+	let processes = referencing_processes(&mut tx, id).await;
+	if let Err(e) = processes {
+		admin_logger(LogType::Error, &format!("Error reading the processes of vendor {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let processes = processes.unwrap();
+	if !processes.is_empty() {
+		admin_logger(LogType::Warning, &format!("Vendor {} was not deactivated, processes {} send tasks to it", id, processes.join(", ")), None);
+		return Err(StatusCode::CONFLICT);
+	}
+
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the deactivation of vendor {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deactivated vendor {}", auth.user.userid, id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// moves the vendor of the onboarding ticket along with it, see linked::follow
+pub async fn follow_ticket(conn: &mut PgConnection, ticket_id: i32, statuses: &[String]) -> Result<(), FollowErr> {
+	let vendor: Option<(i32, String)> = sqlx::query_as("select id, status from vendors where ticket_id=$1 for update")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let Some((id, current)) = vendor else {
+		return Ok(());
+	};
+
+	let mut status = current.as_str();
+	for target in statuses {
+		if let Some(next) = next_status(status, target) {
+			status = next;
+		}
+	}
+	if status == current {
+		return Ok(());
+	}
+
+	sqlx::query("update vendors set status=$2, updated_at=$3 where id=$1")
+		.bind(id)
+		.bind(status)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	admin_logger(LogType::Info, &format!("Vendor {} moved from {} to {} by ticket {}", id, current, status, ticket_id), None);
+	return Ok(());
+}
+
+#[cfg(test)]
+mod vendors_tests {
+	use serde_json::json;
+	use crate::process::Step;
+	use super::{check_vendor, normalize_iban, valid_bic, valid_iban, vendor_errors, VendorInput};
+
+	#[test]
+	fn banking_details_are_checked() {
+		assert_eq!(normalize_iban("de89 3704 0044 0532 0130 00"), "DE89370400440532013000");
+		assert!(valid_iban("DE89370400440532013000"));
+		assert!(valid_iban("GB82WEST12345698765432"));
+		assert!(!valid_iban("DE88370400440532013000"));
+		assert!(!valid_iban("DE89"));
+		assert!(valid_bic("DEUTDEFF"));
+		assert!(valid_bic("DEUTDEFF500"));
+		assert!(!valid_bic("DEUT1EFF"));
+
+		let vendor = VendorInput {
+			name: "Acme".to_string(), tax_id: None, email: Some("billing.acme.com".to_string()), phone: None, address: None,
+			bank_account_holder: "Acme GmbH".to_string(), iban: "DE89 3704 0044 0532 0130 00".to_string(), bic: Some("DEUTDEF".to_string())
+		};
+		let fields: Vec<String> = check_vendor(&vendor).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/bic", "/email"]);
+	}
+
+	#[test]
+	fn only_blocking_tasks_reference_active_vendors() {
+		let steps: Vec<Step> = serde_json::from_value(json!([
+			{ "event": "initiate", "args": null, "next": [1], "required": [], "callbacks": null },
+			{ "event": "blocking_task", "args": null, "next": [2], "required": [0], "callbacks": null, "vendor_id": 4 },
+			{ "event": "blocking_task", "args": null, "next": [3], "required": [1], "callbacks": null, "vendor_id": 5 },
+			{ "event": "approve", "args": ["finance"], "next": [4], "required": [2], "callbacks": null, "vendor_id": 4 },
+			{ "event": "complete", "args": null, "next": [], "required": [3], "callbacks": null }
+		])).unwrap();
+		let errors = vendor_errors(&steps, &[4]);
+		let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+		assert_eq!(fields, vec!["/steps/2/vendor_id", "/steps/3/vendor_id"]);
+		assert!(vendor_errors(&steps[..2], &[4]).is_empty());
+	}
+}
@@ -0,0 +1,68 @@
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use crate::db_types::Ticket;
+use crate::users;
+
+// what a user may see of a ticket. every ticket read goes through ticket_access
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TicketAccess {
+	// owner, current or past assignee or admin. watching a ticket does not widen what a user sees of it
+	Full,
+	// anyone else on a public ticket. the state, rejections and assignees are hidden
+	Public,
+	Denied
+}
+
+pub fn access_level(involved: bool, is_public: bool) -> TicketAccess {
+	if involved {
+		return TicketAccess::Full;
+	}
+	if is_public {
+		return TicketAccess::Public;
+	}
+	return TicketAccess::Denied;
+}
+
+// works for archived tickets too, their assignments are kept in user_active_tickets_archive
+pub async fn ticket_access(conn: &mut PgConnection, ticket: &Ticket, userid: uuid::Uuid) -> Result<TicketAccess, sqlx::Error> {
+	if ticket.owner_id == userid {
+		return Ok(TicketAccess::Full);
+	}
+
+	let involved = sqlx::query(
+		r#"select 1 from user_active_tickets where ticketid=$1 and userid=$2
+			union all select 1 from user_active_tickets_archive where ticketid=$1 and userid=$2 limit 1"#)
+		.bind(ticket.id)
+		.bind(userid)
+		.fetch_optional(&mut *conn)
+		.await?;
+	if involved.is_some() {
+		return Ok(TicketAccess::Full);
+	}
+
+	let is_admin = users::user_is_admin(conn, userid).await?;
+	return Ok(access_level(is_admin, ticket.is_public));
+}
+
+// restricts a ticket list aliased `t` to the tickets `userid` has full access to. admins are not filtered
+pub fn push_involved_filter(builder: &mut QueryBuilder<Postgres>, userid: uuid::Uuid) {
+	builder.push(" and (t.owner_id=")
+		.push_bind(userid)
+		.push(" or exists (select 1 from user_active_tickets u where u.ticketid=t.id and u.userid=")
+		.push_bind(userid)
+		.push(") or exists (select 1 from user_active_tickets_archive u where u.ticketid=t.id and u.userid=")
+		.push_bind(userid)
+		.push("))");
+}
+
+#[cfg(test)]
+mod visibility_tests {
+	use super::{access_level, TicketAccess};
+
+	#[test]
+	fn access_levels() {
+		assert_eq!(access_level(true, false), TicketAccess::Full);
+		assert_eq!(access_level(true, true), TicketAccess::Full);
+		assert_eq!(access_level(false, true), TicketAccess::Public);
+		assert_eq!(access_level(false, false), TicketAccess::Denied);
+	}
+}
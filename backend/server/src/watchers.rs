@@ -0,0 +1,90 @@
+use axum::{extract, http::StatusCode};
+use sqlx::{PgConnection, PgPool};
+use crate::{auth::AuthUser, db_types::Ticket, logger::{admin_logger, log, LogType}, outbox, visibility::{self, TicketAccess}};
+
+// adds a notification for every watcher of the ticket. returns the number of notifications added
+pub async fn notify_watchers(conn: &mut PgConnection, ticket_id: i32, message: &str) -> Result<u64, sqlx::Error> {
+	let result = sqlx::query(
+		r#"insert into notifications (userid, message, created_at)
+			select userid, $2, $3 from ticket_watchers where ticketid=$1"#)
+		.bind(ticket_id)
+		.bind(message)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await?;
+	if result.rows_affected() > 0 {
+		outbox::enqueue(conn).await?;
+	}
+	return Ok(result.rows_affected());
+}
+
+// users only watch tickets themselves
+#[utoipa::path(post, path = "/ticket/{id}/watch", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 201, description = "Watching, changes of the ticket are notified"),
+	(status = 403, description = "No access"),
+	(status = 404, description = "Unknown ticket")
+))]
+pub async fn watch_ticket(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let mut conn = pool.acquire().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+	let query: Result<Option<Ticket>, _> = sqlx::query_as("select * from tickets where id=$1")
+		.bind(ticket_id)
+		.fetch_optional(&mut *conn)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading ticket {} in watch_ticket: {}", ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	let ticket = query.unwrap().ok_or(StatusCode::NOT_FOUND)?;
+
+	// users that can see a ticket can watch it
+	match visibility::ticket_access(&mut conn, &ticket, user.userid).await {
+		Err(e) => {
+			log(LogType::Error, format!("Error checking if {} can watch ticket {}: {}", user.userid, ticket.id, e), ticket.log_id);
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		}
+		Ok(TicketAccess::Denied) => return Err(StatusCode::FORBIDDEN),
+		Ok(_) => {}
+	}
+
+	let query = sqlx::query("insert into ticket_watchers (ticketid, userid, created_at) values ($1, $2, $3) on conflict do nothing")
+		.bind(ticket.id)
+		.bind(user.userid)
+		.bind(chrono::Utc::now())
+		.execute(&mut *conn)
+		.await;
+
+	if let Err(e) = query {
+		log(LogType::Error, format!("Error adding watcher {} to ticket {}: {}", user.userid, ticket.id, e), ticket.log_id);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(StatusCode::CREATED);
+}
+
+#[utoipa::path(delete, path = "/ticket/{id}/watch", tag = "tickets", params(("id" = i32, Path, description = "Ticket id")), responses(
+	(status = 200, description = "No longer watching")
+))]
+pub async fn unwatch_ticket(
+	user: AuthUser,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(ticket_id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from ticket_watchers where ticketid=$1 and userid=$2")
+		.bind(ticket_id)
+		.bind(user.userid)
+		.execute(&pool)
+		.await;
+
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing watcher {} from ticket {}: {}", user.userid, ticket_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(StatusCode::OK);
+}
@@ -0,0 +1,455 @@
+use std::time::Duration;
+use axum::{extract, http::StatusCode, Json};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::auth::new_secret;
+use crate::callbacks::{sign_payload, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::db_types::Ticket;
+use crate::logger::{admin_logger, LogType};
+use crate::outbox::backoff;
+use crate::rbac::{Authorized, ManageProcesses};
+use crate::shutdown;
+use utoipa::ToSchema;
+
+pub const EVENT_HEADER: &str = "X-Erp-Event";
+pub const DELIVERY_HEADER: &str = "X-Erp-Delivery";
+
+static HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+fn poll_interval() -> Duration {
+	let secs = std::env::var("WEBHOOK_POLL_SECS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5);
+	return Duration::from_secs(secs);
+}
+
+// a subscriber taking longer counts as a failed attempt
+fn request_timeout() -> Duration {
+	let millis = std::env::var("WEBHOOK_TIMEOUT_MS")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or(5000);
+	return Duration::from_millis(millis);
+}
+
+// deliveries failing this often are given up and marked failed
+fn max_attempts() -> i32 {
+	return std::env::var("WEBHOOK_MAX_ATTEMPTS")
+		.ok()
+		.and_then(|s| s.parse::<i32>().ok())
+		.unwrap_or(8);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+pub enum WebhookEvent {
+	#[serde(rename = "ticket.created")]
+	Created,
+	#[serde(rename = "ticket.approved")]
+	Approved,
+	#[serde(rename = "ticket.rejected")]
+	Rejected,
+	#[serde(rename = "ticket.completed")]
+	Completed
+}
+
+impl WebhookEvent {
+	pub fn as_str(&self) -> &'static str {
+		return match self {
+			WebhookEvent::Created => "ticket.created",
+			WebhookEvent::Approved => "ticket.approved",
+			WebhookEvent::Rejected => "ticket.rejected",
+			WebhookEvent::Completed => "ticket.completed"
+		};
+	}
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct Subscription {
+	pub id: i32,
+	pub url: String,
+	pub events: Vec<String>,
+	pub process_id: Option<String>,
+	pub created_by: Option<uuid::Uuid>,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateSubscription {
+	pub url: String,
+	pub events: Vec<WebhookEvent>,
+	pub process_id: Option<String>
+}
+
+// the secret is only returned when the subscription is created
+#[derive(Serialize, ToSchema)]
+pub struct CreatedSubscription {
+	#[serde(flatten)]
+	pub subscription: Subscription,
+	pub secret: String
+}
+
+#[derive(Serialize, FromRow, Debug, ToSchema)]
+pub struct Delivery {
+	pub id: i64,
+	pub subscription_id: i32,
+	pub event: String,
+	#[schema(value_type = Object)]
+	pub payload: Value,
+	pub status: String,
+	pub attempts: i32,
+	pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+	pub last_error: Option<String>,
+	pub response_status: Option<i32>,
+	pub created_at: chrono::DateTime<chrono::Utc>,
+	pub delivered_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(FromRow)]
+struct DueDelivery {
+	id: i64,
+	event: String,
+	payload: Value,
+	attempts: i32,
+	url: String,
+	secret: String
+}
+
+pub fn check_subscription(payload: &CreateSubscription) -> Result<(), String> {
+	if !payload.url.starts_with("https://") && !payload.url.starts_with("http://") {
+		return Err(format!("Invalid url: {}", payload.url));
+	}
+	if payload.events.is_empty() {
+		return Err("At least one event is required".to_string());
+	}
+	return Ok(());
+}
+
+// body of every delivery. `detail` holds what is specific to the event, like the node that was approved
+pub fn event_payload(event: WebhookEvent, ticket: &Ticket, detail: Value, now: chrono::DateTime<chrono::Utc>) -> Value {
+	return serde_json::json!({
+		"event": event.as_str(),
+		"occurred_at": now,
+		"ticket": {
+			"id": ticket.id,
+			"process_id": ticket.process_id,
+			"owner_id": ticket.owner_id,
+			"status": ticket.status,
+			"priority": ticket.priority
+		},
+		"detail": detail
+	});
+}
+
+// queues a delivery for every subscription of the ticket's tenant that wants the event. call in the ticket
+// transaction so subscribers never hear of changes that were rolled back
+pub async fn enqueue(conn: &mut PgConnection, event: WebhookEvent, ticket: &Ticket, detail: Value) -> Result<(), sqlx::Error> {
+	let now = chrono::Utc::now();
+	sqlx::query(
+		r#"insert into webhook_deliveries (subscription_id, event, payload, next_attempt_at, created_at)
+			select s.id, $1, $2, $3, $3 from webhook_subscriptions s
+			where $1=any(s.events) and (s.process_id is null or s.process_id=$4)
+			and s.tenant_id=(select tenant_id from tickets where id=$5)"#)
+		.bind(event.as_str())
+		.bind(event_payload(event, ticket, detail, now))
+		.bind(now)
+		.bind(&ticket.process_id)
+		.bind(ticket.id)
+		.execute(conn)
+		.await?;
+	return Ok(());
+}
+
+// posts the delivery. Err holds the response status, if there was one, and what went wrong
+async fn post(delivery: &DueDelivery) -> Result<i32, (Option<i32>, String)> {
+	let body = delivery.payload.to_string();
+	let timestamp = chrono::Utc::now().timestamp();
+	let response = HTTP.post(&delivery.url)
+		.timeout(request_timeout())
+		.header(reqwest::header::CONTENT_TYPE, "application/json")
+		.header(EVENT_HEADER, &delivery.event)
+		.header(DELIVERY_HEADER, delivery.id.to_string())
+		.header(TIMESTAMP_HEADER, timestamp.to_string())
+		.header(SIGNATURE_HEADER, sign_payload(&delivery.secret, timestamp, body.as_bytes()))
+		.body(body)
+		.send()
+		.await
+		.map_err(|e| (None, format!("Unable to reach {}. e: {}", delivery.url, e)))?;
+
+	let status = response.status().as_u16() as i32;
+	if !response.status().is_success() {
+		return Err((Some(status), format!("{} answered {}", delivery.url, status)));
+	}
+	return Ok(status);
+}
+
+// sends one due delivery. returns false when there is nothing left to send
+async fn send_next_delivery(pool: &PgPool) -> Result<bool, sqlx::Error> {
+	let now = chrono::Utc::now();
+	let mut tx = pool.begin().await?;
+
+	// deliveries locked by another worker are skipped
+	let delivery: Option<DueDelivery> = sqlx::query_as(
+		r#"select d.id, d.event, d.payload, d.attempts, s.url, s.secret from webhook_deliveries d
+			join webhook_subscriptions s on d.subscription_id=s.id
+			where d.status='pending' and d.next_attempt_at <= $1 order by d.id limit 1 for update of d skip locked"#)
+		.bind(now)
+		.fetch_optional(&mut *tx)
+		.await?;
+	let delivery = match delivery {
+		Some(delivery) => delivery,
+		None => return Ok(false)
+	};
+
+	match post(&delivery).await {
+		Ok(status) => {
+			sqlx::query("update webhook_deliveries set status='delivered', attempts=attempts+1, response_status=$2, delivered_at=$3, last_error=null where id=$1")
+				.bind(delivery.id)
+				.bind(status)
+				.bind(chrono::Utc::now())
+				.execute(&mut *tx)
+				.await?;
+		}
+		Err((status, e)) => {
+			let attempts = delivery.attempts + 1;
+			let gave_up = attempts >= max_attempts();
+			admin_logger(LogType::FailedToPing, &format!("Webhook delivery {} ({}) failed: {}", delivery.id, delivery.event, e), None);
+			sqlx::query("update webhook_deliveries set status=$2, attempts=$3, next_attempt_at=$4, response_status=$5, last_error=$6 where id=$1")
+				.bind(delivery.id)
+				.bind(if gave_up { "failed" } else { "pending" })
+				.bind(attempts)
+				.bind(now + backoff(attempts))
+				.bind(status)
+				.bind(&e)
+				.execute(&mut *tx)
+				.await?;
+			if gave_up {
+				admin_logger(LogType::Error, &format!("Webhook delivery {} failed {} times and was given up", delivery.id, attempts), None);
+			}
+		}
+	}
+
+	tx.commit().await?;
+	return Ok(true);
+}
+
+// every delivery is sent in its own transaction. a failing subscriber only delays its own deliveries
+pub async fn send_due_deliveries(pool: &PgPool) {
+	while !shutdown::is_stopping() {
+		match send_next_delivery(pool).await {
+			Ok(true) => {}
+			Ok(false) => return,
+			Err(e) => {
+				admin_logger(LogType::Error, &format!("Error sending webhook deliveries: {}", e), None);
+				return;
+			}
+		}
+	}
+}
+
+// sends the deliveries of a committed transaction without waiting for the next poll
+pub fn dispatch(pool: &PgPool) {
+	let pool = pool.clone();
+	shutdown::spawn(async move {
+		send_due_deliveries(&pool).await;
+	});
+}
+
+pub async fn run_webhooks(pool: PgPool) {
+	let mut interval = tokio::time::interval(poll_interval());
+	while shutdown::tick(&mut interval).await {
+		send_due_deliveries(&pool).await;
+	}
+}
+
+#[utoipa::path(post, path = "/admin/webhooks", tag = "webhooks", request_body = CreateSubscription, responses(
+	(status = 201, description = "Subscribed. the secret signing the deliveries is only shown here", body = CreatedSubscription),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown process", body = String),
+	(status = 422, description = "Invalid url or no events", body = String)
+))]
+pub async fn create_subscription(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateSubscription>
+) -> Result<(StatusCode, Json<CreatedSubscription>), (StatusCode, String)> {
+	check_subscription(&payload).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+	let mut events: Vec<String> = payload.events.iter().map(|e| e.as_str().to_string()).collect();
+	events.sort();
+	events.dedup();
+
+	let secret = new_secret();
+	let query: Result<Subscription, _> = sqlx::query_as(
+		r#"insert into webhook_subscriptions (url, events, process_id, secret, created_by, created_at) values ($1, $2, $3, $4, $5, $6)
+			returning id, url, events, process_id, created_by, created_at"#)
+		.bind(&payload.url)
+		.bind(&events)
+		.bind(&payload.process_id)
+		.bind(&secret)
+		.bind(auth.user.userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = query {
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err((StatusCode::NOT_FOUND, format!("Unknown process: {:?}", payload.process_id)));
+		}
+		admin_logger(LogType::Error, &format!("Error saving webhook subscription for {}: {}", payload.url, e), None);
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+	}
+	let subscription = query.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} subscribed {} to {:?}", auth.user.userid, subscription.url, subscription.events), None);
+	return Ok((StatusCode::CREATED, Json(CreatedSubscription { subscription, secret })));
+}
+
+#[utoipa::path(get, path = "/admin/webhooks", tag = "webhooks", responses(
+	(status = 200, description = "Webhook subscriptions", body = Vec<Subscription>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_subscriptions(
+	_auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<Subscription>>, StatusCode> {
+	let query: Result<Vec<Subscription>, _> = sqlx::query_as(
+		"select id, url, events, process_id, created_by, created_at from webhook_subscriptions order by id")
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading webhook subscriptions: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// pending deliveries of the subscription are dropped with it
+#[utoipa::path(delete, path = "/admin/webhooks/{id}", tag = "webhooks", params(("id" = i32, Path, description = "Subscription id")), responses(
+	(status = 200, description = "Deleted with its pending deliveries"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown subscription")
+))]
+pub async fn delete_subscription(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from webhook_subscriptions where id=$1")
+		.bind(id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting webhook subscription {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted webhook subscription {}", auth.user.userid, id), None);
+	return Ok(StatusCode::OK);
+}
+
+// the latest deliveries of the subscription, newest first
+#[utoipa::path(get, path = "/admin/webhooks/{id}/deliveries", tag = "webhooks", params(("id" = i32, Path, description = "Subscription id")), responses(
+	(status = 200, description = "Deliveries of the subscription, newest first", body = Vec<Delivery>),
+	(status = 403, description = "Missing the manage_processes permission")
+))]
+pub async fn get_deliveries(
+	_auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<Json<Vec<Delivery>>, StatusCode> {
+	// the join keeps subscriptions of other tenants out
+	let query: Result<Vec<Delivery>, _> = sqlx::query_as(
+		r#"select d.id, d.subscription_id, d.event, d.payload, d.status, d.attempts, d.next_attempt_at, d.last_error,
+			d.response_status, d.created_at, d.delivered_at
+			from webhook_deliveries d join webhook_subscriptions s on d.subscription_id=s.id
+			where s.id=$1 order by d.id desc limit 100"#)
+		.bind(id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error reading deliveries of webhook subscription {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+
+	return Ok(Json(query.unwrap()));
+}
+
+// sends a failed delivery again with a fresh attempt count
+#[utoipa::path(post, path = "/admin/webhook_deliveries/{id}/retry", tag = "webhooks", params(("id" = i64, Path, description = "Delivery id")), responses(
+	(status = 202, description = "Queued again with a fresh attempt count"),
+	(status = 403, description = "Missing the manage_processes permission"),
+	(status = 404, description = "Unknown delivery")
+))]
+pub async fn retry_delivery(
+	auth: Authorized<ManageProcesses>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i64>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query(
+		r#"update webhook_deliveries d set status='pending', attempts=0, next_attempt_at=$2 from webhook_subscriptions s
+			where d.id=$1 and d.subscription_id=s.id and d.status='failed'"#)
+		.bind(id)
+		.bind(chrono::Utc::now())
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error requeueing webhook delivery {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} requeued webhook delivery {}", auth.user.userid, id), None);
+	dispatch(&pool);
+	return Ok(StatusCode::ACCEPTED);
+}
+
+#[cfg(test)]
+mod webhooks_tests {
+	use serde_json::json;
+	use crate::db_types::Ticket;
+	use crate::ticket::TicketStatus;
+	use super::{check_subscription, event_payload, CreateSubscription, WebhookEvent};
+
+	#[test]
+	fn events_use_their_wire_names() {
+		let payload: CreateSubscription = serde_json::from_value(json!({
+			"url": "https://erp.example.com/hooks", "events": ["ticket.created", "ticket.completed"], "process_id": null
+		})).unwrap();
+		assert_eq!(payload.events, vec![WebhookEvent::Created, WebhookEvent::Completed]);
+		assert!(check_subscription(&payload).is_ok());
+		for event in [WebhookEvent::Created, WebhookEvent::Approved, WebhookEvent::Rejected, WebhookEvent::Completed] {
+			assert_eq!(serde_json::to_value(event).unwrap(), json!(event.as_str()));
+		}
+
+		assert!(serde_json::from_value::<CreateSubscription>(json!({ "url": "https://erp", "events": ["ticket.deleted"] })).is_err());
+		let empty: CreateSubscription = serde_json::from_value(json!({ "url": "https://erp", "events": [] })).unwrap();
+		assert!(check_subscription(&empty).is_err());
+		let ftp: CreateSubscription = serde_json::from_value(json!({ "url": "ftp://erp", "events": ["ticket.created"] })).unwrap();
+		assert!(check_subscription(&ftp).is_err());
+	}
+
+	#[test]
+	fn payloads_describe_the_ticket() {
+		let now = chrono::Utc::now();
+		let ticket = Ticket {
+			id: 7, owner_id: uuid::Uuid::nil(), process_id: "leave".to_string(), log_id: uuid::Uuid::nil(), is_public: false,
+			created_at: now, updated_at: now, status: TicketStatus::Rejected, complete: 1, priority: 2, due_at: None,
+			state: json!({ "salary": 100 }), instances: json!({})
+		};
+		let payload = event_payload(WebhookEvent::Rejected, &ticket, json!({ "node": 2, "reason": "no" }), now);
+		assert_eq!(payload["event"], "ticket.rejected");
+		assert_eq!(payload["ticket"], json!({
+			"id": 7, "process_id": "leave", "owner_id": uuid::Uuid::nil(), "status": "rejected", "priority": 2
+		}));
+		assert_eq!(payload["detail"]["reason"], "no");
+		// the state of the ticket can hold anything and is not sent out
+		assert!(!payload.to_string().contains("salary"));
+	}
+}
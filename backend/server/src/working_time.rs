@@ -0,0 +1,469 @@
+use std::collections::BTreeSet;
+use axum::{extract, http::StatusCode, Json};
+use chrono::{Datelike, Duration, FixedOffset, NaiveDate, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::logger::{admin_logger, LogType};
+use crate::rbac::{Authorized, ManageUsers};
+use crate::schema::{FieldError, FieldErrors, field_error, internal_error};
+use utoipa::{IntoParams, ToSchema};
+
+// deadlines further away than this are not worth counting day by day, they are cut off
+const MAX_DAYS: i64 = 3660;
+// holidays are loaded this far ahead of the first day counted
+const HOLIDAY_DAYS: i64 = 2 * 366;
+
+// the working hours deadlines are counted in. weeks without a working day are not accepted
+#[derive(Debug, Clone)]
+pub struct WorkCalendar {
+	// iso days of the week, 1 is monday
+	pub workdays: Vec<u32>,
+	pub day_start: NaiveTime,
+	pub day_end: NaiveTime,
+	pub offset: FixedOffset,
+	pub holidays: BTreeSet<NaiveDate>
+}
+
+impl Default for WorkCalendar {
+	// monday to friday, 9 to 5 utc, for organizations that did not set up a calendar
+	fn default() -> Self {
+		return WorkCalendar {
+			workdays: vec![1, 2, 3, 4, 5],
+			day_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+			day_end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+			offset: FixedOffset::east_opt(0).unwrap(),
+			holidays: BTreeSet::new()
+		};
+	}
+}
+
+impl WorkCalendar {
+	pub fn is_working_day(&self, day: NaiveDate) -> bool {
+		return self.workdays.contains(&day.weekday().number_from_monday()) && !self.holidays.contains(&day);
+	}
+
+	// the working hours of the day in utc, None on days off
+	fn hours_of(&self, day: NaiveDate) -> Option<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)> {
+		if !self.is_working_day(day) {
+			return None;
+		}
+		let start = self.offset.from_local_datetime(&day.and_time(self.day_start)).single()?;
+		let end = self.offset.from_local_datetime(&day.and_time(self.day_end)).single()?;
+		return Some((start.with_timezone(&chrono::Utc), end.with_timezone(&chrono::Utc)));
+	}
+
+	fn local_day(&self, time: chrono::DateTime<chrono::Utc>) -> NaiveDate {
+		return time.with_timezone(&self.offset).date_naive();
+	}
+
+	// the time `duration` of working hours after `start`. a duration of zero outside working hours is the next start of work
+	pub fn add(&self, start: chrono::DateTime<chrono::Utc>, duration: Duration) -> chrono::DateTime<chrono::Utc> {
+		let mut left = duration.max(Duration::zero());
+		let mut day = self.local_day(start);
+		for _ in 0..MAX_DAYS {
+			if let Some((open, close)) = self.hours_of(day) {
+				let from = open.max(start);
+				if from < close || (from == close && left.is_zero() && from == open) {
+					let available = close - from;
+					if left <= available {
+						return from + left;
+					}
+					left -= available;
+				}
+			}
+			day = day.succ_opt().unwrap_or(day);
+		}
+		return start + Duration::days(MAX_DAYS);
+	}
+
+	// the working hours between the two times, zero if `to` is not after `from`
+	pub fn between(&self, from: chrono::DateTime<chrono::Utc>, to: chrono::DateTime<chrono::Utc>) -> Duration {
+		let mut total = Duration::zero();
+		if to <= from {
+			return total;
+		}
+		let last = self.local_day(to);
+		let mut day = self.local_day(from);
+		for _ in 0..MAX_DAYS {
+			if day > last {
+				break;
+			}
+			if let Some((open, close)) = self.hours_of(day) {
+				let (start, end) = (open.max(from), close.min(to));
+				if start < end {
+					total += end - start;
+				}
+			}
+			day = day.succ_opt().unwrap_or(last);
+		}
+		return total;
+	}
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveCalendar {
+	pub workdays: Vec<u32>,
+	pub day_start: NaiveTime,
+	pub day_end: NaiveTime,
+	#[serde(default)]
+	pub utc_offset_minutes: i32
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct StoredCalendar {
+	pub id: i32,
+	// null for the calendar of the organization
+	pub department_id: Option<i32>,
+	pub workdays: Vec<i32>,
+	pub day_start: NaiveTime,
+	pub day_end: NaiveTime,
+	pub utc_offset_minutes: i32,
+	pub updated_by: Option<uuid::Uuid>,
+	pub updated_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateHoliday {
+	// the whole organization when missing
+	pub department_id: Option<i32>,
+	pub day: NaiveDate,
+	pub name: String
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Holiday {
+	pub id: i32,
+	pub department_id: Option<i32>,
+	pub day: NaiveDate,
+	pub name: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HolidaysQuery {
+	pub year: Option<i32>,
+	pub department_id: Option<i32>
+}
+
+pub fn check_calendar(calendar: &SaveCalendar) -> Vec<FieldError> {
+	let mut errors = Vec::new();
+	let mut error = |field: &str, message: &str| errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+	if calendar.workdays.is_empty() {
+		error("/workdays", "At least one working day is required");
+	}
+	if calendar.workdays.iter().any(|d| !(1..=7).contains(d)) {
+		error("/workdays", "Expected days from 1 (monday) to 7 (sunday)");
+	}
+	if calendar.day_start >= calendar.day_end {
+		error("/day_end", "Has to be after the start of the day");
+	}
+	if !(-840..=840).contains(&calendar.utc_offset_minutes) {
+		error("/utc_offset_minutes", "Expected between -840 and 840 minutes");
+	}
+	return errors;
+}
+
+#[derive(FromRow)]
+struct CalendarRow {
+	workdays: Vec<i32>,
+	day_start: NaiveTime,
+	day_end: NaiveTime,
+	utc_offset_minutes: i32
+}
+
+// the calendar of the primary department of the user, or of the organization, with the holidays of both from `since`.
+// filtered by the tenant of the user so background work without a tenant reads the right one
+pub async fn calendar_of(conn: &mut PgConnection, userid: uuid::Uuid, since: NaiveDate) -> Result<WorkCalendar, sqlx::Error> {
+	let department: Option<(i32,)> = sqlx::query_as(
+		"select department_id from user_departments where userid=$1 order by is_primary desc, department_id limit 1")
+		.bind(userid)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let department_id = department.map(|d| d.0);
+
+	let row: Option<CalendarRow> = sqlx::query_as(
+		r#"select workdays, day_start, day_end, utc_offset_minutes from work_calendars
+			where tenant_id=(select tenant_id from users where userid=$1) and (department_id=$2 or department_id is null)
+			order by department_id nulls last limit 1"#)
+		.bind(userid)
+		.bind(department_id)
+		.fetch_optional(&mut *conn)
+		.await?;
+	let holidays: Vec<(NaiveDate,)> = sqlx::query_as(
+		r#"select day from public_holidays
+			where tenant_id=(select tenant_id from users where userid=$1) and (department_id=$2 or department_id is null) and day between $3 and $4"#)
+		.bind(userid)
+		.bind(department_id)
+		.bind(since)
+		.bind(since + Duration::days(HOLIDAY_DAYS))
+		.fetch_all(&mut *conn)
+		.await?;
+
+	let mut calendar = match row {
+		Some(row) => WorkCalendar {
+			workdays: row.workdays.into_iter().map(|d| d as u32).collect(),
+			day_start: row.day_start,
+			day_end: row.day_end,
+			offset: FixedOffset::east_opt(row.utc_offset_minutes * 60).unwrap_or(FixedOffset::east_opt(0).unwrap()),
+			holidays: BTreeSet::new()
+		},
+		None => WorkCalendar::default()
+	};
+	calendar.holidays = holidays.into_iter().map(|h| h.0).collect();
+	return Ok(calendar);
+}
+
+#[utoipa::path(get, path = "/work_calendars", tag = "working_time", responses(
+	(status = 200, description = "The calendar of the organization and of every department that has its own", body = Vec<StoredCalendar>),
+	(status = 403, description = "Missing the manage_users permission")
+))]
+pub async fn get_calendars(
+	_auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Json<Vec<StoredCalendar>>, StatusCode> {
+	let calendars: Result<Vec<StoredCalendar>, _> = sqlx::query_as("select * from work_calendars order by department_id nulls first")
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = calendars {
+		admin_logger(LogType::Error, &format!("Error reading work calendars: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(calendars.unwrap()));
+}
+
+async fn save_calendar(pool: &PgPool, userid: uuid::Uuid, department_id: Option<i32>, payload: SaveCalendar) -> Result<Json<StoredCalendar>, (StatusCode, Json<FieldErrors>)> {
+	let errors = check_calendar(&payload);
+	if !errors.is_empty() {
+		return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(FieldErrors { errors })));
+	}
+	let mut workdays: Vec<i32> = payload.workdays.iter().map(|d| *d as i32).collect();
+	workdays.sort();
+	workdays.dedup();
+
+	let mut tx = pool.begin().await.map_err(|_| internal_error())?;
+	// the unique index is on an expression, so the row is replaced instead of upserted
+	let query = sqlx::query("delete from work_calendars where department_id is not distinct from $1")
+		.bind(department_id)
+		.execute(&mut *tx)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error replacing the work calendar of department {:?}: {}", department_id, e), None);
+		return Err(internal_error());
+	}
+	let calendar: Result<StoredCalendar, _> = sqlx::query_as(
+		r#"insert into work_calendars (department_id, workdays, day_start, day_end, utc_offset_minutes, updated_by, updated_at)
+			values ($1, $2, $3, $4, $5, $6, $7) returning *"#)
+		.bind(department_id)
+		.bind(&workdays)
+		.bind(payload.day_start)
+		.bind(payload.day_end)
+		.bind(payload.utc_offset_minutes)
+		.bind(userid)
+		.bind(chrono::Utc::now())
+		.fetch_one(&mut *tx)
+		.await;
+	if let Err(e) = calendar {
+		if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err(field_error(StatusCode::NOT_FOUND, "/department_id", "Unknown department".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error saving the work calendar of department {:?}: {}", department_id, e), None);
+		return Err(internal_error());
+	}
+	if let Err(e) = tx.commit().await {
+		admin_logger(LogType::Error, &format!("Error commiting the work calendar of department {:?}: {}", department_id, e), None);
+		return Err(internal_error());
+	}
+
+	admin_logger(LogType::Info, &format!("User {} set the work calendar of {}", userid, department_id.map(|d| format!("department {}", d)).unwrap_or("the organization".to_string())), None);
+	return Ok(Json(calendar.unwrap()));
+}
+
+// the working hours of the organization
+#[utoipa::path(put, path = "/work_calendars/default", tag = "working_time", request_body = SaveCalendar, responses(
+	(status = 200, description = "Saved", body = StoredCalendar),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 422, description = "Invalid workdays, hours or offset", body = FieldErrors)
+))]
+pub async fn save_default_calendar(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<SaveCalendar>
+) -> Result<Json<StoredCalendar>, (StatusCode, Json<FieldErrors>)> {
+	return save_calendar(&pool, auth.user.userid, None, payload).await;
+}
+
+// the working hours of a department working differently from the organization
+#[utoipa::path(put, path = "/departments/{id}/calendar", tag = "working_time", params(("id" = i32, Path, description = "Department id")), request_body = SaveCalendar, responses(
+	(status = 200, description = "Saved", body = StoredCalendar),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 422, description = "Unknown department or invalid calendar", body = FieldErrors)
+))]
+pub async fn save_department_calendar(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(department_id): extract::Path<i32>,
+	Json(payload): Json<SaveCalendar>
+) -> Result<Json<StoredCalendar>, (StatusCode, Json<FieldErrors>)> {
+	return save_calendar(&pool, auth.user.userid, Some(department_id), payload).await;
+}
+
+// the department works the hours of the organization again
+#[utoipa::path(delete, path = "/departments/{id}/calendar", tag = "working_time", params(("id" = i32, Path, description = "Department id")), responses(
+	(status = 204, description = "Removed, the department uses the calendar of the organization"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "The department has no calendar")
+))]
+pub async fn remove_department_calendar(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(department_id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from work_calendars where department_id=$1")
+		.bind(department_id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error removing the work calendar of department {}: {}", department_id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} removed the work calendar of department {}", auth.user.userid, department_id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+// the holidays of the organization, and of the department if one is given
+#[utoipa::path(get, path = "/holidays", tag = "working_time", params(HolidaysQuery), responses(
+	(status = 200, description = "Holidays, by day", body = Vec<Holiday>),
+	(status = 403, description = "Missing the manage_users permission")
+))]
+pub async fn get_holidays(
+	_auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Query(query): extract::Query<HolidaysQuery>
+) -> Result<Json<Vec<Holiday>>, StatusCode> {
+	let holidays: Result<Vec<Holiday>, _> = sqlx::query_as(
+		r#"select id, department_id, day, name from public_holidays
+			where ($1::int is null or extract(year from day)=$1) and (department_id is null or department_id=$2)
+			order by day, department_id nulls first"#)
+		.bind(query.year)
+		.bind(query.department_id)
+		.fetch_all(&pool)
+		.await;
+	if let Err(e) = holidays {
+		admin_logger(LogType::Error, &format!("Error reading holidays: {}", e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	return Ok(Json(holidays.unwrap()));
+}
+
+#[utoipa::path(post, path = "/holidays", tag = "working_time", request_body = CreateHoliday, responses(
+	(status = 201, description = "Created", body = Holiday),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 409, description = "There is a holiday on the day already", body = FieldErrors),
+	(status = 422, description = "Unknown department or empty name", body = FieldErrors)
+))]
+pub async fn create_holiday(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	Json(payload): Json<CreateHoliday>
+) -> Result<(StatusCode, Json<Holiday>), (StatusCode, Json<FieldErrors>)> {
+	if payload.name.trim().is_empty() {
+		return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/name", "A name is required".to_string()));
+	}
+	let holiday: Result<Holiday, _> = sqlx::query_as(
+		"insert into public_holidays (department_id, day, name) values ($1, $2, $3) returning id, department_id, day, name")
+		.bind(payload.department_id)
+		.bind(payload.day)
+		.bind(payload.name.trim())
+		.fetch_one(&pool)
+		.await;
+	if let Err(e) = holiday {
+		let database_error = e.as_database_error();
+		if database_error.map(|d| d.is_unique_violation()).unwrap_or(false) {
+			return Err(field_error(StatusCode::CONFLICT, "/day", format!("{} is a holiday already", payload.day)));
+		}
+		if database_error.map(|d| d.is_foreign_key_violation()).unwrap_or(false) {
+			return Err(field_error(StatusCode::UNPROCESSABLE_ENTITY, "/department_id", "Unknown department".to_string()));
+		}
+		admin_logger(LogType::Error, &format!("Error saving holiday {}: {}", payload.day, e), None);
+		return Err(internal_error());
+	}
+	let holiday = holiday.unwrap();
+
+	admin_logger(LogType::Info, &format!("User {} added holiday {} on {}", auth.user.userid, holiday.id, holiday.day), None);
+	return Ok((StatusCode::CREATED, Json(holiday)));
+}
+
+#[utoipa::path(delete, path = "/holidays/{id}", tag = "working_time", params(("id" = i32, Path, description = "Holiday id")), responses(
+	(status = 204, description = "Deleted"),
+	(status = 403, description = "Missing the manage_users permission"),
+	(status = 404, description = "Unknown holiday")
+))]
+pub async fn delete_holiday(
+	auth: Authorized<ManageUsers>,
+	extract::State(pool): extract::State<PgPool>,
+	extract::Path(id): extract::Path<i32>
+) -> Result<StatusCode, StatusCode> {
+	let query = sqlx::query("delete from public_holidays where id=$1")
+		.bind(id)
+		.execute(&pool)
+		.await;
+	if let Err(e) = query {
+		admin_logger(LogType::Error, &format!("Error deleting holiday {}: {}", id, e), None);
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	if query.unwrap().rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	admin_logger(LogType::Info, &format!("User {} deleted holiday {}", auth.user.userid, id), None);
+	return Ok(StatusCode::NO_CONTENT);
+}
+
+#[cfg(test)]
+mod working_time_tests {
+	use chrono::{Duration, FixedOffset, NaiveDate};
+	use super::{check_calendar, SaveCalendar, WorkCalendar};
+
+	fn time(s: &str) -> chrono::DateTime<chrono::Utc> {
+		return s.parse().unwrap();
+	}
+
+	#[test]
+	fn deadlines_skip_nights_weekends_and_holidays() {
+		let mut calendar = WorkCalendar::default();
+		// friday 16:00 plus 3 hours is monday 11:00
+		assert_eq!(calendar.add(time("2024-06-07T16:00:00Z"), Duration::hours(3)), time("2024-06-10T11:00:00Z"));
+		// started on a saturday, the clock starts on monday morning
+		assert_eq!(calendar.add(time("2024-06-08T12:00:00Z"), Duration::hours(8)), time("2024-06-10T17:00:00Z"));
+
+		calendar.holidays.insert(NaiveDate::from_ymd_opt(2024, 6, 10).unwrap());
+		assert_eq!(calendar.add(time("2024-06-07T16:00:00Z"), Duration::hours(3)), time("2024-06-11T11:00:00Z"));
+
+		assert_eq!(calendar.between(time("2024-06-07T16:00:00Z"), time("2024-06-11T11:00:00Z")), Duration::hours(3));
+		assert_eq!(calendar.between(time("2024-06-08T00:00:00Z"), time("2024-06-09T23:00:00Z")), Duration::zero());
+	}
+
+	#[test]
+	fn local_hours_follow_the_offset() {
+		let calendar = WorkCalendar { offset: FixedOffset::east_opt(2 * 3600).unwrap(), ..WorkCalendar::default() };
+		// 9:00 at +02:00 is 7:00 utc
+		assert_eq!(calendar.add(time("2024-06-10T05:00:00Z"), Duration::hours(1)), time("2024-06-10T08:00:00Z"));
+		assert_eq!(calendar.between(time("2024-06-10T00:00:00Z"), time("2024-06-11T00:00:00Z")), Duration::hours(8));
+	}
+
+	#[test]
+	fn calendars_are_checked() {
+		let calendar = SaveCalendar {
+			workdays: vec![0, 5],
+			day_start: "18:00:00".parse().unwrap(),
+			day_end: "08:00:00".parse().unwrap(),
+			utc_offset_minutes: 900
+		};
+		let fields: Vec<String> = check_calendar(&calendar).into_iter().map(|e| e.field).collect();
+		assert_eq!(fields, vec!["/workdays", "/day_end", "/utc_offset_minutes"]);
+	}
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::{extract::{self, ws::{Message, WebSocket, WebSocketUpgrade}}, http::{HeaderMap, StatusCode}, response::Response};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::auth::{self, AuthUser};
+use crate::db_types::Ticket;
+use crate::logger::{admin_logger, LogType};
+use crate::shutdown;
+use utoipa::{IntoParams, ToSchema};
+
+const MAX_CLIENTS_PER_USER: usize = 3;
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveEventKind {ApproveRequest, Notify, Completion}
+
+// sent as json to every open socket of the user
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct LiveEvent {
+	pub kind: LiveEventKind,
+	pub ticket_id: i32,
+	pub process_id: String,
+	pub node: i32,
+	pub message: String,
+	pub created_at: chrono::DateTime<chrono::Utc>
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WsQuery {
+	// browsers cannot set headers on websocket requests so the access token can also be passed here
+	pub token: Option<String>
+}
+
+// (connection id, channel to the socket task) for every connected user
+type Clients = HashMap<uuid::Uuid, Vec<(uuid::Uuid, UnboundedSender<LiveEvent>)>>;
+
+static CLIENTS: Lazy<Mutex<Clients>> = Lazy::new(|| {
+	return Mutex::new(HashMap::new());
+});
+
+impl LiveEvent {
+	pub fn new(kind: LiveEventKind, ticket: &Ticket, node: i32, message: String) -> LiveEvent {
+		return LiveEvent {
+			kind,
+			ticket_id: ticket.id,
+			process_id: ticket.process_id.clone(),
+			node,
+			message,
+			created_at: chrono::Utc::now()
+		};
+	}
+}
+
+fn register(userid: uuid::Uuid) -> Option<(uuid::Uuid, UnboundedReceiver<LiveEvent>)> {
+	let mut clients = CLIENTS.lock().unwrap();
+	let connections = clients.entry(userid).or_default();
+	if connections.len() >= MAX_CLIENTS_PER_USER {
+		return None;
+	}
+	let (sender, receiver) = unbounded_channel();
+	let connection_id = uuid::Uuid::new_v4();
+	connections.push((connection_id, sender));
+	return Some((connection_id, receiver));
+}
+
+fn unregister(userid: uuid::Uuid, connection_id: uuid::Uuid) {
+	let mut clients = CLIENTS.lock().unwrap();
+	if let Some(connections) = clients.get_mut(&userid) {
+		connections.retain(|(id, _)| *id != connection_id);
+		if connections.is_empty() {
+			clients.remove(&userid);
+		}
+	}
+}
+
+// sends the events to the users that are connected. users that are not connected find them in their notifications.
+// only call this after the transaction that produced the events was committed. returns the number of delivered events
+pub fn publish(events: Vec<(uuid::Uuid, LiveEvent)>) -> usize {
+	let clients = CLIENTS.lock().unwrap();
+	let mut delivered = 0;
+	for (userid, event) in events {
+		if let Some(connections) = clients.get(&userid) {
+			for (_, sender) in connections {
+				// a closed channel means the socket is going away and will unregister itself
+				if sender.send(event.clone()).is_ok() {
+					delivered += 1;
+				}
+			}
+		}
+	}
+	return delivered;
+}
+
+#[utoipa::path(get, path = "/ws/notifications", tag = "notifications", params(WsQuery), responses(
+	(status = 101, description = "Upgraded to a websocket. live events of the user are sent as json text messages", body = LiveEvent),
+	(status = 401, description = "Missing or invalid token")
+))]
+pub async fn notifications_ws(
+	ws: WebSocketUpgrade,
+	headers: HeaderMap,
+	extract::Query(query): extract::Query<WsQuery>,
+	extract::State(pool): extract::State<PgPool>
+) -> Result<Response, StatusCode> {
+	let token = auth::bearer_token(&headers)
+		.or(query.token.as_deref())
+		.ok_or(StatusCode::UNAUTHORIZED)?;
+	let user = auth::authenticate(&pool, token).await?;
+
+	return Ok(ws.on_upgrade(move |socket| handle_socket(socket, user)));
+}
+
+async fn handle_socket(mut socket: WebSocket, user: AuthUser) {
+	let registration = register(user.userid);
+	if registration.is_none() {
+		admin_logger(LogType::Warning, &format!("User {} attempted to open more than {} notification sockets", user.userid, MAX_CLIENTS_PER_USER), None);
+		let _ = socket.send(Message::Close(None)).await;
+		return;
+	}
+	let (connection_id, mut receiver) = registration.unwrap();
+
+	loop {
+		tokio::select! {
+			event = receiver.recv() => {
+				let event = match event {
+					Some(event) => event,
+					None => break
+				};
+				let serialized = serde_json::to_string(&event).unwrap();
+				if socket.send(Message::Text(serialized)).await.is_err() {
+					break;
+				}
+			}
+			_ = shutdown::stopped() => {
+				let _ = socket.send(Message::Close(None)).await;
+				break;
+			}
+			message = socket.recv() => {
+				// clients only send pings and close frames. axum answers the pings
+				match message {
+					None | Some(Err(_)) | Some(Ok(Message::Close(_))) => break,
+					Some(Ok(_)) => {}
+				}
+			}
+		}
+	}
+
+	unregister(user.userid, connection_id);
+}
+
+#[cfg(test)]
+mod ws_tests {
+	use super::{publish, register, unregister, LiveEvent, LiveEventKind, MAX_CLIENTS_PER_USER};
+
+	fn event() -> LiveEvent {
+		return LiveEvent {
+			kind: LiveEventKind::ApproveRequest,
+			ticket_id: 7,
+			process_id: "leave".to_string(),
+			node: 1,
+			message: "Ticket 7 needs your approval".to_string(),
+			created_at: chrono::Utc::now()
+		};
+	}
+
+	#[test]
+	fn events_reach_connected_users() {
+		let userid = uuid::Uuid::new_v4();
+		let (first, mut first_rx) = register(userid).unwrap();
+		let (_second, mut second_rx) = register(userid).unwrap();
+		let _third = register(userid).unwrap();
+		assert!(register(userid).is_none(), "at most {} sockets per user", MAX_CLIENTS_PER_USER);
+
+		assert_eq!(publish(vec![(userid, event()), (uuid::Uuid::new_v4(), event())]), 3);
+		assert_eq!(first_rx.try_recv().unwrap().ticket_id, 7);
+		assert_eq!(second_rx.try_recv().unwrap().kind, LiveEventKind::ApproveRequest);
+
+		unregister(userid, first);
+		assert_eq!(publish(vec![(userid, event())]), 2);
+		assert!(first_rx.try_recv().is_err());
+
+		let json = serde_json::to_value(event()).unwrap();
+		assert_eq!(json["kind"], "approve_request");
+	}
+}